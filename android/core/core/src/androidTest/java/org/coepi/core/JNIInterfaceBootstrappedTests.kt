@@ -5,9 +5,13 @@ import androidx.test.ext.junit.runners.AndroidJUnit4
 import kotlinx.coroutines.ExperimentalCoroutinesApi
 import org.coepi.core.jni.JniApi
 import org.coepi.core.jni.JniLogCallback
+import org.coepi.core.jni.JniResult
+import org.coepi.core.jni.JniStringResult
 import org.coepi.core.jni.JniVoidResult
 import org.coepi.core.services.CoreLogger
+import org.json.JSONObject
 import org.junit.Assert.assertEquals
+import org.junit.Assert.assertTrue
 import org.junit.Before
 import org.junit.Test
 import org.junit.runner.RunWith
@@ -47,7 +51,7 @@ class JNIInterfaceBootstrappedTests {
             .absolutePath.removeSuffix("/remove")
 
         val n = JniApi()
-        val result = n.bootstrapCore(dbPath, "debug", true,
+        val result = n.bootstrapCore(dbPath, "{\"app_version\":\"test\"}", "debug", true,
             JniLogCallback(object : CoreLogger {
                 override fun log(level: Int, message: String) {
                     println("[CORE] level: $level, message: $message")
@@ -55,7 +59,7 @@ class JNIInterfaceBootstrappedTests {
             })
         )
         // Double check
-        assertEquals(JniVoidResult(1, ""), result)
+        assertEquals(1, result.status)
     }
 
     // Manual testing
@@ -84,10 +88,44 @@ class JNIInterfaceBootstrappedTests {
         assertEquals(JniVoidResult(6, "Not found"), value)
     }
 
+    @Test
+    fun snoozeAlert() {
+        val value = JniApi().snoozeAlert("1", 1592567315)
+        assertEquals(JniVoidResult(6, "Not found"), value)
+    }
+
+    @Test
+    fun snoozeAlertWithNegativeTime() {
+        val value = JniApi().snoozeAlert("1", -1)
+        assertEquals(4, value.status)
+    }
+
     @Test
     fun recordTcn() {
-        val value = JniApi().recordTcn("2485a64b57addcaea3ed1b538d07dbce", 34.03f)
+        val time = System.currentTimeMillis() / 1000
+        val value = JniApi().recordTcn("2485a64b57addcaea3ed1b538d07dbce", 34.03f, time)
+        assertEquals(JniVoidResult(1, ""), value)
+    }
+
+    @Test
+    fun recordTcnWithFutureTime() {
+        val time = System.currentTimeMillis() / 1000 + 3600
+        val value = JniApi().recordTcn("2485a64b57addcaea3ed1b538d07dbce", 34.03f, time)
+        assertEquals(4, value.status)
+    }
+
+    @Test
+    fun flushObservedTcns() {
+        val time = System.currentTimeMillis() / 1000
+        JniApi().recordTcn("5b3d8e1f0a2c4e6f8a1b3c5d7e9f0a1b", 1.5f, time)
+
+        val value = JniApi().flushObservedTcns()
         assertEquals(JniVoidResult(1, ""), value)
+
+        // Stored without waiting for the periodic flush
+        val stats = JniApi().getTcnStats()
+        assertEquals(1, stats.status)
+        assertTrue(JSONObject(stats.json!!).getInt("total") > 0)
     }
 
     @Test
@@ -101,20 +139,34 @@ class JNIInterfaceBootstrappedTests {
         // NOTE: JSON format
         val value =
             JniApi().setSymptomIds("""["breathlessness", "muscle_aches", "runny_nose"]""")
-        assertEquals(JniVoidResult(1, ""), value)
+        assertEquals(
+            JniStringResult(
+                1,
+                "",
+                """{"accepted":["breathlessness","muscle_aches","runny_nose"],"rejected":[]}"""
+            ),
+            value
+        )
     }
 
     @Test
-    fun setInvalidSymptomIdReturnsError() {
+    fun setInvalidSymptomIdIsRejected() {
         // NOTE: JSON format
         val value = JniApi().setSymptomIds("""["not_supported", "muscle_aches", "runny_nose"]""")
-        assertEquals(3, value.status)
+        assertEquals(
+            JniStringResult(
+                1,
+                "",
+                """{"accepted":["muscle_aches","runny_nose"],"rejected":["not_supported"]}"""
+            ),
+            value
+        )
     }
 
     @Test
-    fun setInvalidSymptomIdsJsonReturnsError() {
-        val value = JniApi().setSymptomIds("sdjfhskdf")
-        assertEquals(3, value.status)
+    fun setSymptomIdsObjectReturnsError() {
+        val value = JniApi().setSymptomIds("""{"ids": ["fever"]}""")
+        assertEquals(4, value.status)
     }
 
     @Test
@@ -248,6 +300,13 @@ class JNIInterfaceBootstrappedTests {
         assertEquals(JniVoidResult(1, ""), result)
     }
 
+    @Test
+    fun setEarliestSymptomDate() {
+        val result = JniApi()
+            .setEarliestSymptomDate(1, System.currentTimeMillis() / 1000 - 3600)
+        assertEquals(JniVoidResult(1, ""), result)
+    }
+
     @Test
     fun clearSymptoms() {
         val result = JniApi().clearSymptoms()
@@ -260,6 +319,13 @@ class JNIInterfaceBootstrappedTests {
         assertEquals(JniVoidResult(1, ""), result)
     }
 
+    @Test
+    fun submitSymptomsWithoutInputsHasNothingToSend() {
+        JniApi().clearSymptoms()
+        val result = JniApi().submitSymptomsWithOutcome()
+        assertEquals(JniResult(1, "", """{"outcome":"nothing_to_send"}"""), result)
+    }
+
     // TODO more detailed tests, e.g. for each supported enum string (probably it makes sense to add
     // TODO constants in the app)
 }
@@ -3,11 +3,20 @@ package org.coepi.core
 import android.content.Context
 import androidx.test.ext.junit.runners.AndroidJUnit4
 import kotlinx.coroutines.ExperimentalCoroutinesApi
+import org.coepi.core.domain.model.AlertDebugInfo
+import org.coepi.core.domain.model.CoughSeverity
+import org.coepi.core.domain.model.FeverSeverity
+import org.coepi.core.domain.model.UnixTime
+import org.coepi.core.domain.model.UserInput.Some
 import org.coepi.core.jni.JniAlert
 import org.coepi.core.jni.JniAlertsArrayResult
 import org.coepi.core.jni.JniApi
 import org.coepi.core.jni.JniOneAlertResult
 import org.coepi.core.jni.JniPublicSymptoms
+import org.coepi.core.jni.JniResult
+import org.coepi.core.services.alertsFromJson
+import org.json.JSONArray
+import org.json.JSONObject
 import org.junit.Assert.assertEquals
 import org.junit.Before
 import org.junit.Test
@@ -40,15 +49,18 @@ class JNIInterfaceTests {
                         reportTime = 234324,
                         earliestSymptomTime = 1590356601,
                         feverSeverity = 1,
-                        coughSeverity = 3,
+                        coughSeverity = 2,
                         breathlessness = true,
                         muscleAches = true,
                         lossSmellOrTaste = false,
                         diarrhea = false,
                         runnyNose = true,
                         other = false,
-                        noSymptoms = true
-                    ), 1592567315, 1592567335, 1.2f, 2.1f, false
+                        noSymptoms = true,
+                        memoVersion = 1,
+                        reportKind = 0
+                    ), 1592567315, 1592567335, 1.2f, 2.1f, 1.5f, 1.9f, false, false,
+                    1592481515, 1592567915, "0.1.0", null, -1, false
                 )
             ),
             value
@@ -67,34 +79,133 @@ class JNIInterfaceTests {
                             reportTime = 131321,
                             earliestSymptomTime = 1590356601,
                             feverSeverity = 1,
-                            coughSeverity = 3,
+                            coughSeverity = 2,
                             breathlessness = true,
                             muscleAches = true,
                             lossSmellOrTaste = false,
                             diarrhea = false,
                             runnyNose = true,
                             other = false,
-                            noSymptoms = true
-                        ), 1592567315, 1592567335, 1.2f, 2.1f, false
+                            noSymptoms = true,
+                            memoVersion = 1,
+                            reportKind = 0
+                        ), 1592567315, 1592567335, 1.2f, 2.1f, 1.5f, 1.9f, false, false,
+                        1592481515, 1592567915, "0.1.0", null, -1, false
                     ),
                     JniAlert(
                         "343356", "224", JniPublicSymptoms(
                             reportTime = 32516899200,
                             earliestSymptomTime = 1590356601,
                             feverSeverity = 1,
-                            coughSeverity = 3,
+                            coughSeverity = 2,
                             breathlessness = true,
                             muscleAches = true,
                             lossSmellOrTaste = false,
                             diarrhea = false,
                             runnyNose = true,
                             other = false,
-                            noSymptoms = true
-                        ), 1592567315, 1592567335, 1.2f, 2.1f, false
+                            noSymptoms = true,
+                            memoVersion = 1,
+                            reportKind = 0
+                        ), 1592567315, 1592567335, 1.2f, 2.1f, 1.5f, 1.9f, false, false,
+                        1592481515, 1592567915, "0.1.0", null, -1, false
                     )
                 )
             ),
             value
         )
     }
+
+    @Test
+    fun testFetchManyAlertsDoesntOverflowLocalReferences() {
+        val value = JniApi().testReturnManyAlerts(1000)
+        assertEquals(1, value.status)
+        assertEquals(1000, value.obj.size)
+        assertEquals("999", value.obj.last().id)
+    }
+
+    @Test
+    fun testJsonResultWithValue() {
+        val value = JniApi().testReturnJsonResult()
+        assertEquals(1, value.status)
+        assertEquals("", value.message)
+
+        val alerts = alertsFromJson(value.json!!)
+        assertEquals(1, alerts.size)
+        val alert = alerts.first()
+        assertEquals("123", alert.id)
+        assertEquals(UnixTime.fromValue(234324), alert.reportTime)
+        assertEquals(Some(UnixTime.fromValue(1590356601)), alert.earliestSymptomTime)
+        assertEquals(FeverSeverity.MILD, alert.feverSeverity)
+        assertEquals(CoughSeverity.DRY, alert.coughSeverity)
+        assertEquals(UnixTime.fromValue(1592481515), alert.reportCoverageStart)
+        assertEquals(AlertDebugInfo("0.1.0", null), alert.debugInfo)
+    }
+
+    @Test
+    fun testJsonResultWithError() {
+        val value = JniApi().testReturnJsonErrorResult()
+        assertEquals(JniResult(6, "Not found", null), value)
+    }
+
+    @Test
+    fun testJsonResultWithoutValue() {
+        val value = JniApi().testReturnJsonEmptyResult()
+        assertEquals(JniResult(1, "", null), value)
+    }
+
+    @Test
+    fun testGroupExposures() {
+        val windows = """[
+            {"start": 1000, "end": 1001, "min": 0.5, "avg": 1.0, "count": 1},
+            {"start": 1500, "end": 1501, "min": 1.0, "avg": 2.0, "count": 3},
+            {"start": 5000, "end": 5001, "min": 1.0, "avg": 1.0, "count": 1}
+        ]"""
+        val value = JniApi().groupExposures(windows, 1000)
+        assertEquals(1, value.status)
+
+        val groups = JSONArray(value.json!!)
+        assertEquals(2, groups.length())
+        assertEquals(1000L, groups.getJSONObject(0).getLong("start"))
+        assertEquals(1501L, groups.getJSONObject(0).getLong("end"))
+        assertEquals(4, groups.getJSONObject(0).getInt("count"))
+    }
+
+    @Test
+    fun testGroupExposuresWithNegativeThresholdFails() {
+        val value = JniApi().groupExposures("[]", -1)
+        assertEquals(4, value.status)
+    }
+
+    @Test
+    fun testMemoRoundTrip() {
+        // Memo of the core's verification report
+        val memoHex = "01009a6ab95e000000009a6ab95e000000002101"
+        val report = JniApi().decodeMemo(memoHex)
+        assertEquals(1, report.status)
+        val symptoms = JSONObject(report.json!!)
+        assertEquals(true, symptoms.getBoolean("breathlessness"))
+        assertEquals("Serious", symptoms.getString("fever_severity"))
+
+        val memo = JniApi().encodeMemo(report.json!!)
+        assertEquals(JniResult(1, "", "\"$memoHex\""), memo)
+    }
+
+    @Test
+    fun testDecodeInvalidMemoFails() {
+        val value = JniApi().decodeMemo("0100")
+        assertEquals(4, value.status)
+    }
+
+    // The samples are the golden files in conformance/ (tests run against debug builds of the core)
+    @Test
+    fun testGetConformanceSamples() {
+        val value = JniApi().getConformanceSamples()
+        assertEquals(1, value.status)
+
+        val samples = JSONObject(value.json!!)
+        assertEquals("sent", samples.getJSONObject("submit_outcome_sent").getString("outcome"))
+        val alert = samples.getJSONObject("alert")
+        assertEquals(alert.getString("report_id") + "-1590528300", alert.getString("id"))
+    }
 }
@@ -22,9 +22,77 @@ data class Alert(
     var contactEnd: UnixTime,
     var minDistance: Length,
     var avgDistance: Length,
-    var isRead: Boolean
+    // Approximated, less sensitive to a single noisy close sample. Min / avg distance for alerts
+    // stored by older versions.
+    var p10Distance: Length,
+    var medianDistance: Length,
+    var isRead: Boolean,
+    val memoVersion: Int,
+    val reportKind: ReportKind,
+    val hasRawReport: Boolean,
+    // When the reporter was broadcasting. Null for alerts stored by older versions.
+    val reportCoverageStart: UnixTime?,
+    val reportCoverageEnd: UnixTime?,
+    val debugInfo: AlertDebugInfo,
+    // Hidden until then. Null if not snoozed.
+    val snoozedUntil: UnixTime?,
+    // A later "no symptoms" report of the reporter retracted the symptoms
+    val retracted: Boolean
 ) : Parcelable
 
+// Versions of the client that created the alert, for support. Null for alerts stored by older versions.
+@Parcelize
+data class AlertDebugInfo(
+    val coreVersion: String?,
+    val appVersion: String?
+) : Parcelable
+
+// Alerts of one report (encountered person), most recent exposure first
+@Parcelize
+data class ReportAlerts(
+    val reportId: String,
+    val alerts: List<Alert>
+) : Parcelable
+
+// Not deleted alerts
+@Parcelize
+data class AlertSummary(
+    val total: Int,
+    val unread: Int,
+    val lastExposureEnd: UnixTime?,
+    val exposuresLast14Days: Int
+) : Parcelable
+
+// Minutes of exposure of not deleted alerts, by min distance (10th percentile distance if configured):
+// close < 2m, medium < 4m, far
+@Parcelize
+data class ExposureMinutes(
+    val close: Long,
+    val medium: Long,
+    val far: Long
+) : Parcelable
+
+// What the report is based on. Test results are reserved: not created by the apps yet.
+enum class ReportKind {
+    SYMPTOMS, TEST_RESULT, UNKNOWN
+}
+
+// Same numbering as the core's database and FFI (ReportKind::as_u8)
+fun toReportKind(int: Int): ReportKind = when (int) {
+    0 -> ReportKind.SYMPTOMS
+    1 -> ReportKind.TEST_RESULT
+    2 -> ReportKind.UNKNOWN
+    else -> error("Invalid value: $int")
+}
+
+// Name of the variant in the core's JSON
+fun reportKindFromJson(name: String): ReportKind = when (name) {
+    "Symptoms" -> ReportKind.SYMPTOMS
+    "TestResult" -> ReportKind.TEST_RESULT
+    "Unknown" -> ReportKind.UNKNOWN
+    else -> error("Invalid value: $name")
+}
+
 enum class FeverSeverity {
     NONE, MILD, SERIOUS
 }
@@ -46,17 +114,35 @@ fun toFeverSeverity(int: Int): FeverSeverity = when (int) {
     else -> error("Invalid value: $int")
 }
 
+// Name of the variant in the core's JSON
+fun feverSeverityFromJson(name: String): FeverSeverity = when (name) {
+    "None" -> FeverSeverity.NONE
+    "Mild" -> FeverSeverity.MILD
+    "Serious" -> FeverSeverity.SERIOUS
+    else -> error("Invalid value: $name")
+}
+
+// Same numbering as the core's memos and database (CoughSeverity::as_u8): dry is 2, wet 3
 fun CoughSeverity.toInt(): Int = when (this) {
     CoughSeverity.NONE -> 0
     CoughSeverity.EXISTING -> 1
-    CoughSeverity.WET -> 2
-    CoughSeverity.DRY -> 3
+    CoughSeverity.DRY -> 2
+    CoughSeverity.WET -> 3
 }
 
 fun toCoughSeverity(int: Int): CoughSeverity = when (int) {
     0 -> CoughSeverity.NONE
     1 -> CoughSeverity.EXISTING
-    2 -> CoughSeverity.WET
-    3 -> CoughSeverity.DRY
+    2 -> CoughSeverity.DRY
+    3 -> CoughSeverity.WET
     else -> error("Invalid value: $int")
 }
+
+// Name of the variant in the core's JSON
+fun coughSeverityFromJson(name: String): CoughSeverity = when (name) {
+    "None" -> CoughSeverity.NONE
+    "Existing" -> CoughSeverity.EXISTING
+    "Wet" -> CoughSeverity.WET
+    "Dry" -> CoughSeverity.DRY
+    else -> error("Invalid value: $name")
+}
@@ -11,22 +11,147 @@ class JniApi {
         System.loadLibrary("coepi_core")
     }
 
+    // configJson: JSON object with the CoreConfig fields (e.g. app_version). Missing fields use defaults.
+    // Invalid values are status 9, with a JSON array of {field, reason} (all the invalid fields) as message.
+    // JSON object with the db migrations run on bootstrap: from_version, to_version,
+    // steps (version, duration_ms, rows_affected, outcome: applied / failed / pending),
+    // and keys_reset: null, or why the stored keys were corrupted and replaced with new ones
+    // (unreadable_authorization_key / unreadable_tck / tck_key_mismatch). If set, past reports can't be
+    // extended: inform the user.
     external fun bootstrapCore(
-        dbPath: String, level: String, coepiOnly: Boolean, logCallback: JniLogCallback
-    ): JniVoidResult
+        dbPath: String, configJson: String, level: String, coepiOnly: Boolean,
+        logCallback: JniLogCallback
+    ): JniResult
+
+    // Stops background work (flushes pending TCNs). Call before terminating / deleting the database.
+    external fun shutdownCore(): JniVoidResult
+
+    // Uses a separate database for the profile. Pending TCNs of the previous profile are flushed.
+    external fun switchProfile(profile: String): JniVoidResult
 
     external fun clearSymptoms(): JniVoidResult
 
-    external fun fetchNewReports(): JniAlertsArrayResult
+    // JSON array with the new alerts
+    // Status 10 if the alerts couldn't be saved because the device storage is full: the app can ask the user to free space.
+    external fun fetchNewReports(): JniResult
+
+    external fun getAlertsGrouped(): JniReportAlertsArrayResult
+
+    external fun getAlertSummary(): JniAlertSummaryResult
+
+    // cursor: empty for the first page, otherwise next_cursor of the previous page.
+    // JSON object: alerts (newest first, without the snoozed ones), next_cursor (null on the last page)
+    // and total (number of alerts, only in the first page)
+    external fun getAlertsPage(cursor: String, pageSize: Int): JniResult
+
+    // Only available in debug builds of the core. JSON object with the canonical samples of the payloads
+    // by name, the same as the golden files in conformance/
+    external fun getConformanceSamples(): JniResult
+
+    // JSON object with the minutes of exposure in the last days by distance: close, medium, far
+    external fun getExposureMinutes(days: Int): JniResult
 
     external fun deleteAlert(id: String): JniVoidResult
 
+    // Deletes the alerts of the report and suppresses its future exposures. Result: number of deleted alerts
+    external fun dismissReport(reportId: String): JniIntResult
+
     external fun updateAlertIsRead(id: String, isRead: Int): JniVoidResult
 
+    // until: unix time. The alert isn't returned by fetchNewReports / getAlertsGrouped until then.
+    external fun snoozeAlert(id: String, until: Long): JniVoidResult
+
+    // Debugging: the base64 report of the alerts with the report id. Error if raw reports aren't stored:
+    // only debug builds of the core store them.
+    external fun getRawReport(reportId: String): JniStringResult
+
+    // JSON object with the last db migrations that ran steps (like bootstrapCore's), for support. Null if none.
+    external fun getLastMigrationReport(): JniResult
+
+    // Only available in debug builds of the core. The migrations bootstrapCore would run (outcome pending),
+    // without changing the database. Can be called before bootstrapCore.
+    external fun previewMigrations(dbPath: String): JniResult
+
+    // JSON array with the last reports update runs, most recent first
+    external fun getUpdateHistory(): JniResult
+
+    // Runs the due maintenance tasks (pruning, vacuum, metrics upload). Call from a periodic background job.
+    // JSON array with the task runs: time, task, duration_millis, error_code (null if the task succeeded)
+    external fun runHousekeeping(): JniResult
+
+    // Minutes since local midnight. isEnabled: 1 = true, 0 = false
+    external fun setQuietHours(
+        isEnabled: Int, startMinute: Int, endMinute: Int, utcOffsetMinutes: Int
+    ): JniVoidResult
+
+    // sigsJson: JSON array of base64 report signature prefixes. Matching reports aren't processed.
+    external fun setReportDenylist(sigsJson: String): JniVoidResult
+
+    // sigsJson: like setReportDenylist. If not empty, only matching reports are processed. The denylist wins.
+    external fun setReportAllowlist(sigsJson: String): JniVoidResult
+
+    // Whether to notify about new alerts now. False during quiet hours: the notification stays pending.
+    external fun flushPendingNotifications(): JniBooleanResult
+
+    external fun alertReferenceToken(alertId: String): JniStringResult
+
+    external fun verifyAlertReferenceToken(token: String, alertId: String): JniBooleanResult
+
+    // Posts the alert's acknowledgement (reference token, contact day and duration bucket) to the
+    // health department endpoint of the config. User triggered. Error if the endpoint isn't configured.
+    external fun submitAcknowledgement(alertId: String): JniVoidResult
+
+    // Empty if the TCN couldn't be generated (not bootstrapped or keys not readable)
     external fun generateTcn(): String
 
-    // Meters
-    external fun recordTcn(tcn: String, distance: Float): JniVoidResult
+    // JSON object: tcn (hex), expiry (unix time at which the next TCN begins)
+    external fun generateTcnWithExpiry(): JniStringResult
+
+    // tcn: hex (32 chars) or base64 (24 chars). distance: meters.
+    // time: unix time of the observation (the scan, not the delivery to the core). Error if in the future.
+    external fun recordTcn(tcn: String, distance: Float, time: Long): JniVoidResult
+
+    // Observed now (e.g. a burst of scan results). tcns and distances: same size.
+    // JSON object: accepted and invalid (malformed TCNs) observations.
+    external fun recordTcns(tcns: Array<String>, distances: FloatArray): JniResult
+
+    // Writes the observed TCNs not flushed yet, e.g. when the app goes to background or is terminated.
+    // Otherwise they're written periodically.
+    external fun flushObservedTcns(): JniVoidResult
+
+    // JSON object with the counts of stored TCNs: total, matched, unmatched,
+    // and of TCNs rejected as malformed since bootstrap: malformed_tcn_observations
+    external fun getTcnStats(): JniResult
+
+    // JSON object with what's stored on the device and when it's deleted:
+    // observed_tcns (count, oldest_contact_start, newest_contact_end), alerts (active, deleted),
+    // keys (tcn_key, authorization_key, alert_token_key: whether they exist),
+    // retention (observed_tcns_days, alerts_days, expired_report_alerts_days)
+    // and housekeeping (array of {task, due}: unix time of the next run)
+    external fun getPrivacyReport(): JniResult
+
+    // Performance debugging (e.g. battery drain), cheap enough to call every few seconds. JSON object:
+    // tcn_batch_size (TCNs not flushed yet), log_queue (depth, dropped), fetch (started_at,
+    // running_secs; null if no fetch is running), matching_threads (null without a thread pool)
+    // and housekeeping (array of {task, time}: unix time of the last successful run, null if never)
+    external fun getRuntimeStats(): JniResult
+
+    // Debugging: JSON array of the last raw observations passed to recordTcn, oldest first:
+    // tcn_prefix (hex, 4 bytes), distance, time. Error if not enabled in the config
+    // (recording.debug_recent_observations).
+    external fun getRecentObservationsDebug(): JniResult
+
+    // Regroups exposure windows (JSON array of {start, end, min, avg, count}) with the threshold.
+    // json: the measurements of the groups, in the same format. Doesn't access the database.
+    external fun groupExposures(windowsJson: String, thresholdSeconds: Long): JniResult
+
+    // Memo (hex) of the report (PublicSymptoms JSON), like created by the core. Doesn't access the
+    // database.
+    external fun encodeMemo(reportJson: String): JniResult
+
+    // Report (PublicSymptoms JSON) of the memo (hex), like decoded by the core. Doesn't access the
+    // database.
+    external fun decodeMemo(memoHex: String): JniResult
 
     // TODO test:
     external fun setBreathlessnessCause(cause: String): JniVoidResult
@@ -37,6 +162,9 @@ class JniApi {
 
     external fun setCoughType(coughType: String): JniVoidResult
 
+    // time: unix time. Not in the future and at most 90 days ago.
+    external fun setEarliestSymptomDate(isSet: Int, time: Long): JniVoidResult
+
     external fun setEarliestSymptomStartedDaysAgo(isSet: Int, days: Int): JniVoidResult
 
     external fun setFeverDays(isSet: Int, days: Int): JniVoidResult
@@ -47,10 +175,19 @@ class JniApi {
 
     external fun setFeverTakenTemperatureToday(isSet: Int, taken: Int): JniVoidResult
 
-    external fun setSymptomIds(ids: String): JniVoidResult
+    // ids: JSON array of strings or comma separated.
+    // Result: JSON object with the applied ids (accepted) and the dropped tokens (rejected)
+    external fun setSymptomIds(ids: String): JniStringResult
 
     external fun submitSymptoms(): JniVoidResult
 
+    // Like submitSymptoms. JSON object with the outcome: "sent" (with the "receipt"), "nothing_to_send",
+    // or "rejected" (with the "http_status" and "reason"). A rejected report isn't an error.
+    external fun submitSymptomsWithOutcome(): JniResult
+
+    // Resubmits the last submitted symptoms with a corrected earliest symptom date
+    external fun resubmitWithEarliestSymptom(daysAgo: Int): JniVoidResult
+
     // Tests ////////////////////////////////////////////////////////////////////////
 
     // Basic
@@ -73,6 +210,23 @@ class JniApi {
 
     external fun testReturnMultipleAlerts(): JniAlertsArrayResult
 
+    external fun testReturnManyAlerts(count: Int): JniAlertsArrayResult
+
+    // json: array with an alert
+    external fun testReturnJsonResult(): JniResult
+
+    // Not found error
+    external fun testReturnJsonErrorResult(): JniResult
+
+    // Success without value
+    external fun testReturnJsonEmptyResult(): JniResult
+
+    // Only in core builds with the test-support feature.
+    // reportsJson: array of base64 reports. observedTcnsJson: array of
+    // {tcn (hex or base64), contact_start, contact_end, min_distance, avg_distance, total_count}.
+    // Returns the alerts JSON, without touching the database or network.
+    external fun matchReportsForTest(reportsJson: String, observedTcnsJson: String): JniStringResult
+
     /////////////////////////////////////////////////////////////////////////////////
 }
 
@@ -98,11 +252,37 @@ open class JniLogCallback(private val coreLogger: CoreLogger) {
     }
 }
 
+// Result of any type: json is the value serialized by the core.
+// Null for errors and results without value.
+data class JniResult(
+    val status: Int,
+    val message: String,
+    val json: String?
+)
+
 data class JniVoidResult(
     val status: Int,
     val message: String
 )
 
+data class JniStringResult(
+    val status: Int,
+    val message: String,
+    val obj: String
+)
+
+data class JniBooleanResult(
+    val status: Int,
+    val message: String,
+    val obj: Boolean
+)
+
+data class JniIntResult(
+    val status: Int,
+    val message: String,
+    val obj: Int
+)
+
 data class JniOneAlertResult(
     val status: Int,
     val message: String,
@@ -135,6 +315,71 @@ data class JniAlertsArrayResult(
     }
 }
 
+data class JniReportAlertsArrayResult(
+    val status: Int,
+    val message: String,
+    val obj: Array<JniReportAlerts>
+) {
+    override fun equals(other: Any?): Boolean {
+        if (this === other) return true
+        if (javaClass != other?.javaClass) return false
+
+        other as JniReportAlertsArrayResult
+
+        if (status != other.status) return false
+        if (message != other.message) return false
+        if (!obj.contentEquals(other.obj)) return false
+
+        return true
+    }
+
+    override fun hashCode(): Int {
+        var result = status
+        result = 31 * result + message.hashCode()
+        result = 31 * result + obj.contentHashCode()
+        return result
+    }
+}
+
+data class JniAlertSummaryResult(
+    val status: Int,
+    val message: String,
+    val obj: JniAlertSummary
+)
+
+data class JniAlertSummary(
+    val total: Int,
+    val unread: Int,
+    val lastExposureEnd: Long, // -1 if there are no alerts
+    val exposuresLast14Days: Int
+)
+
+data class JniReportAlerts(
+    val reportId: String,
+    val report: JniPublicSymptoms,
+    val alerts: Array<JniAlert>
+) {
+    override fun equals(other: Any?): Boolean {
+        if (this === other) return true
+        if (javaClass != other?.javaClass) return false
+
+        other as JniReportAlerts
+
+        if (reportId != other.reportId) return false
+        if (report != other.report) return false
+        if (!alerts.contentEquals(other.alerts)) return false
+
+        return true
+    }
+
+    override fun hashCode(): Int {
+        var result = reportId.hashCode()
+        result = 31 * result + report.hashCode()
+        result = 31 * result + alerts.contentHashCode()
+        return result
+    }
+}
+
 data class JniAlert(
     var id: String,
     var reportId: String,
@@ -143,7 +388,16 @@ data class JniAlert(
     var contactEnd: Long,
     var minDistance: Float,
     var avgDistance: Float,
-    var isRead: Boolean
+    var p10Distance: Float,
+    var medianDistance: Float,
+    var isRead: Boolean,
+    var hasRawReport: Boolean,
+    var reportCoverageStart: Long, // -1 if not known
+    var reportCoverageEnd: Long, // -1 if not known
+    var coreVersion: String?, // null if not known
+    var appVersion: String?, // null if not known
+    var snoozedUntil: Long, // -1 if not snoozed
+    var retracted: Boolean
 )
 
 data class JniPublicSymptoms(
@@ -157,7 +411,9 @@ data class JniPublicSymptoms(
     val diarrhea: Boolean,
     val runnyNose: Boolean,
     val other: Boolean,
-    val noSymptoms: Boolean
+    val noSymptoms: Boolean,
+    val memoVersion: Int,
+    val reportKind: Int // Numbering of the core's ReportKind::as_u8
 )
 
 fun JniVoidResult.asResult(): Result<Unit, Throwable> = when (status) {
@@ -168,5 +424,17 @@ fun JniVoidResult.asResult(): Result<Unit, Throwable> = when (status) {
 fun JniVoidResult.statusDescription(): String =
     statusDescription(status, message)
 
+// parse: maps the JSON to the value. A success without JSON is a failure, as the value is missing.
+fun <T> JniResult.asResult(parse: (String) -> T): Result<T, Throwable> = when (status) {
+    1 -> when (json) {
+        null -> Failure(Throwable("Missing value. ${statusDescription()}"))
+        else -> Success(parse(json))
+    }
+    else -> Failure(Throwable(statusDescription()))
+}
+
+fun JniResult.statusDescription(): String =
+    statusDescription(status, message)
+
 private fun statusDescription(status: Int, message: String): String =
     "Status: $status Message: $message"
@@ -4,42 +4,180 @@ import org.coepi.core.domain.common.Result
 import org.coepi.core.domain.common.Result.Failure
 import org.coepi.core.domain.common.Result.Success
 import org.coepi.core.domain.model.Alert
+import org.coepi.core.domain.model.AlertDebugInfo
+import org.coepi.core.domain.model.AlertSummary
+import org.coepi.core.domain.model.ExposureMinutes
 import org.coepi.core.domain.model.Length
 import org.coepi.core.domain.model.LengthtUnit.METERS
+import org.coepi.core.domain.model.ReportAlerts
 import org.coepi.core.domain.model.UnixTime
 import org.coepi.core.domain.model.UserInput.None
 import org.coepi.core.domain.model.UserInput.Some
+import org.coepi.core.domain.model.coughSeverityFromJson
+import org.coepi.core.domain.model.feverSeverityFromJson
+import org.coepi.core.domain.model.reportKindFromJson
 import org.coepi.core.domain.model.toCoughSeverity
 import org.coepi.core.domain.model.toFeverSeverity
+import org.coepi.core.domain.model.toReportKind
 import org.coepi.core.jni.JniAlert
-import org.coepi.core.jni.JniAlertsArrayResult
 import org.coepi.core.jni.JniApi
 import org.coepi.core.jni.asResult
+import org.json.JSONArray
+import org.json.JSONObject
 
 interface AlertsApi {
     fun fetchNewAlerts(): Result<List<Alert>, Throwable>
+    fun alertsGrouped(): Result<List<ReportAlerts>, Throwable>
+    fun alertSummary(): Result<AlertSummary, Throwable>
+    // Exposure minutes of the last days, by distance
+    fun exposureMinutes(days: Int): Result<ExposureMinutes, Throwable>
     fun deleteAlert(id: String): Result<Unit, Throwable>
+    // Deletes all the alerts of the report, and future ones. Returns the number of deleted alerts.
+    fun dismissReport(reportId: String): Result<Int, Throwable>
     fun updateIsRead(id: String, isRead: Boolean): Result<Unit, Throwable>
+    // Hides the alert until the time, without deleting it
+    fun snoozeAlert(id: String, until: UnixTime): Result<Unit, Throwable>
+    // Debugging. Fails in release builds of the core, which don't store raw reports.
+    fun rawReport(reportId: String): Result<String, Throwable>
+    // JSON, for diagnostics
+    fun updateHistory(): Result<String, Throwable>
+    // Short token referencing the alert, e.g. to read to a call center
+    fun alertReferenceToken(alertId: String): Result<String, Throwable>
+    fun verifyAlertReferenceToken(token: String, alertId: String): Result<Boolean, Throwable>
+    // Exports the acknowledged alert to the health department endpoint of the config. User triggered.
+    fun submitAcknowledgement(alertId: String): Result<Unit, Throwable>
+    // Minutes since local midnight. New alerts aren't notified between start and end.
+    fun setQuietHours(
+        isEnabled: Boolean, startMinute: Int, endMinute: Int, utcOffsetMinutes: Int
+    ): Result<Unit, Throwable>
+    // Whether to notify about new alerts now. Call after fetchNewAlerts and when the quiet hours end.
+    fun flushPendingNotifications(): Result<Boolean, Throwable>
+    // Base64 signature prefixes of reports verified by the app. Denied reports aren't processed.
+    fun setReportDenylist(signaturePrefixes: List<String>): Result<Unit, Throwable>
+    // If not empty, only the reports with these prefixes are processed. The denylist wins.
+    fun setReportAllowlist(signaturePrefixes: List<String>): Result<Unit, Throwable>
 }
 
 class AlertsFetcherImpl(private val api: JniApi) : AlertsApi {
 
-    override fun fetchNewAlerts(): Result<List<Alert>, Throwable> {
-        val result = api.fetchNewReports()
+    override fun fetchNewAlerts(): Result<List<Alert>, Throwable> =
+        api.fetchNewReports().asResult { alertsFromJson(it) }
+
+    override fun alertsGrouped(): Result<List<ReportAlerts>, Throwable> {
+        val result = api.getAlertsGrouped()
+        return when (result.status) {
+            1 -> Success(result.obj.map { reportAlerts ->
+                ReportAlerts(
+                    reportId = reportAlerts.reportId,
+                    alerts = reportAlerts.alerts.map { it.toAlert() }
+                )
+            })
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
+    override fun alertSummary(): Result<AlertSummary, Throwable> {
+        val result = api.getAlertSummary()
         return when (result.status) {
-            1 -> Success(result.obj.map { it.toAlert() })
-            else -> Failure(Throwable(result.statusDescription()))
+            1 -> Success(
+                AlertSummary(
+                    total = result.obj.total,
+                    unread = result.obj.unread,
+                    lastExposureEnd = when {
+                        result.obj.lastExposureEnd == -1L -> null
+                        result.obj.lastExposureEnd < -1L ->
+                            error("Invalid lastExposureEnd: ${result.obj.lastExposureEnd}")
+                        else -> UnixTime.fromValue(result.obj.lastExposureEnd)
+                    },
+                    exposuresLast14Days = result.obj.exposuresLast14Days
+                )
+            )
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
         }
     }
 
+    override fun exposureMinutes(days: Int): Result<ExposureMinutes, Throwable> =
+        api.getExposureMinutes(days).asResult {
+            val json = JSONObject(it)
+            ExposureMinutes(
+                close = json.getLong("close"),
+                medium = json.getLong("medium"),
+                far = json.getLong("far")
+            )
+        }
+
     override fun deleteAlert(id: String): Result<Unit, Throwable> =
         api.deleteAlert(id).asResult()
 
+    override fun dismissReport(reportId: String): Result<Int, Throwable> {
+        val result = api.dismissReport(reportId)
+        return when (result.status) {
+            1 -> Success(result.obj)
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
     override fun updateIsRead(id: String, isRead: Boolean): Result<Unit, Throwable> =
         api.updateAlertIsRead(id, if (isRead) 1 else 0).asResult()
 
-    private fun JniAlertsArrayResult.statusDescription(): String =
-        statusDescription(status, message)
+    override fun snoozeAlert(id: String, until: UnixTime): Result<Unit, Throwable> =
+        api.snoozeAlert(id, until.value).asResult()
+
+    override fun rawReport(reportId: String): Result<String, Throwable> {
+        val result = api.getRawReport(reportId)
+        return when (result.status) {
+            1 -> Success(result.obj)
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
+    override fun updateHistory(): Result<String, Throwable> =
+        api.getUpdateHistory().asResult { it }
+
+    override fun alertReferenceToken(alertId: String): Result<String, Throwable> {
+        val result = api.alertReferenceToken(alertId)
+        return when (result.status) {
+            1 -> Success(result.obj)
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
+    override fun verifyAlertReferenceToken(
+        token: String,
+        alertId: String
+    ): Result<Boolean, Throwable> {
+        val result = api.verifyAlertReferenceToken(token, alertId)
+        return when (result.status) {
+            1 -> Success(result.obj)
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
+    override fun submitAcknowledgement(alertId: String): Result<Unit, Throwable> =
+        api.submitAcknowledgement(alertId).asResult()
+
+    override fun setQuietHours(
+        isEnabled: Boolean,
+        startMinute: Int,
+        endMinute: Int,
+        utcOffsetMinutes: Int
+    ): Result<Unit, Throwable> =
+        api.setQuietHours(if (isEnabled) 1 else 0, startMinute, endMinute, utcOffsetMinutes)
+            .asResult()
+
+    override fun flushPendingNotifications(): Result<Boolean, Throwable> {
+        val result = api.flushPendingNotifications()
+        return when (result.status) {
+            1 -> Success(result.obj)
+            else -> Failure(Throwable(statusDescription(result.status, result.message)))
+        }
+    }
+
+    override fun setReportDenylist(signaturePrefixes: List<String>): Result<Unit, Throwable> =
+        api.setReportDenylist(JSONArray(signaturePrefixes).toString()).asResult()
+
+    override fun setReportAllowlist(signaturePrefixes: List<String>): Result<Unit, Throwable> =
+        api.setReportAllowlist(JSONArray(signaturePrefixes).toString()).asResult()
 
     private fun statusDescription(status: Int, message: String) =
         "Status: $status Message: $message"
@@ -63,6 +201,14 @@ class AlertsFetcherImpl(private val api: JniApi) : AlertsApi {
             avgDistance < 0 -> error("Invalid avg distance: $avgDistance")
             else -> Length(avgDistance, METERS)
         },
+        p10Distance = when {
+            p10Distance < 0 -> error("Invalid p10 distance: $p10Distance")
+            else -> Length(p10Distance, METERS)
+        },
+        medianDistance = when {
+            medianDistance < 0 -> error("Invalid median distance: $medianDistance")
+            else -> Length(medianDistance, METERS)
+        },
         reportTime = when {
             symptoms.reportTime < 0 -> error("Invalid report time: ${symptoms.reportTime}")
             else -> UnixTime.fromValue(symptoms.reportTime)
@@ -84,6 +230,84 @@ class AlertsFetcherImpl(private val api: JniApi) : AlertsApi {
         runnyNose = symptoms.runnyNose,
         other = symptoms.other,
         noSymptoms = symptoms.noSymptoms,
-        isRead = isRead
+        isRead = isRead,
+        memoVersion = symptoms.memoVersion,
+        reportKind = toReportKind(symptoms.reportKind),
+        hasRawReport = hasRawReport,
+        reportCoverageStart = toOptionalUnixTime(reportCoverageStart, "report coverage start"),
+        reportCoverageEnd = toOptionalUnixTime(reportCoverageEnd, "report coverage end"),
+        debugInfo = AlertDebugInfo(
+            coreVersion = coreVersion,
+            appVersion = appVersion
+        ),
+        snoozedUntil = toOptionalUnixTime(snoozedUntil, "snoozed until"),
+        retracted = retracted
     )
+
+    private fun toOptionalUnixTime(value: Long, name: String): UnixTime? = when {
+        value == -1L -> null
+        value < -1L -> error("Invalid $name: $value")
+        else -> UnixTime.fromValue(value)
+    }
+}
+
+// JSON array of alerts, as serialized by the core
+internal fun alertsFromJson(json: String): List<Alert> {
+    val array = JSONArray(json)
+    return (0 until array.length()).map { array.getJSONObject(it).toAlert() }
+}
+
+private fun JSONObject.toAlert(): Alert {
+    val symptoms = getJSONObject("symptoms")
+    val debugInfo = getJSONObject("debug_info")
+    return Alert(
+        id = getString("id"),
+        reportId = getString("report_id"),
+        contactStart = UnixTime.fromValue(getLong("contact_start")),
+        contactEnd = UnixTime.fromValue(getLong("contact_end")),
+        minDistance = Length(getDouble("min_distance").toFloat(), METERS),
+        avgDistance = Length(getDouble("avg_distance").toFloat(), METERS),
+        p10Distance = Length(getDouble("p10_distance").toFloat(), METERS),
+        medianDistance = Length(getDouble("median_distance").toFloat(), METERS),
+        reportTime = UnixTime.fromValue(symptoms.getJSONObject("report_time").getLong("value")),
+        earliestSymptomTime = when {
+            symptoms.isNull("earliest_symptom_time") -> None
+            else -> Some(
+                UnixTime.fromValue(
+                    symptoms.getJSONObject("earliest_symptom_time").getLong("value")
+                )
+            )
+        },
+        feverSeverity = feverSeverityFromJson(symptoms.getString("fever_severity")),
+        coughSeverity = coughSeverityFromJson(symptoms.getString("cough_severity")),
+        breathlessness = symptoms.getBoolean("breathlessness"),
+        muscleAches = symptoms.getBoolean("muscle_aches"),
+        lossSmellOrTaste = symptoms.getBoolean("loss_smell_or_taste"),
+        diarrhea = symptoms.getBoolean("diarrhea"),
+        runnyNose = symptoms.getBoolean("runny_nose"),
+        other = symptoms.getBoolean("other"),
+        noSymptoms = symptoms.getBoolean("no_symptoms"),
+        isRead = getBoolean("is_read"),
+        memoVersion = symptoms.getInt("memo_version"),
+        reportKind = reportKindFromJson(symptoms.getString("report_kind")),
+        hasRawReport = getBoolean("has_raw_report"),
+        reportCoverageStart = optionalUnixTime("report_coverage_start"),
+        reportCoverageEnd = optionalUnixTime("report_coverage_end"),
+        debugInfo = AlertDebugInfo(
+            coreVersion = debugInfo.optionalString("core_version"),
+            appVersion = debugInfo.optionalString("app_version")
+        ),
+        snoozedUntil = optionalUnixTime("snoozed_until"),
+        retracted = getBoolean("retracted")
+    )
+}
+
+private fun JSONObject.optionalUnixTime(name: String): UnixTime? = when {
+    isNull(name) -> null
+    else -> UnixTime.fromValue(getLong(name))
+}
+
+private fun JSONObject.optionalString(name: String): String? = when {
+    isNull(name) -> null
+    else -> getString(name)
 }
@@ -3,9 +3,11 @@ package org.coepi.core.services
 import android.content.Context
 import org.coepi.core.jni.JniApi
 import org.coepi.core.jni.JniLogCallback
+import org.json.JSONObject
 
 interface CoreBootstrapper {
-    fun bootstrap(applicationContext: Context, logger: CoreLogger)
+    // appVersion: recorded with reports and alerts, for support
+    fun bootstrap(applicationContext: Context, logger: CoreLogger, appVersion: String)
 }
 
 interface CoreLogger {
@@ -14,14 +16,16 @@ interface CoreLogger {
 
 class CoreBootstrapperImpl(private val api: JniApi) : CoreBootstrapper {
 
-    override fun bootstrap(applicationContext: Context, logger: CoreLogger) {
+    override fun bootstrap(applicationContext: Context, logger: CoreLogger, appVersion: String) {
         // getDatabasePath requires a db name, but we use need the directory
         // (to initialize multiple databases), so adding and removing a suffix.
         val dbPath = applicationContext.getDatabasePath("remove")
             .absolutePath.removeSuffix("/remove")
 
+        val configJson = JSONObject().put("app_version", appVersion).toString()
+
         val result = api.bootstrapCore(
-            dbPath, "debug", true,
+            dbPath, configJson, "debug", true,
             JniLogCallback(logger)
         )
         if (result.status != 1) {
@@ -8,10 +8,16 @@ import org.coepi.core.jni.asResult
 interface ObservedTcnsRecorder {
     // Meters
     fun recordTcn(tcn: Tcn, distance: Float): Result<Unit, Throwable>
+    // JSON with the counts of stored TCNs (total, matched by a report, unmatched)
+    // and of malformed TCNs rejected since bootstrap (malformed_tcn_observations)
+    fun tcnStats(): Result<String, Throwable>
 }
 
 class ObservedTcnsRecorderImpl(private val api: JniApi) :
     ObservedTcnsRecorder {
     override fun recordTcn(tcn: Tcn, distance: Float): Result<Unit, Throwable> =
         api.recordTcn(tcn.toHex(), distance).asResult()
+
+    override fun tcnStats(): Result<String, Throwable> =
+        api.getTcnStats().asResult { it }
 }
@@ -4,6 +4,8 @@ import com.google.gson.Gson
 import org.coepi.core.jni.JniApi
 import org.coepi.core.jni.asResult
 import org.coepi.core.domain.common.Result
+import org.coepi.core.domain.common.Result.Failure
+import org.coepi.core.domain.common.Result.Success
 import org.coepi.core.domain.model.SymptomId
 import org.coepi.core.domain.model.SymptomId.BREATHLESSNESS
 import org.coepi.core.domain.model.SymptomId.COUGH
@@ -32,6 +34,7 @@ import org.coepi.core.domain.model.SymptomInputs.Fever.TemperatureSpot.Ear
 import org.coepi.core.domain.model.SymptomInputs.Fever.TemperatureSpot.Mouth
 import org.coepi.core.domain.model.SymptomInputs.Fever.TemperatureSpot.Other
 import org.coepi.core.domain.model.Temperature
+import org.coepi.core.domain.model.UnixTime
 import org.coepi.core.domain.model.UserInput
 import java.io.Serializable
 
@@ -46,9 +49,14 @@ interface SymptomsInputManager {
     fun setFeverTakenTemperatureSpot(input: UserInput<Fever.TemperatureSpot>): Result<Unit, Throwable>
     fun setFeverHighestTemperatureTaken(input: UserInput<Temperature>): Result<Unit, Throwable>
     fun setEarliestSymptomStartedDaysAgo(input: UserInput<Int>): Result<Unit, Throwable>
+    // Alternative to setEarliestSymptomStartedDaysAgo: the last call of either wins
+    fun setEarliestSymptomDate(input: UserInput<UnixTime>): Result<Unit, Throwable>
 
     fun submitSymptoms(): Result<Unit, Throwable>
     fun clearSymptoms(): Result<Unit, Throwable>
+
+    // Resubmits the last submitted symptoms, with a corrected earliest symptom date
+    fun resubmitWithEarliestSymptom(daysAgo: Int): Result<Unit, Throwable>
 }
 
 class SymptomInputsManagerImpl(private val api: JniApi, private val gson: Gson) :
@@ -56,7 +64,11 @@ class SymptomInputsManagerImpl(private val api: JniApi, private val gson: Gson)
 
     override fun setSymptoms(inputs: Set<SymptomId>): Result<Unit, Throwable> {
         val jniIdentifiers = inputs.map { it.toJniIdentifier() }
-        return api.setSymptomIds(gson.toJson(jniIdentifiers)).asResult()
+        val result = api.setSymptomIds(gson.toJson(jniIdentifiers))
+        return when (result.status) {
+            1 -> Success(Unit)
+            else -> Failure(Throwable("Status: ${result.status} Message: ${result.message}"))
+        }
     }
 
     override fun setCoughType(input: UserInput<Cough.Type>): Result<Unit, Throwable> =
@@ -134,10 +146,19 @@ class SymptomInputsManagerImpl(private val api: JniApi, private val gson: Gson)
             api.setEarliestSymptomStartedDaysAgo(it.isSet, it.value).asResult()
         }
 
+    override fun setEarliestSymptomDate(input: UserInput<UnixTime>): Result<Unit, Throwable> =
+        when (input) {
+            is UserInput.Some -> api.setEarliestSymptomDate(1, input.value.value)
+            is UserInput.None -> api.setEarliestSymptomDate(0, -1)
+        }.asResult()
+
     override fun submitSymptoms(): Result<Unit, Throwable> = api.submitSymptoms().asResult()
 
     override fun clearSymptoms(): Result<Unit, Throwable> = api.clearSymptoms().asResult()
 
+    override fun resubmitWithEarliestSymptom(daysAgo: Int): Result<Unit, Throwable> =
+        api.resubmitWithEarliestSymptom(daysAgo).asResult()
+
     //endregion
 
     private fun <T : Serializable> UserInput<T>.toJniStringInput(f: (T) -> String): String =
@@ -1,14 +1,40 @@
 package org.coepi.core.services
 
-import org.coepi.core.jni.JniApi
+import org.coepi.core.domain.common.Result
+import org.coepi.core.domain.common.Result.Failure
+import org.coepi.core.domain.common.Result.Success
 import org.coepi.core.domain.model.Tcn
+import org.coepi.core.domain.model.UnixTime
 import org.coepi.core.extensions.hexToByteArray
+import org.coepi.core.jni.JniApi
+import org.json.JSONObject
 
 interface TcnGenerator {
     fun generateTcn(): Tcn
+
+    // TCN to broadcast now and when the next one begins. Same TCN until the expiry.
+    fun generateTcnWithExpiry(): Result<TcnWithExpiry, Throwable>
 }
 
+data class TcnWithExpiry(val tcn: Tcn, val expiry: UnixTime)
+
 class TcnGeneratorImpl(private val api: JniApi) : TcnGenerator {
     override fun generateTcn(): Tcn =
         Tcn(api.generateTcn().hexToByteArray())
+
+    override fun generateTcnWithExpiry(): Result<TcnWithExpiry, Throwable> {
+        val result = api.generateTcnWithExpiry()
+        return when (result.status) {
+            1 -> {
+                val json = JSONObject(result.obj)
+                Success(
+                    TcnWithExpiry(
+                        Tcn(json.getString("tcn").hexToByteArray()),
+                        UnixTime.fromValue(json.getLong("expiry"))
+                    )
+                )
+            }
+            else -> Failure(Throwable("Status: ${result.status} Message: ${result.message}"))
+        }
+    }
 }
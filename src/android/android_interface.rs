@@ -1,50 +1,96 @@
-use crate::database::alert_dao::AlertDao;
+use crate::database::{
+    alert_dao::{AlertDao, AlertsPage},
+    migration::MigrationReport,
+    preferences::Preferences,
+    tcn_dao::TcnDao,
+};
 use crate::reporting::symptom_inputs_manager::SymptomInputsProcessor;
-use crate::tcn_ext::tcn_keys::TcnKeys;
-use crate::tcn_recording::observed_tcn_processor::ObservedTcnProcessor;
+use crate::tcn_ext::{
+    tcn_hex::TcnHex,
+    tcn_keys::{TcnKeys, TcnWithExpiry},
+};
+use crate::tcn_recording::observed_tcn_processor::{
+    ObservedTcnProcessor, SaveBatchOutcome, TcnDiagnostics,
+};
 use crate::{
-    dependencies::{bootstrap, dependencies},
+    config::{config_violations_json, CoreConfig},
+    conformance,
+    dependencies::{self, bootstrap, dependencies, shutdown, switch_profile, BootstrapReport},
     errors::ServicesError,
     expect_log,
+    ffi_params::{invalid_param, null_param, param_str, unix_time_param, ParamKind},
+    privacy::{privacy_report, PrivacyReport},
     reporting::{
-        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms},
-        symptom_inputs::UserInput,
+        memo::{decode_memo_hex, encode_memo_json},
+        public_symptoms::PublicSymptoms,
+        symptom_inputs::{SubmitOutcome, UserInput},
     },
     reports_interval::UnixTime,
-    reports_update::reports_updater::Alert,
+    reports_update::{
+        acknowledgement::AcknowledgementService,
+        alert_reference::AlertReferences,
+        exposure::{group_exposure_windows_json, ExposureWindow},
+        quiet_hours::QuietHours,
+        reports_updater::{Alert, AlertSummary, ReportAlerts},
+    },
+    runtime_stats::{runtime_stats, RuntimeStats},
     simple_logger,
 };
 use jni::{
     objects::{GlobalRef, JClass, JObject, JString, JValue},
-    sys::{jboolean, jfloat, jint, jobject, jobjectArray, jstring},
+    sys::{jboolean, jfloat, jfloatArray, jint, jlong, jobject, jobjectArray, jstring},
     JNIEnv, JavaVM,
 };
 use log::*;
-use mpsc::Receiver;
-use simple_logger::{CoreLogLevel, CoreLogMessageThreadSafe, SENDER};
+use serde::Serialize;
+use serde_json::Value;
+use simple_logger::{CoreLogLevel, LogCallback};
 use std::str::FromStr;
-use std::{
-    sync::mpsc::{self, Sender},
-    thread,
-};
+use std::sync::Arc;
+use std::time::Instant;
 
+// Local references created while converting one array element (see set_object_array_elements)
+const ELEMENT_LOCAL_FRAME_CAPACITY: i32 = 16;
+
+// JSON object with the report of the db migrations run on bootstrap and keys_reset (see BootstrapReport)
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_bootstrapCore(
     env: JNIEnv,
     _: JClass,
     db_path_j_string: JString,
+    config_json_j_string: JString,
     log_level_j_string: JString,
     log_coepi_only: jboolean,
     log_callback: jobject,
 ) -> jobject {
-    bootstrap_core(
+    to_json_result_jobject(
+        bootstrap_core(
+            &env,
+            db_path_j_string,
+            config_json_j_string,
+            log_level_j_string,
+            log_coepi_only,
+            log_callback,
+        ),
         &env,
-        db_path_j_string,
-        log_level_j_string,
-        log_coepi_only,
-        log_callback,
     )
-    .to_void_jni(&env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_shutdownCore(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    shutdown().to_void_jni(&env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_switchProfile(
+    env: JNIEnv,
+    _: JClass,
+    profile: JString,
+) -> jobject {
+    switch_core_profile(&env, profile).to_void_jni(&env)
 }
 
 #[no_mangle]
@@ -52,30 +98,107 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_fetchNewReports(
     env: JNIEnv,
     _: JClass,
 ) -> jobject {
-    let arr = fetch_new_reports(&env);
+    to_json_result_jobject(fetch_new_reports(), &env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getAlertSummary(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    let (status, message, summary) =
+        match dependencies().and_then(|dependencies| dependencies.alert_dao.alert_summary()) {
+            Ok(summary) => (1, None, summary),
+            Err(e) => {
+                let jni_error = e.to_jni_error();
+                (
+                    jni_error.status,
+                    Some(jni_error.message),
+                    AlertSummary::default(),
+                )
+            }
+        };
+
+    let summary_j_object_res = alert_summary_to_jobject(summary, &env);
+    // If we can't create a result to send to JNI, we only can crash
+    let summary_j_object = expect_log!(
+        summary_j_object_res,
+        "Couldn't create JNI alert summary object"
+    );
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        summary_j_object,
+        "org/coepi/core/jni/JniAlertSummaryResult",
+        "Lorg/coepi/core/jni/JniAlertSummary;",
+        &env,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getExposureMinutes(
+    env: JNIEnv,
+    _: JClass,
+    days: jint,
+) -> jobject {
+    to_json_result_jobject(
+        dependencies()
+            .and_then(|dependencies| dependencies.alert_dao.exposure_minutes(days as u32)),
+        &env,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getAlertsGrouped(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    let arr = dependencies()
+        .and_then(|dependencies| dependencies.alert_dao.all_grouped())
+        .and_then(|report_alerts| report_alerts_to_jobject_array(report_alerts, &env));
 
     match arr {
-        Ok(a) => to_alerts_result_jobject(1, None, a, &env),
+        Ok(a) => to_report_alerts_result_jobject(1, None, a, &env),
         Err(e) => {
-            // If there's an error, return a JNI object with error status and an empty JNI array
-            // TODO it may be possible to avoid empty array by making array in JniAlertsArrayResult optional
             let jni_error = e.to_jni_error();
-            let empty_alerts_jobject_array = alerts_to_jobject_array(vec![], &env);
+            let empty_array_res = report_alerts_to_jobject_array(vec![], &env);
             // If the creation of the empty array fails, we've to crash, because we've to return an array.
-            let empty_alerts_array = expect_log!(
-                empty_alerts_jobject_array,
+            let empty_array = expect_log!(
+                empty_array_res,
                 "Critical: Failed instantiating empty error object"
             );
-            to_alerts_result_jobject(
+            to_report_alerts_result_jobject(
                 jni_error.status,
                 Some(jni_error.message.as_ref()),
-                empty_alerts_array,
+                empty_array,
                 &env,
             )
         }
     }
 }
 
+// JSON object with the alerts of the page (see AlertsPage)
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getAlertsPage(
+    env: JNIEnv,
+    _: JClass,
+    cursor: JString,
+    page_size: jint,
+) -> jobject {
+    to_json_result_jobject(get_alerts_page(&env, cursor, page_size), &env)
+}
+
+// Debug builds only: JSON object with the canonical samples of the payloads, by name (see conformance.rs)
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getConformanceSamples(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(conformance::conformance_samples(), &env)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_deleteAlert(
     env: JNIEnv,
@@ -85,6 +208,22 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_deleteAlert(
     delete_alert(&env, id).to_void_jni(&env)
 }
 
+// Result: number of deleted alerts
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_dismissReport(
+    env: JNIEnv,
+    _: JClass,
+    report_id: JString,
+) -> jobject {
+    match dismiss_report(&env, report_id) {
+        Ok(count) => jni_int_result(1, None, count as i32, &env),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            jni_int_result(jni_error.status, Some(&jni_error.message), 0, &env)
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_updateAlertIsRead(
     env: JNIEnv,
@@ -95,14 +234,352 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_updateAlertIsRead(
     update_alert_is_read(&env, id, is_read).to_void_jni(&env)
 }
 
+// until: unix time. The alert isn't returned by fetchNewReports / getAlertsGrouped until then.
+// Regroups exposure windows (JSON array of {start, end, min, avg, count}) with the threshold.
+// json: the measurements of the groups, in the same format. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_groupExposures(
+    env: JNIEnv,
+    _: JClass,
+    windows_json: JString,
+    threshold_seconds: jlong,
+) -> jobject {
+    to_json_result_jobject(group_exposures(&env, windows_json, threshold_seconds), &env)
+}
+
+// json: the memo (hex) of the report (PublicSymptoms JSON), like created by the core. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_encodeMemo(
+    env: JNIEnv,
+    _: JClass,
+    report_json: JString,
+) -> jobject {
+    to_json_result_jobject(encode_memo(&env, report_json), &env)
+}
+
+// json: the report (PublicSymptoms) of the memo (hex), like decoded by the core. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_decodeMemo(
+    env: JNIEnv,
+    _: JClass,
+    memo_hex: JString,
+) -> jobject {
+    to_json_result_jobject(decode_memo(&env, memo_hex), &env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_snoozeAlert(
+    env: JNIEnv,
+    _: JClass,
+    id: JString,
+    until: jlong,
+) -> jobject {
+    snooze_alert(&env, id, until).to_void_jni(&env)
+}
+
+// Error (status 5) if raw reports aren't stored, e.g. in release builds
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getRawReport(
+    env: JNIEnv,
+    _: JClass,
+    report_id: JString,
+) -> jobject {
+    let (status, message, report) = match get_raw_report(&env, report_id) {
+        Ok(report) => (1, None, report),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            (jni_error.status, Some(jni_error.message), "".to_owned())
+        }
+    };
+
+    let report_j_string_res = env.new_string(report);
+    // If we can't create a result to send to JNI, we only can crash
+    let report_j_string = expect_log!(report_j_string_res, "Couldn't create JNI report string");
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        JObject::from(report_j_string),
+        "org/coepi/core/jni/JniStringResult",
+        "Ljava/lang/String;",
+        &env,
+    )
+}
+
+// JSON object with the last db migrations that ran steps (MigrationReport), null if there weren't any
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getLastMigrationReport(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(
+        dependencies().map(|dependencies| {
+            dependencies
+                .reports_updater
+                .preferences
+                .last_migration_report()
+        }),
+        &env,
+    )
+}
+
+// Debug builds only: the db migrations bootstrap would run (dry run). Can be called before bootstrap.
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_previewMigrations(
+    env: JNIEnv,
+    _: JClass,
+    db_path: JString,
+) -> jobject {
+    to_json_result_jobject(preview_migrations(&env, db_path), &env)
+}
+
+// JSON array of the last reports update runs (UpdateMetrics), most recent first
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getUpdateHistory(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(
+        dependencies().and_then(|dependencies| dependencies.reports_updater.update_history()),
+        &env,
+    )
+}
+
+// Runs the due housekeeping tasks (deleting old data, database maintenance, metrics upload).
+// To call from a WorkManager worker. JSON array of the task runs.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_runHousekeeping(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(
+        dependencies().map(|dependencies| dependencies.housekeeper.run_due(None)),
+        &env,
+    )
+}
+
+// Minutes since local midnight. New alerts aren't notified between start and end (see flushPendingNotifications).
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setQuietHours(
+    env: JNIEnv,
+    _: JClass,
+    enabled: jint,
+    start_minute: jint,
+    end_minute: jint,
+    utc_offset_minutes: jint,
+) -> jobject {
+    dependencies()
+        .and_then(|dependencies| {
+            dependencies.reports_updater.set_quiet_hours(QuietHours {
+                enabled: enabled == 1,
+                start_minute: start_minute as u32,
+                end_minute: end_minute as u32,
+                utc_offset_minutes,
+            })
+        })
+        .to_void_jni(&env)
+}
+
+// sigsJson: JSON array of base64 report signature prefixes. Matching reports are skipped by the next updates.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setReportDenylist(
+    env: JNIEnv,
+    _: JClass,
+    sigs_json: JString,
+) -> jobject {
+    set_report_denylist(&env, sigs_json).to_void_jni(&env)
+}
+
+// sigsJson: like setReportDenylist. If not empty, only matching reports are processed. The denylist wins.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setReportAllowlist(
+    env: JNIEnv,
+    _: JClass,
+    sigs_json: JString,
+) -> jobject {
+    set_report_allowlist(&env, sigs_json).to_void_jni(&env)
+}
+
+// Whether the app should notify about new alerts now. Call after fetchNewReports and when the quiet hours end.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_flushPendingNotifications(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    match dependencies() {
+        Ok(dependencies) => {
+            let should_notify = dependencies.reports_updater.flush_pending_notifications();
+            jni_boolean_result(1, None, should_notify, &env)
+        }
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            jni_boolean_result(jni_error.status, Some(&jni_error.message), false, &env)
+        }
+    }
+}
+
+// Test support: alerts (JSON) for the reports and observed TCNs (JSON), without db or network.
+// See test_support::match_reports_json.
+#[cfg(feature = "test-support")]
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_matchReportsForTest(
+    env: JNIEnv,
+    _: JClass,
+    reports_json: JString,
+    observed_tcns_json: JString,
+) -> jobject {
+    let (status, message, alerts) =
+        match match_reports_for_test(&env, reports_json, observed_tcns_json) {
+            Ok(alerts) => (1, None, alerts),
+            Err(e) => {
+                let jni_error = e.to_jni_error();
+                (jni_error.status, Some(jni_error.message), "[]".to_owned())
+            }
+        };
+
+    let alerts_j_string_res = env.new_string(alerts);
+    // If we can't create a result to send to JNI, we only can crash
+    let alerts_j_string = expect_log!(alerts_j_string_res, "Couldn't create JNI alerts string");
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        JObject::from(alerts_j_string),
+        "org/coepi/core/jni/JniStringResult",
+        "Ljava/lang/String;",
+        &env,
+    )
+}
+
+// JSON object with the counts of stored TCNs (TcnStats)
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getTcnStats(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(get_tcn_stats(), &env)
+}
+
+// JSON object with what's stored on the device and when it's deleted (PrivacyReport)
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getPrivacyReport(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(get_privacy_report(), &env)
+}
+
+// Performance debugging, cheap enough to call every few seconds. JSON object (RuntimeStats)
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getRuntimeStats(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(get_runtime_stats(), &env)
+}
+
+// Debugging: JSON array of the last raw observations passed to recordTcn, oldest first.
+// See ObservedTcnProcessor::recent_observations
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_getRecentObservationsDebug(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    let result = dependencies()
+        .and_then(|dependencies| dependencies.observed_tcn_processor.recent_observations());
+    to_json_result_jobject(result, &env)
+}
+
+// Short token referencing the alert, e.g. to read to a call center. See AlertReferences.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_alertReferenceToken(
+    env: JNIEnv,
+    _: JClass,
+    alert_id: JString,
+) -> jobject {
+    let (status, message, token) = match alert_reference_token(&env, alert_id) {
+        Ok(token) => (1, None, token),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            (jni_error.status, Some(jni_error.message), "".to_owned())
+        }
+    };
+
+    let token_j_string_res = env.new_string(token);
+    // If we can't create a result to send to JNI, we only can crash
+    let token_j_string = expect_log!(token_j_string_res, "Couldn't create JNI token string");
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        JObject::from(token_j_string),
+        "org/coepi/core/jni/JniStringResult",
+        "Ljava/lang/String;",
+        &env,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_verifyAlertReferenceToken(
+    env: JNIEnv,
+    _: JClass,
+    token: JString,
+    alert_id: JString,
+) -> jobject {
+    match verify_alert_reference_token(&env, token, alert_id) {
+        Ok(is_valid) => jni_boolean_result(1, None, is_valid, &env),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            jni_boolean_result(jni_error.status, Some(&jni_error.message), false, &env)
+        }
+    }
+}
+
+// Posts the alert's acknowledgement to the configured health department endpoint (opt-in, user triggered).
+// Error if the export isn't configured. See AcknowledgementService.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_submitAcknowledgement(
+    env: JNIEnv,
+    _: JClass,
+    alert_id: JString,
+) -> jobject {
+    submit_acknowledgement(&env, alert_id).to_void_jni(&env)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_recordTcn(
     env: JNIEnv,
     _: JClass,
     tcn: JString,
     distance: jfloat,
+    time: jlong,
+) -> jobject {
+    record_tcn(&env, tcn, distance, time).to_void_jni(&env)
+}
+
+// Observed now (e.g. a burst of scan results). tcns and distances: same length.
+// JSON object: accepted and invalid (malformed TCNs) observations.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_recordTcns(
+    env: JNIEnv,
+    _: JClass,
+    tcns: jobjectArray,
+    distances: jfloatArray,
 ) -> jobject {
-    record_tcn(&env, tcn, distance).to_void_jni(&env)
+    to_json_result_jobject(record_tcns(&env, tcns, distances), &env)
+}
+
+// Writes the observed TCNs not flushed yet, e.g. when the app goes to background or is terminated.
+// Otherwise they're written periodically.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_flushObservedTcns(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    dependencies()
+        .and_then(|dependencies| dependencies.observed_tcn_processor.flush())
+        .to_void_jni(&env)
 }
 
 // NOTE: Returns directly success string
@@ -112,23 +589,84 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_generateTcn(
     _: JClass,
 ) -> jstring {
     // Maybe send byte array directly?
-    let tcn_hex = hex::encode(dependencies().tcn_keys.generate_tcn().0);
-    info!("Generated TCN: {:?}", tcn_hex);
+    let tcn_res = dependencies().and_then(|dependencies| dependencies.tcn_keys.generate_tcn());
+    // Empty if the TCN couldn't be generated (not bootstrapped or keys not readable)
+    let tcn_hex = match tcn_res {
+        Ok(tcn) => TcnHex::from(&tcn).into_string(),
+        Err(e) => {
+            error!("Couldn't generate TCN: {:?}", e);
+            "".to_owned()
+        }
+    };
+    info!("Generated TCN: {}", tcn_hex);
 
-    let output_res = env.new_string(tcn_hex);
+    let output_res = env.new_string(tcn_hex.as_str());
     // Unclear about when new_string can return Error (TODO), and there's no meaningful handling in the app, so for now crash
     let output = expect_log!(output_res, "Couldn't create java string");
 
     output.into_inner()
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setSymptomIds(
-    env: JNIEnv,
-    _: JClass,
-    ids: JString,
-) -> jobject {
-    set_symptom_ids(&env, ids).to_void_jni(&env)
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_generateTcnWithExpiry(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    let (status, message, tcn_with_expiry) = match generate_tcn_with_expiry() {
+        Ok(tcn_with_expiry) => (1, None, tcn_with_expiry),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            (jni_error.status, Some(jni_error.message), "{}".to_owned())
+        }
+    };
+
+    let tcn_with_expiry_j_string_res = env.new_string(tcn_with_expiry);
+    // If we can't create a result to send to JNI, we only can crash
+    let tcn_with_expiry_j_string = expect_log!(
+        tcn_with_expiry_j_string_res,
+        "Couldn't create JNI TCN with expiry string"
+    );
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        JObject::from(tcn_with_expiry_j_string),
+        "org/coepi/core/jni/JniStringResult",
+        "Ljava/lang/String;",
+        &env,
+    )
+}
+
+// ids: JSON array of strings or comma separated. Result: JSON object with the accepted and rejected ids.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setSymptomIds(
+    env: JNIEnv,
+    _: JClass,
+    ids: JString,
+) -> jobject {
+    let (status, message, ids_result) = match set_symptom_ids(&env, ids) {
+        Ok(ids_result) => (1, None, ids_result),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            (jni_error.status, Some(jni_error.message), "{}".to_owned())
+        }
+    };
+
+    let ids_result_j_string_res = env.new_string(ids_result);
+    // If we can't create a result to send to JNI, we only can crash
+    let ids_result_j_string = expect_log!(
+        ids_result_j_string_res,
+        "Couldn't create JNI symptom ids result string"
+    );
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        JObject::from(ids_result_j_string),
+        "org/coepi/core/jni/JniStringResult",
+        "Ljava/lang/String;",
+        &env,
+    )
 }
 
 #[no_mangle]
@@ -148,8 +686,11 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setCoughDays(
     days: jint,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .set_cough_days(is_set == 1, days as u32)
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_cough_days(is_set == 1, days as u32)
+        })
         .to_void_jni(&env)
 }
 
@@ -181,8 +722,11 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setFeverDays(
     // TODO is_set jboolean
     // TODO assert is_set / days etc. in type's bounds, also iOS
     dependencies()
-        .symptom_inputs_processor
-        .set_fever_days(is_set == 1, days as u32)
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_fever_days(is_set == 1, days as u32)
+        })
         .to_void_jni(&env)
 }
 
@@ -194,8 +738,11 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setFeverTakenTemperature
     taken: jint,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .set_fever_taken_temperature_today(is_set == 1, taken == 1)
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_fever_taken_temperature_today(is_set == 1, taken == 1)
+        })
         .to_void_jni(&env)
 }
 
@@ -216,8 +763,11 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setFeverHighestTemperatu
     temp: jfloat,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .set_fever_highest_temperature_taken(is_set == 1, temp as f32)
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_fever_highest_temperature_taken(is_set == 1, temp as f32)
+        })
         .to_void_jni(&env)
 }
 
@@ -229,8 +779,28 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setEarliestSymptomStarte
     days: jint,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .set_earliest_symptom_started_days_ago(is_set == 1, days as u32)
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_earliest_symptom_started_days_ago(is_set == 1, days as u32)
+        })
+        .to_void_jni(&env)
+}
+
+// time: unix time. Not in the future and at most 90 days ago.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_setEarliestSymptomDate(
+    env: JNIEnv,
+    _: JClass,
+    is_set: jint,
+    time: jlong,
+) -> jobject {
+    dependencies()
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .set_earliest_symptom_date(is_set == 1, time as u64)
+        })
         .to_void_jni(&env)
 }
 
@@ -240,8 +810,7 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_clearSymptoms(
     _: JClass,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .clear()
+        .and_then(|dependencies| dependencies.symptom_inputs_processor.clear())
         .to_void_jni(&env)
 }
 
@@ -251,152 +820,490 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_submitSymptoms(
     _: JClass,
 ) -> jobject {
     dependencies()
-        .symptom_inputs_processor
-        .submit()
+        .and_then(|dependencies| dependencies.symptom_inputs_processor.submit())
+        .and_then(SubmitOutcome::into_void_result)
+        .to_void_jni(&env)
+}
+
+// Like submitSymptoms, with the outcome as JSON (see SubmitOutcome). A rejected report isn't an error.
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_submitSymptomsWithOutcome(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(
+        dependencies().and_then(|dependencies| dependencies.symptom_inputs_processor.submit()),
+        &env,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_resubmitWithEarliestSymptom(
+    env: JNIEnv,
+    _: JClass,
+    days_ago: jint,
+) -> jobject {
+    dependencies()
+        .and_then(|dependencies| {
+            dependencies
+                .symptom_inputs_processor
+                .resubmit_with_earliest_symptom(days_ago as u32)
+        })
         .to_void_jni(&env)
 }
 
 fn bootstrap_core(
     env: &JNIEnv,
     db_path_j_string: JString,
+    config_json_j_string: JString,
     log_level_j_string: JString,
     log_coepi_only: jboolean,
     log_callback: jobject,
-) -> Result<(), ServicesError> {
+) -> Result<BootstrapReport, ServicesError> {
     init_log(&env, log_level_j_string, log_coepi_only, log_callback);
 
-    let db_path_java_str = env.get_string(db_path_j_string)?;
-    let db_path_str = db_path_java_str.to_str()?;
+    let db_path_str = jstring_param(env, db_path_j_string, "db_path", ParamKind::Text)?;
 
-    let db_result = bootstrap(db_path_str)?;
+    let config_json = jstring_param(env, config_json_j_string, "config_json", ParamKind::Text)?;
+    let config = CoreConfig::from_json(&config_json)?;
+
+    let db_result = bootstrap(&db_path_str, config)?;
     info!("Bootstrapping result: {:?}", db_result);
 
-    Ok(())
+    Ok(db_result)
+}
+
+fn switch_core_profile(env: &JNIEnv, profile: JString) -> Result<(), ServicesError> {
+    let profile_str = jstring_param(env, profile, "profile", ParamKind::Identifier)?;
+
+    switch_profile(&profile_str)
 }
 
-fn fetch_new_reports(env: &JNIEnv) -> Result<jobjectArray, ServicesError> {
+fn fetch_new_reports() -> Result<Vec<Alert>, ServicesError> {
     info!("Updating reports");
-    let result = dependencies().reports_updater.update_and_fetch_alerts()?;
+    let dependencies = dependencies()?;
+    let start_time = Instant::now();
+    let result = dependencies.reports_updater.update_and_fetch_alerts()?;
     info!("New reports: {:?}", result);
 
-    alerts_to_jobject_array(result, &env)
+    // Piggybacks on the periodic reports update, if there's time left
+    dependencies
+        .housekeeper
+        .run_after_fetch(start_time.elapsed());
+
+    Ok(result)
+}
+
+fn get_alerts_page(
+    env: &JNIEnv,
+    cursor: JString,
+    page_size: jint,
+) -> Result<AlertsPage, ServicesError> {
+    let cursor_str = jstring_param(env, cursor, "cursor", ParamKind::Identifier)?;
+
+    if page_size < 0 {
+        return Err(ServicesError::FFIParameters(format!(
+            "Invalid page size: {}",
+            page_size
+        )));
+    }
+
+    let cursor = Some(cursor_str.as_str()).filter(|cursor| !cursor.is_empty());
+    dependencies()?
+        .alert_dao
+        .alerts_page(cursor, page_size as u32)
 }
 
 fn delete_alert(env: &JNIEnv, id: JString) -> Result<(), ServicesError> {
-    let id_java_str = env.get_string(id)?;
-    let id_str = id_java_str.to_str()?;
+    let id_str = jstring_param(env, id, "id", ParamKind::Identifier)?;
 
-    dependencies().alert_dao.delete(id_str.to_owned())
+    dependencies()?.alert_dao.delete(id_str)
+}
+
+fn dismiss_report(env: &JNIEnv, report_id: JString) -> Result<usize, ServicesError> {
+    let report_id_str = jstring_param(env, report_id, "report_id", ParamKind::Identifier)?;
+
+    dependencies()?.alert_dao.delete_by_report(report_id_str)
 }
 
 fn update_alert_is_read(env: &JNIEnv, id: JString, is_read: jint) -> Result<(), ServicesError> {
-    let id_java_str = env.get_string(id)?;
-    let id_str = id_java_str.to_str()?;
+    let id_str = jstring_param(env, id, "id", ParamKind::Identifier)?;
 
-    dependencies()
+    dependencies()?
         .alert_dao
-        .update_is_read(id_str.to_owned(), is_read == 1)
+        .update_is_read(id_str, is_read == 1)
 }
 
-fn record_tcn(env: &JNIEnv, tcn: JString, distance: jfloat) -> Result<(), ServicesError> {
-    let tcn_java_str = env.get_string(tcn)?;
-    let tcn_str = tcn_java_str.to_str()?;
+fn group_exposures(
+    env: &JNIEnv,
+    windows_json: JString,
+    threshold_seconds: jlong,
+) -> Result<Vec<ExposureWindow>, ServicesError> {
+    let windows_json_str = jstring_param(env, windows_json, "windows_json", ParamKind::Text)?;
+
+    if threshold_seconds < 0 {
+        return Err(ServicesError::FFIParameters(format!(
+            "Invalid exposure threshold: {}",
+            threshold_seconds
+        )));
+    }
 
-    dependencies()
+    group_exposure_windows_json(&windows_json_str, threshold_seconds as u64)
+}
+
+fn encode_memo(env: &JNIEnv, report_json: JString) -> Result<String, ServicesError> {
+    let report_json_str = jstring_param(env, report_json, "report_json", ParamKind::Text)?;
+    encode_memo_json(&report_json_str)
+}
+
+fn decode_memo(env: &JNIEnv, memo_hex: JString) -> Result<PublicSymptoms, ServicesError> {
+    let memo_hex_str = jstring_param(env, memo_hex, "memo_hex", ParamKind::Identifier)?;
+    decode_memo_hex(&memo_hex_str)
+}
+
+fn snooze_alert(env: &JNIEnv, id: JString, until: jlong) -> Result<(), ServicesError> {
+    let id_str = jstring_param(env, id, "id", ParamKind::Identifier)?;
+
+    if until < 0 {
+        return Err(ServicesError::FFIParameters(format!(
+            "Invalid snooze time: {}",
+            until
+        )));
+    }
+
+    dependencies()?.alert_dao.snooze(
+        id_str,
+        UnixTime {
+            value: until as u64,
+        },
+    )
+}
+
+fn get_raw_report(env: &JNIEnv, report_id: JString) -> Result<String, ServicesError> {
+    let report_id_str = jstring_param(env, report_id, "report_id", ParamKind::Identifier)?;
+
+    dependencies()?.reports_updater.raw_report(report_id_str)
+}
+
+#[cfg(debug_assertions)]
+fn preview_migrations(env: &JNIEnv, db_path: JString) -> Result<MigrationReport, ServicesError> {
+    let db_path_str = jstring_param(env, db_path, "db_path", ParamKind::Text)?;
+
+    dependencies::preview_migrations(&db_path_str)
+}
+
+#[cfg(feature = "test-support")]
+fn match_reports_for_test(
+    env: &JNIEnv,
+    reports_json: JString,
+    observed_tcns_json: JString,
+) -> Result<String, ServicesError> {
+    let reports_json_str = jstring_param(env, reports_json, "reports_json", ParamKind::Text)?;
+    let observed_tcns_json_str = jstring_param(
+        env,
+        observed_tcns_json,
+        "observed_tcns_json",
+        ParamKind::Text,
+    )?;
+
+    crate::test_support::match_reports_json(&reports_json_str, &observed_tcns_json_str)
+}
+
+fn generate_tcn_with_expiry() -> Result<String, ServicesError> {
+    let (tcn, expiry) = dependencies()?.tcn_keys.current_tcn_with_expiry()?;
+    serde_json::to_string(&TcnWithExpiry::new(&tcn, expiry)).map_err(ServicesError::from)
+}
+
+fn get_tcn_stats() -> Result<TcnDiagnostics, ServicesError> {
+    let dependencies = dependencies()?;
+    Ok(TcnDiagnostics {
+        stored: dependencies.reports_updater.tcn_dao.stats()?,
+        malformed_tcn_observations: dependencies
+            .observed_tcn_processor
+            .malformed_tcn_observations(),
+    })
+}
+
+fn get_privacy_report() -> Result<PrivacyReport, ServicesError> {
+    let dependencies = dependencies()?;
+    privacy_report(
+        dependencies.reports_updater.preferences.as_ref(),
+        dependencies.reports_updater.tcn_dao.as_ref(),
+        dependencies.alert_dao.as_ref(),
+        &dependencies.housekeeper,
+        &dependencies.reports_updater.risk_config,
+    )
+}
+
+fn get_runtime_stats() -> Result<RuntimeStats, ServicesError> {
+    let dependencies = dependencies()?;
+    Ok(runtime_stats(
+        &dependencies.observed_tcn_processor,
+        &dependencies.reports_updater.fetch_tracker,
+        &dependencies.housekeeper,
+        simple_logger::LOG_QUEUE.stats(),
+        &(dependencies.reports_updater.clock)(),
+    ))
+}
+
+fn alert_reference_token(env: &JNIEnv, alert_id: JString) -> Result<String, ServicesError> {
+    let alert_id_str = jstring_param(env, alert_id, "alert_id", ParamKind::Identifier)?;
+
+    dependencies()?
+        .alert_references
+        .alert_reference_token(&alert_id_str)
+}
+
+fn verify_alert_reference_token(
+    env: &JNIEnv,
+    token: JString,
+    alert_id: JString,
+) -> Result<bool, ServicesError> {
+    let token_str = jstring_param(env, token, "token", ParamKind::Identifier)?;
+    let alert_id_str = jstring_param(env, alert_id, "alert_id", ParamKind::Identifier)?;
+
+    dependencies()?
+        .alert_references
+        .verify_alert_reference_token(&token_str, &alert_id_str)
+}
+
+fn submit_acknowledgement(env: &JNIEnv, alert_id: JString) -> Result<(), ServicesError> {
+    let alert_id_str = jstring_param(env, alert_id, "alert_id", ParamKind::Identifier)?;
+
+    dependencies()?
+        .acknowledgement_service
+        .submit_acknowledgement(&alert_id_str)
+}
+
+fn record_tcn(
+    env: &JNIEnv,
+    tcn: JString,
+    distance: jfloat,
+    time: jlong,
+) -> Result<(), ServicesError> {
+    let tcn_str = jstring_param(env, tcn, "tcn", ParamKind::Identifier)?;
+    let time = unix_time_param("time", time)?;
+
+    dependencies()?
+        .observed_tcn_processor
+        .save_with_time(&tcn_str, distance as f32, time)
+}
+
+fn record_tcns(
+    env: &JNIEnv,
+    tcns: jobjectArray,
+    distances: jfloatArray,
+) -> Result<SaveBatchOutcome, ServicesError> {
+    let count = env.get_array_length(tcns)?;
+    let distances_count = env.get_array_length(distances)?;
+    if distances_count != count {
+        return Err(invalid_param(
+            "distances",
+            &format!("has {} elements, tcns: {}", distances_count, count),
+        ));
+    }
+    let mut distances_buf = vec![0.0; count as usize];
+    env.get_float_array_region(distances, 0, &mut distances_buf)?;
+
+    let mut observations = Vec::with_capacity(count as usize);
+    let mut unreadable = 0;
+    for (index, distance) in distances_buf.into_iter().enumerate() {
+        let tcn_j_object = env.get_object_array_element(tcns, index as i32)?;
+        let tcn_res = jstring_param(
+            env,
+            JString::from(tcn_j_object),
+            "tcns",
+            ParamKind::Identifier,
+        );
+        // Deleted right away: the array can exceed the local references capacity
+        env.delete_local_ref(tcn_j_object)?;
+        match tcn_res {
+            Ok(tcn) => observations.push((tcn, distance)),
+            Err(error) => {
+                warn!("Malformed TCN observation: {:?}", error);
+                unreadable += 1;
+            }
+        }
+    }
+
+    let mut outcome = dependencies()?
         .observed_tcn_processor
-        .save(tcn_str, distance as f32)
+        .save_batch(observations)?;
+    outcome.invalid += unreadable;
+    Ok(outcome)
 }
 
-fn set_symptom_ids(env: &JNIEnv, ids: JString) -> Result<(), ServicesError> {
-    let java_str = env.get_string(ids)?;
-    let ids_str = java_str.to_str()?;
+fn set_report_denylist(env: &JNIEnv, sigs_json: JString) -> Result<(), ServicesError> {
+    let sigs_json_str = jstring_param(env, sigs_json, "sigs_json", ParamKind::Text)?;
+
+    dependencies()?
+        .reports_updater
+        .set_report_denylist(&sigs_json_str)
+}
+
+fn set_report_allowlist(env: &JNIEnv, sigs_json: JString) -> Result<(), ServicesError> {
+    let sigs_json_str = jstring_param(env, sigs_json, "sigs_json", ParamKind::Text)?;
+
+    dependencies()?
+        .reports_updater
+        .set_report_allowlist(&sigs_json_str)
+}
+
+fn set_symptom_ids(env: &JNIEnv, ids: JString) -> Result<String, ServicesError> {
+    let ids_str = jstring_param(env, ids, "ids", ParamKind::Identifier)?;
 
     debug!("Setting symptom ids: {:?}", ids_str);
 
-    dependencies()
+    let ids_result = dependencies()?
         .symptom_inputs_processor
-        .set_symptom_ids(ids_str)
+        .set_symptom_ids(&ids_str)?;
+    serde_json::to_string(&ids_result).map_err(ServicesError::from)
 }
 
 fn set_cough_type(env: &JNIEnv, cough_type: JString) -> Result<(), ServicesError> {
-    let java_str = env.get_string(cough_type)?;
-    let cough_type_str = java_str.to_str()?;
+    let cough_type_str = jstring_param(env, cough_type, "cough_type", ParamKind::Identifier)?;
 
     debug!("Setting cough type: {:?}", cough_type_str);
 
-    dependencies()
+    dependencies()?
         .symptom_inputs_processor
-        .set_cough_type(cough_type_str)
+        .set_cough_type(&cough_type_str)
 }
 
 fn set_cough_status(env: &JNIEnv, cough_status: JString) -> Result<(), ServicesError> {
-    let java_str = env.get_string(cough_status)?;
-    let str = java_str.to_str()?;
+    let str = jstring_param(env, cough_status, "cough_status", ParamKind::Identifier)?;
 
-    dependencies()
+    dependencies()?
         .symptom_inputs_processor
-        .set_cough_status(str)
+        .set_cough_status(&str)
 }
 
 fn set_breathlessness_cause(env: &JNIEnv, cause: JString) -> Result<(), ServicesError> {
-    let java_str = env.get_string(cause)?;
-    let str = java_str.to_str()?;
+    let str = jstring_param(env, cause, "cause", ParamKind::Identifier)?;
 
-    dependencies()
+    dependencies()?
         .symptom_inputs_processor
-        .set_breathlessness_cause(str)
+        .set_breathlessness_cause(&str)
 }
 
 fn set_fever_taken_temperature_spot(env: &JNIEnv, spot: JString) -> Result<(), ServicesError> {
-    let java_str = env.get_string(spot)?;
-    let str = java_str.to_str()?;
+    let str = jstring_param(env, spot, "spot", ParamKind::Identifier)?;
 
     debug!("Setting temperature spot cause: {:?}", str);
-    dependencies()
+    dependencies()?
         .symptom_inputs_processor
-        .set_fever_taken_temperature_spot(str)
+        .set_fever_taken_temperature_spot(&str)
+}
+
+// Reads a string parameter. name: of the parameter, for the errors (see param_str).
+fn jstring_param(
+    env: &JNIEnv,
+    j_string: JString,
+    name: &str,
+    kind: ParamKind,
+) -> Result<String, ServicesError> {
+    if j_string.into_inner().is_null() {
+        return Err(null_param(name));
+    }
+
+    let java_str = env
+        .get_string(j_string)
+        .map_err(|e| invalid_param(name, &format!("couldn't be read: {}", e)))?;
+    param_str(name, java_str.to_bytes(), kind).map(|str| str.to_owned())
+}
+
+pub fn alerts_to_jobject_array(
+    alerts: Vec<Alert>,
+    env: &JNIEnv,
+) -> Result<jobjectArray, ServicesError> {
+    let alerts_array = env.new_object_array(
+        alerts.len() as i32,
+        "org/coepi/core/jni/JniAlert",
+        JObject::null(),
+    )?;
+
+    set_object_array_elements(alerts_array, alerts, alert_to_jobject, env)?;
+
+    Ok(alerts_array)
+}
+
+// Local references are only freed when the native call returns, and the local reference table is
+// limited (512 entries on older Android versions). Each element creates several references
+// (class, strings, nested objects), so convert each one in its own local frame, which releases
+// them as soon as the element is stored in the array.
+fn set_object_array_elements<T>(
+    array: jobjectArray,
+    elements: Vec<T>,
+    to_jobject: fn(T, &JNIEnv) -> Result<jobject, ServicesError>,
+    env: &JNIEnv,
+) -> Result<(), ServicesError> {
+    for (index, element) in elements.into_iter().enumerate() {
+        env.push_local_frame(ELEMENT_LOCAL_FRAME_CAPACITY)?;
+        let res = to_jobject(element, env).and_then(|j_object| {
+            env.set_object_array_element(array, index as i32, j_object)
+                .map_err(ServicesError::from)
+        });
+        // Pop also on error, to leave the frames balanced
+        env.pop_local_frame(JObject::null())?;
+        res?;
+    }
+    Ok(())
 }
 
-fn to_alerts_result_jobject(
+fn to_report_alerts_result_jobject(
     status: i32,
     message: Option<&str>,
-    alerts: jobjectArray,
+    report_alerts: jobjectArray,
     env: &JNIEnv,
 ) -> jobject {
     jni_obj_result(
         status,
         message,
-        JObject::from(alerts),
-        "org/coepi/core/jni/JniAlertsArrayResult",
-        "[Lorg/coepi/core/jni/JniAlert;",
+        JObject::from(report_alerts),
+        "org/coepi/core/jni/JniReportAlertsArrayResult",
+        "[Lorg/coepi/core/jni/JniReportAlerts;",
         &env,
     )
 }
 
-fn alerts_to_jobject_array(
-    alerts: Vec<Alert>,
+fn report_alerts_to_jobject_array(
+    report_alerts: Vec<ReportAlerts>,
     env: &JNIEnv,
 ) -> Result<jobjectArray, ServicesError> {
-    let alerts_j_objects_res: Result<Vec<jobject>, ServicesError> = alerts
-        .into_iter()
-        .map(|alert| alert_to_jobject(alert, &env))
-        .collect();
+    let array = env.new_object_array(
+        report_alerts.len() as i32,
+        "org/coepi/core/jni/JniReportAlerts",
+        JObject::null(),
+    )?;
 
-    let alerts_j_objects: Vec<jobject> = alerts_j_objects_res?;
+    set_object_array_elements(array, report_alerts, report_alerts_to_jobject, env)?;
 
-    let placeholder_alert_j_object = alert_to_jobject(placeholder_alert(), &env)?;
+    Ok(array)
+}
 
-    let alerts_array = env.new_object_array(
-        alerts_j_objects.len() as i32,
-        "org/coepi/core/jni/JniAlert",
-        placeholder_alert_j_object,
-    )?;
+fn report_alerts_to_jobject(
+    report_alerts: ReportAlerts,
+    env: &JNIEnv,
+) -> Result<jobject, ServicesError> {
+    let jni_report_alerts_class = env.find_class("org/coepi/core/jni/JniReportAlerts")?;
 
-    for (index, alert_j_object) in alerts_j_objects.into_iter().enumerate() {
-        env.set_object_array_element(alerts_array, index as i32, alert_j_object)?;
-    }
+    let report_id_j_string = env.new_string(report_alerts.report_id)?;
+    let report_id_j_value = JValue::from(JObject::from(report_id_j_string));
+    let report_j_value = JValue::from(public_symptoms_to_jobject(&report_alerts.report, env)?);
+    let alerts_j_value = JValue::from(JObject::from(alerts_to_jobject_array(
+        report_alerts.alerts,
+        env,
+    )?));
 
-    Ok(alerts_array)
+    let result: Result<jobject, jni::errors::Error> = env
+        .new_object(
+            jni_report_alerts_class,
+            "(Ljava/lang/String;Lorg/coepi/core/jni/JniPublicSymptoms;[Lorg/coepi/core/jni/JniAlert;)V",
+            &[report_id_j_value, report_j_value, alerts_j_value],
+        )
+        .map(|o| o.into_inner());
+
+    result.map_err(ServicesError::from)
 }
 
 fn init_log(env: &JNIEnv, level_j_string: JString, coepi_only: jboolean, callback: jobject) -> i32 {
@@ -406,7 +1313,7 @@ fn init_log(env: &JNIEnv, level_j_string: JString, coepi_only: jboolean, callbac
                 java_vm,
                 callback: callback_global_ref,
             };
-            register_callback_internal(Box::new(callback_wrapper));
+            simple_logger::register_log_callback(Arc::new(callback_wrapper));
 
             let level_java_str = env.get_string(level_j_string).unwrap();
             let level_str = level_java_str.to_str().unwrap();
@@ -495,8 +1402,97 @@ pub fn jni_obj_result(
     expect_log!(obj, "Couldn't create JNI result object").into_inner()
 }
 
-trait LogCallbackWrapper {
-    fn call(&self, level: CoreLogLevel, text: String);
+// JniResult with the value serialized to JSON. The JSON is null for errors and empty values
+// (unit, None), so new results need only serde derives instead of a Java class.
+pub fn to_json_result_jobject<T: Serialize>(
+    result: Result<T, ServicesError>,
+    env: &JNIEnv,
+) -> jobject {
+    let json_result =
+        result.and_then(|value| serde_json::to_value(value).map_err(ServicesError::from));
+
+    let (status, message, json) = match json_result {
+        Ok(Value::Null) => (1, None, None),
+        Ok(value) => (1, None, Some(value.to_string())),
+        Err(e) => {
+            let jni_error = e.to_jni_error();
+            (jni_error.status, Some(jni_error.message), None)
+        }
+    };
+
+    let json_j_object = match json {
+        Some(json) => {
+            let json_j_string_res = env.new_string(json);
+            // If we can't create a result to send to JNI, we only can crash
+            JObject::from(expect_log!(
+                json_j_string_res,
+                "Couldn't create JNI JSON string"
+            ))
+        }
+        None => JObject::null(),
+    };
+
+    jni_obj_result(
+        status,
+        message.as_deref(),
+        json_j_object,
+        "org/coepi/core/jni/JniResult",
+        "Ljava/lang/String;",
+        env,
+    )
+}
+
+pub fn jni_boolean_result(
+    status: i32,
+    message: Option<&str>,
+    value: bool,
+    env: &JNIEnv,
+) -> jobject {
+    let cls_res = env.find_class("org/coepi/core/jni/JniBooleanResult");
+
+    let status_j_value = JValue::from(status);
+
+    let msg = message.unwrap_or("");
+    let msg_j_string_res = env.new_string(msg);
+    // If we can't create a result to send to JNI, we only can crash
+    let msg_j_string = expect_log!(msg_j_string_res, "Couldn't create JNI msg string");
+    let msg_j_value = JValue::from(msg_j_string);
+
+    // If we can't create a result to send to JNI, we only can crash
+    let cls = expect_log!(cls_res, "Couldn't create JNI result class");
+
+    let obj = env.new_object(
+        cls,
+        "(ILjava/lang/String;Z)V",
+        &[status_j_value, msg_j_value, JValue::from(value)],
+    );
+
+    // If we can't create a result to send to JNI, we only can crash
+    expect_log!(obj, "Couldn't create JNI result object").into_inner()
+}
+
+pub fn jni_int_result(status: i32, message: Option<&str>, value: i32, env: &JNIEnv) -> jobject {
+    let cls_res = env.find_class("org/coepi/core/jni/JniIntResult");
+
+    let status_j_value = JValue::from(status);
+
+    let msg = message.unwrap_or("");
+    let msg_j_string_res = env.new_string(msg);
+    // If we can't create a result to send to JNI, we only can crash
+    let msg_j_string = expect_log!(msg_j_string_res, "Couldn't create JNI msg string");
+    let msg_j_value = JValue::from(msg_j_string);
+
+    // If we can't create a result to send to JNI, we only can crash
+    let cls = expect_log!(cls_res, "Couldn't create JNI result class");
+
+    let obj = env.new_object(
+        cls,
+        "(ILjava/lang/String;I)V",
+        &[status_j_value, msg_j_value, JValue::from(value)],
+    );
+
+    // If we can't create a result to send to JNI, we only can crash
+    expect_log!(obj, "Couldn't create JNI result object").into_inner()
 }
 
 struct LogCallbackWrapperImpl {
@@ -512,7 +1508,7 @@ struct LogCallbackWrapperImpl {
     java_vm: JavaVM,
 }
 
-impl LogCallbackWrapper for LogCallbackWrapperImpl {
+impl LogCallback for LogCallbackWrapperImpl {
     fn call(&self, level: CoreLogLevel, text: String) {
         match self.java_vm.attach_current_thread() {
             Ok(env) => self.call(level, text, &env),
@@ -555,98 +1551,61 @@ impl LogCallbackWrapperImpl {
     }
 }
 
-fn register_callback_internal(callback: Box<dyn LogCallbackWrapper>) {
-    // Make callback implement Send (marker for thread safe, basically) https://doc.rust-lang.org/std/marker/trait.Send.html
-    let log_callback = unsafe {
-        std::mem::transmute::<Box<dyn LogCallbackWrapper>, Box<dyn LogCallbackWrapper + Send>>(
-            callback,
-        )
-    };
-
-    // Create channel
-    let (tx, rx): (
-        Sender<CoreLogMessageThreadSafe>,
-        Receiver<CoreLogMessageThreadSafe>,
-    ) = mpsc::channel();
-
-    // Save the sender in a static variable, which will be used to push elements to the callback
-    unsafe {
-        SENDER = Some(tx);
-    }
+fn alert_summary_to_jobject<'a>(
+    summary: AlertSummary,
+    env: &JNIEnv<'a>,
+) -> Result<JObject<'a>, ServicesError> {
+    let jni_alert_summary_class = env.find_class("org/coepi/core/jni/JniAlertSummary")?;
 
-    // Thread waits for elements pushed to SENDER and calls the callback
-    thread::spawn(move || {
-        for log_entry in rx.iter() {
-            log_callback.call(log_entry.level, log_entry.text.into());
-        }
-    });
-}
-
-// To prefill the JNI array (TODO can this be skipped?)
-fn placeholder_alert() -> Alert {
-    let symptoms = PublicSymptoms {
-        report_time: UnixTime { value: 0 },
-        earliest_symptom_time: UserInput::Some(UnixTime { value: 0 }),
-        fever_severity: FeverSeverity::None,
-        cough_severity: CoughSeverity::None,
-        breathlessness: false,
-        muscle_aches: false,
-        loss_smell_or_taste: false,
-        diarrhea: false,
-        runny_nose: false,
-        other: false,
-        no_symptoms: false,
+    let last_exposure_end = match summary.last_exposure_end {
+        Some(end) => end as i64,
+        None => -1,
     };
 
-    Alert {
-        id: "0".to_owned(),
-        report_id: "0".to_owned(),
-        symptoms,
-        contact_start: 0,
-        contact_end: 0,
-        min_distance: 0.0,
-        avg_distance: 0.0,
-        is_read: false,
-    }
+    let jni_alert_summary_obj = env.new_object(
+        jni_alert_summary_class,
+        "(IIJI)V",
+        &[
+            JValue::from(summary.total as i32),
+            JValue::from(summary.unread as i32),
+            JValue::from(last_exposure_end),
+            JValue::from(summary.exposures_last_14_days as i32),
+        ],
+    )?;
+
+    Ok(jni_alert_summary_obj)
 }
 
-pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesError> {
+fn public_symptoms_to_jobject<'a>(
+    symptoms: &PublicSymptoms,
+    env: &JNIEnv<'a>,
+) -> Result<JObject<'a>, ServicesError> {
     let jni_public_symptoms_class = env.find_class("org/coepi/core/jni/JniPublicSymptoms")?;
 
-    let report_time_j_value = JValue::from(alert.symptoms.report_time.value as i64);
+    let report_time_j_value = JValue::from(symptoms.report_time.value as i64);
 
-    let earliest_time = match &alert.symptoms.earliest_symptom_time {
+    let earliest_time = match &symptoms.earliest_symptom_time {
         UserInput::Some(time) => time.value as i64,
         UserInput::None => -1,
     };
     let earliest_time_j_value = JValue::from(earliest_time);
 
-    let fever_severity = match &alert.symptoms.fever_severity {
-        FeverSeverity::None => 0,
-        FeverSeverity::Mild => 1,
-        FeverSeverity::Serious => 2,
-    };
-    let fever_severity_j_value = JValue::from(fever_severity);
-
-    let cough_severity = match &alert.symptoms.cough_severity {
-        CoughSeverity::None => 0,
-        CoughSeverity::Existing => 1,
-        CoughSeverity::Wet => 2,
-        CoughSeverity::Dry => 3,
-    };
-    let cough_severity_j_value = JValue::from(cough_severity);
+    let fever_severity_j_value = JValue::from(symptoms.fever_severity.as_u8() as jint);
+    let cough_severity_j_value = JValue::from(symptoms.cough_severity.as_u8() as jint);
 
-    let breathlessness_j_value = JValue::from(alert.symptoms.breathlessness);
-    let muscle_aches_j_value = JValue::from(alert.symptoms.muscle_aches);
-    let loss_smell_or_taste_j_value = JValue::from(alert.symptoms.loss_smell_or_taste);
-    let diarrhea_j_value = JValue::from(alert.symptoms.diarrhea);
-    let runny_nose_j_value = JValue::from(alert.symptoms.runny_nose);
-    let other_j_value = JValue::from(alert.symptoms.other);
-    let no_symptoms_j_value = JValue::from(alert.symptoms.no_symptoms);
+    let breathlessness_j_value = JValue::from(symptoms.breathlessness);
+    let muscle_aches_j_value = JValue::from(symptoms.muscle_aches);
+    let loss_smell_or_taste_j_value = JValue::from(symptoms.loss_smell_or_taste);
+    let diarrhea_j_value = JValue::from(symptoms.diarrhea);
+    let runny_nose_j_value = JValue::from(symptoms.runny_nose);
+    let other_j_value = JValue::from(symptoms.other);
+    let no_symptoms_j_value = JValue::from(symptoms.no_symptoms);
+    let memo_version_j_value = JValue::from(symptoms.memo_version as i32);
+    let report_kind_j_value = JValue::from(symptoms.report_kind.as_u8() as jint);
 
     let jni_public_symptoms_obj = env.new_object(
         jni_public_symptoms_class,
-        "(JJIIZZZZZZZ)V",
+        "(JJIIZZZZZZZII)V",
         &[
             report_time_j_value,
             earliest_time_j_value,
@@ -659,9 +1618,17 @@ pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesE
             runny_nose_j_value,
             other_j_value,
             no_symptoms_j_value,
+            memo_version_j_value,
+            report_kind_j_value,
         ],
     )?;
 
+    Ok(jni_public_symptoms_obj)
+}
+
+pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesError> {
+    let jni_public_symptoms_obj = public_symptoms_to_jobject(&alert.symptoms, env)?;
+
     let jni_alert_class = env.find_class("org/coepi/core/jni/JniAlert")?;
 
     let id_j_string = env.new_string(alert.id)?;
@@ -673,12 +1640,32 @@ pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesE
     let contact_end_j_value = JValue::from(alert.contact_end as i64);
     let min_distance_j_value = JValue::from(alert.min_distance);
     let avg_distance_j_value = JValue::from(alert.avg_distance);
+    let p10_distance_j_value = JValue::from(alert.p10_distance);
+    let median_distance_j_value = JValue::from(alert.median_distance);
     let is_read_j_value = JValue::from(alert.is_read);
+    let has_raw_report_j_value = JValue::from(alert.has_raw_report);
+    // -1 -> not known (alerts stored before the coverage was added)
+    let report_coverage_start_j_value =
+        JValue::from(alert.report_coverage_start.map(|t| t as i64).unwrap_or(-1));
+    let report_coverage_end_j_value =
+        JValue::from(alert.report_coverage_end.map(|t| t as i64).unwrap_or(-1));
+    // null -> not known (alerts stored before the versions were added)
+    let core_version_j_value = JValue::from(optional_string_to_jobject(
+        alert.debug_info.core_version,
+        env,
+    )?);
+    let app_version_j_value = JValue::from(optional_string_to_jobject(
+        alert.debug_info.app_version,
+        env,
+    )?);
+    // -1 -> not snoozed
+    let snoozed_until_j_value = JValue::from(alert.snoozed_until.map(|t| t as i64).unwrap_or(-1));
+    let retracted_j_value = JValue::from(alert.retracted);
 
     let result: Result<jobject, jni::errors::Error> = env
         .new_object(
             jni_alert_class,
-            "(Ljava/lang/String;Ljava/lang/String;Lorg/coepi/core/jni/JniPublicSymptoms;JJFFZ)V",
+            "(Ljava/lang/String;Ljava/lang/String;Lorg/coepi/core/jni/JniPublicSymptoms;JJFFFFZZJJLjava/lang/String;Ljava/lang/String;JZ)V",
             &[
                 id_j_value,
                 report_id_j_value,
@@ -687,7 +1674,16 @@ pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesE
                 contact_end_j_value,
                 min_distance_j_value,
                 avg_distance_j_value,
+                p10_distance_j_value,
+                median_distance_j_value,
                 is_read_j_value,
+                has_raw_report_j_value,
+                report_coverage_start_j_value,
+                report_coverage_end_j_value,
+                core_version_j_value,
+                app_version_j_value,
+                snoozed_until_j_value,
+                retracted_j_value,
             ],
         )
         .map(|o| o.into_inner());
@@ -695,6 +1691,16 @@ pub fn alert_to_jobject(alert: Alert, env: &JNIEnv) -> Result<jobject, ServicesE
     result.map_err(ServicesError::from)
 }
 
+fn optional_string_to_jobject<'a>(
+    string: Option<String>,
+    env: &JNIEnv<'a>,
+) -> Result<JObject<'a>, ServicesError> {
+    match string {
+        Some(string) => Ok(JObject::from(env.new_string(string)?)),
+        None => Ok(JObject::null()),
+    }
+}
+
 trait ResultExt<T, ServicesError> {
     fn to_void_jni(self, env: &JNIEnv) -> jobject;
 }
@@ -737,6 +1743,28 @@ impl JniErrorMappable for ServicesError {
                 status: 6,
                 message: "Not found".to_owned(),
             },
+            ServicesError::Database(msg) => JniError {
+                status: 7,
+                message: msg.to_owned(),
+            },
+            ServicesError::NotBootstrapped => JniError {
+                status: 8,
+                message: "Not bootstrapped".to_owned(),
+            },
+            // JSON array of {field, reason}
+            ServicesError::InvalidConfig(violations) => JniError {
+                status: 9,
+                message: config_violations_json(violations),
+            },
+            // The app can ask the user to free space
+            ServicesError::StorageFull => JniError {
+                status: 10,
+                message: "Storage full".to_owned(),
+            },
+            ServicesError::InvalidInput(msg) => JniError {
+                status: 11,
+                message: msg.to_owned(),
+            },
         }
     }
 }
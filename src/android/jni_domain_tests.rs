@@ -1,16 +1,19 @@
-use super::android_interface::{alert_to_jobject, jni_obj_result};
+use super::android_interface::{
+    alert_to_jobject, alerts_to_jobject_array, jni_obj_result, to_json_result_jobject,
+};
 use crate::{
+    errors::ServicesError,
     expect_log,
     reporting::{
-        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms},
+        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
         symptom_inputs::UserInput,
     },
     reports_interval::UnixTime,
-    reports_update::reports_updater::Alert,
+    reports_update::reports_updater::{Alert, AlertDebugInfo},
 };
 use jni::{
     objects::{JClass, JObject},
-    sys::jobject,
+    sys::{jint, jobject},
     JNIEnv,
 };
 use log::error;
@@ -63,6 +66,56 @@ pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_testReturnMultipleAlerts
     )
 }
 
+// Many more alerts than the local reference table has entries (512 in older Android versions),
+// to check that the conversion doesn't overflow it
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_testReturnManyAlerts(
+    env: JNIEnv,
+    _: JClass,
+    count: jint,
+) -> jobject {
+    let alerts: Vec<Alert> = (0..count)
+        .map(|index| create_test_alert(&index.to_string(), 131321))
+        .collect();
+
+    let array_res = alerts_to_jobject_array(alerts, &env);
+    let array = expect_log!(array_res, "Failed creating array jobject");
+
+    jni_obj_result(
+        1,
+        None,
+        JObject::from(array),
+        "org/coepi/core/jni/JniAlertsArrayResult",
+        "[Lorg/coepi/core/jni/JniAlert;",
+        &env,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_testReturnJsonResult(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    let alert = create_test_alert("123", 234324);
+    to_json_result_jobject(Ok(vec![alert]), &env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_testReturnJsonErrorResult(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject::<Vec<Alert>>(Err(ServicesError::NotFound), &env)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn Java_org_coepi_core_jni_JniApi_testReturnJsonEmptyResult(
+    env: JNIEnv,
+    _: JClass,
+) -> jobject {
+    to_json_result_jobject(Ok(()), &env)
+}
+
 fn create_test_alert(id: &str, report_time: u64) -> Alert {
     let symptoms = PublicSymptoms {
         report_time: UnixTime { value: report_time },
@@ -76,6 +129,8 @@ fn create_test_alert(id: &str, report_time: u64) -> Alert {
         runny_nose: true,
         other: false,
         no_symptoms: true,
+        memo_version: 1,
+        report_kind: ReportKind::Symptoms,
     };
 
     Alert {
@@ -86,6 +141,18 @@ fn create_test_alert(id: &str, report_time: u64) -> Alert {
         contact_end: 1592567335,
         min_distance: 1.2,
         avg_distance: 2.1,
+        p10_distance: 1.5,
+        median_distance: 1.9,
         is_read: false,
+        has_raw_report: false,
+        report_coverage_start: Some(1592481515),
+        report_coverage_end: Some(1592567915),
+        debug_info: AlertDebugInfo {
+            core_version: Some("0.1.0".to_owned()),
+            app_version: None,
+            tcn_prefixes: vec![],
+        },
+        snoozed_until: None,
+        retracted: false,
     }
 }
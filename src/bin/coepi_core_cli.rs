@@ -0,0 +1,13 @@
+// Command line client of the core (see coepi_core::cli). Build with: cargo build --features cli
+use std::{env, process};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match coepi_core::cli::run(&args) {
+        Ok(json) => println!("{}", json),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
+}
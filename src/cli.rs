@@ -0,0 +1,339 @@
+// Command line client of the core, for backend integration testing without the apps (see
+// src/bin/coepi_core_cli.rs). Drives the same services as the FFI, on a local database.
+
+use crate::{
+    config::CoreConfig,
+    dependencies::{create_profile_dependencies, CoreDependencies, DEFAULT_PROFILE},
+    errors::ServicesError,
+    reporting::{
+        memo::{decode_memo_hex, encode_memo_json},
+        symptom_inputs::{SymptomInputs, SymptomInputsSubmitter},
+    },
+    tcn_ext::{tcn_hex::TcnHex, tcn_keys::TcnKeys},
+    tcn_recording::observed_tcn_processor::{ObservedTcnProcessor, TcnDiagnostics},
+};
+use serde::Serialize;
+
+pub const USAGE: &str = "Usage: coepi_core_cli [--db <dir>] [--config <json>] [--api-url <url>] <command>
+
+Commands:
+    record-tcn <hex> <distance>  Records an observed TCN. Prints the stored TCN counts.
+    generate-tcn                 Prints the current TCN of this device (hex).
+    submit-symptoms <json>       Submits the symptom inputs (JSON, see SymptomInputs). Prints the outcome.
+    fetch                        Downloads and matches the new reports. Prints the alerts.
+    alerts                       Prints the stored alerts.
+    encode-memo <json>           Prints the memo (hex) of the report (JSON, see PublicSymptoms).
+    decode-memo <hex>            Prints the report (JSON) of the memo.
+
+--db: directory of the database, default: the working directory.
+--config: core config JSON, like passed by the apps on bootstrap.
+--api-url: reports endpoint, overrides the config's networking.api_url.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    RecordTcn { tcn: String, distance: f32 },
+    GenerateTcn,
+    SubmitSymptoms { inputs_json: String },
+    Fetch,
+    Alerts,
+    EncodeMemo { report_json: String },
+    DecodeMemo { memo_hex: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Invocation {
+    db_path: String,
+    config: CoreConfig,
+    command: Command,
+}
+
+// Runs the command in args (without the program name). Ok: the JSON result, Err: the error message.
+pub fn run(args: &[String]) -> Result<String, String> {
+    let invocation = parse_args(args).map_err(|e| format!("{}\n\n{}", e, USAGE))?;
+    execute(&invocation).map_err(|e| e.to_string())
+}
+
+fn parse_args(args: &[String]) -> Result<Invocation, ServicesError> {
+    let mut db_path = ".".to_owned();
+    let mut config = CoreConfig::default();
+    let mut api_url = None;
+    let mut positional: Vec<&str> = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(|value| value.as_str())
+                .ok_or_else(|| invalid(format!("Missing value of: {}", arg)))
+        };
+        match arg.as_str() {
+            "--db" => db_path = value()?.to_owned(),
+            "--config" => config = CoreConfig::from_json(value()?)?,
+            "--api-url" => api_url = Some(value()?.to_owned()),
+            _ => positional.push(arg),
+        }
+    }
+    // Regardless of the order of --config and --api-url
+    if let Some(api_url) = api_url {
+        config.networking.api_url = api_url;
+    }
+
+    let command = match positional.as_slice() {
+        ["record-tcn", tcn, distance] => Command::RecordTcn {
+            tcn: (*tcn).to_owned(),
+            distance: distance
+                .parse()
+                .map_err(|_| invalid(format!("Invalid distance: {}", distance)))?,
+        },
+        ["generate-tcn"] => Command::GenerateTcn,
+        ["submit-symptoms", inputs_json] => Command::SubmitSymptoms {
+            inputs_json: (*inputs_json).to_owned(),
+        },
+        ["fetch"] => Command::Fetch,
+        ["alerts"] => Command::Alerts,
+        ["encode-memo", report_json] => Command::EncodeMemo {
+            report_json: (*report_json).to_owned(),
+        },
+        ["decode-memo", memo_hex] => Command::DecodeMemo {
+            memo_hex: (*memo_hex).to_owned(),
+        },
+        _ => return Err(invalid(format!("Invalid command: {:?}", positional))),
+    };
+
+    Ok(Invocation {
+        db_path,
+        config,
+        command,
+    })
+}
+
+fn invalid(message: String) -> ServicesError {
+    ServicesError::FFIParameters(message)
+}
+
+fn execute(invocation: &Invocation) -> Result<String, ServicesError> {
+    // The memo utility doesn't need a database
+    match &invocation.command {
+        Command::EncodeMemo { report_json } => return to_json(&encode_memo_json(report_json)?),
+        Command::DecodeMemo { memo_hex } => return to_json(&decode_memo_hex(memo_hex)?),
+        _ => {}
+    }
+    invocation.config.validate()?;
+    let dependencies =
+        create_profile_dependencies(&invocation.db_path, DEFAULT_PROFILE, &invocation.config)?;
+    let result = execute_with(&dependencies, &invocation.command);
+    // Stops the periodic flushing, flushing pending TCNs
+    dependencies.observed_tcn_processor.stop();
+    result
+}
+
+fn execute_with(
+    dependencies: &CoreDependencies,
+    command: &Command,
+) -> Result<String, ServicesError> {
+    match command {
+        Command::RecordTcn { tcn, distance } => {
+            let processor = &dependencies.observed_tcn_processor;
+            processor.save(tcn, *distance)?;
+            processor.stop();
+            to_json(&TcnDiagnostics {
+                stored: dependencies.reports_updater.tcn_dao.stats()?,
+                malformed_tcn_observations: processor.malformed_tcn_observations(),
+            })
+        }
+        Command::GenerateTcn => {
+            let tcn = dependencies.tcn_keys.generate_tcn()?;
+            to_json(&TcnHex::from(&tcn).into_string())
+        }
+        Command::SubmitSymptoms { inputs_json } => {
+            let inputs: SymptomInputs = serde_json::from_str(inputs_json)
+                .map_err(|e| invalid(format!("Invalid symptom inputs: {}", e)))?;
+            let outcome = dependencies
+                .symptom_inputs_processor
+                .inputs_manager
+                .inputs_submitter
+                .submit_inputs(inputs)?;
+            to_json(&outcome)
+        }
+        Command::Fetch => to_json(&dependencies.reports_updater.update_and_fetch_alerts()?),
+        Command::Alerts => to_json(&dependencies.alert_dao.all()?),
+        Command::EncodeMemo { report_json } => to_json(&encode_memo_json(report_json)?),
+        Command::DecodeMemo { memo_hex } => to_json(&decode_memo_hex(memo_hex)?),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, ServicesError> {
+    serde_json::to_string(value).map_err(ServicesError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fixtures::VERIFICATION_REPORT_MEMO;
+    use serde_json::Value;
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+    use uuid::Uuid;
+
+    // Reports endpoint on localhost: serves no reports and records the posted ones
+    struct MockBackend {
+        url: String,
+        requests: Arc<Mutex<Vec<(String, String)>>>, // Request line, body
+    }
+
+    impl MockBackend {
+        fn start() -> MockBackend {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/tcnreport", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(vec![]));
+            let recorded = requests.clone();
+            // Lives until the test process ends
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        let header = header.to_lowercase();
+                        if let Some(length) = header.strip_prefix("content-length:") {
+                            content_length = length.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    recorded.lock().unwrap().push((
+                        request_line.trim().to_owned(),
+                        String::from_utf8(body).unwrap(),
+                    ));
+
+                    let response = "[]";
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.len(),
+                        response
+                    );
+                }
+            });
+            MockBackend { url, requests }
+        }
+
+        fn requests(&self) -> Vec<(String, String)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn test_commands_against_mock_backend() {
+        let backend = MockBackend::start();
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_cli_{}", Uuid::new_v4()));
+        let db_path = db_dir.to_str().unwrap().to_owned();
+        let run_command = |command: &[&str]| -> Value {
+            let mut args: Vec<String> = vec!["--db".to_owned(), db_path.clone()];
+            args.extend(
+                ["--api-url", &backend.url]
+                    .iter()
+                    .chain(command)
+                    .map(|arg| arg.to_string()),
+            );
+            let output = run(&args);
+            assert!(output.is_ok(), "{:?} failed: {:?}", command, output);
+            serde_json::from_str(&output.unwrap()).unwrap()
+        };
+
+        let tcn = run_command(&["generate-tcn"]);
+        assert_eq!(32, tcn.as_str().unwrap().len());
+
+        let stats = run_command(&["record-tcn", "2485a64b57addcaea3ed1b538d07dbce", "1.5"]);
+        assert_eq!(Value::from(1), stats["total"]);
+
+        let outcome = run_command(&[
+            "submit-symptoms",
+            r#"{"ids":["Cough"],"cough":{"cough_type":"Wet","days":null,"status":null},"breathlessness":{"cause":null},"fever":{"days":null,"taken_temperature_today":null,"temperature_spot":null,"highest_temperature":null},"earliest_symptom":{"time":null}}"#,
+        ]);
+        assert_eq!(Value::from("sent"), outcome["outcome"]);
+        let posts: Vec<(String, String)> = backend
+            .requests()
+            .into_iter()
+            .filter(|(request_line, _)| request_line.starts_with("POST /tcnreport"))
+            .collect();
+        assert_eq!(1, posts.len());
+        assert!(base64::decode(&posts[0].1).is_ok());
+
+        assert_eq!(Value::Array(vec![]), run_command(&["fetch"]));
+        assert!(backend
+            .requests()
+            .iter()
+            .any(|(request_line, _)| request_line.starts_with("GET /tcnreport?intervalNumber=")));
+        assert_eq!(Value::Array(vec![]), run_command(&["alerts"]));
+
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_memo_commands() {
+        let run_command = |command: &[&str]| -> Result<String, String> {
+            let args: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+            run(&args)
+        };
+
+        let report_json = run_command(&["decode-memo", VERIFICATION_REPORT_MEMO]).unwrap();
+        let report: Value = serde_json::from_str(&report_json).unwrap();
+        assert_eq!(Value::from(true), report["breathlessness"]);
+        assert_eq!(Value::from("Serious"), report["fever_severity"]);
+
+        let memo_json = run_command(&["encode-memo", &report_json]).unwrap();
+        assert_eq!(
+            Value::from(VERIFICATION_REPORT_MEMO),
+            serde_json::from_str::<Value>(&memo_json).unwrap()
+        );
+
+        // An error, not the usage
+        let output = run_command(&["decode-memo", "0100"]);
+        assert!(!output.unwrap_err().ends_with(USAGE));
+    }
+
+    #[test]
+    fn test_invalid_invocations_print_usage() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+
+        for invalid_args in &[
+            vec![],
+            vec!["unknown"],
+            vec!["record-tcn", "2485a64b57addcaea3ed1b538d07dbce"],
+            vec!["record-tcn", "2485a64b57addcaea3ed1b538d07dbce", "near"],
+            vec!["fetch", "--db"],
+        ] {
+            let output = run(&args(invalid_args));
+            assert!(output.unwrap_err().ends_with(USAGE));
+        }
+
+        let invocation = parse_args(&args(&[
+            "--api-url",
+            "http://localhost:8080/tcnreport",
+            "--config",
+            r#"{"app_version":"1.2.0 (45)"}"#,
+            "fetch",
+        ]))
+        .unwrap();
+        assert_eq!(Command::Fetch, invocation.command);
+        assert_eq!(".", invocation.db_path);
+        assert_eq!("1.2.0 (45)", invocation.config.app_version);
+        assert_eq!(
+            "http://localhost:8080/tcnreport",
+            invocation.config.networking.api_url
+        );
+    }
+}
@@ -0,0 +1,697 @@
+use crate::{
+    database::database::DEFAULT_SLOW_QUERY_THRESHOLD, errors::ServicesError,
+    networking::DEFAULT_API_URL, reports_interval::UnixTime,
+    tcn_recording::observed_tcn_processor::ObservedTcn,
+};
+use log::*;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+// Version of this library, recorded with submitted reports and created alerts
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Passed by the app on bootstrap, as JSON. Missing fields use the defaults.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoreConfig {
+    // Free form, e.g. "1.2.0 (45)". Only used for support triage.
+    pub app_version: String,
+    pub risk: RiskConfig,
+    pub networking: NetworkingConfig,
+    pub recording: RecordingConfig,
+    pub metrics: MetricsConfig,
+    pub acknowledgement: AcknowledgementConfig,
+    pub database: DatabaseConfig,
+    pub reports: ReportsConfig,
+}
+
+// A config field with an invalid value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigViolation {
+    pub field: String, // Path, e.g. "risk.max_alert_age_days"
+    pub reason: String,
+}
+
+// For the FFI error messages: a JSON array of the violations
+pub fn config_violations_json(violations: &[ConfigViolation]) -> String {
+    serde_json::to_string(violations).unwrap_or_else(|_| format!("{:?}", violations))
+}
+
+impl CoreConfig {
+    // Unknown fields are ignored with a warning, e.g. tunables of a newer version of the library.
+    // The values aren't validated (see validate).
+    pub fn from_json(json: &str) -> Result<CoreConfig, ServicesError> {
+        let invalid = |e: serde_json::Error| {
+            ServicesError::FFIParameters(format!("Invalid core config: {}", e))
+        };
+        let value: Value = serde_json::from_str(json).map_err(invalid)?;
+        let known = serde_json::to_value(CoreConfig::default())?;
+        for field in unknown_fields(&value, &known, "") {
+            warn!("Ignoring unknown core config field: {}", field);
+        }
+        serde_json::from_value(value).map_err(invalid)
+    }
+
+    // Checks all the fields, so the app can fix them in one pass. Err(InvalidConfig) with the invalid ones.
+    pub fn validate(&self) -> Result<(), ServicesError> {
+        let mut violations = vec![];
+        let mut check = |valid: bool, field: &str, reason: &str| {
+            if !valid {
+                violations.push(ConfigViolation {
+                    field: field.to_owned(),
+                    reason: reason.to_owned(),
+                });
+            }
+        };
+        let risk = &self.risk;
+        check(
+            risk.min_samples_per_tcn >= 1,
+            "risk.min_samples_per_tcn",
+            "Must be positive",
+        );
+        check(
+            risk.max_alert_age_days >= 1,
+            "risk.max_alert_age_days",
+            "Must be at least 1 day",
+        );
+        check(
+            risk.report_expiry_days >= 1,
+            "risk.report_expiry_days",
+            "Must be at least 1 day",
+        );
+        check(
+            risk.expired_report_relevance_days >= 1,
+            "risk.expired_report_relevance_days",
+            "Must be at least 1 day",
+        );
+        check(
+            self.networking.max_reports_bytes_per_interval > 0,
+            "networking.max_reports_bytes_per_interval",
+            "Must be positive",
+        );
+        check(
+            self.networking.max_report_pages_per_interval > 0,
+            "networking.max_report_pages_per_interval",
+            "Must be positive",
+        );
+        check(
+            is_http_url(&self.networking.api_url),
+            "networking.api_url",
+            "Must be an http(s) URL",
+        );
+        if self.metrics.enabled || !self.metrics.endpoint.is_empty() {
+            check(
+                is_http_url(&self.metrics.endpoint),
+                "metrics.endpoint",
+                "Must be an http(s) URL",
+            );
+        }
+        if self.database.log_slow_queries {
+            check(
+                self.database.slow_query_threshold_ms > 0,
+                "database.slow_query_threshold_ms",
+                "Must be positive",
+            );
+        }
+        if !self.acknowledgement.endpoint.is_empty() {
+            check(
+                is_http_url(&self.acknowledgement.endpoint),
+                "acknowledgement.endpoint",
+                "Must be an http(s) URL",
+            );
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ServicesError::InvalidConfig(violations))
+        }
+    }
+
+    pub fn client_version(&self) -> ClientVersion {
+        ClientVersion {
+            core: CORE_VERSION.to_owned(),
+            app: self.app_version.clone(),
+        }
+    }
+}
+
+// Health guidance on which contacts count as exposures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiskConfig {
+    // A stored TCN observation (see ObservedTcn) is used for matching only if it meets both thresholds.
+    // One-off observations are often radio noise rather than proximity.
+    pub min_samples_per_tcn: usize,
+    pub min_window_seconds: u64,
+    // Exposures that ended longer ago aren't actionable: they don't create alerts,
+    // and stored alerts are deleted when they reach this age.
+    pub max_alert_age_days: u64,
+    // Reports that weren't seen in the fetched intervals for this long were expired by the backend.
+    // Their alerts are deleted once the contact is older than expired_report_relevance_days.
+    pub report_expiry_days: u64,
+    pub expired_report_relevance_days: u64,
+    // Classify the exposure minutes by the 10th percentile distance instead of the min distance,
+    // which is dominated by a single noisy close sample. Off until the guidance is validated.
+    pub use_p10_distance: bool,
+}
+
+impl Default for RiskConfig {
+    // Every observation counts. Alerts are actionable for 14 days (incubation period).
+    // The backend serves reports for 21 days.
+    fn default() -> Self {
+        RiskConfig {
+            min_samples_per_tcn: 1,
+            min_window_seconds: 0,
+            max_alert_age_days: 14,
+            report_expiry_days: 21,
+            expired_report_relevance_days: 7,
+            use_p10_distance: false,
+        }
+    }
+}
+
+impl RiskConfig {
+    pub fn counts_toward_exposure(&self, tcn: &ObservedTcn) -> bool {
+        let window = tcn
+            .contact_end
+            .value
+            .saturating_sub(tcn.contact_start.value);
+        tcn.total_count >= self.min_samples_per_tcn && window >= self.min_window_seconds
+    }
+
+    // Exposures that ended before it are too old to alert
+    pub fn oldest_alert_contact_end(&self, now: &UnixTime) -> u64 {
+        now.value
+            .saturating_sub(self.max_alert_age_days.saturating_mul(24 * 60 * 60))
+    }
+
+    // Reports last seen in an interval that ended before it are expired
+    pub fn oldest_report_last_seen(&self, now: &UnixTime) -> u64 {
+        now.value
+            .saturating_sub(self.report_expiry_days.saturating_mul(24 * 60 * 60))
+    }
+
+    // Alerts of expired reports whose exposure ended before it are deleted
+    pub fn oldest_expired_report_contact_end(&self, now: &UnixTime) -> u64 {
+        now.value.saturating_sub(
+            self.expired_report_relevance_days
+                .saturating_mul(24 * 60 * 60),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkingConfig {
+    // Reports responses above it are rejected (see NetworkingError::payload_too_large),
+    // so a misbehaving backend can't exhaust the app's memory. Of all the pages of an interval.
+    pub max_reports_bytes_per_interval: u64,
+    // Backends may page large intervals (see get_reports_pages). Intervals with more pages are
+    // rejected like oversized ones.
+    pub max_report_pages_per_interval: u32,
+    // Reports endpoint of the backend, e.g. of a staging deployment
+    pub api_url: String,
+}
+
+impl Default for NetworkingConfig {
+    fn default() -> Self {
+        NetworkingConfig {
+            max_reports_bytes_per_interval: 5 * 1024 * 1024,
+            max_report_pages_per_interval: 50,
+            api_url: DEFAULT_API_URL.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    // Journals the observed TCNs before they're flushed (see TcnBatchesManager),
+    // so a hard kill of the process doesn't lose them. Doubles the write volume.
+    pub journal_tcns: bool,
+    // Keeps the last raw observations in memory, to debug distances in the field
+    // (see ObservedTcnProcessor::recent_observations). Off by default.
+    pub debug_recent_observations: bool,
+    // Stores the 4 byte prefixes of the observed TCNs each alert was matched with, to debug false
+    // positives (see AlertDebugInfo::tcn_prefixes). Off by default.
+    pub debug_alert_tcn_prefixes: bool,
+    // Samples of a TCN within a bucket of this length count once (see TcnBatchesManager::sample_bucket_secs).
+    // 0 counts every sample.
+    pub sample_bucket_secs: u64,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            journal_tcns: false,
+            debug_recent_observations: false,
+            debug_alert_tcn_prefixes: false,
+            sample_bucket_secs: 10,
+        }
+    }
+}
+
+// Opt-in upload of anonymous aggregate counts (see MetricsUploader). Off by default.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    // URL the counts are posted to
+    pub endpoint: String,
+}
+
+// Export of acknowledged alerts to a health department (see AcknowledgementService).
+// Only for pilots that require it: off unless the endpoint is set.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AcknowledgementConfig {
+    // URL the acknowledgements are posted to
+    pub endpoint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReportsConfig {
+    // Replaces the stored symptoms of a refetched report if they changed, e.g. amended by the reporter
+    // (see AlertsUpdateListener). Off for deployments whose reports are immutable.
+    pub update_symptoms: bool,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        ReportsConfig {
+            update_symptoms: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    // Logs and counts the database operations that take longer than the threshold
+    // (see Database::slow_query_threshold, DbStats::slow_queries)
+    pub log_slow_queries: bool,
+    pub slow_query_threshold_ms: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            log_slow_queries: true,
+            slow_query_threshold_ms: DEFAULT_SLOW_QUERY_THRESHOLD.as_millis() as u64,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn slow_query_threshold(&self) -> Option<Duration> {
+        if self.log_slow_queries {
+            Some(Duration::from_millis(self.slow_query_threshold_ms))
+        } else {
+            None
+        }
+    }
+}
+
+fn is_http_url(str: &str) -> bool {
+    Url::parse(str)
+        .map(|url| url.scheme() == "http" || url.scheme() == "https")
+        .unwrap_or(false)
+}
+
+// Paths of the fields of value that aren't in known (e.g. "risk.typo"). Nested objects are compared by field.
+fn unknown_fields(value: &Value, known: &Value, path: &str) -> Vec<String> {
+    match (value, known) {
+        (Value::Object(fields), Value::Object(known_fields)) => fields
+            .iter()
+            .flat_map(|(name, field_value)| {
+                let field_path = if path.is_empty() {
+                    name.to_owned()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match known_fields.get(name) {
+                    Some(known_value) => unknown_fields(field_value, known_value, &field_path),
+                    None => vec![field_path],
+                }
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Versions of the client that created a report or alert
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientVersion {
+    pub core: String,
+    pub app: String,
+}
+
+impl Default for ClientVersion {
+    fn default() -> Self {
+        CoreConfig::default().client_version()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcn_recording::distance_histogram::DistanceHistogram;
+    use tcn::TemporaryContactNumber;
+
+    #[test]
+    fn test_config_from_json() {
+        let config_res = CoreConfig::from_json(r#"{"app_version":"1.2.0 (45)"}"#);
+        assert!(config_res.is_ok());
+        assert_eq!(
+            ClientVersion {
+                core: CORE_VERSION.to_owned(),
+                app: "1.2.0 (45)".to_owned()
+            },
+            config_res.unwrap().client_version()
+        );
+    }
+
+    #[test]
+    fn test_config_missing_fields_use_defaults() {
+        let config_res = CoreConfig::from_json("{}");
+        assert!(config_res.is_ok());
+        assert_eq!(CoreConfig::default(), config_res.unwrap());
+    }
+
+    #[test]
+    fn test_risk_config_from_json() {
+        let config_res =
+            CoreConfig::from_json(r#"{"risk":{"min_samples_per_tcn":3,"min_window_seconds":60}}"#);
+        assert!(config_res.is_ok());
+        assert_eq!(
+            RiskConfig {
+                min_samples_per_tcn: 3,
+                min_window_seconds: 60,
+                ..RiskConfig::default()
+            },
+            config_res.unwrap().risk
+        );
+    }
+
+    #[test]
+    fn test_networking_config_from_json() {
+        let config_res =
+            CoreConfig::from_json(r#"{"networking":{"max_reports_bytes_per_interval":1024}}"#);
+        assert!(config_res.is_ok());
+        let config = config_res.unwrap();
+        assert_eq!(1024, config.networking.max_reports_bytes_per_interval);
+        assert_eq!(RiskConfig::default(), config.risk);
+
+        assert_eq!(
+            5 * 1024 * 1024,
+            CoreConfig::default()
+                .networking
+                .max_reports_bytes_per_interval
+        );
+        assert_eq!(DEFAULT_API_URL, config.networking.api_url);
+        assert_eq!(50, config.networking.max_report_pages_per_interval);
+    }
+
+    #[test]
+    fn test_api_url_must_be_http_url() {
+        let config_res = CoreConfig::from_json(
+            r#"{"networking":{"api_url":"http://localhost:8080/tcnreport"}}"#,
+        );
+        let config = config_res.unwrap();
+        assert_eq!("http://localhost:8080/tcnreport", config.networking.api_url);
+        assert!(config.validate().is_ok());
+
+        let config = CoreConfig {
+            networking: NetworkingConfig {
+                api_url: "localhost:8080".to_owned(),
+                ..NetworkingConfig::default()
+            },
+            ..CoreConfig::default()
+        };
+        match config.validate() {
+            Err(ServicesError::InvalidConfig(violations)) => {
+                assert_eq!(1, violations.len());
+                assert_eq!("networking.api_url", violations[0].field);
+            }
+            other => panic!("Expected InvalidConfig error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recording_config_from_json() {
+        let config_res = CoreConfig::from_json(r#"{"recording":{"journal_tcns":true}}"#);
+        assert!(config_res.is_ok());
+        assert!(config_res.unwrap().recording.journal_tcns);
+
+        assert!(!CoreConfig::default().recording.journal_tcns);
+        assert!(!CoreConfig::default().recording.debug_recent_observations);
+        assert!(!CoreConfig::default().recording.debug_alert_tcn_prefixes);
+        assert_eq!(10, CoreConfig::default().recording.sample_bucket_secs);
+
+        let config_res = CoreConfig::from_json(r#"{"recording":{"sample_bucket_secs":0}}"#);
+        assert_eq!(0, config_res.unwrap().recording.sample_bucket_secs);
+    }
+
+    #[test]
+    fn test_metrics_config_from_json() {
+        let config_res = CoreConfig::from_json(
+            r#"{"metrics":{"enabled":true,"endpoint":"https://metrics.example.org"}}"#,
+        );
+        assert!(config_res.is_ok());
+        assert_eq!(
+            MetricsConfig {
+                enabled: true,
+                endpoint: "https://metrics.example.org".to_owned()
+            },
+            config_res.unwrap().metrics
+        );
+
+        assert!(!CoreConfig::default().metrics.enabled);
+    }
+
+    #[test]
+    fn test_acknowledgement_config() {
+        let config_res = CoreConfig::from_json(
+            r#"{"acknowledgement":{"endpoint":"https://health.example.org/ack"}}"#,
+        );
+        assert!(config_res.is_ok());
+        let config = config_res.unwrap();
+        assert_eq!(
+            "https://health.example.org/ack",
+            config.acknowledgement.endpoint
+        );
+        assert!(config.validate().is_ok());
+
+        // Off by default
+        assert!(CoreConfig::default().acknowledgement.endpoint.is_empty());
+
+        let invalid = CoreConfig {
+            acknowledgement: AcknowledgementConfig {
+                endpoint: "health.example.org".to_owned(),
+            },
+            ..CoreConfig::default()
+        };
+        match invalid.validate() {
+            Err(ServicesError::InvalidConfig(violations)) => {
+                assert_eq!(1, violations.len());
+                assert_eq!("acknowledgement.endpoint", violations[0].field);
+            }
+            other => panic!("Expected InvalidConfig error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_oldest_alert_contact_end() {
+        let risk_config = RiskConfig {
+            max_alert_age_days: 14,
+            ..RiskConfig::default()
+        };
+        assert_eq!(
+            1591706000 - 14 * 24 * 60 * 60,
+            risk_config.oldest_alert_contact_end(&UnixTime { value: 1591706000 })
+        );
+        // Window longer than the time since the epoch
+        assert_eq!(
+            0,
+            risk_config.oldest_alert_contact_end(&UnixTime { value: 1000 })
+        );
+    }
+
+    #[test]
+    fn test_report_expiry_times() {
+        let risk_config = RiskConfig {
+            report_expiry_days: 21,
+            expired_report_relevance_days: 7,
+            ..RiskConfig::default()
+        };
+        let now = UnixTime { value: 1591706000 };
+        assert_eq!(
+            1591706000 - 21 * 24 * 60 * 60,
+            risk_config.oldest_report_last_seen(&now)
+        );
+        assert_eq!(
+            1591706000 - 7 * 24 * 60 * 60,
+            risk_config.oldest_expired_report_contact_end(&now)
+        );
+    }
+
+    #[test]
+    fn test_risk_config_thresholds() {
+        let risk_config = RiskConfig {
+            min_samples_per_tcn: 3,
+            min_window_seconds: 60,
+            ..RiskConfig::default()
+        };
+        let observed_tcn = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1000 },
+            contact_end: UnixTime { value: 1060 },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 3,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        assert!(risk_config.counts_toward_exposure(&observed_tcn));
+        assert!(!risk_config.counts_toward_exposure(&ObservedTcn {
+            total_count: 2,
+            ..observed_tcn.clone()
+        }));
+        assert!(!risk_config.counts_toward_exposure(&ObservedTcn {
+            contact_end: UnixTime { value: 1059 },
+            ..observed_tcn.clone()
+        }));
+        // Default: every observation counts
+        assert!(RiskConfig::default().counts_toward_exposure(&ObservedTcn {
+            contact_end: UnixTime { value: 1000 },
+            total_count: 1,
+            ..observed_tcn
+        }));
+    }
+
+    #[test]
+    fn test_invalid_config_json_is_error() {
+        let config_res = CoreConfig::from_json(r#"{"app_version":1}"#);
+        match config_res {
+            Err(ServicesError::FFIParameters(_)) => {}
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_config_fields_are_ignored() {
+        let json =
+            r#"{"app_version":"1.2.0","theme":"dark","risk":{"max_alert_age_days":10,"typo":1}}"#;
+        let config_res = CoreConfig::from_json(json);
+        assert!(config_res.is_ok());
+        assert_eq!(10, config_res.unwrap().risk.max_alert_age_days);
+
+        let value: Value = serde_json::from_str(json).unwrap();
+        let known = serde_json::to_value(CoreConfig::default()).unwrap();
+        let mut unknown = unknown_fields(&value, &known, "");
+        unknown.sort();
+        assert_eq!(vec!["risk.typo".to_owned(), "theme".to_owned()], unknown);
+    }
+
+    #[test]
+    fn test_risk_config_p10_distance_switch_from_json() {
+        let config_res = CoreConfig::from_json(r#"{"risk":{"use_p10_distance":true}}"#);
+        assert!(config_res.is_ok());
+        let risk = config_res.unwrap().risk;
+        assert!(risk.use_p10_distance);
+        assert_eq!(
+            RiskConfig::default().min_samples_per_tcn,
+            risk.min_samples_per_tcn
+        );
+
+        assert!(!RiskConfig::default().use_p10_distance);
+    }
+
+    #[test]
+    fn test_reports_config_from_json() {
+        assert!(CoreConfig::default().reports.update_symptoms);
+
+        let config_res = CoreConfig::from_json(r#"{"reports":{"update_symptoms":false}}"#);
+        assert!(config_res.is_ok());
+        assert!(!config_res.unwrap().reports.update_symptoms);
+    }
+
+    #[test]
+    fn test_database_config() {
+        assert_eq!(
+            Some(Duration::from_millis(100)),
+            CoreConfig::default().database.slow_query_threshold()
+        );
+
+        let config_res = CoreConfig::from_json(r#"{"database":{"slow_query_threshold_ms":250}}"#);
+        assert!(config_res.is_ok());
+        assert_eq!(
+            Some(Duration::from_millis(250)),
+            config_res.unwrap().database.slow_query_threshold()
+        );
+
+        let config_res = CoreConfig::from_json(r#"{"database":{"log_slow_queries":false}}"#);
+        assert!(config_res.is_ok());
+        let config = config_res.unwrap();
+        assert_eq!(None, config.database.slow_query_threshold());
+        assert!(config.validate().is_ok());
+
+        let invalid = CoreConfig {
+            database: DatabaseConfig {
+                slow_query_threshold_ms: 0,
+                ..DatabaseConfig::default()
+            },
+            ..CoreConfig::default()
+        };
+        match invalid.validate() {
+            Err(ServicesError::InvalidConfig(violations)) => {
+                assert_eq!(1, violations.len());
+                assert_eq!("database.slow_query_threshold_ms", violations[0].field);
+            }
+            other => panic!("Expected InvalidConfig error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(CoreConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_all_violations() {
+        let config = CoreConfig {
+            risk: RiskConfig {
+                min_samples_per_tcn: 0,
+                max_alert_age_days: 0,
+                ..RiskConfig::default()
+            },
+            metrics: MetricsConfig {
+                enabled: true,
+                endpoint: "metrics.example.org".to_owned(),
+            },
+            ..CoreConfig::default()
+        };
+
+        match config.validate() {
+            Err(ServicesError::InvalidConfig(violations)) => {
+                let fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+                assert_eq!(
+                    vec![
+                        "risk.min_samples_per_tcn",
+                        "risk.max_alert_age_days",
+                        "metrics.endpoint"
+                    ],
+                    fields
+                );
+                assert_eq!(
+                    r#"[{"field":"risk.min_samples_per_tcn","reason":"Must be positive"},{"field":"risk.max_alert_age_days","reason":"Must be at least 1 day"},{"field":"metrics.endpoint","reason":"Must be an http(s) URL"}]"#,
+                    config_violations_json(&violations)
+                );
+            }
+            other => panic!("Expected InvalidConfig error, got: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// Canonical JSON samples of the FFI payloads, built from fixed data. Debug and test builds only.
+// They're committed as golden files (conformance/<name>.json, see canonical_json), which the app test
+// suites parse too. Changing a serialized field fails test_samples_match_golden_files: update the files
+// with `UPDATE_CONFORMANCE_SAMPLES=1 cargo test conformance` and tell the app teams.
+use crate::{
+    config::{
+        AcknowledgementConfig, CoreConfig, DatabaseConfig, MetricsConfig, NetworkingConfig,
+        RecordingConfig, ReportsConfig, RiskConfig,
+    },
+    database::{
+        alert_dao::AlertsPage,
+        migration::{MigrationOutcome, MigrationReport, MigrationStepReport},
+        tcn_dao::TcnStats,
+    },
+    dependencies::BootstrapReport,
+    errors::ServicesError,
+    reporting::{
+        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
+        symptom_inputs::{SubmitOutcome, UserInput},
+    },
+    reports_interval::UnixTime,
+    reports_update::reports_updater::{
+        Alert, AlertDebugInfo, AlertSummary, ExposureMinutes, UpdateMetrics,
+    },
+    tcn_ext::tcn_keys::KeyIntegrityIssue,
+    tcn_recording::observed_tcn_processor::{SaveBatchOutcome, TcnDiagnostics},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Id of the verification report (see test_support::fixtures::VERIFICATION_REPORT)
+const REPORT_ID: &str = "a3ca7559d19e5dbe4ee7fde437ac7b192ca5822606206b00065dcdaf1849bbd5\
+    c7c2c3777facaf470531b367e13f8a14e44dfe25669b04feafd3da46575c510b";
+const CONTACT_START: u64 = 1590528300;
+
+// By sample name. Floats are exactly representable, so their JSON doesn't depend on the formatter.
+// Served by get_conformance_samples, which isn't in host builds.
+#[allow(dead_code)]
+pub fn conformance_samples() -> Result<BTreeMap<String, Value>, ServicesError> {
+    let alert = alert();
+    let samples = vec![
+        sample("alert", &alert)?,
+        sample(
+            "alerts_page",
+            &AlertsPage {
+                alerts: vec![alert.clone()],
+                next_cursor: Some(format!("{}:{}", CONTACT_START, alert.id)),
+                total: Some(3),
+            },
+        )?,
+        sample(
+            "alert_summary",
+            &AlertSummary {
+                total: 3,
+                unread: 1,
+                last_exposure_end: Some(1590529200),
+                exposures_last_14_days: 2,
+            },
+        )?,
+        sample(
+            "exposure_minutes",
+            &ExposureMinutes {
+                close: 15,
+                medium: 30,
+                far: 45,
+            },
+        )?,
+        sample("update_metrics", &update_metrics())?,
+        sample(
+            "submit_outcome_sent",
+            &SubmitOutcome::Sent {
+                receipt: "8d0f5c2e-6d1b-4a8e-9f3c-2b7a1e4d6c90".to_owned(),
+            },
+        )?,
+        sample(
+            "submit_outcome_nothing_to_send",
+            &SubmitOutcome::NothingToSend,
+        )?,
+        sample(
+            "submit_outcome_rejected",
+            &SubmitOutcome::Rejected {
+                http_status: 422,
+                reason: "Invalid report".to_owned(),
+            },
+        )?,
+        sample("bootstrap_report", &bootstrap_report())?,
+        sample("config", &config())?,
+        sample(
+            "tcn_diagnostics",
+            &TcnDiagnostics {
+                stored: TcnStats {
+                    total: 120,
+                    matched: 2,
+                    unmatched: 118,
+                },
+                malformed_tcn_observations: 1,
+            },
+        )?,
+        sample(
+            "save_batch_outcome",
+            &SaveBatchOutcome {
+                accepted: 9,
+                invalid: 1,
+            },
+        )?,
+    ];
+    Ok(samples.into_iter().collect())
+}
+
+fn sample<T: Serialize>(name: &str, value: &T) -> Result<(String, Value), ServicesError> {
+    Ok((name.to_owned(), serde_json::to_value(value)?))
+}
+
+fn alert() -> Alert {
+    Alert {
+        id: format!("{}-{}", REPORT_ID, CONTACT_START),
+        report_id: REPORT_ID.to_owned(),
+        symptoms: PublicSymptoms {
+            report_time: UnixTime { value: 1589209754 },
+            earliest_symptom_time: UserInput::Some(UnixTime { value: 1589209754 }),
+            fever_severity: FeverSeverity::Serious,
+            cough_severity: CoughSeverity::Existing,
+            breathlessness: true,
+            muscle_aches: true,
+            loss_smell_or_taste: false,
+            diarrhea: false,
+            runny_nose: true,
+            other: false,
+            no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        },
+        contact_start: CONTACT_START,
+        contact_end: 1590529200,
+        min_distance: 2.5,
+        avg_distance: 4.25,
+        p10_distance: 2.75,
+        median_distance: 4.0,
+        is_read: false,
+        has_raw_report: true,
+        report_coverage_start: Some(1590451200),
+        report_coverage_end: Some(1590537600),
+        debug_info: AlertDebugInfo {
+            core_version: Some("0.1.0".to_owned()),
+            app_version: Some("1.2.0 (45)".to_owned()),
+            tcn_prefixes: vec!["18e57df5".to_owned(), "a1b2c3d4".to_owned()],
+        },
+        snoozed_until: None,
+        retracted: false,
+    }
+}
+
+fn update_metrics() -> UpdateMetrics {
+    UpdateMetrics {
+        time: 1590537600,
+        duration_millis: 1250,
+        intervals: 4,
+        reports: 200,
+        matches: 1,
+        oversized_intervals: 0,
+        normalized_reports: 2,
+        expired_exposures: 0,
+        filtered_reports: 3,
+        unchanged_intervals: 1,
+        invalid_reports: 1,
+        error_code: None,
+    }
+}
+
+fn bootstrap_report() -> BootstrapReport {
+    BootstrapReport {
+        migration: MigrationReport {
+            from_version: 26,
+            to_version: 28,
+            steps: vec![
+                MigrationStepReport {
+                    version: 26,
+                    duration_ms: 12,
+                    rows_affected: 0,
+                    outcome: MigrationOutcome::Applied,
+                },
+                MigrationStepReport {
+                    version: 27,
+                    duration_ms: 35,
+                    rows_affected: 4,
+                    outcome: MigrationOutcome::Applied,
+                },
+            ],
+        },
+        keys_reset: Some(KeyIntegrityIssue::TckKeyMismatch),
+    }
+}
+
+// The config the app passes to bootstrap. Explicit values, so changing a default doesn't change the sample.
+fn config() -> CoreConfig {
+    CoreConfig {
+        app_version: "1.2.0 (45)".to_owned(),
+        risk: RiskConfig {
+            min_samples_per_tcn: 2,
+            min_window_seconds: 60,
+            max_alert_age_days: 14,
+            report_expiry_days: 21,
+            expired_report_relevance_days: 7,
+            use_p10_distance: true,
+        },
+        networking: NetworkingConfig {
+            max_reports_bytes_per_interval: 5242880,
+            max_report_pages_per_interval: 50,
+            api_url: "https://reports.example.org/tcnreport/0.4.0".to_owned(),
+        },
+        recording: RecordingConfig {
+            journal_tcns: true,
+            debug_recent_observations: false,
+            debug_alert_tcn_prefixes: true,
+            sample_bucket_secs: 10,
+        },
+        metrics: MetricsConfig {
+            enabled: true,
+            endpoint: "https://metrics.example.org/coepi".to_owned(),
+        },
+        acknowledgement: AcknowledgementConfig {
+            endpoint: "".to_owned(),
+        },
+        database: DatabaseConfig {
+            log_slow_queries: true,
+            slow_query_threshold_ms: 100,
+        },
+        reports: ReportsConfig {
+            update_symptoms: true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::BTreeSet,
+        env, fs,
+        path::{Path, PathBuf},
+    };
+
+    // Format of the golden files: pretty printed (keys sorted), with a trailing newline
+    fn canonical_json(sample: &Value) -> Result<String, ServicesError> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(sample)?))
+    }
+
+    fn golden_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance")
+    }
+
+    #[test]
+    fn test_samples_match_golden_files() {
+        let samples = conformance_samples().unwrap();
+        let update = env::var("UPDATE_CONFORMANCE_SAMPLES").is_ok();
+
+        for (name, sample) in &samples {
+            let path = golden_dir().join(format!("{}.json", name));
+            let json = canonical_json(sample).unwrap();
+            if update {
+                fs::write(&path, &json).unwrap();
+            }
+            let golden = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Couldn't read golden file {:?}: {}", path, e));
+            assert_eq!(
+                golden, json,
+                "Sample {} doesn't match its golden file (see conformance.rs to update it)",
+                name
+            );
+        }
+    }
+
+    // Golden files of removed samples would be tested by the apps, but not by the core
+    #[test]
+    fn test_golden_files_have_samples() {
+        let names: BTreeSet<String> = conformance_samples().unwrap().keys().cloned().collect();
+        let golden_names: BTreeSet<String> = fs::read_dir(golden_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, golden_names);
+    }
+}
@@ -1,143 +1,794 @@
-use super::database::Database;
+use super::database::{column, to_db_i64, Database};
 use crate::{
     errors::ServicesError,
-    expect_log,
+    ffi_params::invalid_param,
     reporting::{
-        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms},
+        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
         symptom_inputs::UserInput,
     },
     reports_interval,
-    reports_update::reports_updater::Alert,
+    reports_update::{
+        exposure::{union_duration, ReportExposure},
+        reports_updater::{Alert, AlertDebugInfo, AlertSummary, ExposureMinutes, ReportAlerts},
+    },
 };
 use log::*;
 use reports_interval::UnixTime;
-use rusqlite::{params, Row, NO_PARAMS};
-use std::sync::Arc;
+use rusqlite::{params, Row, Transaction, NO_PARAMS};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+const RECENT_EXPOSURES_DAYS: u64 = 14;
+
+// Exposure minutes buckets: close is below CLOSE_CONTACT_DISTANCE, medium below
+// MEDIUM_CONTACT_DISTANCE, far the rest. Meters.
+const CLOSE_CONTACT_DISTANCE: f64 = 2.0;
+const MEDIUM_CONTACT_DISTANCE: f64 = 4.0;
+
+// Number of interval states kept (see ReportIntervalState). The states of the earliest intervals are
+// deleted when new ones are saved.
+pub const REPORT_INTERVAL_STATES_SIZE: i64 = 32;
+
+// Max characters of the alert and report ids. Report ids are 128 (hex of the report signature), alert
+// ids add the contact start to them. Well above the legacy ids (Debug format of the signature).
+pub const MAX_ID_LENGTH: usize = 256;
+
+// Not deleted alerts, with the symptoms of their report, in the column order expected by to_alert
+const SELECT_ALERTS: &str = "select
+    a.id,
+    a.start,
+    a.end,
+    a.min_distance,
+    a.avg_distance,
+    p.report_time,
+    p.earliest_symptom_time,
+    p.fever_severity,
+    p.cough_severity,
+    p.breathlessness,
+    p.muscle_aches,
+    p.loss_smell_or_taste,
+    p.diarrhea,
+    p.runny_nose,
+    p.other,
+    p.no_symptoms,
+    a.report_id,
+    a.read,
+    p.memo_version,
+    b.report_id is not null,
+    a.report_coverage_start,
+    a.report_coverage_end,
+    a.core_version,
+    a.app_version,
+    a.snoozed_until,
+    a.p10_distance,
+    a.median_distance,
+    r.report_id is not null,
+    e.tcn_prefixes,
+    p.report_kind
+    from alert a left join report p on a.report_id = p.report_id
+    left join report_blob b on a.report_id = b.report_id
+    left join retracted_report r on a.report_id = r.report_id
+    left join exposure e on a.id = e.alert_id
+    where a.deleted is null";
+
+// Counts of the not deleted alerts (see AlertSummary). ?1: start of the recent exposures.
+const SELECT_ALERT_SUMMARY: &str = "select
+    count(*),
+    coalesce(sum(read = 0), 0),
+    max(end),
+    coalesce(sum(end >= ?1), 0)
+    from alert where deleted is null";
+
+// Only changes the row if the symptoms differ. ?1: report id, ?2 - ?14: symptoms, ?15: update time.
+const UPDATE_REPORT_SYMPTOMS: &str = "update report set
+    report_time=?2,
+    earliest_symptom_time=?3,
+    fever_severity=?4,
+    cough_severity=?5,
+    breathlessness=?6,
+    muscle_aches=?7,
+    loss_smell_or_taste=?8,
+    diarrhea=?9,
+    runny_nose=?10,
+    other=?11,
+    no_symptoms=?12,
+    memo_version=?13,
+    report_kind=?14,
+    symptoms_updated_at=?15
+    where report_id=?1 and (
+        report_time, earliest_symptom_time, fever_severity, cough_severity, breathlessness, muscle_aches,
+        loss_smell_or_taste, diarrhea, runny_nose, other, no_symptoms, memo_version, report_kind
+    ) is not (?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)";
+
+const DELETE_REPORT_ALERTS: &str =
+    "update alert set deleted=1 where report_id=?1 and deleted is null";
+
+// Appended to SELECT_ALERTS: excludes the alerts snoozed until after ?1
+const NOT_SNOOZED_CONDITION: &str = "and (a.snoozed_until is null or a.snoozed_until <= ?1)";
+
+// Appended to SELECT_ALERTS and NOT_SNOOZED_CONDITION: a page of alerts, newest first (keyset
+// pagination). ?2, ?3: start and id of the last alert of the previous page (null for the first page),
+// ?4: limit.
+const ALERTS_PAGE_CONDITION: &str = "and (?2 is null or (a.start, a.id) < (?2, ?3))
+    order by a.start desc, a.id desc limit ?4";
+
+// Appended to NOT_SNOOZED_CONDITION: the alerts of all pages
+const COUNT_ALERTS: &str = "select count(*) from alert a where a.deleted is null";
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct DbStats {
+    // Loaded alert rows with invalid values that were repaired (see AlertDaoImpl::to_alert), since the
+    // DAO was created
+    pub normalized_alerts: u64,
+    // Current size of the database file, to diagnose StorageFull errors. Free pages are reused before
+    // the file grows. 0 if they couldn't be read.
+    pub page_size: u64,
+    pub page_count: u64,
+    pub free_pages: u64,
+    // Alerts deleted by the user or expired. The rows are kept with the deleted flag, so the alerts
+    // aren't restored by the next reports update. 0 if they couldn't be counted.
+    pub deleted_alerts: u64,
+    // Database operations above the slow query threshold (see Database::slow_query_threshold), since the
+    // database was opened
+    pub slow_queries: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SaveOutcome {
+    pub saved: usize, // New alerts (alerts with an already stored id are ignored)
+    pub failed: Vec<(String, ServicesError)>, // Alert id, error
+}
+
+// Alerts of a page, newest (by contact start) first. Without the alerts that are snoozed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertsPage {
+    pub alerts: Vec<Alert>,
+    // Opaque: passed to get the next page. None if this is the last page.
+    pub next_cursor: Option<String>,
+    // Number of alerts in all the pages. Only set in the first page.
+    pub total: Option<u32>,
+}
+
+// Position after the last alert of a page: its stored start and id. Encoded as "start:id".
+#[derive(Debug, Clone, PartialEq)]
+struct AlertsCursor {
+    start: i64,
+    id: String,
+}
+
+impl AlertsCursor {
+    fn parse(cursor: &str) -> Result<AlertsCursor, ServicesError> {
+        let mut parts = cursor.splitn(2, ':');
+        let start = parts.next().and_then(|start| start.parse().ok());
+        match (start, parts.next()) {
+            (Some(start), Some(id)) => Ok(AlertsCursor {
+                start,
+                id: id.to_owned(),
+            }),
+            _ => Err(invalid_param("cursor", "is not an alerts page cursor")),
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!("{}:{}", self.start, self.id)
+    }
+}
+
+// A fetched report, by the reports intervals it was seen in (see ReportsUpdater)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessedReport {
+    pub report_id: String,
+    pub first_seen_interval: u64,
+    pub last_seen_interval: u64,
+    pub interval_length: u64,
+    // Identifies the reports of the last seen interval and the TCNs they were matched with
+    pub content_hash: String,
+}
+
+// A fetched reports interval, as it was last processed (see ReportsUpdater)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportIntervalState {
+    pub interval_number: u64,
+    pub interval_length: u64,
+    // Identifies the report strings, as the backend served them
+    pub payload_hash: String,
+    // Identifies the TCNs and filters the reports were matched with
+    pub inputs_hash: String,
+}
 
 pub trait AlertDao {
+    // Without the alerts that are snoozed
     fn all(&self) -> Result<Vec<Alert>, ServicesError>;
-    fn save(&self, alerts: Vec<Alert>) -> Result<(), ServicesError>;
+    fn all_including_snoozed(&self) -> Result<Vec<Alert>, ServicesError>;
+    // Err(NotFound) if there's no (not deleted) alert with the id
+    fn alert(&self, id: &str) -> Result<Alert, ServicesError>;
+    // Grouped by report, ordered by most recent exposure. Without the alerts that are snoozed.
+    fn all_grouped(&self) -> Result<Vec<ReportAlerts>, ServicesError>;
+    fn alert_summary(&self) -> Result<AlertSummary, ServicesError>;
+    // cursor: next_cursor of the previous page, None for the first page.
+    // New alerts saved between the page fetches don't shift the following pages.
+    fn alerts_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: u32,
+    ) -> Result<AlertsPage, ServicesError>;
+    // Minutes of exposure in the last days, by min distance (or 10th percentile distance, see
+    // AlertDaoImpl::use_p10_distance). Only the part of an exposure within the window counts, and
+    // overlapping exposures of a bucket count once (see union_duration).
+    // Uses the exposure window of the alert if persisted.
+    fn exposure_minutes(&self, days: u32) -> Result<ExposureMinutes, ServicesError>;
+    // Alerts that fail don't prevent the others from being saved.
+    // Err(StorageFull) if the device storage is full: no alert is saved.
+    fn save(&self, alerts: Vec<Alert>) -> Result<SaveOutcome, ServicesError>;
     fn delete(&self, id: String) -> Result<(), ServicesError>;
+    // Deletes the alerts of the report, and suppresses its future exposures (see is_report_dismissed).
+    // Returns the number of deleted alerts.
+    fn delete_by_report(&self, report_id: String) -> Result<usize, ServicesError>;
+    fn is_report_dismissed(&self, report_id: &str) -> Result<bool, ServicesError>;
+    // Flags the alerts of the reports as retracted by the "no symptoms" report, including later alerts of
+    // the reports. Only reports with alerts whose report time is before retracted_by_time are retracted.
+    // Returns the number of newly retracted reports.
+    fn retract_reports(
+        &self,
+        report_ids: &[String],
+        retracted_by: &str,
+        retracted_by_time: UnixTime,
+    ) -> Result<usize, ServicesError>;
+    // Deletes the alerts whose contact ended before the time (no longer actionable).
+    // Returns the number of deleted alerts.
+    fn delete_ended_before(&self, contact_end: u64) -> Result<usize, ServicesError>;
     fn update_is_read(&self, id: String, is_read: bool) -> Result<(), ServicesError>;
+    // Hides the alert from all() until the time. Err(NotFound) if there's no (not deleted) alert with the id.
+    fn snooze(&self, id: String, until: UnixTime) -> Result<(), ServicesError>;
+    // Records the export of the alert's acknowledgement (see AcknowledgementService).
+    // Err(NotFound) if there's no (not deleted) alert with the id.
+    fn set_acknowledgement_submitted(&self, id: &str, time: UnixTime) -> Result<(), ServicesError>;
+    // Time of the export, None if not exported. Err(NotFound) if there's no (not deleted) alert with the id.
+    fn acknowledgement_submitted(&self, id: &str) -> Result<Option<u64>, ServicesError>;
+    // Updates the contact window and distances of stored alerts whose exposure was extended.
+    // Read and deleted state are kept.
+    fn update_measurements(&self, alerts: &[Alert]) -> Result<(), ServicesError>;
+    // Replaces the stored symptoms of the reports whose decoded symptoms changed (e.g. amended by the
+    // reporter), and sets their symptoms_updated_at to the time. Reports that aren't stored are ignored.
+    // Returns the ids of the updated reports.
+    fn update_report_symptoms(
+        &self,
+        reports: &[(String, PublicSymptoms)],
+        time: &UnixTime,
+    ) -> Result<Vec<String>, ServicesError>;
+    fn save_raw_report(&self, report_id: String, report: String) -> Result<(), ServicesError>;
+    fn raw_report(&self, report_id: String) -> Result<String, ServicesError>;
+    // Persisted exposures of the report (see ExposureGrouper::extend), ordered by start
+    fn exposures(&self, report_id: &str) -> Result<Vec<ReportExposure>, ServicesError>;
+    // Inserts or replaces (by alert id)
+    fn save_exposures(&self, exposures: Vec<ReportExposure>) -> Result<(), ServicesError>;
+    // None if the report wasn't fetched yet
+    fn processed_report(&self, report_id: &str) -> Result<Option<ProcessedReport>, ServicesError>;
+    // Inserts or updates (by report id). The first seen interval is kept, the last seen interval and
+    // content hash are only replaced by a later interval (retried intervals are older).
+    fn save_processed_reports(&self, reports: Vec<ProcessedReport>) -> Result<(), ServicesError>;
+    // Deletes the alerts whose contact ended before contact_end, of the reports last seen in an interval
+    // that ended before last_seen_end. Returns the number of deleted alerts.
+    fn expire_unseen_reports(
+        &self,
+        last_seen_end: u64,
+        contact_end: u64,
+    ) -> Result<usize, ServicesError>;
+    // Forgets the reports last seen in an interval that ended before the time.
+    // Returns the number of deleted reports.
+    fn delete_processed_reports_seen_before(
+        &self,
+        last_seen_end: u64,
+    ) -> Result<usize, ServicesError>;
+    // None if the interval wasn't processed (or its state was deleted)
+    fn report_interval_state(
+        &self,
+        interval_number: u64,
+        interval_length: u64,
+    ) -> Result<Option<ReportIntervalState>, ServicesError>;
+    // Inserts or replaces (by interval). Keeps only the REPORT_INTERVAL_STATES_SIZE latest intervals.
+    fn save_report_interval_states(
+        &self,
+        states: Vec<ReportIntervalState>,
+    ) -> Result<(), ServicesError>;
+    fn db_stats(&self) -> DbStats;
 }
 
 pub struct AlertDaoImpl {
     db: Arc<Database>,
+    normalized_alerts: AtomicU64,
+    use_p10_distance: bool,
 }
 
 impl AlertDaoImpl {
     pub fn new(db: Arc<Database>) -> AlertDaoImpl {
-        Self::create_table_if_not_exists(&db);
-        AlertDaoImpl { db }
-    }
-
-    fn create_table_if_not_exists(db: &Arc<Database>) {
-        // TODO use blob for tcn? https://docs.rs/rusqlite/0.23.1/rusqlite/blob/index.html
-        // TODO ideally FFI should send byte arrays too
-        let res = db.execute_sql(
-            "create table if not exists alert(
-                id text primary key,
-                start integer not null,
-                end integer not null,
-                min_distance real not null,
-                avg_distance real not null,
-                report_time integer not null,
-                earliest_symptom_time integer,
-                fever_severity integer not null,
-                cough_severity integer not null,
-                breathlessness integer not null,
-                muscle_aches integer not null,
-                loss_smell_or_taste integer not null,
-                diarrhea integer not null,
-                runny_nose integer not null,
-                other integer not null,
-                no_symptoms integer not null,
-                report_id text not null,
-                read integer not null,
-                deleted integer
-            )",
-            params![],
-        );
-        expect_log!(res, "Couldn't create Alert table");
+        AlertDaoImpl {
+            db,
+            normalized_alerts: AtomicU64::new(0),
+            use_p10_distance: false,
+        }
+    }
+
+    // Classifies the exposure minutes by the 10th percentile distance instead of the min distance
+    // (see RiskConfig::use_p10_distance)
+    pub fn use_p10_distance(mut self, use_p10_distance: bool) -> Self {
+        self.use_p10_distance = use_p10_distance;
+        self
+    }
+
+    // Returns the number of inserted rows (0 if an alert with the same id exists).
+    // The symptoms are stored with the first alert of the report: the alerts of a report have the same symptoms.
+    fn insert(
+        t: &Transaction,
+        alert: &Alert,
+        received_at: &UnixTime,
+    ) -> Result<usize, ServicesError> {
+        check_id_length(&alert.id, "Alert id")?;
+        check_id_length(&alert.report_id, "Report id")?;
+        let contact_start = to_db_i64(alert.contact_start, "Alert contact start")?;
+        let contact_end = to_db_i64(alert.contact_end, "Alert contact end")?;
+        let report_time = to_db_i64(alert.symptoms.report_time.value, "Report time")?;
+        let earliest_symptom_time = match alert.symptoms.earliest_symptom_time.as_opt() {
+            Some(unix_time) => Some(to_db_i64(unix_time.value, "Earliest symptom time")?),
+            None => None,
+        };
+        let report_coverage_start = match alert.report_coverage_start {
+            Some(start) => Some(to_db_i64(start, "Report coverage start")?),
+            None => None,
+        };
+        let report_coverage_end = match alert.report_coverage_end {
+            Some(end) => Some(to_db_i64(end, "Report coverage end")?),
+            None => None,
+        };
+        let snoozed_until = match alert.snoozed_until {
+            Some(until) => Some(to_db_i64(until, "Snoozed until")?),
+            None => None,
+        };
+        let received_at = to_db_i64(received_at.value, "Report received at")?;
+        t.execute(
+            "insert or ignore into report(
+                report_id,
+                report_time,
+                earliest_symptom_time,
+                fever_severity,
+                cough_severity,
+                breathlessness,
+                muscle_aches,
+                loss_smell_or_taste,
+                diarrhea,
+                runny_nose,
+                other,
+                no_symptoms,
+                memo_version,
+                received_at,
+                report_kind
+            ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                alert.report_id,
+                report_time,
+                earliest_symptom_time,
+                alert.symptoms.fever_severity.as_u8() as i64,
+                alert.symptoms.cough_severity.as_u8() as i64,
+                to_db_int(alert.symptoms.breathlessness),
+                to_db_int(alert.symptoms.muscle_aches),
+                to_db_int(alert.symptoms.loss_smell_or_taste),
+                to_db_int(alert.symptoms.diarrhea),
+                to_db_int(alert.symptoms.runny_nose),
+                to_db_int(alert.symptoms.other),
+                to_db_int(alert.symptoms.no_symptoms),
+                alert.symptoms.memo_version as i64,
+                received_at,
+                alert.symptoms.report_kind.as_u8() as i64
+            ],
+        )?;
+        t.execute(
+            "insert or ignore into alert(
+                id,
+                start,
+                end,
+                min_distance,
+                avg_distance,
+                report_id,
+                read,
+                report_coverage_start,
+                report_coverage_end,
+                core_version,
+                app_version,
+                snoozed_until,
+                p10_distance,
+                median_distance
+            ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                alert.id,
+                contact_start,
+                contact_end,
+                alert.min_distance as f64,
+                alert.avg_distance as f64,
+                alert.report_id,
+                to_db_int(alert.is_read),
+                report_coverage_start,
+                report_coverage_end,
+                alert.debug_info.core_version,
+                alert.debug_info.app_version,
+                snoozed_until,
+                alert.p10_distance as f64,
+                alert.median_distance as f64
+            ],
+        )
+        .map_err(ServicesError::from)
+    }
+
+    // Inserts the alert in a savepoint: if the alert insert fails, its report insert is rolled back too,
+    // so no report is left without alerts.
+    fn insert_in_savepoint(
+        t: &Transaction,
+        alert: &Alert,
+        received_at: &UnixTime,
+    ) -> Result<usize, ServicesError> {
+        t.execute_batch("savepoint alert_insert;")?;
+        match Self::insert(t, alert, received_at) {
+            Ok(inserted) => {
+                t.execute_batch("release alert_insert;")?;
+                Ok(inserted)
+            }
+            Err(error) => {
+                let rollback_res =
+                    t.execute_batch("rollback to alert_insert; release alert_insert;");
+                if rollback_res.is_err() {
+                    // As we're already returning the insert error, show only a log for the rollback error.
+                    error!(
+                        "Couldn't roll back insert of alert: {}, error: {:?}",
+                        alert.id, rollback_res
+                    );
+                }
+                Err(error)
+            }
+        }
+    }
+
+    fn save_at(&self, alerts: Vec<Alert>, now: &UnixTime) -> Result<SaveOutcome, ServicesError> {
+        let mut outcome = SaveOutcome::default();
+        self.db.transaction(|t| {
+            // A failing insert is rolled back alone: the other alerts are still committed.
+            // Except with the storage full: everything is rolled back, and the error returned.
+            for alert in alerts {
+                match Self::insert_in_savepoint(t, &alert, now) {
+                    Ok(inserted) => outcome.saved += inserted,
+                    Err(ServicesError::StorageFull) => {
+                        error!("Couldn't save alert: {}, storage is full", alert.id);
+                        return Err(ServicesError::StorageFull);
+                    }
+                    Err(error) => {
+                        error!("Couldn't save alert: {}, error: {:?}", alert.id, error);
+                        outcome.failed.push((alert.id, error));
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        Ok(outcome)
     }
 
-    fn to_alert(row: &Row) -> Alert {
-        let id_res = row.get(0);
-        let id = expect_log!(id_res, "Invalid row: no id");
+    fn all_at(&self, now: &UnixTime) -> Result<Vec<Alert>, ServicesError> {
+        self.db
+            .query(
+                &format!("{} {}", SELECT_ALERTS, NOT_SNOOZED_CONDITION),
+                params![to_db_i64(now.value, "Now")?],
+                |row| self.to_alert(row),
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
 
-        let start_res = row.get(1);
-        let start: i64 = expect_log!(start_res, "Invalid row: no start");
+    fn alerts_page_at(
+        &self,
+        cursor: Option<&str>,
+        page_size: u32,
+        now: &UnixTime,
+    ) -> Result<AlertsPage, ServicesError> {
+        if page_size == 0 {
+            return Err(invalid_param("page_size", "is 0"));
+        }
+        let cursor = cursor.map(AlertsCursor::parse).transpose()?;
+        let now = to_db_i64(now.value, "Now")?;
 
-        let end_res = row.get(2);
-        let end: i64 = expect_log!(end_res, "Invalid row: no end");
+        // One more than the page size: tells if there's a next page
+        let mut rows = self
+            .db
+            .query(
+                &format!(
+                    "{} {} {}",
+                    SELECT_ALERTS, NOT_SNOOZED_CONDITION, ALERTS_PAGE_CONDITION
+                ),
+                params![
+                    now,
+                    cursor.as_ref().map(|cursor| cursor.start),
+                    cursor.as_ref().map(|cursor| cursor.id.clone()),
+                    page_size as i64 + 1
+                ],
+                // The stored start: to_alert may repair it
+                |row| Ok((column::<i64>(row, 1, "start")?, self.to_alert(row)?)),
+            )?
+            .rows;
+
+        let next_cursor = if rows.len() > page_size as usize {
+            rows.truncate(page_size as usize);
+            rows.last().map(|(start, alert)| {
+                AlertsCursor {
+                    start: *start,
+                    id: alert.id.clone(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
 
-        let min_distance_res = row.get(3);
-        let min_distance: f64 = expect_log!(min_distance_res, "Invalid row: no min_distance");
+        let total = match cursor {
+            Some(_) => None,
+            None => Some(self.db.query_row(
+                &format!("{} {}", COUNT_ALERTS, NOT_SNOOZED_CONDITION),
+                params![now],
+                |row| row.get::<_, i64>(0),
+            )? as u32),
+        };
 
-        let avg_distance_res = row.get(4);
-        let avg_distance: f64 = expect_log!(avg_distance_res, "Invalid row: no avg_distance");
+        Ok(AlertsPage {
+            alerts: rows.into_iter().map(|(_, alert)| alert).collect(),
+            next_cursor,
+            total,
+        })
+    }
 
-        let report_time_res = row.get(5);
-        let report_time: i64 = expect_log!(report_time_res, "Invalid row: no report_time");
+    fn alert_summary_at(&self, now: &UnixTime) -> Result<AlertSummary, ServicesError> {
+        let recent_start = now
+            .value
+            .saturating_sub(RECENT_EXPOSURES_DAYS * 24 * 60 * 60);
 
-        let earliest_symptom_time_res = row.get(6);
-        // TODO does this work for Option?
-        let earliest_symptom_time: Option<i64> = expect_log!(
-            earliest_symptom_time_res,
-            "Invalid row: no earliest_symptom_time"
-        );
-        let earliest_symptom_time_unix_time: Option<UnixTime> =
-            earliest_symptom_time.map(|t| UnixTime { value: t as u64 });
+        self.db
+            .query_row(SELECT_ALERT_SUMMARY, params![recent_start as i64], |row| {
+                let total: i64 = row.get(0)?;
+                let unread: i64 = row.get(1)?;
+                let last_exposure_end: Option<i64> = row.get(2)?;
+                let exposures_last_14_days: i64 = row.get(3)?;
+                Ok(AlertSummary {
+                    total: total as u32,
+                    unread: unread as u32,
+                    last_exposure_end: last_exposure_end.map(|end| end as u64),
+                    exposures_last_14_days: exposures_last_14_days as u32,
+                })
+            })
+            .map_err(ServicesError::from)
+    }
+
+    fn exposure_minutes_at(
+        &self,
+        days: u32,
+        now: &UnixTime,
+    ) -> Result<ExposureMinutes, ServicesError> {
+        let window_start = to_db_i64(
+            now.value.saturating_sub(days as u64 * 24 * 60 * 60),
+            "Exposure minutes window start",
+        )?;
+
+        // Rows stored before the percentiles were recorded fall back to the min distance
+        let distance_column = if self.use_p10_distance {
+            "coalesce(e.p10_distance, e.min_distance, a.p10_distance, a.min_distance)"
+        } else {
+            "coalesce(e.min_distance, a.min_distance)"
+        };
+
+        // Windows clipped to the start of the days
+        let windows = self
+            .db
+            .query(
+                &format!(
+                    "select
+                    max(coalesce(e.start, a.start), ?1),
+                    coalesce(e.end, a.end),
+                    {}
+                    from alert a left join exposure e on e.alert_id = a.id
+                    where a.deleted is null and coalesce(e.end, a.end) >= ?1",
+                    distance_column
+                ),
+                params![window_start],
+                |row| {
+                    let start: i64 = column(row, 0, "start")?;
+                    let end: i64 = column(row, 1, "end")?;
+                    let distance: f64 = column(row, 2, "distance")?;
+                    Ok((start.max(0) as u64, end.max(0) as u64, distance))
+                },
+            )?
+            .rows;
+
+        Ok(ExposureMinutes {
+            close: bucket_minutes(&windows, |distance| distance < CLOSE_CONTACT_DISTANCE),
+            medium: bucket_minutes(&windows, |distance| {
+                distance >= CLOSE_CONTACT_DISTANCE && distance < MEDIUM_CONTACT_DISTANCE
+            }),
+            far: bucket_minutes(&windows, |distance| distance >= MEDIUM_CONTACT_DISTANCE),
+        })
+    }
+
+    // The apps may have persisted the ids of reports stored before migration 23: they're mapped to the
+    // current id, if known
+    fn current_report_id(&self, report_id: String) -> Result<String, ServicesError> {
+        self.db
+            .query_row(
+                "select coalesce(
+                    (select report_id from report_legacy_id where legacy_id=?1), ?1
+                )",
+                params![report_id],
+                |row| row.get(0),
+            )
+            .map_err(ServicesError::from)
+    }
 
-        let fever_severity_raw_res = row.get(7);
-        let fever_severity_raw: i8 =
-            expect_log!(fever_severity_raw_res, "Invalid row: no fever_severity");
-        let fever_severity_res = FeverSeverity::from(fever_severity_raw as u8);
-        let fever_severity = expect_log!(fever_severity_res, "Invalid raw value");
+    fn to_exposure(row: &Row) -> Result<ReportExposure, ServicesError> {
+        let alert_id = column(row, 0, "alert_id")?;
+        let report_id = column(row, 1, "report_id")?;
+        let start: i64 = column(row, 2, "start")?;
+        let end: i64 = column(row, 3, "end")?;
+        let min_distance: f64 = column(row, 4, "min_distance")?;
+        let avg_distance: f64 = column(row, 5, "avg_distance")?;
+        let total_count: i64 = column(row, 6, "total_count")?;
+        // None if stored before they were recorded
+        let p10_distance: Option<f64> = column(row, 7, "p10_distance")?;
+        let median_distance: Option<f64> = column(row, 8, "median_distance")?;
+        let tcn_prefixes: Option<String> = column(row, 9, "tcn_prefixes")?;
+
+        Ok(ReportExposure {
+            alert_id,
+            report_id,
+            contact_start: start as u64,
+            contact_end: end as u64,
+            min_distance: min_distance as f32,
+            avg_distance: avg_distance as f32,
+            p10_distance: p10_distance.unwrap_or(min_distance) as f32,
+            median_distance: median_distance.unwrap_or(avg_distance) as f32,
+            total_count: total_count as usize,
+            tcn_prefixes: from_tcn_prefixes_json(tcn_prefixes),
+        })
+    }
 
-        let cough_severity_raw_res = row.get(8);
-        let cough_severity_raw: i8 =
-            expect_log!(cough_severity_raw_res, "Invalid row: no cough_severity");
-        let cough_severity_res = CoughSeverity::from(cough_severity_raw as u8);
-        let cough_severity = expect_log!(cough_severity_res, "Invalid raw value");
+    fn to_processed_report(row: &Row) -> Result<ProcessedReport, ServicesError> {
+        let report_id = column(row, 0, "report_id")?;
+        let first_seen_interval: i64 = column(row, 1, "first_seen_interval")?;
+        let last_seen_interval: i64 = column(row, 2, "last_seen_interval")?;
+        let interval_length: i64 = column(row, 3, "interval_length")?;
+        let content_hash = column(row, 4, "content_hash")?;
 
-        let breathlessness_res = row.get(9);
-        let breathlessness: i8 = expect_log!(breathlessness_res, "Invalid row: no breathlessness");
+        Ok(ProcessedReport {
+            report_id,
+            first_seen_interval: first_seen_interval as u64,
+            last_seen_interval: last_seen_interval as u64,
+            interval_length: interval_length as u64,
+            content_hash,
+        })
+    }
 
-        let muscle_aches_res = row.get(10);
-        let muscle_aches: i8 = expect_log!(muscle_aches_res, "Invalid row: no muscle_aches");
+    fn to_report_interval_state(row: &Row) -> Result<ReportIntervalState, ServicesError> {
+        let interval_number: i64 = column(row, 0, "interval_number")?;
+        let interval_length: i64 = column(row, 1, "interval_length")?;
+        let payload_hash = column(row, 2, "payload_hash")?;
+        let inputs_hash = column(row, 3, "inputs_hash")?;
+
+        Ok(ReportIntervalState {
+            interval_number: interval_number as u64,
+            interval_length: interval_length as u64,
+            payload_hash,
+            inputs_hash,
+        })
+    }
 
-        let loss_smell_or_taste_res = row.get(11);
-        let loss_smell_or_taste: i8 = expect_log!(
-            loss_smell_or_taste_res,
-            "Invalid row: no loss_smell_or_taste"
-        );
+    // Rows can have values that save() doesn't write (older versions, manual edits, corruption).
+    // These are repaired instead of crashing: negative values are clamped to 0, a reversed contact
+    // window is swapped, unknown severities are mapped to None and oversized ids are truncated. Rows
+    // with missing values (e.g. of an alert without report row) or wrong types can't be repaired and are
+    // skipped.
+    fn to_alert(&self, row: &Row) -> Result<Alert, ServicesError> {
+        let mut normalized = false;
 
-        let diarrhea_res = row.get(12);
-        let diarrhea: i8 = expect_log!(diarrhea_res, "Invalid row: no diarrhea");
+        let id = bounded_id(column(row, 0, "id")?, "id", &mut normalized);
 
-        let runny_nose_res = row.get(13);
-        let runny_nose: i8 = expect_log!(runny_nose_res, "Invalid row: no runny_nose");
+        let start: i64 = column(row, 1, "start")?;
+        let start = non_negative_time(start, "start", &mut normalized);
 
-        let other_res = row.get(14);
-        let other: i8 = expect_log!(other_res, "Invalid row: no other");
+        let end: i64 = column(row, 2, "end")?;
+        let end = non_negative_time(end, "end", &mut normalized);
 
-        let no_symptoms_res = row.get(15);
-        let no_symptoms: i8 = expect_log!(no_symptoms_res, "Invalid row: no no_symptoms");
+        let (start, end) = if end < start {
+            warn!("Reversed contact window: {} - {}, swapping", start, end);
+            normalized = true;
+            (end, start)
+        } else {
+            (start, end)
+        };
 
-        let report_id_res = row.get(16);
-        let report_id = expect_log!(report_id_res, "Invalid row: no report_id");
+        let min_distance: f64 = column(row, 3, "min_distance")?;
+        let min_distance = non_negative_distance(min_distance, "min_distance", &mut normalized);
 
-        let read_res = row.get(17);
-        let read: i8 = expect_log!(read_res, "Invalid row: no read");
+        let avg_distance: f64 = column(row, 4, "avg_distance")?;
+        let avg_distance = non_negative_distance(avg_distance, "avg_distance", &mut normalized);
 
-        Alert {
+        let report_time: i64 = column(row, 5, "report_time")?;
+        let report_time = non_negative_time(report_time, "report_time", &mut normalized);
+
+        let earliest_symptom_time: Option<i64> = column(row, 6, "earliest_symptom_time")?;
+        let earliest_symptom_time_unix_time: Option<UnixTime> =
+            earliest_symptom_time.map(|t| UnixTime { value: t as u64 });
+
+        let fever_severity_raw: i64 = column(row, 7, "fever_severity")?;
+        let fever_severity = severity(
+            fever_severity_raw,
+            FeverSeverity::try_from,
+            FeverSeverity::None,
+            &mut normalized,
+        );
+
+        let cough_severity_raw: i64 = column(row, 8, "cough_severity")?;
+        let cough_severity = severity(
+            cough_severity_raw,
+            CoughSeverity::try_from,
+            CoughSeverity::None,
+            &mut normalized,
+        );
+
+        let breathlessness: i8 = column(row, 9, "breathlessness")?;
+        let muscle_aches: i8 = column(row, 10, "muscle_aches")?;
+        let loss_smell_or_taste: i8 = column(row, 11, "loss_smell_or_taste")?;
+        let diarrhea: i8 = column(row, 12, "diarrhea")?;
+        let runny_nose: i8 = column(row, 13, "runny_nose")?;
+        let other: i8 = column(row, 14, "other")?;
+        let no_symptoms: i8 = column(row, 15, "no_symptoms")?;
+        let report_id = bounded_id(column(row, 16, "report_id")?, "report_id", &mut normalized);
+        let read: i8 = column(row, 17, "read")?;
+        let memo_version: i64 = column(row, 18, "memo_version")?;
+        let has_raw_report: i8 = column(row, 19, "has_raw_report")?;
+        let report_coverage_start: Option<i64> = column(row, 20, "report_coverage_start")?;
+        let report_coverage_end: Option<i64> = column(row, 21, "report_coverage_end")?;
+        let core_version: Option<String> = column(row, 22, "core_version")?;
+        let app_version: Option<String> = column(row, 23, "app_version")?;
+        let snoozed_until: Option<i64> = column(row, 24, "snoozed_until")?;
+
+        // None if stored before they were recorded
+        let p10_distance: Option<f64> = column(row, 25, "p10_distance")?;
+        let p10_distance = p10_distance
+            .map(|distance| non_negative_distance(distance, "p10_distance", &mut normalized))
+            .unwrap_or(min_distance);
+        let median_distance: Option<f64> = column(row, 26, "median_distance")?;
+        let median_distance = median_distance
+            .map(|distance| non_negative_distance(distance, "median_distance", &mut normalized))
+            .unwrap_or(avg_distance);
+        let retracted: i8 = column(row, 27, "retracted")?;
+        let tcn_prefixes: Option<String> = column(row, 28, "tcn_prefixes")?;
+        let report_kind_raw: i64 = column(row, 29, "report_kind")?;
+        let report_kind = u8::try_from(report_kind_raw)
+            .map_err(|_| ServicesError::General(format!("Not supported: {}", report_kind_raw)))
+            .and_then(ReportKind::try_from)
+            .unwrap_or_else(|error| {
+                warn!("Invalid report kind: {:?}, using Unknown", error);
+                normalized = true;
+                ReportKind::Unknown
+            });
+
+        if normalized {
+            warn!("Normalized invalid values of alert: {}", id);
+            self.normalized_alerts.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(Alert {
             id,
             report_id,
             symptoms: PublicSymptoms {
-                report_time: UnixTime {
-                    value: report_time as u64,
-                },
+                report_time: UnixTime { value: report_time },
                 earliest_symptom_time: UserInput::from(earliest_symptom_time_unix_time),
                 fever_severity,
                 cough_severity,
@@ -148,46 +799,75 @@ impl AlertDaoImpl {
                 runny_nose: to_bool(runny_nose),
                 other: to_bool(other),
                 no_symptoms: to_bool(no_symptoms),
+                memo_version: memo_version as u16,
+                report_kind,
             },
-            contact_start: start as u64,
-            contact_end: end as u64,
-            min_distance: min_distance as f32,
-            avg_distance: avg_distance as f32,
+            contact_start: start,
+            contact_end: end,
+            min_distance,
+            avg_distance,
+            p10_distance,
+            median_distance,
             is_read: to_bool(read),
-        }
+            has_raw_report: to_bool(has_raw_report),
+            report_coverage_start: report_coverage_start.map(|start| start as u64),
+            report_coverage_end: report_coverage_end.map(|end| end as u64),
+            debug_info: AlertDebugInfo {
+                core_version,
+                app_version,
+                tcn_prefixes: from_tcn_prefixes_json(tcn_prefixes),
+            },
+            snoozed_until: snoozed_until.map(|until| until as u64),
+            retracted: to_bool(retracted),
+        })
     }
 }
 
 impl AlertDao for AlertDaoImpl {
     fn all(&self) -> Result<Vec<Alert>, ServicesError> {
+        self.all_at(&UnixTime::now())
+    }
+
+    fn all_including_snoozed(&self) -> Result<Vec<Alert>, ServicesError> {
         self.db
-            .query(
-                "select 
-                id,
-                start,
-                end,
-                min_distance ,
-                avg_distance,
-                report_time,
-                earliest_symptom_time,
-                fever_severity,
-                cough_severity,
-                breathlessness,
-                muscle_aches,
-                loss_smell_or_taste,
-                diarrhea,
-                runny_nose,
-                other,
-                no_symptoms,
-                report_id,
-                read
-                from alert where deleted is null",
-                NO_PARAMS,
-                |row| Self::to_alert(row),
-            )
+            .query(SELECT_ALERTS, NO_PARAMS, |row| self.to_alert(row))
+            .map(|result| result.rows)
             .map_err(ServicesError::from)
     }
 
+    fn alert(&self, id: &str) -> Result<Alert, ServicesError> {
+        let alerts = self.db.query(
+            &format!("{} and a.id = ?1", SELECT_ALERTS),
+            params![id],
+            |row| self.to_alert(row),
+        )?;
+        alerts
+            .rows
+            .into_iter()
+            .next()
+            .ok_or(ServicesError::NotFound)
+    }
+
+    fn all_grouped(&self) -> Result<Vec<ReportAlerts>, ServicesError> {
+        self.all().map(group_by_report)
+    }
+
+    fn alert_summary(&self) -> Result<AlertSummary, ServicesError> {
+        self.alert_summary_at(&UnixTime::now())
+    }
+
+    fn alerts_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: u32,
+    ) -> Result<AlertsPage, ServicesError> {
+        self.alerts_page_at(cursor, page_size, &UnixTime::now())
+    }
+
+    fn exposure_minutes(&self, days: u32) -> Result<ExposureMinutes, ServicesError> {
+        self.exposure_minutes_at(days, &UnixTime::now())
+    }
+
     fn delete(&self, id: String) -> Result<(), ServicesError> {
         debug!("Deleting alert with id: {}", id);
 
@@ -212,6 +892,64 @@ impl AlertDao for AlertDaoImpl {
         }
     }
 
+    fn delete_by_report(&self, report_id: String) -> Result<usize, ServicesError> {
+        let report_id = self.current_report_id(report_id)?;
+        debug!("Deleting alerts of report: {}", report_id);
+
+        let mut count = 0;
+        self.db.transaction(|t| {
+            count = t.execute(DELETE_REPORT_ALERTS, params![report_id])?;
+            t.execute(
+                "insert or ignore into dismissed_report(report_id) values(?1)",
+                params![report_id],
+            )?;
+            Ok(())
+        })?;
+        debug!("Deleted: {} alerts", count);
+        Ok(count)
+    }
+
+    fn is_report_dismissed(&self, report_id: &str) -> Result<bool, ServicesError> {
+        self.db
+            .query_row(
+                "select exists(select 1 from dismissed_report where report_id=?1)",
+                params![report_id],
+                |row| row.get(0),
+            )
+            .map_err(ServicesError::from)
+    }
+
+    fn retract_reports(
+        &self,
+        report_ids: &[String],
+        retracted_by: &str,
+        retracted_by_time: UnixTime,
+    ) -> Result<usize, ServicesError> {
+        let retracted_by_time = to_db_i64(retracted_by_time.value, "Retracting report time")?;
+        let mut count = 0;
+        self.db.transaction(|t| {
+            for report_id in report_ids {
+                count += t.execute(
+                    "insert or ignore into retracted_report(report_id, retracted_by)
+                    select report_id, ?2 from report where report_id=?1 and report_time<?3",
+                    params![report_id, retracted_by, retracted_by_time],
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    fn delete_ended_before(&self, contact_end: u64) -> Result<usize, ServicesError> {
+        let contact_end = to_db_i64(contact_end, "Alert contact end")?;
+        self.db
+            .execute_sql(
+                "update alert set deleted=1 where end<?1 and deleted is null",
+                params![contact_end],
+            )
+            .map_err(ServicesError::from)
+    }
+
     fn update_is_read(&self, id: String, is_read: bool) -> Result<(), ServicesError> {
         debug!("Marking alert as read with id: {}", id);
 
@@ -237,95 +975,477 @@ impl AlertDao for AlertDaoImpl {
         }
     }
 
-    fn save(&self, alerts: Vec<Alert>) -> Result<(), ServicesError> {
+    fn snooze(&self, id: String, until: UnixTime) -> Result<(), ServicesError> {
+        debug!("Snoozing alert with id: {} until: {}", id, until.value);
+
+        let count = self.db.execute_sql(
+            "update alert set snoozed_until=?1 where id=?2 and deleted is null",
+            params![to_db_i64(until.value, "Snoozed until")?, id],
+        )?;
+        if count > 0 {
+            Ok(())
+        } else {
+            error!("Didn't find alert to snooze: {}", id);
+            Err(ServicesError::NotFound)
+        }
+    }
+
+    fn set_acknowledgement_submitted(&self, id: &str, time: UnixTime) -> Result<(), ServicesError> {
+        let count = self.db.execute_sql(
+            "update alert set acknowledgement_submitted=?1 where id=?2 and deleted is null",
+            params![to_db_i64(time.value, "Acknowledgement submitted")?, id],
+        )?;
+        if count > 0 {
+            Ok(())
+        } else {
+            error!("Didn't find alert to record acknowledgement: {}", id);
+            Err(ServicesError::NotFound)
+        }
+    }
+
+    fn acknowledgement_submitted(&self, id: &str) -> Result<Option<u64>, ServicesError> {
+        let time: Option<i64> = self.db.query_row(
+            "select acknowledgement_submitted from alert where id=?1 and deleted is null",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(time.map(|time| time.max(0) as u64))
+    }
+
+    fn save(&self, alerts: Vec<Alert>) -> Result<SaveOutcome, ServicesError> {
+        self.save_at(alerts, &UnixTime::now())
+    }
+
+    fn update_measurements(&self, alerts: &[Alert]) -> Result<(), ServicesError> {
         self.db.transaction(|t| {
             for alert in alerts {
                 t.execute(
-                    "insert or ignore into alert(
-                        id,
-                        start,
-                        end,
-                        min_distance,
-                        avg_distance,
-                        report_time,
-                        earliest_symptom_time,
-                        fever_severity,
-                        cough_severity,
-                        breathlessness,
-                        muscle_aches,
-                        loss_smell_or_taste,
-                        diarrhea,
-                        runny_nose,
-                        other,
-                        no_symptoms,
-                        report_id,
-                        read
-                    ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                    "update alert set start=?1, end=?2, min_distance=?3, avg_distance=?4, p10_distance=?5,
+                    median_distance=?6 where id=?7",
                     params![
-                        alert.id,
-                        alert.contact_start as i64,
-                        alert.contact_end as i64,
+                        to_db_i64(alert.contact_start, "Alert contact start")?,
+                        to_db_i64(alert.contact_end, "Alert contact end")?,
                         alert.min_distance as f64,
                         alert.avg_distance as f64,
-                        alert.symptoms.report_time.value as i64,
-                        alert
-                            .symptoms
-                            .earliest_symptom_time
-                            .as_opt()
-                            .map(|unix_time| unix_time.value as i64),
-                        alert.symptoms.fever_severity.raw_value() as i64,
-                        alert.symptoms.cough_severity.raw_value() as i64,
-                        to_db_int(alert.symptoms.breathlessness),
-                        to_db_int(alert.symptoms.muscle_aches),
-                        to_db_int(alert.symptoms.loss_smell_or_taste),
-                        to_db_int(alert.symptoms.diarrhea),
-                        to_db_int(alert.symptoms.runny_nose),
-                        to_db_int(alert.symptoms.other),
-                        to_db_int(alert.symptoms.no_symptoms),
-                        alert.report_id,
-                        to_db_int(alert.is_read)
+                        alert.p10_distance as f64,
+                        alert.median_distance as f64,
+                        alert.id
                     ],
                 )?;
             }
             Ok(())
         })
     }
-}
 
-fn to_bool(db_int: i8) -> bool {
-    if db_int == 1 {
-        true
-    } else if db_int == 0 {
-        false
-    } else {
-        error!("Invalid db_int: {}", db_int);
-        panic!()
+    fn update_report_symptoms(
+        &self,
+        reports: &[(String, PublicSymptoms)],
+        time: &UnixTime,
+    ) -> Result<Vec<String>, ServicesError> {
+        let time = to_db_i64(time.value, "Symptoms updated at")?;
+        let mut updated = vec![];
+        self.db.transaction(|t| {
+            for (report_id, symptoms) in reports {
+                let earliest_symptom_time = match symptoms.earliest_symptom_time.as_opt() {
+                    Some(unix_time) => Some(to_db_i64(unix_time.value, "Earliest symptom time")?),
+                    None => None,
+                };
+                let changed = t.execute(
+                    UPDATE_REPORT_SYMPTOMS,
+                    params![
+                        report_id,
+                        to_db_i64(symptoms.report_time.value, "Report time")?,
+                        earliest_symptom_time,
+                        symptoms.fever_severity.as_u8() as i64,
+                        symptoms.cough_severity.as_u8() as i64,
+                        to_db_int(symptoms.breathlessness),
+                        to_db_int(symptoms.muscle_aches),
+                        to_db_int(symptoms.loss_smell_or_taste),
+                        to_db_int(symptoms.diarrhea),
+                        to_db_int(symptoms.runny_nose),
+                        to_db_int(symptoms.other),
+                        to_db_int(symptoms.no_symptoms),
+                        symptoms.memo_version as i64,
+                        symptoms.report_kind.as_u8() as i64,
+                        time
+                    ],
+                )?;
+                if changed > 0 {
+                    updated.push(report_id.clone());
+                }
+            }
+            Ok(())
+        })?;
+        Ok(updated)
     }
-}
 
-fn to_db_int(b: bool) -> i8 {
-    if b {
-        1
-    } else {
-        0
+    fn save_raw_report(&self, report_id: String, report: String) -> Result<(), ServicesError> {
+        self.db
+            .execute_sql(
+                "insert or ignore into report_blob(report_id, report) values(?1, ?2)",
+                params![report_id, report],
+            )
+            .map(|_| ())
+            .map_err(ServicesError::from)
     }
-}
 
-// fn to_db_user_input(input: UserInput<T>) {
+    fn raw_report(&self, report_id: String) -> Result<String, ServicesError> {
+        let report_id = self.current_report_id(report_id)?;
+        self.db
+            .query_row(
+                "select report from report_blob where report_id=?",
+                params![report_id],
+                |row| row.get(0),
+            )
+            .map_err(ServicesError::from)
+    }
 
-// }
+    fn exposures(&self, report_id: &str) -> Result<Vec<ReportExposure>, ServicesError> {
+        self.db
+            .query(
+                "select alert_id, report_id, start, end, min_distance, avg_distance, total_count,
+                p10_distance, median_distance, tcn_prefixes
+                from exposure where report_id=?1 order by start",
+                params![report_id],
+                Self::to_exposure,
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rusqlite::Connection;
+    fn save_exposures(&self, exposures: Vec<ReportExposure>) -> Result<(), ServicesError> {
+        self.db.transaction(|t| {
+            for exposure in exposures {
+                t.execute(
+                    "insert or replace into exposure(
+                        alert_id, report_id, start, end, min_distance, avg_distance, total_count,
+                        p10_distance, median_distance, tcn_prefixes
+                    ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        exposure.alert_id,
+                        exposure.report_id,
+                        to_db_i64(exposure.contact_start, "Exposure contact start")?,
+                        to_db_i64(exposure.contact_end, "Exposure contact end")?,
+                        exposure.min_distance as f64,
+                        exposure.avg_distance as f64,
+                        to_db_i64(exposure.total_count, "Exposure total count")?,
+                        exposure.p10_distance as f64,
+                        exposure.median_distance as f64,
+                        to_tcn_prefixes_json(&exposure.tcn_prefixes)?
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
 
-    #[test]
-    fn test_saves_and_loads_alert() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
-        let alert_dao = AlertDaoImpl::new(database);
+    fn processed_report(&self, report_id: &str) -> Result<Option<ProcessedReport>, ServicesError> {
+        self.db
+            .query(
+                "select report_id, first_seen_interval, last_seen_interval, interval_length, content_hash
+                from processed_report where report_id=?1",
+                params![report_id],
+                Self::to_processed_report,
+            )
+            .map(|result| result.rows.into_iter().next())
+            .map_err(ServicesError::from)
+    }
+
+    fn save_processed_reports(&self, reports: Vec<ProcessedReport>) -> Result<(), ServicesError> {
+        self.db.transaction(|t| {
+            for report in reports {
+                // The update expressions read the stored values
+                t.execute(
+                    "insert into processed_report(
+                        report_id, first_seen_interval, last_seen_interval, interval_length, content_hash
+                    ) values(?1, ?2, ?3, ?4, ?5)
+                    on conflict(report_id) do update set
+                        first_seen_interval=min(first_seen_interval, excluded.first_seen_interval),
+                        last_seen_interval=max(last_seen_interval, excluded.last_seen_interval),
+                        interval_length=case when excluded.last_seen_interval >= last_seen_interval
+                            then excluded.interval_length else interval_length end,
+                        content_hash=case when excluded.last_seen_interval >= last_seen_interval
+                            then excluded.content_hash else content_hash end",
+                    params![
+                        report.report_id,
+                        to_db_i64(report.first_seen_interval, "First seen interval")?,
+                        to_db_i64(report.last_seen_interval, "Last seen interval")?,
+                        to_db_i64(report.interval_length, "Interval length")?,
+                        report.content_hash
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    fn expire_unseen_reports(
+        &self,
+        last_seen_end: u64,
+        contact_end: u64,
+    ) -> Result<usize, ServicesError> {
+        let last_seen_end = to_db_i64(last_seen_end, "Last seen end")?;
+        let contact_end = to_db_i64(contact_end, "Alert contact end")?;
+        self.db
+            .execute_sql(
+                "update alert set deleted=1 where end<?2 and deleted is null and report_id in (
+                    select report_id from processed_report
+                    where (last_seen_interval + 1) * interval_length < ?1
+                )",
+                params![last_seen_end, contact_end],
+            )
+            .map_err(ServicesError::from)
+    }
+
+    fn delete_processed_reports_seen_before(
+        &self,
+        last_seen_end: u64,
+    ) -> Result<usize, ServicesError> {
+        let last_seen_end = to_db_i64(last_seen_end, "Last seen end")?;
+        self.db
+            .execute_sql(
+                "delete from processed_report where (last_seen_interval + 1) * interval_length < ?1",
+                params![last_seen_end],
+            )
+            .map_err(ServicesError::from)
+    }
+
+    fn report_interval_state(
+        &self,
+        interval_number: u64,
+        interval_length: u64,
+    ) -> Result<Option<ReportIntervalState>, ServicesError> {
+        let interval_number = to_db_i64(interval_number, "Interval number")?;
+        let interval_length = to_db_i64(interval_length, "Interval length")?;
+        self.db
+            .query(
+                "select interval_number, interval_length, payload_hash, inputs_hash
+                from report_interval_state where interval_number=?1 and interval_length=?2",
+                params![interval_number, interval_length],
+                Self::to_report_interval_state,
+            )
+            .map(|result| result.rows.into_iter().next())
+            .map_err(ServicesError::from)
+    }
+
+    fn save_report_interval_states(
+        &self,
+        states: Vec<ReportIntervalState>,
+    ) -> Result<(), ServicesError> {
+        self.db.transaction(|t| {
+            for state in states {
+                t.execute(
+                    "insert or replace into report_interval_state(
+                        interval_number, interval_length, payload_hash, inputs_hash
+                    ) values(?1, ?2, ?3, ?4)",
+                    params![
+                        to_db_i64(state.interval_number, "Interval number")?,
+                        to_db_i64(state.interval_length, "Interval length")?,
+                        state.payload_hash,
+                        state.inputs_hash
+                    ],
+                )?;
+            }
+            // Keep only the latest intervals (by start)
+            t.execute(
+                "delete from report_interval_state where rowid not in
+                (select rowid from report_interval_state
+                order by interval_number * interval_length desc limit ?1)",
+                params![REPORT_INTERVAL_STATES_SIZE],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn db_stats(&self) -> DbStats {
+        let pragma = |name: &str| -> u64 {
+            match self.db.core_pragma_query::<i64>(name) {
+                Ok(value) => value.max(0) as u64,
+                Err(error) => {
+                    warn!("Couldn't read db stats: {:?}", error);
+                    0
+                }
+            }
+        };
+        let deleted_alerts_res = self.db.query_row(
+            "select count(*) from alert where deleted = 1",
+            NO_PARAMS,
+            |row| row.get::<_, i64>(0),
+        );
+        let deleted_alerts = match deleted_alerts_res {
+            Ok(count) => count as u64,
+            Err(error) => {
+                warn!("Couldn't count deleted alerts: {:?}", error);
+                0
+            }
+        };
+        DbStats {
+            normalized_alerts: self.normalized_alerts.load(Ordering::Relaxed),
+            page_size: pragma("page_size"),
+            page_count: pragma("page_count"),
+            free_pages: pragma("freelist_count"),
+            deleted_alerts,
+            slow_queries: self.db.slow_queries(),
+        }
+    }
+}
+
+// Minutes covered by the windows (start, end, distance) whose distance is in the bucket
+fn bucket_minutes(windows: &[(u64, u64, f64)], in_bucket: impl Fn(f64) -> bool) -> u64 {
+    let bucket_windows: Vec<(u64, u64)> = windows
+        .iter()
+        .filter(|(_, _, distance)| in_bucket(*distance))
+        .map(|(start, end, _)| (*start, *end))
+        .collect();
+    union_duration(&bucket_windows) / 60
+}
+
+// Err if longer than MAX_ID_LENGTH
+fn check_id_length(id: &str, field: &str) -> Result<(), ServicesError> {
+    let length = id.chars().count();
+    if length > MAX_ID_LENGTH {
+        return Err(ServicesError::InvalidInput(format!(
+            "{} has {} characters, max: {}",
+            field, length, MAX_ID_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn bounded_id(id: String, field: &str, normalized: &mut bool) -> String {
+    let length = id.chars().count();
+    if length > MAX_ID_LENGTH {
+        warn!("Oversized {}: {} characters, truncating", field, length);
+        *normalized = true;
+        id.chars().take(MAX_ID_LENGTH).collect()
+    } else {
+        id
+    }
+}
+
+fn non_negative_time(value: i64, field: &str, normalized: &mut bool) -> u64 {
+    if value < 0 {
+        warn!("Negative {}: {}, clamping to 0", field, value);
+        *normalized = true;
+        0
+    } else {
+        value as u64
+    }
+}
+
+fn non_negative_distance(value: f64, field: &str, normalized: &mut bool) -> f32 {
+    if value.is_nan() || value < 0.0 {
+        warn!("Invalid {}: {}, clamping to 0", field, value);
+        *normalized = true;
+        0.0
+    } else {
+        value as f32
+    }
+}
+
+fn severity<T>(
+    raw_value: i64,
+    from_raw: fn(u8) -> Result<T, ServicesError>,
+    none: T,
+    normalized: &mut bool,
+) -> T {
+    let severity_res = u8::try_from(raw_value)
+        .map_err(|_| ServicesError::General(format!("Not supported: {}", raw_value)))
+        .and_then(from_raw);
+    severity_res.unwrap_or_else(|error| {
+        warn!("Invalid severity: {:?}, using None", error);
+        *normalized = true;
+        none
+    })
+}
+
+fn group_by_report(alerts: Vec<Alert>) -> Vec<ReportAlerts> {
+    let mut groups: Vec<ReportAlerts> = vec![];
+    let mut group_indices: HashMap<String, usize> = HashMap::new();
+
+    for alert in alerts {
+        match group_indices.get(&alert.report_id) {
+            Some(index) => groups[*index].alerts.push(alert),
+            None => {
+                group_indices.insert(alert.report_id.clone(), groups.len());
+                groups.push(ReportAlerts {
+                    report_id: alert.report_id.clone(),
+                    report: alert.symptoms.clone(),
+                    alerts: vec![alert],
+                });
+            }
+        }
+    }
+
+    for group in &mut groups {
+        group
+            .alerts
+            .sort_by(|a1, a2| a2.contact_start.cmp(&a1.contact_start));
+    }
+    // Groups aren't empty and alerts are sorted, so the first alert is the most recent exposure.
+    groups.sort_by(|g1, g2| g2.alerts[0].contact_start.cmp(&g1.alerts[0].contact_start));
+
+    groups
+}
+
+fn to_bool(db_int: i8) -> bool {
+    if db_int == 1 {
+        true
+    } else if db_int == 0 {
+        false
+    } else {
+        error!("Invalid db_int: {}", db_int);
+        panic!()
+    }
+}
+
+fn to_db_int(b: bool) -> i8 {
+    if b {
+        1
+    } else {
+        0
+    }
+}
+
+// JSON array, null if there are none (not recorded)
+fn to_tcn_prefixes_json(prefixes: &[String]) -> Result<Option<String>, ServicesError> {
+    if prefixes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(prefixes)?))
+}
+
+fn from_tcn_prefixes_json(json: Option<String>) -> Vec<String> {
+    match json.map(|json| serde_json::from_str(&json)) {
+        Some(Ok(prefixes)) => prefixes,
+        Some(Err(error)) => {
+            // Debugging only: not worth failing the alert
+            warn!("Invalid TCN prefixes: {:?}", error);
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
+// fn to_db_user_input(input: UserInput<T>) {
+
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::database::QueryRows,
+        expect_log,
+        reporting::memo::{MemoMapper, MemoMapperImpl},
+        test_support::{migrated_database, set_storage_full},
+    };
+    use rusqlite::ToSql;
+
+    #[test]
+    fn test_saves_and_loads_alert() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
             report_time: UnixTime { value: 0 },
@@ -339,6 +1459,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert = Alert {
@@ -349,7 +1471,19 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.8,
+            median_distance: 3.9,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: Some(500),
+            report_coverage_end: Some(1500),
+            debug_info: AlertDebugInfo {
+                core_version: Some("0.1.0".to_owned()),
+                app_version: Some("1.2.0 (45)".to_owned()),
+                tcn_prefixes: vec![],
+            },
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert.clone()]);
@@ -364,11 +1498,81 @@ mod tests {
         assert_eq!(loaded_alerts[0], alert);
     }
 
+    // Memos, the database and the FFI integers share the numbering (see CoughSeverity::as_u8)
+    #[test]
+    fn test_severities_round_trip_through_memo_db_and_ffi() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+        let memo_mapper = MemoMapperImpl {};
+        let fevers = vec![
+            FeverSeverity::None,
+            FeverSeverity::Mild,
+            FeverSeverity::Serious,
+        ];
+        let coughs = vec![
+            CoughSeverity::None,
+            CoughSeverity::Existing,
+            CoughSeverity::Dry,
+            CoughSeverity::Wet,
+        ];
+
+        for (index, cough) in coughs.iter().enumerate() {
+            let fever = &fevers[index % fevers.len()];
+            let symptoms = PublicSymptoms {
+                report_time: UnixTime { value: 0 },
+                earliest_symptom_time: UserInput::None,
+                fever_severity: fever.clone(),
+                cough_severity: cough.clone(),
+                breathlessness: false,
+                muscle_aches: false,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: false,
+                other: false,
+                no_symptoms: false,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            };
+            let id = index.to_string();
+            let alert = Alert {
+                id: id.clone(),
+                report_id: id.clone(),
+                symptoms: memo_mapper.to_report(memo_mapper.to_memo(symptoms)),
+                contact_start: 1000,
+                contact_end: 2000,
+                min_distance: 2.3,
+                avg_distance: 4.3,
+                p10_distance: 2.3,
+                median_distance: 4.3,
+                is_read: false,
+                has_raw_report: false,
+                report_coverage_start: None,
+                report_coverage_end: None,
+                debug_info: AlertDebugInfo::default(),
+                snoozed_until: None,
+                retracted: false,
+            };
+            assert!(alert_dao.save(vec![alert]).is_ok());
+
+            let loaded = alert_dao.all().unwrap();
+            let loaded = loaded.iter().find(|alert| alert.id == id).unwrap();
+            assert_eq!(fever, &loaded.symptoms.fever_severity);
+            assert_eq!(cough, &loaded.symptoms.cough_severity);
+
+            // JNI: passed as jint
+            let fever_int = loaded.symptoms.fever_severity.as_u8() as i32;
+            let cough_int = loaded.symptoms.cough_severity.as_u8() as i32;
+            assert_eq!(fever, &FeverSeverity::try_from(fever_int as u8).unwrap());
+            assert_eq!(cough, &CoughSeverity::try_from(cough_int as u8).unwrap());
+        }
+        assert_eq!(2, CoughSeverity::Dry.as_u8());
+        assert_eq!(3, CoughSeverity::Wet.as_u8());
+        assert!(FeverSeverity::try_from(3).is_err());
+        assert!(CoughSeverity::try_from(4).is_err());
+    }
+
     #[test]
     fn test_new_alert_with_same_id_ignored() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -383,6 +1587,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert1 = Alert {
@@ -393,7 +1599,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let alert2 = Alert {
@@ -404,7 +1618,15 @@ mod tests {
             contact_end: 2001,
             min_distance: 2.4,
             avg_distance: 4.4,
+            p10_distance: 2.4,
+            median_distance: 4.4,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert1.clone(), alert2.clone()]);
@@ -421,9 +1643,7 @@ mod tests {
 
     #[test]
     fn test_saves_and_loads_multiple_alerts() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -438,6 +1658,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert1 = Alert {
@@ -448,7 +1670,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let alert2 = Alert {
@@ -459,7 +1689,15 @@ mod tests {
             contact_end: 2001,
             min_distance: 2.4,
             avg_distance: 4.4,
+            p10_distance: 2.4,
+            median_distance: 4.4,
             is_read: true,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert1.clone(), alert2.clone()]);
@@ -477,9 +1715,7 @@ mod tests {
 
     #[test]
     fn test_deletes_alert() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -494,6 +1730,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert1 = Alert {
@@ -504,7 +1742,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let alert2 = Alert {
@@ -515,7 +1761,15 @@ mod tests {
             contact_end: 2001,
             min_distance: 2.4,
             avg_distance: 4.4,
+            p10_distance: 2.4,
+            median_distance: 4.4,
             is_read: true,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert1.clone(), alert2.clone()]);
@@ -535,9 +1789,7 @@ mod tests {
 
     #[test]
     fn test_alert_not_restored_after_delete() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -552,6 +1804,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert1 = Alert {
@@ -562,7 +1816,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let alert2 = Alert {
@@ -573,7 +1835,15 @@ mod tests {
             contact_end: 2001,
             min_distance: 2.4,
             avg_distance: 4.4,
+            p10_distance: 2.4,
+            median_distance: 4.4,
             is_read: true,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert1.clone(), alert2.clone()]);
@@ -596,9 +1866,7 @@ mod tests {
 
     #[test]
     fn test_marks_alert_as_read() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -613,6 +1881,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert = Alert {
@@ -623,7 +1893,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert.clone()]);
@@ -649,9 +1927,7 @@ mod tests {
 
     #[test]
     fn test_marks_alert_as_unread() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -666,6 +1942,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert = Alert {
@@ -676,7 +1954,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: true,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert.clone()]);
@@ -702,9 +1988,7 @@ mod tests {
 
     #[test]
     fn test_marks_alert_as_read_if_already_read_does_nothing() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let alert_dao = AlertDaoImpl::new(database);
 
         let symptoms = PublicSymptoms {
@@ -719,6 +2003,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let alert1 = Alert {
@@ -729,7 +2015,15 @@ mod tests {
             contact_end: 2000,
             min_distance: 2.3,
             avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
             is_read: true,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
         };
 
         let save_res = alert_dao.save(vec![alert1.clone()]);
@@ -752,4 +2046,1503 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_saves_and_loads_memo_version() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert = Alert {
+            symptoms: PublicSymptoms {
+                memo_version: 2,
+                ..test_alert().symptoms
+            },
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+
+        let loaded_alerts = loaded_alerts_res.unwrap();
+
+        assert_eq!(loaded_alerts.len(), 1);
+        assert_eq!(loaded_alerts[0].symptoms.memo_version, 2);
+    }
+
+    #[test]
+    fn test_saves_and_loads_report_kind() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let alert = Alert {
+            symptoms: PublicSymptoms {
+                report_kind: ReportKind::TestResult,
+                ..test_alert().symptoms
+            },
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        // Stored without kind (default) and with a kind unknown to this version
+        let insert_res = database.execute_batch(
+            "insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms)
+            values('2', 0, NULL, 0, 0, 1, 0, 0, 0, 0, 0, 0);
+            insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms, report_kind)
+            values('3', 0, NULL, 0, 0, 1, 0, 0, 0, 0, 0, 0, 7);
+            insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('2', 1000, 2000, 1.5, 2.0, '2', 0);
+            insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('3', 1000, 2000, 1.5, 2.0, '3', 0);",
+        );
+        assert!(insert_res.is_ok());
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        let mut loaded_alerts = loaded_alerts_res.unwrap();
+        loaded_alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+
+        assert_eq!(
+            vec![
+                ReportKind::TestResult,
+                ReportKind::Symptoms,
+                ReportKind::Unknown
+            ],
+            loaded_alerts
+                .iter()
+                .map(|alert| alert.symptoms.report_kind)
+                .collect::<Vec<ReportKind>>()
+        );
+        assert_eq!(1, alert_dao.db_stats().normalized_alerts);
+    }
+
+    #[test]
+    fn test_updates_changed_report_symptoms() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let alert = test_alert();
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let symptoms_updated_at = || {
+            database
+                .query_row(
+                    "select symptoms_updated_at from report where report_id='1'",
+                    NO_PARAMS,
+                    |row| row.get::<_, Option<i64>>(0),
+                )
+                .unwrap()
+        };
+
+        // Same symptoms: nothing to update
+        let unchanged_res = alert_dao.update_report_symptoms(
+            &[(alert.report_id.clone(), alert.symptoms.clone())],
+            &UnixTime { value: 1590528000 },
+        );
+        assert!(unchanged_res.is_ok());
+        assert!(unchanged_res.unwrap().is_empty());
+        assert_eq!(None, symptoms_updated_at());
+
+        let changed_symptoms = PublicSymptoms {
+            report_time: UnixTime { value: 1590528000 },
+            diarrhea: true,
+            no_symptoms: false,
+            ..alert.symptoms.clone()
+        };
+        let changed_res = alert_dao.update_report_symptoms(
+            &[
+                (alert.report_id.clone(), changed_symptoms.clone()),
+                // Unknown reports are ignored
+                ("2".to_owned(), changed_symptoms.clone()),
+            ],
+            &UnixTime { value: 1590529000 },
+        );
+        assert!(changed_res.is_ok());
+        assert_eq!(vec!["1".to_owned()], changed_res.unwrap());
+        assert_eq!(Some(1590529000), symptoms_updated_at());
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        assert_eq!(
+            vec![Alert {
+                symptoms: changed_symptoms,
+                ..alert
+            }],
+            loaded_alerts_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_saves_and_loads_raw_report() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert = test_alert();
+
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let save_raw_res =
+            alert_dao.save_raw_report(alert.report_id.clone(), "cmVwb3J0".to_owned());
+        assert!(save_raw_res.is_ok());
+
+        let raw_report_res = alert_dao.raw_report(alert.report_id.clone());
+        assert!(raw_report_res.is_ok());
+        assert_eq!(raw_report_res.unwrap(), "cmVwb3J0");
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+
+        let loaded_alerts = loaded_alerts_res.unwrap();
+
+        assert_eq!(loaded_alerts.len(), 1);
+        assert_eq!(
+            loaded_alerts[0],
+            Alert {
+                has_raw_report: true,
+                ..alert
+            }
+        );
+    }
+
+    #[test]
+    fn test_raw_report_not_stored_is_not_found() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+
+        let raw_report_res = alert_dao.raw_report("1".to_owned());
+        assert!(matches!(raw_report_res, Err(ServicesError::NotFound)));
+    }
+
+    #[test]
+    fn test_loads_alerts_grouped_by_report() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert1 = Alert {
+            id: "1".to_owned(),
+            report_id: "report1".to_owned(),
+            contact_start: 1000,
+            contact_end: 2000,
+            ..test_alert()
+        };
+        let alert2 = Alert {
+            id: "2".to_owned(),
+            report_id: "report2".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..test_alert()
+        };
+        let alert3 = Alert {
+            id: "3".to_owned(),
+            report_id: "report1".to_owned(),
+            contact_start: 5000,
+            contact_end: 6000,
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![alert1.clone(), alert2.clone(), alert3.clone()]);
+        assert!(save_res.is_ok());
+
+        let grouped_res = alert_dao.all_grouped();
+        assert!(grouped_res.is_ok());
+
+        let grouped = grouped_res.unwrap();
+
+        assert_eq!(
+            grouped,
+            vec![
+                ReportAlerts {
+                    report_id: "report1".to_owned(),
+                    report: test_alert().symptoms,
+                    alerts: vec![alert3, alert1],
+                },
+                ReportAlerts {
+                    report_id: "report2".to_owned(),
+                    report: test_alert().symptoms,
+                    alerts: vec![alert2],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alert_summary_empty() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let summary_res = alert_dao.alert_summary();
+        assert!(summary_res.is_ok());
+        assert_eq!(AlertSummary::default(), summary_res.unwrap());
+    }
+
+    #[test]
+    fn test_alert_summary_only_deleted() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+        let delete_res = alert_dao.delete("1".to_owned());
+        assert!(delete_res.is_ok());
+
+        let summary_res = alert_dao.alert_summary();
+        assert!(summary_res.is_ok());
+        assert_eq!(AlertSummary::default(), summary_res.unwrap());
+        assert_eq!(1, alert_dao.db_stats().deleted_alerts);
+    }
+
+    #[test]
+    fn test_alert_summary_mixed() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let now = UnixTime { value: 1590356601 };
+        let day = 24 * 60 * 60;
+
+        let recent_unread = Alert {
+            id: "1".to_owned(),
+            contact_start: now.value - 2 * day,
+            contact_end: now.value - 2 * day + 60,
+            ..test_alert()
+        };
+        let old_read = Alert {
+            id: "2".to_owned(),
+            contact_start: now.value - 20 * day,
+            contact_end: now.value - 20 * day + 60,
+            is_read: true,
+            ..test_alert()
+        };
+        let deleted = Alert {
+            id: "3".to_owned(),
+            contact_start: now.value - 60,
+            contact_end: now.value,
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![recent_unread.clone(), old_read, deleted]);
+        assert!(save_res.is_ok());
+        let delete_res = alert_dao.delete("3".to_owned());
+        assert!(delete_res.is_ok());
+
+        let summary_res = alert_dao.alert_summary_at(&now);
+        assert!(summary_res.is_ok());
+        assert_eq!(
+            AlertSummary {
+                total: 2,
+                unread: 1,
+                last_exposure_end: Some(recent_unread.contact_end),
+                exposures_last_14_days: 1,
+            },
+            summary_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_alert_queries_use_indexes() {
+        let database = migrated_database();
+
+        let summary_plan = query_plan(&database, SELECT_ALERT_SUMMARY, params![0]);
+        assert!(
+            summary_plan.contains("USING INDEX alert_not_deleted"),
+            "Summary plan: {}",
+            summary_plan
+        );
+        let delete_plan = query_plan(&database, DELETE_REPORT_ALERTS, params!["1"]);
+        assert!(
+            delete_plan.contains("USING INDEX alert_report_id"),
+            "Delete by report plan: {}",
+            delete_plan
+        );
+    }
+
+    // Details of the steps of the query's plan, one per line
+    fn query_plan(database: &Database, sql: &str, params: &[&dyn ToSql]) -> String {
+        let plan_res = database.query(&format!("explain query plan {}", sql), params, |row| {
+            column(row, 3, "detail")
+        });
+        let plan: QueryRows<String> = expect_log!(plan_res, "Couldn't explain query");
+        plan.rows.join("\n")
+    }
+
+    #[test]
+    fn test_exposure_minutes_empty() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let minutes_res = alert_dao.exposure_minutes(14);
+        assert!(minutes_res.is_ok());
+        assert_eq!(ExposureMinutes::default(), minutes_res.unwrap());
+    }
+
+    #[test]
+    fn test_exposure_minutes_by_distance() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let now = UnixTime { value: 1590356601 };
+        let day = 24 * 60 * 60;
+
+        let close = Alert {
+            id: "1".to_owned(),
+            contact_start: now.value - 2 * day,
+            contact_end: now.value - 2 * day + 30 * 60,
+            min_distance: 1.0,
+            ..test_alert()
+        };
+        let medium = Alert {
+            id: "2".to_owned(),
+            contact_start: now.value - day,
+            contact_end: now.value - day + 10 * 60,
+            min_distance: 3.0,
+            ..test_alert()
+        };
+        // Starts before the window: only the last 20 minutes count
+        let far = Alert {
+            id: "3".to_owned(),
+            contact_start: now.value - 7 * day - 10 * 60,
+            contact_end: now.value - 7 * day + 20 * 60,
+            min_distance: 6.0,
+            ..test_alert()
+        };
+        let outside_window = Alert {
+            id: "4".to_owned(),
+            contact_start: now.value - 10 * day,
+            contact_end: now.value - 10 * day + 60 * 60,
+            min_distance: 1.0,
+            ..test_alert()
+        };
+        let deleted = Alert {
+            id: "5".to_owned(),
+            contact_start: now.value - 60 * 60,
+            contact_end: now.value,
+            min_distance: 1.0,
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![close, medium.clone(), far, outside_window, deleted]);
+        assert!(save_res.is_ok());
+        let delete_res = alert_dao.delete("5".to_owned());
+        assert!(delete_res.is_ok());
+
+        // The persisted exposure window of the medium alert is used
+        let save_res = alert_dao.save_exposures(vec![ReportExposure {
+            alert_id: medium.id.clone(),
+            report_id: medium.report_id.clone(),
+            contact_start: medium.contact_start,
+            contact_end: medium.contact_start + 25 * 60,
+            min_distance: 3.5,
+            avg_distance: 4.0,
+            p10_distance: 3.7,
+            median_distance: 4.0,
+            total_count: 5,
+            tcn_prefixes: vec![],
+        }]);
+        assert!(save_res.is_ok());
+
+        let minutes_res = alert_dao.exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(
+            ExposureMinutes {
+                close: 30,
+                medium: 25,
+                far: 20,
+            },
+            minutes_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exposure_minutes_by_p10_distance() {
+        let database = migrated_database();
+        let now = UnixTime { value: 1590356601 };
+        let day = 24 * 60 * 60;
+
+        // A single noisy close sample: close by min distance, medium by 10th percentile distance
+        let noisy = Alert {
+            id: "1".to_owned(),
+            contact_start: now.value - day,
+            contact_end: now.value - day + 30 * 60,
+            min_distance: 0.5,
+            p10_distance: 2.5,
+            ..test_alert()
+        };
+        let save_res = AlertDaoImpl::new(database.clone()).save(vec![noisy.clone()]);
+        assert!(save_res.is_ok());
+
+        let minutes_res = AlertDaoImpl::new(database.clone()).exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(30, minutes_res.unwrap().close);
+
+        let alert_dao = AlertDaoImpl::new(database.clone()).use_p10_distance(true);
+        let minutes_res = alert_dao.exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(
+            ExposureMinutes {
+                close: 0,
+                medium: 30,
+                far: 0,
+            },
+            minutes_res.unwrap()
+        );
+
+        // The persisted exposure's percentile is used. Rows stored before it was recorded fall back
+        // to the min distance.
+        let save_res = alert_dao.save_exposures(vec![ReportExposure {
+            alert_id: noisy.id.clone(),
+            report_id: noisy.report_id.clone(),
+            contact_start: noisy.contact_start,
+            contact_end: noisy.contact_end,
+            min_distance: 0.5,
+            avg_distance: 5.0,
+            p10_distance: 4.5,
+            median_distance: 5.0,
+            total_count: 20,
+            tcn_prefixes: vec![],
+        }]);
+        assert!(save_res.is_ok());
+        let minutes_res = alert_dao.exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(30, minutes_res.unwrap().far);
+
+        let update_res = database.execute_sql("update exposure set p10_distance = null", params![]);
+        assert!(update_res.is_ok());
+        let minutes_res = alert_dao.exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(30, minutes_res.unwrap().close);
+    }
+
+    #[test]
+    fn test_exposure_minutes_count_overlapping_exposures_once() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let now = UnixTime { value: 1590356601 };
+        let start = now.value - 60 * 60;
+        // Two people nearby at the same time
+        let alert1 = Alert {
+            id: "1".to_owned(),
+            contact_start: start,
+            contact_end: start + 20 * 60,
+            min_distance: 1.0,
+            ..test_alert()
+        };
+        let alert2 = Alert {
+            id: "2".to_owned(),
+            report_id: "2".to_owned(),
+            contact_start: start + 10 * 60,
+            contact_end: start + 30 * 60,
+            min_distance: 1.5,
+            ..test_alert()
+        };
+        // Same time, other bucket: counted there
+        let far = Alert {
+            id: "3".to_owned(),
+            report_id: "3".to_owned(),
+            contact_start: start,
+            contact_end: start + 5 * 60,
+            min_distance: 6.0,
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![alert1, alert2, far]);
+        assert!(save_res.is_ok());
+
+        let minutes_res = alert_dao.exposure_minutes_at(7, &now);
+        assert!(minutes_res.is_ok());
+        assert_eq!(
+            ExposureMinutes {
+                close: 30,
+                medium: 0,
+                far: 5,
+            },
+            minutes_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_save_out_of_range_time_fails_only_that_alert() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+
+        let max_alert = Alert {
+            id: "1".to_owned(),
+            contact_end: i64::MAX as u64,
+            ..test_alert()
+        };
+        let out_of_range_alert = Alert {
+            id: "2".to_owned(),
+            contact_end: i64::MAX as u64 + 1,
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![max_alert.clone(), out_of_range_alert]);
+        assert!(save_res.is_ok());
+        let outcome = save_res.unwrap();
+        assert_eq!(1, outcome.saved);
+        assert_eq!(1, outcome.failed.len());
+        match &outcome.failed[0] {
+            (id, ServicesError::Database(_)) => assert_eq!("2", id),
+            other => panic!("Expected database error, got: {:?}", other),
+        }
+
+        // The valid alert was stored
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        assert_eq!(loaded_alerts_res.unwrap(), vec![max_alert]);
+    }
+
+    #[test]
+    fn test_save_constraint_violation_keeps_valid_alerts() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let exec_res = database.execute_batch(
+            "create trigger reject_alert before insert on alert when new.id = 'rejected'
+            begin
+                select raise(abort, 'Rejected alert');
+            end;",
+        );
+        assert!(exec_res.is_ok());
+
+        let alert1 = Alert {
+            id: "1".to_owned(),
+            ..test_alert()
+        };
+        let rejected_alert = Alert {
+            id: "rejected".to_owned(),
+            ..test_alert()
+        };
+        let alert2 = Alert {
+            id: "2".to_owned(),
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![
+            alert1.clone(),
+            rejected_alert,
+            alert2.clone(),
+            alert1.clone(), // Already stored: ignored
+        ]);
+        assert!(save_res.is_ok());
+        let outcome = save_res.unwrap();
+        assert_eq!(2, outcome.saved);
+        assert_eq!(1, outcome.failed.len());
+        assert_eq!("rejected", outcome.failed[0].0);
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        let mut loaded_alerts = loaded_alerts_res.unwrap();
+        loaded_alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+        assert_eq!(loaded_alerts, vec![alert1, alert2]);
+    }
+
+    #[test]
+    fn test_save_failing_alert_leaves_no_orphan_report() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let exec_res = database.execute_batch(
+            "create trigger reject_alert before insert on alert when new.id = 'rejected'
+            begin
+                select raise(abort, 'Rejected alert');
+            end;",
+        );
+        assert!(exec_res.is_ok());
+
+        let rejected_alert = Alert {
+            id: "rejected".to_owned(),
+            report_id: "rejected_report".to_owned(),
+            ..test_alert()
+        };
+        let alert = Alert {
+            id: "2".to_owned(),
+            ..test_alert()
+        };
+
+        let save_res = alert_dao.save(vec![rejected_alert, alert.clone()]);
+        assert!(save_res.is_ok());
+        let outcome = save_res.unwrap();
+        assert_eq!(1, outcome.saved);
+        assert_eq!(1, outcome.failed.len());
+        assert_eq!("rejected", outcome.failed[0].0);
+
+        // The report of the rejected alert was rolled back with it
+        let report_ids: Vec<String> = database
+            .query("select report_id from report", NO_PARAMS, |row| {
+                row.get(0).map_err(ServicesError::from)
+            })
+            .unwrap()
+            .rows;
+        assert_eq!(report_ids, vec![alert.report_id.clone()]);
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        assert_eq!(loaded_alerts_res.unwrap(), vec![alert]);
+    }
+
+    #[test]
+    fn test_loads_alert_by_id() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert = test_alert();
+        let other_alert = Alert {
+            id: "2".to_owned(),
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![alert.clone(), other_alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let alert_res = alert_dao.alert("2");
+        assert!(alert_res.is_ok());
+        assert_eq!(alert_res.unwrap(), other_alert);
+
+        let delete_res = alert_dao.delete("2".to_owned());
+        assert!(delete_res.is_ok());
+
+        match alert_dao.alert("2") {
+            Err(ServicesError::NotFound) => {}
+            other => panic!("Expected NotFound, got: {:?}", other),
+        }
+        match alert_dao.alert("3") {
+            Err(ServicesError::NotFound) => {}
+            other => panic!("Expected NotFound, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_row_values_are_normalized() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let alert = test_alert();
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        // Reversed window, negative distances, unknown severities
+        let insert_res = database.execute_batch(
+            "insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms)
+            values('2', 0, NULL, 7, -3, 1, 1, 0, 0, 1, 0, 1);
+            insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('2', 3000, 1000, -1.5, -2.0, '2', 0);",
+        );
+        assert!(insert_res.is_ok());
+        // Negative end: clamped, then swapped with the start
+        let insert_res = database.execute_batch(
+            "insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms)
+            values('3', -100, NULL, 1, 2, 1, 1, 0, 0, 1, 0, 1);
+            insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('3', 1000, -5, 2.3, 4.3, '3', 0);",
+        );
+        assert!(insert_res.is_ok());
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        let mut loaded_alerts = loaded_alerts_res.unwrap();
+        loaded_alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+
+        assert_eq!(
+            loaded_alerts,
+            vec![
+                alert.clone(),
+                Alert {
+                    id: "2".to_owned(),
+                    report_id: "2".to_owned(),
+                    symptoms: PublicSymptoms {
+                        fever_severity: FeverSeverity::None,
+                        cough_severity: CoughSeverity::None,
+                        earliest_symptom_time: UserInput::None,
+                        ..alert.symptoms.clone()
+                    },
+                    contact_start: 1000,
+                    contact_end: 3000,
+                    min_distance: 0.0,
+                    avg_distance: 0.0,
+                    // Not stored: the normalized min / avg distance
+                    p10_distance: 0.0,
+                    median_distance: 0.0,
+                    ..alert.clone()
+                },
+                Alert {
+                    id: "3".to_owned(),
+                    report_id: "3".to_owned(),
+                    symptoms: PublicSymptoms {
+                        earliest_symptom_time: UserInput::None,
+                        ..alert.symptoms.clone()
+                    },
+                    contact_start: 0,
+                    contact_end: 1000,
+                    ..alert
+                }
+            ]
+        );
+
+        assert_eq!(2, alert_dao.db_stats().normalized_alerts);
+    }
+
+    #[test]
+    fn test_save_rejects_oversized_ids() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let max_id = "a".repeat(MAX_ID_LENGTH);
+        let oversized_id = "a".repeat(MAX_ID_LENGTH + 1);
+        let alerts = vec![
+            Alert {
+                id: oversized_id.clone(),
+                ..test_alert()
+            },
+            Alert {
+                id: "2".to_owned(),
+                report_id: oversized_id,
+                ..test_alert()
+            },
+            Alert {
+                id: max_id.clone(),
+                report_id: max_id.clone(),
+                ..test_alert()
+            },
+        ];
+
+        let save_res = alert_dao.save(alerts.clone());
+        let outcome = expect_log!(save_res, "Couldn't save alerts");
+        assert_eq!(1, outcome.saved);
+        assert_eq!(2, outcome.failed.len());
+        for (_, error) in &outcome.failed {
+            assert!(matches!(error, ServicesError::InvalidInput(_)));
+        }
+        assert_eq!(vec![alerts[2].clone()], alert_dao.all().unwrap());
+    }
+
+    #[test]
+    fn test_oversized_ids_are_truncated_on_load() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        // Like stored before the ids were bounded: the report table of older versions has no check
+        let oversized_id = "b".repeat(MAX_ID_LENGTH + 10);
+        let insert_res = database.execute_batch(&format!(
+            "pragma ignore_check_constraints = on;
+            insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms)
+            values('{0}', 0, NULL, 1, 2, 1, 1, 0, 0, 1, 0, 1);
+            insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('{0}-1000', 1000, 2000, 2.3, 4.3, '{0}', 0);
+            pragma ignore_check_constraints = off;",
+            oversized_id
+        ));
+        expect_log!(insert_res, "Couldn't insert oversized ids");
+
+        let loaded_alerts_res = alert_dao.all();
+        let loaded_alerts = expect_log!(loaded_alerts_res, "Couldn't load alerts");
+        assert_eq!(1, loaded_alerts.len());
+        assert_eq!("b".repeat(MAX_ID_LENGTH), loaded_alerts[0].id);
+        assert_eq!("b".repeat(MAX_ID_LENGTH), loaded_alerts[0].report_id);
+        assert_eq!(1, alert_dao.db_stats().normalized_alerts);
+
+        // The report table of this version rejects them
+        let insert_res = database.execute_sql(
+            "insert into report(report_id, report_time, earliest_symptom_time, fever_severity,
+            cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose,
+            other, no_symptoms)
+            values(?1, 0, NULL, 1, 2, 1, 1, 0, 0, 1, 0, 1)",
+            params![format!("{}c", oversized_id)],
+        );
+        assert!(insert_res.is_err());
+    }
+
+    #[test]
+    fn test_unreadable_rows_are_skipped() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let alert = test_alert();
+        let save_res = alert_dao.save(vec![alert.clone()]);
+        assert!(save_res.is_ok());
+
+        // Text start: can't be read as integer
+        let insert_res = database.execute_sql(
+            "insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('2', 'abc', 1000, 2.3, 4.3, '1', 0)",
+            params![],
+        );
+        assert!(insert_res.is_ok());
+        // No report row: the symptoms are missing
+        let insert_res = database.execute_sql(
+            "insert into alert(id, start, end, min_distance, avg_distance, report_id, read)
+            values('3', 1000, 2000, 2.3, 4.3, 'unknown', 0)",
+            params![],
+        );
+        assert!(insert_res.is_ok());
+
+        let loaded_alerts_res = alert_dao.all();
+        assert!(loaded_alerts_res.is_ok());
+        assert_eq!(vec![alert], loaded_alerts_res.unwrap());
+
+        for id in &["2", "3"] {
+            match alert_dao.alert(id) {
+                Err(ServicesError::NotFound) => {}
+                other => panic!("Expected NotFound, got: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_alerts_of_a_report_share_the_report_row() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        let alert1 = test_alert();
+        let alert2 = Alert {
+            id: "2".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..test_alert()
+        };
+        let other_report_alert = Alert {
+            id: "3".to_owned(),
+            report_id: "2".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 5000 },
+                fever_severity: FeverSeverity::Serious,
+                cough_severity: CoughSeverity::Wet,
+                no_symptoms: false,
+                memo_version: 2,
+                ..test_alert().symptoms
+            },
+            ..test_alert()
+        };
+        let save_res = alert_dao.save_at(
+            vec![alert1.clone(), alert2.clone(), other_report_alert.clone()],
+            &UnixTime { value: 6000 },
+        );
+        assert_eq!(3, save_res.unwrap().saved);
+
+        // Saved later: the stored report row is kept
+        let alert4 = Alert {
+            id: "4".to_owned(),
+            contact_start: 5000,
+            contact_end: 6000,
+            ..test_alert()
+        };
+        let save_res = alert_dao.save_at(vec![alert4.clone()], &UnixTime { value: 7000 });
+        assert_eq!(1, save_res.unwrap().saved);
+
+        let reports_res = database.query(
+            "select report_id, received_at from report order by report_id",
+            NO_PARAMS,
+            |row| {
+                let report_id: String = column(row, 0, "report_id")?;
+                let received_at: i64 = column(row, 1, "received_at")?;
+                Ok((report_id, received_at))
+            },
+        );
+        assert_eq!(
+            vec![("1".to_owned(), 6000), ("2".to_owned(), 6000)],
+            reports_res.unwrap().rows
+        );
+
+        let mut loaded_alerts = alert_dao.all().unwrap();
+        loaded_alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+        assert_eq!(
+            vec![alert1, alert2, other_report_alert.clone(), alert4],
+            loaded_alerts
+        );
+        assert_eq!(other_report_alert, alert_dao.alert("3").unwrap());
+
+        // Deleted alerts keep the report row of the other alerts
+        assert!(alert_dao.delete("1".to_owned()).is_ok());
+        assert_eq!(3, alert_dao.all().unwrap().len());
+    }
+
+    #[test]
+    fn test_save_with_storage_full_saves_nothing() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database.clone());
+
+        // More than fits in the free space of the allocated pages
+        let alerts: Vec<Alert> = (0..1000)
+            .map(|index| Alert {
+                id: index.to_string(),
+                report_id: index.to_string(),
+                ..test_alert()
+            })
+            .collect();
+
+        set_storage_full(&database, true);
+        match alert_dao.save(alerts.clone()) {
+            Err(ServicesError::StorageFull) => {}
+            other => panic!("Expected StorageFull, got: {:?}", other),
+        }
+        assert!(alert_dao.all().unwrap().is_empty());
+
+        let stats = alert_dao.db_stats();
+        assert!(stats.page_size > 0);
+        assert!(stats.page_count > 0);
+        assert!(stats.free_pages <= stats.page_count);
+
+        // Saved once there's space
+        set_storage_full(&database, false);
+        assert_eq!(1000, alert_dao.save(alerts).unwrap().saved);
+        assert!(alert_dao.db_stats().page_count > stats.page_count);
+    }
+
+    #[test]
+    fn test_saves_and_replaces_exposures() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let exposure = ReportExposure {
+            alert_id: "1".to_owned(),
+            report_id: "report".to_owned(),
+            contact_start: 1000,
+            contact_end: 2000,
+            min_distance: 0.5,
+            avg_distance: 1.5,
+            p10_distance: 0.9,
+            median_distance: 1.4,
+            total_count: 3,
+            tcn_prefixes: vec![],
+        };
+        let other_report_exposure = ReportExposure {
+            alert_id: "2".to_owned(),
+            report_id: "other report".to_owned(),
+            ..exposure.clone()
+        };
+        let save_res = alert_dao.save_exposures(vec![exposure.clone(), other_report_exposure]);
+        assert!(save_res.is_ok());
+
+        let extended = ReportExposure {
+            contact_end: 3000,
+            total_count: 5,
+            ..exposure
+        };
+        let save_res = alert_dao.save_exposures(vec![extended.clone()]);
+        assert!(save_res.is_ok());
+
+        let exposures_res = alert_dao.exposures("report");
+        assert!(exposures_res.is_ok());
+        assert_eq!(vec![extended], exposures_res.unwrap());
+    }
+
+    #[test]
+    fn test_update_measurements_keeps_read_state() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert = test_alert();
+        assert!(alert_dao.save(vec![alert.clone()]).is_ok());
+        assert!(alert_dao.update_is_read(alert.id.clone(), true).is_ok());
+
+        let extended = Alert {
+            contact_start: 500,
+            contact_end: 3000,
+            min_distance: 1.0,
+            avg_distance: 2.0,
+            p10_distance: 1.4,
+            median_distance: 1.9,
+            ..alert.clone()
+        };
+        let update_res = alert_dao.update_measurements(&[extended.clone()]);
+        assert!(update_res.is_ok());
+
+        let loaded_res = alert_dao.alert(&alert.id);
+        assert!(loaded_res.is_ok());
+        assert_eq!(
+            Alert {
+                is_read: true,
+                ..extended
+            },
+            loaded_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_delete_by_report_deletes_only_its_alerts() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let alert1 = test_alert();
+        let alert2 = Alert {
+            id: "2".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..test_alert()
+        };
+        let other_report_alert = Alert {
+            id: "3".to_owned(),
+            report_id: "2".to_owned(),
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![alert1, alert2, other_report_alert.clone()]);
+        assert!(save_res.is_ok());
+
+        assert_eq!(2, alert_dao.delete_by_report("1".to_owned()).unwrap());
+        assert_eq!(vec![other_report_alert], alert_dao.all().unwrap());
+        assert!(alert_dao.is_report_dismissed("1").unwrap());
+        assert!(!alert_dao.is_report_dismissed("2").unwrap());
+
+        // Already dismissed
+        assert_eq!(0, alert_dao.delete_by_report("1".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn test_retract_reports_flags_only_earlier_reports() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let earlier_alert = test_alert(); // Report time 0
+        let later_alert = Alert {
+            id: "2".to_owned(),
+            report_id: "2".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 5000 },
+                ..test_alert().symptoms
+            },
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![earlier_alert.clone(), later_alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let report_ids = vec!["1".to_owned(), "2".to_owned(), "unknown".to_owned()];
+        let retract_res = alert_dao.retract_reports(&report_ids, "3", UnixTime { value: 3000 });
+        assert_eq!(1, retract_res.unwrap());
+        assert_eq!(
+            vec![
+                Alert {
+                    retracted: true,
+                    ..earlier_alert.clone()
+                },
+                later_alert
+            ],
+            alert_dao.all().unwrap()
+        );
+
+        // Later alerts of the retracted report are flagged too
+        let new_alert = Alert {
+            id: "4".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..earlier_alert
+        };
+        assert!(alert_dao.save(vec![new_alert]).is_ok());
+        assert!(alert_dao.alert("4").unwrap().retracted);
+
+        // Already retracted
+        let retract_res = alert_dao.retract_reports(&report_ids, "3", UnixTime { value: 3000 });
+        assert_eq!(0, retract_res.unwrap());
+    }
+
+    #[test]
+    fn test_delete_ended_before() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let old_alert = test_alert(); // Ends at 2000
+        let recent_alert = Alert {
+            id: "2".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![old_alert, recent_alert.clone()]);
+        assert!(save_res.is_ok());
+
+        assert_eq!(1, alert_dao.delete_ended_before(3000).unwrap());
+        assert_eq!(vec![recent_alert], alert_dao.all().unwrap());
+        // Already deleted
+        assert_eq!(0, alert_dao.delete_ended_before(3000).unwrap());
+    }
+
+    #[test]
+    fn test_saves_and_updates_processed_reports() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        assert_eq!(None, alert_dao.processed_report("1").unwrap());
+
+        let report = ProcessedReport {
+            report_id: "1".to_owned(),
+            first_seen_interval: 10,
+            last_seen_interval: 10,
+            interval_length: 21600,
+            content_hash: "a".to_owned(),
+        };
+        assert!(alert_dao
+            .save_processed_reports(vec![report.clone()])
+            .is_ok());
+        assert_eq!(
+            Some(report.clone()),
+            alert_dao.processed_report("1").unwrap()
+        );
+
+        let seen_later = ProcessedReport {
+            first_seen_interval: 11,
+            last_seen_interval: 11,
+            content_hash: "b".to_owned(),
+            ..report.clone()
+        };
+        assert!(alert_dao.save_processed_reports(vec![seen_later]).is_ok());
+        let expected = ProcessedReport {
+            first_seen_interval: 10,
+            last_seen_interval: 11,
+            content_hash: "b".to_owned(),
+            ..report.clone()
+        };
+        assert_eq!(
+            Some(expected.clone()),
+            alert_dao.processed_report("1").unwrap()
+        );
+
+        // A retried older interval doesn't move the last seen interval back
+        assert!(alert_dao.save_processed_reports(vec![report]).is_ok());
+        assert_eq!(Some(expected), alert_dao.processed_report("1").unwrap());
+    }
+
+    #[test]
+    fn test_saves_and_replaces_report_interval_states() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        assert_eq!(None, alert_dao.report_interval_state(10, 21600).unwrap());
+
+        let state = ReportIntervalState {
+            interval_number: 10,
+            interval_length: 21600,
+            payload_hash: "a".to_owned(),
+            inputs_hash: "b".to_owned(),
+        };
+        assert!(alert_dao
+            .save_report_interval_states(vec![state.clone()])
+            .is_ok());
+        assert_eq!(
+            Some(state.clone()),
+            alert_dao.report_interval_state(10, 21600).unwrap()
+        );
+        // By number and length
+        assert_eq!(None, alert_dao.report_interval_state(10, 3600).unwrap());
+
+        let changed = ReportIntervalState {
+            payload_hash: "c".to_owned(),
+            ..state
+        };
+        assert!(alert_dao
+            .save_report_interval_states(vec![changed.clone()])
+            .is_ok());
+        assert_eq!(
+            Some(changed),
+            alert_dao.report_interval_state(10, 21600).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_keeps_only_latest_report_interval_states() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let states_count = REPORT_INTERVAL_STATES_SIZE as u64 + 3;
+        // Saved latest first: the earliest intervals are deleted, not the last saved
+        let states = (0..states_count)
+            .rev()
+            .map(|number| ReportIntervalState {
+                interval_number: number,
+                interval_length: 21600,
+                payload_hash: "a".to_owned(),
+                inputs_hash: "b".to_owned(),
+            })
+            .collect();
+        assert!(alert_dao.save_report_interval_states(states).is_ok());
+
+        for number in 0..3 {
+            assert_eq!(
+                None,
+                alert_dao.report_interval_state(number, 21600).unwrap()
+            );
+        }
+        for number in 3..states_count {
+            assert!(alert_dao
+                .report_interval_state(number, 21600)
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[test]
+    fn test_expire_unseen_reports() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let old_alert = test_alert(); // Ends at 2000
+        let recent_alert = Alert {
+            id: "2".to_owned(),
+            contact_start: 3000,
+            contact_end: 4000,
+            ..test_alert()
+        };
+        let seen_report_alert = Alert {
+            id: "3".to_owned(),
+            report_id: "2".to_owned(),
+            ..test_alert()
+        };
+        let not_fetched_report_alert = Alert {
+            id: "4".to_owned(),
+            report_id: "3".to_owned(),
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![
+            old_alert,
+            recent_alert.clone(),
+            seen_report_alert.clone(),
+            not_fetched_report_alert.clone(),
+        ]);
+        assert!(save_res.is_ok());
+
+        // Report 1 last seen in [1000, 2000), report 2 in [5000, 6000)
+        let unseen_report = ProcessedReport {
+            report_id: "1".to_owned(),
+            first_seen_interval: 1,
+            last_seen_interval: 1,
+            interval_length: 1000,
+            content_hash: "a".to_owned(),
+        };
+        let seen_report = ProcessedReport {
+            report_id: "2".to_owned(),
+            first_seen_interval: 5,
+            last_seen_interval: 5,
+            ..unseen_report.clone()
+        };
+        let save_res = alert_dao.save_processed_reports(vec![unseen_report, seen_report]);
+        assert!(save_res.is_ok());
+
+        assert_eq!(1, alert_dao.expire_unseen_reports(3000, 3000).unwrap());
+        assert_eq!(
+            vec![recent_alert, seen_report_alert, not_fetched_report_alert],
+            alert_dao.all().unwrap()
+        );
+
+        assert_eq!(
+            1,
+            alert_dao
+                .delete_processed_reports_seen_before(3000)
+                .unwrap()
+        );
+        assert_eq!(None, alert_dao.processed_report("1").unwrap());
+        assert!(alert_dao.processed_report("2").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_active_snooze_hides_alert() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let other_alert = Alert {
+            id: "2".to_owned(),
+            ..test_alert()
+        };
+        let save_res = alert_dao.save(vec![test_alert(), other_alert.clone()]);
+        assert!(save_res.is_ok());
+
+        let snooze_res = alert_dao.snooze("1".to_owned(), UnixTime { value: 2000 });
+        assert!(snooze_res.is_ok());
+
+        let alerts = alert_dao.all_at(&UnixTime { value: 1999 }).unwrap();
+        assert_eq!(vec![other_alert], alerts);
+
+        let all_alerts = alert_dao.all_including_snoozed().unwrap();
+        assert_eq!(2, all_alerts.len());
+        assert_eq!(Some(2000), all_alerts[0].snoozed_until);
+    }
+
+    #[test]
+    fn test_expired_snooze_shows_alert() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+
+        let snooze_res = alert_dao.snooze("1".to_owned(), UnixTime { value: 2000 });
+        assert!(snooze_res.is_ok());
+
+        let alerts = alert_dao.all_at(&UnixTime { value: 2000 }).unwrap();
+        assert_eq!(1, alerts.len());
+        assert_eq!(Some(2000), alerts[0].snoozed_until);
+    }
+
+    #[test]
+    fn test_snooze_deleted_alert_is_not_found() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+        assert!(alert_dao.delete("1".to_owned()).is_ok());
+
+        let snooze_res = alert_dao.snooze("1".to_owned(), UnixTime { value: 2000 });
+        assert!(matches!(snooze_res, Err(ServicesError::NotFound)));
+    }
+
+    #[test]
+    fn test_records_acknowledgement_submission() {
+        let database = migrated_database();
+        let alert_dao = AlertDaoImpl::new(database);
+
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+        assert_eq!(None, alert_dao.acknowledgement_submitted("1").unwrap());
+
+        let set_res = alert_dao.set_acknowledgement_submitted("1", UnixTime { value: 3000 });
+        assert!(set_res.is_ok());
+        assert_eq!(
+            Some(3000),
+            alert_dao.acknowledgement_submitted("1").unwrap()
+        );
+
+        let set_res = alert_dao.set_acknowledgement_submitted("2", UnixTime { value: 3000 });
+        assert!(matches!(set_res, Err(ServicesError::NotFound)));
+        let submitted_res = alert_dao.acknowledgement_submitted("2");
+        assert!(matches!(submitted_res, Err(ServicesError::NotFound)));
+    }
+
+    #[test]
+    fn test_alerts_pages() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+        let now = UnixTime { value: 10000 };
+
+        // 3 and 4: same start, ordered by id
+        let starts = [1000, 3000, 2000, 2000, 4000, 5000];
+        let alerts: Vec<Alert> = (1..=6)
+            .zip(starts.iter())
+            .map(|(index, start)| paged_alert(&index.to_string(), *start))
+            .collect();
+        assert!(alert_dao.save(alerts).is_ok());
+        // Neither snoozed nor deleted alerts are paged
+        assert!(alert_dao
+            .snooze("6".to_owned(), UnixTime { value: 20000 })
+            .is_ok());
+        assert!(alert_dao.delete("1".to_owned()).is_ok());
+
+        let first_page = alert_dao.alerts_page_at(None, 2, &now).unwrap();
+        assert_eq!(vec!["5", "2"], alert_ids(&first_page));
+        assert_eq!(Some(4), first_page.total);
+
+        let second_page = alert_dao
+            .alerts_page_at(first_page.next_cursor.as_deref(), 2, &now)
+            .unwrap();
+        assert_eq!(vec!["4", "3"], alert_ids(&second_page));
+        assert_eq!(None, second_page.total);
+
+        // The final page is full: no next page
+        assert_eq!(None, second_page.next_cursor);
+
+        let single_page = alert_dao.alerts_page_at(None, 10, &now).unwrap();
+        assert_eq!(vec!["5", "2", "4", "3"], alert_ids(&single_page));
+        assert_eq!(None, single_page.next_cursor);
+        assert_eq!(Some(4), single_page.total);
+    }
+
+    #[test]
+    fn test_alerts_pages_are_stable_when_alerts_arrive() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+        let now = UnixTime { value: 10000 };
+
+        let alerts: Vec<Alert> = (1..=5)
+            .map(|index| paged_alert(&index.to_string(), index * 1000))
+            .collect();
+        assert!(alert_dao.save(alerts).is_ok());
+
+        let first_page = alert_dao.alerts_page_at(None, 2, &now).unwrap();
+        assert_eq!(vec!["5", "4"], alert_ids(&first_page));
+
+        // Newer than the read pages: shown when paging again from the start
+        assert!(alert_dao.save(vec![paged_alert("6", 6000)]).is_ok());
+
+        let second_page = alert_dao
+            .alerts_page_at(first_page.next_cursor.as_deref(), 2, &now)
+            .unwrap();
+        assert_eq!(vec!["3", "2"], alert_ids(&second_page));
+
+        let last_page = alert_dao
+            .alerts_page_at(second_page.next_cursor.as_deref(), 2, &now)
+            .unwrap();
+        assert_eq!(vec!["1"], alert_ids(&last_page));
+        assert_eq!(None, last_page.next_cursor);
+
+        let first_page = alert_dao.alerts_page_at(None, 2, &now).unwrap();
+        assert_eq!(vec!["6", "5"], alert_ids(&first_page));
+        assert_eq!(Some(6), first_page.total);
+    }
+
+    #[test]
+    fn test_alerts_page_with_invalid_parameters() {
+        let alert_dao = AlertDaoImpl::new(migrated_database());
+        let now = UnixTime { value: 10000 };
+
+        for cursor in &["", "abc", "1000", "x:1"] {
+            let page_res = alert_dao.alerts_page_at(Some(*cursor), 2, &now);
+            assert!(
+                matches!(page_res, Err(ServicesError::FFIParameters(_))),
+                "Cursor: {}",
+                cursor
+            );
+        }
+        let page_res = alert_dao.alerts_page_at(None, 0, &now);
+        assert!(matches!(page_res, Err(ServicesError::FFIParameters(_))));
+    }
+
+    fn paged_alert(id: &str, contact_start: u64) -> Alert {
+        Alert {
+            id: id.to_owned(),
+            contact_start,
+            contact_end: contact_start + 60,
+            ..test_alert()
+        }
+    }
+
+    fn alert_ids(page: &AlertsPage) -> Vec<&str> {
+        page.alerts.iter().map(|alert| alert.id.as_str()).collect()
+    }
+
+    fn test_alert() -> Alert {
+        Alert {
+            id: "1".to_owned(),
+            report_id: "1".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 0 },
+                earliest_symptom_time: UserInput::Some(UnixTime { value: 1590356601 }),
+                fever_severity: FeverSeverity::Mild,
+                cough_severity: CoughSeverity::Dry,
+                breathlessness: true,
+                muscle_aches: true,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: true,
+                other: false,
+                no_symptoms: true,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            },
+            contact_start: 1000,
+            contact_end: 2000,
+            min_distance: 2.3,
+            avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
+            is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
+        }
+    }
 }
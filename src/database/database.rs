@@ -1,11 +1,60 @@
 use crate::{errors::ServicesError, expect_log};
 use log::*;
 use rusqlite::types::FromSql;
-use rusqlite::{Connection, Error, Result, Row, ToSql, Transaction};
-use std::sync::Mutex;
+use rusqlite::{Connection, Error, OpenFlags, Result, Row, ToSql, Transaction, NO_PARAMS};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(100);
 
 pub struct Database {
     conn: Mutex<Connection>,
+    // Only for file databases in WAL mode: large reads (e.g. the TCNs to match) don't block writes.
+    read_conn: Option<Mutex<Connection>>,
+    // None: slow queries aren't detected
+    slow_query_threshold: Option<Duration>,
+    slow_query_listener: Option<Box<dyn Fn(&SlowQuery) + Send + Sync>>,
+    slow_queries: AtomicU64,
+}
+
+// A database operation that took longer than the slow query threshold (see Database::slow_query_threshold)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    // The Database method, e.g. "query" or "transaction"
+    pub operation: &'static str,
+    // Without the parameters, which can be personal data (e.g. TCNs). None for transactions.
+    pub sql: Option<String>,
+    pub duration: Duration,
+    pub thread: String,
+}
+
+impl fmt::Display for SlowQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Slow {} ({} ms, thread: {})",
+            self.operation,
+            self.duration.as_millis(),
+            self.thread
+        )?;
+        if let Some(sql) = &self.sql {
+            // Multiline statements on one line
+            write!(
+                f,
+                ": {}",
+                sql.split_whitespace().collect::<Vec<&str>>().join(" ")
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl Database {
@@ -18,53 +67,116 @@ impl Database {
     {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
-        conn.execute(sql, pars)
+        self.timed("execute", Some(sql), || conn.execute(sql, pars))
     }
-    #[allow(dead_code)]
     pub fn execute_batch(&self, sql: &str) -> Result<()> {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
-        conn.execute_batch(sql)
+        self.timed("execute_batch", Some(sql), || conn.execute_batch(sql))
     }
 
-    pub fn core_pragma_query<T>(&self, pragma_variable_name: &str) -> T
+    pub fn core_pragma_query<T>(&self, pragma_variable_name: &str) -> Result<T, ServicesError>
     where
         T: FromSql,
     {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
         let mut value_res: Result<T, Error> = Err(Error::QueryReturnedNoRows);
-        let _ = conn.pragma_query(None, pragma_variable_name, |row| {
+        conn.pragma_query(None, pragma_variable_name, |row| {
             value_res = row.get(0);
             Ok(())
-        });
-        expect_log!(value_res, "Failed to retrieve pragma value")
+        })?;
+        value_res.map_err(|e| {
+            ServicesError::Database(format!(
+                "Couldn't read pragma {}: {}",
+                pragma_variable_name, e
+            ))
+        })
     }
 
-    pub fn core_pragma_update(&self, pragma_variable_name: &str, new_value: &i32) {
+    pub fn core_pragma_update(
+        &self,
+        pragma_variable_name: &str,
+        new_value: &i32,
+    ) -> Result<(), ServicesError> {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
-        let update_res = conn.pragma_update(None, pragma_variable_name, new_value);
-        expect_log!(update_res, "Failed to update pragma value");
+        conn.pragma_update(None, pragma_variable_name, new_value)
+            .map_err(ServicesError::from)
     }
 
-    pub fn query<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<Vec<T>, rusqlite::Error>
+    // Rows the mapper fails on are skipped (and counted), the other rows are still returned.
+    pub fn query<T, P, F>(
+        &self,
+        sql: &str,
+        params: P,
+        f: F,
+    ) -> Result<QueryRows<T>, rusqlite::Error>
     where
         P: IntoIterator,
         P::Item: ToSql,
-        F: Fn(&Row<'_>) -> T,
+        F: Fn(&Row<'_>) -> Result<T, ServicesError>,
     {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
+        self.timed("query", Some(sql), || {
+            Self::query_connection(&conn, sql, params, f)
+        })
+    }
 
+    // Like query, on the read-only connection if there's one. Sees the data committed when the query starts.
+    pub fn read_query<T, P, F>(
+        &self,
+        sql: &str,
+        params: P,
+        f: F,
+    ) -> Result<QueryRows<T>, rusqlite::Error>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+        F: Fn(&Row<'_>) -> Result<T, ServicesError>,
+    {
+        match &self.read_conn {
+            Some(read_conn) => {
+                let res = read_conn.lock();
+                let conn = expect_log!(res, "Couldn't lock mutex");
+                self.timed("read_query", Some(sql), || {
+                    Self::query_connection(&conn, sql, params, f)
+                })
+            }
+            None => self.query(sql, params, f),
+        }
+    }
+
+    fn query_connection<T, P, F>(
+        conn: &Connection,
+        sql: &str,
+        params: P,
+        f: F,
+    ) -> Result<QueryRows<T>, rusqlite::Error>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+        F: Fn(&Row<'_>) -> Result<T, ServicesError>,
+    {
         let mut statement = conn.prepare(sql)?;
         let mut rows = statement.query(params)?;
 
         let mut objs = Vec::new();
-        while let Some(row) = rows.next().unwrap() {
-            objs.push(f(row));
+        let mut skipped = 0;
+        while let Some(row) = rows.next()? {
+            match f(row) {
+                Ok(obj) => objs.push(obj),
+                Err(e) => {
+                    warn!("Skipping row: {}", e);
+                    skipped += 1;
+                }
+            }
         }
-        Ok(objs)
+        Ok(QueryRows {
+            rows: objs,
+            skipped,
+        })
     }
 
     pub fn query_row<T, P, F>(&self, sql: &str, params: P, f: F) -> Result<T, rusqlite::Error>
@@ -75,7 +187,23 @@ impl Database {
     {
         let res = self.conn.lock();
         let conn = expect_log!(res, "Couldn't lock mutex");
-        conn.query_row(sql, params, f)
+        self.timed("query_row", Some(sql), || conn.query_row(sql, params, f))
+    }
+
+    // Copies the WAL into the database file and truncates it. Does nothing if the database isn't in WAL mode.
+    pub fn checkpoint(&self) -> Result<(), ServicesError> {
+        let busy: i64 = self.query_row("pragma wal_checkpoint(truncate);", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+        if busy != 0 {
+            warn!("WAL checkpoint couldn't complete: database busy");
+        }
+        Ok(())
+    }
+
+    // Rebuilds the database file, releasing the space of the deleted rows
+    pub fn vacuum(&self) -> Result<(), ServicesError> {
+        self.execute_batch("vacuum;").map_err(ServicesError::from)
     }
 
     pub fn transaction<F>(&self, f: F) -> Result<(), ServicesError>
@@ -85,39 +213,167 @@ impl Database {
         let conn_res = self.conn.lock();
         let mut conn = expect_log!(conn_res, "Couldn't lock connection");
 
-        let t = conn.transaction()?;
-        match f(&t) {
-            Ok(_) => t.commit().map_err(ServicesError::from),
-            Err(commit_error) => {
-                let rollback_res = t.rollback();
-                if rollback_res.is_err() {
-                    // As we're already returning error status, show only a log for rollback error.
-                    error!(
-                        "There was an error committing and rollback failed too with: {:?}",
-                        rollback_res
-                    );
+        self.timed("transaction", None, || {
+            let t = conn.transaction()?;
+            match f(&t) {
+                Ok(_) => t.commit().map_err(ServicesError::from),
+                Err(commit_error) => {
+                    let rollback_res = t.rollback();
+                    if rollback_res.is_err() {
+                        // As we're already returning error status, show only a log for rollback error.
+                        error!(
+                            "There was an error committing and rollback failed too with: {:?}",
+                            rollback_res
+                        );
+                    }
+                    Err(commit_error)
                 }
-                Err(commit_error)
             }
-        }
+        })
     }
 
     pub fn new(conn: Connection) -> Database {
-        let load_array_mod_res = rusqlite::vtab::array::load_module(&conn);
+        Self::load_array_module(&conn);
+        Database {
+            conn: Mutex::new(conn),
+            read_conn: None,
+            slow_query_threshold: Some(DEFAULT_SLOW_QUERY_THRESHOLD),
+            slow_query_listener: None,
+            slow_queries: AtomicU64::new(0),
+        }
+    }
+
+    // Operations that take longer are logged (warn) and counted (see slow_queries). None disables it.
+    // The time waiting for the connection isn't counted.
+    pub fn slow_query_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_query_threshold = threshold;
+        self
+    }
+
+    // Called with the slow queries, after they're logged. E.g. for tests.
+    pub fn slow_query_listener(
+        mut self,
+        listener: impl Fn(&SlowQuery) + Send + Sync + 'static,
+    ) -> Self {
+        self.slow_query_listener = Some(Box::new(listener));
+        self
+    }
+
+    // Slow queries since the database was opened
+    pub fn slow_queries(&self) -> u64 {
+        self.slow_queries.load(Ordering::Relaxed)
+    }
+
+    fn timed<T>(&self, operation: &'static str, sql: Option<&str>, f: impl FnOnce() -> T) -> T {
+        let threshold = match self.slow_query_threshold {
+            Some(threshold) => threshold,
+            None => return f(),
+        };
+        let start = Instant::now();
+        let res = f();
+        let duration = start.elapsed();
+        if duration > threshold {
+            self.slow_queries.fetch_add(1, Ordering::Relaxed);
+            let slow_query = SlowQuery {
+                operation,
+                sql: sql.map(|sql| sql.to_owned()),
+                duration,
+                thread: thread::current().name().unwrap_or("unnamed").to_owned(),
+            };
+            warn!("{}", slow_query);
+            if let Some(listener) = &self.slow_query_listener {
+                listener(&slow_query);
+            }
+        }
+        res
+    }
+
+    // Enables WAL, so a second, read-only connection can read while the primary one writes.
+    pub fn open_file(path: &str) -> Result<Database, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        let journal_mode: String =
+            conn.query_row("pragma journal_mode = wal;", NO_PARAMS, |row| row.get(0))?;
+
+        let read_conn = if journal_mode.eq_ignore_ascii_case("wal") {
+            let read_conn = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            Self::load_array_module(&read_conn);
+            Some(Mutex::new(read_conn))
+        } else {
+            warn!(
+                "Couldn't enable WAL (journal mode: {}), reading with the primary connection",
+                journal_mode
+            );
+            None
+        };
+
+        Ok(Database {
+            read_conn,
+            ..Database::new(conn)
+        })
+    }
+
+    fn load_array_module(conn: &Connection) {
+        let load_array_mod_res = rusqlite::vtab::array::load_module(conn);
         expect_log!(
             load_array_mod_res,
             "Couldn't load array module (needed for IN query)"
         );
-        Database {
-            conn: Mutex::new(conn),
-        }
     }
 }
 
+// Result of a query: the mapped rows and the count of rows that couldn't be mapped.
+#[derive(Debug, PartialEq)]
+pub struct QueryRows<T> {
+    pub rows: Vec<T>,
+    pub skipped: usize,
+}
+
+// Reads a column in a query mapper. The error names the column.
+pub fn column<T>(row: &Row<'_>, index: usize, name: &str) -> Result<T, ServicesError>
+where
+    T: FromSql,
+{
+    row.get(index)
+        .map_err(|e| ServicesError::Database(format!("Invalid row: no {}: {}", name, e)))
+}
+
+// SQLite integers are i64: values that don't fit are an error instead of wrapping.
+pub fn to_db_i64<T>(value: T, field: &str) -> Result<i64, ServicesError>
+where
+    T: Copy + Display,
+    i64: TryFrom<T>,
+{
+    i64::try_from(value)
+        .map_err(|_| ServicesError::Database(format!("{} out of range: {}", field, value)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
+    use crate::{
+        database::{
+            migration::{Migration, REQUIRED_DB_VERSION},
+            tcn_dao::{TcnDao, TcnDaoImpl},
+        },
+        reports_interval::UnixTime,
+        reports_update::exposure::ExposureGrouper,
+        tcn_recording::{
+            distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn,
+            tcn_batches_manager::TcnBatchesManager,
+        },
+    };
+    use rusqlite::params;
+    use std::{
+        fs,
+        sync::{mpsc, Arc},
+        thread,
+        time::{Duration, Instant},
+    };
+    use tcn::TemporaryContactNumber;
+    use uuid::Uuid;
 
     #[test]
     fn test_pragma_logic() {
@@ -125,14 +381,248 @@ mod tests {
             Connection::open_in_memory().expect("Couldn't create database!"),
         ));
         let pragma_variable_name = "user_version";
-        let db_version: i32 = database.core_pragma_query(pragma_variable_name);
+        let db_version: i32 = database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(0, db_version);
-        database.core_pragma_update(pragma_variable_name, &17);
-        let db_version_17: i32 = database.core_pragma_query(pragma_variable_name);
+        assert!(database
+            .core_pragma_update(pragma_variable_name, &17)
+            .is_ok());
+        let db_version_17: i32 = database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(17, db_version_17);
 
-        database.core_pragma_update(pragma_variable_name, &1024);
-        let db_version_1024: i32 = database.core_pragma_query(pragma_variable_name);
+        assert!(database
+            .core_pragma_update(pragma_variable_name, &1024)
+            .is_ok());
+        let db_version_1024: i32 = database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(1024, db_version_1024);
     }
+
+    #[test]
+    fn test_long_read_doesnt_block_tcn_flush() {
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        fs::create_dir_all(&db_dir).unwrap();
+        let db_path = db_dir.join("db.sqlite");
+
+        let database_res = Database::open_file(db_path.to_str().unwrap());
+        assert!(database_res.is_ok());
+        let database = Arc::new(database_res.unwrap());
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+
+        let stored_tcns: Vec<ObservedTcn> = (0..20).map(|i| observed_tcn(i, 1000)).collect();
+        let save_res = tcn_dao.overwrite(stored_tcns);
+        assert!(save_res.is_ok());
+
+        // Synthetic long match: ~500ms reading the TCNs
+        let (started_sender, started_receiver) = mpsc::channel();
+        let reader_database = database.clone();
+        let reader = thread::spawn(move || {
+            reader_database.read_query("select tcn from tcn", NO_PARAMS, |row| {
+                let _ = started_sender.send(());
+                thread::sleep(Duration::from_millis(25));
+                column::<Vec<u8>>(row, 0, "tcn")
+            })
+        });
+        assert!(started_receiver.recv().is_ok());
+
+        // Observations recorded meanwhile
+        let batches_manager = TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper::default());
+        batches_manager.push(observed_tcn(0, 1100));
+        batches_manager.push(observed_tcn(100, 1100));
+        let flush_start = Instant::now();
+        let flush_res = batches_manager.flush();
+        assert!(flush_res.is_ok());
+        assert!(flush_start.elapsed() < Duration::from_millis(250));
+
+        // The read sees the TCNs stored when it started
+        let read_res = reader.join().unwrap();
+        assert!(read_res.is_ok());
+        assert_eq!(20, read_res.unwrap().rows.len());
+
+        let all_res = tcn_dao.all();
+        assert!(all_res.is_ok());
+        assert_eq!(21, all_res.unwrap().len());
+
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_checkpoint_truncates_wal() {
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        fs::create_dir_all(&db_dir).unwrap();
+        let db_path = db_dir.join("db.sqlite");
+
+        let database = Arc::new(Database::open_file(db_path.to_str().unwrap()).unwrap());
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        let insert_res = database.execute_sql(
+            "insert into preferences(key, value) values('key', 'value')",
+            NO_PARAMS,
+        );
+        assert!(insert_res.is_ok());
+        let wal_path = db_dir.join("db.sqlite-wal");
+        assert!(fs::metadata(&wal_path).unwrap().len() > 0);
+
+        assert!(database.checkpoint().is_ok());
+        assert_eq!(0, fs::metadata(&wal_path).unwrap().len());
+        assert!(database.vacuum().is_ok());
+
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_in_memory_database_reads_with_primary_connection() {
+        let database =
+            Database::new(Connection::open_in_memory().expect("Couldn't create database!"));
+        assert!(database.read_conn.is_none());
+        let query_res = database.read_query("select 1", NO_PARAMS, |row| column(row, 0, "one"));
+        assert!(query_res.is_ok());
+        assert_eq!(vec![1], query_res.unwrap().rows);
+    }
+
+    #[test]
+    fn test_query_skips_rows_the_mapper_fails_on() {
+        let database =
+            Database::new(Connection::open_in_memory().expect("Couldn't create database!"));
+        let create_res = database.execute_batch(
+            "create table item(name text not null, amount integer not null);
+            insert into item(name, amount) values('a', 1), ('b', -1), ('c', 3);",
+        );
+        assert!(create_res.is_ok());
+
+        let query_res = database.query(
+            "select name, amount from item order by name",
+            NO_PARAMS,
+            |row| {
+                let name: String = column(row, 0, "name")?;
+                let amount: i64 = column(row, 1, "amount")?;
+                if amount < 0 {
+                    Err(ServicesError::Database(format!(
+                        "Negative amount: {}",
+                        amount
+                    )))
+                } else {
+                    Ok((name, amount))
+                }
+            },
+        );
+        assert!(query_res.is_ok());
+        assert_eq!(
+            QueryRows {
+                rows: vec![("a".to_owned(), 1), ("c".to_owned(), 3)],
+                skipped: 1
+            },
+            query_res.unwrap()
+        );
+
+        // Reading a missing column is an error of the row, not of the query
+        let query_res = database.query("select name from item", NO_PARAMS, |row| {
+            column::<i64>(row, 1, "amount")
+        });
+        assert!(query_res.is_ok());
+        assert_eq!(3, query_res.unwrap().skipped);
+    }
+
+    // Inserts the numbers from 1 to count
+    const INSERT_NUMBERS: &str = "with recursive number(value) as (
+            select 1 union all select value + 1 from number where value < ?1
+        )
+        insert into item(value) select value from number";
+
+    fn database_with_slow_query_listener(
+        threshold: Option<Duration>,
+    ) -> (Database, Arc<Mutex<Vec<SlowQuery>>>) {
+        let slow_queries = Arc::new(Mutex::new(vec![]));
+        let listener_slow_queries = slow_queries.clone();
+        let database =
+            Database::new(Connection::open_in_memory().expect("Couldn't create database!"))
+                .slow_query_threshold(threshold)
+                .slow_query_listener(move |slow_query| {
+                    listener_slow_queries
+                        .lock()
+                        .unwrap()
+                        .push(slow_query.clone())
+                });
+        let create_res = database.execute_batch("create table item(value integer not null);");
+        assert!(create_res.is_ok());
+        (database, slow_queries)
+    }
+
+    #[test]
+    fn test_slow_queries_are_logged_and_counted() {
+        let (database, slow_queries) =
+            database_with_slow_query_listener(Some(Duration::from_millis(5)));
+
+        let insert_res = database.execute_sql(INSERT_NUMBERS, params![200_000]);
+        assert!(insert_res.is_ok());
+        let transaction_res = database.transaction(|_| {
+            thread::sleep(Duration::from_millis(20));
+            Ok(())
+        });
+        assert!(transaction_res.is_ok());
+
+        let slow_queries = slow_queries.lock().unwrap();
+        assert_eq!(slow_queries.len() as u64, database.slow_queries());
+        let insert = slow_queries
+            .iter()
+            .find(|slow_query| slow_query.operation == "execute")
+            .expect("Insert not logged");
+        assert!(insert.duration > Duration::from_millis(5));
+        // The statement, without the parameters
+        let message = insert.to_string();
+        assert!(message.starts_with("Slow execute ("), "{}", message);
+        assert!(message.contains("insert into item(value) select value from number"));
+        assert!(!message.contains("200000"));
+
+        let transaction = slow_queries
+            .iter()
+            .find(|slow_query| slow_query.operation == "transaction")
+            .expect("Transaction not logged");
+        assert_eq!(None, transaction.sql);
+        assert!(transaction.duration >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_fast_queries_arent_logged() {
+        let (database, slow_queries) =
+            database_with_slow_query_listener(Some(Duration::from_secs(10)));
+
+        assert!(database.execute_sql(INSERT_NUMBERS, params![100]).is_ok());
+        let query_res = database.query("select value from item", NO_PARAMS, |row| {
+            column::<i64>(row, 0, "value")
+        });
+        assert!(query_res.is_ok());
+        assert_eq!(100, query_res.unwrap().rows.len());
+
+        assert_eq!(0, database.slow_queries());
+        assert!(slow_queries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slow_queries_arent_logged_when_disabled() {
+        let (database, slow_queries) = database_with_slow_query_listener(None);
+
+        assert!(database
+            .execute_sql(INSERT_NUMBERS, params![200_000])
+            .is_ok());
+
+        assert_eq!(0, database.slow_queries());
+        assert!(slow_queries.lock().unwrap().is_empty());
+    }
+
+    fn observed_tcn(byte: u8, contact_start: u64) -> ObservedTcn {
+        ObservedTcn {
+            tcn: TemporaryContactNumber([byte; 16]),
+            contact_start: UnixTime {
+                value: contact_start,
+            },
+            contact_end: UnixTime {
+                value: contact_start + 10,
+            },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        }
+    }
 }
@@ -1,8 +1,246 @@
-use super::database::Database;
-use crate::expect_log;
+use super::{
+    database::{column, Database},
+    preferences::{Preferences, PreferencesDao, PreferencesImpl},
+};
+use crate::{
+    errors::ServicesError,
+    reports_update::reports_updater::{report_id, SignedReportExt},
+    tcn_ext::tcn_hex::TcnHex,
+};
 use log::*;
-use rusqlite::params;
-use std::sync::Arc;
+use rusqlite::{params, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tcn::SignedReport;
+
+pub const REQUIRED_DB_VERSION: i32 = 28;
+
+// One stored window per TCN and contact start (see TcnDao::overwrite). Also the lookup of the stored
+// windows of TCNs, e.g. to merge a flushed batch. Not in SCHEMA: created by migration 27, after removing
+// the duplicates, as the earlier migrations run SCHEMA on tables that can have them.
+const TCN_WINDOW_INDEX: &str =
+    "create unique index if not exists tcn_tcn_contact_start on tcn(tcn, contact_start);";
+
+// The current schema. Created by migration 0 for new installs; existing tables are changed only by
+// the later migrations, so the DAOs can rely on the schema matching REQUIRED_DB_VERSION.
+const SCHEMA: &str = "
+    create table if not exists preferences(
+        key text primary key,
+        value text not null
+    );
+
+    -- TCNs are stored as their 16 bytes (hex text before migration 25)
+    create table if not exists tcn(
+        tcn blob not null,
+        contact_start integer not null,
+        contact_end integer not null,
+        min_distance real not null,
+        avg_distance real not null,
+        total_count integer not null,
+        matched integer not null default 0,
+        matched_report_id text,
+        distance_histogram text
+    );
+
+    create table if not exists alert(
+        id text primary key,
+        start integer not null,
+        end integer not null,
+        min_distance real not null,
+        avg_distance real not null,
+        report_id text not null,
+        read integer not null,
+        deleted integer,
+        report_coverage_start integer,
+        report_coverage_end integer,
+        core_version text,
+        app_version text,
+        snoozed_until integer,
+        acknowledgement_submitted integer,
+        p10_distance real,
+        median_distance real
+    );
+    -- Dismissing and expiring the alerts of a report
+    create index if not exists alert_report_id on alert(report_id);
+    -- The not deleted alerts, e.g. for the summary (unread count, last exposure)
+    create index if not exists alert_not_deleted on alert(read, end) where deleted is null;
+
+    -- Symptoms of the reports alerts were created from, stored once per report.
+    -- received_at is null for the reports of alerts stored before the table existed.
+    create table if not exists report(
+        -- alert_dao::MAX_ID_LENGTH
+        report_id text primary key check(length(report_id) <= 256),
+        report_time integer not null,
+        earliest_symptom_time integer,
+        fever_severity integer not null,
+        cough_severity integer not null,
+        breathlessness integer not null,
+        muscle_aches integer not null,
+        loss_smell_or_taste integer not null,
+        diarrhea integer not null,
+        runny_nose integer not null,
+        other integer not null,
+        no_symptoms integer not null,
+        memo_version integer not null default 1,
+        received_at integer,
+        -- ReportKind::as_u8
+        report_kind integer not null default 0,
+        -- Last time the symptoms were replaced by the ones of a refetched report (see
+        -- AlertDao::update_report_symptoms). Null if they weren't.
+        symptoms_updated_at integer
+    );
+
+    -- Base64 reports, for debugging
+    create table if not exists report_blob(
+        report_id text primary key,
+        report text not null
+    );
+
+    -- Exposures alerts were derived from, extended by later updates
+    create table if not exists exposure(
+        alert_id text primary key,
+        report_id text not null,
+        start integer not null,
+        end integer not null,
+        min_distance real not null,
+        avg_distance real not null,
+        total_count integer not null,
+        p10_distance real,
+        median_distance real,
+        -- JSON array of hex prefixes of the matched TCNs, if recorded (recording.debug_alert_tcn_prefixes)
+        tcn_prefixes text
+    );
+    create index if not exists exposure_report_id on exposure(report_id);
+
+    -- Reports the user dismissed: their exposures don't create alerts
+    create table if not exists dismissed_report(
+        report_id text primary key
+    );
+
+    -- Reports whose symptoms a later no symptoms report of the same reporter retracted
+    create table if not exists retracted_report(
+        report_id text primary key,
+        retracted_by text not null
+    );
+
+    create table if not exists update_run(
+        id integer primary key autoincrement,
+        time integer not null,
+        duration_millis integer not null,
+        intervals integer not null,
+        reports integer not null,
+        matches integer not null,
+        oversized_intervals integer not null default 0,
+        normalized_reports integer not null default 0,
+        expired_exposures integer not null default 0,
+        filtered_reports integer not null default 0,
+        unchanged_intervals integer not null default 0,
+        invalid_reports integer not null default 0,
+        error_code text
+    );
+
+    -- Observations of the in-memory TCN batch, appended as they're pushed (optional, see TcnBatchesManager).
+    -- Rows that survive a process death are replayed on bootstrap.
+    create table if not exists tcn_scratch(
+        id integer primary key autoincrement,
+        tcn blob not null,
+        contact_start integer not null,
+        contact_end integer not null,
+        min_distance real not null,
+        avg_distance real not null,
+        total_count integer not null,
+        distance_histogram text
+    );
+
+    -- Fetched reports, by the reports intervals they were seen in (see ReportsUpdater). Intervals are numbers
+    -- of interval_length. content_hash identifies what the reports of the last seen interval were matched with.
+    create table if not exists processed_report(
+        report_id text primary key,
+        first_seen_interval integer not null,
+        last_seen_interval integer not null,
+        interval_length integer not null,
+        content_hash text not null
+    );
+
+    -- Fetched reports intervals, as they were last processed (see ReportsUpdater). Only the latest are kept.
+    create table if not exists report_interval_state(
+        interval_number integer not null,
+        interval_length integer not null,
+        payload_hash text not null,
+        inputs_hash text not null,
+        primary key(interval_number, interval_length)
+    );
+
+    -- Ids of the reports stored before report ids were derived from the signature bytes (migration 23),
+    -- so the apps can still use the ids of the alerts they persisted
+    create table if not exists report_legacy_id(
+        legacy_id text primary key,
+        report_id text not null
+    );
+
+    -- Runs of the housekeeping tasks (see Housekeeper), like update_run for the reports updates
+    create table if not exists housekeeping_run(
+        id integer primary key autoincrement,
+        time integer not null,
+        task text not null,
+        duration_millis integer not null,
+        error_code text
+    );
+";
+
+// Columns of the current alert table, copied by migration 20
+const ALERT_COLUMNS: &str = "id, start, end, min_distance, avg_distance, report_id, read, deleted,
+    report_coverage_start, report_coverage_end, core_version, app_version, snoozed_until,
+    acknowledgement_submitted, p10_distance, median_distance";
+
+// Hex of the 64 signature bytes (see report_id)
+const REPORT_ID_LENGTH: usize = 128;
+
+// Columns referencing report ids, renamed by migration 23
+const REPORT_ID_COLUMNS: &[(&str, &str)] = &[
+    ("alert", "report_id"),
+    ("report", "report_id"),
+    ("report_blob", "report_id"),
+    ("exposure", "report_id"),
+    ("dismissed_report", "report_id"),
+    ("retracted_report", "report_id"),
+    ("retracted_report", "retracted_by"),
+    ("processed_report", "report_id"),
+    ("tcn", "matched_report_id"),
+];
+
+// Columns moved from the alert table to the report table by migration 20
+const REPORT_SYMPTOM_COLUMNS: &str = "report_time, earliest_symptom_time, fever_severity,
+    cough_severity, breathlessness, muscle_aches, loss_smell_or_taste, diarrhea, runny_nose, other,
+    no_symptoms, memo_version";
+
+// What run_db_migrations did (or would do, see preview_migrations), for support diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub from_version: i32,
+    // Version of the database after the run. If a step failed, its version.
+    pub to_version: i32,
+    pub steps: Vec<MigrationStepReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationStepReport {
+    pub version: i32, // Migrated from (see migrate_step)
+    pub duration_ms: u64,
+    // Rows inserted, updated or deleted. Schema changes aren't counted.
+    pub rows_affected: u64,
+    pub outcome: MigrationOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationOutcome {
+    Applied,
+    // The later steps weren't run
+    Failed,
+    // Not run (see preview_migrations)
+    Pending,
+}
 
 pub struct Migration {
     database: Arc<Database>,
@@ -13,65 +251,542 @@ impl Migration {
         Migration { database: database }
     }
 
-    pub fn run_db_migrations(&self, required_db_version: i32) {
+    // The version is stored after each step, so a failed step is retried on the next run.
+    // Returns what was migrated. The report of a run with steps, also a failed one, is persisted
+    // (see Preferences::last_migration_report).
+    pub fn run_db_migrations(
+        &self,
+        required_db_version: i32,
+    ) -> Result<MigrationReport, ServicesError> {
         let pragma_variable_name = "user_version";
-        let db_version_before_migration = self.database.core_pragma_query(pragma_variable_name);
+        let db_version_before_migration: i32 =
+            self.database.core_pragma_query(pragma_variable_name)?;
         debug!("Running conditional DB migrations. Current/Required DB version: {}/{}", db_version_before_migration, required_db_version);
 
-        let db_version_after_migration =
-            self.migrate_db(db_version_before_migration, required_db_version);
+        let mut report = MigrationReport {
+            from_version: db_version_before_migration,
+            to_version: db_version_before_migration,
+            steps: vec![],
+        };
+        if db_version_before_migration >= required_db_version {
+            warn!(
+                "DB version is greater than required: {} >= {}",
+                db_version_before_migration, required_db_version
+            );
+            return Ok(report);
+        }
+
+        let migration_res = self.migrate_steps(required_db_version, &mut report);
+        self.save_report(&report);
+        migration_res.map(|_| report)
+    }
 
-        if db_version_after_migration > db_version_before_migration {
+    // Dry run: the steps run_db_migrations would run (outcome pending). Doesn't change the database.
+    pub fn preview_migrations(
+        &self,
+        required_db_version: i32,
+    ) -> Result<MigrationReport, ServicesError> {
+        let db_version: i32 = self.database.core_pragma_query("user_version")?;
+        Ok(MigrationReport {
+            from_version: db_version,
+            to_version: db_version.max(required_db_version),
+            steps: (db_version..required_db_version)
+                .map(|version| MigrationStepReport {
+                    version,
+                    duration_ms: 0,
+                    rows_affected: 0,
+                    outcome: MigrationOutcome::Pending,
+                })
+                .collect(),
+        })
+    }
+
+    fn migrate_steps(
+        &self,
+        required_db_version: i32,
+        report: &mut MigrationReport,
+    ) -> Result<(), ServicesError> {
+        while report.to_version < required_db_version {
+            let db_version = report.to_version;
+            debug!("DB version is {}", db_version);
+            let changes_before = self.total_changes();
+            let start = Instant::now();
+            let step_res = self.migrate_step(db_version);
+            let mut step = MigrationStepReport {
+                version: db_version,
+                duration_ms: start.elapsed().as_millis() as u64,
+                rows_affected: self.total_changes().saturating_sub(changes_before),
+                outcome: MigrationOutcome::Applied,
+            };
+            if let Err(e) = step_res {
+                error!("Migration from DB version {} failed: {:?}", db_version, e);
+                step.outcome = MigrationOutcome::Failed;
+                report.steps.push(step);
+                return Err(ServicesError::Database(format!(
+                    "Migration from DB version {} failed: {}",
+                    db_version, e
+                )));
+            }
+            report.steps.push(step);
             self.database
-                .core_pragma_update(pragma_variable_name, &db_version_after_migration);
+                .core_pragma_update("user_version", &(db_version + 1))?;
+            report.to_version = db_version + 1;
         }
+        Ok(())
     }
 
-    fn migrate_db(&self, from_version: i32, to_version: i32) -> i32 {
-        if from_version >= to_version {
-            warn!(
-                "DB version is greater than required: {} >= {}",
-                from_version, to_version
-            );
-            return from_version;
+    // Rows changed by the connection since it was opened
+    fn total_changes(&self) -> u64 {
+        let changes_res = self
+            .database
+            .query_row("select total_changes()", NO_PARAMS, |row| {
+                row.get::<_, i64>(0)
+            });
+        match changes_res {
+            Ok(changes) => changes as u64,
+            Err(error) => {
+                warn!("Couldn't read total changes: {:?}", error);
+                0
+            }
         }
-        let mut db_version = from_version;
-        while db_version < to_version {
-            debug!("DB version is {}", db_version);
-            match db_version {
-                0 => {
-                    self.migration_0_drop_tcn_table();
-                    db_version += 1;
+    }
+
+    fn save_report(&self, report: &MigrationReport) {
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(self.database.clone()),
+        };
+        // Fails if the preferences table couldn't be created
+        if let Err(error) = preferences.set_last_migration_report(report.clone()) {
+            warn!("Couldn't save migration report: {:?}", error);
+        }
+    }
+
+    fn migrate_step(&self, from_version: i32) -> Result<(), ServicesError> {
+        match from_version {
+            0 => self.migration_0_create_schema(),
+            1 => self.migration_1_add_alert_memo_version(),
+            2 => self.migration_2_add_alert_report_coverage(),
+            3 => self.migration_3_add_alert_client_version(),
+            4 => self.migration_4_add_tcn_matched(),
+            5 => self.migration_5_add_update_run_oversized_intervals(),
+            6 => self.migration_6_add_update_run_normalized_reports(),
+            7 => self.migration_7_add_update_run_expired_exposures(),
+            8 => self.migration_8_create_missing_tables(),
+            9 => self.migration_9_create_tcn_scratch(),
+            10 => self.migration_10_add_alert_snoozed_until(),
+            11 => self.migration_11_create_processed_report(),
+            12 => self.migration_12_add_update_run_filtered_reports(),
+            13 => self.migration_13_create_housekeeping_run(),
+            14 => self.migration_14_add_alert_acknowledgement_submitted(),
+            15 => self.migration_15_add_distance_percentiles(),
+            16 => self.migration_16_create_report_interval_state(),
+            17 => self.migration_17_create_alert_indexes(),
+            18 => self.migration_18_add_update_run_invalid_reports(),
+            19 => self.migration_19_create_retracted_report(),
+            20 => self.migration_20_create_report(),
+            21 => self.migration_21_add_exposure_tcn_prefixes(),
+            22 => self.migration_22_add_report_kind(),
+            23 => self.migration_23_derive_report_ids_from_signature(),
+            24 => self.migration_24_add_report_symptoms_updated_at(),
+            25 => self.migration_25_store_tcns_as_blob(),
+            26 => self.migration_26_create_tcn_index(),
+            27 => self.migration_27_make_tcn_windows_unique(),
+            _ => Err(ServicesError::Database(format!(
+                "Migration from DB version {} not handled",
+                from_version
+            ))),
+        }
+    }
+
+    fn migration_0_create_schema(&self) -> Result<(), ServicesError> {
+        // The tcn table of versions before 0.4 is incompatible: the observations are dropped
+        warn!("Dropping tcn table");
+        self.database
+            .execute_sql("drop table if exists tcn;", params![])?;
+        self.create_schema()
+    }
+
+    fn migration_1_add_alert_memo_version(&self) -> Result<(), ServicesError> {
+        // Alerts stored before memo_version was added were all decoded from v1 memos
+        self.add_column_if_needed("alert", "memo_version", "integer not null default 1")
+    }
+
+    fn migration_2_add_alert_report_coverage(&self) -> Result<(), ServicesError> {
+        // Not known for stored alerts: left null
+        self.add_column_if_needed("alert", "report_coverage_start", "integer")?;
+        self.add_column_if_needed("alert", "report_coverage_end", "integer")
+    }
+
+    fn migration_3_add_alert_client_version(&self) -> Result<(), ServicesError> {
+        // Not known for stored alerts: left null
+        self.add_column_if_needed("alert", "core_version", "text")?;
+        self.add_column_if_needed("alert", "app_version", "text")
+    }
+
+    fn migration_4_add_tcn_matched(&self) -> Result<(), ServicesError> {
+        // Stored TCNs are flagged on their next match
+        self.add_column_if_needed("tcn", "matched", "integer not null default 0")?;
+        self.add_column_if_needed("tcn", "matched_report_id", "text")
+    }
+
+    fn migration_5_add_update_run_oversized_intervals(&self) -> Result<(), ServicesError> {
+        // Stored runs were recorded without a size budget
+        self.add_column_if_needed(
+            "update_run",
+            "oversized_intervals",
+            "integer not null default 0",
+        )
+    }
+
+    fn migration_6_add_update_run_normalized_reports(&self) -> Result<(), ServicesError> {
+        // Stored runs didn't normalize reports
+        self.add_column_if_needed(
+            "update_run",
+            "normalized_reports",
+            "integer not null default 0",
+        )
+    }
+
+    fn migration_7_add_update_run_expired_exposures(&self) -> Result<(), ServicesError> {
+        // Stored runs didn't filter exposures by age
+        self.add_column_if_needed(
+            "update_run",
+            "expired_exposures",
+            "integer not null default 0",
+        )
+    }
+
+    fn migration_8_create_missing_tables(&self) -> Result<(), ServicesError> {
+        // Tables used to be created by the DAOs: databases of versions that didn't have a table yet
+        // get it here, with the current schema.
+        self.create_schema()
+    }
+
+    fn migration_9_create_tcn_scratch(&self) -> Result<(), ServicesError> {
+        // Only creates the missing table
+        self.create_schema()
+    }
+
+    fn migration_10_add_alert_snoozed_until(&self) -> Result<(), ServicesError> {
+        // Stored alerts aren't snoozed
+        self.add_column_if_needed("alert", "snoozed_until", "integer")
+    }
+
+    fn migration_11_create_processed_report(&self) -> Result<(), ServicesError> {
+        // Only creates the missing table. Alerts of reports fetched before aren't expired.
+        self.create_schema()
+    }
+
+    fn migration_12_add_update_run_filtered_reports(&self) -> Result<(), ServicesError> {
+        // Stored runs didn't filter reports by signature
+        self.add_column_if_needed(
+            "update_run",
+            "filtered_reports",
+            "integer not null default 0",
+        )
+    }
+
+    fn migration_13_create_housekeeping_run(&self) -> Result<(), ServicesError> {
+        // Only creates the missing table
+        self.create_schema()
+    }
+
+    fn migration_14_add_alert_acknowledgement_submitted(&self) -> Result<(), ServicesError> {
+        // Stored alerts weren't acknowledged
+        self.add_column_if_needed("alert", "acknowledgement_submitted", "integer")
+    }
+
+    fn migration_15_add_distance_percentiles(&self) -> Result<(), ServicesError> {
+        // Not known for stored observations: left null, the readers fall back to the min / avg distance
+        self.add_column_if_needed("tcn", "distance_histogram", "text")?;
+        self.add_column_if_needed("tcn_scratch", "distance_histogram", "text")?;
+        self.add_column_if_needed("alert", "p10_distance", "real")?;
+        self.add_column_if_needed("alert", "median_distance", "real")?;
+        self.add_column_if_needed("exposure", "p10_distance", "real")?;
+        self.add_column_if_needed("exposure", "median_distance", "real")
+    }
+
+    fn migration_16_create_report_interval_state(&self) -> Result<(), ServicesError> {
+        // Stored runs didn't skip unchanged intervals. The intervals fetched before are matched once more.
+        self.add_column_if_needed(
+            "update_run",
+            "unchanged_intervals",
+            "integer not null default 0",
+        )?;
+        self.create_schema()
+    }
+
+    fn migration_17_create_alert_indexes(&self) -> Result<(), ServicesError> {
+        // Only creates the missing indexes
+        self.create_schema()
+    }
+
+    fn migration_18_add_update_run_invalid_reports(&self) -> Result<(), ServicesError> {
+        // Stored runs didn't count the reports that couldn't be verified
+        self.add_column_if_needed(
+            "update_run",
+            "invalid_reports",
+            "integer not null default 0",
+        )
+    }
+
+    fn migration_19_create_retracted_report(&self) -> Result<(), ServicesError> {
+        // Only creates the missing table. Stored alerts aren't retracted.
+        self.create_schema()
+    }
+
+    fn migration_20_create_report(&self) -> Result<(), ServicesError> {
+        // The symptoms of the stored alerts are moved to the report table, once per report (the alerts of a
+        // report have the same symptoms). The alert table is recreated without them: sqlite can't drop
+        // columns. Alert tables created with the current schema only got memo_version (migration 1).
+        let columns = self.table_columns("alert")?;
+        if !columns.iter().any(|c| c == "memo_version") {
+            return self.create_schema();
+        }
+        let has_symptoms = columns.iter().any(|c| c == "report_time");
+        self.database.transaction(|t| {
+            // The indexes are recreated with the new table
+            t.execute_batch(
+                "drop index if exists alert_report_id;
+                drop index if exists alert_not_deleted;
+                alter table alert rename to alert_legacy;",
+            )?;
+            t.execute_batch(SCHEMA)?;
+            if has_symptoms {
+                t.execute(
+                    &format!(
+                        "insert or ignore into report(report_id, {0}) select report_id, {0} from alert_legacy
+                        order by rowid",
+                        REPORT_SYMPTOM_COLUMNS
+                    ),
+                    NO_PARAMS,
+                )?;
+            }
+            t.execute(
+                &format!(
+                    "insert into alert({0}) select {0} from alert_legacy",
+                    ALERT_COLUMNS
+                ),
+                NO_PARAMS,
+            )?;
+            t.execute_batch("drop table alert_legacy;")?;
+            Ok(())
+        })
+    }
+
+    fn migration_21_add_exposure_tcn_prefixes(&self) -> Result<(), ServicesError> {
+        // Not recorded for stored exposures: left null
+        self.add_column_if_needed("exposure", "tcn_prefixes", "text")
+    }
+
+    fn migration_22_add_report_kind(&self) -> Result<(), ServicesError> {
+        // Stored reports were all decoded from symptom memos (ReportKind::Symptoms)
+        self.add_column_if_needed("report", "report_kind", "integer not null default 0")
+    }
+
+    fn migration_23_derive_report_ids_from_signature(&self) -> Result<(), ServicesError> {
+        // Report ids were the Debug format of the signature, which depends on the tcn version. The current id
+        // is the prefix of the ids of the report's alerts, or derived from the raw report. Reports without
+        // either (not matched) keep their legacy id, and are processed again when they're fetched.
+        self.create_schema()?;
+        let mut renamed: HashMap<String, String> = HashMap::new();
+        let alert_ids = self.database.query(
+            "select report_id, id from alert union select report_id, alert_id from exposure",
+            NO_PARAMS,
+            |row| Ok((column(row, 0, "report id")?, column(row, 1, "alert id")?)),
+        )?;
+        for (legacy_id, alert_id) in alert_ids.rows {
+            let alert_id: String = alert_id;
+            match alert_id.split('-').next() {
+                Some(id) if id.len() == REPORT_ID_LENGTH && hex::decode(id).is_ok() => {
+                    renamed.insert(legacy_id, id.to_owned());
                 }
-                _ => {
-                    warn!("Migration from DB version {} not handled!", db_version);
-                    break;
+                _ => warn!("Unexpected alert id: {}", alert_id),
+            }
+        }
+        let raw_reports = self.database.query(
+            "select report_id, report from report_blob",
+            NO_PARAMS,
+            |row| Ok((column(row, 0, "report id")?, column(row, 1, "report")?)),
+        )?;
+        for (legacy_id, report_str) in raw_reports.rows {
+            let report_str: String = report_str;
+            match SignedReport::with_str(&report_str) {
+                Some(report) => {
+                    renamed.insert(legacy_id, report_id(&report));
                 }
+                None => warn!("Couldn't derive the id of stored report: {}", legacy_id),
             }
         }
+        renamed.retain(|legacy_id, id| legacy_id != id);
+        debug!("Deriving the ids of {} stored reports", renamed.len());
 
-        db_version
+        self.database.transaction(|t| {
+            for (legacy_id, id) in &renamed {
+                for (table, id_column) in REPORT_ID_COLUMNS {
+                    t.execute(
+                        &format!(
+                            "update or ignore {0} set {1}=?2 where {1}=?1",
+                            table, id_column
+                        ),
+                        params![legacy_id, id],
+                    )?;
+                }
+                t.execute(
+                    "insert or replace into report_legacy_id(legacy_id, report_id) values(?1, ?2)",
+                    params![legacy_id, id],
+                )?;
+            }
+            Ok(())
+        })
     }
 
-    fn migration_0_drop_tcn_table(&self) {
-        warn!("Dropping tcn table");
-        let exec_res = self
-            .database
-            .execute_sql("drop table if exists tcn;", params![]);
-        expect_log!(exec_res, "Dropping tcn table failed!");
+    fn migration_24_add_report_symptoms_updated_at(&self) -> Result<(), ServicesError> {
+        // The symptoms of stored reports weren't updated: left null
+        self.add_column_if_needed("report", "symptoms_updated_at", "integer")
+    }
+
+    fn migration_25_store_tcns_as_blob(&self) -> Result<(), ServicesError> {
+        // The TCN columns were hex text. The tables are created again with the current schema, and
+        // their rows copied with the TCNs decoded. Rows with an invalid TCN weren't loaded: dropped.
+        for table in &["tcn", "tcn_scratch"] {
+            self.store_tcns_as_blob(table)?;
+        }
+        Ok(())
+    }
+
+    fn migration_26_create_tcn_index(&self) -> Result<(), ServicesError> {
+        // Only created the missing tcn_tcn index. Replaced by the index of migration 27: SCHEMA doesn't
+        // have it anymore.
+        self.create_schema()
+    }
+
+    fn migration_27_make_tcn_windows_unique(&self) -> Result<(), ServicesError> {
+        self.database.transaction(|t| {
+            // Written by concurrent flushes. Keeps the row with the most samples.
+            let deleted = t.execute(
+                "delete from tcn where rowid not in (
+                    select rowid from (
+                        select rowid, max(total_count) from tcn group by tcn, contact_start
+                    )
+                )",
+                NO_PARAMS,
+            )?;
+            if deleted > 0 {
+                warn!("Deleted {} duplicate TCN windows", deleted);
+            }
+            t.execute_batch(&format!(
+                "drop index if exists tcn_tcn; {}",
+                TCN_WINDOW_INDEX
+            ))?;
+            Ok(())
+        })
+    }
+
+    // Skipped if the table doesn't exist (see add_column_if_needed) or was created with the blob column
+    fn store_tcns_as_blob(&self, table: &str) -> Result<(), ServicesError> {
+        let tcn_types = self.database.query(
+            "select type from pragma_table_info(?1) where name = 'tcn'",
+            params![table],
+            |row| column(row, 0, "column type"),
+        )?;
+        let tcn_type: Option<&String> = tcn_types.rows.first();
+        if tcn_type.map_or(true, |tcn_type| tcn_type.eq_ignore_ascii_case("blob")) {
+            return Ok(());
+        }
+        let columns = self.table_columns(table)?.join(", ");
+        debug!("Storing the TCNs of table {} as blob", table);
+
+        self.database.transaction(|t| {
+            t.execute_batch(&format!(
+                "alter table {0} rename to {0}_hex;
+                {1}
+                insert into {0}({2}) select {2} from {0}_hex;
+                drop table {0}_hex;",
+                table, SCHEMA, columns
+            ))?;
+            let hex_tcns: Vec<(i64, String)> = t
+                .prepare(&format!(
+                    "select rowid, tcn from {} where typeof(tcn) = 'text'",
+                    table
+                ))?
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            for (rowid, hex_tcn) in hex_tcns {
+                match TcnHex::new(&hex_tcn) {
+                    Ok(tcn_hex) => t.execute(
+                        &format!("update {} set tcn = ?2 where rowid = ?1", table),
+                        params![rowid, tcn_hex.to_tcn().0.to_vec()],
+                    )?,
+                    Err(_) => {
+                        warn!("Dropping invalid stored TCN: {}", hex_tcn);
+                        t.execute(
+                            &format!("delete from {} where rowid = ?1", table),
+                            params![rowid],
+                        )?
+                    }
+                };
+            }
+            Ok(())
+        })
+    }
+
+    fn create_schema(&self) -> Result<(), ServicesError> {
+        self.database
+            .execute_batch(SCHEMA)
+            .map_err(ServicesError::from)
+    }
+
+    // Tables that don't exist yet are skipped: migration 8 creates them with the current schema.
+    fn add_column_if_needed(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<(), ServicesError> {
+        let columns = self.table_columns(table)?;
+        if columns.is_empty() || columns.iter().any(|c| c == column) {
+            return Ok(());
+        }
+        debug!("Adding column {} to table {}", column, table);
+        self.database.execute_sql(
+            &format!(
+                "alter table {} add column {} {};",
+                table, column, definition
+            ),
+            params![],
+        )?;
+        Ok(())
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<String>, ServicesError> {
+        let columns = self.database.query(
+            "select name from pragma_table_info(?)",
+            params![table],
+            |row| column(row, 0, "column name"),
+        )?;
+        Ok(columns.rows)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::tcn_dao::TcnDaoImpl;
+    use crate::database::{
+        alert_dao::{AlertDao, AlertDaoImpl},
+        tcn_dao::{TcnDao, TcnDaoImpl},
+        update_run_dao::{UpdateRunDao, UpdateRunDaoImpl},
+    };
+    use crate::expect_log;
+    use crate::reporting::public_symptoms::ReportKind;
     use crate::reports_interval::UnixTime;
     use crate::reports_update::exposure::ExposureGrouper;
     use crate::simple_logger;
+    use crate::tcn_recording::distance_histogram::DistanceHistogram;
     use crate::tcn_recording::observed_tcn_processor::ObservedTcn;
     use crate::tcn_recording::tcn_batches_manager::TcnBatchesManager;
-    use rusqlite::{params, Connection, Row};
+    use crate::test_support::fixtures::VERIFICATION_REPORT;
+    use rusqlite::{params, Connection, Row, NO_PARAMS};
     use tcn::TemporaryContactNumber;
 
     #[test]
@@ -80,23 +795,32 @@ mod tests {
             Connection::open_in_memory().expect("Couldn't create database!"),
         ));
         let pragma_variable_name = "user_version";
-        let db_version: i32 = database.core_pragma_query(pragma_variable_name);
+        let db_version: i32 = database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(0, db_version);
         let target_db_version = 17;
-        database.core_pragma_update(pragma_variable_name, &target_db_version);
-        let db_version_before_migration: i32 = database.core_pragma_query(pragma_variable_name);
+        assert!(database
+            .core_pragma_update(pragma_variable_name, &target_db_version)
+            .is_ok());
+        let db_version_before_migration: i32 =
+            database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(target_db_version, db_version_before_migration);
 
         let migration_handler = Migration::new(database.clone());
         //migrate to same version
-        migration_handler.run_db_migrations(target_db_version);
+        assert!(migration_handler
+            .run_db_migrations(target_db_version)
+            .is_ok());
 
-        let db_version_after_migration: i32 = database.core_pragma_query(pragma_variable_name);
+        let db_version_after_migration: i32 =
+            database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(target_db_version, db_version_after_migration);
 
         //migrate to lower version
-        migration_handler.run_db_migrations(target_db_version - 1);
-        let db_version_after_migration: i32 = database.core_pragma_query(pragma_variable_name);
+        assert!(migration_handler
+            .run_db_migrations(target_db_version - 1)
+            .is_ok());
+        let db_version_after_migration: i32 =
+            database.core_pragma_query(pragma_variable_name).unwrap();
         assert_eq!(target_db_version, db_version_after_migration);
     }
 
@@ -112,7 +836,7 @@ mod tests {
 
         //migrate DB
         let migration_handler = Migration::new(database.clone());
-        migration_handler.run_db_migrations(3);
+        assert!(migration_handler.run_db_migrations(3).is_ok());
         core_table_info(table_name, database.clone());
 
         //verify flushing works
@@ -128,6 +852,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 0.506, // (0.1 + 0.62 + 0.8 + 0.21 + 0.8) / 5
             total_count: 5,
+            distance_histogram: DistanceHistogram::default(),
         });
 
         batches_manager.push(ObservedTcn {
@@ -137,6 +862,7 @@ mod tests {
             min_distance: 2.0,
             avg_distance: 0.7, // (1.2 + 0.5 + 0.4) / 3
             total_count: 3,
+            distance_histogram: DistanceHistogram::default(),
         });
 
         let len_res = batches_manager.len();
@@ -166,6 +892,967 @@ mod tests {
         assert_eq!(6, table_columns_after_migration.len());
     }
 
+    #[test]
+    fn test_migration_adds_alert_memo_version() {
+        simple_logger::setup();
+        let table_name = "alert";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+        assert_eq!(19, core_table_info(table_name, database.clone()).len());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler.run_db_migrations(2).is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert_eq!(20, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"memo_version".to_owned()));
+
+        let memo_version_res = database.query_row(
+            "select memo_version from alert where id='1'",
+            params![],
+            |row| row.get(0),
+        );
+        let memo_version: i64 = expect_log!(memo_version_res, "Couldn't read memo_version");
+        assert_eq!(1, memo_version);
+    }
+
+    #[test]
+    fn test_migration_adds_alert_report_coverage() {
+        simple_logger::setup();
+        let table_name = "alert";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler.run_db_migrations(3).is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert_eq!(22, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"report_coverage_start".to_owned()));
+        assert!(table_columns_after_migration.contains(&"report_coverage_end".to_owned()));
+
+        let coverage_res = database.query_row(
+            "select report_coverage_start, report_coverage_end from alert where id='1'",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        let coverage: (Option<i64>, Option<i64>) =
+            expect_log!(coverage_res, "Couldn't read report coverage");
+        assert_eq!((None, None), coverage);
+    }
+
+    #[test]
+    fn test_migration_adds_alert_client_version() {
+        simple_logger::setup();
+        let table_name = "alert";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler.run_db_migrations(4).is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert_eq!(24, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"core_version".to_owned()));
+        assert!(table_columns_after_migration.contains(&"app_version".to_owned()));
+
+        let versions_res = database.query_row(
+            "select core_version, app_version from alert where id='1'",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        let versions: (Option<String>, Option<String>) =
+            expect_log!(versions_res, "Couldn't read client version");
+        assert_eq!((None, None), versions);
+    }
+
+    #[test]
+    fn test_migration_adds_alert_snoozed_until() {
+        simple_logger::setup();
+        let table_name = "alert";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler.run_db_migrations(11).is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert_eq!(25, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"snoozed_until".to_owned()));
+
+        let snoozed_until_res = database.query_row(
+            "select snoozed_until from alert where id='1'",
+            params![],
+            |row| row.get(0),
+        );
+        let snoozed_until: Option<i64> =
+            expect_log!(snoozed_until_res, "Couldn't read snoozed_until");
+        assert_eq!(None, snoozed_until);
+    }
+
+    #[test]
+    fn test_migration_adds_alert_acknowledgement_submitted() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let table_columns_after_migration = core_table_info("alert", database.clone());
+        assert_eq!(16, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"acknowledgement_submitted".to_owned()));
+
+        let submitted_res = database.query_row(
+            "select acknowledgement_submitted from alert where id='1'",
+            params![],
+            |row| row.get(0),
+        );
+        let submitted: Option<i64> = expect_log!(submitted_res, "Couldn't read acknowledgement");
+        assert_eq!(None, submitted);
+    }
+
+    #[test]
+    fn test_migration_adds_distance_percentiles() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let alert_columns = core_table_info("alert", database.clone());
+        assert!(alert_columns.contains(&"p10_distance".to_owned()));
+        assert!(alert_columns.contains(&"median_distance".to_owned()));
+        let exposure_columns = core_table_info("exposure", database.clone());
+        assert!(exposure_columns.contains(&"p10_distance".to_owned()));
+        assert!(exposure_columns.contains(&"median_distance".to_owned()));
+        assert!(core_table_info("tcn", database.clone()).contains(&"distance_histogram".to_owned()));
+        assert!(core_table_info("tcn_scratch", database.clone())
+            .contains(&"distance_histogram".to_owned()));
+
+        // Stored alerts fall back to the min / avg distance
+        let alerts_res = AlertDaoImpl::new(database).all();
+        let alerts = expect_log!(alerts_res, "Couldn't load alerts");
+        assert_eq!(1, alerts.len());
+        assert_eq!(2.3, alerts[0].p10_distance);
+        assert_eq!(4.3, alerts[0].median_distance);
+    }
+
+    #[test]
+    fn test_migration_creates_alert_indexes() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let indexes_res = database.query(
+            "select name from sqlite_master where type='index' and tbl_name='alert'
+            and name not like 'sqlite_autoindex%' order by name",
+            NO_PARAMS,
+            |row| column(row, 0, "name"),
+        );
+        let indexes = expect_log!(indexes_res, "Couldn't read alert indexes");
+        assert_eq!(vec!["alert_not_deleted", "alert_report_id"], indexes.rows);
+    }
+
+    #[test]
+    fn test_migration_creates_tcn_index() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &4).is_ok());
+        let res = database.execute_batch(
+            "CREATE TABLE tcn(
+                tcn text not null,
+                contact_start integer not null,
+                contact_end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null
+            );",
+        );
+        expect_log!(res, "Couldn't create tcn table for db version 4");
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let indexes_res = database.query(
+            "select name from sqlite_master where type='index' and tbl_name='tcn'",
+            NO_PARAMS,
+            |row| column(row, 0, "name"),
+        );
+        let indexes = expect_log!(indexes_res, "Couldn't read tcn indexes");
+        assert_eq!(vec!["tcn_tcn_contact_start"], indexes.rows);
+    }
+
+    #[test]
+    fn test_migration_removes_duplicate_tcn_windows() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &27).is_ok());
+        // Windows of TCN 1 at 1000 and 5000 written twice, a window of TCN 2 once
+        let res = database.execute_batch(
+            "CREATE TABLE tcn(
+                tcn blob not null,
+                contact_start integer not null,
+                contact_end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null
+            );
+            CREATE INDEX tcn_tcn ON tcn(tcn);
+            INSERT INTO tcn VALUES(X'01010101010101010101010101010101', 1000, 2000, 1.0, 1.0, 2);
+            INSERT INTO tcn VALUES(X'01010101010101010101010101010101', 1000, 3000, 0.5, 1.0, 3);
+            INSERT INTO tcn VALUES(X'01010101010101010101010101010101', 5000, 6000, 1.0, 1.0, 2);
+            INSERT INTO tcn VALUES(X'01010101010101010101010101010101', 5000, 5000, 1.0, 1.0, 1);
+            INSERT INTO tcn VALUES(X'02020202020202020202020202020202', 1000, 2000, 1.0, 1.0, 2);",
+        );
+        expect_log!(res, "Couldn't create tcn table for db version 27");
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let windows_res = database.query(
+            "select contact_start, contact_end, total_count from tcn order by tcn, contact_start",
+            NO_PARAMS,
+            |row| {
+                Ok((
+                    column::<i64>(row, 0, "contact_start")?,
+                    column::<i64>(row, 1, "contact_end")?,
+                    column::<i64>(row, 2, "total_count")?,
+                ))
+            },
+        );
+        let windows = expect_log!(windows_res, "Couldn't read tcn windows");
+        assert_eq!(
+            vec![(1000, 3000, 3), (5000, 6000, 2), (1000, 2000, 2)],
+            windows.rows
+        );
+
+        let indexes_res = database.query(
+            "select name from sqlite_master where type='index' and tbl_name='tcn'",
+            NO_PARAMS,
+            |row| column(row, 0, "name"),
+        );
+        let indexes = expect_log!(indexes_res, "Couldn't read tcn indexes");
+        assert_eq!(vec!["tcn_tcn_contact_start"], indexes.rows);
+
+        let insert_res = database.execute_sql(
+            "insert into tcn values(X'01010101010101010101010101010101', 1000, 4000, 1.0, 1.0, 4)",
+            NO_PARAMS,
+        );
+        assert!(insert_res.is_err());
+    }
+
+    #[test]
+    fn test_migration_moves_alert_symptoms_to_report() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+        // A second alert of the report, and an alert of another report
+        let insert_res = database.execute_batch(
+            "INSERT INTO alert VALUES('2', 3000, 4000, 1.5, 2.5, 0, NULL, 1, 2, 1, 1, 0, 0, 1, 0, 1, '1', 1, NULL);
+            INSERT INTO alert VALUES('3', 1000, 2000, 2.3, 4.3, 500, 400, 2, 3, 0, 0, 1, 1, 0, 1, 0, '2', 0, 1);",
+        );
+        expect_log!(insert_res, "Couldn't insert alerts");
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler.run_db_migrations(20).is_ok());
+        assert_eq!(28, core_table_info("alert", database.clone()).len());
+
+        let migration_res = migration_handler.run_db_migrations(21);
+        assert!(migration_res.is_ok());
+        let report = migration_res.unwrap();
+        assert_eq!(21, report.to_version);
+        // 2 reports and 3 alerts inserted
+        assert_eq!(1, report.steps.len());
+        assert_eq!(20, report.steps[0].version);
+        assert_eq!(5, report.steps[0].rows_affected);
+        assert_eq!(MigrationOutcome::Applied, report.steps[0].outcome);
+
+        let alert_columns = core_table_info("alert", database.clone());
+        assert_eq!(16, alert_columns.len());
+        assert!(!alert_columns.contains(&"fever_severity".to_owned()));
+        assert!(!alert_columns.contains(&"memo_version".to_owned()));
+
+        let reports_res = database.query(
+            "select report_id, report_time, earliest_symptom_time, fever_severity, memo_version,
+            received_at from report order by report_id",
+            NO_PARAMS,
+            |row| {
+                let report_id: String = column(row, 0, "report_id")?;
+                let report_time: i64 = column(row, 1, "report_time")?;
+                let earliest_symptom_time: Option<i64> = column(row, 2, "earliest_symptom_time")?;
+                let fever_severity: i64 = column(row, 3, "fever_severity")?;
+                let memo_version: i64 = column(row, 4, "memo_version")?;
+                let received_at: Option<i64> = column(row, 5, "received_at")?;
+                Ok((
+                    report_id,
+                    report_time,
+                    earliest_symptom_time,
+                    fever_severity,
+                    memo_version,
+                    received_at,
+                ))
+            },
+        );
+        let reports = expect_log!(reports_res, "Couldn't read reports");
+        assert_eq!(
+            vec![
+                ("1".to_owned(), 0, None, 1, 1, None),
+                ("2".to_owned(), 500, Some(400), 2, 1, None)
+            ],
+            reports.rows
+        );
+
+        // The alerts are loaded with the symptoms of their report. Deleted alerts are kept.
+        let deleted_res = database.query_row(
+            "select count(*) from alert where deleted is not null",
+            NO_PARAMS,
+            |row| row.get(0),
+        );
+        let deleted: i64 = expect_log!(deleted_res, "Couldn't count deleted alerts");
+        assert_eq!(1, deleted);
+        // The alert DAO expects the current schema
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+        let alerts_res = AlertDaoImpl::new(database.clone()).all();
+        let mut alerts = expect_log!(alerts_res, "Couldn't load alerts");
+        alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+        assert_eq!(2, alerts.len());
+        assert_eq!(alerts[0].symptoms, alerts[1].symptoms);
+        assert!(alerts[1].is_read);
+        assert_eq!(3000, alerts[1].contact_start);
+        assert!(alerts[0].symptoms.breathlessness);
+        assert!(alerts[0].symptoms.no_symptoms);
+        assert!(!alerts[0].symptoms.loss_smell_or_taste);
+
+        // The indexes are recreated
+        let indexes_res = database.query(
+            "select name from sqlite_master where type='index' and tbl_name='alert'
+            and name not like 'sqlite_autoindex%' order by name",
+            NO_PARAMS,
+            |row| column(row, 0, "name"),
+        );
+        let indexes = expect_log!(indexes_res, "Couldn't read alert indexes");
+        assert_eq!(vec!["alert_not_deleted", "alert_report_id"], indexes.rows);
+    }
+
+    #[test]
+    fn test_migration_adds_exposure_tcn_prefixes() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &21).is_ok());
+        let create_res = database.execute_batch(
+            "CREATE TABLE exposure(
+                alert_id text primary key,
+                report_id text not null,
+                start integer not null,
+                end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null,
+                p10_distance real,
+                median_distance real
+            );
+            INSERT INTO exposure VALUES('1', 'report', 1000, 2000, 0.5, 1.5, 3, 0.9, 1.4);",
+        );
+        expect_log!(create_res, "Couldn't create exposure table");
+
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+
+        assert!(core_table_info("exposure", database.clone()).contains(&"tcn_prefixes".to_owned()));
+
+        // Stored exposures have no prefixes
+        let exposures_res = AlertDaoImpl::new(database).exposures("report");
+        let exposures = expect_log!(exposures_res, "Couldn't load exposures");
+        assert_eq!(1, exposures.len());
+        assert!(exposures[0].tcn_prefixes.is_empty());
+    }
+
+    #[test]
+    fn test_migration_adds_report_kind() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &22).is_ok());
+        let create_res = database.execute_batch(
+            "CREATE TABLE report(
+                report_id text primary key,
+                report_time integer not null,
+                earliest_symptom_time integer,
+                fever_severity integer not null,
+                cough_severity integer not null,
+                breathlessness integer not null,
+                muscle_aches integer not null,
+                loss_smell_or_taste integer not null,
+                diarrhea integer not null,
+                runny_nose integer not null,
+                other integer not null,
+                no_symptoms integer not null,
+                memo_version integer not null default 1,
+                received_at integer
+            );
+            INSERT INTO report VALUES('1', 1000, NULL, 1, 2, 1, 0, 0, 0, 0, 0, 0, 1, 2000);",
+        );
+        expect_log!(create_res, "Couldn't create report table");
+
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+
+        assert!(core_table_info("report", database.clone()).contains(&"report_kind".to_owned()));
+        // Migration 24
+        assert!(
+            core_table_info("report", database.clone()).contains(&"symptoms_updated_at".to_owned())
+        );
+
+        // Stored reports are symptom reports
+        let report_kind_res = database.query_row(
+            "select report_kind from report where report_id='1'",
+            params![],
+            |row| row.get(0),
+        );
+        let report_kind: i64 = expect_log!(report_kind_res, "Couldn't read report_kind");
+        assert_eq!(ReportKind::Symptoms.as_u8() as i64, report_kind);
+    }
+
+    #[test]
+    fn test_migration_derives_report_ids_from_signature() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(Migration::new(database.clone())
+            .run_db_migrations(23)
+            .is_ok());
+
+        // Report a has alerts, report b only a raw report, report c was fetched but not matched
+        let id_a = "ab".repeat(64);
+        let id_b = report_id(&SignedReport::with_str(VERIFICATION_REPORT).unwrap());
+        let create_res = database.execute_batch(&format!(
+            "INSERT INTO alert(id, start, end, min_distance, avg_distance, report_id, read)
+                VALUES('{0}-1000', 1000, 1300, 1.0, 1.5, 'Signature(a)', 1);
+            INSERT INTO exposure(alert_id, report_id, start, end, min_distance, avg_distance, total_count)
+                VALUES('{0}-1000', 'Signature(a)', 1000, 1300, 1.0, 1.5, 2);
+            INSERT INTO report(report_id, report_time, fever_severity, cough_severity, breathlessness,
+                muscle_aches, loss_smell_or_taste, diarrhea, runny_nose, other, no_symptoms)
+                VALUES('Signature(a)', 900, 0, 0, 1, 0, 0, 0, 0, 0, 0);
+            INSERT INTO tcn(tcn, contact_start, contact_end, min_distance, avg_distance, total_count, matched,
+                matched_report_id) VALUES('{2}', 1000, 1300, 1.0, 1.5, 2, 1, 'Signature(a)');
+            INSERT INTO retracted_report(report_id, retracted_by) VALUES('Signature(a)', 'Signature(b)');
+            INSERT INTO report_blob(report_id, report) VALUES('Signature(b)', '{1}');
+            INSERT INTO dismissed_report(report_id) VALUES('Signature(b)');
+            INSERT INTO processed_report VALUES('Signature(c)', 1, 1, 21600, 'hash');",
+            id_a,
+            VERIFICATION_REPORT,
+            "12".repeat(16)
+        ));
+        expect_log!(create_res, "Couldn't insert legacy reports");
+
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+
+        let ids = |sql: &str| -> Vec<String> {
+            let res = database.query(sql, NO_PARAMS, |row| column(row, 0, "id"));
+            expect_log!(res, "Couldn't read ids").rows
+        };
+        assert_eq!(vec![id_a.clone()], ids("select report_id from alert"));
+        assert_eq!(vec![id_a.clone()], ids("select report_id from exposure"));
+        assert_eq!(vec![id_a.clone()], ids("select report_id from report"));
+        assert_eq!(vec![id_a.clone()], ids("select matched_report_id from tcn"));
+        assert_eq!(vec![format!("{}-1000", id_a)], ids("select id from alert"));
+        assert_eq!(
+            vec![id_a.clone()],
+            ids("select report_id from retracted_report")
+        );
+        assert_eq!(
+            vec![id_b.clone()],
+            ids("select retracted_by from retracted_report")
+        );
+        assert_eq!(vec![id_b.clone()], ids("select report_id from report_blob"));
+        assert_eq!(
+            vec![id_b.clone()],
+            ids("select report_id from dismissed_report")
+        );
+        // Can't be derived
+        assert_eq!(
+            vec!["Signature(c)".to_owned()],
+            ids("select report_id from processed_report")
+        );
+
+        // The apps can still use the legacy ids
+        let alert_dao = AlertDaoImpl::new(database.clone());
+        assert_eq!(
+            VERIFICATION_REPORT,
+            alert_dao.raw_report("Signature(b)".to_owned()).unwrap()
+        );
+        assert_eq!(
+            1,
+            alert_dao
+                .delete_by_report("Signature(a)".to_owned())
+                .unwrap()
+        );
+        assert!(alert_dao.is_report_dismissed(&id_a).unwrap());
+    }
+
+    #[test]
+    fn test_migration_creates_processed_report() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &11).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        let migration_res = migration_handler.run_db_migrations(12);
+        assert!(migration_res.is_ok());
+        assert_eq!(12, migration_res.unwrap().to_version);
+
+        assert_eq!(
+            5,
+            core_table_info("processed_report", database.clone()).len()
+        );
+        // Existing tables aren't changed
+        assert_eq!(19, core_table_info("alert", database).len());
+    }
+
+    #[test]
+    fn test_migration_adds_tcn_matched() {
+        simple_logger::setup();
+        let table_name = "tcn";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &4).is_ok());
+        let res = database.execute_batch(
+            "CREATE TABLE tcn(
+                tcn text not null,
+                contact_start integer not null,
+                contact_end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null
+            );
+            INSERT INTO tcn VALUES('00000000000000000000000000000000', 1000, 2000, 2.3, 2.3, 1);",
+        );
+        expect_log!(res, "Couldn't create tcn table for db version 4");
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert_eq!(9, table_columns_after_migration.len());
+        assert!(table_columns_after_migration.contains(&"matched".to_owned()));
+        assert!(table_columns_after_migration.contains(&"matched_report_id".to_owned()));
+
+        let matched_res = database.query_row(
+            "select matched, matched_report_id from tcn",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        let matched: (bool, Option<String>) = expect_log!(matched_res, "Couldn't read matched");
+        assert_eq!((false, None), matched);
+    }
+
+    #[test]
+    fn test_migration_stores_tcns_as_blob() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &25).is_ok());
+        let res = database.execute_batch(&format!(
+            "CREATE TABLE tcn(
+                tcn text not null,
+                contact_start integer not null,
+                contact_end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null,
+                matched integer not null default 0,
+                matched_report_id text,
+                distance_histogram text
+            );
+            CREATE TABLE tcn_scratch(
+                id integer primary key autoincrement,
+                tcn text not null,
+                contact_start integer not null,
+                contact_end integer not null,
+                min_distance real not null,
+                avg_distance real not null,
+                total_count integer not null,
+                distance_histogram text
+            );
+            INSERT INTO tcn VALUES('{0}', 1000, 2000, 2.3, 2.5, 2, 1, 'report1', NULL);
+            INSERT INTO tcn VALUES('{1}', 1000, 2000, 2.3, 2.5, 1, 0, NULL, NULL);
+            INSERT INTO tcn VALUES('not-hex', 1000, 2000, 2.3, 2.5, 1, 0, NULL, NULL);
+            INSERT INTO tcn_scratch VALUES(3, '{1}', 2000, 2100, 1.5, 1.5, 1, NULL);",
+            "12".repeat(16),
+            "ab".repeat(16)
+        ));
+        expect_log!(res, "Couldn't create tcn tables for db version 25");
+
+        let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+
+        for table in &["tcn", "tcn_scratch"] {
+            let type_res = database.query_row(
+                "select type from pragma_table_info(?1) where name = 'tcn'",
+                params![table],
+                |row| row.get::<_, String>(0),
+            );
+            assert_eq!("blob", type_res.unwrap());
+        }
+        assert_eq!(9, core_table_info("tcn", database.clone()).len());
+
+        let tcn_dao = TcnDaoImpl::new(database.clone());
+        let tcn = |byte: u8, contact_start: u64, total_count: usize| ObservedTcn {
+            tcn: TemporaryContactNumber([byte; 16]),
+            contact_start: UnixTime {
+                value: contact_start,
+            },
+            contact_end: UnixTime {
+                value: contact_start + 1000,
+            },
+            min_distance: 2.3,
+            avg_distance: 2.5,
+            total_count,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let mut tcns = tcn_dao.all().unwrap();
+        tcns.sort_by_key(|observed_tcn| observed_tcn.tcn.0);
+        // The invalid TCN is dropped
+        assert_eq!(vec![tcn(0x12, 1000, 2), tcn(0xab, 1000, 1)], tcns);
+        assert_eq!(
+            vec!["report1".to_owned()],
+            tcn_dao
+                .matched_report_ids(vec![TemporaryContactNumber([0x12; 16])])
+                .unwrap()
+        );
+
+        assert_eq!(
+            vec![ObservedTcn {
+                contact_end: UnixTime { value: 2100 },
+                min_distance: 1.5,
+                avg_distance: 1.5,
+                ..tcn(0xab, 2000, 1)
+            }],
+            tcn_dao.scratch().unwrap()
+        );
+        assert_eq!(Some(3), tcn_dao.last_scratch_id().unwrap());
+
+        // Migrated already: a rerun doesn't change the rows
+        assert!(database.core_pragma_update("user_version", &25).is_ok());
+        let rerun_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(rerun_res.is_ok());
+        assert_eq!(2, tcn_dao.all().unwrap().len());
+    }
+
+    #[test]
+    fn test_migration_adds_update_run_columns() {
+        simple_logger::setup();
+        let table_name = "update_run";
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &5).is_ok());
+        let res = database.execute_batch(
+            "CREATE TABLE update_run(
+                id integer primary key autoincrement,
+                time integer not null,
+                duration_millis integer not null,
+                intervals integer not null,
+                reports integer not null,
+                matches integer not null,
+                error_code text
+            );
+            INSERT INTO update_run(time, duration_millis, intervals, reports, matches)
+            VALUES(1590528300, 1200, 3, 10, 1);",
+        );
+        expect_log!(res, "Couldn't create update_run table for db version 5");
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        let table_columns_after_migration = core_table_info(table_name, database.clone());
+        assert!(table_columns_after_migration.contains(&"oversized_intervals".to_owned()));
+        assert!(table_columns_after_migration.contains(&"normalized_reports".to_owned()));
+        assert!(table_columns_after_migration.contains(&"expired_exposures".to_owned()));
+        assert!(table_columns_after_migration.contains(&"filtered_reports".to_owned()));
+        assert!(table_columns_after_migration.contains(&"unchanged_intervals".to_owned()));
+        assert!(table_columns_after_migration.contains(&"invalid_reports".to_owned()));
+
+        let oversized_intervals_res = database.query_row(
+            "select oversized_intervals from update_run",
+            params![],
+            |row| row.get(0),
+        );
+        let oversized_intervals: i64 =
+            expect_log!(oversized_intervals_res, "Couldn't read oversized intervals");
+        assert_eq!(0, oversized_intervals);
+    }
+
+    #[test]
+    fn test_fresh_install_creates_schema() {
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+
+        let migration_handler = Migration::new(database.clone());
+        let migration_res = migration_handler.run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        assert_eq!(REQUIRED_DB_VERSION, migration_res.unwrap().to_version);
+        let db_version: i32 = database.core_pragma_query("user_version").unwrap();
+        assert_eq!(REQUIRED_DB_VERSION, db_version);
+
+        assert_eq!(9, core_table_info("tcn", database.clone()).len());
+        assert_eq!(16, core_table_info("alert", database.clone()).len());
+        assert_eq!(16, core_table_info("report", database.clone()).len());
+        assert_eq!(13, core_table_info("update_run", database.clone()).len());
+        assert_eq!(8, core_table_info("tcn_scratch", database.clone()).len());
+        assert_eq!(
+            5,
+            core_table_info("processed_report", database.clone()).len()
+        );
+        assert_eq!(
+            5,
+            core_table_info("housekeeping_run", database.clone()).len()
+        );
+        assert_eq!(
+            4,
+            core_table_info("report_interval_state", database.clone()).len()
+        );
+        assert_eq!(
+            2,
+            core_table_info("retracted_report", database.clone()).len()
+        );
+
+        // The DAOs work without creating tables
+        assert!(TcnDaoImpl::new(database.clone()).all().is_ok());
+        assert!(AlertDaoImpl::new(database.clone()).all().is_ok());
+        let update_run_dao = UpdateRunDaoImpl::new(database);
+        assert!(update_run_dao.all().is_ok());
+        assert!(update_run_dao.housekeeping_history().is_ok());
+    }
+
+    #[test]
+    fn test_upgrade_creates_tables_missing_in_old_version() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &1).is_ok());
+        prep_alert_table_for_db_version_1(database.clone());
+
+        let migration_handler = Migration::new(database.clone());
+        assert!(migration_handler
+            .run_db_migrations(REQUIRED_DB_VERSION)
+            .is_ok());
+
+        // Didn't exist in version 1
+        assert_eq!(9, core_table_info("tcn", database.clone()).len());
+        assert_eq!(13, core_table_info("update_run", database.clone()).len());
+        assert_eq!(10, core_table_info("exposure", database.clone()).len());
+        assert_eq!(
+            5,
+            core_table_info("processed_report", database.clone()).len()
+        );
+        assert_eq!(
+            5,
+            core_table_info("housekeeping_run", database.clone()).len()
+        );
+        assert_eq!(
+            4,
+            core_table_info("report_interval_state", database.clone()).len()
+        );
+
+        // The stored alert was migrated, not recreated
+        let alerts_res = AlertDaoImpl::new(database).all();
+        assert!(alerts_res.is_ok());
+        assert_eq!(1, alerts_res.unwrap().len());
+    }
+
+    #[test]
+    fn test_failing_migration_step_returns_error_and_keeps_version() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        assert!(database.core_pragma_update("user_version", &5).is_ok());
+        // Columns can't be added to a view: migration 5 fails
+        let res = database.execute_batch("CREATE VIEW update_run AS SELECT 1 AS time;");
+        expect_log!(res, "Couldn't create update_run view");
+
+        let migration_handler = Migration::new(database.clone());
+        let migration_res = migration_handler.run_db_migrations(REQUIRED_DB_VERSION);
+        match migration_res {
+            Err(ServicesError::Database(_)) => {}
+            other => panic!("Expected database error, got: {:?}", other),
+        }
+        let db_version: i32 = database.core_pragma_query("user_version").unwrap();
+        assert_eq!(5, db_version);
+        // No preferences table at this version: the report isn't persisted, but the run goes on
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        };
+        assert_eq!(None, preferences.last_migration_report());
+
+        // Retried on the next run
+        let res = database.execute_batch("DROP VIEW update_run;");
+        expect_log!(res, "Couldn't drop update_run view");
+        let migration_res = migration_handler.run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        assert_eq!(REQUIRED_DB_VERSION, migration_res.unwrap().to_version);
+        assert_eq!(13, core_table_info("update_run", database).len());
+    }
+
+    #[test]
+    fn test_migration_report_of_app_version_03_upgrade() {
+        simple_logger::setup();
+        let database = Arc::new(Database::new(
+            Connection::open_in_memory().expect("Couldn't create database!"),
+        ));
+        prep_data_structure_for_app_version_03(database.clone());
+        let migration_handler = Migration::new(database.clone());
+
+        let preview_res = migration_handler.preview_migrations(REQUIRED_DB_VERSION);
+        assert!(preview_res.is_ok());
+        let preview = preview_res.unwrap();
+        assert_eq!(0, preview.from_version);
+        assert_eq!(REQUIRED_DB_VERSION, preview.to_version);
+        assert_eq!(
+            (0..REQUIRED_DB_VERSION).collect::<Vec<i32>>(),
+            preview
+                .steps
+                .iter()
+                .map(|step| step.version)
+                .collect::<Vec<i32>>()
+        );
+        assert!(preview
+            .steps
+            .iter()
+            .all(|step| step.outcome == MigrationOutcome::Pending));
+        // Nothing was migrated
+        let db_version: i32 = database.core_pragma_query("user_version").unwrap();
+        assert_eq!(0, db_version);
+        assert_eq!(2, core_table_info("tcn", database.clone()).len());
+
+        let migration_res = migration_handler.run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        let report = migration_res.unwrap();
+        assert_eq!(0, report.from_version);
+        assert_eq!(REQUIRED_DB_VERSION, report.to_version);
+        assert_eq!(
+            (0..REQUIRED_DB_VERSION).collect::<Vec<i32>>(),
+            report
+                .steps
+                .iter()
+                .map(|step| step.version)
+                .collect::<Vec<i32>>()
+        );
+        assert!(report
+            .steps
+            .iter()
+            .all(|step| step.outcome == MigrationOutcome::Applied));
+
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        };
+        assert_eq!(Some(report), preferences.last_migration_report());
+
+        // Up to date: no steps, the persisted report is kept
+        let migration_res = migration_handler.run_db_migrations(REQUIRED_DB_VERSION);
+        assert!(migration_res.is_ok());
+        assert!(migration_res.unwrap().steps.is_empty());
+        let preview_res = migration_handler.preview_migrations(REQUIRED_DB_VERSION);
+        assert!(preview_res.is_ok());
+        assert!(preview_res.unwrap().steps.is_empty());
+        assert_eq!(
+            REQUIRED_DB_VERSION,
+            preferences.last_migration_report().unwrap().to_version
+        );
+    }
+
+    fn prep_alert_table_for_db_version_1(database: Arc<Database>) {
+        let exported_db_sql = "BEGIN TRANSACTION;
+        CREATE TABLE IF NOT EXISTS alert(
+                        id text primary key,
+                        start integer not null,
+                        end integer not null,
+                        min_distance real not null,
+                        avg_distance real not null,
+                        report_time integer not null,
+                        earliest_symptom_time integer,
+                        fever_severity integer not null,
+                        cough_severity integer not null,
+                        breathlessness integer not null,
+                        muscle_aches integer not null,
+                        loss_smell_or_taste integer not null,
+                        diarrhea integer not null,
+                        runny_nose integer not null,
+                        other integer not null,
+                        no_symptoms integer not null,
+                        report_id text not null,
+                        read integer not null,
+                        deleted integer
+                    );
+        INSERT INTO alert VALUES('1', 1000, 2000, 2.3, 4.3, 0, NULL, 1, 2, 1, 1, 0, 0, 1, 0, 1, '1', 0, NULL);
+        COMMIT;";
+
+        let res = database.execute_batch(exported_db_sql);
+        expect_log!(res, "Couldn't recreate alert table for db version 1");
+    }
+
     fn migration_0_alter_tcn_table(database: Arc<Database>) {
         let exec_res = database.execute_sql(
             "alter table tcn rename column contact_time to contact_start;",
@@ -217,18 +1904,16 @@ mod tests {
                 params![table_name],
                 |row: &Row| to_table_information(row),
             )
-            .unwrap();
+            .unwrap()
+            .rows;
         debug!("{} table columns: {:#?}", table_name, columns);
         columns
     }
 
-    fn to_table_information(row: &Row) -> String {
-        let ord: Result<i32, _> = row.get(0);
-        let ord_value = expect_log!(ord, "Invalid row: no ordinal");
-
-        let column_name_res = row.get(1);
-        let column_name: String = expect_log!(column_name_res, "Invalid row: no column name");
+    fn to_table_information(row: &Row) -> Result<String, ServicesError> {
+        let ord_value: i32 = column(row, 0, "ordinal")?;
+        let column_name: String = column(row, 1, "column name")?;
         debug!("Column {}: {}", ord_value, column_name);
-        column_name
+        Ok(column_name)
     }
 }
@@ -2,4 +2,5 @@ pub mod alert_dao;
 pub mod database;
 pub mod preferences;
 pub mod tcn_dao;
+pub mod update_run_dao;
 pub mod migration;
@@ -1,5 +1,9 @@
-use super::database::Database;
-use crate::{byte_vec_to_32_byte_array, expect_log, reports_interval::ReportsInterval};
+use super::{database::Database, migration::MigrationReport};
+use crate::{
+    byte_vec_to_32_byte_array, errors::ServicesError, expect_log,
+    reporting::public_symptoms::PublicSymptoms, reports_interval::ReportsInterval,
+    reports_update::quiet_hours::QuietHours, tcn_ext::tcn_keys::KeyIntegrityIssue,
+};
 use log::*;
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
@@ -58,62 +62,133 @@ pub struct PreferencesDao {
 
 impl PreferencesDao {
     pub fn load(&self, key: &str) -> Option<String> {
+        self.try_load(key).unwrap_or_else(|e| {
+            error!("Error loading preference: {:?}", e);
+            None
+        })
+    }
+
+    pub fn save(&self, key: &str, value: &str) {
+        let res = self.try_save(key, value);
+        expect_log!(res, "Couldn't insert preference");
+    }
+
+    // Unlike load, a failed read is an error instead of None. For values that are created when
+    // missing (the keys): a read error must not replace them.
+    pub fn try_load(&self, key: &str) -> Result<Option<String>, ServicesError> {
         let result = self.db.query_row(
             "select value from preferences where key=?1",
             &[key],
-            |row| {
-                let res = row.get(0);
-                let value: String = expect_log!(res, "Invalid row");
-                Ok(value)
-            },
+            |row| row.get(0),
         );
-
-        if let Err(e) = &result {
-            match e {
-                // Empty result: not an error
-                rusqlite::Error::QueryReturnedNoRows => {}
-                _ => error!("Error loading preference: {:?}", e),
-            }
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(ServicesError::Database(format!(
+                "Couldn't load preference: {}: {}",
+                key, e
+            ))),
         }
-
-        result.ok()
     }
 
-    pub fn save(&self, key: &str, value: &str) {
-        let res = self.db.execute_sql(
-            "insert or replace into preferences(key, value) values(?1, ?2)",
-            params![key, value],
-        );
-        expect_log!(res, "Couldn't insert preference");
+    pub fn try_save(&self, key: &str, value: &str) -> Result<(), ServicesError> {
+        self.db
+            .execute_sql(
+                "insert or replace into preferences(key, value) values(?1, ?2)",
+                params![key, value],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                ServicesError::Database(format!("Couldn't save preference: {}: {}", key, e))
+            })
     }
 
     pub fn new(db: Arc<Database>) -> PreferencesDao {
-        Self::create_table_if_not_exists(&db);
         PreferencesDao { db }
     }
+}
 
-    fn create_table_if_not_exists(db: &Arc<Database>) {
-        let res = db.execute_sql(
-            "create table if not exists preferences(
-                key text primary key,
-                value text not null
-            )",
-            params![],
-        );
-        expect_log!(res, "Couldn't create preferences table");
-    }
+// Stored keys that failed the integrity check on bootstrap, as they were stored. For diagnostics.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedKeys {
+    pub time: u64, // Unix time
+    pub issue: KeyIntegrityIssue,
+    pub authorization_key: Option<String>, // Hex
+    pub tck: Option<String>,               // JSON
+}
+
+// A report post that didn't get a response from the backend (see SymptomInputsSubmitterImpl::send_report).
+// Submitting the same symptoms posts it again as it is, so the backend can discard it if it was stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingReportSubmission {
+    pub symptoms: PublicSymptoms,
+    pub report: String, // Base64 signed report
+    pub idempotency_key: String,
+    pub created_at: u64, // Unix time
 }
 
 pub trait Preferences {
     fn last_completed_reports_interval(&self) -> Option<ReportsInterval>;
     fn set_last_completed_reports_interval(&self, value: ReportsInterval);
 
+    // Intervals whose reports couldn't be fetched, before the last completed one. Fetched again in the next updates.
+    fn reports_intervals_to_retry(&self) -> Vec<ReportsInterval>;
+    fn set_reports_intervals_to_retry(&self, value: Vec<ReportsInterval>);
+
     // TODO encrypted
-    fn authorization_key(&self) -> Option<[u8; 32]>;
-    fn set_autorization_key(&self, value: [u8; 32]);
+    // Err if the key couldn't be read, Ok(None) if there's no key yet
+    fn authorization_key(&self) -> Result<Option<[u8; 32]>, ServicesError>;
+    fn set_autorization_key(&self, value: [u8; 32]) -> Result<(), ServicesError>;
+
+    fn tck(&self) -> Result<Option<TckBytesWrapper>, ServicesError>;
+    fn set_tck(&self, value: TckBytesWrapper) -> Result<(), ServicesError>;
 
-    fn tck(&self) -> Option<TckBytesWrapper>;
-    fn set_tck(&self, value: TckBytesWrapper);
+    // Start of the rotation period of TCK index 1 (unix time)
+    fn tcn_epoch(&self) -> Option<u64>;
+    fn set_tcn_epoch(&self, value: u64);
+
+    // Key of the alert reference tokens (HMAC)
+    fn alert_token_key(&self) -> Option<[u8; 32]>;
+    fn set_alert_token_key(&self, value: [u8; 32]);
+
+    // Kept after the inputs are cleared, to resubmit with corrections
+    fn last_submitted_report(&self) -> Option<PublicSymptoms>;
+    fn set_last_submitted_report(&self, value: PublicSymptoms);
+
+    // None clears it
+    fn pending_report_submission(&self) -> Option<PendingReportSubmission>;
+    fn set_pending_report_submission(&self, value: Option<PendingReportSubmission>);
+
+    fn quiet_hours(&self) -> Option<QuietHours>;
+    fn set_quiet_hours(&self, value: QuietHours);
+
+    // New alerts were stored but the user wasn't notified yet (quiet hours)
+    fn pending_alert_notification(&self) -> bool;
+    fn set_pending_alert_notification(&self, value: bool);
+
+    // Unix time of the last aggregate metrics upload (see MetricsUploader)
+    fn last_metrics_upload(&self) -> Option<u64>;
+    fn set_last_metrics_upload(&self, value: u64);
+
+    // Base64 signature prefixes of reports supplied by the host app (see ReportsUpdater::set_report_denylist)
+    fn report_denylist(&self) -> Vec<String>;
+    fn set_report_denylist(&self, value: Vec<String>);
+
+    // Empty: all reports are allowed
+    fn report_allowlist(&self) -> Vec<String>;
+    fn set_report_allowlist(&self, value: Vec<String>);
+
+    // Unix time of the last successful run of the housekeeping task (see Housekeeper)
+    fn last_housekeeping_run(&self, task: &str) -> Option<u64>;
+    fn set_last_housekeeping_run(&self, task: &str, value: u64);
+
+    // Last run of the db migrations that ran steps (see Migration::run_db_migrations)
+    fn last_migration_report(&self) -> Option<MigrationReport>;
+    fn set_last_migration_report(&self, value: MigrationReport) -> Result<(), ServicesError>;
+
+    // Copies the stored keys as they are, replacing the previous archive (see TcnKeysImpl::check_integrity)
+    fn archive_keys(&self, time: u64, issue: KeyIntegrityIssue) -> Result<(), ServicesError>;
+    fn archived_keys(&self) -> Option<ArchivedKeys>;
 }
 
 pub struct PreferencesImpl {
@@ -136,32 +211,229 @@ impl Preferences for PreferencesImpl {
             .save("last_completed_reports_interval", str.as_ref())
     }
 
-    fn authorization_key(&self) -> Option<[u8; 32]> {
-        let str = self.dao.load("authorization_key");
+    fn reports_intervals_to_retry(&self) -> Vec<ReportsInterval> {
+        let str = self.dao.load("reports_intervals_to_retry");
+        str.map(|str| {
+            let res = serde_json::from_str(str.as_ref());
+            expect_log!(res, "Invalid intervals str")
+        })
+        .unwrap_or_default()
+    }
+
+    fn set_reports_intervals_to_retry(&self, value: Vec<ReportsInterval>) {
+        let res = serde_json::to_string(&value);
+        let str = expect_log!(res, "Couldn't serialize intervals");
+        self.dao.save("reports_intervals_to_retry", str.as_ref())
+    }
+
+    fn authorization_key(&self) -> Result<Option<[u8; 32]>, ServicesError> {
+        match self.dao.try_load("authorization_key")? {
+            Some(str) => {
+                let bytes = hex::decode(str)?;
+                let key = byte_vec_to_32_byte_array(bytes).map_err(|e| {
+                    ServicesError::General(format!("Invalid authorization key: {}", e))
+                })?;
+                Ok(Some(key))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_autorization_key(&self, value: [u8; 32]) -> Result<(), ServicesError> {
+        self.dao
+            .try_save("authorization_key", hex::encode(&value).as_ref())
+    }
+
+    fn tck(&self) -> Result<Option<TckBytesWrapper>, ServicesError> {
+        match self.dao.try_load("tck")? {
+            Some(str) => Ok(Some(serde_json::from_str(str.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_tck(&self, value: TckBytesWrapper) -> Result<(), ServicesError> {
+        let str = serde_json::to_string(&value)?;
+        self.dao.try_save("tck", str.as_ref())
+    }
+
+    fn tcn_epoch(&self) -> Option<u64> {
+        let str = self.dao.load("tcn_epoch");
+        str.map(|str| {
+            let res = str.parse();
+            expect_log!(res, "Invalid tcn epoch str")
+        })
+    }
+
+    fn set_tcn_epoch(&self, value: u64) {
+        self.dao.save("tcn_epoch", value.to_string().as_ref())
+    }
+
+    fn alert_token_key(&self) -> Option<[u8; 32]> {
+        let str = self.dao.load("alert_token_key");
         let bytes = str.map(|str| {
             let res = hex::decode(str);
-            expect_log!(res, "Invalid interval str")
+            expect_log!(res, "Invalid alert token key str")
         });
-        bytes.map(|bytes| byte_vec_to_32_byte_array(bytes))
+        bytes.map(|bytes| {
+            let res = byte_vec_to_32_byte_array(bytes);
+            expect_log!(res, "Invalid alert token key length")
+        })
+    }
+
+    fn set_alert_token_key(&self, value: [u8; 32]) {
+        self.dao
+            .save("alert_token_key", hex::encode(&value).as_ref())
+    }
+
+    fn last_submitted_report(&self) -> Option<PublicSymptoms> {
+        let str = self.dao.load("last_submitted_report");
+        str.map(|str| {
+            let res = serde_json::from_str(str.as_ref());
+            expect_log!(res, "Invalid last submitted report str")
+        })
+    }
+
+    fn set_last_submitted_report(&self, value: PublicSymptoms) {
+        let res = serde_json::to_string(&value);
+        let str = expect_log!(res, "Couldn't serialize last submitted report");
+        self.dao.save("last_submitted_report", str.as_ref())
+    }
+
+    fn pending_report_submission(&self) -> Option<PendingReportSubmission> {
+        let str = self.dao.load("pending_report_submission");
+        str.and_then(|str| {
+            serde_json::from_str(str.as_ref())
+                .map_err(|e| warn!("Invalid pending report submission: {:?}", e))
+                .ok()
+                .flatten()
+        })
+    }
+
+    fn set_pending_report_submission(&self, value: Option<PendingReportSubmission>) {
+        let res = serde_json::to_string(&value);
+        let str = expect_log!(res, "Couldn't serialize pending report submission");
+        self.dao.save("pending_report_submission", str.as_ref())
+    }
+
+    fn quiet_hours(&self) -> Option<QuietHours> {
+        let str = self.dao.load("quiet_hours");
+        str.map(|str| {
+            let res = serde_json::from_str(str.as_ref());
+            expect_log!(res, "Invalid quiet hours str")
+        })
     }
 
-    fn set_autorization_key(&self, value: [u8; 32]) {
+    fn set_quiet_hours(&self, value: QuietHours) {
+        let res = serde_json::to_string(&value);
+        let str = expect_log!(res, "Couldn't serialize quiet hours");
+        self.dao.save("quiet_hours", str.as_ref())
+    }
+
+    fn pending_alert_notification(&self) -> bool {
+        let str = self.dao.load("pending_alert_notification");
+        str.map(|str| {
+            let res = str.parse();
+            expect_log!(res, "Invalid pending alert notification str")
+        })
+        .unwrap_or(false)
+    }
+
+    fn set_pending_alert_notification(&self, value: bool) {
         self.dao
-            .save("authorization_key", hex::encode(&value).as_ref())
+            .save("pending_alert_notification", value.to_string().as_ref())
     }
 
-    fn tck(&self) -> Option<TckBytesWrapper> {
-        let str = self.dao.load("tck");
+    fn last_metrics_upload(&self) -> Option<u64> {
+        let str = self.dao.load("last_metrics_upload");
+        str.map(|str| {
+            let res = str.parse();
+            expect_log!(res, "Invalid last metrics upload str")
+        })
+    }
+
+    fn set_last_metrics_upload(&self, value: u64) {
+        self.dao
+            .save("last_metrics_upload", value.to_string().as_ref())
+    }
+
+    fn report_denylist(&self) -> Vec<String> {
+        let str = self.dao.load("report_denylist");
         str.map(|str| {
             let res = serde_json::from_str(str.as_ref());
-            expect_log!(res, "Invalid tck wrapper str")
+            expect_log!(res, "Invalid report denylist str")
         })
+        .unwrap_or_default()
     }
 
-    fn set_tck(&self, value: TckBytesWrapper) {
+    fn set_report_denylist(&self, value: Vec<String>) {
         let res = serde_json::to_string(&value);
-        let str = expect_log!(res, "Couldn't serialize tck wrapper");
-        self.dao.save("tck", str.as_ref())
+        let str = expect_log!(res, "Couldn't serialize report denylist");
+        self.dao.save("report_denylist", str.as_ref())
+    }
+
+    fn report_allowlist(&self) -> Vec<String> {
+        let str = self.dao.load("report_allowlist");
+        str.map(|str| {
+            let res = serde_json::from_str(str.as_ref());
+            expect_log!(res, "Invalid report allowlist str")
+        })
+        .unwrap_or_default()
+    }
+
+    fn set_report_allowlist(&self, value: Vec<String>) {
+        let res = serde_json::to_string(&value);
+        let str = expect_log!(res, "Couldn't serialize report allowlist");
+        self.dao.save("report_allowlist", str.as_ref())
+    }
+
+    fn last_housekeeping_run(&self, task: &str) -> Option<u64> {
+        let str = self.dao.load(&format!("last_housekeeping_run_{}", task));
+        str.map(|str| {
+            let res = str.parse();
+            expect_log!(res, "Invalid last housekeeping run str")
+        })
+    }
+
+    fn set_last_housekeeping_run(&self, task: &str, value: u64) {
+        self.dao.save(
+            &format!("last_housekeeping_run_{}", task),
+            value.to_string().as_ref(),
+        )
+    }
+
+    // Diagnostics only: a report that can't be read is logged and treated as missing
+    fn last_migration_report(&self) -> Option<MigrationReport> {
+        let str = self.dao.load("last_migration_report");
+        str.and_then(|str| {
+            serde_json::from_str(str.as_ref())
+                .map_err(|e| warn!("Invalid last migration report: {:?}", e))
+                .ok()
+        })
+    }
+
+    fn set_last_migration_report(&self, value: MigrationReport) -> Result<(), ServicesError> {
+        let str = serde_json::to_string(&value)?;
+        self.dao.try_save("last_migration_report", str.as_ref())
+    }
+
+    fn archive_keys(&self, time: u64, issue: KeyIntegrityIssue) -> Result<(), ServicesError> {
+        let archive = ArchivedKeys {
+            time,
+            issue,
+            authorization_key: self.dao.try_load("authorization_key")?,
+            tck: self.dao.try_load("tck")?,
+        };
+        let str = serde_json::to_string(&archive)?;
+        self.dao.try_save("archived_keys", str.as_ref())
+    }
+
+    fn archived_keys(&self) -> Option<ArchivedKeys> {
+        let str = self.dao.load("archived_keys");
+        str.and_then(|str| {
+            serde_json::from_str(str.as_ref())
+                .map_err(|e| warn!("Invalid archived keys: {:?}", e))
+                .ok()
+        })
     }
 }
 
@@ -182,24 +454,106 @@ impl Preferences for PreferencesTckMock {
         return;
     }
 
-    fn authorization_key(&self) -> std::option::Option<[u8; 32]> {
+    fn reports_intervals_to_retry(&self) -> Vec<ReportsInterval> {
+        vec![]
+    }
+
+    fn set_reports_intervals_to_retry(&self, _: Vec<ReportsInterval>) {}
+
+    fn authorization_key(&self) -> Result<Option<[u8; 32]>, ServicesError> {
         let bytes = [
             42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
             126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
         ];
-        return Option::Some(bytes);
+        Ok(Some(bytes))
     }
 
-    fn set_autorization_key(&self, _value: [u8; 32]) {
-        return;
+    fn set_autorization_key(&self, _value: [u8; 32]) -> Result<(), ServicesError> {
+        Ok(())
     }
 
-    fn tck(&self) -> std::option::Option<TckBytesWrapper> {
-        Some(self.tck_bytes)
+    fn tck(&self) -> Result<Option<TckBytesWrapper>, ServicesError> {
+        Ok(Some(self.tck_bytes))
     }
 
-    fn set_tck(&self, _value: TckBytesWrapper) {
-        return;
+    fn set_tck(&self, _value: TckBytesWrapper) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn tcn_epoch(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_tcn_epoch(&self, _value: u64) {}
+
+    fn alert_token_key(&self) -> Option<[u8; 32]> {
+        None
+    }
+
+    fn set_alert_token_key(&self, _value: [u8; 32]) {}
+
+    fn last_submitted_report(&self) -> Option<PublicSymptoms> {
+        None
+    }
+
+    fn set_last_submitted_report(&self, _value: PublicSymptoms) {}
+
+    fn pending_report_submission(&self) -> Option<PendingReportSubmission> {
+        None
+    }
+
+    fn set_pending_report_submission(&self, _value: Option<PendingReportSubmission>) {}
+
+    fn quiet_hours(&self) -> Option<QuietHours> {
+        None
+    }
+
+    fn set_quiet_hours(&self, _value: QuietHours) {}
+
+    fn pending_alert_notification(&self) -> bool {
+        false
+    }
+
+    fn set_pending_alert_notification(&self, _value: bool) {}
+
+    fn last_metrics_upload(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_last_metrics_upload(&self, _value: u64) {}
+
+    fn report_denylist(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn set_report_denylist(&self, _value: Vec<String>) {}
+
+    fn report_allowlist(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn set_report_allowlist(&self, _value: Vec<String>) {}
+
+    fn last_housekeeping_run(&self, _task: &str) -> Option<u64> {
+        None
+    }
+
+    fn set_last_housekeeping_run(&self, _task: &str, _value: u64) {}
+
+    fn last_migration_report(&self) -> Option<MigrationReport> {
+        None
+    }
+
+    fn set_last_migration_report(&self, _value: MigrationReport) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn archive_keys(&self, _time: u64, _issue: KeyIntegrityIssue) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn archived_keys(&self) -> Option<ArchivedKeys> {
+        None
     }
 }
 
@@ -212,30 +566,122 @@ impl Preferences for PreferencesNoopMock {
 
     fn set_last_completed_reports_interval(&self, _: ReportsInterval) {}
 
-    fn authorization_key(&self) -> std::option::Option<[u8; 32]> {
-        Option::None
+    fn reports_intervals_to_retry(&self) -> Vec<ReportsInterval> {
+        vec![]
     }
 
-    fn set_autorization_key(&self, _value: [u8; 32]) {}
+    fn set_reports_intervals_to_retry(&self, _: Vec<ReportsInterval>) {}
 
-    fn tck(&self) -> std::option::Option<TckBytesWrapper> {
-        Option::None
+    fn authorization_key(&self) -> Result<Option<[u8; 32]>, ServicesError> {
+        Ok(None)
+    }
+
+    fn set_autorization_key(&self, _value: [u8; 32]) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn tck(&self) -> Result<Option<TckBytesWrapper>, ServicesError> {
+        Ok(None)
+    }
+
+    fn set_tck(&self, _value: TckBytesWrapper) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn tcn_epoch(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_tcn_epoch(&self, _value: u64) {}
+
+    fn alert_token_key(&self) -> Option<[u8; 32]> {
+        None
+    }
+
+    fn set_alert_token_key(&self, _value: [u8; 32]) {}
+
+    fn last_submitted_report(&self) -> Option<PublicSymptoms> {
+        None
+    }
+
+    fn set_last_submitted_report(&self, _value: PublicSymptoms) {}
+
+    fn pending_report_submission(&self) -> Option<PendingReportSubmission> {
+        None
+    }
+
+    fn set_pending_report_submission(&self, _value: Option<PendingReportSubmission>) {}
+
+    fn quiet_hours(&self) -> Option<QuietHours> {
+        None
+    }
+
+    fn set_quiet_hours(&self, _value: QuietHours) {}
+
+    fn pending_alert_notification(&self) -> bool {
+        false
+    }
+
+    fn set_pending_alert_notification(&self, _value: bool) {}
+
+    fn last_metrics_upload(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_last_metrics_upload(&self, _value: u64) {}
+
+    fn report_denylist(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn set_report_denylist(&self, _value: Vec<String>) {}
+
+    fn report_allowlist(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn set_report_allowlist(&self, _value: Vec<String>) {}
+
+    fn last_housekeeping_run(&self, _task: &str) -> Option<u64> {
+        None
+    }
+
+    fn set_last_housekeeping_run(&self, _task: &str, _value: u64) {}
+
+    fn last_migration_report(&self) -> Option<MigrationReport> {
+        None
+    }
+
+    fn set_last_migration_report(&self, _value: MigrationReport) -> Result<(), ServicesError> {
+        Ok(())
+    }
+
+    fn archive_keys(&self, _time: u64, _issue: KeyIntegrityIssue) -> Result<(), ServicesError> {
+        Ok(())
     }
 
-    fn set_tck(&self, _value: TckBytesWrapper) {}
+    fn archived_keys(&self) -> Option<ArchivedKeys> {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tcn_ext::tcn_keys::TckBytesWrapperExt;
-    use rusqlite::Connection;
+    use crate::{
+        database::migration::{MigrationOutcome, MigrationStepReport},
+        reporting::{
+            public_symptoms::{CoughSeverity, FeverSeverity, ReportKind},
+            symptom_inputs::UserInput,
+        },
+        reports_interval::UnixTime,
+        tcn_ext::tcn_keys::TckBytesWrapperExt,
+        test_support::{fixtures::TEST_RAK_BYTES, migrated_database},
+    };
 
     #[test]
     fn test_saves_last_completed_reports_interval() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let preferences_dao = PreferencesDao::new(database);
         let preferences = PreferencesImpl {
             dao: preferences_dao,
@@ -253,11 +699,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_saves_reports_intervals_to_retry() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert!(preferences.reports_intervals_to_retry().is_empty());
+
+        let intervals = vec![
+            ReportsInterval {
+                number: 1,
+                length: 10,
+            },
+            ReportsInterval {
+                number: 3,
+                length: 10,
+            },
+        ];
+        preferences.set_reports_intervals_to_retry(intervals.clone());
+
+        assert_eq!(preferences.reports_intervals_to_retry(), intervals);
+    }
+
     #[test]
     fn test_saves_tck() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let preferences_dao = PreferencesDao::new(database);
         let preferences = PreferencesImpl {
             dao: preferences_dao,
@@ -265,36 +732,277 @@ mod tests {
 
         let tck_bytes_wrapper = create_test_tck();
 
-        preferences.set_tck(tck_bytes_wrapper);
+        assert!(preferences.set_tck(tck_bytes_wrapper).is_ok());
 
-        assert_eq!(preferences.tck().unwrap(), tck_bytes_wrapper);
+        assert_eq!(preferences.tck().unwrap(), Some(tck_bytes_wrapper));
     }
 
     #[test]
     fn test_saves_autorization_key() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let preferences_dao = PreferencesDao::new(database);
         let preferences = PreferencesImpl {
             dao: preferences_dao,
         };
 
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
 
-        preferences.set_autorization_key(rak_bytes);
+        assert_eq!(preferences.authorization_key().unwrap(), None);
+        assert!(preferences.set_autorization_key(rak_bytes).is_ok());
 
-        assert_eq!(preferences.authorization_key().unwrap(), rak_bytes);
+        assert_eq!(preferences.authorization_key().unwrap(), Some(rak_bytes));
+    }
+
+    #[test]
+    fn test_unreadable_keys_are_errors() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        };
+        database
+            .execute_sql("drop table preferences", params![])
+            .unwrap();
+
+        assert!(matches!(
+            preferences.authorization_key(),
+            Err(ServicesError::Database(_))
+        ));
+        assert!(matches!(preferences.tck(), Err(ServicesError::Database(_))));
+        assert!(preferences.set_autorization_key([0; 32]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_authorization_key_is_error() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        preferences.set_autorization_key([1; 32]).unwrap();
+        let str = preferences.dao.load("authorization_key").unwrap();
+        preferences.dao.save("authorization_key", &str[..40]);
+
+        assert!(preferences.authorization_key().is_err());
+    }
+
+    #[test]
+    fn test_saves_tcn_epoch() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.tcn_epoch(), None);
+
+        preferences.set_tcn_epoch(1591706000);
+
+        assert_eq!(preferences.tcn_epoch(), Some(1591706000));
+    }
+
+    #[test]
+    fn test_saves_alert_token_key() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.alert_token_key(), None);
+
+        let key = [7; 32];
+        preferences.set_alert_token_key(key);
+
+        assert_eq!(preferences.alert_token_key(), Some(key));
+    }
+
+    #[test]
+    fn test_saves_last_submitted_report() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.last_submitted_report(), None);
+
+        let report = PublicSymptoms {
+            report_time: UnixTime { value: 1590356601 },
+            earliest_symptom_time: UserInput::Some(UnixTime { value: 1590356000 }),
+            fever_severity: FeverSeverity::Mild,
+            cough_severity: CoughSeverity::Dry,
+            breathlessness: true,
+            muscle_aches: false,
+            loss_smell_or_taste: false,
+            diarrhea: false,
+            runny_nose: true,
+            other: false,
+            no_symptoms: false,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        };
+        preferences.set_last_submitted_report(report.clone());
+
+        assert_eq!(preferences.last_submitted_report(), Some(report));
+    }
+
+    #[test]
+    fn test_saves_and_clears_pending_report_submission() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.pending_report_submission(), None);
+
+        let submission = PendingReportSubmission {
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 1590356601 },
+                earliest_symptom_time: UserInput::None,
+                fever_severity: FeverSeverity::None,
+                cough_severity: CoughSeverity::Dry,
+                breathlessness: false,
+                muscle_aches: false,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: false,
+                other: false,
+                no_symptoms: false,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            },
+            report: "cmVwb3J0".to_owned(),
+            idempotency_key: "key".to_owned(),
+            created_at: 1590356601,
+        };
+        preferences.set_pending_report_submission(Some(submission.clone()));
+        assert_eq!(preferences.pending_report_submission(), Some(submission));
+
+        preferences.set_pending_report_submission(None);
+        assert_eq!(preferences.pending_report_submission(), None);
+    }
+
+    #[test]
+    fn test_last_submitted_report_of_previous_version_is_symptoms_report() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        // Stored before the report kind was added
+        preferences.dao.save(
+            "last_submitted_report",
+            r#"{"report_time":{"value":1590356601},"earliest_symptom_time":null,"fever_severity":"Mild","cough_severity":"Dry","breathlessness":true,"muscle_aches":false,"loss_smell_or_taste":false,"diarrhea":false,"runny_nose":true,"other":false,"no_symptoms":false,"memo_version":1}"#,
+        );
+
+        let report = preferences.last_submitted_report();
+        assert!(report.is_some());
+        assert_eq!(ReportKind::Symptoms, report.unwrap().report_kind);
+    }
+
+    #[test]
+    fn test_saves_quiet_hours() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.quiet_hours(), None);
+
+        let quiet_hours = QuietHours {
+            enabled: true,
+            start_minute: 22 * 60,
+            end_minute: 7 * 60,
+            utc_offset_minutes: 120,
+        };
+        preferences.set_quiet_hours(quiet_hours.clone());
+
+        assert_eq!(preferences.quiet_hours(), Some(quiet_hours));
+    }
+
+    #[test]
+    fn test_saves_pending_alert_notification() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert!(!preferences.pending_alert_notification());
+
+        preferences.set_pending_alert_notification(true);
+        assert!(preferences.pending_alert_notification());
+
+        preferences.set_pending_alert_notification(false);
+        assert!(!preferences.pending_alert_notification());
+    }
+
+    #[test]
+    fn test_saves_last_metrics_upload() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.last_metrics_upload(), None);
+
+        preferences.set_last_metrics_upload(1591706000);
+
+        assert_eq!(preferences.last_metrics_upload(), Some(1591706000));
+    }
+
+    #[test]
+    fn test_saves_report_lists() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert!(preferences.report_denylist().is_empty());
+        assert!(preferences.report_allowlist().is_empty());
+
+        preferences.set_report_denylist(vec!["AbC+".to_owned(), "x/y".to_owned()]);
+        preferences.set_report_allowlist(vec!["Zz".to_owned()]);
+
+        assert_eq!(
+            preferences.report_denylist(),
+            vec!["AbC+".to_owned(), "x/y".to_owned()]
+        );
+        assert_eq!(preferences.report_allowlist(), vec!["Zz".to_owned()]);
+    }
+
+    #[test]
+    fn test_saves_last_housekeeping_runs_by_task() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        assert_eq!(preferences.last_housekeeping_run("vacuum"), None);
+
+        preferences.set_last_housekeeping_run("vacuum", 1591706000);
+        preferences.set_last_housekeeping_run("prune_tcns", 1591707000);
+
+        assert_eq!(
+            preferences.last_housekeeping_run("vacuum"),
+            Some(1591706000)
+        );
+        assert_eq!(
+            preferences.last_housekeeping_run("prune_tcns"),
+            Some(1591707000)
+        );
+    }
+
+    #[test]
+    fn test_saves_last_migration_report() {
+        let database = migrated_database();
+        let preferences = PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        };
+        let report = MigrationReport {
+            from_version: 20,
+            to_version: 21,
+            steps: vec![MigrationStepReport {
+                version: 20,
+                duration_ms: 12,
+                rows_affected: 5,
+                outcome: MigrationOutcome::Failed,
+            }],
+        };
+
+        let save_res = preferences.set_last_migration_report(report.clone());
+        assert!(save_res.is_ok());
+
+        assert_eq!(preferences.last_migration_report(), Some(report));
     }
 
     fn create_test_tck() -> TckBytesWrapper {
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
 
         let tck_inner_bytes = [
             34, 166, 47, 23, 224, 52, 240, 95, 140, 186, 95, 243, 26, 13, 174, 128, 224, 229, 158,
@@ -1,18 +1,16 @@
+use super::database::{column, to_db_i64, Database};
 use crate::{
     byte_vec_to_16_byte_array,
-    errors::{ServicesError},
-    expect_log,
-    reports_interval, tcn_recording::observed_tcn_processor::ObservedTcn,
+    errors::ServicesError,
+    reports_interval,
+    tcn_recording::{distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn},
 };
 use log::*;
 use reports_interval::UnixTime;
-use rusqlite::{params, Row, NO_PARAMS, types::Value};
-use std::{
-    sync::Arc,
-    rc::Rc,
-};
+use rusqlite::{params, types::Value, Row, NO_PARAMS};
+use serde::Serialize;
+use std::{collections::HashMap, rc::Rc, sync::Arc};
 use tcn::TemporaryContactNumber;
-use super::database::Database;
 
 pub trait TcnDao: Send + Sync {
     fn all(&self) -> Result<Vec<ObservedTcn>, ServicesError>;
@@ -21,7 +19,42 @@ pub trait TcnDao: Send + Sync {
         with: Vec<TemporaryContactNumber>,
     ) -> Result<Vec<ObservedTcn>, ServicesError>;
     // Removes all matching TCNs (same TCN bytes) and stores observed_tcns 
+    // The matched flag of the removed TCNs is kept.
     fn overwrite(&self, observed_tcns: Vec<ObservedTcn>) -> Result<(), ServicesError>;
+    // Flags the stored TCNs as matched by the report. A TCN keeps the first report that matched it.
+    fn mark_matched(
+        &self,
+        tcns: Vec<TemporaryContactNumber>,
+        report_id: &str,
+    ) -> Result<(), ServicesError>;
+    // Reports that matched the TCNs first (see mark_matched)
+    fn matched_report_ids(
+        &self,
+        tcns: Vec<TemporaryContactNumber>,
+    ) -> Result<Vec<String>, ServicesError>;
+    fn stats(&self) -> Result<TcnStats, ServicesError>;
+
+    // Journal of the TCN batch (see TcnBatchesManager): raw observations, in push order
+    fn append_scratch(&self, observed_tcn: &ObservedTcn) -> Result<(), ServicesError>;
+    fn scratch(&self) -> Result<Vec<ObservedTcn>, ServicesError>;
+    fn last_scratch_id(&self) -> Result<Option<i64>, ServicesError>;
+    // Removes the observations appended until id (inclusive)
+    fn delete_scratch_up_to(&self, id: i64) -> Result<(), ServicesError>;
+
+    // Removes the observations that ended before contact_end (also if they started before). Observations
+    // that straddle it are kept: they can still create alerts. Returns the number of deleted rows.
+    fn delete_ended_before(&self, contact_end: u64) -> Result<usize, ServicesError>;
+
+    // Earliest contact start and latest contact end of the stored observations. None if there are none.
+    fn contact_time_range(&self) -> Result<Option<(UnixTime, UnixTime)>, ServicesError>;
+}
+
+// Counts of distinct stored TCNs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TcnStats {
+    pub total: u64,
+    pub matched: u64,
+    pub unmatched: u64,
 }
 
 pub struct TcnDaoImpl {
@@ -29,44 +62,18 @@ pub struct TcnDaoImpl {
 }
 
 impl TcnDaoImpl {
-    fn create_table_if_not_exists(db: &Arc<Database>) {
-        // TODO use blob for tcn? https://docs.rs/rusqlite/0.23.1/rusqlite/blob/index.html
-        // TODO ideally FFI should send byte arrays too
-        let res = db.execute_sql(
-            "create table if not exists tcn(
-                tcn text not null,
-                contact_start integer not null,
-                contact_end integer not null,
-                min_distance real not null,
-                avg_distance real not null,
-                total_count integer not null
-            )",
-            params![],
-        );
-        expect_log!(res, "Couldn't create tcn table");
-    }
-
-    fn to_tcn(row: &Row) -> ObservedTcn {
-        let tcn: Result<String, _> = row.get(0);
-        let tcn_value = expect_log!(tcn, "Invalid row: no TCN");
-        let tcn = Self::db_tcn_str_to_tcn(tcn_value);
-
-        let contact_start_res = row.get(1);
-        let contact_start: i64 = expect_log!(contact_start_res, "Invalid row: no contact start");
-
-        let contact_end_res = row.get(2);
-        let contact_end: i64 = expect_log!(contact_end_res, "Invalid row: no contact end");
-
-        let min_distance_res = row.get(3);
-        let min_distance: f64 = expect_log!(min_distance_res, "Invalid row: no min distance");
-
-        let avg_distance_res = row.get(4);
-        let avg_distance: f64 = expect_log!(avg_distance_res, "Invalid row: no avg distance");
-
-        let total_count_res = row.get(5);
-        let total_count: i64 = expect_log!(total_count_res, "Invalid row: no total count");
-
-        ObservedTcn {
+    fn to_tcn(row: &Row) -> Result<ObservedTcn, ServicesError> {
+        let tcn_value: Vec<u8> = column(row, 0, "TCN")?;
+        let tcn = Self::db_tcn_bytes_to_tcn(tcn_value)?;
+
+        let contact_start: i64 = column(row, 1, "contact start")?;
+        let contact_end: i64 = column(row, 2, "contact end")?;
+        let min_distance: f64 = column(row, 3, "min distance")?;
+        let avg_distance: f64 = column(row, 4, "avg distance")?;
+        let total_count: i64 = column(row, 5, "total count")?;
+        let distance_histogram: Option<String> = column(row, 6, "distance histogram")?;
+
+        Ok(ObservedTcn {
             tcn,
             contact_start: UnixTime {
                 value: contact_start as u64,
@@ -77,31 +84,36 @@ impl TcnDaoImpl {
             min_distance: min_distance as f32,
             avg_distance: avg_distance as f32,
             total_count: total_count as usize,
-        }
+            distance_histogram: DistanceHistogram::from_db_str(distance_histogram.as_deref())?,
+        })
     }
 
-    // TCN string loaded from DB is assumed to be valid
-    fn db_tcn_str_to_tcn(str: String) -> TemporaryContactNumber {
-        let tcn_value_bytes_vec_res = hex::decode(str);
-        let tcn_value_bytes_vec = expect_log!(tcn_value_bytes_vec_res, "Invalid stored TCN format");
-        let tcn_value_bytes = byte_vec_to_16_byte_array(tcn_value_bytes_vec);
-        TemporaryContactNumber(tcn_value_bytes)
+    // overwrite() writes only valid TCNs: an invalid one was corrupted and its row is skipped
+    fn db_tcn_bytes_to_tcn(bytes: Vec<u8>) -> Result<TemporaryContactNumber, ServicesError> {
+        byte_vec_to_16_byte_array(bytes)
+            .map(TemporaryContactNumber)
+            .map_err(|e| ServicesError::Database(format!("Invalid stored TCN format: {}", e)))
+    }
+
+    fn db_tcn_values(tcns: &[TemporaryContactNumber]) -> Vec<Value> {
+        tcns.iter().map(|tcn| Value::Blob(tcn.0.to_vec())).collect()
     }
 
     pub fn new(db: Arc<Database>) -> TcnDaoImpl {
-        Self::create_table_if_not_exists(&db);
         TcnDaoImpl { db }
     }
 }
 
 impl TcnDao for TcnDaoImpl {
+    // Large read (used for matching): on the read-only connection, so it doesn't block flushes
     fn all(&self) -> Result<Vec<ObservedTcn>, ServicesError> {
         self.db
-            .query(
-                "select tcn, contact_start, contact_end, min_distance, avg_distance, total_count from tcn",
+            .read_query(
+                "select tcn, contact_start, contact_end, min_distance, avg_distance, total_count, distance_histogram from tcn",
                 NO_PARAMS,
                 |row| Self::to_tcn(row),
             )
+            .map(|result| result.rows)
             .map_err(ServicesError::from)
     }
 
@@ -109,80 +121,242 @@ impl TcnDao for TcnDaoImpl {
         &self,
         with: Vec<TemporaryContactNumber>,
     ) -> Result<Vec<ObservedTcn>, ServicesError> {
-        let tcn_strs: Vec<Value> = with.into_iter().map(|tcn| 
-            Value::Text(hex::encode(tcn.0))
-        )
-        .collect();
+        let tcn_values = Self::db_tcn_values(&with);
 
         self.db
             .query(
-                "select tcn, contact_start, contact_end, min_distance, avg_distance, total_count from tcn where tcn in rarray(?);",
-                params![Rc::new(tcn_strs)],
+                "select tcn, contact_start, contact_end, min_distance, avg_distance, total_count, distance_histogram from tcn where tcn in rarray(?);",
+                params![Rc::new(tcn_values)],
                 |row| Self::to_tcn(row),
             )
+            .map(|result| result.rows)
             .map_err(ServicesError::from)
     }
 
     fn overwrite(&self, observed_tcns: Vec<ObservedTcn>) -> Result<(), ServicesError> {
-        debug!("Overwriting db exposures with same TCNs, with: {:?}", observed_tcns);
+        debug!(
+            "Overwriting db exposures with same TCNs, with: {:?}",
+            observed_tcns
+        );
 
-        let tcn_strs: Vec<Value> = observed_tcns.clone().into_iter().map(|tcn| 
-            Value::Text(hex::encode(tcn.tcn.0))
-        )
-        .collect();
+        let tcn_values: Vec<Value> = observed_tcns
+            .iter()
+            .map(|tcn| Value::Blob(tcn.tcn.0.to_vec()))
+            .collect();
 
         self.db.transaction(|t| {
+            // Matched flags of the exposures being replaced. OR-ed into the new exposures.
+            let matched_res: Result<HashMap<Vec<u8>, Option<String>>, rusqlite::Error> = t
+                .prepare("select tcn, min(matched_report_id) from tcn where tcn in rarray(?) and matched = 1 group by tcn;")
+                .and_then(|mut statement| {
+                    let matched = statement
+                        .query_map(params![Rc::new(tcn_values.clone())], |row| Ok((row.get(0)?, row.get(1)?)))?
+                        .collect();
+                    matched
+                });
+            let matched = matched_res.map_err(ServicesError::from)?;
+
             // Delete all the exposures for TCNs
-            let delete_res = t.execute("delete from tcn where tcn in rarray(?);", params![Rc::new(tcn_strs)]);
-            if delete_res.is_err() {
-                return Err(ServicesError::General("Delete TCNs failed".to_owned()))
-            } 
+            let delete_res = t.execute("delete from tcn where tcn in rarray(?);", params![Rc::new(tcn_values)]);
+            if let Err(error) = delete_res {
+                return Err(write_error(error, "Delete TCNs failed"))
+            }
 
-            // Insert up to date exposures
+            // Insert up to date exposures. A window passed twice replaces the first one: the
+            // (tcn, contact_start) index is unique, and the overwrite has to stay idempotent.
             for tcn in observed_tcns {
-                let tcn_str = hex::encode(tcn.tcn.0);
-                let insert_res = t.execute("insert into tcn(tcn, contact_start, contact_end, min_distance, avg_distance, total_count) values(?1, ?2, ?3, ?4, ?5, ?6)",
+                let tcn_bytes = tcn.tcn.0.to_vec();
+                // Out of range values roll back the transaction, including the delete.
+                let contact_start = to_db_i64(tcn.contact_start.value, "TCN contact start")?;
+                let contact_end = to_db_i64(tcn.contact_end.value, "TCN contact end")?;
+                let total_count = to_db_i64(tcn.total_count, "TCN total count")?;
+                let matched_report_id = matched.get(&tcn_bytes);
+                let insert_res = t.execute("insert or replace into tcn(tcn, contact_start, contact_end, min_distance, avg_distance, total_count, matched, matched_report_id, distance_histogram) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
                 params![
-                    tcn_str,
-                    tcn.contact_start.value as i64,
-                    tcn.contact_end.value as i64,
+                    tcn_bytes,
+                    contact_start,
+                    contact_end,
                     tcn.min_distance as f64, // db requires f64 / real
                     tcn.avg_distance as f64, // db requires f64 / real
-                    tcn.total_count as i64
+                    total_count,
+                    matched_report_id.is_some(),
+                    matched_report_id.cloned().flatten(),
+                    tcn.distance_histogram.to_db_str()
                 ]);
 
-                if insert_res.is_err() {
-                    return Err(ServicesError::General("Insert TCN failed".to_owned()))
+                if let Err(error) = insert_res {
+                    return Err(write_error(error, "Insert TCN failed"))
                 }
             }
 
             Ok(())
         })
     }
+
+    fn mark_matched(
+        &self,
+        tcns: Vec<TemporaryContactNumber>,
+        report_id: &str,
+    ) -> Result<(), ServicesError> {
+        let tcn_values = Self::db_tcn_values(&tcns);
+
+        self.db
+            .execute_sql(
+                "update tcn set matched = 1, matched_report_id = coalesce(matched_report_id, ?1) where tcn in rarray(?2);",
+                params![report_id, Rc::new(tcn_values)],
+            )
+            .map(|_| ())
+            .map_err(ServicesError::from)
+    }
+
+    fn matched_report_ids(
+        &self,
+        tcns: Vec<TemporaryContactNumber>,
+    ) -> Result<Vec<String>, ServicesError> {
+        let tcn_values = Self::db_tcn_values(&tcns);
+
+        self.db
+            .query(
+                "select distinct matched_report_id from tcn where tcn in rarray(?1) and matched_report_id is not null order by matched_report_id;",
+                params![Rc::new(tcn_values)],
+                |row| column(row, 0, "matched_report_id"),
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
+
+    fn stats(&self) -> Result<TcnStats, ServicesError> {
+        self.db
+            .query_row(
+                "select count(distinct tcn), count(distinct case when matched = 1 then tcn end) from tcn",
+                NO_PARAMS,
+                |row| {
+                    let total: i64 = row.get(0)?;
+                    let matched: i64 = row.get(1)?;
+                    Ok(TcnStats {
+                        total: total as u64,
+                        matched: matched as u64,
+                        unmatched: (total - matched) as u64,
+                    })
+                },
+            )
+            .map_err(ServicesError::from)
+    }
+
+    fn append_scratch(&self, observed_tcn: &ObservedTcn) -> Result<(), ServicesError> {
+        let contact_start = to_db_i64(observed_tcn.contact_start.value, "TCN contact start")?;
+        let contact_end = to_db_i64(observed_tcn.contact_end.value, "TCN contact end")?;
+        let total_count = to_db_i64(observed_tcn.total_count, "TCN total count")?;
+        self.db
+            .execute_sql(
+                "insert into tcn_scratch(tcn, contact_start, contact_end, min_distance, avg_distance, total_count, distance_histogram) values(?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    observed_tcn.tcn.0.to_vec(),
+                    contact_start,
+                    contact_end,
+                    observed_tcn.min_distance as f64, // db requires f64 / real
+                    observed_tcn.avg_distance as f64, // db requires f64 / real
+                    total_count,
+                    observed_tcn.distance_histogram.to_db_str()
+                ],
+            )
+            .map(|_| ())
+            .map_err(ServicesError::from)
+    }
+
+    fn scratch(&self) -> Result<Vec<ObservedTcn>, ServicesError> {
+        self.db
+            .query(
+                "select tcn, contact_start, contact_end, min_distance, avg_distance, total_count, distance_histogram from tcn_scratch order by id",
+                NO_PARAMS,
+                |row| Self::to_tcn(row),
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
+
+    fn last_scratch_id(&self) -> Result<Option<i64>, ServicesError> {
+        self.db
+            .query_row("select max(id) from tcn_scratch", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .map_err(ServicesError::from)
+    }
+
+    fn delete_scratch_up_to(&self, id: i64) -> Result<(), ServicesError> {
+        self.db
+            .execute_sql("delete from tcn_scratch where id <= ?1", params![id])
+            .map(|_| ())
+            .map_err(ServicesError::from)
+    }
+
+    fn delete_ended_before(&self, contact_end: u64) -> Result<usize, ServicesError> {
+        let contact_end = to_db_i64(contact_end, "TCN contact end")?;
+        self.db
+            .execute_sql(
+                "delete from tcn where contact_end < ?1",
+                params![contact_end],
+            )
+            .map_err(ServicesError::from)
+    }
+
+    fn contact_time_range(&self) -> Result<Option<(UnixTime, UnixTime)>, ServicesError> {
+        self.db
+            .query_row(
+                "select min(contact_start), max(contact_end) from tcn",
+                NO_PARAMS,
+                |row| {
+                    let start: Option<i64> = row.get(0)?;
+                    let end: Option<i64> = row.get(1)?;
+                    Ok(start.zip(end).map(|(start, end)| {
+                        (
+                            UnixTime {
+                                value: start.max(0) as u64,
+                            },
+                            UnixTime {
+                                value: end.max(0) as u64,
+                            },
+                        )
+                    }))
+                },
+            )
+            .map_err(ServicesError::from)
+    }
+}
+
+// Keeps StorageFull, so the flush can back off (see ObservedTcnProcessorImpl)
+fn write_error(error: rusqlite::Error, message: &str) -> ServicesError {
+    match ServicesError::from(error) {
+        ServicesError::StorageFull => ServicesError::StorageFull,
+        _ => ServicesError::General(message.to_owned()),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rusqlite::Connection;
-    use crate::{tcn_recording::tcn_batches_manager::TcnBatchesManager, reports_update::exposure::ExposureGrouper};
+    use crate::{
+        config::RiskConfig,
+        expect_log,
+        reports_update::exposure::ExposureGrouper,
+        tcn_recording::tcn_batches_manager::TcnBatchesManager,
+        test_support::{fixtures::VERIFICATION_REPORT_TCN, migrated_database},
+    };
+    use std::time::Instant;
 
     #[test]
     fn saves_and_loads_observed_tcn() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = TcnDaoImpl::new(database);
 
         let observed_tcn = ObservedTcn {
-            tcn: TemporaryContactNumber([
-                24, 229, 125, 245, 98, 86, 219, 221, 172, 25, 232, 150, 206, 66, 164, 173,
-            ]),
+            tcn: TemporaryContactNumber(VERIFICATION_REPORT_TCN),
             contact_start: UnixTime { value: 1590528300 },
             contact_end: UnixTime { value: 1590528301 },
             min_distance: 0.0,
             avg_distance: 0.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let save_res = tcn_dao.overwrite(vec![observed_tcn.clone()]);
@@ -197,22 +371,44 @@ mod tests {
         assert_eq!(loaded_tcns[0], observed_tcn);
     }
 
+    #[test]
+    fn saves_and_loads_distance_histogram() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database);
+
+        let mut distance_histogram = DistanceHistogram::with_sample(0.4);
+        distance_histogram.merge(&DistanceHistogram::with_sample(3.2));
+        let observed_tcn = ObservedTcn {
+            distance_histogram,
+            ..test_tcn(VERIFICATION_REPORT_TCN, 1590528300)
+        };
+
+        let save_res = tcn_dao.overwrite(vec![observed_tcn.clone()]);
+        assert!(save_res.is_ok());
+        let loaded_tcns_res = tcn_dao.all();
+        assert!(loaded_tcns_res.is_ok());
+        assert_eq!(vec![observed_tcn.clone()], loaded_tcns_res.unwrap());
+
+        let append_res = tcn_dao.append_scratch(&observed_tcn);
+        assert!(append_res.is_ok());
+        let scratch_res = tcn_dao.scratch();
+        assert!(scratch_res.is_ok());
+        assert_eq!(vec![observed_tcn], scratch_res.unwrap());
+    }
+
     #[test]
     fn saves_and_loads_multiple_tcns() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = TcnDaoImpl::new(database);
 
         let observed_tcn_1 = ObservedTcn {
-            tcn: TemporaryContactNumber([
-                24, 229, 125, 245, 98, 86, 219, 221, 172, 25, 232, 150, 206, 66, 164, 173,
-            ]),
+            tcn: TemporaryContactNumber(VERIFICATION_REPORT_TCN),
             contact_start: UnixTime { value: 1590528300 },
             contact_end: UnixTime { value: 1590528301 },
             min_distance: 0.0,
             avg_distance: 0.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         let observed_tcn_2 = ObservedTcn {
             tcn: TemporaryContactNumber([
@@ -223,6 +419,7 @@ mod tests {
             min_distance: 0.0,
             avg_distance: 0.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         let observed_tcn_3 = ObservedTcn {
             tcn: TemporaryContactNumber([
@@ -233,6 +430,7 @@ mod tests {
             min_distance: 0.0,
             avg_distance: 0.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let save_res_1 = tcn_dao.overwrite(vec![observed_tcn_1.clone()]);
@@ -253,11 +451,73 @@ mod tests {
         assert_eq!(loaded_tcns[2], observed_tcn_3);
     }
 
+    #[test]
+    fn test_overwrite_out_of_range_fails_without_changes() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database);
+
+        let observed_tcn = ObservedTcn {
+            tcn: TemporaryContactNumber(VERIFICATION_REPORT_TCN),
+            contact_start: UnixTime { value: 1590528300 },
+            contact_end: UnixTime {
+                value: i64::MAX as u64,
+            },
+            min_distance: 0.0,
+            avg_distance: 0.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let save_res = tcn_dao.overwrite(vec![observed_tcn.clone()]);
+        assert!(save_res.is_ok());
+
+        let out_of_range_tcn = ObservedTcn {
+            contact_end: UnixTime {
+                value: i64::MAX as u64 + 1,
+            },
+            ..observed_tcn.clone()
+        };
+        let save_res = tcn_dao.overwrite(vec![out_of_range_tcn]);
+        match save_res {
+            Err(ServicesError::Database(_)) => {}
+            other => panic!("Expected database error, got: {:?}", other),
+        }
+
+        // The delete was rolled back too
+        let loaded_tcns_res = tcn_dao.all();
+        assert!(loaded_tcns_res.is_ok());
+        assert_eq!(loaded_tcns_res.unwrap(), vec![observed_tcn]);
+    }
+
+    #[test]
+    fn test_overwrite_keeps_one_row_per_window() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database);
+
+        let first = test_tcn([1; 16], 1000);
+        let second = test_tcn([1; 16], 2000);
+        assert!(tcn_dao
+            .overwrite(vec![first.clone(), second.clone()])
+            .is_ok());
+        assert!(tcn_dao
+            .overwrite(vec![first.clone(), second.clone()])
+            .is_ok());
+        assert_eq!(vec![first.clone(), second.clone()], all_sorted(&tcn_dao));
+
+        // A window passed twice: the last one is stored
+        let updated_first = ObservedTcn {
+            contact_end: UnixTime { value: 1500 },
+            total_count: 2,
+            ..first.clone()
+        };
+        assert!(tcn_dao
+            .overwrite(vec![first, updated_first.clone(), second.clone()])
+            .is_ok());
+        assert_eq!(vec![updated_first, second], all_sorted(&tcn_dao));
+    }
+
     #[test]
     fn test_finds_tcn() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let stored_tcn1 = ObservedTcn {
@@ -267,6 +527,7 @@ mod tests {
             min_distance: 0.4,
             avg_distance: 0.4,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let stored_tcn2 = ObservedTcn {
@@ -276,6 +537,7 @@ mod tests {
             min_distance: 1.8,
             avg_distance: 1.8,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let stored_tcn3 = ObservedTcn {
@@ -285,6 +547,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 2.3,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let save_res = tcn_dao.overwrite(vec![
@@ -313,9 +576,7 @@ mod tests {
     
     #[test]
     fn test_multiple_exposures_updated_correctly() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let batches_manager =
@@ -328,6 +589,7 @@ mod tests {
             min_distance: 0.4,
             avg_distance: 0.4,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let stored_tcn2 = ObservedTcn {
@@ -337,6 +599,7 @@ mod tests {
             min_distance: 2.0,
             avg_distance: 2.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         let save_res = tcn_dao.overwrite(vec![stored_tcn1.clone(), stored_tcn2.clone()]);
         assert!(save_res.is_ok());
@@ -348,6 +611,7 @@ mod tests {
             min_distance: 1.0,
             avg_distance: 1.0,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         batches_manager.push(tcn.clone());
@@ -373,7 +637,8 @@ mod tests {
                 contact_end: UnixTime { value: 3000 },
                 min_distance: 0.4,
                 avg_distance: 0.4,
-                total_count: 1
+                total_count: 1,
+                distance_histogram: DistanceHistogram::default()
             }
         );
         // The new TCN was merged with stored_tcn2
@@ -385,8 +650,282 @@ mod tests {
                 contact_end: UnixTime { value: 9000 },
                 min_distance: 1.0,
                 avg_distance: 1.5, // (2.0 + 1.0) / (1 + 1),
-                total_count: 2     // 1 + 1
+                total_count: 2,    // 1 + 1
+                distance_histogram: DistanceHistogram::default()
             }
         );
     }
+
+    #[test]
+    fn test_marks_matched_tcns_and_counts_them() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database.clone());
+
+        let save_res = tcn_dao.overwrite(vec![
+            test_tcn([0; 16], 1000),
+            test_tcn([0; 16], 5000),
+            test_tcn([1; 16], 1000),
+            test_tcn([2; 16], 1000),
+        ]);
+        assert!(save_res.is_ok());
+
+        let mark_res = tcn_dao.mark_matched(vec![TemporaryContactNumber([0; 16])], "report1");
+        assert!(mark_res.is_ok());
+        // Already matched TCN keeps its report
+        let mark_res = tcn_dao.mark_matched(
+            vec![
+                TemporaryContactNumber([0; 16]),
+                TemporaryContactNumber([2; 16]),
+            ],
+            "report2",
+        );
+        assert!(mark_res.is_ok());
+
+        assert_eq!(
+            vec![
+                (tcn_bytes(0), true, Some("report1".to_owned())),
+                (tcn_bytes(0), true, Some("report1".to_owned())),
+                (tcn_bytes(1), false, None),
+                (tcn_bytes(2), true, Some("report2".to_owned())),
+            ],
+            matched_flags(&database)
+        );
+
+        let stats_res = tcn_dao.stats();
+        assert!(stats_res.is_ok());
+        assert_eq!(
+            TcnStats {
+                total: 3,
+                matched: 2,
+                unmatched: 1
+            },
+            stats_res.unwrap()
+        );
+
+        let report_ids_res = tcn_dao.matched_report_ids(vec![
+            TemporaryContactNumber([0; 16]),
+            TemporaryContactNumber([1; 16]),
+            TemporaryContactNumber([2; 16]),
+        ]);
+        assert!(report_ids_res.is_ok());
+        assert_eq!(
+            vec!["report1".to_owned(), "report2".to_owned()],
+            report_ids_res.unwrap()
+        );
+        let report_ids_res = tcn_dao.matched_report_ids(vec![TemporaryContactNumber([1; 16])]);
+        assert!(report_ids_res.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_matched_flag_survives_overwrite() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        let save_res = tcn_dao.overwrite(vec![test_tcn([0; 16], 1000), test_tcn([1; 16], 1000)]);
+        assert!(save_res.is_ok());
+        let mark_res = tcn_dao.mark_matched(vec![TemporaryContactNumber([0; 16])], "report1");
+        assert!(mark_res.is_ok());
+
+        // Merged with the stored exposure
+        batches_manager.push(test_tcn([0; 16], 1500));
+        // New exposure of the same TCN
+        batches_manager.push(test_tcn([0; 16], 9000));
+        batches_manager.push(test_tcn([1; 16], 1500));
+        let flush_res = batches_manager.flush();
+        assert!(flush_res.is_ok());
+
+        assert_eq!(
+            vec![
+                (tcn_bytes(0), true, Some("report1".to_owned())),
+                (tcn_bytes(0), true, Some("report1".to_owned())),
+                (tcn_bytes(1), false, None),
+            ],
+            matched_flags(&database)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_tcn_rows_are_skipped() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database.clone());
+
+        let stored_tcn = test_tcn([0; 16], 1000);
+        let save_res = tcn_dao.overwrite(vec![stored_tcn.clone()]);
+        assert!(save_res.is_ok());
+
+        let insert_res = database.execute_sql(
+            "insert into tcn(tcn, contact_start, contact_end, min_distance, avg_distance, total_count)
+            values(x'0000', 1000, 1100, 1.0, 1.0, 1), ('00000000000000000000000000000000', 1000, 1100, 1.0, 1.0, 1),
+            (?1, 'abc', 1100, 1.0, 1.0, 1)",
+            params![tcn_bytes(1)],
+        );
+        assert!(insert_res.is_ok());
+
+        let all_res = tcn_dao.all();
+        assert!(all_res.is_ok());
+        assert_eq!(vec![stored_tcn.clone()], all_res.unwrap());
+
+        let find_res = tcn_dao.find_tcns(vec![
+            TemporaryContactNumber([0; 16]),
+            TemporaryContactNumber([1; 16]),
+        ]);
+        assert!(find_res.is_ok());
+        assert_eq!(vec![stored_tcn], find_res.unwrap());
+    }
+
+    #[test]
+    fn test_deletes_tcns_ended_before() {
+        let tcn_dao = TcnDaoImpl::new(migrated_database());
+        let old_tcn = test_tcn([1; 16], 1590000000);
+        let recent_tcn = test_tcn([2; 16], 1590500000);
+        assert!(tcn_dao.overwrite(vec![old_tcn, recent_tcn.clone()]).is_ok());
+
+        // Ends at 1590000100
+        assert_eq!(0, tcn_dao.delete_ended_before(1590000100).unwrap());
+        assert_eq!(1, tcn_dao.delete_ended_before(1590000101).unwrap());
+        assert_eq!(vec![recent_tcn], tcn_dao.all().unwrap());
+    }
+
+    #[test]
+    fn test_keeps_tcns_straddling_the_cutoff() {
+        let tcn_dao = TcnDaoImpl::new(migrated_database());
+        let risk_config = RiskConfig::default();
+        let now = UnixTime { value: 1591706000 };
+        let cutoff = risk_config.oldest_alert_contact_end(&now);
+        assert_eq!(now.value - 14 * 24 * 60 * 60, cutoff);
+
+        let ended_before = ObservedTcn {
+            contact_end: UnixTime { value: cutoff - 1 },
+            ..test_tcn([1; 16], cutoff - 3600)
+        };
+        let straddling = ObservedTcn {
+            contact_end: UnixTime { value: cutoff + 1 },
+            ..test_tcn([2; 16], cutoff - 3600)
+        };
+        let ending_at_cutoff = ObservedTcn {
+            contact_end: UnixTime { value: cutoff },
+            ..test_tcn([3; 16], cutoff - 3600)
+        };
+        let recent = test_tcn([4; 16], cutoff + 3600);
+        let save_res = tcn_dao.overwrite(vec![
+            ended_before,
+            straddling.clone(),
+            ending_at_cutoff.clone(),
+            recent.clone(),
+        ]);
+        assert!(save_res.is_ok());
+
+        assert_eq!(1, tcn_dao.delete_ended_before(cutoff).unwrap());
+        let mut tcns = tcn_dao.all().unwrap();
+        tcns.sort_by_key(|tcn| tcn.tcn.0);
+        assert_eq!(vec![straddling, ending_at_cutoff, recent], tcns);
+    }
+
+    #[test]
+    fn test_finds_tcns_among_many_with_index() {
+        let database = migrated_database();
+        let tcn_dao = TcnDaoImpl::new(database.clone());
+
+        let stored_tcns: Vec<ObservedTcn> = (0..5000u32)
+            .map(|index| {
+                let mut bytes = [0; 16];
+                bytes[..4].copy_from_slice(&index.to_be_bytes());
+                test_tcn(bytes, 1000 + index as u64)
+            })
+            .collect();
+        assert!(tcn_dao.overwrite(stored_tcns.clone()).is_ok());
+
+        let start = Instant::now();
+        let find_res = tcn_dao.find_tcns(vec![
+            stored_tcns[4999].tcn.clone(),
+            stored_tcns[7].tcn.clone(),
+            TemporaryContactNumber([0xff; 16]),
+        ]);
+        debug!("Found TCNs in {:?}", start.elapsed());
+        assert!(find_res.is_ok());
+        let mut found = find_res.unwrap();
+        found.sort_by_key(|tcn| tcn.contact_start.value);
+        assert_eq!(
+            vec![stored_tcns[7].clone(), stored_tcns[4999].clone()],
+            found
+        );
+
+        // The lookups of find_tcns and overwrite don't scan the table
+        for sql in &[
+            "select * from tcn where tcn in rarray(?);",
+            "delete from tcn where tcn in rarray(?);",
+        ] {
+            let plan_res = database.query(
+                &format!("explain query plan {}", sql),
+                params![Rc::new(Vec::<Value>::new())],
+                |row| column::<String>(row, 3, "detail"),
+            );
+            let plan = expect_log!(plan_res, "Couldn't read query plan").rows;
+            assert!(
+                plan.iter()
+                    .any(|detail| detail.contains("INDEX tcn_tcn_contact_start")),
+                "Query plan: {:?}",
+                plan
+            );
+        }
+    }
+
+    #[test]
+    fn test_contact_time_range() {
+        let tcn_dao = TcnDaoImpl::new(migrated_database());
+        assert_eq!(None, tcn_dao.contact_time_range().unwrap());
+
+        let save_res = tcn_dao.overwrite(vec![
+            test_tcn([1; 16], 1590500000),
+            test_tcn([2; 16], 1590000000),
+            test_tcn([3; 16], 1590200000),
+        ]);
+        assert!(save_res.is_ok());
+
+        assert_eq!(
+            Some((
+                UnixTime { value: 1590000000 },
+                UnixTime { value: 1590500100 }
+            )),
+            tcn_dao.contact_time_range().unwrap()
+        );
+    }
+
+    fn test_tcn(bytes: [u8; 16], contact_start: u64) -> ObservedTcn {
+        ObservedTcn {
+            tcn: TemporaryContactNumber(bytes),
+            contact_start: UnixTime {
+                value: contact_start,
+            },
+            contact_end: UnixTime {
+                value: contact_start + 100,
+            },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        }
+    }
+
+    fn all_sorted(tcn_dao: &TcnDaoImpl) -> Vec<ObservedTcn> {
+        let all_res = tcn_dao.all();
+        let mut tcns = expect_log!(all_res, "Couldn't load TCNs");
+        tcns.sort_by_key(|tcn| (tcn.tcn.0, tcn.contact_start.value));
+        tcns
+    }
+
+    fn tcn_bytes(byte: u8) -> Vec<u8> {
+        vec![byte; 16]
+    }
+
+    fn matched_flags(database: &Database) -> Vec<(Vec<u8>, bool, Option<String>)> {
+        let flags_res = database.query(
+            "select tcn, matched, matched_report_id from tcn order by tcn, contact_start",
+            NO_PARAMS,
+            |row| Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2))),
+        );
+        expect_log!(flags_res, "Couldn't read matched flags").rows
+    }
 }
@@ -0,0 +1,253 @@
+use super::database::{column, to_db_i64, Database};
+use crate::{
+    errors::ServicesError, housekeeping::housekeeper::HousekeepingRun,
+    reports_update::reports_updater::UpdateMetrics,
+};
+use rusqlite::{params, Row, NO_PARAMS};
+use std::sync::Arc;
+
+// Number of runs kept. Older runs are deleted when a new one is saved.
+pub const UPDATE_HISTORY_SIZE: i64 = 20;
+// Like UPDATE_HISTORY_SIZE, for the housekeeping runs (a run per task)
+pub const HOUSEKEEPING_HISTORY_SIZE: i64 = 50;
+
+pub trait UpdateRunDao: Send + Sync {
+    fn save(&self, metrics: &UpdateMetrics) -> Result<(), ServicesError>;
+    // Most recent run first
+    fn all(&self) -> Result<Vec<UpdateMetrics>, ServicesError>;
+
+    fn save_housekeeping_runs(&self, runs: &[HousekeepingRun]) -> Result<(), ServicesError>;
+    // Most recent run first
+    fn housekeeping_history(&self) -> Result<Vec<HousekeepingRun>, ServicesError>;
+}
+
+pub struct UpdateRunDaoImpl {
+    db: Arc<Database>,
+}
+
+impl UpdateRunDaoImpl {
+    pub fn new(db: Arc<Database>) -> UpdateRunDaoImpl {
+        UpdateRunDaoImpl { db }
+    }
+
+    fn to_metrics(row: &Row) -> Result<UpdateMetrics, ServicesError> {
+        let time: i64 = column(row, 0, "time")?;
+        let duration_millis: i64 = column(row, 1, "duration")?;
+        let intervals: i64 = column(row, 2, "intervals")?;
+        let reports: i64 = column(row, 3, "reports")?;
+        let matches: i64 = column(row, 4, "matches")?;
+        let oversized_intervals: i64 = column(row, 5, "oversized intervals")?;
+        let normalized_reports: i64 = column(row, 6, "normalized reports")?;
+        let expired_exposures: i64 = column(row, 7, "expired exposures")?;
+        let filtered_reports: i64 = column(row, 8, "filtered reports")?;
+        let unchanged_intervals: i64 = column(row, 9, "unchanged intervals")?;
+        let invalid_reports: i64 = column(row, 10, "invalid reports")?;
+        let error_code: Option<String> = column(row, 11, "error code")?;
+
+        Ok(UpdateMetrics {
+            time: time as u64,
+            duration_millis: duration_millis as u64,
+            intervals: intervals as u32,
+            reports: reports as u32,
+            matches: matches as u32,
+            oversized_intervals: oversized_intervals as u32,
+            normalized_reports: normalized_reports as u32,
+            expired_exposures: expired_exposures as u32,
+            filtered_reports: filtered_reports as u32,
+            unchanged_intervals: unchanged_intervals as u32,
+            invalid_reports: invalid_reports as u32,
+            error_code,
+        })
+    }
+
+    fn to_housekeeping_run(row: &Row) -> Result<HousekeepingRun, ServicesError> {
+        let time: i64 = column(row, 0, "time")?;
+        let task: String = column(row, 1, "task")?;
+        let duration_millis: i64 = column(row, 2, "duration")?;
+        let error_code: Option<String> = column(row, 3, "error code")?;
+
+        Ok(HousekeepingRun {
+            time: time as u64,
+            task,
+            duration_millis: duration_millis as u64,
+            error_code,
+        })
+    }
+}
+
+impl UpdateRunDao for UpdateRunDaoImpl {
+    fn save(&self, metrics: &UpdateMetrics) -> Result<(), ServicesError> {
+        let time = to_db_i64(metrics.time, "Update run time")?;
+        let duration_millis = to_db_i64(metrics.duration_millis, "Update run duration")?;
+
+        self.db.transaction(|t| {
+            t.execute(
+                "insert into update_run(time, duration_millis, intervals, reports, matches, oversized_intervals, normalized_reports, expired_exposures, filtered_reports, unchanged_intervals, invalid_reports, error_code)
+                values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    time,
+                    duration_millis,
+                    metrics.intervals,
+                    metrics.reports,
+                    metrics.matches,
+                    metrics.oversized_intervals,
+                    metrics.normalized_reports,
+                    metrics.expired_exposures,
+                    metrics.filtered_reports,
+                    metrics.unchanged_intervals,
+                    metrics.invalid_reports,
+                    metrics.error_code
+                ],
+            )?;
+            // FIFO: keep only the most recent runs
+            t.execute(
+                "delete from update_run where id not in
+                (select id from update_run order by id desc limit ?1)",
+                params![UPDATE_HISTORY_SIZE],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn all(&self) -> Result<Vec<UpdateMetrics>, ServicesError> {
+        self.db
+            .query(
+                "select time, duration_millis, intervals, reports, matches, oversized_intervals, normalized_reports, expired_exposures, filtered_reports, unchanged_intervals, invalid_reports, error_code
+                from update_run order by id desc",
+                NO_PARAMS,
+                |row| Self::to_metrics(row),
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
+
+    fn save_housekeeping_runs(&self, runs: &[HousekeepingRun]) -> Result<(), ServicesError> {
+        self.db.transaction(|t| {
+            for run in runs {
+                t.execute(
+                    "insert into housekeeping_run(time, task, duration_millis, error_code)
+                    values(?1, ?2, ?3, ?4)",
+                    params![
+                        to_db_i64(run.time, "Housekeeping run time")?,
+                        run.task,
+                        to_db_i64(run.duration_millis, "Housekeeping run duration")?,
+                        run.error_code
+                    ],
+                )?;
+            }
+            t.execute(
+                "delete from housekeeping_run where id not in
+                (select id from housekeeping_run order by id desc limit ?1)",
+                params![HOUSEKEEPING_HISTORY_SIZE],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn housekeeping_history(&self) -> Result<Vec<HousekeepingRun>, ServicesError> {
+        self.db
+            .query(
+                "select time, task, duration_millis, error_code from housekeeping_run order by id desc",
+                NO_PARAMS,
+                |row| Self::to_housekeeping_run(row),
+            )
+            .map(|result| result.rows)
+            .map_err(ServicesError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::migrated_database;
+
+    #[test]
+    fn test_saves_and_loads_update_runs() {
+        let update_run_dao = UpdateRunDaoImpl::new(migrated_database());
+
+        let successful_run = UpdateMetrics {
+            time: 1590528300,
+            duration_millis: 1200,
+            intervals: 3,
+            reports: 10,
+            matches: 1,
+            oversized_intervals: 0,
+            normalized_reports: 2,
+            expired_exposures: 1,
+            filtered_reports: 2,
+            unchanged_intervals: 1,
+            invalid_reports: 1,
+            error_code: None,
+        };
+        let failed_run = UpdateMetrics {
+            time: 1590529300,
+            duration_millis: 300,
+            intervals: 0,
+            reports: 0,
+            matches: 0,
+            oversized_intervals: 1,
+            normalized_reports: 0,
+            expired_exposures: 0,
+            filtered_reports: 0,
+            unchanged_intervals: 0,
+            invalid_reports: 0,
+            error_code: Some("networking".to_owned()),
+        };
+
+        assert!(update_run_dao.save(&successful_run).is_ok());
+        assert!(update_run_dao.save(&failed_run).is_ok());
+
+        let runs_res = update_run_dao.all();
+        assert!(runs_res.is_ok());
+        assert_eq!(runs_res.unwrap(), vec![failed_run, successful_run]);
+    }
+
+    #[test]
+    fn test_keeps_only_most_recent_runs() {
+        let update_run_dao = UpdateRunDaoImpl::new(migrated_database());
+
+        let runs_count = UPDATE_HISTORY_SIZE as u64 + 5;
+        for i in 0..runs_count {
+            let save_res = update_run_dao.save(&UpdateMetrics {
+                time: i,
+                ..UpdateMetrics::default()
+            });
+            assert!(save_res.is_ok());
+        }
+
+        let runs_res = update_run_dao.all();
+        assert!(runs_res.is_ok());
+        let times: Vec<u64> = runs_res.unwrap().into_iter().map(|run| run.time).collect();
+        let expected_times: Vec<u64> = (5..runs_count).rev().collect();
+        assert_eq!(times, expected_times);
+    }
+
+    #[test]
+    fn test_saves_and_loads_housekeeping_runs() {
+        let update_run_dao = UpdateRunDaoImpl::new(migrated_database());
+
+        let runs: Vec<HousekeepingRun> = (0..HOUSEKEEPING_HISTORY_SIZE as u64 + 2)
+            .map(|i| HousekeepingRun {
+                time: 1590528300 + i,
+                task: "vacuum".to_owned(),
+                duration_millis: 10,
+                error_code: if i % 2 == 0 {
+                    None
+                } else {
+                    Some("db".to_owned())
+                },
+            })
+            .collect();
+        assert!(update_run_dao.save_housekeeping_runs(&runs[..2]).is_ok());
+        assert!(update_run_dao.save_housekeeping_runs(&runs[2..]).is_ok());
+
+        let history_res = update_run_dao.housekeeping_history();
+        assert!(history_res.is_ok());
+        let history = history_res.unwrap();
+        assert_eq!(HOUSEKEEPING_HISTORY_SIZE as usize, history.len());
+        assert_eq!(runs.last(), history.first());
+        assert_eq!(runs[2], history[history.len() - 1]);
+        // Update runs are a separate history
+        assert!(update_run_dao.all().unwrap().is_empty());
+    }
+}
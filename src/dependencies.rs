@@ -1,14 +1,20 @@
-use crate::networking::{TcnApi, TcnApiImpl};
+use crate::networking::{AcknowledgementApiImpl, MetricsApiImpl, TcnApi, TcnApiImpl};
 use crate::{
+    config::{ClientVersion, CoreConfig, NetworkingConfig},
     database::{
         alert_dao::{AlertDao, AlertDaoImpl},
         database::Database,
-        migration::Migration,
+        migration::{Migration, MigrationReport, REQUIRED_DB_VERSION},
         preferences::{Preferences, PreferencesDao, PreferencesImpl},
         tcn_dao::{TcnDao, TcnDaoImpl},
+        update_run_dao::{UpdateRunDao, UpdateRunDaoImpl},
     },
     errors::ServicesError,
-    expect_log,
+    housekeeping::{
+        housekeeper::{Housekeeper, HousekeepingTask},
+        tasks::{CheckpointDatabaseTask, PruneTcnsTask, PurgeAlertsTask, VacuumDatabaseTask},
+    },
+    metrics::metrics_uploader::MetricsUploader,
     reporting::{
         memo::{MemoMapper, MemoMapperImpl},
         symptom_inputs::{SymptomInputs, SymptomInputsSubmitterImpl},
@@ -16,25 +22,30 @@ use crate::{
             SymptomInputsManagerImpl, SymptomInputsProcessor, SymptomInputsProcessorImpl,
         },
     },
+    reports_interval::UnixTime,
     reports_update::{
+        acknowledgement::{AcknowledgementService, AcknowledgementServiceImpl},
+        alert_reference::{AlertReferences, AlertReferencesImpl},
         exposure::ExposureGrouper,
-        reports_updater::ReportsUpdater,
-        tcn_matcher::{TcnMatcher, TcnMatcherRayon},
+        reports_updater::{ReportsUpdater, ReportsUpdaterBuilder},
+        tcn_matcher::{DefaultTcnMatcher, TcnMatcher},
     },
-    tcn_ext::tcn_keys::{TcnKeys, TcnKeysImpl},
+    tcn_ext::tcn_keys::{KeyIntegrityIssue, TcnKeys, TcnKeysImpl},
     tcn_recording::{
-        observed_tcn_processor::{ObservedTcnProcessor, ObservedTcnProcessorImpl},
+        flush_scheduler::TimerFlushScheduler,
+        observed_tcn_processor::{DistanceBounds, ObservedTcnProcessor, ObservedTcnProcessorImpl},
         tcn_batches_manager::TcnBatchesManager,
     },
 };
 use log::*;
-use once_cell::sync::OnceCell;
-use parking_lot::RwLock;
-use rusqlite::Connection;
-use std::sync::Arc;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::{fs, path::Path, sync::Arc, thread};
 
 #[allow(dead_code)]
-pub struct Dependencies<'a, A, B, C, D, F, G, H, I, J>
+pub struct Dependencies<'a, A, B, C, D, F, G, H, I, J, K, L, M>
 where
     A: Preferences,
     B: TcnDao,
@@ -45,64 +56,41 @@ where
     H: MemoMapper,
     I: TcnKeys,
     J: AlertDao,
+    K: UpdateRunDao,
+    L: AlertReferences,
+    M: AcknowledgementService,
 {
     pub api: &'a D,
-    pub reports_updater: ReportsUpdater<'a, A, B, C, D, H, J>,
+    pub reports_updater: ReportsUpdater<'a, A, B, C, D, H, J, K>,
     pub symptom_inputs_processor: F,
     pub observed_tcn_processor: G,
     pub tcn_keys: Arc<I>,
     pub alert_dao: Arc<J>,
+    pub alert_references: Arc<L>,
+    pub acknowledgement_service: M,
+    pub housekeeper: Housekeeper<A, K>,
+    pub client_version: ClientVersion,
+    // Db migrations run when the dependencies were created (no steps if the db was up to date)
+    pub migration_report: MigrationReport,
+    // Set if the stored keys were replaced when the dependencies were created (see TcnKeysImpl::check_integrity)
+    pub keys_reset: Option<KeyIntegrityIssue>,
 }
 
-pub static DEPENDENCIES: OnceCell<
-    Dependencies<
-        PreferencesImpl,
-        TcnDaoImpl,
-        TcnMatcherRayon,
-        TcnApiImpl,
-        SymptomInputsProcessorImpl<
-            SymptomInputsManagerImpl<
-                SymptomInputsSubmitterImpl<
-                    MemoMapperImpl,
-                    TcnKeysImpl<PreferencesImpl>,
-                    TcnApiImpl,
-                >,
-            >,
-        >,
-        ObservedTcnProcessorImpl<TcnDaoImpl>,
-        MemoMapperImpl,
-        TcnKeysImpl<PreferencesImpl>,
-        AlertDaoImpl,
-    >,
-> = OnceCell::new();
-
-pub fn bootstrap(db_path: &str) -> Result<(), ServicesError> {
-    info!("Bootstrapping with db path: {:?}", db_path);
-
-    let sqlite_path = format!("{}/db.sqlite", db_path);
-    debug!("Sqlite path: {:?}", sqlite_path);
-
-    let connection_res = Connection::open(sqlite_path);
-    let connection = expect_log!(connection_res, "Couldn't create database!");
-    let database = Arc::new(Database::new(connection));
-
-    let migration_handler = Migration::new(database.clone());
-    migration_handler.run_db_migrations(1);
-
-    if let Err(_) = DEPENDENCIES.set(create_dependencies(database)) {
-        return Err(ServicesError::General(
-            "Couldn't initialize dependencies".to_owned(),
-        ));
-    };
-
-    Ok(())
+// Result of bootstrap, for the app
+#[derive(Debug, Clone, Serialize)]
+pub struct BootstrapReport {
+    #[serde(flatten)]
+    pub migration: MigrationReport,
+    // Set if the stored keys were corrupted and replaced with new ones: the app should tell the user
+    // that reports can't cover the contacts before
+    pub keys_reset: Option<KeyIntegrityIssue>,
 }
 
-pub fn dependencies() -> &'static Dependencies<
+pub type CoreDependencies = Dependencies<
     'static,
     PreferencesImpl,
     TcnDaoImpl,
-    TcnMatcherRayon,
+    DefaultTcnMatcher,
     TcnApiImpl,
     SymptomInputsProcessorImpl<
         SymptomInputsManagerImpl<
@@ -111,6 +99,7 @@ pub fn dependencies() -> &'static Dependencies<
                 MemoMapperImpl,
                 TcnKeysImpl<PreferencesImpl>,
                 TcnApiImpl,
+                PreferencesImpl,
             >,
         >,
     >,
@@ -118,44 +107,246 @@ pub fn dependencies() -> &'static Dependencies<
     MemoMapperImpl,
     TcnKeysImpl<PreferencesImpl>,
     AlertDaoImpl,
-> {
-    let res = DEPENDENCIES
-        .get()
-        .ok_or(ServicesError::General("DEPENDENCIES not set".to_owned()));
-
-    // Note that the error message here is unlikely to appear on Android, as if DEPENDENCIES is not set
-    // most likely bootstrap hasn't been executed (which initializes the logger)
-    expect_log!(
-        res,
-        "DEPENDENCIES not set. Maybe app didn't call bootstrap?"
-    )
+    UpdateRunDaoImpl,
+    AlertReferencesImpl<PreferencesImpl, AlertDaoImpl>,
+    AcknowledgementServiceImpl<
+        AcknowledgementApiImpl,
+        AlertReferencesImpl<PreferencesImpl, AlertDaoImpl>,
+        AlertDaoImpl,
+    >,
+>;
+
+// Profile used by bootstrap(). Stored in "db.sqlite", like before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+struct ActiveProfile {
+    db_path: String,
+    config: CoreConfig,
+    name: String,
+    dependencies: Arc<CoreDependencies>,
+}
+
+static ACTIVE_PROFILE: Lazy<RwLock<Option<ActiveProfile>>> = Lazy::new(|| RwLock::new(None));
+
+// Serializes the profile switches. Separate from ACTIVE_PROFILE, which is only locked for writing to swap
+// the dependencies: the other operations aren't blocked while the new profile's database is migrated.
+static PROFILE_SWITCH: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+// The dependencies borrow the api for their whole life, so it's leaked: once per networking config.
+// Bootstraps and profile switches with a config that already has an api reuse it.
+static TCN_APIS: Lazy<Mutex<Vec<&'static TcnApiImpl>>> = Lazy::new(|| Mutex::new(vec![]));
+
+// Returns the db migrations run on bootstrap (for support diagnostics) and whether the keys were reset.
+pub fn bootstrap(db_path: &str, config: CoreConfig) -> Result<BootstrapReport, ServicesError> {
+    bootstrap_with_profile(db_path, DEFAULT_PROFILE, config)
+}
+
+// Each profile has its own database (and with it, preferences).
+pub fn bootstrap_with_profile(
+    db_path: &str,
+    profile: &str,
+    config: CoreConfig,
+) -> Result<BootstrapReport, ServicesError> {
+    info!(
+        "Bootstrapping with db path: {:?}, profile: {}, config: {:?}",
+        db_path, profile, config
+    );
+
+    config.validate()?;
+
+    let mut active_profile = ACTIVE_PROFILE.write();
+    if active_profile.is_some() {
+        return Err(ServicesError::General(
+            "Couldn't initialize dependencies".to_owned(),
+        ));
+    }
+
+    let dependencies = create_profile_dependencies(db_path, profile, &config)?;
+    let bootstrap_report = BootstrapReport {
+        migration: dependencies.migration_report.clone(),
+        keys_reset: dependencies.keys_reset,
+    };
+    *active_profile = Some(ActiveProfile {
+        db_path: db_path.to_owned(),
+        config,
+        name: profile.to_owned(),
+        dependencies: Arc::new(dependencies),
+    });
+
+    Ok(bootstrap_report)
+}
+
+// Dry run of the db migrations of the default profile: what bootstrap would migrate.
+// Can be called before bootstrap. Doesn't create or change the database.
+pub fn preview_migrations(db_path: &str) -> Result<MigrationReport, ServicesError> {
+    let sqlite_path = format!("{}/{}", db_path, sqlite_file_name(DEFAULT_PROFILE)?);
+    let conn = if Path::new(&sqlite_path).exists() {
+        Connection::open_with_flags(&sqlite_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    } else {
+        // Like the database bootstrap would create
+        Connection::open_in_memory()
+    }
+    .map_err(|e| {
+        ServicesError::FFIParameters(format!(
+            "Couldn't open database at {:?}: {}",
+            sqlite_path, e
+        ))
+    })?;
+
+    Migration::new(Arc::new(Database::new(conn))).preview_migrations(REQUIRED_DB_VERSION)
+}
+
+// Makes `profile` the active profile, creating its database if needed.
+// The previous profile's pending TCNs are flushed. Operations that already retrieved
+// its dependencies complete on it, operations started after the switch use the new profile.
+pub fn switch_profile(profile: &str) -> Result<(), ServicesError> {
+    info!("Switching to profile: {}", profile);
+
+    let _switch_guard = PROFILE_SWITCH.lock();
+
+    let (db_path, config) = {
+        let active_profile_guard = ACTIVE_PROFILE.read();
+        let active_profile = active_profile_guard
+            .as_ref()
+            .ok_or(ServicesError::NotBootstrapped)?;
+
+        if active_profile.name == profile {
+            return Ok(());
+        }
+        (
+            active_profile.db_path.clone(),
+            active_profile.config.clone(),
+        )
+    };
+
+    // Without locking the active profile: opening and migrating the database can take a while
+    let dependencies = create_profile_dependencies(&db_path, profile, &config)?;
+
+    let previous_dependencies = {
+        let mut active_profile_guard = ACTIVE_PROFILE.write();
+        let active_profile = active_profile_guard
+            .as_mut()
+            .ok_or(ServicesError::NotBootstrapped)?;
+        active_profile.name = profile.to_owned();
+        std::mem::replace(&mut active_profile.dependencies, Arc::new(dependencies))
+    };
+
+    previous_dependencies.observed_tcn_processor.stop();
+
+    Ok(())
+}
+
+// Stops the background work (periodic TCN flushing), flushing pending TCNs first.
+// Should be called before the app terminates the process or deletes the database.
+pub fn shutdown() -> Result<(), ServicesError> {
+    info!("Shutting down");
+
+    dependencies()?.observed_tcn_processor.stop();
+
+    Ok(())
+}
+
+// Err(NotBootstrapped) if bootstrap wasn't called or failed, to return an error to the app
+// instead of crashing.
+pub fn dependencies() -> Result<Arc<CoreDependencies>, ServicesError> {
+    ACTIVE_PROFILE
+        .read()
+        .as_ref()
+        .map(|active_profile| active_profile.dependencies.clone())
+        .ok_or(ServicesError::NotBootstrapped)
+}
+
+pub(crate) fn create_profile_dependencies(
+    db_path: &str,
+    profile: &str,
+    config: &CoreConfig,
+) -> Result<CoreDependencies, ServicesError> {
+    let sqlite_file_name = sqlite_file_name(profile)?;
+    prepare_db_dir(db_path)?;
+
+    let sqlite_path = format!("{}/{}", db_path, sqlite_file_name);
+    debug!("Sqlite path: {:?}", sqlite_path);
+
+    let database = Database::open_file(&sqlite_path).map_err(|e| {
+        ServicesError::FFIParameters(format!(
+            "Couldn't open database at {:?}: {}",
+            sqlite_path, e
+        ))
+    })?;
+    let database = Arc::new(database.slow_query_threshold(config.database.slow_query_threshold()));
+
+    // The DAOs expect the current schema: a database that can't be migrated fails the bootstrap
+    let migration_handler = Migration::new(database.clone());
+    let migration_report = migration_handler.run_db_migrations(REQUIRED_DB_VERSION)?;
+
+    let mut dependencies = create_dependencies(database, config, migration_report);
+    dependencies.keys_reset = dependencies.tcn_keys.check_integrity()?;
+
+    Ok(dependencies)
+}
+
+// Creates the directory if it doesn't exist and checks that it's writable,
+// so the app can fall back to another location instead of crashing.
+fn prepare_db_dir(db_path: &str) -> Result<(), ServicesError> {
+    fs::create_dir_all(db_path).map_err(|e| {
+        ServicesError::FFIParameters(format!("Couldn't create db directory {:?}: {}", db_path, e))
+    })?;
+
+    let probe_path = Path::new(db_path).join(".coepi_write_probe");
+    fs::write(&probe_path, b"")
+        .and_then(|_| fs::remove_file(&probe_path))
+        .map_err(|e| {
+            ServicesError::FFIParameters(format!(
+                "Db directory {:?} isn't writable: {}",
+                db_path, e
+            ))
+        })
+}
+
+fn sqlite_file_name(profile: &str) -> Result<String, ServicesError> {
+    if profile.is_empty()
+        || !profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ServicesError::FFIParameters(format!(
+            "Invalid profile name: {:?}",
+            profile
+        )));
+    }
+
+    if profile == DEFAULT_PROFILE {
+        Ok("db.sqlite".to_owned())
+    } else {
+        Ok(format!("db_{}.sqlite", profile))
+    }
+}
+
+// See TCN_APIS
+fn tcn_api(config: &NetworkingConfig) -> &'static TcnApiImpl {
+    let api = TcnApiImpl {
+        api_url: config.api_url.clone(),
+        max_reports_bytes: config.max_reports_bytes_per_interval,
+        max_report_pages: config.max_report_pages_per_interval,
+    };
+    let mut apis = TCN_APIS.lock();
+    match apis.iter().find(|existing| ***existing == api) {
+        Some(existing) => *existing,
+        None => {
+            let leaked: &'static TcnApiImpl = Box::leak(Box::new(api));
+            apis.push(leaked);
+            leaked
+        }
+    }
 }
 
 fn create_dependencies(
     database: Arc<Database>,
-    // required_db_version: i32,
-) -> Dependencies<
-    'static,
-    PreferencesImpl,
-    TcnDaoImpl,
-    TcnMatcherRayon,
-    TcnApiImpl,
-    SymptomInputsProcessorImpl<
-        SymptomInputsManagerImpl<
-            SymptomInputsSubmitterImpl<
-                'static,
-                MemoMapperImpl,
-                TcnKeysImpl<PreferencesImpl>,
-                TcnApiImpl,
-            >,
-        >,
-    >,
-    ObservedTcnProcessorImpl<TcnDaoImpl>,
-    MemoMapperImpl,
-    TcnKeysImpl<PreferencesImpl>,
-    AlertDaoImpl,
-> {
-    let api = &TcnApiImpl {};
+    config: &CoreConfig,
+    migration_report: MigrationReport,
+) -> CoreDependencies {
+    let api = tcn_api(&config.networking);
+    let client_version = config.client_version();
 
     let preferences_dao = PreferencesDao::new(database.clone());
     let preferences = Arc::new(PreferencesImpl {
@@ -164,44 +355,354 @@ fn create_dependencies(
 
     let memo_mapper = &MemoMapperImpl {};
 
-    let tcn_keys = Arc::new(TcnKeysImpl {
-        preferences: preferences.clone(),
-    });
+    let tcn_keys = Arc::new(TcnKeysImpl::new(preferences.clone()));
 
     let symptom_inputs_submitter = SymptomInputsSubmitterImpl {
         memo_mapper,
         tcn_keys: tcn_keys.clone(),
         api,
+        client_version: client_version.clone(),
+        preferences: preferences.clone(),
+        clock: UnixTime::now,
+        sleep: thread::sleep,
     };
 
     let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
-    let alert_dao = Arc::new(AlertDaoImpl::new(database));
+    let alert_dao = Arc::new(
+        AlertDaoImpl::new(database.clone()).use_p10_distance(config.risk.use_p10_distance),
+    );
+    let update_run_dao = Arc::new(UpdateRunDaoImpl::new(database.clone()));
+
+    let exposure_grouper = ExposureGrouper::default();
+
+    let alert_references = Arc::new(AlertReferencesImpl::new(
+        preferences.clone(),
+        alert_dao.clone(),
+    ));
+
+    // Off unless the endpoint is configured
+    let acknowledgement_service = AcknowledgementServiceImpl::new(
+        AcknowledgementApiImpl {
+            endpoint: config.acknowledgement.endpoint.clone(),
+        },
+        alert_references.clone(),
+        alert_dao.clone(),
+        config.acknowledgement.clone(),
+    );
+
+    let metrics_uploader = MetricsUploader::new(
+        MetricsApiImpl {
+            endpoint: config.metrics.endpoint.clone(),
+        },
+        preferences.clone(),
+        tcn_dao.clone(),
+        alert_dao.clone(),
+        config.metrics.clone(),
+    );
+
+    // In order: the database maintenance follows the deletions
+    let housekeeping_tasks: Vec<Box<dyn HousekeepingTask>> = vec![
+        Box::new(PruneTcnsTask {
+            tcn_dao: tcn_dao.clone(),
+            risk_config: config.risk.clone(),
+        }),
+        Box::new(PurgeAlertsTask {
+            alert_dao: alert_dao.clone(),
+            risk_config: config.risk.clone(),
+        }),
+        // Opt-in aggregate metrics (at most daily)
+        Box::new(metrics_uploader),
+        Box::new(CheckpointDatabaseTask {
+            database: database.clone(),
+        }),
+        Box::new(VacuumDatabaseTask { database }),
+    ];
+    let housekeeper = Housekeeper::new(
+        preferences.clone(),
+        update_run_dao.clone(),
+        housekeeping_tasks,
+    );
 
-    let exposure_grouper = ExposureGrouper { threshold: 3600 };
+    let tcn_batches_manager = TcnBatchesManager::new(tcn_dao.clone(), exposure_grouper.clone())
+        .journal(config.recording.journal_tcns)
+        .sample_bucket_secs(config.recording.sample_bucket_secs);
+    // Observations of a batch that wasn't flushed because the process died
+    if let Err(e) = tcn_batches_manager.replay_journal() {
+        error!("Couldn't replay journaled TCNs: {:?}", e);
+    }
 
     Dependencies {
         api,
-        reports_updater: ReportsUpdater {
-            preferences: preferences.clone(),
-            tcn_dao: tcn_dao.clone(),
-            tcn_matcher: TcnMatcherRayon {},
+        reports_updater: ReportsUpdaterBuilder::new(
+            preferences.clone(),
+            tcn_dao.clone(),
             api,
             memo_mapper,
-            exposure_grouper: exposure_grouper.clone(),
-            alert_dao: alert_dao.clone(),
-        },
+            alert_dao.clone(),
+            update_run_dao,
+        )
+        .exposure_grouper(exposure_grouper)
+        // Raw reports are stored (and retrievable) only in debug builds
+        .store_raw_reports(cfg!(debug_assertions))
+        .record_tcn_prefixes(config.recording.debug_alert_tcn_prefixes)
+        .client_version(client_version.clone())
+        .risk_config(config.risk.clone())
+        .update_report_symptoms(config.reports.update_symptoms)
+        .build(),
         symptom_inputs_processor: SymptomInputsProcessorImpl {
             inputs_manager: SymptomInputsManagerImpl {
                 inputs: Arc::new(RwLock::new(SymptomInputs::default())),
                 inputs_submitter: symptom_inputs_submitter,
             },
         },
-        observed_tcn_processor: ObservedTcnProcessorImpl::new(TcnBatchesManager::new(
-            tcn_dao.clone(),
-            exposure_grouper,
-        )),
+        observed_tcn_processor: ObservedTcnProcessorImpl::new(
+            tcn_batches_manager,
+            &TimerFlushScheduler {},
+            DistanceBounds::default(),
+        )
+        .record_recent_observations(config.recording.debug_recent_observations),
         tcn_keys,
         alert_dao,
+        alert_references,
+        acknowledgement_service,
+        housekeeper,
+        client_version,
+        migration_report,
+        keys_reset: None,
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcn_ext::tcn_hex::TcnHex;
+    use uuid::Uuid;
+
+    // Note: this is the only test using the global dependencies
+    #[test]
+    fn test_profiles_are_isolated() {
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        fs::create_dir_all(&db_dir).unwrap();
+        let db_path = db_dir.to_str().unwrap();
+
+        let bootstrap_res = bootstrap_with_profile(db_path, "staging", CoreConfig::default());
+        assert!(bootstrap_res.is_ok());
+        let bootstrap_report = bootstrap_res.unwrap();
+        assert_eq!(0, bootstrap_report.migration.from_version);
+        assert_eq!(REQUIRED_DB_VERSION, bootstrap_report.migration.to_version);
+        assert_eq!(None, bootstrap_report.keys_reset);
+        save_tcn("2485a64b57addcaea3ed1b538d07dbce");
+
+        let api = dependencies().unwrap().api;
+
+        let switch_res = switch_profile("prod");
+        assert!(switch_res.is_ok());
+        assert!(stored_tcns().is_empty());
+        // Not leaked again
+        assert!(std::ptr::eq(api, dependencies().unwrap().api));
+        save_tcn("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3");
+
+        let switch_res = switch_profile("staging");
+        assert!(switch_res.is_ok());
+        assert_eq!(vec!["2485a64b57addcaea3ed1b538d07dbce"], stored_tcns());
+
+        let switch_res = switch_profile("prod");
+        assert!(switch_res.is_ok());
+        assert_eq!(vec!["c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3"], stored_tcns());
+
+        assert!(fs::metadata(db_dir.join("db_staging.sqlite")).is_ok());
+        assert!(fs::metadata(db_dir.join("db_prod.sqlite")).is_ok());
+
+        // Fails before the dependencies are swapped: the active profile is kept
+        let switch_res = switch_profile("invalid name");
+        assert!(matches!(switch_res, Err(ServicesError::FFIParameters(_))));
+        assert_eq!(vec!["c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3"], stored_tcns());
+
+        let shutdown_res = shutdown();
+        assert!(shutdown_res.is_ok());
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_tcn_api_is_shared_by_networking_config() {
+        let config = NetworkingConfig {
+            api_url: "https://tcn.example.org/shared".to_owned(),
+            ..NetworkingConfig::default()
+        };
+        let other_config = NetworkingConfig {
+            api_url: "https://tcn.example.org/other".to_owned(),
+            ..NetworkingConfig::default()
+        };
+
+        assert!(std::ptr::eq(tcn_api(&config), tcn_api(&config)));
+        assert!(!std::ptr::eq(tcn_api(&config), tcn_api(&other_config)));
+        assert_eq!(other_config.api_url, tcn_api(&other_config).api_url);
+    }
+
+    #[test]
+    fn test_invalid_config_fails_bootstrap() {
+        let config = CoreConfig {
+            networking: NetworkingConfig {
+                max_reports_bytes_per_interval: 0,
+                ..NetworkingConfig::default()
+            },
+            ..CoreConfig::default()
+        };
+        // Rejected before the global dependencies are accessed
+        let bootstrap_res = bootstrap_with_profile("unused", "invalid", config);
+        match bootstrap_res {
+            Err(ServicesError::InvalidConfig(violations)) => assert_eq!(1, violations.len()),
+            other => panic!("Expected InvalidConfig error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_profile_sqlite_file_name() {
+        assert_eq!("db.sqlite", sqlite_file_name(DEFAULT_PROFILE).unwrap());
+        assert_eq!(
+            "db_staging-2.sqlite",
+            sqlite_file_name("staging-2").unwrap()
+        );
+        assert!(sqlite_file_name("").is_err());
+        assert!(sqlite_file_name("../prod").is_err());
+        assert!(sqlite_file_name("pr od").is_err());
+    }
+
+    #[test]
+    fn test_missing_db_dir_is_created() {
+        let base_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        let db_dir = base_dir.join("cleared").join("cache");
+        let db_path = db_dir.to_str().unwrap();
+
+        let dependencies_res =
+            create_profile_dependencies(db_path, DEFAULT_PROFILE, &CoreConfig::default());
+        assert!(dependencies_res.is_ok());
+        dependencies_res.unwrap().observed_tcn_processor.stop();
+
+        assert!(fs::metadata(db_dir.join("db.sqlite")).is_ok());
+        assert!(fs::metadata(db_dir.join(".coepi_write_probe")).is_err());
+        let _ = fs::remove_dir_all(&base_dir);
+    }
+
+    #[test]
+    fn test_preview_migrations_doesnt_change_db() {
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        let db_path = db_dir.to_str().unwrap();
+
+        // No database yet: all the steps are pending, and it's not created
+        let preview_res = preview_migrations(db_path);
+        assert!(preview_res.is_ok());
+        assert_eq!(
+            REQUIRED_DB_VERSION as usize,
+            preview_res.unwrap().steps.len()
+        );
+        assert!(fs::metadata(db_dir.join("db.sqlite")).is_err());
+
+        let dependencies_res =
+            create_profile_dependencies(db_path, DEFAULT_PROFILE, &CoreConfig::default());
+        assert!(dependencies_res.is_ok());
+        let dependencies = dependencies_res.unwrap();
+        dependencies.observed_tcn_processor.stop();
+        assert_eq!(
+            REQUIRED_DB_VERSION as usize,
+            dependencies.migration_report.steps.len()
+        );
+
+        let preview_res = preview_migrations(db_path);
+        assert!(preview_res.is_ok());
+        let preview = preview_res.unwrap();
+        assert_eq!(REQUIRED_DB_VERSION, preview.from_version);
+        assert!(preview.steps.is_empty());
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_corrupted_keys_are_reset() {
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        let db_path = db_dir.to_str().unwrap();
+
+        let dependencies =
+            create_profile_dependencies(db_path, DEFAULT_PROFILE, &CoreConfig::default()).unwrap();
+        dependencies.observed_tcn_processor.stop();
+        assert_eq!(None, dependencies.keys_reset);
+        assert!(dependencies.tcn_keys.generate_tcn().is_ok());
+        // Truncated
+        dependencies
+            .reports_updater
+            .preferences
+            .dao
+            .save("tck", "{\"tck_bytes\":[1,0,58,");
+
+        let dependencies_res =
+            create_profile_dependencies(db_path, DEFAULT_PROFILE, &CoreConfig::default());
+        assert!(dependencies_res.is_ok());
+        let dependencies = dependencies_res.unwrap();
+        dependencies.observed_tcn_processor.stop();
+        assert_eq!(
+            Some(KeyIntegrityIssue::UnreadableTck),
+            dependencies.keys_reset
+        );
+        assert!(dependencies.tcn_keys.generate_tcn().is_ok());
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    #[test]
+    fn test_db_dir_that_cant_be_created_is_error() {
+        let base_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        fs::create_dir_all(&base_dir).unwrap();
+        // A file where a directory is expected
+        let file_path = base_dir.join("file");
+        fs::write(&file_path, b"").unwrap();
+        let db_path = file_path.join("db");
+
+        let dependencies_res =
+            create_profile_dependencies(db_path.to_str().unwrap(), "prod", &CoreConfig::default());
+        match dependencies_res {
+            Err(ServicesError::FFIParameters(msg)) => assert!(msg.contains("file")),
+            Err(error) => panic!("Unexpected error: {:?}", error),
+            Ok(_) => panic!("Expected error"),
+        }
+        let _ = fs::remove_dir_all(&base_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_read_only_db_dir_is_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let db_dir = std::env::temp_dir().join(format!("coepi_core_{}", Uuid::new_v4()));
+        fs::create_dir_all(&db_dir).unwrap();
+        fs::set_permissions(&db_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Permissions don't apply to root (e.g. some CI containers): nothing to test.
+        let is_writable = fs::write(db_dir.join("root_check"), b"").is_ok();
+        if !is_writable {
+            let prepare_res = prepare_db_dir(db_dir.to_str().unwrap());
+            match prepare_res {
+                Err(ServicesError::FFIParameters(msg)) => assert!(msg.contains("isn't writable")),
+                other => panic!("Expected FFIParameters error, got: {:?}", other),
+            }
+        }
+
+        fs::set_permissions(&db_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let _ = fs::remove_dir_all(&db_dir);
+    }
+
+    fn save_tcn(tcn: &str) {
+        let save_res = dependencies()
+            .unwrap()
+            .observed_tcn_processor
+            .save(tcn, 1.0);
+        assert!(save_res.is_ok());
+    }
+
+    fn stored_tcns() -> Vec<String> {
+        let tcns_res = dependencies().unwrap().reports_updater.tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        tcns_res
+            .unwrap()
+            .iter()
+            .map(|observed_tcn| TcnHex::from(&observed_tcn.tcn).into_string())
+            .collect()
+    }
+}
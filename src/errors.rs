@@ -1,5 +1,8 @@
-use crate::networking::NetworkingError;
-use rusqlite::Error::QueryReturnedNoRows;
+use crate::{config::ConfigViolation, networking::NetworkingError};
+use rusqlite::{
+    Error::{QueryReturnedNoRows, SqliteFailure},
+    ErrorCode,
+};
 use std::{error, fmt, io::Error as StdError, io::ErrorKind};
 use tcn::Error as TcnError;
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -11,6 +14,34 @@ pub enum ServicesError {
     FFIParameters(String),
     NotFound,
     General(String),
+    Database(String),
+    // bootstrap wasn't called or failed
+    NotBootstrapped,
+    // All the invalid fields of the config passed to bootstrap (see CoreConfig::validate)
+    InvalidConfig(Vec<ConfigViolation>),
+    // The database couldn't be written because the device storage is full (SQLITE_FULL).
+    // The app can ask the user to free space.
+    StorageFull,
+    // A value created by the core can't be stored, e.g. an oversized id. A bug, not an app error.
+    InvalidInput(String),
+}
+
+impl ServicesError {
+    // Identifies the kind of error, e.g. in stored diagnostics
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServicesError::Networking(_) => "networking",
+            ServicesError::Error(_) => "error",
+            ServicesError::FFIParameters(_) => "ffi_parameters",
+            ServicesError::NotFound => "not_found",
+            ServicesError::General(_) => "general",
+            ServicesError::Database(_) => "database",
+            ServicesError::NotBootstrapped => "not_bootstrapped",
+            ServicesError::InvalidConfig(_) => "invalid_config",
+            ServicesError::StorageFull => "storage_full",
+            ServicesError::InvalidInput(_) => "invalid_input",
+        }
+    }
 }
 
 impl fmt::Display for ServicesError {
@@ -83,6 +114,9 @@ impl From<rusqlite::Error> for ServicesError {
     fn from(error: rusqlite::Error) -> Self {
         match error {
             QueryReturnedNoRows => ServicesError::NotFound,
+            SqliteFailure(ref failure, _) if failure.code == ErrorCode::DiskFull => {
+                ServicesError::StorageFull
+            }
             _ => ServicesError::Error(Box::new(StdError::new(
                 ErrorKind::Other,
                 format!("{}", error),
@@ -0,0 +1,168 @@
+// Validation of the string parameters passed by the apps (iOS C strings, Android JNI strings).
+// The errors name the parameter and the position of the problem, so that invalid input can be
+// traced back to the app call.
+use crate::{errors::ServicesError, reports_interval::UnixTime};
+use std::str;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    // JSON, paths, base64: any text without NUL characters
+    Text,
+    // TCN hex, alert / report ids, symptom ids and inputs, profile names: no control characters
+    Identifier,
+}
+
+// bytes: without the terminating NUL. JNI strings are modified UTF-8: their NUL (0xC0 0x80) and
+// supplementary characters (surrogate pairs) are reported as invalid UTF-8.
+// Only called by the interfaces, which aren't built for the host.
+#[allow(dead_code)]
+pub fn param_str<'a>(
+    name: &str,
+    bytes: &'a [u8],
+    kind: ParamKind,
+) -> Result<&'a str, ServicesError> {
+    let str = str::from_utf8(bytes).map_err(|e| {
+        invalid_param(
+            name,
+            &format!("is not valid UTF-8 at byte {}", e.valid_up_to()),
+        )
+    })?;
+
+    if let Some(index) = str.find('\0') {
+        return Err(invalid_param(
+            name,
+            &format!("contains a NUL character at byte {}", index),
+        ));
+    }
+
+    if kind == ParamKind::Identifier {
+        if let Some((index, _)) = str.char_indices().find(|(_, c)| c.is_control()) {
+            return Err(invalid_param(
+                name,
+                &format!("contains a control character at byte {}", index),
+            ));
+        }
+    }
+
+    Ok(str)
+}
+
+// Unix time passed as a signed integer (e.g. Java long). Not called by the host build.
+#[allow(dead_code)]
+pub fn unix_time_param(name: &str, value: i64) -> Result<UnixTime, ServicesError> {
+    if value < 0 {
+        return Err(invalid_param(name, &format!("is negative: {}", value)));
+    }
+    Ok(UnixTime {
+        value: value as u64,
+    })
+}
+
+#[allow(dead_code)] // Only called by the interfaces
+pub fn null_param(name: &str) -> ServicesError {
+    invalid_param(name, "is null")
+}
+
+pub fn invalid_param(name: &str, problem: &str) -> ServicesError {
+    ServicesError::FFIParameters(format!("parameter '{}' {}", name, problem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_message<T: std::fmt::Debug>(res: Result<T, ServicesError>) -> String {
+        match res {
+            Err(ServicesError::FFIParameters(message)) => message,
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_params() {
+        let tcn = "2485a64b57addcaea3ed1b538d07dbce";
+        let res = param_str("tcn", tcn.as_bytes(), ParamKind::Identifier);
+        assert!(res.is_ok());
+        assert_eq!(tcn, res.unwrap());
+
+        // Control characters are valid in text, e.g. formatted JSON
+        let json = "[\n\t\"Zz\"\n]";
+        let res = param_str("sigs_json", json.as_bytes(), ParamKind::Text);
+        assert!(res.is_ok());
+        assert_eq!(json, res.unwrap());
+
+        let res = param_str("profile", "pröd".as_bytes(), ParamKind::Identifier);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_error_with_position() {
+        for kind in &[ParamKind::Text, ParamKind::Identifier] {
+            let res = param_str("tcn", b"2485a64\xff57addc", *kind);
+            assert_eq!(
+                "parameter 'tcn' is not valid UTF-8 at byte 7",
+                error_message(res)
+            );
+        }
+
+        // Truncated multi-byte character
+        let res = param_str("config_json", b"{\"a\":\"\xc3", ParamKind::Text);
+        assert_eq!(
+            "parameter 'config_json' is not valid UTF-8 at byte 6",
+            error_message(res)
+        );
+
+        // NUL in modified UTF-8 (JNI)
+        let res = param_str("id", b"ab\xc0\x80", ParamKind::Identifier);
+        assert_eq!(
+            "parameter 'id' is not valid UTF-8 at byte 2",
+            error_message(res)
+        );
+    }
+
+    #[test]
+    fn test_interior_nul_is_error() {
+        for kind in &[ParamKind::Text, ParamKind::Identifier] {
+            let res = param_str("reports_json", b"[\"a\0\"]", *kind);
+            assert_eq!(
+                "parameter 'reports_json' contains a NUL character at byte 3",
+                error_message(res)
+            );
+        }
+    }
+
+    #[test]
+    fn test_control_character_in_identifier_is_error() {
+        let res = param_str("ids", b"Cough\nFever", ParamKind::Identifier);
+        assert_eq!(
+            "parameter 'ids' contains a control character at byte 5",
+            error_message(res)
+        );
+
+        let res = param_str("tcn", "é\u{1b}".as_bytes(), ParamKind::Identifier);
+        assert_eq!(
+            "parameter 'tcn' contains a control character at byte 2",
+            error_message(res)
+        );
+    }
+
+    #[test]
+    fn test_negative_unix_time_is_error() {
+        assert_eq!(
+            UnixTime { value: 1591706000 },
+            unix_time_param("time", 1591706000).unwrap()
+        );
+        assert_eq!(
+            "parameter 'time' is negative: -1",
+            error_message(unix_time_param("time", -1))
+        );
+    }
+
+    #[test]
+    fn test_null_param_is_error() {
+        assert_eq!(
+            "parameter 'db_path' is null",
+            error_message::<()>(Err(null_param("db_path")))
+        );
+    }
+}
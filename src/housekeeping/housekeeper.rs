@@ -0,0 +1,353 @@
+use crate::{
+    database::{preferences::Preferences, update_run_dao::UpdateRunDao},
+    errors::ServicesError,
+    reports_interval::UnixTime,
+};
+use log::*;
+use serde::Serialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// Time the apps get for a background fetch (iOS: ~30s). Housekeeping after a fetch uses what the fetch left.
+pub const FETCH_TIME_BUDGET: Duration = Duration::from_secs(25);
+
+// A periodic chore, e.g. deleting old data. Run by the Housekeeper at most once per min_interval_secs.
+pub trait HousekeepingTask: Send + Sync {
+    // Stable: the time of the last run is stored with it
+    fn name(&self) -> &'static str;
+    fn min_interval_secs(&self) -> u64;
+    fn run(&self, now: &UnixTime) -> Result<(), ServicesError>;
+}
+
+// Diagnostics of a task run, stored like the reports update runs (UpdateMetrics)
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct HousekeepingRun {
+    pub time: u64, // Start
+    pub task: String,
+    pub duration_millis: u64,
+    pub error_code: Option<String>, // None if the task succeeded
+}
+
+// When a task is due next. The apps trigger the runs (see Housekeeper::run_due), so it runs then at the
+// earliest.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ScheduledRun {
+    pub task: String,
+    pub due: u64, // Unix time. Now if the task is due.
+}
+
+// Last successful run of a task
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct LastRun {
+    pub task: String,
+    pub time: Option<u64>, // Unix time. None if it never succeeded.
+}
+
+// Runs the tasks that are due, in order. Called by the apps' background schedulers (WorkManager /
+// BGTaskScheduler) and after successful fetches, instead of bolting the chores onto the reports update.
+pub struct Housekeeper<P: Preferences, Z: UpdateRunDao> {
+    pub preferences: Arc<P>,
+    pub update_run_dao: Arc<Z>,
+    pub tasks: Vec<Box<dyn HousekeepingTask>>,
+    pub clock: fn() -> UnixTime,
+}
+
+impl<P: Preferences, Z: UpdateRunDao> Housekeeper<P, Z> {
+    pub fn new(
+        preferences: Arc<P>,
+        update_run_dao: Arc<Z>,
+        tasks: Vec<Box<dyn HousekeepingTask>>,
+    ) -> Housekeeper<P, Z> {
+        Housekeeper {
+            preferences,
+            update_run_dao,
+            tasks,
+            clock: UnixTime::now,
+        }
+    }
+
+    // A failing task doesn't stop the following ones, and is due again in the next call.
+    // budget: no task is started after it's used up. The remaining ones run in the next call.
+    pub fn run_due(&self, budget: Option<Duration>) -> Vec<HousekeepingRun> {
+        let start = Instant::now();
+        let now = (self.clock)();
+
+        let mut runs = vec![];
+        for task in self
+            .tasks
+            .iter()
+            .filter(|task| self.is_due(task.as_ref(), &now))
+        {
+            if budget.map_or(false, |budget| start.elapsed() >= budget) {
+                info!("Housekeeping budget used up, deferring: {}", task.name());
+                break;
+            }
+            runs.push(self.run_task(task.as_ref(), &now));
+        }
+
+        // Diagnostics only: not being able to store them doesn't affect the tasks
+        if !runs.is_empty() {
+            if let Err(error) = self.update_run_dao.save_housekeeping_runs(&runs) {
+                error!("Couldn't save housekeeping runs: {:?}", error);
+            }
+        }
+        runs
+    }
+
+    // Opportunistic run after a successful reports update, with what's left of FETCH_TIME_BUDGET
+    pub fn run_after_fetch(&self, fetch_duration: Duration) -> Vec<HousekeepingRun> {
+        match FETCH_TIME_BUDGET.checked_sub(fetch_duration) {
+            Some(remaining) if remaining > Duration::from_secs(0) => self.run_due(Some(remaining)),
+            _ => {
+                debug!("No time left for housekeeping after fetch");
+                vec![]
+            }
+        }
+    }
+
+    pub fn history(&self) -> Result<Vec<HousekeepingRun>, ServicesError> {
+        self.update_run_dao.housekeeping_history()
+    }
+
+    // In task order
+    pub fn next_runs(&self) -> Vec<ScheduledRun> {
+        let now = (self.clock)();
+        self.tasks
+            .iter()
+            .map(|task| ScheduledRun {
+                task: task.name().to_owned(),
+                due: self.next_run(task.as_ref(), &now),
+            })
+            .collect()
+    }
+
+    // In task order
+    pub fn last_runs(&self) -> Vec<LastRun> {
+        self.tasks
+            .iter()
+            .map(|task| LastRun {
+                task: task.name().to_owned(),
+                time: self.preferences.last_housekeeping_run(task.name()),
+            })
+            .collect()
+    }
+
+    fn is_due(&self, task: &dyn HousekeepingTask, now: &UnixTime) -> bool {
+        self.next_run(task, now) <= now.value
+    }
+
+    fn next_run(&self, task: &dyn HousekeepingTask, now: &UnixTime) -> u64 {
+        self.preferences
+            .last_housekeeping_run(task.name())
+            .map_or(now.value, |last_run| {
+                last_run
+                    .saturating_add(task.min_interval_secs())
+                    .max(now.value)
+            })
+    }
+
+    fn run_task(&self, task: &dyn HousekeepingTask, now: &UnixTime) -> HousekeepingRun {
+        debug!("Running housekeeping task: {}", task.name());
+        let task_start = Instant::now();
+        let res = task.run(now);
+        let duration_millis = task_start.elapsed().as_millis() as u64;
+
+        match &res {
+            Ok(_) => self
+                .preferences
+                .set_last_housekeeping_run(task.name(), now.value),
+            Err(error) => error!("Housekeeping task: {} failed: {:?}", task.name(), error),
+        }
+
+        HousekeepingRun {
+            time: now.value,
+            task: task.name().to_owned(),
+            duration_millis,
+            error_code: res.err().map(|error| error.code().to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            preferences::{PreferencesDao, PreferencesImpl},
+            update_run_dao::UpdateRunDaoImpl,
+        },
+        test_support::migrated_database,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingTask {
+        name: &'static str,
+        min_interval_secs: u64,
+        fails: bool,
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl HousekeepingTask for CountingTask {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn min_interval_secs(&self) -> u64 {
+            self.min_interval_secs
+        }
+
+        fn run(&self, _now: &UnixTime) -> Result<(), ServicesError> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            if self.fails {
+                Err(ServicesError::Database("Disk I/O error".to_owned()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn counting_task(
+        name: &'static str,
+        min_interval_secs: u64,
+        fails: bool,
+    ) -> (Box<dyn HousekeepingTask>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let task = CountingTask {
+            name,
+            min_interval_secs,
+            fails,
+            runs: runs.clone(),
+        };
+        (Box::new(task), runs)
+    }
+
+    fn create_housekeeper(
+        tasks: Vec<Box<dyn HousekeepingTask>>,
+    ) -> Housekeeper<PreferencesImpl, UpdateRunDaoImpl> {
+        let database = migrated_database();
+        let mut housekeeper = Housekeeper::new(
+            Arc::new(PreferencesImpl {
+                dao: PreferencesDao::new(database.clone()),
+            }),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+            tasks,
+        );
+        housekeeper.clock = || UnixTime { value: 1591706000 };
+        housekeeper
+    }
+
+    #[test]
+    fn test_tasks_run_when_their_interval_elapsed() {
+        let (hourly_task, hourly_runs) = counting_task("hourly", 60 * 60, false);
+        let (daily_task, daily_runs) = counting_task("daily", 24 * 60 * 60, false);
+        let mut housekeeper = create_housekeeper(vec![hourly_task, daily_task]);
+
+        let runs = housekeeper.run_due(None);
+        let tasks: Vec<&str> = runs.iter().map(|run| run.task.as_ref()).collect();
+        assert_eq!(vec!["hourly", "daily"], tasks);
+
+        assert!(housekeeper.run_due(None).is_empty());
+
+        housekeeper.clock = || UnixTime {
+            value: 1591706000 + 2 * 60 * 60,
+        };
+        let runs = housekeeper.run_due(None);
+        assert_eq!(1, runs.len());
+        assert_eq!("hourly", runs[0].task);
+
+        assert_eq!(2, hourly_runs.load(Ordering::SeqCst));
+        assert_eq!(1, daily_runs.load(Ordering::SeqCst));
+        assert_eq!(3, housekeeper.history().unwrap().len());
+    }
+
+    #[test]
+    fn test_failing_task_doesnt_block_the_rest() {
+        let (failing_task, failing_runs) = counting_task("failing", 60 * 60, true);
+        let (other_task, other_runs) = counting_task("other", 60 * 60, false);
+        let housekeeper = create_housekeeper(vec![failing_task, other_task]);
+
+        let runs = housekeeper.run_due(None);
+        assert_eq!(
+            vec![Some("database".to_owned()), None],
+            runs.into_iter()
+                .map(|run| run.error_code)
+                .collect::<Vec<Option<String>>>()
+        );
+        assert_eq!(1, other_runs.load(Ordering::SeqCst));
+
+        // The failed task is retried, the other one isn't due
+        let runs = housekeeper.run_due(None);
+        assert_eq!(1, runs.len());
+        assert_eq!("failing", runs[0].task);
+        assert_eq!(2, failing_runs.load(Ordering::SeqCst));
+        assert_eq!(1, other_runs.load(Ordering::SeqCst));
+
+        let history = housekeeper.history().unwrap();
+        assert_eq!(3, history.len());
+        assert_eq!(Some("database".to_owned()), history[0].error_code);
+    }
+
+    #[test]
+    fn test_next_runs() {
+        let (hourly_task, _) = counting_task("hourly", 60 * 60, false);
+        let (daily_task, _) = counting_task("daily", 24 * 60 * 60, false);
+        let mut housekeeper = create_housekeeper(vec![hourly_task, daily_task]);
+
+        // Never run: due now
+        let due_now = vec![
+            ScheduledRun {
+                task: "hourly".to_owned(),
+                due: 1591706000,
+            },
+            ScheduledRun {
+                task: "daily".to_owned(),
+                due: 1591706000,
+            },
+        ];
+        assert_eq!(due_now, housekeeper.next_runs());
+
+        housekeeper.run_due(None);
+        assert_eq!(
+            vec![
+                ScheduledRun {
+                    task: "hourly".to_owned(),
+                    due: 1591706000 + 60 * 60,
+                },
+                ScheduledRun {
+                    task: "daily".to_owned(),
+                    due: 1591706000 + 24 * 60 * 60,
+                },
+            ],
+            housekeeper.next_runs()
+        );
+
+        // Overdue: due now
+        housekeeper.clock = || UnixTime {
+            value: 1591706000 + 2 * 60 * 60,
+        };
+        assert_eq!(
+            vec![1591706000 + 2 * 60 * 60, 1591706000 + 24 * 60 * 60],
+            housekeeper
+                .next_runs()
+                .into_iter()
+                .map(|run| run.due)
+                .collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_no_tasks_are_started_without_budget() {
+        let (task, runs) = counting_task("task", 60 * 60, false);
+        let housekeeper = create_housekeeper(vec![task]);
+
+        assert!(housekeeper.run_after_fetch(FETCH_TIME_BUDGET).is_empty());
+        assert!(housekeeper.run_due(Some(Duration::from_secs(0))).is_empty());
+        assert_eq!(0, runs.load(Ordering::SeqCst));
+
+        // Still due
+        assert_eq!(1, housekeeper.run_after_fetch(Duration::from_secs(1)).len());
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod housekeeper;
+pub mod tasks;
@@ -0,0 +1,123 @@
+use super::housekeeper::HousekeepingTask;
+use crate::{
+    config::RiskConfig,
+    database::{
+        alert_dao::AlertDao, database::Database, preferences::Preferences, tcn_dao::TcnDao,
+    },
+    errors::ServicesError,
+    metrics::metrics_uploader::MetricsUploader,
+    networking::MetricsApi,
+    reports_interval::UnixTime,
+};
+use log::*;
+use std::sync::Arc;
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+
+// Observations that ended before RiskConfig::oldest_alert_contact_end can't create alerts anymore
+pub struct PruneTcnsTask<T: TcnDao> {
+    pub tcn_dao: Arc<T>,
+    pub risk_config: RiskConfig,
+}
+
+impl<T: TcnDao + 'static> HousekeepingTask for PruneTcnsTask<T> {
+    fn name(&self) -> &'static str {
+        "prune_tcns"
+    }
+
+    fn min_interval_secs(&self) -> u64 {
+        DAY_SECS
+    }
+
+    fn run(&self, now: &UnixTime) -> Result<(), ServicesError> {
+        let deleted = self
+            .tcn_dao
+            .delete_ended_before(self.risk_config.oldest_alert_contact_end(now))?;
+        info!("Deleted {} expired TCN observations", deleted);
+        Ok(())
+    }
+}
+
+// The reports update deletes them too, but only runs if the app fetches
+pub struct PurgeAlertsTask<D: AlertDao> {
+    pub alert_dao: Arc<D>,
+    pub risk_config: RiskConfig,
+}
+
+impl<D: AlertDao + Send + Sync + 'static> HousekeepingTask for PurgeAlertsTask<D> {
+    fn name(&self) -> &'static str {
+        "purge_alerts"
+    }
+
+    fn min_interval_secs(&self) -> u64 {
+        DAY_SECS
+    }
+
+    fn run(&self, now: &UnixTime) -> Result<(), ServicesError> {
+        let deleted = self
+            .alert_dao
+            .delete_ended_before(self.risk_config.oldest_alert_contact_end(now))?;
+        info!("Deleted {} expired alerts", deleted);
+        Ok(())
+    }
+}
+
+// The uploader limits itself to a daily upload: an upload that failed is retried after an hour.
+impl<A, P, T, D> HousekeepingTask for MetricsUploader<A, P, T, D>
+where
+    A: MetricsApi + Send + Sync + 'static,
+    P: Preferences + Send + Sync + 'static,
+    T: TcnDao + 'static,
+    D: AlertDao + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        "upload_metrics"
+    }
+
+    fn min_interval_secs(&self) -> u64 {
+        HOUR_SECS
+    }
+
+    fn run(&self, _now: &UnixTime) -> Result<(), ServicesError> {
+        self.upload_if_due().map(|_| ())
+    }
+}
+
+// Keeps the WAL from growing between the automatic checkpoints
+pub struct CheckpointDatabaseTask {
+    pub database: Arc<Database>,
+}
+
+impl HousekeepingTask for CheckpointDatabaseTask {
+    fn name(&self) -> &'static str {
+        "checkpoint_database"
+    }
+
+    fn min_interval_secs(&self) -> u64 {
+        DAY_SECS
+    }
+
+    fn run(&self, _now: &UnixTime) -> Result<(), ServicesError> {
+        self.database.checkpoint()
+    }
+}
+
+// Slow with large databases: last, so a short budget skips it
+pub struct VacuumDatabaseTask {
+    pub database: Arc<Database>,
+}
+
+impl HousekeepingTask for VacuumDatabaseTask {
+    fn name(&self) -> &'static str {
+        "vacuum_database"
+    }
+
+    fn min_interval_secs(&self) -> u64 {
+        7 * DAY_SECS
+    }
+
+    fn run(&self, _now: &UnixTime) -> Result<(), ServicesError> {
+        self.database.vacuum()
+    }
+}
@@ -48,7 +48,14 @@ typedef struct {
 #endif
 
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
-CFStringRef bootstrap_core(const char *db_path, CoreLogLevel level, bool coepi_only);
+CFStringRef alert_reference_token(const char *c_alert_id);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef bootstrap_core(const char *db_path,
+                           const char *config_json,
+                           CoreLogLevel level,
+                           bool coepi_only);
 #endif
 
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
@@ -59,18 +66,103 @@ int32_t call_callback(void (*callback)(int32_t, bool, CFStringRef));
 CFStringRef clear_symptoms(void);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef decode_memo(const char *c_memo_hex);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef delete_alert(const char *id);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef dismiss_report(const char *report_id);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef encode_memo(const char *c_report_json);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef fetch_new_reports(void);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef flush_observed_tcns(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef flush_pending_notifications(void);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef generate_tcn(void);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef generate_tcn_with_expiry(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_alert_summary(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_alerts_grouped(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_alerts_page(const char *cursor, uint32_t page_size);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_conformance_samples(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_exposure_minutes(uint32_t days);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_raw_report(const char *report_id);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_privacy_report(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_runtime_stats(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_recent_observations_debug(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_tcn_stats(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_last_migration_report(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef preview_migrations(const char *c_db_path);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef get_update_history(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef group_exposures(const char *c_windows_json, uint64_t threshold_seconds);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+// Only in builds with the test-support feature
+CFStringRef match_reports_for_test(const char *c_reports_json, const char *c_observed_tcns_json);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 FFIReturnStruct pass_and_return_struct(const FFIParameterStruct *par);
 #endif
@@ -84,7 +176,11 @@ CFStringRef post_report(const char *c_report);
 #endif
 
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
-CFStringRef record_tcn(const char *c_tcn, float distance);
+CFStringRef record_tcn(const char *c_tcn, float distance, int64_t time);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef record_tcns(const char *c_observations_json);
 #endif
 
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
@@ -95,10 +191,18 @@ int32_t register_callback(void (*callback)(int32_t, bool, CFStringRef));
 int32_t register_log_callback(void (*log_callback)(CoreLogMessage));
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef resubmit_with_earliest_symptom(uint32_t c_days_ago);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 FFIReturnStruct return_struct(void);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef run_housekeeping(void);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef set_breathlessness_cause(const char *c_cause);
 #endif
@@ -115,6 +219,10 @@ CFStringRef set_cough_status(const char *c_status);
 CFStringRef set_cough_type(const char *c_cough_type);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef set_earliest_symptom_date(uint8_t c_is_set, uint64_t c_time);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef set_earliest_symptom_started_days_ago(uint8_t c_is_set, uint32_t c_days);
 #endif
@@ -135,6 +243,21 @@ CFStringRef set_fever_taken_temperature_spot(const char *c_cause);
 CFStringRef set_fever_taken_temperature_today(uint8_t c_is_set, uint8_t c_taken);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef set_quiet_hours(uint8_t c_enabled,
+                            uint32_t c_start_minute,
+                            uint32_t c_end_minute,
+                            int32_t c_utc_offset_minutes);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef set_report_allowlist(const char *c_sigs_json);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef set_report_denylist(const char *c_sigs_json);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef set_symptom_ids(const char *c_ids);
 #endif
@@ -143,10 +266,30 @@ CFStringRef set_symptom_ids(const char *c_ids);
 int32_t setup_logger(CoreLogLevel level, bool coepi_only);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef shutdown_core(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef snooze_alert(const char *id, uint64_t until);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef submit_acknowledgement(const char *c_alert_id);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef submit_symptoms(void);
 #endif
 
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef submit_symptoms_with_outcome(void);
+#endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef switch_core_profile(const char *c_profile);
+#endif
+
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 int32_t trigger_callback(const char *my_str);
 #endif
@@ -158,3 +301,7 @@ int32_t trigger_logging_macros(void);
 #if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
 CFStringRef update_alert_is_read(const char *id, uint8_t is_read);
 #endif
+
+#if (defined(TARGET_OS_IOS) || defined(TARGET_OS_MACOS))
+CFStringRef verify_alert_reference_token(const char *c_token, const char *c_alert_id);
+#endif
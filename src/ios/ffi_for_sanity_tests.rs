@@ -1,5 +1,5 @@
 use super::ios_interface::cstring_to_str;
-use crate::expect_log;
+use crate::{expect_log, ffi_params::ParamKind};
 use core_foundation::{
     base::TCFType,
     string::{CFString, CFStringRef},
@@ -41,7 +41,7 @@ struct MyStruct {
 
 #[no_mangle]
 pub unsafe extern "C" fn pass_struct(par: *const FFIParameterStruct) -> i32 {
-    let my_str = cstring_to_str(&(*par).my_str).unwrap();
+    let my_str = cstring_to_str(&(*par).my_str, "my_str", ParamKind::Text).unwrap();
 
     let my_struct = MyStruct {
         my_int: (*par).my_int,
@@ -83,7 +83,7 @@ pub unsafe extern "C" fn return_struct() -> FFIReturnStruct {
 
 #[no_mangle]
 pub unsafe extern "C" fn pass_and_return_struct(par: *const FFIParameterStruct) -> FFIReturnStruct {
-    let my_str = cstring_to_str(&(*par).my_str).unwrap();
+    let my_str = cstring_to_str(&(*par).my_str, "my_str", ParamKind::Text).unwrap();
     let cf_string = CFString::new(&my_str.to_owned());
     let cf_string_ref = cf_string.as_concrete_TypeRef();
 
@@ -133,7 +133,7 @@ pub unsafe extern "C" fn register_callback(
 
 #[no_mangle]
 pub unsafe extern "C" fn trigger_callback(my_str: *const c_char) -> i32 {
-    let str = cstring_to_str(&my_str).unwrap();
+    let str = cstring_to_str(&my_str, "my_str", ParamKind::Text).unwrap();
     match &SENDER {
         // Push element to SENDER.
         Some(s) => {
@@ -1,23 +1,43 @@
-use crate::database::alert_dao::AlertDao;
-use crate::reporting::symptom_inputs_manager::SymptomInputsProcessor;
+use crate::database::{alert_dao::AlertDao, preferences::Preferences, tcn_dao::TcnDao};
+use crate::reporting::{
+    memo::{decode_memo_hex, encode_memo_json},
+    symptom_inputs::SubmitOutcome,
+    symptom_inputs_manager::SymptomInputsProcessor,
+};
+use crate::reports_update::acknowledgement::AcknowledgementService;
+use crate::reports_update::alert_reference::AlertReferences;
+use crate::reports_update::exposure::group_exposure_windows_json;
+use crate::reports_update::quiet_hours::QuietHours;
 use crate::simple_logger;
-use crate::tcn_ext::tcn_keys::TcnKeys;
-use crate::tcn_recording::observed_tcn_processor::ObservedTcnProcessor;
+use crate::tcn_ext::{
+    tcn_hex::TcnHex,
+    tcn_keys::{TcnKeys, TcnWithExpiry},
+};
+use crate::tcn_recording::observed_tcn_processor::{
+    ObservedTcnProcessor, SaveBatchOutcome, TcnDiagnostics,
+};
 use crate::{
-    dependencies::{bootstrap, dependencies, DEPENDENCIES},
+    config::{config_violations_json, CoreConfig},
+    conformance,
+    dependencies::{self, bootstrap, dependencies, shutdown, switch_profile},
     errors::ServicesError,
+    ffi_params::{null_param, param_str, unix_time_param, ParamKind},
     networking,
+    privacy::privacy_report,
+    reports_interval::UnixTime,
+    runtime_stats::runtime_stats,
 };
 use core_foundation::base::TCFType;
 use core_foundation::string::{CFString, CFStringRef};
 use log::*;
-use networking::TcnApi;
-use serde::Serialize;
+use networking::{PostReportRequest, TcnApi};
+use serde::{Deserialize, Serialize};
 use simple_logger::{CoreLogLevel, CoreLogMessageThreadSafe, SENDER};
 use std::os::raw::c_char;
 use std::str::FromStr;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
+use std::time::Instant;
 
 // Generic struct to return results to app
 // For convenience, status will be HTTP status codes
@@ -36,9 +56,13 @@ pub unsafe extern "C" fn setup_logger(level: CoreLogLevel, coepi_only: bool) ->
     log::max_level() as i32
 }
 
+// An invalid config is status 400, with a JSON array of {field, reason} (all the invalid fields) as error message
+// Data: the report of the db migrations run on bootstrap (see get_last_migration_report), with keys_reset:
+// null, or why the stored keys were replaced (see BootstrapReport)
 #[no_mangle]
 pub unsafe extern "C" fn bootstrap_core(
     db_path: *const c_char,
+    config_json: *const c_char,
     level: CoreLogLevel,
     coepi_only: bool,
 ) -> CFStringRef {
@@ -46,68 +70,427 @@ pub unsafe extern "C" fn bootstrap_core(
     let filter_level = LevelFilter::from_str(&level_string).expect("Incorrect log level selected!");
     let _ = simple_logger::setup_logger(filter_level, coepi_only);
 
-    let db_path_str = cstring_to_str(&db_path);
-    let result = db_path_str.and_then(|path| bootstrap(path).map_err(ServicesError::from));
+    let db_path_str = cstring_to_str(&db_path, "db_path", ParamKind::Text);
+    let config = cstring_to_str(&config_json, "config_json", ParamKind::Text)
+        .and_then(CoreConfig::from_json);
+    let result = db_path_str.and_then(|path| config.and_then(|config| bootstrap(path, config)));
     info!("Bootstrapping result: {:?}", result);
     return to_result_str(result);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn shutdown_core() -> CFStringRef {
+    let result = shutdown();
+    info!("Shutdown result: {:?}", result);
+    to_result_str(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn switch_core_profile(c_profile: *const c_char) -> CFStringRef {
+    let result =
+        cstring_to_str(&c_profile, "profile", ParamKind::Identifier).and_then(switch_profile);
+    info!("Switch profile result: {:?}", result);
+    to_result_str(result)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn fetch_new_reports() -> CFStringRef {
     info!("Updating alerts");
 
-    let result = dependencies().reports_updater.update_and_fetch_alerts();
+    let result = dependencies().and_then(|dependencies| {
+        let start_time = Instant::now();
+        let result = dependencies.reports_updater.update_and_fetch_alerts()?;
+
+        // Piggybacks on the periodic reports update, if there's time left
+        dependencies
+            .housekeeper
+            .run_after_fetch(start_time.elapsed());
+        Ok(result)
+    });
 
     info!("New alerts: {:?}", result);
 
     return to_result_str(result);
 }
 
+// Runs the due housekeeping tasks (deleting old data, database maintenance, metrics upload).
+// To call from a background task. Result: JSON array of the task runs.
+#[no_mangle]
+pub unsafe extern "C" fn run_housekeeping() -> CFStringRef {
+    let result = dependencies().map(|dependencies| dependencies.housekeeper.run_due(None));
+    info!("Housekeeping result: {:?}", result);
+    to_result_str(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn get_alert_summary() -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| dependencies.alert_dao.alert_summary());
+    to_result_str(result)
+}
+
+// Minutes of exposure in the last days, by distance
+#[no_mangle]
+pub unsafe extern "C" fn get_exposure_minutes(days: u32) -> CFStringRef {
+    let result =
+        dependencies().and_then(|dependencies| dependencies.alert_dao.exposure_minutes(days));
+    to_result_str(result)
+}
+
+// Regroups exposure windows (JSON array of {start, end, min, avg, count}) with the threshold.
+// Returns the measurements of the groups, in the same format. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn group_exposures(
+    c_windows_json: *const c_char,
+    threshold_seconds: u64,
+) -> CFStringRef {
+    let result = cstring_to_str(&c_windows_json, "windows_json", ParamKind::Text)
+        .and_then(|windows_json| group_exposure_windows_json(windows_json, threshold_seconds));
+    to_result_str(result)
+}
+
+// Memo (hex) of the report (PublicSymptoms JSON), like created by the core. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn encode_memo(c_report_json: *const c_char) -> CFStringRef {
+    let result =
+        cstring_to_str(&c_report_json, "report_json", ParamKind::Text).and_then(encode_memo_json);
+    to_result_str(result)
+}
+
+// Report (PublicSymptoms JSON) of the memo (hex), like decoded by the core. No db access.
+#[no_mangle]
+pub unsafe extern "C" fn decode_memo(c_memo_hex: *const c_char) -> CFStringRef {
+    let result =
+        cstring_to_str(&c_memo_hex, "memo_hex", ParamKind::Identifier).and_then(decode_memo_hex);
+    to_result_str(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn get_alerts_grouped() -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| dependencies.alert_dao.all_grouped());
+    to_result_str(result)
+}
+
+// cursor: empty for the first page, otherwise next_cursor of the previous page.
+// JSON object: alerts (newest first, without the snoozed ones), next_cursor (null on the last page)
+// and total (number of alerts, only in the first page)
+#[no_mangle]
+pub unsafe extern "C" fn get_alerts_page(cursor: *const c_char, page_size: u32) -> CFStringRef {
+    let cursor_str = cstring_to_str(&cursor, "cursor", ParamKind::Identifier);
+    let result = cursor_str.and_then(|cursor| {
+        let cursor = Some(cursor).filter(|cursor| !cursor.is_empty());
+        dependencies()?.alert_dao.alerts_page(cursor, page_size)
+    });
+    to_result_str(result)
+}
+
+// Debug builds only: JSON object with the canonical samples of the payloads, by name (see conformance.rs)
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub unsafe extern "C" fn get_conformance_samples() -> CFStringRef {
+    to_result_str(conformance::conformance_samples())
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn delete_alert(id: *const c_char) -> CFStringRef {
-    let id_str = cstring_to_str(&id);
-    let result = id_str.and_then(|id| dependencies().alert_dao.delete(id.to_owned()));
+    let id_str = cstring_to_str(&id, "id", ParamKind::Identifier);
+    let result = id_str.and_then(|id| dependencies()?.alert_dao.delete(id.to_owned()));
+    to_result_str(result)
+}
+
+// Returns the number of deleted alerts
+#[no_mangle]
+pub unsafe extern "C" fn dismiss_report(report_id: *const c_char) -> CFStringRef {
+    let report_id_str = cstring_to_str(&report_id, "report_id", ParamKind::Identifier);
+    let result = report_id_str.and_then(|report_id| {
+        dependencies()?
+            .alert_dao
+            .delete_by_report(report_id.to_owned())
+    });
     to_result_str(result)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn update_alert_is_read(id: *const c_char, is_read: u8) -> CFStringRef {
-    let id_str = cstring_to_str(&id);
+    let id_str = cstring_to_str(&id, "id", ParamKind::Identifier);
     let result = id_str.and_then(|id| {
-        dependencies()
+        dependencies()?
             .alert_dao
             .update_is_read(id.to_owned(), is_read == 1)
     });
     to_result_str(result)
 }
 
-// Meters
+// until: unix time. The alert isn't returned by fetch_new_reports / get_alerts_grouped until then.
+#[no_mangle]
+pub unsafe extern "C" fn snooze_alert(id: *const c_char, until: u64) -> CFStringRef {
+    let id_str = cstring_to_str(&id, "id", ParamKind::Identifier);
+    let result = id_str.and_then(|id| {
+        dependencies()?
+            .alert_dao
+            .snooze(id.to_owned(), UnixTime { value: until })
+    });
+    to_result_str(result)
+}
+
+// Error if raw reports aren't stored, e.g. in release builds
 #[no_mangle]
-pub unsafe extern "C" fn record_tcn(c_tcn: *const c_char, distance: f32) -> CFStringRef {
-    let tcn_str = cstring_to_str(&c_tcn);
+pub unsafe extern "C" fn get_raw_report(report_id: *const c_char) -> CFStringRef {
+    let report_id_str = cstring_to_str(&report_id, "report_id", ParamKind::Identifier);
+    let result = report_id_str.and_then(|report_id| {
+        dependencies()?
+            .reports_updater
+            .raw_report(report_id.to_owned())
+    });
+    to_result_str(result)
+}
+
+// Last db migrations that ran steps, for support diagnostics. Null if there weren't any.
+#[no_mangle]
+pub unsafe extern "C" fn get_last_migration_report() -> CFStringRef {
+    let result = dependencies().map(|dependencies| {
+        dependencies
+            .reports_updater
+            .preferences
+            .last_migration_report()
+    });
+    to_result_str(result)
+}
+
+// Debug builds only: the db migrations bootstrap would run (dry run). Can be called before bootstrap.
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub unsafe extern "C" fn preview_migrations(c_db_path: *const c_char) -> CFStringRef {
+    let result = cstring_to_str(&c_db_path, "db_path", ParamKind::Text)
+        .and_then(dependencies::preview_migrations);
+    to_result_str(result)
+}
+
+// Last reports update runs, most recent first
+#[no_mangle]
+pub unsafe extern "C" fn get_update_history() -> CFStringRef {
+    let result =
+        dependencies().and_then(|dependencies| dependencies.reports_updater.update_history());
+    to_result_str(result)
+}
+
+// Minutes since local midnight. New alerts aren't notified between start and end (see flush_pending_notifications).
+#[no_mangle]
+pub unsafe extern "C" fn set_quiet_hours(
+    c_enabled: u8,
+    c_start_minute: u32,
+    c_end_minute: u32,
+    c_utc_offset_minutes: i32,
+) -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        dependencies.reports_updater.set_quiet_hours(QuietHours {
+            enabled: c_enabled == 1,
+            start_minute: c_start_minute,
+            end_minute: c_end_minute,
+            utc_offset_minutes: c_utc_offset_minutes,
+        })
+    });
+    to_result_str(result)
+}
+
+// c_sigs_json: JSON array of base64 report signature prefixes. Matching reports are skipped by the next updates.
+#[no_mangle]
+pub unsafe extern "C" fn set_report_denylist(c_sigs_json: *const c_char) -> CFStringRef {
+    let result = cstring_to_str(&c_sigs_json, "sigs_json", ParamKind::Text).and_then(|sigs_json| {
+        dependencies()?
+            .reports_updater
+            .set_report_denylist(sigs_json)
+    });
+    to_result_str(result)
+}
+
+// c_sigs_json: like set_report_denylist. If not empty, only matching reports are processed. The denylist wins.
+#[no_mangle]
+pub unsafe extern "C" fn set_report_allowlist(c_sigs_json: *const c_char) -> CFStringRef {
+    let result = cstring_to_str(&c_sigs_json, "sigs_json", ParamKind::Text).and_then(|sigs_json| {
+        dependencies()?
+            .reports_updater
+            .set_report_allowlist(sigs_json)
+    });
+    to_result_str(result)
+}
+
+// Whether the app should notify about new alerts now. Call after fetch_new_reports and when the quiet hours end.
+#[no_mangle]
+pub unsafe extern "C" fn flush_pending_notifications() -> CFStringRef {
+    let result = dependencies()
+        .map(|dependencies| dependencies.reports_updater.flush_pending_notifications());
+    to_result_str(result)
+}
+
+// Short token referencing the alert, e.g. to read to a call center. See AlertReferences.
+#[no_mangle]
+pub unsafe extern "C" fn alert_reference_token(c_alert_id: *const c_char) -> CFStringRef {
+    let alert_id_str = cstring_to_str(&c_alert_id, "alert_id", ParamKind::Identifier);
+    let result = alert_id_str.and_then(|alert_id| {
+        dependencies()?
+            .alert_references
+            .alert_reference_token(alert_id)
+    });
+    to_result_str(result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn verify_alert_reference_token(
+    c_token: *const c_char,
+    c_alert_id: *const c_char,
+) -> CFStringRef {
+    let result = cstring_to_str(&c_token, "token", ParamKind::Identifier).and_then(|token| {
+        cstring_to_str(&c_alert_id, "alert_id", ParamKind::Identifier).and_then(|alert_id| {
+            dependencies()?
+                .alert_references
+                .verify_alert_reference_token(token, alert_id)
+        })
+    });
+    to_result_str(result)
+}
+
+// Posts the alert's acknowledgement to the configured health department endpoint (opt-in, user triggered).
+// Error if the export isn't configured. See AcknowledgementService.
+#[no_mangle]
+pub unsafe extern "C" fn submit_acknowledgement(c_alert_id: *const c_char) -> CFStringRef {
+    let alert_id_str = cstring_to_str(&c_alert_id, "alert_id", ParamKind::Identifier);
+    let result = alert_id_str.and_then(|alert_id| {
+        dependencies()?
+            .acknowledgement_service
+            .submit_acknowledgement(alert_id)
+    });
+    to_result_str(result)
+}
+
+// distance: meters. time: unix time of the observation (the scan, not the delivery to the core).
+// Error if in the future (see MAX_OBSERVATION_TIME_AHEAD_SECS).
+#[no_mangle]
+pub unsafe extern "C" fn record_tcn(c_tcn: *const c_char, distance: f32, time: i64) -> CFStringRef {
+    let tcn_str = cstring_to_str(&c_tcn, "tcn", ParamKind::Identifier);
     let result = tcn_str.and_then(|tcn_str| {
-        dependencies()
+        let time = unix_time_param("time", time)?;
+        dependencies()?
             .observed_tcn_processor
-            .save(tcn_str, distance)
+            .save_with_time(tcn_str, distance, time)
     });
     return to_result_str(result);
 }
 
-// NOTE: Returns directly success string
+// c_observations_json: JSON array of {tcn, distance}, observed now (e.g. a burst of scan results).
+// JSON object: accepted and invalid (malformed TCNs) observations.
+#[no_mangle]
+pub unsafe extern "C" fn record_tcns(c_observations_json: *const c_char) -> CFStringRef {
+    let observations_json_str =
+        cstring_to_str(&c_observations_json, "observations_json", ParamKind::Text);
+    let result = observations_json_str.and_then(record_tcns_json);
+    to_result_str(result)
+}
+
+// Writes the observed TCNs not flushed yet, e.g. when the app goes to background or is terminated.
+// Otherwise they're written periodically.
+#[no_mangle]
+pub unsafe extern "C" fn flush_observed_tcns() -> CFStringRef {
+    let result =
+        dependencies().and_then(|dependencies| dependencies.observed_tcn_processor.flush());
+    to_result_str(result)
+}
+
+// Counts of stored TCNs: total, matched by a report, unmatched
+#[no_mangle]
+pub unsafe extern "C" fn get_tcn_stats() -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .reports_updater
+            .tcn_dao
+            .stats()
+            .map(|stored| TcnDiagnostics {
+                stored,
+                malformed_tcn_observations: dependencies
+                    .observed_tcn_processor
+                    .malformed_tcn_observations(),
+            })
+    });
+    to_result_str(result)
+}
+
+// JSON object with what's stored on the device and when it's deleted (PrivacyReport): counts and
+// contact time range of the observed TCNs, active / deleted alerts, whether the keys exist, the
+// retention (days) and the next housekeeping runs. No TCNs, ids or keys.
+#[no_mangle]
+pub unsafe extern "C" fn get_privacy_report() -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        privacy_report(
+            dependencies.reports_updater.preferences.as_ref(),
+            dependencies.reports_updater.tcn_dao.as_ref(),
+            dependencies.alert_dao.as_ref(),
+            &dependencies.housekeeper,
+            &dependencies.reports_updater.risk_config,
+        )
+    });
+    to_result_str(result)
+}
+
+// Performance debugging (e.g. battery drain), cheap enough to call every few seconds. JSON object
+// (RuntimeStats): TCNs not flushed yet, log messages waiting for the callback and dropped, the running
+// fetch and for how long, matching threads and the last housekeeping runs.
+#[no_mangle]
+pub unsafe extern "C" fn get_runtime_stats() -> CFStringRef {
+    let result = dependencies().map(|dependencies| {
+        runtime_stats(
+            &dependencies.observed_tcn_processor,
+            &dependencies.reports_updater.fetch_tracker,
+            &dependencies.housekeeper,
+            simple_logger::LOG_QUEUE.stats(),
+            &(dependencies.reports_updater.clock)(),
+        )
+    });
+    to_result_str(result)
+}
+
+// Debugging: the last raw observations passed to record_tcn (JSON array of {tcn_prefix, distance, time}),
+// oldest first. TCNs are truncated to 4 bytes. Error if not enabled (recording.debug_recent_observations).
+#[no_mangle]
+pub unsafe extern "C" fn get_recent_observations_debug() -> CFStringRef {
+    let result = dependencies()
+        .and_then(|dependencies| dependencies.observed_tcn_processor.recent_observations());
+    to_result_str(result)
+}
+
+// Test support: alerts (JSON) for the reports and observed TCNs (JSON), without db or network.
+// See test_support::match_reports_json.
+#[cfg(feature = "test-support")]
+#[no_mangle]
+pub unsafe extern "C" fn match_reports_for_test(
+    c_reports_json: *const c_char,
+    c_observed_tcns_json: *const c_char,
+) -> CFStringRef {
+    let result =
+        cstring_to_str(&c_reports_json, "reports_json", ParamKind::Text).and_then(|reports_json| {
+            cstring_to_str(&c_observed_tcns_json, "observed_tcns_json", ParamKind::Text).and_then(
+                |observed_tcns_json| {
+                    crate::test_support::match_reports_json(reports_json, observed_tcns_json)
+                },
+            )
+        });
+    to_result_str(result)
+}
+
+// NOTE: Returns directly success string. Empty if the TCN couldn't be generated
+// (not bootstrapped or keys not readable), see the logs.
 #[no_mangle]
 pub unsafe extern "C" fn generate_tcn() -> CFStringRef {
     // TODO hex encoding in component, or send byte array directly?
-    let tcn_hex = hex::encode(
-        DEPENDENCIES
-            .get()
-            .expect("Not bootstrapped")
-            .tcn_keys
-            .generate_tcn()
-            .0,
-    );
-    info!("Generated TCN: {:?}", tcn_hex);
+    let tcn_res = dependencies().and_then(|dependencies| dependencies.tcn_keys.generate_tcn());
+    let tcn_hex = match tcn_res {
+        Ok(tcn) => TcnHex::from(&tcn).into_string(),
+        Err(e) => {
+            error!("Couldn't generate TCN: {:?}", e);
+            "".to_owned()
+        }
+    };
+    info!("Generated TCN: {}", tcn_hex);
 
-    let cf_string = CFString::new(&tcn_hex);
+    let cf_string = CFString::new(tcn_hex.as_str());
     let cf_string_ref = cf_string.as_concrete_TypeRef();
 
     ::std::mem::forget(cf_string);
@@ -115,6 +498,18 @@ pub unsafe extern "C" fn generate_tcn() -> CFStringRef {
     cf_string_ref
 }
 
+// TCN (hex) and its expiry: {"tcn": "...", "expiry": 1591706700}
+#[no_mangle]
+pub unsafe extern "C" fn generate_tcn_with_expiry() -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .tcn_keys
+            .current_tcn_with_expiry()
+            .map(|(tcn, expiry)| TcnWithExpiry::new(&tcn, expiry))
+    });
+    to_result_str(result)
+}
+
 fn to_result_str<T: Serialize>(result: Result<T, ServicesError>) -> CFStringRef {
     let lib_result = match result {
         Ok(success) => LibResult {
@@ -124,9 +519,20 @@ fn to_result_str<T: Serialize>(result: Result<T, ServicesError>) -> CFStringRef
         },
         // TODO better error identification, using HTTP status for everything is weird.
         Err(e) => LibResult {
-            status: 500,
+            status: match e {
+                // The app can bootstrap (again) and retry
+                ServicesError::NotBootstrapped => 503,
+                ServicesError::InvalidConfig(_) => 400,
+                // The app can ask the user to free space
+                ServicesError::StorageFull => 507,
+                _ => 500,
+            },
             data: None,
-            error_message: Some(e.to_string()),
+            error_message: Some(match &e {
+                // JSON array of {field, reason}
+                ServicesError::InvalidConfig(violations) => config_violations_json(violations),
+                _ => e.to_string(),
+            }),
         },
     };
 
@@ -151,12 +557,13 @@ fn fallback_error_result_str<T: Serialize>() -> String {
     .unwrap()
 }
 
+// c_ids: JSON array of strings or comma separated. Result: the accepted and rejected ids.
 #[no_mangle]
 pub unsafe extern "C" fn set_symptom_ids(c_ids: *const c_char) -> CFStringRef {
     debug!("Setting symptom ids: {:?}", c_ids);
-    let ids_str = cstring_to_str(&c_ids);
+    let ids_str = cstring_to_str(&c_ids, "ids", ParamKind::Identifier);
     let result = ids_str.and_then(|ids_str| {
-        dependencies()
+        dependencies()?
             .symptom_inputs_processor
             .set_symptom_ids(ids_str)
     });
@@ -166,9 +573,9 @@ pub unsafe extern "C" fn set_symptom_ids(c_ids: *const c_char) -> CFStringRef {
 #[no_mangle]
 pub unsafe extern "C" fn set_cough_type(c_cough_type: *const c_char) -> CFStringRef {
     debug!("Setting cough type: {:?}", c_cough_type);
-    let cough_type_str = cstring_to_str(&c_cough_type);
+    let cough_type_str = cstring_to_str(&c_cough_type, "cough_type", ParamKind::Identifier);
     let result = cough_type_str.and_then(|cough_type_str| {
-        dependencies()
+        dependencies()?
             .symptom_inputs_processor
             .set_cough_type(cough_type_str)
     });
@@ -177,18 +584,20 @@ pub unsafe extern "C" fn set_cough_type(c_cough_type: *const c_char) -> CFString
 
 #[no_mangle]
 pub unsafe extern "C" fn set_cough_days(c_is_set: u8, c_days: u32) -> CFStringRef {
-    let result = dependencies()
-        .symptom_inputs_processor
-        .set_cough_days(c_is_set == 1, c_days);
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_cough_days(c_is_set == 1, c_days)
+    });
     return to_result_str(result);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn set_cough_status(c_status: *const c_char) -> CFStringRef {
     info!("Setting cough status: {:?}", c_status);
-    let status_str = cstring_to_str(&c_status);
+    let status_str = cstring_to_str(&c_status, "cough_status", ParamKind::Identifier);
     let result = status_str.and_then(|status_str| {
-        dependencies()
+        dependencies()?
             .symptom_inputs_processor
             .set_cough_status(status_str)
     });
@@ -198,9 +607,9 @@ pub unsafe extern "C" fn set_cough_status(c_status: *const c_char) -> CFStringRe
 #[no_mangle]
 pub unsafe extern "C" fn set_breathlessness_cause(c_cause: *const c_char) -> CFStringRef {
     debug!("Setting breathlessness cause: {:?}", c_cause);
-    let cause_str = cstring_to_str(&c_cause);
+    let cause_str = cstring_to_str(&c_cause, "cause", ParamKind::Identifier);
     let result = cause_str.and_then(|cause_str| {
-        dependencies()
+        dependencies()?
             .symptom_inputs_processor
             .set_breathlessness_cause(cause_str)
     });
@@ -209,9 +618,11 @@ pub unsafe extern "C" fn set_breathlessness_cause(c_cause: *const c_char) -> CFS
 
 #[no_mangle]
 pub unsafe extern "C" fn set_fever_days(c_is_set: u8, c_days: u32) -> CFStringRef {
-    let result = dependencies()
-        .symptom_inputs_processor
-        .set_fever_days(c_is_set == 1, c_days);
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_fever_days(c_is_set == 1, c_days)
+    });
     return to_result_str(result);
 }
 
@@ -220,18 +631,20 @@ pub unsafe extern "C" fn set_fever_taken_temperature_today(
     c_is_set: u8,
     c_taken: u8,
 ) -> CFStringRef {
-    let result = dependencies()
-        .symptom_inputs_processor
-        .set_fever_taken_temperature_today(c_is_set == 1, c_taken == 1);
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_fever_taken_temperature_today(c_is_set == 1, c_taken == 1)
+    });
     return to_result_str(result);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn set_fever_taken_temperature_spot(c_cause: *const c_char) -> CFStringRef {
     debug!("Setting temperature spot cause: {:?}", c_cause);
-    let spot_str = cstring_to_str(&c_cause);
+    let spot_str = cstring_to_str(&c_cause, "spot", ParamKind::Identifier);
     let result = spot_str.and_then(|spot_str| {
-        dependencies()
+        dependencies()?
             .symptom_inputs_processor
             .set_fever_taken_temperature_spot(spot_str)
     });
@@ -243,9 +656,11 @@ pub unsafe extern "C" fn set_fever_highest_temperature_taken(
     c_is_set: u8,
     c_temp: f32,
 ) -> CFStringRef {
-    let result = dependencies()
-        .symptom_inputs_processor
-        .set_fever_highest_temperature_taken(c_is_set == 1, c_temp);
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_fever_highest_temperature_taken(c_is_set == 1, c_temp)
+    });
     return to_result_str(result);
 }
 
@@ -254,21 +669,57 @@ pub unsafe extern "C" fn set_earliest_symptom_started_days_ago(
     c_is_set: u8,
     c_days: u32,
 ) -> CFStringRef {
-    let result = dependencies()
-        .symptom_inputs_processor
-        .set_earliest_symptom_started_days_ago(c_is_set == 1, c_days);
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_earliest_symptom_started_days_ago(c_is_set == 1, c_days)
+    });
+    return to_result_str(result);
+}
+
+// c_time: unix time. Not in the future and at most 90 days ago.
+#[no_mangle]
+pub unsafe extern "C" fn set_earliest_symptom_date(c_is_set: u8, c_time: u64) -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .set_earliest_symptom_date(c_is_set == 1, c_time)
+    });
     return to_result_str(result);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn clear_symptoms() -> CFStringRef {
-    let result = dependencies().symptom_inputs_processor.clear();
+    let result =
+        dependencies().and_then(|dependencies| dependencies.symptom_inputs_processor.clear());
     return to_result_str(result);
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn submit_symptoms() -> CFStringRef {
-    let result = dependencies().symptom_inputs_processor.submit();
+    let result = dependencies()
+        .and_then(|dependencies| dependencies.symptom_inputs_processor.submit())
+        .and_then(SubmitOutcome::into_void_result);
+    return to_result_str(result);
+}
+
+// Like submit_symptoms. data: the outcome, e.g. {"outcome": "sent", "receipt": "..."} (see SubmitOutcome).
+// A rejected report isn't an error: the status is 200.
+#[no_mangle]
+pub unsafe extern "C" fn submit_symptoms_with_outcome() -> CFStringRef {
+    let result =
+        dependencies().and_then(|dependencies| dependencies.symptom_inputs_processor.submit());
+    return to_result_str(result);
+}
+
+// Resubmits the last submitted symptoms with a corrected earliest symptom date
+#[no_mangle]
+pub unsafe extern "C" fn resubmit_with_earliest_symptom(c_days_ago: u32) -> CFStringRef {
+    let result = dependencies().and_then(|dependencies| {
+        dependencies
+            .symptom_inputs_processor
+            .resubmit_with_earliest_symptom(c_days_ago)
+    });
     return to_result_str(result);
 }
 
@@ -276,29 +727,53 @@ pub unsafe extern "C" fn submit_symptoms() -> CFStringRef {
 pub unsafe extern "C" fn post_report(c_report: *const c_char) -> CFStringRef {
     info!("Posting report: {:?}", c_report);
 
-    let report = cstring_to_str(&c_report);
+    let report = cstring_to_str(&c_report, "report", ParamKind::Text);
 
     let result = report.and_then(|report| {
-        dependencies()
+        let dependencies = dependencies()?;
+        dependencies
             .api
-            .post_report(report.to_owned())
+            .post_report(PostReportRequest::new(
+                report.to_owned(),
+                dependencies.client_version.clone(),
+            ))
             .map_err(ServicesError::from)
     });
 
     return to_result_str(result);
 }
 
-// Convert C string to Rust string slice
-pub unsafe fn cstring_to_str<'a>(cstring: &'a *const c_char) -> Result<&str, ServicesError> {
+// Convert C string to Rust string slice. name: of the parameter, for the errors (see param_str).
+#[derive(Debug, Deserialize)]
+struct TcnObservationJson {
+    tcn: String,
+    distance: f32,
+}
+
+fn record_tcns_json(observations_json: &str) -> Result<SaveBatchOutcome, ServicesError> {
+    let observations: Vec<TcnObservationJson> =
+        serde_json::from_str(observations_json).map_err(|e| {
+            ServicesError::FFIParameters(format!("Invalid TCN observations JSON: {}", e))
+        })?;
+    dependencies()?.observed_tcn_processor.save_batch(
+        observations
+            .into_iter()
+            .map(|observation| (observation.tcn, observation.distance))
+            .collect(),
+    )
+}
+
+pub unsafe fn cstring_to_str<'a>(
+    cstring: &'a *const c_char,
+    name: &str,
+    kind: ParamKind,
+) -> Result<&'a str, ServicesError> {
     if cstring.is_null() {
-        return Err(ServicesError::FFIParameters("cstring is null".to_owned()));
+        return Err(null_param(name));
     }
 
     let raw = ::std::ffi::CStr::from_ptr(*cstring);
-    match raw.to_str() {
-        Ok(s) => Ok(s),
-        Err(e) => Err(ServicesError::FFIParameters(e.to_string())),
-    }
+    param_str(name, raw.to_bytes(), kind)
 }
 
 #[repr(C)]
@@ -354,6 +829,7 @@ fn register_log_callback_internal(callback: Box<dyn LogCallback>) {
     // Thread waits for elements pushed to SENDER and calls the callback
     thread::spawn(move || {
         for log_entry in rx.iter() {
+            simple_logger::LOG_QUEUE.forwarded();
             log_callback.call(log_entry.into());
         }
     });
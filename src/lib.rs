@@ -3,18 +3,32 @@ extern crate serde_big_array;
 use errors::Error;
 use std::io::Cursor;
 use tcn::SignedReport;
+mod config;
+#[cfg(any(test, debug_assertions))]
+mod conformance;
 mod database;
 mod dependencies;
 mod errors;
 mod extensions;
+mod ffi_params;
+mod housekeeping;
+mod metrics;
 mod networking;
+mod privacy;
 mod reporting;
 mod reports_interval;
 mod reports_update;
+mod runtime_stats;
 mod simple_logger;
 mod tcn_ext;
 mod tcn_recording;
 
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
 #[cfg(any(target_os = "ios", target_os = "macos"))]
 mod ios;
 
@@ -23,35 +37,44 @@ mod android;
 
 pub type Res<T> = Result<T, Error>;
 
-// TODO refactor these (byte_vec_to) convertions or better way?
-
-// TODO move to utils file or similar. Consider returning Result instead of panicking.
-pub fn byte_vec_to_16_byte_array(bytes: Vec<u8>) -> [u8; 16] {
+// TODO move to utils file or similar.
+// The byte count must be the array's length: shorter and longer input is an error (not truncated),
+// since the bytes come from the apps (e.g. TCNs) or the database, where a wrong length means malformed data.
+pub fn byte_vec_to_16_byte_array(bytes: Vec<u8>) -> Res<[u8; 16]> {
     let mut array = [0; 16];
-    let bytes = &bytes[..array.len()]; // panics if not enough data
-    array.copy_from_slice(bytes);
-    array
+    copy_to_array(&bytes, &mut array)?;
+    Ok(array)
 }
 
-pub fn byte_vec_to_24_byte_array(bytes: Vec<u8>) -> [u8; 24] {
+pub fn byte_vec_to_24_byte_array(bytes: Vec<u8>) -> Res<[u8; 24]> {
     let mut array = [0; 24];
-    let bytes = &bytes[..array.len()]; // panics if not enough data
-    array.copy_from_slice(bytes);
-    array
+    copy_to_array(&bytes, &mut array)?;
+    Ok(array)
 }
 
-pub fn byte_vec_to_8_byte_array(bytes: Vec<u8>) -> [u8; 8] {
+pub fn byte_vec_to_8_byte_array(bytes: Vec<u8>) -> Res<[u8; 8]> {
     let mut array = [0; 8];
-    let bytes = &bytes[..array.len()]; // panics if not enough data
-    array.copy_from_slice(bytes);
-    array
+    copy_to_array(&bytes, &mut array)?;
+    Ok(array)
 }
 
-pub fn byte_vec_to_32_byte_array(bytes: Vec<u8>) -> [u8; 32] {
+pub fn byte_vec_to_32_byte_array(bytes: Vec<u8>) -> Res<[u8; 32]> {
     let mut array = [0; 32];
-    let bytes = &bytes[..array.len()]; // panics if not enough data
+    copy_to_array(&bytes, &mut array)?;
+    Ok(array)
+}
+
+fn copy_to_array(bytes: &[u8], array: &mut [u8]) -> Res<()> {
+    if bytes.len() != array.len() {
+        return Err(format!(
+            "Invalid byte count: {}, expected: {}",
+            bytes.len(),
+            array.len()
+        )
+        .into());
+    }
     array.copy_from_slice(bytes);
-    array
+    Ok(())
 }
 
 // TODO (deleting of TCNs not critical for now)
@@ -124,3 +147,50 @@ fn signed_report_to_bytes(signed_report: SignedReport) -> Vec<u8> {
         .expect("Couldn't write signed report bytes");
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_vec_to_array_of_exact_length() {
+        let bytes: Vec<u8> = (0..32).collect();
+
+        let array_res = byte_vec_to_32_byte_array(bytes.clone());
+        assert!(array_res.is_ok());
+        assert_eq!(bytes, array_res.unwrap().to_vec());
+
+        let array_res = byte_vec_to_16_byte_array(bytes[..16].to_vec());
+        assert!(array_res.is_ok());
+        assert_eq!(bytes[..16], array_res.unwrap());
+    }
+
+    #[test]
+    fn test_byte_vec_to_array_of_short_input_is_error() {
+        let array_res = byte_vec_to_16_byte_array(vec![1; 15]);
+        assert!(array_res.is_err());
+        assert_eq!(
+            "Invalid byte count: 15, expected: 16",
+            array_res.unwrap_err().to_string()
+        );
+
+        assert!(byte_vec_to_8_byte_array(vec![]).is_err());
+        assert!(byte_vec_to_24_byte_array(vec![1; 23]).is_err());
+        assert!(byte_vec_to_32_byte_array(vec![1; 16]).is_err());
+    }
+
+    // Not truncated: the extra bytes mean the input is malformed
+    #[test]
+    fn test_byte_vec_to_array_of_long_input_is_error() {
+        let array_res = byte_vec_to_16_byte_array(vec![1; 17]);
+        assert!(array_res.is_err());
+        assert_eq!(
+            "Invalid byte count: 17, expected: 16",
+            array_res.unwrap_err().to_string()
+        );
+
+        assert!(byte_vec_to_8_byte_array(vec![1; 9]).is_err());
+        assert!(byte_vec_to_24_byte_array(vec![1; 32]).is_err());
+        assert!(byte_vec_to_32_byte_array(vec![1; 64]).is_err());
+    }
+}
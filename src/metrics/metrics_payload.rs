@@ -0,0 +1,133 @@
+use crate::reports_interval::UnixTime;
+use rand::Rng;
+use serde::Serialize;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// The alerts count is noised by up to ALERTS_NOISE and rounded to a multiple of ALERTS_ROUNDING,
+// so an upload doesn't reveal the exact count of the device.
+const ALERTS_NOISE: i64 = 2;
+const ALERTS_ROUNDING: u64 = 5;
+
+// Randomized response: with this probability, scanning is a coin flip instead of the actual value.
+const SCANNING_RANDOM_RESPONSE_PROBABILITY: f64 = 0.25;
+
+// Local counts of the last day, the input of the payload. Never uploaded as is.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DailyCounts {
+    pub observed_tcns: usize,
+    pub alerts: usize,
+}
+
+// Everything that's uploaded. Counts only: it must not get fields with TCNs, reports, keys or
+// identifiers (test_payload_has_no_identifying_fields).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricsPayload {
+    // Days since the unix epoch
+    pub day: u64,
+    // Whether the device observed TCNs in the day. Randomized response.
+    pub scanning: bool,
+    // Alerts of exposures in the day. Noised and rounded.
+    pub alerts: u64,
+}
+
+impl MetricsPayload {
+    pub fn new<R: Rng>(counts: &DailyCounts, now: &UnixTime, rng: &mut R) -> MetricsPayload {
+        MetricsPayload {
+            day: now.value / SECONDS_PER_DAY,
+            scanning: randomized_response(counts.observed_tcns > 0, rng),
+            alerts: noised_count(counts.alerts, rng),
+        }
+    }
+}
+
+fn randomized_response<R: Rng>(value: bool, rng: &mut R) -> bool {
+    if rng.gen_bool(SCANNING_RANDOM_RESPONSE_PROBABILITY) {
+        rng.gen()
+    } else {
+        value
+    }
+}
+
+fn noised_count<R: Rng>(count: usize, rng: &mut R) -> u64 {
+    let noise = rng.gen_range(-ALERTS_NOISE, ALERTS_NOISE + 1);
+    let noised = (count as i64).saturating_add(noise).max(0) as u64;
+    (noised + ALERTS_ROUNDING / 2) / ALERTS_ROUNDING * ALERTS_ROUNDING
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::Value;
+
+    #[test]
+    fn test_payload_contains_only_counts() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let payload = MetricsPayload::new(
+            &DailyCounts {
+                observed_tcns: 120,
+                alerts: 3,
+            },
+            &UnixTime { value: 1591706000 },
+            &mut rng,
+        );
+
+        let json = serde_json::to_value(&payload).unwrap();
+        let object = json.as_object().unwrap();
+        let keys: Vec<&str> = object.keys().map(|key| key.as_str()).collect();
+        assert_eq!(vec!["alerts", "day", "scanning"], keys);
+        assert!(object
+            .values()
+            .all(|value| value.is_boolean() || value.is_u64()));
+        assert_eq!(Value::from(18422), object["day"]);
+    }
+
+    #[test]
+    fn test_payload_has_no_identifying_fields() {
+        let denylist = [
+            "tcn", "report", "sig", "rak", "rvk", "tck", "memo", "key", "token", "id",
+        ];
+
+        let payload = MetricsPayload::new(
+            &DailyCounts::default(),
+            &UnixTime { value: 0 },
+            &mut StdRng::seed_from_u64(1),
+        );
+        let json = serde_json::to_value(&payload).unwrap();
+
+        for key in json.as_object().unwrap().keys() {
+            for denied in denylist.iter() {
+                assert!(
+                    !key.contains(denied),
+                    "Field: {} matches denylist: {}",
+                    key,
+                    denied
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_alerts_are_noised_and_rounded() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            // The noise doesn't reach the next multiple
+            assert_eq!(0, noised_count(0, &mut rng));
+            assert_eq!(10, noised_count(10, &mut rng));
+
+            let alerts = noised_count(7, &mut rng);
+            assert!(alerts == 5 || alerts == 10, "Alerts: {}", alerts);
+        }
+    }
+
+    #[test]
+    fn test_scanning_is_mostly_the_actual_value() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let flipped = (0..1000)
+            .filter(|_| randomized_response(false, &mut rng))
+            .count();
+        // Expected: 125 (coin flip in 25% of the responses)
+        assert!(flipped > 50 && flipped < 250, "Flipped: {}", flipped);
+    }
+}
@@ -0,0 +1,183 @@
+use super::metrics_payload::{DailyCounts, MetricsPayload};
+use crate::{
+    config::MetricsConfig,
+    database::{alert_dao::AlertDao, preferences::Preferences, tcn_dao::TcnDao},
+    errors::ServicesError,
+    networking::MetricsApi,
+    reports_interval::UnixTime,
+};
+use log::*;
+use std::sync::Arc;
+
+const UPLOAD_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+// Uploads the anonymous aggregate counts of the device (MetricsPayload), if enabled in the config.
+// At most once per day.
+pub struct MetricsUploader<A: MetricsApi, P: Preferences, T: TcnDao, D: AlertDao> {
+    pub api: A,
+    pub preferences: Arc<P>,
+    pub tcn_dao: Arc<T>,
+    pub alert_dao: Arc<D>,
+    pub config: MetricsConfig,
+    pub clock: fn() -> UnixTime,
+}
+
+impl<A: MetricsApi, P: Preferences, T: TcnDao, D: AlertDao> MetricsUploader<A, P, T, D> {
+    pub fn new(
+        api: A,
+        preferences: Arc<P>,
+        tcn_dao: Arc<T>,
+        alert_dao: Arc<D>,
+        config: MetricsConfig,
+    ) -> MetricsUploader<A, P, T, D> {
+        MetricsUploader {
+            api,
+            preferences,
+            tcn_dao,
+            alert_dao,
+            config,
+            clock: UnixTime::now,
+        }
+    }
+
+    // Returns whether the metrics were uploaded
+    pub fn upload_if_due(&self) -> Result<bool, ServicesError> {
+        if !self.config.enabled {
+            return Ok(false);
+        }
+        if self.config.endpoint.is_empty() {
+            warn!("Metrics enabled without endpoint, not uploading");
+            return Ok(false);
+        }
+
+        let now = (self.clock)();
+        if let Some(last_upload) = self.preferences.last_metrics_upload() {
+            if now.value.saturating_sub(last_upload) < UPLOAD_INTERVAL_SECS {
+                return Ok(false);
+            }
+        }
+
+        let payload = MetricsPayload::new(&self.daily_counts(&now)?, &now, &mut rand::thread_rng());
+        self.api.post_metrics(serde_json::to_string(&payload)?)?;
+        self.preferences.set_last_metrics_upload(now.value);
+
+        Ok(true)
+    }
+
+    fn daily_counts(&self, now: &UnixTime) -> Result<DailyCounts, ServicesError> {
+        let day_start = now.value.saturating_sub(UPLOAD_INTERVAL_SECS);
+
+        let observed_tcns = self
+            .tcn_dao
+            .all()?
+            .iter()
+            .filter(|tcn| tcn.contact_end.value >= day_start)
+            .count();
+        let alerts = self
+            .alert_dao
+            .all_including_snoozed()?
+            .iter()
+            .filter(|alert| alert.contact_end >= day_start)
+            .count();
+
+        Ok(DailyCounts {
+            observed_tcns,
+            alerts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            alert_dao::AlertDaoImpl,
+            preferences::{PreferencesDao, PreferencesImpl},
+            tcn_dao::TcnDaoImpl,
+        },
+        networking::NetworkingError,
+        test_support::migrated_database,
+    };
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct MetricsApiRecordingMock {
+        posted: Mutex<Vec<String>>,
+    }
+
+    impl MetricsApi for MetricsApiRecordingMock {
+        fn post_metrics(&self, payload: String) -> Result<(), NetworkingError> {
+            self.posted.lock().push(payload);
+            Ok(())
+        }
+    }
+
+    type TestUploader =
+        MetricsUploader<MetricsApiRecordingMock, PreferencesImpl, TcnDaoImpl, AlertDaoImpl>;
+
+    fn create_uploader(config: MetricsConfig) -> TestUploader {
+        let database = migrated_database();
+        let mut uploader = MetricsUploader::new(
+            MetricsApiRecordingMock::default(),
+            Arc::new(PreferencesImpl {
+                dao: PreferencesDao::new(database.clone()),
+            }),
+            Arc::new(TcnDaoImpl::new(database.clone())),
+            Arc::new(AlertDaoImpl::new(database)),
+            config,
+        );
+        uploader.clock = || UnixTime { value: 1591706000 };
+        uploader
+    }
+
+    fn enabled_config() -> MetricsConfig {
+        MetricsConfig {
+            enabled: true,
+            endpoint: "https://metrics.example.org".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        let uploader = create_uploader(MetricsConfig::default());
+
+        assert!(!uploader.upload_if_due().unwrap());
+        assert!(uploader.api.posted.lock().is_empty());
+        assert_eq!(None, uploader.preferences.last_metrics_upload());
+    }
+
+    #[test]
+    fn test_uploads_at_most_once_per_day() {
+        let mut uploader = create_uploader(enabled_config());
+
+        assert!(uploader.upload_if_due().unwrap());
+        assert_eq!(Some(1591706000), uploader.preferences.last_metrics_upload());
+
+        uploader.clock = || UnixTime {
+            value: 1591706000 + UPLOAD_INTERVAL_SECS - 1,
+        };
+        assert!(!uploader.upload_if_due().unwrap());
+
+        uploader.clock = || UnixTime {
+            value: 1591706000 + UPLOAD_INTERVAL_SECS,
+        };
+        assert!(uploader.upload_if_due().unwrap());
+
+        let posted = uploader.api.posted.lock();
+        assert_eq!(2, posted.len());
+        let payload: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(serde_json::Value::from(18422), payload["day"]);
+    }
+
+    #[test]
+    fn test_enabled_without_endpoint_doesnt_upload() {
+        let uploader = create_uploader(MetricsConfig {
+            enabled: true,
+            endpoint: "".to_owned(),
+        });
+
+        assert!(!uploader.upload_if_due().unwrap());
+        assert!(uploader.api.posted.lock().is_empty());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod metrics_payload;
+pub mod metrics_uploader;
@@ -1,23 +1,120 @@
+use crate::config::{ClientVersion, NetworkingConfig};
 use core::fmt;
 use log::*;
 use reqwest::{
     blocking::{Client, Response},
     Error,
 };
-use std::error;
+use serde::Deserialize;
+use std::{error, io::Read};
+use uuid::Uuid;
 
-static BASE_URL: &str = "https://zmqh8rwdx4.execute-api.us-west-2.amazonaws.com/v4/tcnreport/0.4.0";
+// Default of NetworkingConfig::api_url
+pub const DEFAULT_API_URL: &str =
+    "https://zmqh8rwdx4.execute-api.us-west-2.amazonaws.com/v4/tcnreport/0.4.0";
 // static BASE_URL: &str = "https://v1.api.coepi.org/tcnreport/v0.4.0";
 
 static UNKNOWN_HTTP_STATUS: u16 = 520;
+static PAYLOAD_TOO_LARGE_HTTP_STATUS: u16 = 413;
 
 pub trait TcnApi {
+    // All the pages of the interval (see get_reports_pages)
     fn get_reports(
         &self,
         interval_number: u64,
         interval_length: u64,
     ) -> Result<Vec<String>, NetworkingError>;
-    fn post_report(&self, report: String) -> Result<(), NetworkingError>;
+    fn post_report(&self, request: PostReportRequest) -> Result<(), NetworkingError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostReportRequest {
+    pub report: String,
+    // Sent as header. Retries of a submission reuse the key, so the backend can discard duplicates.
+    pub idempotency_key: String,
+    // Sent as headers: the report's memo has no room for it.
+    pub client_version: ClientVersion,
+}
+
+impl PostReportRequest {
+    // Creates a request with a new idempotency key
+    pub fn new(report: String, client_version: ClientVersion) -> PostReportRequest {
+        PostReportRequest {
+            report,
+            idempotency_key: Uuid::new_v4().to_string(),
+            client_version,
+        }
+    }
+}
+
+// A page of an interval's reports
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReportsPage {
+    pub reports: Vec<String>,
+    pub has_more: bool,
+}
+
+// Of all the pages of an interval
+#[derive(Debug, Clone, PartialEq)]
+pub struct PagingLimits {
+    pub max_pages: u32,
+    pub max_bytes: u64, // Of the reports
+}
+
+impl Default for PagingLimits {
+    fn default() -> Self {
+        let config = NetworkingConfig::default();
+        PagingLimits {
+            max_pages: config.max_report_pages_per_interval,
+            max_bytes: config.max_reports_bytes_per_interval,
+        }
+    }
+}
+
+// Requests the pages 0, 1, ... of an interval until the last one (empty or without more),
+// concatenating their reports. get_page(page, max_bytes): max_bytes is what's left of the budget.
+// An interval exceeding the limits fails as payload too large, instead of being returned partially.
+pub fn get_reports_pages<F>(
+    limits: &PagingLimits,
+    mut get_page: F,
+) -> Result<Vec<String>, NetworkingError>
+where
+    F: FnMut(u32, u64) -> Result<ReportsPage, NetworkingError>,
+{
+    let mut reports = vec![];
+    let mut bytes: u64 = 0;
+    for page in 0..limits.max_pages {
+        let ReportsPage {
+            reports: page_reports,
+            has_more,
+        } = get_page(page, limits.max_bytes.saturating_sub(bytes))?;
+
+        bytes += page_reports
+            .iter()
+            .map(|report| report.len() as u64)
+            .sum::<u64>();
+        if bytes > limits.max_bytes {
+            return Err(NetworkingError::payload_too_large(bytes, limits.max_bytes));
+        }
+
+        // An empty page is the last one even if the server says otherwise, so it can't loop
+        let is_last = page_reports.is_empty() || !has_more;
+        reports.extend(page_reports);
+        if is_last {
+            return Ok(reports);
+        }
+    }
+    Err(NetworkingError::too_many_pages(limits.max_pages))
+}
+
+// Posts the aggregate metrics JSON (see MetricsPayload)
+pub trait MetricsApi {
+    fn post_metrics(&self, payload: String) -> Result<(), NetworkingError>;
+}
+
+// Posts the acknowledgement JSON of an alert (see AcknowledgementPayload)
+pub trait AcknowledgementApi {
+    fn post_acknowledgement(&self, payload: String) -> Result<(), NetworkingError>;
 }
 
 pub struct TcnApiMock {}
@@ -34,12 +131,28 @@ impl TcnApi for TcnApiMock {
         })
     }
 
-    fn post_report(&self, _report: String) -> Result<(), NetworkingError> {
+    fn post_report(&self, _request: PostReportRequest) -> Result<(), NetworkingError> {
         Ok(())
     }
 }
 
-pub struct TcnApiImpl {}
+#[derive(Debug, PartialEq)]
+pub struct TcnApiImpl {
+    pub api_url: String,
+    pub max_reports_bytes: u64,
+    pub max_report_pages: u32,
+}
+
+impl Default for TcnApiImpl {
+    fn default() -> Self {
+        let config = NetworkingConfig::default();
+        TcnApiImpl {
+            api_url: config.api_url,
+            max_reports_bytes: config.max_reports_bytes_per_interval,
+            max_report_pages: config.max_report_pages_per_interval,
+        }
+    }
+}
 
 impl TcnApiImpl {
     fn create_client() -> Result<Client, Error> {
@@ -60,28 +173,40 @@ impl TcnApi for TcnApiImpl {
             interval_number, interval_length
         );
 
-        let url: &str = BASE_URL;
         let client = Self::create_client()?;
-        let response = client
-            .get(url)
-            .header("Content-Type", "application/json")
-            .query(&[("intervalNumber", interval_number)])
-            .query(&[("intervalLength", interval_length)])
-            .send()?;
-        let reports = response.json::<Vec<String>>()?;
+        let limits = PagingLimits {
+            max_pages: self.max_report_pages,
+            max_bytes: self.max_reports_bytes,
+        };
+        let reports = get_reports_pages(&limits, |page, max_bytes| {
+            let response = client
+                .get(&self.api_url)
+                .header("Content-Type", "application/json")
+                .query(&[("intervalNumber", interval_number)])
+                .query(&[("intervalLength", interval_length)])
+                .query(&[("page", page)])
+                .send()?;
+            let content_length = response.content_length();
+            read_reports(response, content_length, max_bytes)
+        })?;
         info!("Retrieved reports count: {}", reports.len());
         Ok(reports)
     }
 
-    fn post_report(&self, report: String) -> Result<(), NetworkingError> {
-        info!("Posting report: {}", report);
+    fn post_report(&self, request: PostReportRequest) -> Result<(), NetworkingError> {
+        info!(
+            "Posting report: {}, idempotency key: {}, client version: {:?}",
+            request.report, request.idempotency_key, request.client_version
+        );
 
-        let url: &str = BASE_URL;
         let client = Self::create_client()?;
         let response = client
-            .post(url)
+            .post(&self.api_url)
             .header("Content-Type", "application/json")
-            .body(report)
+            .header("Idempotency-Key", request.idempotency_key)
+            .header("X-Core-Version", header_value(&request.client_version.core))
+            .header("X-App-Version", header_value(&request.client_version.app))
+            .body(request.report)
             .send()?;
 
         info!("Post report success: {:?}", response);
@@ -89,12 +214,145 @@ impl TcnApi for TcnApiImpl {
     }
 }
 
+pub struct MetricsApiImpl {
+    pub endpoint: String,
+}
+
+impl MetricsApi for MetricsApiImpl {
+    fn post_metrics(&self, payload: String) -> Result<(), NetworkingError> {
+        info!("Posting metrics: {}", payload);
+
+        let client = TcnApiImpl::create_client()?;
+        let response = client
+            .post(&self.endpoint)
+            .header("Content-Type", "application/json")
+            .body(payload)
+            .send()?
+            .as_result()?;
+
+        info!("Post metrics success: {:?}", response);
+        Ok(())
+    }
+}
+
+pub struct AcknowledgementApiImpl {
+    pub endpoint: String,
+}
+
+impl AcknowledgementApi for AcknowledgementApiImpl {
+    fn post_acknowledgement(&self, payload: String) -> Result<(), NetworkingError> {
+        info!("Posting acknowledgement: {}", payload);
+
+        let client = TcnApiImpl::create_client()?;
+        let response = client
+            .post(&self.endpoint)
+            .header("Content-Type", "application/json")
+            .body(payload)
+            .send()?
+            .as_result()?;
+
+        info!("Post acknowledgement success: {:?}", response);
+        Ok(())
+    }
+}
+
+// Body of a reports response
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReportsBody {
+    // Backends that don't page: the whole interval
+    Unpaged(Vec<String>),
+    // Without has_more, the last page is the empty one
+    Paged {
+        reports: Vec<String>,
+        has_more: Option<bool>,
+    },
+}
+
+// Rejects the response if the declared length or the body exceeds max_bytes.
+// The body is read up to max_bytes + 1, so an oversized body isn't held in memory.
+fn read_reports<T: Read>(
+    body: T,
+    content_length: Option<u64>,
+    max_bytes: u64,
+) -> Result<ReportsPage, NetworkingError> {
+    if let Some(length) = content_length {
+        if length > max_bytes {
+            return Err(NetworkingError::payload_too_large(length, max_bytes));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    body.take(max_bytes + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| NetworkingError {
+            http_status: UNKNOWN_HTTP_STATUS,
+            message: format!("Couldn't read reports: {}", e),
+        })?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(NetworkingError::payload_too_large(
+            bytes.len() as u64,
+            max_bytes,
+        ));
+    }
+
+    let body: ReportsBody = serde_json::from_slice(&bytes).map_err(|e| NetworkingError {
+        http_status: UNKNOWN_HTTP_STATUS,
+        message: format!("Invalid reports JSON: {}", e),
+    })?;
+    Ok(match body {
+        ReportsBody::Unpaged(reports) => ReportsPage {
+            reports,
+            has_more: false,
+        },
+        ReportsBody::Paged { reports, has_more } => ReportsPage {
+            has_more: has_more.unwrap_or(!reports.is_empty()),
+            reports,
+        },
+    })
+}
+
+// The app version is free form: drops what isn't allowed in a header value,
+// instead of failing the request.
+fn header_value(str: &str) -> String {
+    str.chars()
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct NetworkingError {
     pub http_status: u16,
     pub message: String,
 }
 
+impl NetworkingError {
+    // size: at least this many bytes (the body isn't read past the limit)
+    pub fn payload_too_large(size: u64, max_bytes: u64) -> NetworkingError {
+        NetworkingError {
+            http_status: PAYLOAD_TOO_LARGE_HTTP_STATUS,
+            message: format!("Payload too large: {} bytes, max: {}", size, max_bytes),
+        }
+    }
+
+    // Treated as payload too large: the interval can't be downloaded within the limits
+    pub fn too_many_pages(max_pages: u32) -> NetworkingError {
+        NetworkingError {
+            http_status: PAYLOAD_TOO_LARGE_HTTP_STATUS,
+            message: format!("Too many report pages, max: {}", max_pages),
+        }
+    }
+
+    pub fn is_payload_too_large(&self) -> bool {
+        self.http_status == PAYLOAD_TOO_LARGE_HTTP_STATUS
+    }
+
+    // The request was refused as such (4xx). Timeouts and rate limiting are transient, so they're not.
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.http_status) && self.http_status != 408 && self.http_status != 429
+    }
+}
+
 impl fmt::Display for NetworkingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -138,13 +396,14 @@ impl AsResult for Response {
 mod tests {
     use super::*;
     use crate::simple_logger;
+    use std::io::Cursor;
     //To run these tests use: 'cargo test -- --ignored'
     #[test]
     #[ignore] 
     fn get_reports_is_ok() {
         simple_logger::setup_logger(LevelFilter::Trace, false);
         warn!("Making actual GET request! Will fail if there is no internet connectivity.");
-        let api = TcnApiImpl {};
+        let api = TcnApiImpl::default();
         let res = api.get_reports(73673, 21600);
         assert!(res.is_ok());
     }
@@ -154,8 +413,142 @@ mod tests {
     fn post_report_is_ok() {
         simple_logger::setup_logger(LevelFilter::Trace, false);
         warn!("Making actual POST request! Will fail if there is no internet connectivity.");
-        let api = TcnApiImpl {};
-        let res = api.post_report("rSqWpM3ZQm7hfQ3q2x2llnFHiNhyRrUQPKEtJ33VKQcwT7Ly6e4KGaj5ZzjWt0m4c0v5n/VH5HO9UXbPXvsQTgEAQQAALFVtMVdNbHBZU1hOSlJYaDJZek5OWjJJeVdXZFpXRUozV2xoU2NHUkhWVDA9jn0pZAeME6ZBRHJOlfIikyfS0Pjg6l0txhhz6hz4exTxv8ryA3/Z26OebSRwzRfRgLdWBfohaOwOcSaynKqVCg==".to_owned());
+        let api = TcnApiImpl::default();
+        let res = api.post_report(PostReportRequest::new("rSqWpM3ZQm7hfQ3q2x2llnFHiNhyRrUQPKEtJ33VKQcwT7Ly6e4KGaj5ZzjWt0m4c0v5n/VH5HO9UXbPXvsQTgEAQQAALFVtMVdNbHBZU1hOSlJYaDJZek5OWjJJeVdXZFpXRUozV2xoU2NHUkhWVDA9jn0pZAeME6ZBRHJOlfIikyfS0Pjg6l0txhhz6hz4exTxv8ryA3/Z26OebSRwzRfRgLdWBfohaOwOcSaynKqVCg==".to_owned(), ClientVersion::default()));
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_read_reports_within_budget() {
+        let body = r#"["report1","report2"]"#;
+        let reports_res = read_reports(body.as_bytes(), Some(body.len() as u64), 1024);
+        assert!(reports_res.is_ok());
+        assert_eq!(
+            ReportsPage {
+                reports: vec!["report1".to_owned(), "report2".to_owned()],
+                has_more: false
+            },
+            reports_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_reports_page() {
+        let body = r#"{"reports":["report1"],"has_more":true}"#;
+        let page_res = read_reports(body.as_bytes(), None, 1024);
+        assert!(page_res.is_ok());
+        assert_eq!(
+            ReportsPage {
+                reports: vec!["report1".to_owned()],
+                has_more: true
+            },
+            page_res.unwrap()
+        );
+
+        // Without has_more: more until an empty page
+        let page_res = read_reports(r#"{"reports":["report1"]}"#.as_bytes(), None, 1024);
+        assert!(page_res.unwrap().has_more);
+        let page_res = read_reports(r#"{"reports":[]}"#.as_bytes(), None, 1024);
+        assert_eq!(ReportsPage::default(), page_res.unwrap());
+    }
+
+    fn test_pages() -> Vec<ReportsPage> {
+        vec![
+            ReportsPage {
+                reports: vec!["report1".to_owned(), "report2".to_owned()],
+                has_more: true,
+            },
+            ReportsPage {
+                reports: vec!["report3".to_owned()],
+                has_more: true,
+            },
+            ReportsPage {
+                reports: vec!["report4".to_owned()],
+                has_more: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_get_reports_pages_until_last() {
+        let pages = test_pages();
+        let mut requested_pages = vec![];
+        let reports_res = get_reports_pages(&PagingLimits::default(), |page, _| {
+            requested_pages.push(page);
+            Ok(pages[page as usize].clone())
+        });
+        assert!(reports_res.is_ok());
+        assert_eq!(
+            vec!["report1", "report2", "report3", "report4"],
+            reports_res.unwrap()
+        );
+        assert_eq!(vec![0, 1, 2], requested_pages);
+
+        // An empty page ends the interval, even with more
+        let mut requested_pages = vec![];
+        let reports_res = get_reports_pages(&PagingLimits::default(), |page, _| {
+            requested_pages.push(page);
+            Ok(match page {
+                0 => pages[0].clone(),
+                _ => ReportsPage {
+                    reports: vec![],
+                    has_more: true,
+                },
+            })
+        });
+        assert_eq!(vec!["report1", "report2"], reports_res.unwrap());
+        assert_eq!(vec![0, 1], requested_pages);
+    }
+
+    #[test]
+    fn test_get_reports_pages_fails_above_page_cap() {
+        let limits = PagingLimits {
+            max_pages: 2,
+            ..PagingLimits::default()
+        };
+        let mut requested_pages = vec![];
+        let reports_res = get_reports_pages(&limits, |page, _| {
+            requested_pages.push(page);
+            Ok(test_pages()[page as usize].clone())
+        });
+        assert!(reports_res.unwrap_err().is_payload_too_large());
+        assert_eq!(vec![0, 1], requested_pages);
+    }
+
+    #[test]
+    fn test_get_reports_pages_fails_above_byte_cap() {
+        let limits = PagingLimits {
+            max_pages: 10,
+            max_bytes: 20, // 3 reports of 7 bytes
+        };
+        let mut budgets = vec![];
+        let reports_res = get_reports_pages(&limits, |page, max_bytes| {
+            budgets.push(max_bytes);
+            Ok(test_pages()[page as usize].clone())
+        });
+        assert!(reports_res.unwrap_err().is_payload_too_large());
+        // The budget left is passed to each page request
+        assert_eq!(vec![20, 6], budgets);
+    }
+
+    #[test]
+    fn test_read_reports_rejects_oversized_body() {
+        let body = format!(r#"["{}"]"#, "a".repeat(2048));
+
+        // Declared length
+        let declared_res = read_reports(body.as_bytes(), Some(body.len() as u64), 1024);
+        assert!(declared_res.unwrap_err().is_payload_too_large());
+
+        // No declared length (e.g. chunked): stops reading past the budget
+        let mut stub = Cursor::new(body.as_bytes());
+        let undeclared_res = read_reports(&mut stub, None, 1024);
+        assert!(undeclared_res.unwrap_err().is_payload_too_large());
+        assert_eq!(1025, stub.position());
+    }
+
+    #[test]
+    fn test_header_value_drops_invalid_chars() {
+        assert_eq!("1.2.0 (45)", header_value("1.2.0 (45)"));
+        assert_eq!("1.2.0 (45)", header_value("1.2.0\n (45)é"));
+    }
 }
@@ -0,0 +1,358 @@
+// What the app stores locally and when it's deleted, for users who want to check it.
+// Built from the existing stats: counts, times and flags only. It must not contain TCNs, report or
+// alert ids, or key material (test_report_has_no_raw_identifiers).
+use crate::{
+    config::RiskConfig,
+    database::{
+        alert_dao::AlertDao, preferences::Preferences, tcn_dao::TcnDao,
+        update_run_dao::UpdateRunDao,
+    },
+    errors::ServicesError,
+    housekeeping::housekeeper::{Housekeeper, ScheduledRun},
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PrivacyReport {
+    pub observed_tcns: StoredTcns,
+    pub alerts: StoredAlerts,
+    pub keys: StoredKeys,
+    pub retention: Retention,
+    // Next runs of the housekeeping tasks. prune_tcns and purge_alerts delete the data older than
+    // the retention.
+    pub housekeeping: Vec<ScheduledRun>,
+}
+
+// TCNs observed from nearby devices
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredTcns {
+    pub count: u64, // Distinct TCNs
+    // Unix time. None if there are no TCNs.
+    pub oldest_contact_start: Option<u64>,
+    pub newest_contact_end: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredAlerts {
+    pub active: u32,
+    // Kept as deleted, so they aren't restored (see DbStats::deleted_alerts)
+    pub deleted: u64,
+}
+
+// Whether the keys were created. Never their values.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredKeys {
+    // Generates the TCNs this device broadcasts, and signs its reports
+    pub tcn_key: bool,
+    pub authorization_key: bool,
+    // Signs the alert reference tokens (see AlertReferences)
+    pub alert_token_key: bool,
+}
+
+// Days after the contact ended (see RiskConfig)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Retention {
+    pub observed_tcns_days: u64,
+    pub alerts_days: u64,
+    // Alerts of reports that the backend stopped serving
+    pub expired_report_alerts_days: u64,
+}
+
+// Entry point of get_privacy_report: unused in host builds, which have no FFI
+#[allow(dead_code)]
+pub fn privacy_report<P, T, D, Z>(
+    preferences: &P,
+    tcn_dao: &T,
+    alert_dao: &D,
+    housekeeper: &Housekeeper<P, Z>,
+    risk_config: &RiskConfig,
+) -> Result<PrivacyReport, ServicesError>
+where
+    P: Preferences,
+    T: TcnDao,
+    D: AlertDao,
+    Z: UpdateRunDao,
+{
+    let contact_time_range = tcn_dao.contact_time_range()?;
+
+    Ok(PrivacyReport {
+        observed_tcns: StoredTcns {
+            count: tcn_dao.stats()?.total,
+            oldest_contact_start: contact_time_range.as_ref().map(|(start, _)| start.value),
+            newest_contact_end: contact_time_range.as_ref().map(|(_, end)| end.value),
+        },
+        alerts: StoredAlerts {
+            active: alert_dao.alert_summary()?.total,
+            deleted: alert_dao.db_stats().deleted_alerts,
+        },
+        keys: StoredKeys {
+            tcn_key: preferences.tck()?.is_some(),
+            authorization_key: preferences.authorization_key()?.is_some(),
+            alert_token_key: preferences.alert_token_key().is_some(),
+        },
+        retention: Retention {
+            observed_tcns_days: risk_config.max_alert_age_days,
+            alerts_days: risk_config.max_alert_age_days,
+            expired_report_alerts_days: risk_config.expired_report_relevance_days,
+        },
+        housekeeping: housekeeper.next_runs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            alert_dao::AlertDaoImpl,
+            preferences::{PreferencesDao, PreferencesImpl, TckBytesWrapper},
+            tcn_dao::TcnDaoImpl,
+            update_run_dao::UpdateRunDaoImpl,
+        },
+        housekeeping::{
+            housekeeper::HousekeepingTask,
+            tasks::{PruneTcnsTask, PurgeAlertsTask},
+        },
+        reports_interval::UnixTime,
+        reports_update::reports_updater::{Alert, AlertDebugInfo},
+        test_support::{
+            fixtures::{observed_tcn, test_public_symptoms, TEST_RAK_BYTES},
+            migrated_database,
+        },
+    };
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    const NOW: u64 = 1591706000;
+    const ALERT_ID: &str = "5c7b2c6e-0d8a-4bb5-9a43-2f1e36c2a4f1";
+    const REPORT_ID: &str = "a4e1c9d07f3b42b6";
+
+    struct TestDependencies {
+        preferences: Arc<PreferencesImpl>,
+        tcn_dao: Arc<TcnDaoImpl>,
+        alert_dao: Arc<AlertDaoImpl>,
+        housekeeper: Housekeeper<PreferencesImpl, UpdateRunDaoImpl>,
+        risk_config: RiskConfig,
+    }
+
+    impl TestDependencies {
+        fn new() -> TestDependencies {
+            let database = migrated_database();
+            let preferences = Arc::new(PreferencesImpl {
+                dao: PreferencesDao::new(database.clone()),
+            });
+            let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+            let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+            let risk_config = RiskConfig::default();
+            let tasks: Vec<Box<dyn HousekeepingTask>> = vec![
+                Box::new(PruneTcnsTask {
+                    tcn_dao: tcn_dao.clone(),
+                    risk_config: risk_config.clone(),
+                }),
+                Box::new(PurgeAlertsTask {
+                    alert_dao: alert_dao.clone(),
+                    risk_config: risk_config.clone(),
+                }),
+            ];
+            let mut housekeeper = Housekeeper::new(
+                preferences.clone(),
+                Arc::new(UpdateRunDaoImpl::new(database)),
+                tasks,
+            );
+            housekeeper.clock = || UnixTime { value: NOW };
+
+            TestDependencies {
+                preferences,
+                tcn_dao,
+                alert_dao,
+                housekeeper,
+                risk_config,
+            }
+        }
+
+        fn report(&self) -> PrivacyReport {
+            let report_res = privacy_report(
+                self.preferences.as_ref(),
+                self.tcn_dao.as_ref(),
+                self.alert_dao.as_ref(),
+                &self.housekeeper,
+                &self.risk_config,
+            );
+            assert!(report_res.is_ok());
+            report_res.unwrap()
+        }
+
+        // TCNs, an active and a deleted alert, and keys
+        fn store_data(&self) {
+            let save_res = self.tcn_dao.overwrite(vec![
+                observed_tcn([1; 16], NOW - 3000, NOW - 2000),
+                observed_tcn([2; 16], NOW - 1000, NOW - 500),
+            ]);
+            assert!(save_res.is_ok());
+
+            let save_res = self.alert_dao.save(vec![
+                test_alert(ALERT_ID),
+                test_alert("1f9e5a0b-77c4-4d1e-8f2a-6b3c9d0e1a2b"),
+            ]);
+            assert!(save_res.is_ok());
+            let delete_res = self
+                .alert_dao
+                .delete("1f9e5a0b-77c4-4d1e-8f2a-6b3c9d0e1a2b".to_owned());
+            assert!(delete_res.is_ok());
+
+            let tck_res = self
+                .preferences
+                .set_tck(TckBytesWrapper { tck_bytes: [7; 66] });
+            assert!(tck_res.is_ok());
+            let key_res = self.preferences.set_autorization_key(TEST_RAK_BYTES);
+            assert!(key_res.is_ok());
+        }
+    }
+
+    fn test_alert(id: &str) -> Alert {
+        Alert {
+            id: id.to_owned(),
+            report_id: REPORT_ID.to_owned(),
+            symptoms: test_public_symptoms(),
+            contact_start: NOW - 3000,
+            contact_end: NOW - 2000,
+            min_distance: 2.3,
+            avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
+            is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
+        }
+    }
+
+    #[test]
+    fn test_report_of_empty_device() {
+        let dependencies = TestDependencies::new();
+
+        assert_eq!(
+            PrivacyReport {
+                observed_tcns: StoredTcns {
+                    count: 0,
+                    oldest_contact_start: None,
+                    newest_contact_end: None,
+                },
+                alerts: StoredAlerts {
+                    active: 0,
+                    deleted: 0,
+                },
+                keys: StoredKeys {
+                    tcn_key: false,
+                    authorization_key: false,
+                    alert_token_key: false,
+                },
+                retention: Retention {
+                    observed_tcns_days: 14,
+                    alerts_days: 14,
+                    expired_report_alerts_days: 7,
+                },
+                housekeeping: vec![
+                    ScheduledRun {
+                        task: "prune_tcns".to_owned(),
+                        due: NOW,
+                    },
+                    ScheduledRun {
+                        task: "purge_alerts".to_owned(),
+                        due: NOW,
+                    },
+                ],
+            },
+            dependencies.report()
+        );
+    }
+
+    #[test]
+    fn test_report_of_stored_data() {
+        let dependencies = TestDependencies::new();
+        dependencies.store_data();
+        dependencies.housekeeper.run_due(None);
+
+        let report = dependencies.report();
+        assert_eq!(
+            StoredTcns {
+                count: 2,
+                oldest_contact_start: Some(NOW - 3000),
+                newest_contact_end: Some(NOW - 500),
+            },
+            report.observed_tcns
+        );
+        assert_eq!(
+            StoredAlerts {
+                active: 1,
+                deleted: 1,
+            },
+            report.alerts
+        );
+        assert_eq!(
+            StoredKeys {
+                tcn_key: true,
+                authorization_key: true,
+                alert_token_key: false,
+            },
+            report.keys
+        );
+        // Next prune: a day after this one
+        assert_eq!(
+            vec![NOW + 24 * 60 * 60, NOW + 24 * 60 * 60],
+            report
+                .housekeeping
+                .iter()
+                .map(|run| run.due)
+                .collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_report_has_no_raw_identifiers() {
+        let dependencies = TestDependencies::new();
+        dependencies.store_data();
+
+        let denylist = [
+            hex::encode([1; 16]),
+            hex::encode([2; 16]),
+            ALERT_ID.to_owned(),
+            REPORT_ID.to_owned(),
+            hex::encode(TEST_RAK_BYTES),
+            base64::encode(TEST_RAK_BYTES),
+            hex::encode([7; 66].as_ref()),
+        ];
+
+        let json = serde_json::to_value(&dependencies.report()).unwrap();
+        let json_str = json.to_string();
+        for denied in denylist.iter() {
+            assert!(
+                !json_str.contains(denied.as_str()),
+                "Report: {} contains: {}",
+                json_str,
+                denied
+            );
+        }
+
+        // Counts, times and flags only. The only strings are the housekeeping task names.
+        let mut strings = vec![];
+        collect_strings(&json, &mut strings);
+        assert_eq!(vec!["prune_tcns", "purge_alerts"], strings);
+    }
+
+    fn collect_strings(value: &Value, strings: &mut Vec<String>) {
+        match value {
+            Value::String(str) => strings.push(str.clone()),
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| collect_strings(value, strings)),
+            Value::Object(map) => map
+                .values()
+                .for_each(|value| collect_strings(value, strings)),
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+    }
+}
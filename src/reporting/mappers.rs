@@ -6,6 +6,7 @@ use super::{
 use crate::{expect_log, reports_interval::UnixTime};
 #[cfg(target_os = "android")]
 use log::error;
+use std::convert::TryFrom;
 
 pub trait BitMapper<T> {
     fn bit_count(&self) -> usize;
@@ -118,12 +119,12 @@ impl BitMapper<CoughSeverity> for CoughSeverityMapper {
     }
 
     fn to_bits_unchecked(&self, value: CoughSeverity) -> BitVector {
-        value.raw_value().to_bits().as_unibble_bit_vector()
+        value.as_u8().to_bits().as_unibble_bit_vector()
     }
 
     fn from_bits_unchecked(&self, bit_vector: BitVector) -> CoughSeverity {
         let value = bit_vector.as_u8();
-        let res = CoughSeverity::from(value);
+        let res = CoughSeverity::try_from(value);
         expect_log!(res, "Not supported raw value")
     }
 }
@@ -135,12 +136,12 @@ impl BitMapper<FeverSeverity> for FeverSeverityMapper {
     }
 
     fn to_bits_unchecked(&self, value: FeverSeverity) -> BitVector {
-        value.raw_value().to_bits().as_unibble_bit_vector()
+        value.as_u8().to_bits().as_unibble_bit_vector()
     }
 
     fn from_bits_unchecked(&self, bit_vector: BitVector) -> FeverSeverity {
         let value = bit_vector.as_u8();
-        let res = FeverSeverity::from(value);
+        let res = FeverSeverity::try_from(value);
         expect_log!(res, "Not supported raw value")
     }
 }
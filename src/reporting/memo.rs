@@ -4,12 +4,27 @@ use super::{
         BitMapper, BitVectorMappable, BoolMapper, CoughSeverityMapper, FeverSeverityMapper,
         TimeMapper, TimeUserInputMapper, VersionMapper,
     },
-    public_symptoms::PublicSymptoms,
+    public_symptoms::{PublicSymptoms, ReportKind},
 };
-use crate::expect_log;
+use crate::{errors::ServicesError, expect_log, ffi_params::invalid_param};
 #[cfg(target_os = "android")]
 use log::error;
-use std::convert::TryInto;
+use std::{convert::TryInto, panic};
+
+// Version written in the memos created by this app.
+pub const CURRENT_MEMO_VERSION: u16 = 1;
+// Reserved for the test result memos of the protocol roadmap, so that the symptom memo versions can
+// keep increasing below it. Not created yet: decoded with the symptoms layout.
+pub const TEST_RESULT_MEMO_VERSION: u16 = 256;
+
+// Later versions are Unknown rather than an error: the memo is decoded with the layout of this core.
+pub fn report_kind(memo_version: u16) -> ReportKind {
+    match memo_version {
+        1..=CURRENT_MEMO_VERSION => ReportKind::Symptoms,
+        TEST_RESULT_MEMO_VERSION => ReportKind::TestResult,
+        _ => ReportKind::Unknown,
+    }
+}
 
 pub struct Memo {
     pub bytes: Vec<u8>,
@@ -28,14 +43,52 @@ impl MemoMapperImpl {
     const COUGH_SEVERITY_MAPPER: CoughSeverityMapper = CoughSeverityMapper {};
     const FEVER_SEVERITY_MAPPER: FeverSeverityMapper = FeverSeverityMapper {};
     const BOOLEAN_MAPPER: BoolMapper = BoolMapper {};
+
+    // Bytes of the memos written by to_memo. Later versions can be longer.
+    fn memo_byte_count() -> usize {
+        let bit_count = Self::VERSION_MAPPER.bit_count()
+            + Self::TIME_MAPPER.bit_count()
+            + Self::TIME_USER_INPUT_MAPPER.bit_count()
+            + Self::COUGH_SEVERITY_MAPPER.bit_count()
+            + Self::FEVER_SEVERITY_MAPPER.bit_count()
+            + 7 * Self::BOOLEAN_MAPPER.bit_count();
+        (bit_count + 7) / 8
+    }
+}
+
+// Memo utility for clients without the core's memo layout (e.g. the web reporting prototype), see the
+// encode_memo / decode_memo FFI functions. No db access.
+
+// report_json: PublicSymptoms JSON (like the symptoms of the alerts). Returns the memo (hex). The memo
+// is written with CURRENT_MEMO_VERSION: memo_version and report_kind of the JSON are ignored.
+pub fn encode_memo_json(report_json: &str) -> Result<String, ServicesError> {
+    let report: PublicSymptoms = serde_json::from_str(report_json)
+        .map_err(|e| invalid_param("report_json", &format!("is not a valid report: {}", e)))?;
+    Ok(hex::encode(MemoMapperImpl {}.to_memo(report).bytes))
+}
+
+// memo_hex: memo bytes (hex), e.g. the memo data of a report. Err if it's shorter than the memos of
+// this core or has values this core doesn't support.
+pub fn decode_memo_hex(memo_hex: &str) -> Result<PublicSymptoms, ServicesError> {
+    let bytes = hex::decode(memo_hex)
+        .map_err(|e| invalid_param("memo_hex", &format!("is not valid hex: {}", e)))?;
+    let byte_count = MemoMapperImpl::memo_byte_count();
+    if bytes.len() < byte_count {
+        return Err(invalid_param(
+            "memo_hex",
+            &format!("has {} bytes, required: {}", bytes.len(), byte_count),
+        ));
+    }
+    // The mappers panic with unsupported values (e.g. severities). The memo is plain data: there's no
+    // state that could be left inconsistent by the panic.
+    panic::catch_unwind(move || MemoMapperImpl {}.to_report(Memo { bytes }))
+        .map_err(|_| invalid_param("memo_hex", "has unsupported values"))
 }
 
 impl MemoMapper for MemoMapperImpl {
     fn to_memo(&self, report: PublicSymptoms) -> Memo {
-        let memo_version: u16 = 1;
-
         let bits = vec![
-            Self::VERSION_MAPPER.to_bits(memo_version),
+            Self::VERSION_MAPPER.to_bits(CURRENT_MEMO_VERSION),
             Self::TIME_MAPPER.to_bits(report.report_time),
             Self::TIME_USER_INPUT_MAPPER.to_bits(report.earliest_symptom_time),
             Self::COUGH_SEVERITY_MAPPER.to_bits(report.cough_severity),
@@ -66,8 +119,8 @@ impl MemoMapper for MemoMapperImpl {
 
         let mut next: usize = 0;
 
-        // Version for now not handled
-        let _ = extract(&bits, &Self::VERSION_MAPPER, next).value(|v| next += v);
+        // Version isn't used for parsing yet (the layout didn't change), but is passed to the apps
+        let memo_version = extract(&bits, &Self::VERSION_MAPPER, next).value(|v| next += v);
 
         let report_time = extract(&bits, &Self::TIME_MAPPER, next).value(|v| next += v);
 
@@ -97,6 +150,8 @@ impl MemoMapper for MemoMapperImpl {
             runny_nose,
             other,
             no_symptoms,
+            memo_version,
+            report_kind: report_kind(memo_version),
         }
     }
 }
@@ -131,6 +186,7 @@ mod tests {
     use crate::reporting::public_symptoms::{CoughSeverity, FeverSeverity};
     use crate::reporting::symptom_inputs::UserInput;
     use crate::reports_interval::UnixTime;
+    use crate::test_support::fixtures::VERIFICATION_REPORT_MEMO;
 
     #[test]
     fn maps_nothing_set() {
@@ -148,6 +204,8 @@ mod tests {
             runny_nose: false,
             other: false,
             no_symptoms: false,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let memo: Memo = memo_mapper.to_memo(report.clone());
@@ -172,6 +230,8 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
         let memo: Memo = memo_mapper.to_memo(report.clone());
@@ -179,4 +239,187 @@ mod tests {
 
         assert_eq!(mapped_report, report.clone());
     }
+
+    #[test]
+    fn maps_only_no_symptoms_set() {
+        let memo_mapper = MemoMapperImpl {};
+
+        let report = PublicSymptoms {
+            report_time: UnixTime { value: 1589209754 },
+            earliest_symptom_time: UserInput::None,
+            fever_severity: FeverSeverity::None,
+            cough_severity: CoughSeverity::None,
+            breathlessness: false,
+            muscle_aches: false,
+            loss_smell_or_taste: false,
+            diarrhea: false,
+            runny_nose: false,
+            other: false,
+            no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        };
+        assert!(report.is_retraction());
+
+        let memo: Memo = memo_mapper.to_memo(report.clone());
+        let mapped_report: PublicSymptoms = memo_mapper.to_report(memo);
+
+        assert_eq!(mapped_report, report);
+        assert!(mapped_report.is_retraction());
+    }
+
+    #[test]
+    fn maps_v1_memo_version() {
+        let memo_mapper = MemoMapperImpl {};
+
+        let memo: Memo = memo_mapper.to_memo(test_report());
+        let mapped_report: PublicSymptoms = memo_mapper.to_report(memo);
+
+        assert_eq!(mapped_report.memo_version, 1);
+        assert_eq!(mapped_report.report_kind, ReportKind::Symptoms);
+    }
+
+    // Forward compatibility: a later version is decoded with this layout, of unknown kind
+    #[test]
+    fn maps_v2_memo_version() {
+        let memo_mapper = MemoMapperImpl {};
+
+        let mut memo: Memo = memo_mapper.to_memo(test_report());
+        // Overwrite the version (first 2 bytes)
+        let version_bytes = MemoMapperImpl::VERSION_MAPPER.to_bits(2).as_u8_array();
+        memo.bytes[..2].copy_from_slice(&version_bytes);
+
+        let mapped_report: PublicSymptoms = memo_mapper.to_report(memo);
+
+        assert_eq!(mapped_report.memo_version, 2);
+        assert_eq!(
+            mapped_report,
+            PublicSymptoms {
+                memo_version: 2,
+                report_kind: ReportKind::Unknown,
+                ..test_report()
+            }
+        );
+    }
+
+    #[test]
+    fn maps_test_result_memo_version() {
+        let memo_mapper = MemoMapperImpl {};
+
+        let mut memo: Memo = memo_mapper.to_memo(test_report());
+        let version_bytes = MemoMapperImpl::VERSION_MAPPER
+            .to_bits(TEST_RESULT_MEMO_VERSION)
+            .as_u8_array();
+        memo.bytes[..2].copy_from_slice(&version_bytes);
+
+        let mapped_report: PublicSymptoms = memo_mapper.to_report(memo);
+
+        assert_eq!(mapped_report.memo_version, TEST_RESULT_MEMO_VERSION);
+        assert_eq!(mapped_report.report_kind, ReportKind::TestResult);
+    }
+
+    #[test]
+    fn report_kind_of_memo_versions() {
+        assert_eq!(ReportKind::Symptoms, report_kind(CURRENT_MEMO_VERSION));
+        assert_eq!(
+            ReportKind::TestResult,
+            report_kind(TEST_RESULT_MEMO_VERSION)
+        );
+        for version in &[0, 2, TEST_RESULT_MEMO_VERSION + 1, u16::MAX] {
+            assert_eq!(ReportKind::Unknown, report_kind(*version));
+        }
+    }
+
+    #[test]
+    fn memo_json_round_trip() {
+        let report_json = serde_json::to_string(&test_report()).unwrap();
+
+        let memo_hex_res = encode_memo_json(&report_json);
+        let memo_hex = expect_log!(memo_hex_res, "Couldn't encode memo");
+        assert_eq!(
+            hex::encode(MemoMapperImpl {}.to_memo(test_report()).bytes),
+            memo_hex
+        );
+
+        let report_res = decode_memo_hex(&memo_hex);
+        assert_eq!(
+            test_report(),
+            expect_log!(report_res, "Couldn't decode memo")
+        );
+    }
+
+    #[test]
+    fn memo_json_matches_verification_memo() {
+        let verification_symptoms = PublicSymptoms {
+            report_time: UnixTime { value: 1589209754 },
+            earliest_symptom_time: UserInput::Some(UnixTime { value: 1589209754 }),
+            fever_severity: FeverSeverity::Serious,
+            cough_severity: CoughSeverity::Existing,
+            breathlessness: true,
+            muscle_aches: false,
+            loss_smell_or_taste: false,
+            diarrhea: false,
+            runny_nose: false,
+            other: false,
+            no_symptoms: false,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        };
+
+        let report_res = decode_memo_hex(VERIFICATION_REPORT_MEMO);
+        assert_eq!(
+            verification_symptoms,
+            expect_log!(report_res, "Couldn't decode memo")
+        );
+
+        let report_json = serde_json::to_string(&verification_symptoms).unwrap();
+        let memo_hex_res = encode_memo_json(&report_json);
+        assert_eq!(
+            VERIFICATION_REPORT_MEMO,
+            expect_log!(memo_hex_res, "Couldn't encode memo")
+        );
+    }
+
+    #[test]
+    fn memo_json_with_invalid_input_fails() {
+        // Unsupported cough severity (15)
+        let mut unsupported_memo = hex::decode(VERIFICATION_REPORT_MEMO).unwrap();
+        unsupported_memo[18] |= 0x0f;
+        let decode_inputs = vec![
+            "not hex".to_owned(),
+            VERIFICATION_REPORT_MEMO[..38].to_owned(),
+            hex::encode(unsupported_memo),
+        ];
+        for memo_hex in decode_inputs {
+            match decode_memo_hex(&memo_hex) {
+                Err(ServicesError::FFIParameters(_)) => {}
+                other => panic!("Expected parameters error, got: {:?}", other),
+            }
+        }
+
+        for report_json in &["", "{}", r#"{"report_time":{"value":1}}"#] {
+            match encode_memo_json(report_json) {
+                Err(ServicesError::FFIParameters(_)) => {}
+                other => panic!("Expected parameters error, got: {:?}", other),
+            }
+        }
+    }
+
+    fn test_report() -> PublicSymptoms {
+        PublicSymptoms {
+            report_time: UnixTime { value: 1589209754 },
+            earliest_symptom_time: UserInput::Some(UnixTime { value: 1589209754 }),
+            fever_severity: FeverSeverity::Mild,
+            cough_severity: CoughSeverity::Wet,
+            breathlessness: false,
+            muscle_aches: true,
+            loss_smell_or_taste: true,
+            diarrhea: false,
+            runny_nose: false,
+            other: true,
+            no_symptoms: false,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        }
+    }
 }
@@ -1,38 +1,47 @@
-use super::symptom_inputs::{Cough, CoughType, Fever, SymptomId, SymptomInputs, UserInput};
+use super::{
+    memo::CURRENT_MEMO_VERSION,
+    symptom_inputs::{Cough, CoughType, Fever, SymptomId, SymptomInputs, UserInput},
+};
 use crate::{errors::ServicesError, reports_interval::UnixTime};
 use log::info;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
-#[derive(Debug, PartialEq, Clone, Serialize, Eq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Eq)]
 pub enum FeverSeverity {
     None,
     Mild,
     Serious,
 }
 
+// Canonical numbering, shared by the memos, the database and the FFI. Part of the memo format: don't change it.
 impl FeverSeverity {
-    pub fn raw_value(&self) -> u8 {
+    pub fn as_u8(&self) -> u8 {
         match self {
             FeverSeverity::None => 0,
             FeverSeverity::Mild => 1,
             FeverSeverity::Serious => 2,
         }
     }
+}
+
+impl TryFrom<u8> for FeverSeverity {
+    type Error = ServicesError;
 
-    pub fn from(raw_value: u8) -> Result<FeverSeverity, ServicesError> {
-        match raw_value {
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
             0 => Ok(FeverSeverity::None),
             1 => Ok(FeverSeverity::Mild),
             2 => Ok(FeverSeverity::Serious),
             _ => Err(ServicesError::General(format!(
-                "Not supported: {}",
-                raw_value
+                "Not supported fever severity: {}",
+                value
             ))),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Eq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Eq)]
 pub enum CoughSeverity {
     None,
     Existing,
@@ -40,8 +49,9 @@ pub enum CoughSeverity {
     Dry,
 }
 
+// Canonical numbering, like FeverSeverity. Note that it doesn't follow the declaration order (Dry is 2).
 impl CoughSeverity {
-    pub fn raw_value(&self) -> u8 {
+    pub fn as_u8(&self) -> u8 {
         match self {
             CoughSeverity::None => 0,
             CoughSeverity::Existing => 1,
@@ -49,22 +59,70 @@ impl CoughSeverity {
             CoughSeverity::Wet => 3,
         }
     }
+}
 
-    pub fn from(raw_value: u8) -> Result<CoughSeverity, ServicesError> {
-        match raw_value {
+impl TryFrom<u8> for CoughSeverity {
+    type Error = ServicesError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
             0 => Ok(CoughSeverity::None),
             1 => Ok(CoughSeverity::Existing),
             2 => Ok(CoughSeverity::Dry),
             3 => Ok(CoughSeverity::Wet),
             _ => Err(ServicesError::General(format!(
-                "Not supported: {}",
-                raw_value
+                "Not supported cough severity: {}",
+                value
             ))),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Serialize, Eq)]
+// What a report is based on, derived from its memo version (see memo::report_kind)
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize, Eq)]
+pub enum ReportKind {
+    Symptoms,
+    // Lab-confirmed test result. Reserved: the apps don't create these reports yet.
+    TestResult,
+    // Memo version not known to this core, e.g. a report type added after it
+    Unknown,
+}
+
+// Reports stored before the kind was added were all symptom reports
+impl Default for ReportKind {
+    fn default() -> Self {
+        ReportKind::Symptoms
+    }
+}
+
+// Canonical numbering, shared by the database and the FFI, like FeverSeverity
+impl ReportKind {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            ReportKind::Symptoms => 0,
+            ReportKind::TestResult => 1,
+            ReportKind::Unknown => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for ReportKind {
+    type Error = ServicesError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ReportKind::Symptoms),
+            1 => Ok(ReportKind::TestResult),
+            2 => Ok(ReportKind::Unknown),
+            _ => Err(ServicesError::General(format!(
+                "Not supported report kind: {}",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Eq)]
 pub struct PublicSymptoms {
     pub report_time: UnixTime,
     pub earliest_symptom_time: UserInput<UnixTime>,
@@ -77,6 +135,9 @@ pub struct PublicSymptoms {
     pub runny_nose: bool,
     pub other: bool,
     pub no_symptoms: bool, // https://github.com/Co-Epi/app-ios/issues/268#issuecomment-645583717
+    pub memo_version: u16, // Version of the memo the report was decoded from
+    #[serde(default)]
+    pub report_kind: ReportKind,
 }
 
 impl PublicSymptoms {
@@ -115,6 +176,8 @@ impl PublicSymptoms {
                 runny_nose,
                 other,
                 no_symptoms,
+                memo_version: CURRENT_MEMO_VERSION,
+                report_kind: ReportKind::Symptoms,
             })
         } else {
             info!(
@@ -124,6 +187,20 @@ impl PublicSymptoms {
             None
         }
     }
+
+    // Only "no symptoms" selected: the reporter retracts the symptoms of their earlier reports.
+    // Sent like other reports, but doesn't create alerts (see ReportsUpdater::retract_superseded_reports).
+    pub fn is_retraction(&self) -> bool {
+        self.no_symptoms
+            && self.fever_severity == FeverSeverity::None
+            && self.cough_severity == CoughSeverity::None
+            && !self.breathlessness
+            && !self.muscle_aches
+            && !self.loss_smell_or_taste
+            && !self.diarrhea
+            && !self.runny_nose
+            && !self.other
+    }
 }
 
 fn to_fever_severity(fever: &Fever) -> FeverSeverity {
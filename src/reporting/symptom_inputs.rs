@@ -1,14 +1,19 @@
 use super::{memo::MemoMapper, public_symptoms::*};
 use crate::{
-    errors::ServicesError, expect_log, networking::TcnApi, reports_interval::UnixTime,
-    tcn_ext::tcn_keys::TcnKeys,
+    config::ClientVersion,
+    database::preferences::{PendingReportSubmission, Preferences},
+    errors::ServicesError,
+    expect_log,
+    networking::{NetworkingError, PostReportRequest, TcnApi},
+    reports_interval::UnixTime,
+    tcn_ext::tcn_keys::{TcnKeys, TCN_ROTATION_PERIOD_SECS},
 };
 use log::*;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, io::Cursor, sync::Arc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashSet, io::Cursor, sync::Arc, time::Duration};
 use tcn::SignedReport;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SymptomInputs {
     pub ids: HashSet<SymptomId>,
     pub cough: Cough,
@@ -116,7 +121,8 @@ pub enum SymptomId {
     None,
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize, Serialize, Eq)]
+// Serialized as a nullable value (JSON null if not set)
+#[derive(Debug, PartialEq, Clone, Eq)]
 pub enum UserInput<T>
 where
     T: Serialize,
@@ -151,50 +157,234 @@ where
     }
 }
 
+impl<T> Serialize for UserInput<T>
+where
+    T: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            UserInput::Some(input) => serializer.serialize_some(input),
+            UserInput::None => serializer.serialize_none(),
+        }
+    }
+}
+
+impl<'de, T> Deserialize<'de> for UserInput<T>
+where
+    T: Serialize + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Option::<T>::deserialize(deserializer).map(UserInput::from)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct EarliestSymptom {
     pub time: UserInput<UnixTime>,
 }
 
+// Including the first attempt
+const POST_REPORT_MAX_ATTEMPTS: usize = 3;
+// Before the first retry. Doubled for each further retry, up to the max.
+const POST_REPORT_RETRY_DELAY: Duration = Duration::from_millis(500);
+const POST_REPORT_MAX_RETRY_DELAY: Duration = Duration::from_secs(4);
+// A pending submission older than this isn't posted again: its report doesn't cover the TCNs sent since
+// it was signed, and the report time would be stale.
+const PENDING_REPORT_SUBMISSION_MAX_AGE_SECS: u64 = TCN_ROTATION_PERIOD_SECS;
+
+// What a symptoms submission did. Errors are failures to send (e.g. connection or server errors):
+// the inputs are kept, so the submission can be retried.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum SubmitOutcome {
+    // receipt: the idempotency key the report was posted with
+    Sent { receipt: String },
+    // The inputs have nothing to report (see PublicSymptoms::with_inputs)
+    NothingToSend,
+    // The backend refused the report (client error): sending it again won't help
+    Rejected { http_status: u16, reason: String },
+}
+
+impl SubmitOutcome {
+    // For the callers that only distinguish success and failure: a rejection is the networking error
+    // it was before there were outcomes.
+    pub fn into_void_result(self) -> Result<(), ServicesError> {
+        match self {
+            SubmitOutcome::Sent { .. } | SubmitOutcome::NothingToSend => Ok(()),
+            SubmitOutcome::Rejected {
+                http_status,
+                reason,
+            } => Err(ServicesError::Networking(NetworkingError {
+                http_status,
+                message: reason,
+            })),
+        }
+    }
+}
+
 pub trait SymptomInputsSubmitter<T: MemoMapper, U: TcnKeys, V: TcnApi> {
-    fn submit_inputs(&self, inputs: SymptomInputs) -> Result<(), ServicesError>;
+    fn submit_inputs(&self, inputs: SymptomInputs) -> Result<SubmitOutcome, ServicesError>;
+    // Sends the last submitted report again, only with a different earliest symptom time
+    // (the backend supersedes the previous report). Err(NotFound) if nothing was submitted yet.
+    fn resubmit_with_earliest_symptom_time(
+        &self,
+        time: UserInput<UnixTime>,
+    ) -> Result<(), ServicesError>;
 }
 
-pub struct SymptomInputsSubmitterImpl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi> {
+pub struct SymptomInputsSubmitterImpl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi, W: Preferences> {
     pub memo_mapper: &'a T,
     pub tcn_keys: Arc<U>,
     pub api: &'a V,
+    pub client_version: ClientVersion,
+    // Stores the last submitted report
+    pub preferences: Arc<W>,
+    // Time of the submitted reports
+    pub clock: fn() -> UnixTime,
+    // Waits between the post attempts (see post_report_retry_delay)
+    pub sleep: fn(Duration),
 }
 
-impl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi> SymptomInputsSubmitter<T, U, V>
-    for SymptomInputsSubmitterImpl<'a, T, U, V>
+impl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi, W: Preferences> SymptomInputsSubmitter<T, U, V>
+    for SymptomInputsSubmitterImpl<'a, T, U, V, W>
 {
-    fn submit_inputs(&self, inputs: SymptomInputs) -> Result<(), ServicesError> {
-        if let Some(report) = PublicSymptoms::with_inputs(inputs, UnixTime::now()) {
-            self.send_report(report)
-        } else {
-            debug!("Nothing to send.");
-            Ok(())
+    fn submit_inputs(&self, inputs: SymptomInputs) -> Result<SubmitOutcome, ServicesError> {
+        let report = match PublicSymptoms::with_inputs(inputs, (self.clock)()) {
+            Some(report) => report,
+            None => {
+                debug!("Nothing to send.");
+                return Ok(SubmitOutcome::NothingToSend);
+            }
+        };
+        match self.send_report(report) {
+            Ok(receipt) => Ok(SubmitOutcome::Sent { receipt }),
+            Err(ServicesError::Networking(error)) if error.is_client_error() => {
+                warn!("Report rejected: {:?}", error);
+                Ok(SubmitOutcome::Rejected {
+                    http_status: error.http_status,
+                    reason: error.message,
+                })
+            }
+            Err(error) => Err(error),
         }
     }
+
+    fn resubmit_with_earliest_symptom_time(
+        &self,
+        time: UserInput<UnixTime>,
+    ) -> Result<(), ServicesError> {
+        let report = self
+            .preferences
+            .last_submitted_report()
+            .ok_or(ServicesError::NotFound)?;
+        self.send_report(PublicSymptoms {
+            earliest_symptom_time: time,
+            ..report
+        })
+        .map(|_| ())
+    }
 }
 
-impl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi> SymptomInputsSubmitterImpl<'a, T, U, V> {
-    fn send_report(&self, report: PublicSymptoms) -> Result<(), ServicesError> {
+impl<'a, T: MemoMapper, U: TcnKeys, V: TcnApi, W: Preferences>
+    SymptomInputsSubmitterImpl<'a, T, U, V, W>
+{
+    // Returns the idempotency key the report was posted with
+    fn send_report(&self, report: PublicSymptoms) -> Result<String, ServicesError> {
         debug!("Will send public report: {:?}", report);
 
-        let memo = self.memo_mapper.to_memo(report);
+        // The same symptoms as a recent post without response (e.g. a timeout) are the same submission:
+        // posted again as they were, with the same idempotency key. Otherwise the pending one is replaced.
+        let now = (self.clock)();
+        let pending = self
+            .preferences
+            .pending_report_submission()
+            .filter(|pending| is_pending_submission_fresh(pending, &now))
+            .filter(|pending| is_same_report(&pending.symptoms, &report));
+        let (report, request) = match pending {
+            Some(pending) => {
+                info!("Posting pending report again");
+                let request = PostReportRequest {
+                    report: pending.report,
+                    idempotency_key: pending.idempotency_key,
+                    client_version: self.client_version.clone(),
+                };
+                (pending.symptoms, request)
+            }
+            None => {
+                let memo = self.memo_mapper.to_memo(report.clone());
+                debug!("Mapped public report to memo: {:?}", memo.bytes);
+
+                let signed_report = self.tcn_keys.create_report(memo.bytes)?;
+                let report_str = base64::encode(signed_report_to_bytes(signed_report));
+                let request = PostReportRequest::new(report_str, self.client_version.clone());
+                self.preferences
+                    .set_pending_report_submission(Some(PendingReportSubmission {
+                        symptoms: report.clone(),
+                        report: request.report.clone(),
+                        idempotency_key: request.idempotency_key.clone(),
+                        created_at: now.value,
+                    }));
+                (report, request)
+            }
+        };
+        let idempotency_key = request.idempotency_key.clone();
+
+        let post_res = self.post_report_with_retries(request);
+        // Kept until the backend responds: a rejected report is as settled as an accepted one
+        let settled = match &post_res {
+            Ok(_) => true,
+            Err(ServicesError::Networking(error)) => error.is_client_error(),
+            Err(_) => false,
+        };
+        if settled {
+            self.preferences.set_pending_report_submission(None);
+        }
+        post_res?;
+
+        self.preferences.set_last_submitted_report(report);
+        Ok(idempotency_key)
+    }
 
-        debug!("Mapped public report to memo: {:?}", memo.bytes);
+    // Retries server / connection errors. All attempts send the same idempotency key,
+    // so a report accepted by the backend in a (seemingly) failed attempt isn't stored twice.
+    fn post_report_with_retries(&self, request: PostReportRequest) -> Result<(), ServicesError> {
+        let mut attempt = 1;
+        loop {
+            match self.api.post_report(request.clone()) {
+                Ok(_) => return Ok(()),
+                Err(error) if error.http_status >= 500 && attempt < POST_REPORT_MAX_ATTEMPTS => {
+                    warn!("Posting report failed (attempt {}): {:?}", attempt, error);
+                    (self.sleep)(post_report_retry_delay(attempt));
+                    attempt += 1;
+                }
+                Err(error) => return Err(ServicesError::from(error)),
+            }
+        }
+    }
+}
 
-        let signed_report = self.tcn_keys.create_report(memo.bytes)?;
+// Exponential backoff. attempt: the failed attempt, starting at 1.
+fn post_report_retry_delay(attempt: usize) -> Duration {
+    let factor = 1u32.checked_shl(attempt as u32 - 1).unwrap_or(u32::MAX);
+    POST_REPORT_RETRY_DELAY
+        .checked_mul(factor)
+        .map_or(POST_REPORT_MAX_RETRY_DELAY, |delay| {
+            delay.min(POST_REPORT_MAX_RETRY_DELAY)
+        })
+}
 
-        let report_str = base64::encode(signed_report_to_bytes(signed_report));
+fn is_pending_submission_fresh(pending: &PendingReportSubmission, now: &UnixTime) -> bool {
+    pending.created_at <= now.value
+        && now.value - pending.created_at < PENDING_REPORT_SUBMISSION_MAX_AGE_SECS
+}
 
-        self.api
-            .post_report(report_str)
-            .map_err(ServicesError::from)
-    }
+// Whether the reports have the same symptoms, regardless of when they were created
+fn is_same_report(report: &PublicSymptoms, other: &PublicSymptoms) -> bool {
+    *report
+        == PublicSymptoms {
+            report_time: report.report_time.clone(),
+            ..other.clone()
+        }
 }
 
 fn signed_report_to_bytes(signed_report: SignedReport) -> Vec<u8> {
@@ -213,9 +403,20 @@ mod tests {
     use crate::reporting::memo::MemoMapperImpl;
     use crate::simple_logger;
     use crate::{
-        database::preferences::PreferencesTckMock,
+        database::preferences::{
+            PreferencesDao, PreferencesImpl, PreferencesNoopMock, PreferencesTckMock,
+        },
         networking::TcnApiMock,
+        reporting::memo::Memo,
         tcn_ext::tcn_keys::{ReportAuthorizationKeyExt, TcnKeysImpl},
+        test_support::{
+            fixtures::TEST_RAK_BYTES, migrated_database, ApiCallArgs, ApiScriptStep, ScriptedTcnApi,
+        },
+    };
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        thread::{self, ThreadId},
     };
     use tcn::{ReportAuthorizationKey, TemporaryContactKey};
 
@@ -296,26 +497,27 @@ mod tests {
             runny_nose: true,
             other: false,
             no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
         };
 
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
         let tck = generate_tck_for_index(rak_bytes, 60);
         debug!(">> tck: {:?}", tck);
         let tck_bytes = TcnKeysImpl::<PreferencesTckMock>::tck_to_bytes(tck);
 
         let preferences = Arc::new(PreferencesTckMock { tck_bytes });
 
-        let tcn_keys = Arc::new(TcnKeysImpl {
-            preferences: preferences.clone(),
-        });
+        let tcn_keys = Arc::new(TcnKeysImpl::new(preferences.clone()));
 
         let submitter = SymptomInputsSubmitterImpl {
             memo_mapper: &MemoMapperImpl {},
             tcn_keys,
             api: &TcnApiMock {},
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
         };
 
         let memo = submitter.memo_mapper.to_memo(report_which_should_be_sent);
@@ -337,13 +539,579 @@ mod tests {
 
         submitter
             .api
-            .post_report(report_str)
+            .post_report(PostReportRequest::new(report_str, ClientVersion::default()))
             .map_err(ServicesError::from)
             .expect("Networking Error");
 
         assert!(true)
     }
 
+    #[test]
+    fn test_post_report_retries_reuse_idempotency_key() {
+        let api = ScriptedTcnApi::default();
+        api.script_post_report(ApiScriptStep::failure(500));
+        api.script_post_report(ApiScriptStep::failure(500));
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        let submit_res = submitter.submit_inputs(testing_get_inputs());
+        assert!(submit_res.is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(3, requests.len());
+        assert_eq!(requests[0], requests[1]);
+        assert_eq!(requests[1], requests[2]);
+    }
+
+    #[test]
+    fn test_post_report_client_error_not_retried() {
+        let api = ScriptedTcnApi::default();
+        api.script_post_report(ApiScriptStep::failure(400));
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        let submit_res = submitter.submit_inputs(testing_get_inputs());
+        assert!(matches!(
+            submit_res,
+            Ok(SubmitOutcome::Rejected {
+                http_status: 400,
+                ..
+            })
+        ));
+        assert_eq!(1, api.posted_reports().len());
+    }
+
+    #[test]
+    fn test_sent_outcome_has_idempotency_key_as_receipt() {
+        let api = ScriptedTcnApi::default();
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        let outcome = submitter.submit_inputs(testing_get_inputs()).unwrap();
+
+        let requests = api.posted_reports();
+        assert_eq!(1, requests.len());
+        assert_eq!(
+            SubmitOutcome::Sent {
+                receipt: requests[0].idempotency_key.clone()
+            },
+            outcome
+        );
+    }
+
+    #[test]
+    fn test_inputs_without_symptoms_are_nothing_to_send() {
+        let api = ScriptedTcnApi::default();
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        let outcome = submitter.submit_inputs(SymptomInputs::default()).unwrap();
+
+        assert_eq!(SubmitOutcome::NothingToSend, outcome);
+        assert!(api.posted_reports().is_empty());
+    }
+
+    #[test]
+    fn test_no_symptoms_inputs_are_sent_as_retraction() {
+        let api = ScriptedTcnApi::default();
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+        let mut ids = HashSet::new();
+        ids.insert(SymptomId::None);
+
+        let outcome = submitter
+            .submit_inputs(SymptomInputs {
+                ids,
+                ..SymptomInputs::default()
+            })
+            .unwrap();
+
+        assert!(matches!(outcome, SubmitOutcome::Sent { .. }));
+        let requests = api.posted_reports();
+        assert_eq!(1, requests.len());
+        let public_symptoms = posted_public_symptoms(&requests[0]);
+        assert!(public_symptoms.no_symptoms);
+        assert!(public_symptoms.is_retraction());
+    }
+
+    #[test]
+    fn test_transient_client_errors_are_failures() {
+        for http_status in &[408, 429] {
+            let api = ScriptedTcnApi::default();
+            api.script_post_report(ApiScriptStep::failure(*http_status));
+            let submitter = SymptomInputsSubmitterImpl {
+                memo_mapper: &MemoMapperImpl {},
+                tcn_keys: test_tcn_keys(),
+                api: &api,
+                client_version: ClientVersion::default(),
+                preferences: Arc::new(PreferencesNoopMock {}),
+                clock: UnixTime::now,
+                sleep: no_sleep,
+            };
+
+            let submit_res = submitter.submit_inputs(testing_get_inputs());
+            assert!(matches!(submit_res, Err(ServicesError::Networking(_))));
+        }
+    }
+
+    #[test]
+    fn test_outcomes_as_void_result() {
+        assert!(SubmitOutcome::Sent {
+            receipt: "key".to_owned()
+        }
+        .into_void_result()
+        .is_ok());
+        assert!(SubmitOutcome::NothingToSend.into_void_result().is_ok());
+        let rejected = SubmitOutcome::Rejected {
+            http_status: 400,
+            reason: "Invalid report".to_owned(),
+        };
+        match rejected.into_void_result() {
+            Err(ServicesError::Networking(error)) => assert_eq!(400, error.http_status),
+            other => panic!("Expected networking error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_outcome_json() {
+        assert_eq!(
+            r#"{"outcome":"sent","receipt":"key"}"#,
+            serde_json::to_string(&SubmitOutcome::Sent {
+                receipt: "key".to_owned()
+            })
+            .unwrap()
+        );
+        assert_eq!(
+            r#"{"outcome":"nothing_to_send"}"#,
+            serde_json::to_string(&SubmitOutcome::NothingToSend).unwrap()
+        );
+        assert_eq!(
+            r#"{"outcome":"rejected","http_status":400,"reason":"Invalid"}"#,
+            serde_json::to_string(&SubmitOutcome::Rejected {
+                http_status: 400,
+                reason: "Invalid".to_owned()
+            })
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_submissions_use_different_idempotency_keys() {
+        let api = ScriptedTcnApi::default();
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_ok());
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(2, requests.len());
+        assert_ne!(requests[0].idempotency_key, requests[1].idempotency_key);
+    }
+
+    #[test]
+    fn test_submitted_report_has_client_version() {
+        let api = ScriptedTcnApi::default();
+        let client_version = ClientVersion {
+            core: "0.1.0".to_owned(),
+            app: "1.2.0 (45)".to_owned(),
+        };
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: client_version.clone(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(1, requests.len());
+        assert_eq!(client_version, requests[0].client_version);
+    }
+
+    #[test]
+    fn test_post_report_fails_after_max_attempts() {
+        let api = ScriptedTcnApi::default();
+        for _ in 0..POST_REPORT_MAX_ATTEMPTS {
+            api.script_post_report(ApiScriptStep::failure(503));
+        }
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        let submit_res = submitter.submit_inputs(testing_get_inputs());
+        match submit_res {
+            Err(ServicesError::Networking(error)) => assert_eq!(503, error.http_status),
+            other => panic!("Expected networking error, got: {:?}", other),
+        }
+        assert_eq!(POST_REPORT_MAX_ATTEMPTS, api.posted_reports().len());
+    }
+
+    #[test]
+    fn test_post_report_retries_back_off() {
+        let api = ScriptedTcnApi::default();
+        for _ in 0..POST_REPORT_MAX_ATTEMPTS {
+            api.script_post_report(ApiScriptStep::failure(503));
+        }
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: record_sleep,
+        };
+
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_err());
+
+        // No wait after the last attempt
+        let sleeps = SLEEPS.with(|sleeps| sleeps.borrow().clone());
+        assert_eq!(
+            vec![Duration::from_millis(500), Duration::from_secs(1)],
+            sleeps
+        );
+    }
+
+    #[test]
+    fn test_post_report_retry_delay_is_bounded() {
+        let delays: Vec<Duration> = (1..=6).map(post_report_retry_delay).collect();
+        assert_eq!(
+            vec![
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(4),
+                Duration::from_secs(4),
+                Duration::from_secs(4),
+            ],
+            delays
+        );
+        assert_eq!(Duration::from_secs(4), post_report_retry_delay(100));
+    }
+
+    // E.g. the app submits again after a timeout
+    #[test]
+    fn test_resubmit_after_server_error_reuses_idempotency_key() {
+        let api = ScriptedTcnApi::default();
+        for _ in 0..POST_REPORT_MAX_ATTEMPTS {
+            api.script_post_report(ApiScriptStep::failure(503));
+        }
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: test_preferences(),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_err());
+        assert!(submitter.preferences.pending_report_submission().is_some());
+
+        let submit_res = submitter.submit_inputs(testing_get_inputs());
+        let receipt = match submit_res {
+            Ok(SubmitOutcome::Sent { receipt }) => receipt,
+            other => panic!("Expected sent outcome, got: {:?}", other),
+        };
+
+        let requests = api.posted_reports();
+        assert_eq!(POST_REPORT_MAX_ATTEMPTS + 1, requests.len());
+        assert!(requests.iter().all(|request| *request == requests[0]));
+        assert_eq!(requests[0].idempotency_key, receipt);
+        // Acknowledged: the next submission is a new one
+        assert_eq!(None, submitter.preferences.pending_report_submission());
+    }
+
+    #[test]
+    fn test_submission_with_other_symptoms_doesnt_reuse_pending_key() {
+        let api = ScriptedTcnApi::default();
+        for _ in 0..POST_REPORT_MAX_ATTEMPTS {
+            api.script_post_report(ApiScriptStep::failure(503));
+        }
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: test_preferences(),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_err());
+
+        let mut other_inputs = testing_get_inputs();
+        other_inputs.ids.insert(SymptomId::RunnyNose);
+        assert!(submitter.submit_inputs(other_inputs).is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(POST_REPORT_MAX_ATTEMPTS + 1, requests.len());
+        assert_ne!(
+            requests[0].idempotency_key,
+            requests[POST_REPORT_MAX_ATTEMPTS].idempotency_key
+        );
+    }
+
+    #[test]
+    fn test_resubmit_after_pending_submission_expired_creates_new_report() {
+        let api = ScriptedTcnApi::default();
+        for _ in 0..POST_REPORT_MAX_ATTEMPTS {
+            api.script_post_report(ApiScriptStep::failure(503));
+        }
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: test_preferences(),
+            clock: test_clock,
+            sleep: no_sleep,
+        };
+        set_test_time(1590356601);
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_err());
+        let pending = submitter.preferences.pending_report_submission().unwrap();
+        assert_eq!(1590356601, pending.created_at);
+
+        // The same symptoms, after the pending submission expired
+        set_test_time(1590356601 + PENDING_REPORT_SUBMISSION_MAX_AGE_SECS);
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(POST_REPORT_MAX_ATTEMPTS + 1, requests.len());
+        let resubmitted = &requests[POST_REPORT_MAX_ATTEMPTS];
+        assert_ne!(pending.idempotency_key, resubmitted.idempotency_key);
+        assert_ne!(pending.report, resubmitted.report);
+        assert_eq!(
+            UnixTime {
+                value: 1590356601 + PENDING_REPORT_SUBMISSION_MAX_AGE_SECS
+            },
+            submitter
+                .preferences
+                .last_submitted_report()
+                .unwrap()
+                .report_time
+        );
+        assert_eq!(None, submitter.preferences.pending_report_submission());
+    }
+
+    #[test]
+    fn test_concurrent_submissions_retry_with_own_idempotency_key() {
+        // Leaked: the submitter borrows the api and is shared with the spawned threads
+        let api: &'static ScriptedTcnApi = Box::leak(Box::new(ScriptedTcnApi::default()));
+        // Slow failures, so the submissions' attempts interleave
+        for _ in 0..2 {
+            api.script_post_report(ApiScriptStep::failure(500).delayed(Duration::from_millis(50)));
+        }
+        let submitter = Arc::new(SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api,
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        });
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let submitter = submitter.clone();
+                thread::spawn(move || submitter.submit_inputs(testing_get_inputs()))
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+
+        // Each failure is retried once, regardless of which submission got it
+        let calls = api.calls();
+        assert_eq!(4, calls.len());
+
+        let mut keys_by_thread: HashMap<ThreadId, HashSet<String>> = HashMap::new();
+        for call in calls {
+            match call.args {
+                ApiCallArgs::PostReport(request) => {
+                    keys_by_thread
+                        .entry(call.thread)
+                        .or_default()
+                        .insert(request.idempotency_key);
+                }
+                args => panic!("Unexpected call: {:?}", args),
+            }
+        }
+        assert_eq!(2, keys_by_thread.len());
+        assert!(keys_by_thread.values().all(|keys| keys.len() == 1));
+        let all_keys: HashSet<&String> = keys_by_thread.values().flatten().collect();
+        assert_eq!(2, all_keys.len());
+    }
+
+    #[test]
+    fn test_resubmit_changes_only_earliest_symptom_time() {
+        let api = ScriptedTcnApi::default();
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: test_preferences(),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        assert!(submitter.submit_inputs(testing_get_inputs()).is_ok());
+        let new_time = UserInput::Some(UnixTime { value: 1590000000 });
+        let resubmit_res = submitter.resubmit_with_earliest_symptom_time(new_time.clone());
+        assert!(resubmit_res.is_ok());
+
+        let requests = api.posted_reports();
+        assert_eq!(2, requests.len());
+        assert_ne!(requests[0].idempotency_key, requests[1].idempotency_key);
+        let submitted = posted_public_symptoms(&requests[0]);
+        let resubmitted = posted_public_symptoms(&requests[1]);
+        assert_ne!(submitted.earliest_symptom_time, new_time);
+        assert_eq!(
+            PublicSymptoms {
+                earliest_symptom_time: new_time,
+                ..submitted
+            },
+            resubmitted
+        );
+        // The resubmitted report is the new last submitted one
+        assert_eq!(
+            Some(resubmitted),
+            submitter.preferences.last_submitted_report()
+        );
+    }
+
+    #[test]
+    fn test_resubmit_without_submitted_report_is_not_found() {
+        let api = ScriptedTcnApi::default();
+        api.script_post_report(ApiScriptStep::failure(400));
+        let submitter = SymptomInputsSubmitterImpl {
+            memo_mapper: &MemoMapperImpl {},
+            tcn_keys: test_tcn_keys(),
+            api: &api,
+            client_version: ClientVersion::default(),
+            preferences: test_preferences(),
+            clock: UnixTime::now,
+            sleep: no_sleep,
+        };
+
+        // A rejected submission isn't stored
+        assert!(matches!(
+            submitter.submit_inputs(testing_get_inputs()),
+            Ok(SubmitOutcome::Rejected { .. })
+        ));
+
+        match submitter.resubmit_with_earliest_symptom_time(UserInput::None) {
+            Err(ServicesError::NotFound) => {}
+            other => panic!("Expected NotFound, got: {:?}", other),
+        }
+        assert_eq!(1, api.posted_reports().len());
+    }
+
+    thread_local! {
+        static SLEEPS: RefCell<Vec<Duration>> = RefCell::new(vec![]);
+    }
+
+    fn record_sleep(duration: Duration) {
+        SLEEPS.with(|sleeps| sleeps.borrow_mut().push(duration));
+    }
+
+    fn no_sleep(_: Duration) {}
+
+    thread_local! {
+        static TEST_TIME: RefCell<u64> = RefCell::new(0);
+    }
+
+    fn set_test_time(value: u64) {
+        TEST_TIME.with(|time| *time.borrow_mut() = value);
+    }
+
+    fn test_clock() -> UnixTime {
+        UnixTime {
+            value: TEST_TIME.with(|time| *time.borrow()),
+        }
+    }
+
+    fn test_preferences() -> Arc<PreferencesImpl> {
+        let database = migrated_database();
+        Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        })
+    }
+
+    fn posted_public_symptoms(request: &PostReportRequest) -> PublicSymptoms {
+        let bytes = base64::decode(&request.report).unwrap();
+        let report = SignedReport::read(bytes.as_slice())
+            .unwrap()
+            .verify()
+            .unwrap();
+        MemoMapperImpl {}.to_report(Memo {
+            bytes: report.memo_data().to_vec(),
+        })
+    }
+
+    fn test_tcn_keys() -> Arc<TcnKeysImpl<PreferencesTckMock>> {
+        let rak_bytes = TEST_RAK_BYTES;
+        let tck = generate_tck_for_index(rak_bytes, 60);
+        let tck_bytes = TcnKeysImpl::<PreferencesTckMock>::tck_to_bytes(tck);
+
+        Arc::new(TcnKeysImpl::new(Arc::new(PreferencesTckMock { tck_bytes })))
+    }
+
     fn generate_tck_for_index(rak_bytes: [u8; 32], index: usize) -> TemporaryContactKey {
         let rak = ReportAuthorizationKey::with_bytes(rak_bytes);
         let mut tck = rak.initial_temporary_contact_key(); // tck <- tck_1
@@ -401,11 +1169,9 @@ mod tests {
         MemoMapperImpl,
         TcnKeysImpl<PreferencesTckMock>,
         TcnApiMock,
+        PreferencesNoopMock,
     > {
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
         let tck = generate_tck_for_index(rak_bytes, 60);
         debug!(">> tck: {:?}", tck);
         let tck_bytes = TcnKeysImpl::<PreferencesTckMock>::tck_to_bytes(tck);
@@ -414,14 +1180,16 @@ mod tests {
             tck_bytes: tck_bytes,
         });
 
-        let tcn_keys = Arc::new(TcnKeysImpl {
-            preferences: preferences.clone(),
-        });
+        let tcn_keys = Arc::new(TcnKeysImpl::new(preferences.clone()));
 
         let submitter = SymptomInputsSubmitterImpl {
             memo_mapper: &MemoMapperImpl {},
             tcn_keys: tcn_keys,
             api: &TcnApiMock {},
+            client_version: ClientVersion::default(),
+            preferences: Arc::new(PreferencesNoopMock {}),
+            clock: UnixTime::now,
+            sleep: no_sleep,
         };
 
         submitter
@@ -439,4 +1207,54 @@ mod tests {
             Err(_) => assert!(false),
         }
     }
+
+    #[test]
+    fn test_user_input_serializes_as_nullable() {
+        let set: UserInput<Days> = UserInput::Some(Days { value: 3 });
+        let not_set: UserInput<Days> = UserInput::None;
+
+        assert_eq!(serde_json::to_string(&set).unwrap(), r#"{"value":3}"#);
+        assert_eq!(serde_json::to_string(&not_set).unwrap(), "null");
+    }
+
+    #[test]
+    fn test_user_input_deserializes_from_nullable() {
+        let set: UserInput<Days> = serde_json::from_str(r#"{"value":3}"#).unwrap();
+        let not_set: UserInput<Days> = serde_json::from_str("null").unwrap();
+
+        assert_eq!(set.map(|days| days.value), UserInput::Some(3));
+        assert_eq!(not_set.map(|days| days.value), UserInput::None);
+    }
+
+    #[test]
+    fn test_symptom_inputs_json() {
+        let mut ids = HashSet::new();
+        ids.insert(SymptomId::Cough);
+
+        let inputs = SymptomInputs {
+            ids,
+            cough: Cough {
+                cough_type: UserInput::Some(CoughType::Wet),
+                days: UserInput::None,
+                status: UserInput::Some(CoughStatus::WorseWhenOutside),
+            },
+            breathlessness: Breathlessness {
+                cause: UserInput::None,
+            },
+            fever: Fever {
+                days: UserInput::Some(Days { value: 2 }),
+                taken_temperature_today: UserInput::Some(false),
+                temperature_spot: UserInput::None,
+                highest_temperature: UserInput::None,
+            },
+            earliest_symptom: EarliestSymptom {
+                time: UserInput::Some(UnixTime { value: 1590356601 }),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_string(&inputs).unwrap(),
+            r#"{"ids":["Cough"],"cough":{"cough_type":"Wet","days":null,"status":"WorseWhenOutside"},"breathlessness":{"cause":null},"fever":{"days":{"value":2},"taken_temperature_today":false,"temperature_spot":null,"highest_temperature":null},"earliest_symptom":{"time":{"value":1590356601}}}"#
+        );
+    }
 }
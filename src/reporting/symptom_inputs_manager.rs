@@ -1,8 +1,8 @@
 use super::{
     memo::MemoMapperImpl,
     symptom_inputs::{
-        BreathlessnessCause, CoughStatus, CoughType, Days, FarenheitTemperature, SymptomId,
-        SymptomInputs, SymptomInputsSubmitter, TemperatureSpot, UserInput,
+        BreathlessnessCause, CoughStatus, CoughType, Days, FarenheitTemperature, SubmitOutcome,
+        SymptomId, SymptomInputs, SymptomInputsSubmitter, TemperatureSpot, UserInput,
     },
 };
 use crate::{
@@ -12,10 +12,20 @@ use crate::{
 use chrono::{Duration, Utc};
 use log::*;
 use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::Value;
 use std::{collections::HashSet, sync::Arc};
 
+// Ids of set_symptom_ids. Rejected: unknown ids and tokens that aren't strings (as JSON)
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SymptomIdsResult {
+    pub accepted: Vec<String>,
+    pub rejected: Vec<String>,
+}
+
 pub trait SymptomInputsProcessor {
-    fn set_symptom_ids(&self, ids: &str) -> Result<(), ServicesError>;
+    // ids: JSON array of strings (nulls are skipped) or comma separated string
+    fn set_symptom_ids(&self, ids: &str) -> Result<SymptomIdsResult, ServicesError>;
     fn set_cough_type(&self, cough_type: &str) -> Result<(), ServicesError>;
     fn set_cough_days(&self, is_set: bool, days: u32) -> Result<(), ServicesError>;
     fn set_cough_status(&self, status: &str) -> Result<(), ServicesError>;
@@ -37,9 +47,16 @@ pub trait SymptomInputsProcessor {
         is_set: bool,
         days: u32,
     ) -> Result<(), ServicesError>;
+    // Absolute alternative to set_earliest_symptom_started_days_ago. The last call of either wins.
+    fn set_earliest_symptom_date(&self, is_set: bool, time: u64) -> Result<(), ServicesError>;
 
-    fn submit(&self) -> Result<(), ServicesError>;
+    // The inputs are cleared unless the submission failed or was rejected
+    fn submit(&self) -> Result<SubmitOutcome, ServicesError>;
     fn clear(&self) -> Result<(), ServicesError>;
+
+    // Resubmits the last submitted report with a corrected earliest symptom date.
+    // Independent of the current inputs.
+    fn resubmit_with_earliest_symptom(&self, days_ago: u32) -> Result<(), ServicesError>;
 }
 
 pub struct SymptomInputsProcessorImpl<T>
@@ -53,39 +70,29 @@ impl<A> SymptomInputsProcessor for SymptomInputsProcessorImpl<A>
 where
     A: SymptomInputsManager,
 {
-    fn set_symptom_ids(&self, ids: &str) -> Result<(), ServicesError> {
-        let res: Result<Vec<&str>, _> = serde_json::from_str(ids);
-        if let Err(error) = &res {
-            error!(
-                "Couldn't deserialize symptom ids: {}, error: {}",
-                ids, error
-            )
-        }
-        let inputs = res?;
-
+    fn set_symptom_ids(&self, ids: &str) -> Result<SymptomIdsResult, ServicesError> {
         let mut symptom_ids = HashSet::new();
-        for str_id in inputs {
-            let symptom_id = match str_id {
-                "cough" => SymptomId::Cough,
-                "breathlessness" => SymptomId::Breathlessness,
-                "fever" => SymptomId::Fever,
-                "muscle_aches" => SymptomId::MuscleAches,
-                "loss_smell_or_taste" => SymptomId::LossSmellOrTaste,
-                "diarrhea" => SymptomId::Diarrhea,
-                "runny_nose" => SymptomId::RunnyNose,
-                "other" => SymptomId::Other,
-                "none" => SymptomId::None,
-                _ => {
-                    error!("Not supported symptom id: {}", str_id);
-                    Err(format!("Not supported symptom id: {}", str_id))?
+        let mut result = SymptomIdsResult {
+            accepted: vec![],
+            rejected: vec![],
+        };
+        // Skips the nulls
+        for token in symptom_id_tokens(ids)?.into_iter().flatten() {
+            match to_symptom_id(&token) {
+                Some(symptom_id) => {
+                    symptom_ids.insert(symptom_id);
+                    result.accepted.push(token);
                 }
-            };
-            symptom_ids.insert(symptom_id);
+                None => result.rejected.push(token),
+            }
+        }
+        if !result.rejected.is_empty() {
+            error!("Not supported symptom ids: {:?}", result.rejected);
         }
 
         self.inputs_manager.select_symptom_ids(symptom_ids);
 
-        Ok(())
+        Ok(result)
     }
 
     fn set_cough_type(&self, cough_type: &str) -> Result<(), ServicesError> {
@@ -240,7 +247,22 @@ where
         Ok(())
     }
 
-    fn submit(&self) -> Result<(), ServicesError> {
+    fn set_earliest_symptom_date(&self, is_set: bool, time: u64) -> Result<(), ServicesError> {
+        let input = match is_set {
+            true => UserInput::Some(validated_earliest_symptom_time(
+                UnixTime { value: time },
+                UnixTime::now(),
+            )?),
+            false => UserInput::None,
+        };
+
+        debug!("Setting earliest symptom date {:?}", input);
+
+        self.inputs_manager.set_earliest_symptom_time(input);
+        Ok(())
+    }
+
+    fn submit(&self) -> Result<SubmitOutcome, ServicesError> {
         self.inputs_manager.submit()
     }
 
@@ -248,6 +270,12 @@ where
         self.inputs_manager.clear();
         Ok(())
     }
+
+    fn resubmit_with_earliest_symptom(&self, days_ago: u32) -> Result<(), ServicesError> {
+        debug!("Resubmitting with earliest symptom days ago: {}", days_ago);
+        self.inputs_manager
+            .resubmit_with_earliest_symptom(Days { value: days_ago })
+    }
 }
 
 pub trait SymptomInputsManager {
@@ -261,9 +289,12 @@ pub trait SymptomInputsManager {
     fn set_fever_taken_temperature_spot(&self, spot: UserInput<TemperatureSpot>);
     fn set_fever_highest_temperature_taken(&self, temp: UserInput<FarenheitTemperature>);
     fn set_earliest_symptom_started_days_ago(&self, days: UserInput<Days>);
+    fn set_earliest_symptom_time(&self, time: UserInput<UnixTime>);
 
-    fn submit(&self) -> Result<(), ServicesError>;
+    fn submit(&self) -> Result<SubmitOutcome, ServicesError>;
     fn clear(&self);
+
+    fn resubmit_with_earliest_symptom(&self, days_ago: Days) -> Result<(), ServicesError>;
 }
 
 pub struct SymptomInputsManagerImpl<T>
@@ -336,25 +367,25 @@ where
     }
 
     fn set_earliest_symptom_started_days_ago(&self, input: UserInput<Days>) {
-        let time = input.map(|days| {
-            let date_time = Utc::now() - Duration::days(days.value as i64);
-            UnixTime {
-                value: date_time.timestamp() as u64,
-            }
-        });
+        let time = input.map(days_ago_to_time);
 
         self.inputs.write().earliest_symptom.time = time;
         self.print_current_state();
     }
 
-    fn submit(&self) -> Result<(), ServicesError> {
+    fn set_earliest_symptom_time(&self, input: UserInput<UnixTime>) {
+        self.inputs.write().earliest_symptom.time = input;
+        self.print_current_state();
+    }
+
+    fn submit(&self) -> Result<SubmitOutcome, ServicesError> {
         debug!("Submitting symptom inputs...");
         self.print_current_state();
         let result = self
             .inputs_submitter
             .submit_inputs(self.inputs.read().clone());
 
-        if result.is_ok() {
+        if let Ok(SubmitOutcome::Sent { .. }) | Ok(SubmitOutcome::NothingToSend) = result {
             self.clear()
         }
         // TODO: if submit doesn't succeed, when to clear the inputs?
@@ -367,4 +398,321 @@ where
         *self.inputs.write() = Default::default();
         self.print_current_state();
     }
+
+    fn resubmit_with_earliest_symptom(&self, days_ago: Days) -> Result<(), ServicesError> {
+        self.inputs_submitter
+            .resubmit_with_earliest_symptom_time(UserInput::Some(days_ago_to_time(days_ago)))
+    }
+}
+
+// None: a null in the JSON array. Array elements that aren't strings are passed as JSON, to be rejected.
+fn symptom_id_tokens(ids: &str) -> Result<Vec<Option<String>>, ServicesError> {
+    let trimmed = ids.trim();
+    if !trimmed.starts_with('[') && !trimmed.starts_with('{') {
+        return Ok(trimmed
+            .split(',')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(|token| Some(token.to_owned()))
+            .collect());
+    }
+
+    match serde_json::from_str(trimmed) {
+        Ok(Value::Array(values)) => Ok(values
+            .into_iter()
+            .map(|value| match value {
+                Value::Null => None,
+                Value::String(str) => Some(str),
+                other => Some(other.to_string()),
+            })
+            .collect()),
+        Ok(_) => {
+            error!("Symptom ids isn't an array: {}", ids);
+            Err(ServicesError::FFIParameters(format!(
+                "Symptom ids must be a JSON array of strings or comma separated, got: {}",
+                ids
+            )))
+        }
+        Err(error) => {
+            error!(
+                "Couldn't deserialize symptom ids: {}, error: {}",
+                ids, error
+            );
+            Err(ServicesError::FFIParameters(format!(
+                "Symptom ids aren't valid JSON: {}",
+                ids
+            )))
+        }
+    }
+}
+
+fn to_symptom_id(str_id: &str) -> Option<SymptomId> {
+    match str_id {
+        "cough" => Some(SymptomId::Cough),
+        "breathlessness" => Some(SymptomId::Breathlessness),
+        "fever" => Some(SymptomId::Fever),
+        "muscle_aches" => Some(SymptomId::MuscleAches),
+        "loss_smell_or_taste" => Some(SymptomId::LossSmellOrTaste),
+        "diarrhea" => Some(SymptomId::Diarrhea),
+        "runny_nose" => Some(SymptomId::RunnyNose),
+        "other" => Some(SymptomId::Other),
+        "none" => Some(SymptomId::None),
+        _ => None,
+    }
+}
+
+// Symptoms that started earlier aren't relevant for the exposures
+const MAX_EARLIEST_SYMPTOM_AGE_DAYS: i64 = 90;
+
+fn validated_earliest_symptom_time(
+    time: UnixTime,
+    now: UnixTime,
+) -> Result<UnixTime, ServicesError> {
+    let oldest = now.value as i64 - Duration::days(MAX_EARLIEST_SYMPTOM_AGE_DAYS).num_seconds();
+    if time.value > now.value {
+        error!("Earliest symptom date: {:?} is in the future", time);
+        return Err(format!("Earliest symptom date is in the future: {}", time.value).into());
+    }
+    if (time.value as i64) < oldest {
+        error!("Earliest symptom date: {:?} is too old", time);
+        return Err(format!(
+            "Earliest symptom date is older than {} days: {}",
+            MAX_EARLIEST_SYMPTOM_AGE_DAYS, time.value
+        )
+        .into());
+    }
+    Ok(time)
+}
+
+fn days_ago_to_time(days: Days) -> UnixTime {
+    let date_time = Utc::now() - Duration::days(days.value as i64);
+    UnixTime {
+        value: date_time.timestamp() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{networking::NetworkingError, reporting::symptom_inputs::SymptomInputsSubmitter};
+
+    #[test]
+    fn test_set_symptom_ids_json_array() {
+        let processor = processor_with_noop_submitter();
+
+        let res = processor.set_symptom_ids(r#"["cough", "fever"]"#);
+
+        assert_eq!(
+            res.unwrap(),
+            SymptomIdsResult {
+                accepted: vec!["cough".to_owned(), "fever".to_owned()],
+                rejected: vec![],
+            }
+        );
+        let ids = processor.inputs_manager.inputs.read().ids.clone();
+        assert_eq!(
+            ids,
+            vec![SymptomId::Cough, SymptomId::Fever]
+                .into_iter()
+                .collect::<HashSet<SymptomId>>()
+        );
+    }
+
+    #[test]
+    fn test_set_symptom_ids_comma_separated() {
+        let processor = processor_with_noop_submitter();
+
+        let res = processor.set_symptom_ids(" runny_nose,diarrhea , ");
+
+        assert_eq!(
+            res.unwrap(),
+            SymptomIdsResult {
+                accepted: vec!["runny_nose".to_owned(), "diarrhea".to_owned()],
+                rejected: vec![],
+            }
+        );
+        let ids = processor.inputs_manager.inputs.read().ids.clone();
+        assert_eq!(
+            ids,
+            vec![SymptomId::RunnyNose, SymptomId::Diarrhea]
+                .into_iter()
+                .collect::<HashSet<SymptomId>>()
+        );
+    }
+
+    #[test]
+    fn test_set_symptom_ids_object_is_rejected() {
+        let processor = processor_with_noop_submitter();
+        assert!(processor.set_symptom_ids(r#"["cough"]"#).is_ok());
+
+        let res = processor.set_symptom_ids(r#"{"ids": ["fever"]}"#);
+
+        assert!(res.is_err());
+        // The previous selection is kept
+        let ids = processor.inputs_manager.inputs.read().ids.clone();
+        assert_eq!(
+            ids,
+            vec![SymptomId::Cough]
+                .into_iter()
+                .collect::<HashSet<SymptomId>>()
+        );
+    }
+
+    #[test]
+    fn test_set_symptom_ids_mixed_validity_array() {
+        let processor = processor_with_noop_submitter();
+
+        let res = processor.set_symptom_ids(r#"["cough", null, "not_supported", 1, "other"]"#);
+
+        assert_eq!(
+            res.unwrap(),
+            SymptomIdsResult {
+                accepted: vec!["cough".to_owned(), "other".to_owned()],
+                rejected: vec!["not_supported".to_owned(), "1".to_owned()],
+            }
+        );
+        let ids = processor.inputs_manager.inputs.read().ids.clone();
+        assert_eq!(
+            ids,
+            vec![SymptomId::Cough, SymptomId::Other]
+                .into_iter()
+                .collect::<HashSet<SymptomId>>()
+        );
+    }
+
+    #[test]
+    fn test_earliest_symptom_time_bounds() {
+        let now = UnixTime { value: 1591706000 };
+        let oldest = 1591706000 - 90 * 24 * 60 * 60;
+
+        assert!(validated_earliest_symptom_time(now.clone(), now.clone()).is_ok());
+        assert!(validated_earliest_symptom_time(UnixTime { value: oldest }, now.clone()).is_ok());
+
+        assert!(
+            validated_earliest_symptom_time(UnixTime { value: 1591706001 }, now.clone()).is_err()
+        );
+        assert!(validated_earliest_symptom_time(UnixTime { value: oldest - 1 }, now).is_err());
+    }
+
+    #[test]
+    fn test_invalid_earliest_symptom_date_keeps_current_input() {
+        let processor = processor_with_noop_submitter();
+        let time = UnixTime::now().value - 60;
+        assert!(processor.set_earliest_symptom_date(true, time).is_ok());
+
+        let in_future = UnixTime::now().value + 24 * 60 * 60;
+        assert!(processor
+            .set_earliest_symptom_date(true, in_future)
+            .is_err());
+
+        assert_eq!(
+            processor.inputs_manager.inputs.read().earliest_symptom.time,
+            UserInput::Some(UnixTime { value: time })
+        );
+    }
+
+    #[test]
+    fn test_last_earliest_symptom_setter_wins() {
+        let processor = processor_with_noop_submitter();
+        let time = UnixTime::now().value - 60;
+
+        assert!(processor
+            .set_earliest_symptom_started_days_ago(true, 3)
+            .is_ok());
+        assert!(processor.set_earliest_symptom_date(true, time).is_ok());
+        assert_eq!(
+            processor.inputs_manager.inputs.read().earliest_symptom.time,
+            UserInput::Some(UnixTime { value: time })
+        );
+
+        assert!(processor
+            .set_earliest_symptom_started_days_ago(true, 3)
+            .is_ok());
+        assert_ne!(
+            processor.inputs_manager.inputs.read().earliest_symptom.time,
+            UserInput::Some(UnixTime { value: time })
+        );
+
+        assert!(processor.set_earliest_symptom_date(false, 0).is_ok());
+        assert_eq!(
+            processor.inputs_manager.inputs.read().earliest_symptom.time,
+            UserInput::None
+        );
+    }
+
+    #[test]
+    fn test_submit_clears_inputs_unless_not_sent() {
+        assert!(clears_inputs_after(|| Ok(SubmitOutcome::Sent {
+            receipt: "key".to_owned()
+        })));
+        assert!(clears_inputs_after(|| Ok(SubmitOutcome::NothingToSend)));
+        assert!(!clears_inputs_after(|| Ok(SubmitOutcome::Rejected {
+            http_status: 400,
+            reason: "Invalid report".to_owned()
+        })));
+        assert!(!clears_inputs_after(|| Err(ServicesError::Networking(
+            NetworkingError {
+                http_status: 503,
+                message: "Service unavailable".to_owned()
+            }
+        ))));
+    }
+
+    struct SymptomInputsSubmitterNoopMock {}
+    impl SymptomInputsSubmitter<MemoMapperImpl, TcnKeysImpl<PreferencesImpl>, TcnApiImpl>
+        for SymptomInputsSubmitterNoopMock
+    {
+        fn submit_inputs(&self, _inputs: SymptomInputs) -> Result<SubmitOutcome, ServicesError> {
+            Ok(SubmitOutcome::NothingToSend)
+        }
+
+        fn resubmit_with_earliest_symptom_time(
+            &self,
+            _time: UserInput<UnixTime>,
+        ) -> Result<(), ServicesError> {
+            Ok(())
+        }
+    }
+
+    fn processor_with_noop_submitter(
+    ) -> SymptomInputsProcessorImpl<SymptomInputsManagerImpl<SymptomInputsSubmitterNoopMock>> {
+        SymptomInputsProcessorImpl {
+            inputs_manager: SymptomInputsManagerImpl {
+                inputs: Arc::new(RwLock::new(SymptomInputs::default())),
+                inputs_submitter: SymptomInputsSubmitterNoopMock {},
+            },
+        }
+    }
+
+    // Every submission has the result of submit_result
+    struct SymptomInputsSubmitterResultMock {
+        submit_result: fn() -> Result<SubmitOutcome, ServicesError>,
+    }
+    impl SymptomInputsSubmitter<MemoMapperImpl, TcnKeysImpl<PreferencesImpl>, TcnApiImpl>
+        for SymptomInputsSubmitterResultMock
+    {
+        fn submit_inputs(&self, _inputs: SymptomInputs) -> Result<SubmitOutcome, ServicesError> {
+            (self.submit_result)()
+        }
+
+        fn resubmit_with_earliest_symptom_time(
+            &self,
+            _time: UserInput<UnixTime>,
+        ) -> Result<(), ServicesError> {
+            Ok(())
+        }
+    }
+
+    // Whether the inputs were cleared after a submission with the result
+    fn clears_inputs_after(submit_result: fn() -> Result<SubmitOutcome, ServicesError>) -> bool {
+        let processor = SymptomInputsProcessorImpl {
+            inputs_manager: SymptomInputsManagerImpl {
+                inputs: Arc::new(RwLock::new(SymptomInputs::default())),
+                inputs_submitter: SymptomInputsSubmitterResultMock { submit_result },
+            },
+        };
+        assert!(processor.set_symptom_ids("cough").is_ok());
+        let _ = processor.submit();
+        processor.inputs_manager.inputs.read().ids.is_empty()
+    }
 }
@@ -1,4 +1,5 @@
 use chrono::prelude::*;
+use log::*;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -56,13 +57,48 @@ impl ReportsInterval {
         }
     }
 
+    // The interval after the last completed one or, if there's none, the interval containing time.
+    pub fn start_interval(
+        last_completed: Option<ReportsInterval>,
+        time: &UnixTime,
+    ) -> ReportsInterval {
+        last_completed
+            .map(|interval| interval.next())
+            .unwrap_or_else(|| Self::create_for_with_default_length(time))
+    }
+
+    // Consecutive intervals, beginning with from, that start before until.
+    pub fn sequence(
+        from: ReportsInterval,
+        until: &UnixTime,
+    ) -> impl Iterator<Item = ReportsInterval> + '_ {
+        std::iter::successors(Some(from), |item| Some(item.next()))
+            .take_while(move |item| item.starts_before(until))
+    }
+
+    // The interval with the latest end at or before time. The intervals don't have to be sorted.
+    // Intervals without the expected length are ignored: their numbers aren't comparable.
     pub fn interval_ending_before(
-        intervals: Vec<ReportsInterval>,
+        intervals: &[ReportsInterval],
+        length: u64,
         time: &UnixTime,
     ) -> Option<ReportsInterval> {
-        // TODO shorter version of this?
-        let reversed: Vec<ReportsInterval> = intervals.into_iter().rev().collect();
-        reversed.into_iter().find(|i| i.ends_before(&time))
+        let mut ended: Vec<ReportsInterval> = intervals
+            .iter()
+            .filter(|interval| {
+                if interval.length != length {
+                    warn!(
+                        "Ignoring interval: {:?} with length other than: {}",
+                        interval, length
+                    );
+                    return false;
+                }
+                interval.end() <= time.value
+            })
+            .copied()
+            .collect();
+        ended.sort_by_key(|interval| interval.end());
+        ended.pop()
     }
 }
 
@@ -70,6 +106,62 @@ impl ReportsInterval {
 mod tests {
     use super::*;
 
+    #[test]
+    fn start_interval_is_next_after_last_completed() {
+        let last_completed = ReportsInterval {
+            number: 73690,
+            length: 21600,
+        };
+        let time = UnixTime {
+            value: last_completed.start() + 100000,
+        };
+
+        let start_interval = ReportsInterval::start_interval(Some(last_completed), &time);
+
+        assert_eq!(start_interval, last_completed.next());
+    }
+
+    #[test]
+    fn start_interval_without_last_completed_contains_time() {
+        let time = UnixTime { value: 1591706000 };
+
+        let start_interval = ReportsInterval::start_interval(None, &time);
+
+        assert_eq!(
+            start_interval,
+            ReportsInterval {
+                number: 73690,
+                length: 21600
+            }
+        );
+    }
+
+    #[test]
+    fn sequence_ends_with_interval_containing_until() {
+        let from = ReportsInterval {
+            number: 73688,
+            length: 21600,
+        };
+        let until = UnixTime { value: 1591706000 }; // In interval 73690
+
+        let sequence: Vec<u64> = ReportsInterval::sequence(from, &until)
+            .map(|interval| interval.number)
+            .collect();
+
+        assert_eq!(sequence, vec![73688, 73689, 73690]);
+    }
+
+    #[test]
+    fn sequence_is_empty_if_from_starts_after_until() {
+        let from = ReportsInterval {
+            number: 73691,
+            length: 21600,
+        };
+        let until = UnixTime { value: 1591706000 };
+
+        assert_eq!(ReportsInterval::sequence(from, &until).count(), 0);
+    }
+
     #[test]
     fn interval_ending_before_if_contained_and_one_interval() {
         let containing_interval = ReportsInterval {
@@ -84,7 +176,7 @@ mod tests {
         };
 
         let interval_ending_before: Option<ReportsInterval> =
-            ReportsInterval::interval_ending_before(intervals, &time);
+            ReportsInterval::interval_ending_before(&intervals, 21600, &time);
 
         // time is contained in the interval, and it's the only interval, so there's no interval ending before of time's interval
         assert!(interval_ending_before.is_none());
@@ -109,7 +201,7 @@ mod tests {
         };
 
         let interval_ending_before: Option<ReportsInterval> =
-            ReportsInterval::interval_ending_before(intervals, &time);
+            ReportsInterval::interval_ending_before(&intervals, 21600, &time);
 
         assert!(interval_ending_before.is_some());
         assert_eq!(interval_ending_before.unwrap(), interval_before);
@@ -122,8 +214,95 @@ mod tests {
         let time = UnixTime { value: 1591706000 }; // arbitrary time
 
         let interval_ending_before: Option<ReportsInterval> =
-            ReportsInterval::interval_ending_before(intervals, &time);
+            ReportsInterval::interval_ending_before(&intervals, 21600, &time);
 
         assert!(interval_ending_before.is_none());
     }
+
+    #[test]
+    fn interval_ending_before_is_latest_ended_if_unsorted() {
+        let intervals: Vec<ReportsInterval> = vec![73688, 73686, 73690, 73689, 73687]
+            .into_iter()
+            .map(|number| ReportsInterval {
+                number,
+                length: 21600,
+            })
+            .collect();
+        let time = UnixTime { value: 1591706000 }; // In interval 73690
+
+        let interval_ending_before =
+            ReportsInterval::interval_ending_before(&intervals, 21600, &time);
+
+        assert_eq!(
+            interval_ending_before,
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            })
+        );
+    }
+
+    #[test]
+    fn interval_ending_before_ignores_other_lengths() {
+        let interval = ReportsInterval {
+            number: 73688,
+            length: 21600,
+        };
+        // Ends later, before time
+        let other_length_interval = ReportsInterval {
+            number: 147379,
+            length: 10800,
+        };
+        let time = UnixTime { value: 1591706000 };
+
+        let interval_ending_before = ReportsInterval::interval_ending_before(
+            &[other_length_interval, interval],
+            21600,
+            &time,
+        );
+
+        assert_eq!(interval_ending_before, Some(interval));
+    }
+
+    #[test]
+    fn interval_ending_before_includes_interval_ending_at_time() {
+        let interval = ReportsInterval {
+            number: 73689,
+            length: 21600,
+        };
+        let time = UnixTime {
+            value: interval.end(),
+        };
+
+        let interval_ending_before =
+            ReportsInterval::interval_ending_before(&[interval, interval.next()], 21600, &time);
+
+        assert_eq!(interval_ending_before, Some(interval));
+    }
+
+    #[test]
+    fn interval_ending_before_has_max_end_not_after_time() {
+        let time = UnixTime { value: 1591706000 };
+        let intervals: Vec<ReportsInterval> = (73680..73695)
+            .map(|number| ReportsInterval {
+                number,
+                length: 21600,
+            })
+            .collect();
+
+        // Every rotation of the input
+        for shift in 0..intervals.len() {
+            let mut rotated = intervals.clone();
+            rotated.rotate_left(shift);
+
+            let interval_ending_before =
+                ReportsInterval::interval_ending_before(&rotated, 21600, &time).unwrap();
+
+            assert!(interval_ending_before.end() <= time.value);
+            assert!(rotated
+                .iter()
+                .filter(|interval| interval.end() <= time.value)
+                .all(|interval| interval.end() <= interval_ending_before.end()));
+        }
+    }
 }
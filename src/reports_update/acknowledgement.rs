@@ -0,0 +1,338 @@
+use super::{alert_reference::AlertReferences, reports_updater::Alert};
+use crate::{
+    config::AcknowledgementConfig, database::alert_dao::AlertDao, errors::ServicesError,
+    networking::AcknowledgementApi, reports_interval::UnixTime,
+};
+use log::*;
+use serde::Serialize;
+use std::sync::Arc;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Lower bounds of the contact duration buckets, in minutes
+const DURATION_BUCKETS_MINUTES: [u64; 4] = [0, 5, 15, 60];
+
+// Everything that's exported. Coarse on purpose: it must not get fields with TCNs, report signatures,
+// alert / report ids or exact times (test_payload_has_no_forbidden_fields).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AcknowledgementPayload {
+    // See AlertReferences: can be matched with a token the user reads out, but not mapped back to the alert
+    pub reference_token: String,
+    // Days since the unix epoch, of the contact start
+    pub contact_day: u64,
+    // Lower bound of the contact duration's bucket (DURATION_BUCKETS_MINUTES)
+    pub duration_bucket_minutes: u64,
+}
+
+impl AcknowledgementPayload {
+    pub fn new(reference_token: String, alert: &Alert) -> AcknowledgementPayload {
+        let duration_minutes = alert.contact_end.saturating_sub(alert.contact_start) / 60;
+        AcknowledgementPayload {
+            reference_token,
+            contact_day: alert.contact_start / SECONDS_PER_DAY,
+            duration_bucket_minutes: DURATION_BUCKETS_MINUTES
+                .iter()
+                .rev()
+                .find(|bucket| duration_minutes >= **bucket)
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+}
+
+// Export of acknowledged exposures to a health department, required by some pilots.
+// Triggered by the user, and only available if the endpoint is configured (AcknowledgementConfig).
+pub trait AcknowledgementService {
+    // Err(General) if the export isn't configured, Err(NotFound) if there's no alert with the id.
+    // An alert is exported once: submitting it again succeeds without posting.
+    fn submit_acknowledgement(&self, alert_id: &str) -> Result<(), ServicesError>;
+}
+
+pub struct AcknowledgementServiceImpl<A: AcknowledgementApi, R: AlertReferences, D: AlertDao> {
+    pub api: A,
+    pub alert_references: Arc<R>,
+    pub alert_dao: Arc<D>,
+    pub config: AcknowledgementConfig,
+    pub clock: fn() -> UnixTime,
+}
+
+impl<A: AcknowledgementApi, R: AlertReferences, D: AlertDao> AcknowledgementServiceImpl<A, R, D> {
+    pub fn new(
+        api: A,
+        alert_references: Arc<R>,
+        alert_dao: Arc<D>,
+        config: AcknowledgementConfig,
+    ) -> AcknowledgementServiceImpl<A, R, D> {
+        AcknowledgementServiceImpl {
+            api,
+            alert_references,
+            alert_dao,
+            config,
+            clock: UnixTime::now,
+        }
+    }
+}
+
+impl<A: AcknowledgementApi, R: AlertReferences, D: AlertDao> AcknowledgementService
+    for AcknowledgementServiceImpl<A, R, D>
+{
+    fn submit_acknowledgement(&self, alert_id: &str) -> Result<(), ServicesError> {
+        if self.config.endpoint.is_empty() {
+            return Err(ServicesError::General(
+                "Acknowledgement export isn't configured".to_owned(),
+            ));
+        }
+        if self
+            .alert_dao
+            .acknowledgement_submitted(alert_id)?
+            .is_some()
+        {
+            info!("Acknowledgement of alert: {} already submitted", alert_id);
+            return Ok(());
+        }
+
+        let alert = self.alert_dao.alert(alert_id)?;
+        let reference_token = self.alert_references.alert_reference_token(alert_id)?;
+        let payload = AcknowledgementPayload::new(reference_token, &alert);
+        self.api
+            .post_acknowledgement(serde_json::to_string(&payload)?)?;
+
+        self.alert_dao
+            .set_acknowledgement_submitted(alert_id, (self.clock)())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            alert_dao::AlertDaoImpl,
+            preferences::{PreferencesDao, PreferencesImpl},
+        },
+        networking::NetworkingError,
+        reporting::{
+            public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
+            symptom_inputs::UserInput,
+        },
+        reports_update::{alert_reference::AlertReferencesImpl, reports_updater::AlertDebugInfo},
+        test_support::migrated_database,
+    };
+    use parking_lot::Mutex;
+    use serde_json::Value;
+
+    // Substrings no payload field may contain
+    const FORBIDDEN_FIELDS: [&str; 8] = [
+        "tcn", "report", "sig", "alert_id", "distance", "symptom", "start", "end",
+    ];
+
+    #[derive(Default)]
+    struct AcknowledgementApiRecordingMock {
+        posted: Mutex<Vec<String>>,
+        fails: bool,
+    }
+
+    impl AcknowledgementApi for AcknowledgementApiRecordingMock {
+        fn post_acknowledgement(&self, payload: String) -> Result<(), NetworkingError> {
+            self.posted.lock().push(payload);
+            if self.fails {
+                Err(NetworkingError {
+                    http_status: 503,
+                    message: "Service unavailable".to_owned(),
+                })
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    type TestService = AcknowledgementServiceImpl<
+        AcknowledgementApiRecordingMock,
+        AlertReferencesImpl<PreferencesImpl, AlertDaoImpl>,
+        AlertDaoImpl,
+    >;
+
+    fn create_service(endpoint: &str, api: AcknowledgementApiRecordingMock) -> TestService {
+        let database = migrated_database();
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        let save_res = alert_dao.save(vec![test_alert()]);
+        assert!(save_res.is_ok());
+        let alert_references = Arc::new(AlertReferencesImpl::new(
+            Arc::new(PreferencesImpl {
+                dao: PreferencesDao::new(database),
+            }),
+            alert_dao.clone(),
+        ));
+
+        let mut service = AcknowledgementServiceImpl::new(
+            api,
+            alert_references,
+            alert_dao,
+            AcknowledgementConfig {
+                endpoint: endpoint.to_owned(),
+            },
+        );
+        service.clock = || UnixTime { value: 1591800000 };
+        service
+    }
+
+    #[test]
+    fn test_submits_minimal_payload() {
+        let service = create_service(
+            "https://health.example.org/ack",
+            AcknowledgementApiRecordingMock::default(),
+        );
+
+        assert!(service.submit_acknowledgement("alert-1").is_ok());
+
+        let posted = service.api.posted.lock().clone();
+        assert_eq!(1, posted.len());
+        let payload: Value = serde_json::from_str(&posted[0]).unwrap();
+        assert_eq!(
+            Value::from(
+                service
+                    .alert_references
+                    .alert_reference_token("alert-1")
+                    .unwrap()
+            ),
+            payload["reference_token"]
+        );
+        assert_eq!(Value::from(18422), payload["contact_day"]);
+        // 20 minutes
+        assert_eq!(Value::from(15), payload["duration_bucket_minutes"]);
+
+        assert_eq!(
+            Some(1591800000),
+            service
+                .alert_dao
+                .acknowledgement_submitted("alert-1")
+                .unwrap()
+        );
+
+        // Exported once
+        assert!(service.submit_acknowledgement("alert-1").is_ok());
+        assert_eq!(1, service.api.posted.lock().len());
+    }
+
+    #[test]
+    fn test_payload_has_no_forbidden_fields() {
+        let service = create_service(
+            "https://health.example.org/ack",
+            AcknowledgementApiRecordingMock::default(),
+        );
+        assert!(service.submit_acknowledgement("alert-1").is_ok());
+
+        let posted = service.api.posted.lock().clone();
+        let payload: Value = serde_json::from_str(&posted[0]).unwrap();
+        let keys: Vec<&str> = payload
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|key| key.as_str())
+            .collect();
+        assert_eq!(
+            vec!["contact_day", "duration_bucket_minutes", "reference_token"],
+            keys
+        );
+        for key in keys {
+            for forbidden in FORBIDDEN_FIELDS.iter() {
+                assert!(!key.contains(forbidden), "Forbidden field: {}", key);
+            }
+        }
+
+        // Neither are the values of the alert
+        for value in &["alert-1", "report-1", "1591706000", "1591707200"] {
+            assert!(!posted[0].contains(value), "Exported: {}", value);
+        }
+    }
+
+    #[test]
+    fn test_not_submitted_without_endpoint() {
+        let service = create_service("", AcknowledgementApiRecordingMock::default());
+
+        let submit_res = service.submit_acknowledgement("alert-1");
+        assert!(matches!(submit_res, Err(ServicesError::General(_))));
+        assert!(service.api.posted.lock().is_empty());
+        assert_eq!(
+            None,
+            service
+                .alert_dao
+                .acknowledgement_submitted("alert-1")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_failed_submission_isnt_recorded() {
+        let service = create_service(
+            "https://health.example.org/ack",
+            AcknowledgementApiRecordingMock {
+                fails: true,
+                ..AcknowledgementApiRecordingMock::default()
+            },
+        );
+
+        let submit_res = service.submit_acknowledgement("alert-1");
+        assert!(matches!(submit_res, Err(ServicesError::Networking(_))));
+        assert_eq!(
+            None,
+            service
+                .alert_dao
+                .acknowledgement_submitted("alert-1")
+                .unwrap()
+        );
+
+        let submit_res = service.submit_acknowledgement("unknown");
+        assert!(matches!(submit_res, Err(ServicesError::NotFound)));
+    }
+
+    #[test]
+    fn test_duration_buckets() {
+        let bucket = |minutes: u64| {
+            let mut alert = test_alert();
+            alert.contact_end = alert.contact_start + minutes * 60;
+            AcknowledgementPayload::new("token".to_owned(), &alert).duration_bucket_minutes
+        };
+        assert_eq!(0, bucket(0));
+        assert_eq!(0, bucket(4));
+        assert_eq!(5, bucket(5));
+        assert_eq!(5, bucket(14));
+        assert_eq!(15, bucket(59));
+        assert_eq!(60, bucket(600));
+    }
+
+    fn test_alert() -> Alert {
+        Alert {
+            id: "alert-1".to_owned(),
+            report_id: "report-1".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 0 },
+                earliest_symptom_time: UserInput::None,
+                fever_severity: FeverSeverity::Mild,
+                cough_severity: CoughSeverity::Dry,
+                breathlessness: true,
+                muscle_aches: false,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: false,
+                other: false,
+                no_symptoms: false,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            },
+            contact_start: 1591706000,
+            contact_end: 1591707200,
+            min_distance: 2.3,
+            avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
+            is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
+        }
+    }
+}
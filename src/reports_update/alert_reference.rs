@@ -0,0 +1,293 @@
+use crate::{
+    database::{alert_dao::AlertDao, preferences::Preferences},
+    errors::ServicesError,
+};
+use hmac::{Hmac, Mac, NewMac};
+use log::*;
+use sha2::Sha256;
+use std::sync::Arc;
+
+// 80 bits: 16 base32 characters
+const TOKEN_BYTES: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Short opaque tokens referencing an alert, e.g. for call center verification.
+// The token is a (truncated) HMAC of the alert's id, which is unique and doesn't change when the exposure
+// is extended (unlike the contact window, see AlertDao::update_measurements): stable for an alert,
+// but can't be mapped back to the alert or its report without the key, which never leaves the device.
+pub trait AlertReferences {
+    fn alert_reference_token(&self, alert_id: &str) -> Result<String, ServicesError>;
+    fn verify_alert_reference_token(
+        &self,
+        token: &str,
+        alert_id: &str,
+    ) -> Result<bool, ServicesError>;
+}
+
+pub struct AlertReferencesImpl<T: Preferences, U: AlertDao> {
+    preferences: Arc<T>,
+    alert_dao: Arc<U>,
+}
+
+impl<T: Preferences, U: AlertDao> AlertReferencesImpl<T, U> {
+    // Generates the key, if it doesn't exist yet
+    pub fn new(preferences: Arc<T>, alert_dao: Arc<U>) -> AlertReferencesImpl<T, U> {
+        if preferences.alert_token_key().is_none() {
+            info!("Generating alert token key");
+            preferences.set_alert_token_key(rand::random());
+        }
+        AlertReferencesImpl {
+            preferences,
+            alert_dao,
+        }
+    }
+
+    fn token_bytes(&self, alert_id: &str) -> Result<Vec<u8>, ServicesError> {
+        let alert = self.alert_dao.alert(alert_id)?;
+        let key = self
+            .preferences
+            .alert_token_key()
+            .ok_or_else(|| ServicesError::General("Alert token key not set".to_owned()))?;
+
+        let mut mac = Hmac::<Sha256>::new_varkey(&key)
+            .map_err(|e| ServicesError::General(format!("Invalid alert token key: {}", e)))?;
+        mac.update(alert.id.as_bytes());
+        let mut bytes = mac.finalize().into_bytes().to_vec();
+        bytes.truncate(TOKEN_BYTES);
+        Ok(bytes)
+    }
+}
+
+impl<T: Preferences, U: AlertDao> AlertReferences for AlertReferencesImpl<T, U> {
+    fn alert_reference_token(&self, alert_id: &str) -> Result<String, ServicesError> {
+        self.token_bytes(alert_id)
+            .map(|bytes| base32_encode(&bytes))
+    }
+
+    fn verify_alert_reference_token(
+        &self,
+        token: &str,
+        alert_id: &str,
+    ) -> Result<bool, ServicesError> {
+        let expected = self.alert_reference_token(alert_id)?;
+        // Users may read the token over the phone: case and surrounding whitespace don't matter
+        let token = token.trim().to_ascii_uppercase();
+        Ok(constant_time_eq(token.as_bytes(), expected.as_bytes()))
+    }
+}
+
+// RFC 4648 base32, without padding
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+// Doesn't leak through timing how many leading characters of a guessed token are correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            alert_dao::AlertDaoImpl,
+            preferences::{PreferencesDao, PreferencesImpl},
+        },
+        reporting::{
+            public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
+            symptom_inputs::UserInput,
+        },
+        reports_interval::UnixTime,
+        reports_update::reports_updater::{Alert, AlertDebugInfo},
+        test_support::migrated_database,
+    };
+
+    #[test]
+    fn test_token_is_stable_and_verifies() {
+        let (alert_references, _) = create_alert_references(vec![test_alert("1", 1000)]);
+
+        let token = alert_references.alert_reference_token("1").unwrap();
+        assert_eq!(16, token.len());
+        assert!(token.bytes().all(|b| BASE32_ALPHABET.contains(&b)));
+        assert_eq!(token, alert_references.alert_reference_token("1").unwrap());
+
+        assert!(alert_references
+            .verify_alert_reference_token(&token, "1")
+            .unwrap());
+        // Read over the phone
+        assert!(alert_references
+            .verify_alert_reference_token(&format!(" {} ", token.to_lowercase()), "1")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let (alert_references, _) =
+            create_alert_references(vec![test_alert("1", 1000), test_alert("2", 1000)]);
+
+        let token = alert_references.alert_reference_token("1").unwrap();
+        let mut tampered = token.clone().into_bytes();
+        tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+
+        assert!(!alert_references
+            .verify_alert_reference_token(&tampered, "1")
+            .unwrap());
+        assert!(!alert_references
+            .verify_alert_reference_token(&token[..15], "1")
+            .unwrap());
+        // Token of another alert
+        assert!(!alert_references
+            .verify_alert_reference_token(&token, "2")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_token_depends_on_key() {
+        let (alert_references, _) = create_alert_references(vec![test_alert("1", 1000)]);
+        let (other_device_alert_references, _) =
+            create_alert_references(vec![test_alert("1", 1000)]);
+
+        assert_ne!(
+            alert_references.alert_reference_token("1").unwrap(),
+            other_device_alert_references
+                .alert_reference_token("1")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_key_is_kept_across_instances() {
+        let (alert_references, preferences) = create_alert_references(vec![test_alert("1", 1000)]);
+        let token = alert_references.alert_reference_token("1").unwrap();
+
+        let recreated_alert_references =
+            AlertReferencesImpl::new(preferences, alert_references.alert_dao.clone());
+        assert_eq!(
+            token,
+            recreated_alert_references
+                .alert_reference_token("1")
+                .unwrap()
+        );
+    }
+
+    // The window of an alert is widened when later observations extend its exposure
+    #[test]
+    fn test_token_verifies_after_alert_is_extended() {
+        let alert = test_alert("1", 1000);
+        let (alert_references, _) = create_alert_references(vec![alert.clone()]);
+        let token = alert_references.alert_reference_token("1").unwrap();
+
+        let extended_alert = Alert {
+            contact_end: alert.contact_end + 600,
+            min_distance: 1.5,
+            ..alert
+        };
+        let update_res = alert_references
+            .alert_dao
+            .update_measurements(&[extended_alert]);
+        assert!(update_res.is_ok());
+        assert_eq!(
+            2600,
+            alert_references.alert_dao.alert("1").unwrap().contact_end
+        );
+
+        assert!(alert_references
+            .verify_alert_reference_token(&token, "1")
+            .unwrap());
+        assert_eq!(token, alert_references.alert_reference_token("1").unwrap());
+    }
+
+    #[test]
+    fn test_token_of_unknown_alert_is_error() {
+        let (alert_references, _) = create_alert_references(vec![]);
+
+        match alert_references.alert_reference_token("1") {
+            Err(ServicesError::NotFound) => {}
+            other => panic!("Expected NotFound, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_base32_encode() {
+        // RFC 4648 test vectors (without padding)
+        assert_eq!("", base32_encode(b""));
+        assert_eq!("MY", base32_encode(b"f"));
+        assert_eq!("MZXQ", base32_encode(b"fo"));
+        assert_eq!("MZXW6", base32_encode(b"foo"));
+        assert_eq!("MZXW6YQ", base32_encode(b"foob"));
+        assert_eq!("MZXW6YTB", base32_encode(b"fooba"));
+        assert_eq!("MZXW6YTBOI", base32_encode(b"foobar"));
+    }
+
+    fn create_alert_references(
+        alerts: Vec<Alert>,
+    ) -> (
+        AlertReferencesImpl<PreferencesImpl, AlertDaoImpl>,
+        Arc<PreferencesImpl>,
+    ) {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let alert_dao = Arc::new(AlertDaoImpl::new(database));
+        let save_res = alert_dao.save(alerts);
+        assert!(save_res.is_ok());
+
+        (
+            AlertReferencesImpl::new(preferences.clone(), alert_dao),
+            preferences,
+        )
+    }
+
+    fn test_alert(id: &str, contact_start: u64) -> Alert {
+        Alert {
+            id: id.to_owned(),
+            report_id: "1".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 0 },
+                earliest_symptom_time: UserInput::None,
+                fever_severity: FeverSeverity::Mild,
+                cough_severity: CoughSeverity::Dry,
+                breathlessness: true,
+                muscle_aches: false,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: false,
+                other: false,
+                no_symptoms: false,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            },
+            contact_start,
+            contact_end: contact_start + 1000,
+            min_distance: 2.3,
+            avg_distance: 4.3,
+            p10_distance: 2.3,
+            median_distance: 4.3,
+            is_read: false,
+            has_raw_report: false,
+            report_coverage_start: None,
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo::default(),
+            snoozed_until: None,
+            retracted: false,
+        }
+    }
+}
@@ -1,7 +1,17 @@
 use crate::{
-    errors::ServicesError, reports_interval, tcn_recording::observed_tcn_processor::ObservedTcn,
+    errors::ServicesError,
+    reports_interval,
+    tcn_recording::{
+        distance_histogram::DistanceHistogram,
+        observed_tcn_processor::{ObservedTcn, MAX_TOTAL_COUNT},
+    },
 };
 use reports_interval::UnixTime;
+use serde::{Deserialize, Serialize};
+use tcn::TemporaryContactNumber;
+
+// Enough to tell the observations of an exposure apart, without storing the reporter's TCNs
+const PROVENANCE_TCN_PREFIX_BYTES: usize = 4;
 
 #[derive(PartialEq, Debug)]
 pub struct Exposure {
@@ -35,6 +45,16 @@ impl Exposure {
         self.tcns.last().unwrap().clone()
     }
 
+    // Hex, sorted and without duplicates (see ReportExposure::tcn_prefixes)
+    pub fn tcn_prefixes(&self) -> Vec<String> {
+        let prefixes = self
+            .tcns
+            .iter()
+            .map(|tcn| hex::encode(&tcn.tcn.0[..PROVENANCE_TCN_PREFIX_BYTES]))
+            .collect();
+        sorted_unique(prefixes)
+    }
+
     pub fn measurements(&self) -> ExposureMeasurements {
         let mut tcns = self.tcns.clone();
         tcns.sort_by_key(|tcn| tcn.contact_start.value);
@@ -44,19 +64,42 @@ impl Exposure {
             .expect("Invalid state: struct guarantees that tcns can't be empty");
 
         let contact_start = first_tcn.contact_start.value;
-        let contact_end = tcns.last().unwrap_or(first_tcn).contact_end.value;
+        // Max instead of last: a TCN can be contained in (overlap) the previous one.
+        let contact_end = tcns
+            .iter()
+            .map(|tcn| tcn.contact_end.value)
+            .max()
+            .unwrap_or(first_tcn.contact_end.value);
 
         let mut min_distance = std::f32::MAX;
         let mut total_count: usize = 0;
+        // Weights summed separately, as total_count is capped.
+        let mut weights = 0.0;
         let mut avg_distance = 0.0;
+        let mut distance_histogram = DistanceHistogram::default();
         for tcn in tcns {
             min_distance = f32::min(min_distance, tcn.min_distance);
-            total_count += tcn.total_count;
+            distance_histogram.merge(&tcn.distance_histogram);
+            total_count = total_count
+                .saturating_add(tcn.total_count)
+                .min(MAX_TOTAL_COUNT);
+            weights += tcn.total_count as f32;
             avg_distance += tcn.avg_distance * tcn.total_count as f32;
         }
         // Note: this struct (Exposure) guarantees that TCNs can't be empty,
         // so don't have to check for 0 division.
-        avg_distance /= total_count as f32;
+        avg_distance /= weights;
+
+        // TCNs stored before the histograms were recorded: fall back to the min / avg distance.
+        // Not below the min distance, which is exact, while the percentiles are bucket approximations.
+        let p10_distance = distance_histogram
+            .percentile(0.1)
+            .unwrap_or(min_distance)
+            .max(min_distance);
+        let median_distance = distance_histogram
+            .percentile(0.5)
+            .unwrap_or(avg_distance)
+            .max(min_distance);
 
         ExposureMeasurements {
             contact_start: UnixTime {
@@ -65,7 +108,10 @@ impl Exposure {
             contact_end: UnixTime { value: contact_end },
             min_distance,
             avg_distance,
+            p10_distance,
+            median_distance,
             total_count,
+            distance_histogram,
         }
     }
 }
@@ -74,7 +120,36 @@ pub struct ExposureMeasurements {
     pub contact_end: UnixTime,
     pub min_distance: f32,
     pub avg_distance: f32,
+    // Approximated from the distance histogram (see DistanceHistogram::percentile)
+    pub p10_distance: f32,
+    pub median_distance: f32,
+    pub total_count: usize,
+    pub distance_histogram: DistanceHistogram,
+}
+
+// Exposure of a report as persisted by AlertDao, so later runs extend it instead of regrouping from scratch.
+// Identified by the id of the alert created for it, which stays the same when the exposure is extended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportExposure {
+    pub alert_id: String,
+    pub report_id: String,
+    pub contact_start: u64,
+    pub contact_end: u64,
+    pub min_distance: f32,
+    pub avg_distance: f32,
+    pub p10_distance: f32,
+    pub median_distance: f32,
     pub total_count: usize,
+    // Prefixes of the observed TCNs that matched the report, to debug false positives.
+    // Empty unless recorded (see ReportsUpdater::record_tcn_prefixes).
+    pub tcn_prefixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExposureChange {
+    New,
+    Extended,
+    Unchanged,
 }
 
 // Groups TCNs by contiguity.
@@ -83,6 +158,12 @@ pub struct ExposureGrouper {
     pub threshold: u64,
 }
 
+impl Default for ExposureGrouper {
+    fn default() -> Self {
+        ExposureGrouper { threshold: 3600 }
+    }
+}
+
 impl ExposureGrouper {
     pub fn group(&self, mut tcns: Vec<ObservedTcn>) -> Vec<Exposure> {
         tcns.sort_by_key(|tcn| tcn.contact_start.value);
@@ -103,20 +184,214 @@ impl ExposureGrouper {
         exposures
     }
 
+    // Merges the exposures grouped from the report's currently stored TCNs into the persisted ones.
+    // A grouped exposure contiguous with a persisted one extends it: the contact window is the union and
+    // min distance the minimum. Average and percentile distances and count are taken from the one with
+    // more samples: the TCNs are usually a superset of the previous run's, but may have been deleted in
+    // the meantime.
+    // Otherwise it's a new exposure. Merging the same TCNs again changes nothing.
+    // Persisted exposures are never merged with each other, so alert ids stay stable.
+    pub fn extend(
+        &self,
+        stored: Vec<ReportExposure>,
+        grouped: Vec<ReportExposure>,
+    ) -> Vec<(ReportExposure, ExposureChange)> {
+        let mut exposures: Vec<(ReportExposure, ExposureChange)> = stored
+            .into_iter()
+            .map(|exposure| (exposure, ExposureChange::Unchanged))
+            .collect();
+
+        for new in grouped {
+            let existing = exposures.iter_mut().find(|(exposure, change)| {
+                *change != ExposureChange::New && self.windows_contiguous(exposure, &new)
+            });
+            match existing {
+                Some((exposure, change)) => {
+                    let extended = extended_exposure(exposure, &new);
+                    if extended != *exposure {
+                        *exposure = extended;
+                        if *change == ExposureChange::Unchanged {
+                            *change = ExposureChange::Extended;
+                        }
+                    }
+                }
+                None => exposures.push((new, ExposureChange::New)),
+            }
+        }
+        exposures
+    }
+
+    fn windows_contiguous(&self, exposure1: &ReportExposure, exposure2: &ReportExposure) -> bool {
+        (exposure2.contact_start as i64 - exposure1.contact_end as i64) < self.threshold as i64
+            && (exposure1.contact_start as i64 - exposure2.contact_end as i64)
+                < self.threshold as i64
+    }
+
     // Notes:
     // - Expects tcn2.start > tcn1.start. If will return otherwise always true.
     // - Overlapping is considered contiguous.
-    // (Note that depending on the implementation of writes, overlaps may not be possible.)
+    // Overlaps happen, e.g. when a TCN window stored in the DB is merged with a batch
+    // observed in the meantime. TcnBatchesManager merges overlapping / contiguous windows into one.
     pub fn is_contiguous(&self, tcn1: &ObservedTcn, tcn2: &ObservedTcn) -> bool {
         // Signed: overlap (start2 < end1) considered contiguous.
         (tcn2.contact_start.value as i64 - tcn1.contact_end.value as i64) < self.threshold as i64
     }
 }
 
+// Observation window as cached by the apps, to regroup them with a different threshold
+// (group_exposure_windows_json). Also the format of the grouped measurements.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExposureWindow {
+    pub start: u64,
+    pub end: u64,
+    pub min: f32,
+    pub avg: f32,
+    pub count: usize,
+}
+
+impl ExposureWindow {
+    fn to_observed_tcn(&self) -> Result<ObservedTcn, ServicesError> {
+        if self.end < self.start
+            || self.count == 0
+            || !self.min.is_finite()
+            || !self.avg.is_finite()
+        {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid exposure window: {:?}",
+                self
+            )));
+        }
+        Ok(ObservedTcn {
+            // Not used for grouping
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: self.start },
+            contact_end: UnixTime { value: self.end },
+            min_distance: self.min,
+            avg_distance: self.avg,
+            total_count: self.count,
+            distance_histogram: DistanceHistogram::default(),
+        })
+    }
+}
+
+impl From<ExposureMeasurements> for ExposureWindow {
+    fn from(measurements: ExposureMeasurements) -> Self {
+        ExposureWindow {
+            start: measurements.contact_start.value,
+            end: measurements.contact_end.value,
+            min: measurements.min_distance,
+            avg: measurements.avg_distance,
+            count: measurements.total_count,
+        }
+    }
+}
+
+// Groups the windows (JSON array of ExposureWindow) with the threshold and returns the measurements
+// of each group, sorted by start. No db access.
+pub fn group_exposure_windows_json(
+    windows_json: &str,
+    threshold: u64,
+) -> Result<Vec<ExposureWindow>, ServicesError> {
+    let windows: Vec<ExposureWindow> = serde_json::from_str(windows_json).map_err(|e| {
+        ServicesError::FFIParameters(format!("Invalid exposure windows JSON: {}", e))
+    })?;
+    let tcns = windows
+        .iter()
+        .map(|window| window.to_observed_tcn())
+        .collect::<Result<Vec<ObservedTcn>, ServicesError>>()?;
+
+    Ok(ExposureGrouper { threshold }
+        .group(tcns)
+        .iter()
+        .map(|exposure| ExposureWindow::from(exposure.measurements()))
+        .collect())
+}
+
+// Time covered by the windows (start, end), counting overlaps once. E.g. TCN windows overlap at the
+// rotation boundaries, and the exposures of different reports can overlap (several people nearby).
+// Windows ending before they start are empty.
+pub fn union_duration(windows: &[(u64, u64)]) -> u64 {
+    let mut sorted = windows.to_vec();
+    sorted.sort_unstable();
+
+    let mut duration = 0;
+    let mut covered_until = 0;
+    for (start, end) in sorted {
+        let start = start.max(covered_until);
+        if end > start {
+            duration += end - start;
+            covered_until = end;
+        }
+    }
+    duration
+}
+
+fn extended_exposure(stored: &ReportExposure, new: &ReportExposure) -> ReportExposure {
+    let aggregates = if new.total_count > stored.total_count {
+        new
+    } else {
+        stored
+    };
+    ReportExposure {
+        alert_id: stored.alert_id.clone(),
+        report_id: stored.report_id.clone(),
+        contact_start: stored.contact_start.min(new.contact_start),
+        contact_end: stored.contact_end.max(new.contact_end),
+        min_distance: f32::min(stored.min_distance, new.min_distance),
+        avg_distance: aggregates.avg_distance,
+        p10_distance: aggregates.p10_distance,
+        median_distance: aggregates.median_distance,
+        total_count: aggregates.total_count,
+        tcn_prefixes: sorted_unique(
+            stored
+                .tcn_prefixes
+                .iter()
+                .chain(&new.tcn_prefixes)
+                .cloned()
+                .collect(),
+        ),
+    }
+}
+
+fn sorted_unique(mut strings: Vec<String>) -> Vec<String> {
+    strings.sort();
+    strings.dedup();
+    strings
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tcn::TemporaryContactNumber;
+
+    #[test]
+    fn test_union_duration_disjoint() {
+        assert_eq!(0, union_duration(&[]));
+        assert_eq!(300, union_duration(&[(1000, 1100), (2000, 2200)]));
+    }
+
+    #[test]
+    fn test_union_duration_overlapping() {
+        // E.g. rotated TCNs observed at the same time
+        assert_eq!(1500, union_duration(&[(1900, 2500), (1000, 2000)]));
+    }
+
+    #[test]
+    fn test_union_duration_nested() {
+        assert_eq!(
+            1000,
+            union_duration(&[(1000, 2000), (1200, 1300), (1500, 2000)])
+        );
+    }
+
+    #[test]
+    fn test_union_duration_adjacent() {
+        assert_eq!(1000, union_duration(&[(1000, 1500), (1500, 2000)]));
+    }
+
+    #[test]
+    fn test_union_duration_ignores_reversed_windows() {
+        assert_eq!(100, union_duration(&[(1000, 1100), (3000, 2000)]));
+    }
 
     #[test]
     fn test_group_in_exposures_empty() {
@@ -135,6 +410,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -143,6 +419,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
         let groups = ExposureGrouper { threshold: 1000 }.group(tcns.clone());
@@ -161,6 +438,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -169,6 +447,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -187,6 +466,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -195,6 +475,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -220,6 +501,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -228,6 +510,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -253,6 +536,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             // starts before previous TCN ends
             ObservedTcn {
@@ -262,6 +546,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -280,6 +565,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -288,6 +574,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -296,6 +583,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -304,6 +592,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -312,6 +601,7 @@ mod tests {
                 min_distance: 0.0,
                 avg_distance: 0.0,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -342,6 +632,7 @@ mod tests {
                 min_distance: 2.3,
                 avg_distance: 2.7, // (2.3 + 3.1) / 2
                 total_count: 2,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -350,6 +641,7 @@ mod tests {
                 min_distance: 0.845,
                 avg_distance: 0.948333333, // (0.845 + 0.5 + 1.5) / 3
                 total_count: 3,
+                distance_histogram: DistanceHistogram::default(),
             },
             ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
@@ -358,6 +650,7 @@ mod tests {
                 min_distance: 0.846,
                 avg_distance: 0.846,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             },
         ];
 
@@ -370,4 +663,447 @@ mod tests {
         assert_eq!(avg_rounded, 1.5151); // (2.3 + 3.1 + 0.845 + 0.5 + 1.5 + 0.846) / (2 + 3 + 1)
         assert_eq!(measurements.total_count, 6); // 2 + 3 + 1
     }
+
+    #[test]
+    fn test_exposure_measurements_total_count_saturates() {
+        let tcns = vec![
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 1000 },
+                contact_end: UnixTime { value: 2000 },
+                min_distance: 1.0,
+                avg_distance: 1.0,
+                total_count: MAX_TOTAL_COUNT - 1,
+                distance_histogram: DistanceHistogram::default(),
+            },
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 2001 },
+                contact_end: UnixTime { value: 3000 },
+                min_distance: 3.0,
+                avg_distance: 3.0,
+                total_count: MAX_TOTAL_COUNT - 1,
+                distance_histogram: DistanceHistogram::default(),
+            },
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 3001 },
+                contact_end: UnixTime { value: 4000 },
+                min_distance: 2.0,
+                avg_distance: 2.0,
+                total_count: usize::MAX,
+                distance_histogram: DistanceHistogram::default(),
+            },
+        ];
+
+        let measurements = Exposure::create_with_tcns(tcns).unwrap().measurements();
+
+        assert_eq!(measurements.total_count, MAX_TOTAL_COUNT);
+        assert!(measurements.avg_distance >= 1.0 && measurements.avg_distance <= 3.0);
+    }
+
+    #[test]
+    fn test_exposure_measurements_contained_tcn() {
+        let tcns = vec![
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 1000 },
+                contact_end: UnixTime { value: 5000 },
+                min_distance: 1.0,
+                avg_distance: 1.0,
+                total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
+            },
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 2000 },
+                contact_end: UnixTime { value: 3000 },
+                min_distance: 2.0,
+                avg_distance: 2.0,
+                total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
+            },
+        ];
+
+        let measurements = Exposure::create_with_tcns(tcns).unwrap().measurements();
+
+        assert_eq!(measurements.contact_start.value, 1000);
+        assert_eq!(measurements.contact_end.value, 5000);
+    }
+
+    #[test]
+    fn test_exposure_measurements_percentiles_ignore_noisy_closest_sample() {
+        // 1 sample at 0.3 m, 19 at 3 - 4 m, split in 2 TCNs
+        let mut histogram1 = DistanceHistogram::with_sample(0.3);
+        for _ in 0..9 {
+            histogram1.merge(&DistanceHistogram::with_sample(3.5));
+        }
+        let mut histogram2 = DistanceHistogram::default();
+        for _ in 0..10 {
+            histogram2.merge(&DistanceHistogram::with_sample(3.2));
+        }
+        let tcns = vec![
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 1000 },
+                contact_end: UnixTime { value: 2000 },
+                min_distance: 0.3,
+                avg_distance: 3.18,
+                total_count: 10,
+                distance_histogram: histogram1,
+            },
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 2001 },
+                contact_end: UnixTime { value: 3000 },
+                min_distance: 3.2,
+                avg_distance: 3.2,
+                total_count: 10,
+                distance_histogram: histogram2,
+            },
+        ];
+
+        let measurements = Exposure::create_with_tcns(tcns).unwrap().measurements();
+
+        assert_eq!(0.3, measurements.min_distance);
+        assert_eq!(20, measurements.distance_histogram.total());
+        // Within the 3 - 4 m bucket
+        assert!(
+            measurements.p10_distance >= 3.0 && measurements.p10_distance <= 4.0,
+            "p10: {}",
+            measurements.p10_distance
+        );
+        assert!(
+            measurements.median_distance >= 3.0 && measurements.median_distance <= 4.0,
+            "median: {}",
+            measurements.median_distance
+        );
+        assert!(measurements.p10_distance <= measurements.median_distance);
+    }
+
+    #[test]
+    fn test_exposure_measurements_percentiles_of_uniform_samples() {
+        // 1 sample per bucket between 0 and 4 m
+        let mut histogram = DistanceHistogram::default();
+        for distance in &[0.2, 0.7, 1.2, 1.7, 2.5, 3.5] {
+            histogram.merge(&DistanceHistogram::with_sample(*distance));
+        }
+        let tcn = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1000 },
+            contact_end: UnixTime { value: 2000 },
+            min_distance: 0.2,
+            avg_distance: 1.63,
+            total_count: 6,
+            distance_histogram: histogram,
+        };
+
+        let measurements = Exposure::create(tcn).measurements();
+
+        // 10th percentile: in the 0 - 0.5 m bucket, not below the min distance
+        assert!(
+            measurements.p10_distance >= 0.2 && measurements.p10_distance <= 0.5,
+            "p10: {}",
+            measurements.p10_distance
+        );
+        // Median: the 3rd sample, in the 1 - 1.5 m bucket
+        assert!(
+            measurements.median_distance >= 1.0 && measurements.median_distance <= 1.5,
+            "median: {}",
+            measurements.median_distance
+        );
+    }
+
+    #[test]
+    fn test_exposure_measurements_without_histogram_fall_back_to_min_and_avg() {
+        let tcn = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1000 },
+            contact_end: UnixTime { value: 2000 },
+            min_distance: 1.1,
+            avg_distance: 2.4,
+            total_count: 3,
+            distance_histogram: DistanceHistogram::default(),
+        };
+
+        let measurements = Exposure::create(tcn).measurements();
+
+        assert_eq!(1.1, measurements.p10_distance);
+        assert_eq!(2.4, measurements.median_distance);
+    }
+
+    #[test]
+    fn test_extend_without_stored_exposures_are_new() {
+        let grouped = vec![
+            report_exposure("a", 1000, 2000, 3),
+            report_exposure("b", 9000, 9500, 1),
+        ];
+
+        let exposures = ExposureGrouper { threshold: 1000 }.extend(vec![], grouped.clone());
+
+        assert_eq!(
+            vec![
+                (grouped[0].clone(), ExposureChange::New),
+                (grouped[1].clone(), ExposureChange::New)
+            ],
+            exposures
+        );
+    }
+
+    #[test]
+    fn test_extend_contiguous_exposure_keeps_id() {
+        let stored = report_exposure("a", 1000, 2000, 3);
+        let mut grouped = report_exposure("b", 1000, 2600, 5);
+        grouped.min_distance = 0.5;
+        grouped.avg_distance = 1.5;
+        grouped.p10_distance = 0.8;
+        grouped.median_distance = 1.4;
+
+        let exposures = ExposureGrouper { threshold: 1000 }.extend(vec![stored], vec![grouped]);
+
+        assert_eq!(1, exposures.len());
+        let (exposure, change) = &exposures[0];
+        assert_eq!(ExposureChange::Extended, *change);
+        assert_eq!("a", exposure.alert_id);
+        assert_eq!(1000, exposure.contact_start);
+        assert_eq!(2600, exposure.contact_end);
+        assert_eq!(0.5, exposure.min_distance);
+        assert_eq!(1.5, exposure.avg_distance);
+        assert_eq!(0.8, exposure.p10_distance);
+        assert_eq!(1.4, exposure.median_distance);
+        assert_eq!(5, exposure.total_count);
+    }
+
+    #[test]
+    fn test_extend_with_fewer_samples_keeps_stored_aggregates() {
+        // E.g. the older TCNs were deleted: only the window grows
+        let stored = report_exposure("a", 1000, 2000, 3);
+        let grouped = report_exposure("b", 2500, 2600, 1);
+
+        let exposures =
+            ExposureGrouper { threshold: 1000 }.extend(vec![stored.clone()], vec![grouped]);
+
+        assert_eq!(
+            vec![(
+                ReportExposure {
+                    contact_end: 2600,
+                    ..stored
+                },
+                ExposureChange::Extended
+            )],
+            exposures
+        );
+    }
+
+    #[test]
+    fn test_extend_disjoint_exposure_is_new() {
+        let stored = report_exposure("a", 1000, 2000, 3);
+        let grouped = report_exposure("b", 5000, 5100, 1);
+
+        let exposures =
+            ExposureGrouper { threshold: 1000 }.extend(vec![stored.clone()], vec![grouped.clone()]);
+
+        assert_eq!(
+            vec![
+                (stored, ExposureChange::Unchanged),
+                (grouped, ExposureChange::New)
+            ],
+            exposures
+        );
+    }
+
+    #[test]
+    fn test_extend_is_idempotent() {
+        let grouper = ExposureGrouper { threshold: 1000 };
+        let grouped = vec![
+            report_exposure("a", 1000, 2000, 3),
+            report_exposure("b", 9000, 9500, 1),
+        ];
+
+        let stored: Vec<ReportExposure> = grouper
+            .extend(vec![], grouped.clone())
+            .into_iter()
+            .map(|(exposure, _)| exposure)
+            .collect();
+        let exposures = grouper.extend(stored.clone(), grouped);
+
+        assert_eq!(
+            stored
+                .into_iter()
+                .map(|exposure| (exposure, ExposureChange::Unchanged))
+                .collect::<Vec<_>>(),
+            exposures
+        );
+    }
+
+    #[test]
+    fn test_exposure_tcn_prefixes_are_sorted_and_unique() {
+        let tcn = |first_byte: u8, start: u64| ObservedTcn {
+            tcn: TemporaryContactNumber([first_byte; 16]),
+            contact_start: UnixTime { value: start },
+            contact_end: UnixTime { value: start + 1 },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let exposure =
+            Exposure::create_with_tcns(vec![tcn(0xbb, 1000), tcn(0xaa, 1500), tcn(0xbb, 2000)])
+                .unwrap();
+
+        assert_eq!(vec!["aaaaaaaa", "bbbbbbbb"], exposure.tcn_prefixes());
+    }
+
+    #[test]
+    fn test_extend_merges_tcn_prefixes() {
+        let stored = ReportExposure {
+            tcn_prefixes: vec!["aaaaaaaa".to_owned(), "cccccccc".to_owned()],
+            ..report_exposure("a", 1000, 2000, 3)
+        };
+        let grouped = ReportExposure {
+            tcn_prefixes: vec!["bbbbbbbb".to_owned(), "cccccccc".to_owned()],
+            ..report_exposure("b", 1500, 2500, 4)
+        };
+
+        let exposures = ExposureGrouper { threshold: 1000 }.extend(vec![stored], vec![grouped]);
+
+        assert_eq!(1, exposures.len());
+        assert_eq!(
+            vec!["aaaaaaaa", "bbbbbbbb", "cccccccc"],
+            exposures[0].0.tcn_prefixes
+        );
+    }
+
+    #[test]
+    fn test_group_windows_json_empty() {
+        let groups = group_exposure_windows_json("[]", 1000).unwrap();
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_group_windows_json_same_group() {
+        let groups = group_exposure_windows_json(
+            r#"[
+                {"start": 1000, "end": 1001, "min": 0.5, "avg": 1.0, "count": 1},
+                {"start": 1500, "end": 1501, "min": 1.0, "avg": 2.0, "count": 3}
+            ]"#,
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![ExposureWindow {
+                start: 1000,
+                end: 1501,
+                min: 0.5,
+                avg: 1.75, // (1.0 + 2.0 * 3) / 4
+                count: 4
+            }],
+            groups
+        );
+    }
+
+    #[test]
+    fn test_group_windows_json_different_groups_sorted() {
+        let groups = group_exposure_windows_json(
+            r#"[
+                {"start": 2002, "end": 2501, "min": 1.0, "avg": 1.0, "count": 1},
+                {"start": 1000, "end": 1001, "min": 2.0, "avg": 2.0, "count": 1}
+            ]"#,
+            1000,
+        )
+        .unwrap();
+
+        let windows: Vec<(u64, u64)> = groups.iter().map(|w| (w.start, w.end)).collect();
+        assert_eq!(vec![(1000, 1001), (2002, 2501)], windows);
+    }
+
+    #[test]
+    fn test_group_windows_json_threshold_changes_groups() {
+        let windows_json = r#"[
+            {"start": 1000, "end": 1001, "min": 1.0, "avg": 1.0, "count": 1},
+            {"start": 2002, "end": 2501, "min": 1.0, "avg": 1.0, "count": 1}
+        ]"#;
+
+        assert_eq!(
+            2,
+            group_exposure_windows_json(windows_json, 1000)
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            1,
+            group_exposure_windows_json(windows_json, 1002)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_group_windows_json_overlap() {
+        let groups = group_exposure_windows_json(
+            r#"[
+                {"start": 1000, "end": 2000, "min": 1.0, "avg": 1.0, "count": 1},
+                {"start": 1600, "end": 2600, "min": 1.0, "avg": 1.0, "count": 1}
+            ]"#,
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(1, groups.len());
+        assert_eq!((1000, 2600), (groups[0].start, groups[0].end));
+    }
+
+    #[test]
+    fn test_group_windows_json_output_format() {
+        let groups = group_exposure_windows_json(
+            r#"[{"start": 1000, "end": 2000, "min": 1.5, "avg": 2.5, "count": 2}]"#,
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::json!([{"start": 1000, "end": 2000, "min": 1.5, "avg": 2.5, "count": 2}]),
+            serde_json::to_value(groups).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_group_windows_json_rejects_invalid_windows() {
+        let res = group_exposure_windows_json(r#"[{"start": 1000}]"#, 1000);
+        assert!(matches!(res, Err(ServicesError::FFIParameters(_))));
+
+        let res = group_exposure_windows_json(
+            r#"[{"start": 2000, "end": 1000, "min": 1.0, "avg": 1.0, "count": 1}]"#,
+            1000,
+        );
+        assert!(matches!(res, Err(ServicesError::FFIParameters(_))));
+
+        // Average distance would be a division by 0
+        let res = group_exposure_windows_json(
+            r#"[{"start": 1000, "end": 2000, "min": 1.0, "avg": 1.0, "count": 0}]"#,
+            1000,
+        );
+        assert!(matches!(res, Err(ServicesError::FFIParameters(_))));
+    }
+
+    fn report_exposure(
+        alert_id: &str,
+        contact_start: u64,
+        contact_end: u64,
+        total_count: usize,
+    ) -> ReportExposure {
+        ReportExposure {
+            alert_id: alert_id.to_owned(),
+            report_id: "report".to_owned(),
+            contact_start,
+            contact_end,
+            min_distance: 1.0,
+            avg_distance: 2.0,
+            p10_distance: 1.2,
+            median_distance: 1.8,
+            total_count,
+            tcn_prefixes: vec![],
+        }
+    }
 }
@@ -1,3 +1,6 @@
+pub mod acknowledgement;
+pub mod alert_reference;
 pub mod exposure;
+pub mod quiet_hours;
 pub mod reports_updater;
 pub mod tcn_matcher;
@@ -0,0 +1,119 @@
+use crate::{errors::ServicesError, reports_interval::UnixTime};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+// Daily period in which new alerts don't trigger a notification. The notification is deferred
+// until the period ends (see ReportsUpdater::flush_pending_notifications).
+// start_minute / end_minute: minutes since local midnight. The period can span midnight (start > end).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub enabled: bool,
+    pub start_minute: u32,
+    pub end_minute: u32,
+    // Of the device's time zone, to get the local time from the unix time
+    pub utc_offset_minutes: i32,
+}
+
+impl QuietHours {
+    pub fn validate(&self) -> Result<(), ServicesError> {
+        let in_day = |minute: u32| (minute as i64) < MINUTES_PER_DAY;
+        if !in_day(self.start_minute) || !in_day(self.end_minute) {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid quiet hours: {:?}",
+                self
+            )));
+        }
+        if (self.utc_offset_minutes as i64).abs() >= MINUTES_PER_DAY {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid quiet hours utc offset: {}",
+                self.utc_offset_minutes
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, time: &UnixTime) -> bool {
+        if !self.enabled || self.start_minute == self.end_minute {
+            return false;
+        }
+        let local_minute = local_minute_of_day(time, self.utc_offset_minutes);
+        let (start, end) = (self.start_minute as i64, self.end_minute as i64);
+        if start < end {
+            local_minute >= start && local_minute < end
+        } else {
+            local_minute >= start || local_minute < end
+        }
+    }
+}
+
+fn local_minute_of_day(time: &UnixTime, utc_offset_minutes: i32) -> i64 {
+    let utc_minutes = (time.value / 60) as i64;
+    (utc_minutes + utc_offset_minutes as i64).rem_euclid(MINUTES_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1591706000: 2020-06-09 12:33:20 UTC
+    const NOON_UTC: UnixTime = UnixTime { value: 1591706000 };
+
+    #[test]
+    fn test_contains_time_in_same_day_period() {
+        let quiet_hours = QuietHours {
+            enabled: true,
+            start_minute: 12 * 60,
+            end_minute: 13 * 60,
+            utc_offset_minutes: 0,
+        };
+        assert!(quiet_hours.contains(&NOON_UTC));
+    }
+
+    #[test]
+    fn test_contains_time_in_period_spanning_midnight() {
+        let quiet_hours = QuietHours {
+            enabled: true,
+            start_minute: 22 * 60,
+            end_minute: 7 * 60,
+            utc_offset_minutes: 0,
+        };
+        assert!(!quiet_hours.contains(&NOON_UTC));
+
+        // 12:33 UTC is 23:33 at UTC+11
+        let quiet_hours = QuietHours {
+            utc_offset_minutes: 11 * 60,
+            ..quiet_hours
+        };
+        assert!(quiet_hours.contains(&NOON_UTC));
+
+        // and 02:33 at UTC-10
+        let quiet_hours = QuietHours {
+            utc_offset_minutes: -10 * 60,
+            ..quiet_hours
+        };
+        assert!(quiet_hours.contains(&NOON_UTC));
+    }
+
+    #[test]
+    fn test_disabled_quiet_hours_dont_contain_time() {
+        let quiet_hours = QuietHours {
+            enabled: false,
+            start_minute: 0,
+            end_minute: MINUTES_PER_DAY as u32 - 1,
+            utc_offset_minutes: 0,
+        };
+        assert!(!quiet_hours.contains(&NOON_UTC));
+    }
+
+    #[test]
+    fn test_validate_rejects_minutes_outside_of_day() {
+        let quiet_hours = QuietHours {
+            enabled: true,
+            start_minute: 22 * 60,
+            end_minute: 24 * 60,
+            utc_offset_minutes: 0,
+        };
+        assert!(quiet_hours.validate().is_err());
+    }
+}
@@ -1,9 +1,16 @@
 use super::{
-    exposure::{Exposure, ExposureGrouper},
-    tcn_matcher::{MatchedReport, TcnMatcher},
+    exposure::{ExposureChange, ExposureGrouper, ReportExposure},
+    quiet_hours::QuietHours,
+    tcn_matcher::{DefaultTcnMatcher, MatchedReport, TcnMatcher},
 };
 use crate::{
-    database::{alert_dao::AlertDao, preferences::Preferences, tcn_dao::TcnDao},
+    config::{ClientVersion, RiskConfig},
+    database::{
+        alert_dao::{AlertDao, ProcessedReport, ReportIntervalState},
+        preferences::Preferences,
+        tcn_dao::TcnDao,
+        update_run_dao::UpdateRunDao,
+    },
     errors::{Error, ServicesError},
     extensions::Also,
     networking::{NetworkingError, TcnApi},
@@ -12,16 +19,32 @@ use crate::{
         public_symptoms::PublicSymptoms,
     },
     reports_interval, signed_report_to_bytes,
+    tcn_ext::tcn_keys::TCN_ROTATION_PERIOD_SECS,
+    tcn_recording::observed_tcn_processor::ObservedTcn,
 };
 use log::*;
+#[cfg(feature = "parallel-matching")]
+use rayon::prelude::*;
 use reports_interval::{ReportsInterval, UnixTime};
 use serde::Serialize;
-use std::{sync::Arc, time::Instant};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 use tcn::SignedReport;
 
 #[derive(Copy, Clone)]
 struct Element {}
 
+// 2 weeks of 6 hour intervals
+const MAX_RETRY_INTERVALS: usize = 56;
+
 #[derive(Debug, Serialize, PartialEq, Clone)]
 pub struct Alert {
     pub id: String,
@@ -36,13 +59,95 @@ pub struct Alert {
     
     pub min_distance: f32, // Meters
     pub avg_distance: f32, // Meters
+    // Approximations (see Exposure::measurements), less sensitive to a single noisy close sample
+    // than the min distance. The min / avg distance for alerts stored before they were recorded.
+    pub p10_distance: f32,    // Meters
+    pub median_distance: f32, // Meters
 
     pub is_read: bool,
+
+    // Whether the base64 report is stored (see AlertDao::raw_report)
+    pub has_raw_report: bool,
+
+    // When the reporter was broadcasting the report's TCNs. None for alerts stored before it was added.
+    pub report_coverage_start: Option<u64>,
+    pub report_coverage_end: Option<u64>,
+
+    pub debug_info: AlertDebugInfo,
+
+    // Unix time until which the alert is hidden (see AlertDao::snooze). None if not snoozed.
+    pub snoozed_until: Option<u64>,
+
+    // Whether a later "no symptoms" report of the reporter retracted the symptoms
+    // (see ReportsUpdater::retract_superseded_reports)
+    pub retracted: bool,
+}
+
+// Versions of the client that created the alert, for support triage.
+// None for alerts stored before they were recorded.
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct AlertDebugInfo {
+    pub core_version: Option<String>,
+    pub app_version: Option<String>,
+    // Hex prefixes of the observed TCNs the exposure was matched with (see ReportExposure::tcn_prefixes).
+    // Only recorded if enabled (recording.debug_alert_tcn_prefixes): left out of the JSON otherwise.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tcn_prefixes: Vec<String>,
+}
+
+// Alerts of a report (i.e. an encountered person), most recent exposure first.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ReportAlerts {
+    pub report_id: String,
+    pub report: PublicSymptoms,
+    pub alerts: Vec<Alert>,
+}
+
+// Overview of the (not deleted) alerts, e.g. for the home screen.
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct AlertSummary {
+    pub total: u32,
+    pub unread: u32,
+    pub last_exposure_end: Option<u64>, // None if there are no alerts
+    pub exposures_last_14_days: u32,
+}
+
+// Minutes of (not deleted) alert exposures in a time window, by distance.
+// See AlertDao::exposure_minutes for the buckets.
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct ExposureMinutes {
+    pub close: u64,
+    pub medium: u64,
+    pub far: u64,
+}
+
+// Diagnostics of a reports update run
+#[derive(Debug, Serialize, PartialEq, Clone, Default)]
+pub struct UpdateMetrics {
+    pub time: u64, // Start
+    pub duration_millis: u64,
+    pub intervals: u32,
+    pub reports: u32,
+    pub matches: u32,
+    // Intervals whose reports were rejected for exceeding the size budget. Points to a server problem.
+    pub oversized_intervals: u32,
+    // Report strings that had to be normalized to decode (see normalize_report_str)
+    pub normalized_reports: u32,
+    // Exposures that didn't create alerts because they're older than RiskConfig::max_alert_age_days
+    pub expired_exposures: u32,
+    // Reports skipped by the host app's deny or allow list (see ReportsUpdater::set_report_denylist)
+    pub filtered_reports: u32,
+    // Intervals whose reports were served unchanged since they were processed, and weren't matched again.
+    // Their reports aren't counted in reports.
+    pub unchanged_intervals: u32,
+    // Reports skipped because they couldn't be verified (see tcn_matcher::match_report)
+    pub invalid_reports: u32,
+    pub error_code: Option<String>, // None if the run succeeded
 }
 
 pub trait SignedReportExt {
     fn with_str(str: &str) -> Option<SignedReport> {
-        base64::decode(str)
+        base64::decode(normalize_report_str(str).as_ref())
             .also(|res| {
                 if let Err(error) = res {
                     error!("Error: {} decoding (base64) report: {:?}", error, res)
@@ -59,6 +164,29 @@ pub trait SignedReportExt {
 }
 impl SignedReportExt for SignedReport {}
 
+// Backend batches sometimes have whitespace around the report strings, or use the URL-safe base64 alphabet.
+// Returns the string in the standard (padded) alphabet. Borrowed if it was already.
+pub fn normalize_report_str(str: &str) -> Cow<str> {
+    let trimmed = str.trim_matches(|c: char| c.is_ascii_whitespace());
+    let is_standard = !trimmed.contains(|c: char| c == '-' || c == '_') && trimmed.len() % 4 == 0;
+    if trimmed.len() == str.len() && is_standard {
+        return Cow::Borrowed(str);
+    }
+
+    let mut normalized: String = trimmed
+        .chars()
+        .map(|c| match c {
+            '-' => '+',
+            '_' => '/',
+            c => c,
+        })
+        .collect();
+    while normalized.len() % 4 != 0 {
+        normalized.push('=');
+    }
+    Cow::Owned(normalized)
+}
+
 pub struct ReportsUpdater<
     'a,
     T: Preferences,
@@ -67,6 +195,7 @@ pub struct ReportsUpdater<
     W: TcnApi,
     X: MemoMapper,
     Y: AlertDao,
+    Z: UpdateRunDao,
 > {
     pub preferences: Arc<T>,
     pub tcn_dao: Arc<U>,
@@ -75,92 +204,439 @@ pub struct ReportsUpdater<
     pub memo_mapper: &'a X,
     pub exposure_grouper: ExposureGrouper,
     pub alert_dao: Arc<Y>,
+    pub update_run_dao: Arc<Z>,
+    // Store the matched base64 reports, for debugging
+    pub store_raw_reports: bool,
+    // Store the prefixes of the TCNs each exposure was matched with, for debugging false positives
+    pub record_tcn_prefixes: bool,
+    pub clock: fn() -> UnixTime,
+    // Recorded with the created alerts
+    pub client_version: ClientVersion,
+    // Filters the stored TCNs before matching
+    pub risk_config: RiskConfig,
+    // Replace the stored symptoms of refetched reports whose symptoms changed (see ReportsConfig)
+    pub update_report_symptoms: bool,
+    pub alerts_update_listener: Option<Arc<dyn AlertsUpdateListener>>,
+    // The running update_and_fetch_alerts, for the runtime stats
+    pub fetch_tracker: FetchTracker,
+}
+
+// Notified when the symptoms of stored alerts changed, so the apps can refresh them. The alerts returned
+// by update_and_fetch_alerts already have the new symptoms.
+pub trait AlertsUpdateListener: Send + Sync {
+    // Called after the update is stored, only if there are updated reports
+    fn alerts_updated(&self, report_ids: &[String]);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FetchInProgress {
+    pub started_at: u64, // Unix time
+    pub running_secs: u64,
+}
+
+// Start of the running fetch (0: none). With concurrent fetches, the first one's.
+#[derive(Debug, Default)]
+pub struct FetchTracker {
+    started_at: AtomicU64,
+}
+
+// Ends the tracked fetch when dropped, also if the fetch failed or panicked
+pub struct FetchGuard<'a> {
+    tracker: &'a FetchTracker,
+    started_at: Option<u64>, // None if another fetch was already tracked
+}
+
+impl FetchTracker {
+    pub fn start(&self, now: &UnixTime) -> FetchGuard {
+        let started_at = self
+            .started_at
+            .compare_exchange(0, now.value, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| now.value);
+        FetchGuard {
+            tracker: self,
+            started_at,
+        }
+    }
+
+    pub fn in_progress(&self, now: &UnixTime) -> Option<FetchInProgress> {
+        match self.started_at.load(Ordering::SeqCst) {
+            0 => None,
+            started_at => Some(FetchInProgress {
+                started_at,
+                running_secs: now.value.saturating_sub(started_at),
+            }),
+        }
+    }
+}
+
+impl Drop for FetchGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(started_at) = self.started_at {
+            let _ = self.tracker.started_at.compare_exchange(
+                started_at,
+                0,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+        }
+    }
 }
 
-impl<'a, T, U, V, W, X, Y> ReportsUpdater<'a, T, U, V, W, X, Y>
+impl<'a, T, U, V, W, X, Y, Z> ReportsUpdater<'a, T, U, V, W, X, Y, Z>
 where
     T: Preferences,
     U: TcnDao,
     V: TcnMatcher,
     W: TcnApi,
-    X: MemoMapper,
+    X: MemoMapper + Sync,
     Y: AlertDao,
+    Z: UpdateRunDao,
 {
     pub fn update_and_fetch_alerts(&self) -> Result<Vec<Alert>, ServicesError> {
-        self.update_alerts()?;
+        let start_time = Instant::now();
+        let now: UnixTime = (self.clock)();
+        let _fetch_guard = self.fetch_tracker.start(&now);
+        let mut metrics = UpdateMetrics {
+            time: now.value,
+            ..UpdateMetrics::default()
+        };
+
+        let invalid_reports_before = self.tcn_matcher.invalid_reports();
+
+        let update_res = self.update_alerts(&now, &mut metrics);
+
+        metrics.invalid_reports = self
+            .tcn_matcher
+            .invalid_reports()
+            .saturating_sub(invalid_reports_before) as u32;
+        metrics.duration_millis = start_time.elapsed().as_millis() as u64;
+        metrics.error_code = update_res.as_ref().err().map(|e| e.code().to_owned());
+        // Diagnostics only: not being able to store them doesn't affect the update
+        if let Err(error) = self.update_run_dao.save(&metrics) {
+            error!("Couldn't save update metrics: {:?}", error);
+        }
+
+        update_res?;
         self.alert_dao.all()
     }
 
-    fn update_alerts(&self) -> Result<(), ServicesError> {
-        let new_alerts = self.fetch_new_reports()?;
-        self.alert_dao.save(new_alerts)
+    // The base64 report of the alerts with the report id, for debugging.
+    // Err(General) if raw reports aren't stored (see store_raw_reports).
+    pub fn raw_report(&self, report_id: String) -> Result<String, ServicesError> {
+        if !self.store_raw_reports {
+            return Err(ServicesError::General(
+                "Raw reports aren't stored".to_owned(),
+            ));
+        }
+        self.alert_dao.raw_report(report_id)
     }
 
-    fn fetch_new_reports(&self) -> Result<Vec<Alert>, ServicesError> {
-        self.retrieve_and_match_new_reports().map(|signed_reports| {
-            signed_reports
-                .into_iter()
-                .filter_map(|matched_report| self.to_ffi_alerts(matched_report).ok())
-                .flatten()
-                .collect()
-        })
+    pub fn update_history(&self) -> Result<Vec<UpdateMetrics>, ServicesError> {
+        self.update_run_dao.all()
     }
 
-    // Note: For now we will not create an FFI layer to handle JSON conversions, since it may be possible
-    // to use directly the data structures.
-    fn to_ffi_alerts(&self, matched_report: MatchedReport) -> Result<Vec<Alert>, ServicesError> {
-        let exposures = self.exposure_grouper.group(matched_report.clone().tcns);
+    pub fn set_quiet_hours(&self, quiet_hours: QuietHours) -> Result<(), ServicesError> {
+        quiet_hours.validate()?;
+        self.preferences.set_quiet_hours(quiet_hours);
+        Ok(())
+    }
 
-        exposures
-            .into_iter()
-            .map(|exposure_tcns| self.to_alert(matched_report.report.clone(), exposure_tcns))
-            .collect()
+    // sigs_json: JSON array of base64 signature prefixes, verified by the host app (e.g. revoked reports).
+    // Reports whose signature starts with one of them aren't matched. Takes precedence over the allowlist.
+    // Alerts already created for them aren't deleted, but expire when the reports aren't seen anymore.
+    pub fn set_report_denylist(&self, sigs_json: &str) -> Result<(), ServicesError> {
+        let prefixes = signature_prefixes(sigs_json)?;
+        debug!("Setting report denylist: {:?}", prefixes);
+        self.preferences.set_report_denylist(prefixes);
+        Ok(())
+    }
+
+    // sigs_json: like set_report_denylist. If not empty, only the reports whose signature starts with
+    // one of the prefixes are matched.
+    pub fn set_report_allowlist(&self, sigs_json: &str) -> Result<(), ServicesError> {
+        let prefixes = signature_prefixes(sigs_json)?;
+        debug!("Setting report allowlist: {:?}", prefixes);
+        self.preferences.set_report_allowlist(prefixes);
+        Ok(())
+    }
+
+    // Whether the user should be notified now about alerts stored since the last notification.
+    // Returns false during quiet hours: the notification stays pending until a call outside of them.
+    // Call after each update and when the quiet hours end (alerts are stored regardless).
+    pub fn flush_pending_notifications(&self) -> bool {
+        if !self.preferences.pending_alert_notification() {
+            return false;
+        }
+        let now = (self.clock)();
+        let in_quiet_hours = self
+            .preferences
+            .quiet_hours()
+            .map(|quiet_hours| quiet_hours.contains(&now))
+            .unwrap_or(false);
+        if in_quiet_hours {
+            debug!("In quiet hours: deferring alerts notification");
+            return false;
+        }
+        self.preferences.set_pending_alert_notification(false);
+        true
     }
 
-    // Creates a new alert, corresponding to an exposure
-    fn to_alert(
+    fn update_alerts(
         &self,
-        signed_report: SignedReport,
-        exposure: Exposure,
-    ) -> Result<Alert, ServicesError> {
-        let report = signed_report.clone().verify()?;
+        now: &UnixTime,
+        metrics: &mut UpdateMetrics,
+    ) -> Result<(), ServicesError> {
+        let chunks = self.retrieve_and_match_new_reports(now, metrics)?;
+        let processed_reports = to_processed_reports(&chunks);
+        let interval_states: Vec<ReportIntervalState> = chunks
+            .iter()
+            .filter_map(|chunk| chunk.interval_state.clone())
+            .collect();
+        let fetch_error = chunks.iter().find_map(|chunk| match &chunk.fetch_outcome {
+            FetchOutcome::Failed(error) => Some(error.clone()),
+            _ => None,
+        });
+        let matched_reports: Vec<MatchedReport> =
+            chunks.into_iter().flat_map(|chunk| chunk.matched).collect();
+        if self.store_raw_reports {
+            self.save_raw_reports(&matched_reports);
+        }
+        let (retractions, matched_reports) = self.split_retractions(matched_reports);
+        // Retractions last: a TCN keeps the first report that matched it
+        self.mark_matched_tcns(&matched_reports);
+        self.mark_matched_tcns(&retractions);
+        let matched_reports = self.without_dismissed_reports(matched_reports)?;
+
+        let stored_exposures = matched_reports
+            .iter()
+            .map(|matched_report| self.alert_dao.exposures(&report_id(&matched_report.report)))
+            .collect::<Result<Vec<Vec<ReportExposure>>, ServicesError>>()?;
+        let oldest_contact_end = self.risk_config.oldest_alert_contact_end(now);
+        let update = to_alerts_update(
+            matched_reports,
+            stored_exposures,
+            &self.exposure_grouper,
+            self.memo_mapper,
+            &self.client_version,
+            self.record_tcn_prefixes,
+            oldest_contact_end,
+        );
+        metrics.expired_exposures = update.expired_exposures;
+
+        let outcome = self.alert_dao.save(update.new_alerts)?;
+        info!("Saved {} new alerts", outcome.saved);
+        if outcome.saved > 0 {
+            // Notified by flush_pending_notifications, which defers it during quiet hours
+            self.preferences.set_pending_alert_notification(true);
+        }
+        if !outcome.failed.is_empty() {
+            // The failed alerts are lost: the reports interval is marked as completed.
+            error!(
+                "Couldn't save {} alerts: {:?}",
+                outcome.failed.len(),
+                outcome.failed
+            );
+        }
+        self.alert_dao
+            .update_measurements(&update.extended_alerts)?;
+        info!("Extended {} alerts", update.extended_alerts.len());
+        // After saving the alerts: the symptoms of their new reports are stored as decoded
+        let updated_reports = if self.update_report_symptoms {
+            self.alert_dao
+                .update_report_symptoms(&update.report_symptoms, now)?
+        } else {
+            vec![]
+        };
+        if !updated_reports.is_empty() {
+            info!("Updated the symptoms of {} reports", updated_reports.len());
+        }
+
+        // Not stored for failed alerts, so the exposures match the alerts
+        let failed_ids: HashSet<&String> = outcome.failed.iter().map(|(id, _)| id).collect();
+        let exposures = update
+            .exposures
+            .into_iter()
+            .filter(|exposure| !failed_ids.contains(&exposure.alert_id))
+            .collect();
+        self.alert_dao.save_exposures(exposures)?;
+        // After saving the alerts: the retracted report can be in the same update
+        self.retract_superseded_reports(&retractions)?;
+        // Recorded after the alerts, so the reports of a failed update are matched again
+        self.alert_dao.save_processed_reports(processed_reports)?;
+        self.alert_dao
+            .save_report_interval_states(interval_states)?;
+
+        // Also applies a window tightened since the alerts were created
+        let deleted = self.alert_dao.delete_ended_before(oldest_contact_end)?;
+        if deleted > 0 {
+            info!("Deleted {} expired alerts", deleted);
+        }
+        self.expire_unseen_reports(now)?;
+
+        if let Some(listener) = &self.alerts_update_listener {
+            if !updated_reports.is_empty() {
+                listener.alerts_updated(&updated_reports);
+            }
+        }
+
+        // The reports of the fetched intervals were processed: the failed ones are fetched again in the next runs.
+        match fetch_error {
+            Some(error) => Err(ServicesError::from(error)),
+            None => Ok(()),
+        }
+    }
 
-        let public_symptoms = self.memo_mapper.to_report(Memo {
-            bytes: report.memo_data().to_vec(),
+    // The backend stops serving reports after a while, e.g. because they were revoked or are too old.
+    // Alerts of reports that weren't seen for RiskConfig::report_expiry_days are deleted once they're
+    // not relevant anymore.
+    fn expire_unseen_reports(&self, now: &UnixTime) -> Result<(), ServicesError> {
+        let oldest_last_seen = self.risk_config.oldest_report_last_seen(now);
+        let expired = self.alert_dao.expire_unseen_reports(
+            oldest_last_seen,
+            self.risk_config.oldest_expired_report_contact_end(now),
+        )?;
+        if expired > 0 {
+            info!("Deleted {} alerts of expired reports", expired);
+        }
+
+        // The exposures of a report ended before it was seen. Once they're older than the max alert age,
+        // their alerts are deleted anyway (see delete_ended_before): the report isn't needed anymore.
+        let oldest_needed_last_seen = self.risk_config.oldest_alert_contact_end(&UnixTime {
+            value: oldest_last_seen,
         });
+        self.alert_dao
+            .delete_processed_reports_seen_before(oldest_needed_last_seen)?;
+        Ok(())
+    }
 
-        let measurements = exposure.measurements();
-        let report_sig_bytes: [u8; 64] = signed_report.sig.into();
-
-        Ok(Alert {
-            id: format!(
-                "{}-{}",
-                hex::encode(report_sig_bytes.to_vec()),
-                measurements.contact_start.value
-            ),
-            report_id: format!("{:?}", signed_report.sig),
-            symptoms: public_symptoms,
-            contact_start: measurements.contact_start.value,
-            contact_end: measurements.contact_end.value,
-            min_distance: measurements.min_distance,
-            avg_distance: measurements.avg_distance,
-            is_read: false,
+    // Separates the "no symptoms" reports (see PublicSymptoms::is_retraction), which don't create alerts.
+    // Returns the retractions and the other reports.
+    fn split_retractions(
+        &self,
+        matched_reports: Vec<MatchedReport>,
+    ) -> (Vec<MatchedReport>, Vec<MatchedReport>) {
+        matched_reports.into_iter().partition(|matched_report| {
+            self.memo_mapper
+                .to_report(Memo {
+                    bytes: matched_report.memo.clone(),
+                })
+                .is_retraction()
         })
     }
 
-    fn retrieve_and_match_new_reports(&self) -> Result<Vec<MatchedReport>, ServicesError> {
-        let now: UnixTime = UnixTime::now();
+    // A "no symptoms" report retracts the earlier reports that matched the same TCNs. TCNs are derived from
+    // the report verification key, so reports sharing TCNs were signed by the same reporter.
+    // Their alerts are kept, flagged as retracted.
+    fn retract_superseded_reports(
+        &self,
+        retractions: &[MatchedReport],
+    ) -> Result<(), ServicesError> {
+        for retraction in retractions {
+            let retraction_id = report_id(&retraction.report);
+            let report_time = self
+                .memo_mapper
+                .to_report(Memo {
+                    bytes: retraction.memo.clone(),
+                })
+                .report_time;
+            let tcns = retraction.tcns.iter().map(|tcn| tcn.tcn.clone()).collect();
+            let report_ids: Vec<String> = self
+                .tcn_dao
+                .matched_report_ids(tcns)?
+                .into_iter()
+                .filter(|report_id| *report_id != retraction_id)
+                .collect();
+            let retracted =
+                self.alert_dao
+                    .retract_reports(&report_ids, &retraction_id, report_time)?;
+            if retracted > 0 {
+                info!("Report: {} retracted {} reports", retraction_id, retracted);
+            }
+        }
+        Ok(())
+    }
+
+    // Exposures of reports the user dismissed (see AlertDao::delete_by_report) don't create alerts
+    fn without_dismissed_reports(
+        &self,
+        matched_reports: Vec<MatchedReport>,
+    ) -> Result<Vec<MatchedReport>, ServicesError> {
+        let mut not_dismissed = vec![];
+        for matched_report in matched_reports {
+            let report_id = report_id(&matched_report.report);
+            if self.alert_dao.is_report_dismissed(&report_id)? {
+                debug!("Ignoring match of dismissed report: {}", report_id);
+            } else {
+                not_dismissed.push(matched_report);
+            }
+        }
+        Ok(not_dismissed)
+    }
 
-        let matching_reports = self.matching_reports(self.determine_start_interval(&now), &now);
+    // The matched flags are only statistics, so errors are logged and don't interrupt the update.
+    fn mark_matched_tcns(&self, matched_reports: &[MatchedReport]) {
+        for matched_report in matched_reports {
+            let tcns = matched_report
+                .tcns
+                .iter()
+                .map(|tcn| tcn.tcn.clone())
+                .collect();
+            let res = self
+                .tcn_dao
+                .mark_matched(tcns, &report_id(&matched_report.report));
+            if let Err(error) = res {
+                error!("Couldn't mark matched TCNs: {:?}", error);
+            }
+        }
+    }
 
-        if let Ok(matching_reports) = &matching_reports {
-            let intervals = matching_reports.iter().map(|c| c.interval).collect();
-            self.store_last_completed_interval(intervals, &now);
-        };
+    // Raw reports are only a debugging aid, so errors are logged and don't interrupt the update.
+    fn save_raw_reports(&self, matched_reports: &[MatchedReport]) {
+        for matched_report in matched_reports {
+            let report_str = base64::encode(signed_report_to_bytes(matched_report.report.clone()));
+            let res = self
+                .alert_dao
+                .save_raw_report(report_id(&matched_report.report), report_str);
+            if let Err(error) = res {
+                error!("Couldn't save raw report: {:?}", error);
+            }
+        }
+    }
 
-        matching_reports
-            .map(|chunks| chunks.into_iter().flat_map(|chunk| chunk.matched).collect())
-            .map_err(ServicesError::from)
+    fn retrieve_and_match_new_reports(
+        &self,
+        now: &UnixTime,
+        metrics: &mut UpdateMetrics,
+    ) -> Result<Vec<MatchedReportsChunk>, ServicesError> {
+        let start_interval = self.determine_start_interval(now);
+        let retry_intervals = self.preferences.reports_intervals_to_retry();
+        let chunks = self.matching_reports(start_interval, retry_intervals.clone(), now)?;
+
+        self.store_fetch_progress(&chunks, start_interval, retry_intervals, now);
+
+        let fetched: Vec<&MatchedReportsChunk> = chunks
+            .iter()
+            .filter(|chunk| !chunk.fetch_outcome.is_failed())
+            .collect();
+        metrics.intervals = fetched.len() as u32;
+        metrics.reports = fetched
+            .iter()
+            .map(|c| c.reports.len() as u32 + c.filtered_reports)
+            .sum();
+        metrics.matches = fetched.iter().map(|c| c.matched.len() as u32).sum();
+        metrics.normalized_reports = fetched.iter().map(|c| c.normalized_reports).sum();
+        metrics.filtered_reports = fetched.iter().map(|c| c.filtered_reports).sum();
+        metrics.unchanged_intervals = fetched.iter().filter(|c| c.is_unchanged).count() as u32;
+        metrics.oversized_intervals = chunks
+            .iter()
+            .filter(|chunk| match &chunk.fetch_outcome {
+                FetchOutcome::Failed(error) => error.is_payload_too_large(),
+                _ => false,
+            })
+            .count() as u32;
+
+        Ok(chunks)
     }
 
     fn retrieve_last_completed_interval(&self) -> Option<ReportsInterval> {
@@ -173,104 +649,221 @@ where
             "Determining start reports interval. Last completed interval: {:?}",
             last
         );
-        let next = last.map(|interval| interval.next());
-        debug!("Next interval: {:?}", next);
-        let result = next.unwrap_or_else(|| ReportsInterval::create_for_with_default_length(time));
+        let result = ReportsInterval::start_interval(last, time);
         debug!("Interval to fetch: {:?}", result);
         result
     }
 
+    // The intervals to retry (before start_interval), followed by the sequence from start_interval until now.
+    // Only matching errors are returned: fetch errors are recorded in the chunks.
     fn matching_reports(
         &self,
         start_interval: ReportsInterval,
+        retry_intervals: Vec<ReportsInterval>,
         until: &UnixTime,
     ) -> Result<Vec<MatchedReportsChunk>, ServicesError> {
-        let sequence = Self::generate_intervals_sequence(start_interval, until);
-        let reports = sequence.map(|interval| self.retrieve_reports(interval));
-        let matched_results = reports.map(|interval| self.match_retrieved_reports_result(interval));
-        matched_results
+        let sequence = ReportsInterval::sequence(start_interval, until);
+        let intervals = retry_intervals
             .into_iter()
+            .filter(|interval| interval.start() < start_interval.start())
+            .chain(sequence);
+        let filter = ReportFilter {
+            denylist: self.preferences.report_denylist(),
+            allowlist: self.preferences.report_allowlist(),
+        };
+        intervals
+            .map(|interval| self.retrieve_reports(interval))
+            .map(|chunk| self.to_matched_reports_chunk(chunk, &filter))
             .collect::<Result<Vec<MatchedReportsChunk>, ServicesError>>()
-            .map_err(ServicesError::from)
     }
 
-    fn generate_intervals_sequence(
-        from: ReportsInterval,
-        until: &UnixTime,
-    ) -> impl Iterator<Item = ReportsInterval> + '_ {
-        std::iter::successors(Some(from), |item| Some(item.next()))
-            .take_while(move |item| item.starts_before(until))
+    fn retrieve_reports(&self, interval: ReportsInterval) -> SignedReportsChunk {
+        match self.api.get_reports(interval.number, interval.length) {
+            Ok(report_strings) => SignedReportsChunk {
+                payload_hash: if report_strings.is_empty() {
+                    None
+                } else {
+                    Some(payload_hash(&report_strings))
+                },
+                normalized_reports: report_strings
+                    .iter()
+                    .filter(|report_string| {
+                        matches!(normalize_report_str(report_string), Cow::Owned(_))
+                    })
+                    .count() as u32,
+                fetch_outcome: if report_strings.is_empty() {
+                    FetchOutcome::Empty
+                } else {
+                    FetchOutcome::Fetched
+                },
+                reports: report_strings
+                    .into_iter()
+                    .filter_map(|report_string| {
+                        SignedReport::with_str(&report_string).also(|res| {
+                            if res.is_none() {
+                                error!("Failed to convert report string: $it to report");
+                            }
+                        })
+                    })
+                    .collect(),
+                interval,
+            },
+            Err(error) => {
+                error!(
+                    "Couldn't fetch reports of interval: {:?}, error: {:?}",
+                    interval, error
+                );
+                SignedReportsChunk {
+                    reports: vec![],
+                    interval,
+                    fetch_outcome: FetchOutcome::Failed(error),
+                    normalized_reports: 0,
+                    payload_hash: None,
+                }
+            }
+        }
     }
 
-    fn retrieve_reports(
+    /**
+     * Maps reports chunk to a new chunk containing possible matches.
+     */
+    fn to_matched_reports_chunk(
         &self,
-        interval: ReportsInterval,
-    ) -> Result<SignedReportsChunk, NetworkingError> {
-        let reports_strings_result: Result<Vec<String>, NetworkingError> =
-            self.api.get_reports(interval.number, interval.length);
+        chunk: SignedReportsChunk,
+        filter: &ReportFilter,
+    ) -> Result<MatchedReportsChunk, ServicesError> {
+        let tcns = if chunk.reports.is_empty() {
+            vec![]
+        } else {
+            self.tcns_to_match()?
+        };
+        // Some backends don't support ETags, but serve the same batch until the interval is completed.
+        // If neither the batch nor what it's matched with changed, the reports aren't verified and matched
+        // again: the result would be the same.
+        let interval_state = chunk
+            .payload_hash
+            .as_ref()
+            .map(|payload_hash| ReportIntervalState {
+                interval_number: chunk.interval.number,
+                interval_length: chunk.interval.length,
+                payload_hash: payload_hash.clone(),
+                inputs_hash: inputs_hash(&tcns, filter),
+            });
+        if let Some(state) = &interval_state {
+            let stored_state = self
+                .alert_dao
+                .report_interval_state(state.interval_number, state.interval_length)?;
+            if stored_state.as_ref() == Some(state) {
+                debug!(
+                    "Skipping unchanged reports of interval: {:?}",
+                    chunk.interval
+                );
+                return Ok(MatchedReportsChunk {
+                    reports: vec![],
+                    matched: vec![],
+                    interval: chunk.interval,
+                    fetch_outcome: chunk.fetch_outcome,
+                    normalized_reports: chunk.normalized_reports,
+                    filtered_reports: 0,
+                    content_hash: String::new(),
+                    interval_state: None,
+                    is_unchanged: true,
+                });
+            }
+        }
 
-        reports_strings_result.map(|report_strings| SignedReportsChunk {
-            reports: report_strings
+        let fetched_count = chunk.reports.len();
+        // Filtered reports aren't processed either: they're not seen for expiry purposes
+        let chunk = SignedReportsChunk {
+            reports: chunk
+                .reports
                 .into_iter()
-                .filter_map(|report_string| {
-                    SignedReport::with_str(&report_string).also(|res| {
-                        if res.is_none() {
-                            error!("Failed to convert report string: $it to report");
-                        }
-                    })
-                })
+                .filter(|report| filter.allows(report))
                 .collect(),
-            interval,
+            ..chunk
+        };
+        let filtered_reports = (fetched_count - chunk.reports.len()) as u32;
+        if filtered_reports > 0 {
+            debug!(
+                "Skipping {} reports filtered by the host app in interval: {:?}",
+                filtered_reports, chunk.interval
+            );
+        }
+        let content_hash = content_hash(&chunk.reports, &tcns);
+        let unprocessed = self.unprocessed_reports(&chunk, &content_hash)?;
+        let matches = if unprocessed.is_empty() {
+            vec![]
+        } else {
+            self.find_matches(tcns, unprocessed)?
+        };
+        Ok(MatchedReportsChunk {
+            reports: chunk.reports,
+            matched: matches,
+            interval: chunk.interval,
+            fetch_outcome: chunk.fetch_outcome,
+            normalized_reports: chunk.normalized_reports,
+            filtered_reports,
+            content_hash,
+            interval_state,
+            is_unchanged: false,
         })
     }
 
-    fn match_retrieved_reports_result(
+    // Intervals are fetched until they're completed, usually with the same reports each time. If neither
+    // the reports of the interval nor the TCNs changed since the reports were processed, they aren't
+    // matched again: the result would be the same.
+    fn unprocessed_reports(
         &self,
-        reports_result: Result<SignedReportsChunk, NetworkingError>,
-    ) -> Result<MatchedReportsChunk, ServicesError> {
-        reports_result
-            .map_err(ServicesError::from)
-            .and_then(|chunk| self.to_matched_reports_chunk(&chunk))
+        chunk: &SignedReportsChunk,
+        content_hash: &str,
+    ) -> Result<Vec<SignedReport>, ServicesError> {
+        let mut unprocessed = vec![];
+        for report in &chunk.reports {
+            let processed = self.alert_dao.processed_report(&report_id(report))?;
+            let is_processed = processed.map_or(false, |processed| {
+                processed.last_seen_interval == chunk.interval.number
+                    && processed.interval_length == chunk.interval.length
+                    && processed.content_hash == content_hash
+            });
+            if !is_processed {
+                unprocessed.push(report.clone());
+            }
+        }
+        if unprocessed.len() < chunk.reports.len() {
+            debug!(
+                "Skipping {} reports already processed in interval: {:?}",
+                chunk.reports.len() - unprocessed.len(),
+                chunk.interval
+            );
+        }
+        Ok(unprocessed)
     }
 
-    /**
-     * Maps reports chunk to a new chunk containing possible matches.
-     */
-    fn to_matched_reports_chunk(
-        &self,
-        chunk: &SignedReportsChunk,
-    ) -> Result<MatchedReportsChunk, ServicesError> {
-        self.find_matches(chunk.reports.clone())
-            .map(|matches| MatchedReportsChunk {
-                reports: chunk.reports.clone(),
-                matched: matches,
-                interval: chunk.interval.clone(),
+    fn tcns_to_match(&self) -> Result<Vec<ObservedTcn>, ServicesError> {
+        self.tcn_dao
+            .all()
+            .map(|tcns| self.filter_by_risk_config(tcns))
+            .map_err(|error| {
+                error!("Couldn't load TCNs to match: ({:?})", error);
+                error
             })
-            .map_err(ServicesError::from)
     }
 
     fn find_matches(
         &self,
+        tcns: Vec<ObservedTcn>,
         reports: Vec<SignedReport>,
     ) -> Result<Vec<MatchedReport>, ServicesError> {
         let matching_start_time = Instant::now();
 
         info!("R Start matching...");
 
-        let tcns = self.tcn_dao.all();
-
-        if let Ok(tcns) = &tcns {
-            let tcns_for_debugging: Vec<String> = tcns
-                .clone()
-                .into_iter()
-                .map(|tcn| hex::encode(tcn.tcn.0))
-                .collect();
-
-            info!("R DB TCNs: {:?}", tcns_for_debugging);
-        }
+        let tcns_for_debugging: Vec<String> =
+            tcns.iter().map(|tcn| hex::encode(tcn.tcn.0)).collect();
+        info!("R DB TCNs: {:?}", tcns_for_debugging);
 
         let matched_reports: Result<Vec<MatchedReport>, ServicesError> =
-            tcns.and_then(|tcns| self.tcn_matcher.match_reports(tcns, reports));
+            self.tcn_matcher.match_reports(tcns, reports);
 
         let time = matching_start_time.elapsed().as_secs();
         info!("Took {:?}s to match reports", time);
@@ -295,8 +888,70 @@ where
         matched_reports
     }
 
-    fn store_last_completed_interval(&self, intervals: Vec<ReportsInterval>, now: &UnixTime) {
-        let interval = ReportsInterval::interval_ending_before(intervals.clone(), now);
+    // Applied before matching, so TCNs that don't count toward an exposure aren't flagged as matched either.
+    fn filter_by_risk_config(&self, tcns: Vec<ObservedTcn>) -> Vec<ObservedTcn> {
+        let count = tcns.len();
+        let filtered: Vec<ObservedTcn> = tcns
+            .into_iter()
+            .filter(|tcn| self.risk_config.counts_toward_exposure(tcn))
+            .collect();
+        if filtered.len() < count {
+            debug!(
+                "Ignoring {} TCN observations below the risk thresholds",
+                count - filtered.len()
+            );
+        }
+        filtered
+    }
+
+    // Only fetched (possibly empty) intervals advance the last completed interval. Failed intervals
+    // it moves past are queued to be retried; the ones after it are fetched again anyway.
+    fn store_fetch_progress(
+        &self,
+        chunks: &[MatchedReportsChunk],
+        start_interval: ReportsInterval,
+        retry_intervals: Vec<ReportsInterval>,
+        now: &UnixTime,
+    ) {
+        // Retried intervals are before start_interval: they never move the last completed interval back.
+        let completed: Vec<ReportsInterval> = chunks
+            .iter()
+            .filter(|chunk| {
+                !chunk.fetch_outcome.is_failed() && chunk.interval.start() >= start_interval.start()
+            })
+            .map(|chunk| chunk.interval)
+            .collect();
+        self.store_last_completed_interval(&completed, start_interval.length, now);
+
+        let last_completed = self.retrieve_last_completed_interval();
+        let mut to_retry: Vec<ReportsInterval> = chunks
+            .iter()
+            .filter(|chunk| chunk.fetch_outcome.is_failed())
+            .map(|chunk| chunk.interval)
+            .filter(|interval| {
+                last_completed.map_or(false, |last| interval.start() <= last.start())
+            })
+            .collect();
+        if to_retry.len() > MAX_RETRY_INTERVALS {
+            warn!(
+                "Too many intervals to retry: {}, dropping the oldest",
+                to_retry.len()
+            );
+            to_retry.drain(..to_retry.len() - MAX_RETRY_INTERVALS);
+        }
+        if to_retry != retry_intervals {
+            debug!("Storing reports intervals to retry: {:?}", to_retry);
+            self.preferences.set_reports_intervals_to_retry(to_retry);
+        }
+    }
+
+    fn store_last_completed_interval(
+        &self,
+        intervals: &[ReportsInterval],
+        length: u64,
+        now: &UnixTime,
+    ) {
+        let interval = ReportsInterval::interval_ending_before(intervals, length, now);
         debug!(
             "Storing last completed reports interval: {:?}, for intervals: {:?}",
             interval, intervals
@@ -309,47 +964,2171 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
-struct MatchedReportsChunk {
-    reports: Vec<SignedReport>,
-    matched: Vec<MatchedReport>,
-    interval: ReportsInterval,
+// Collaborators without a sensible default are passed to new(), the rest can be overridden.
+pub struct ReportsUpdaterBuilder<
+    'a,
+    T: Preferences,
+    U: TcnDao,
+    V: TcnMatcher,
+    W: TcnApi,
+    X: MemoMapper,
+    Y: AlertDao,
+    Z: UpdateRunDao,
+> {
+    preferences: Arc<T>,
+    tcn_dao: Arc<U>,
+    tcn_matcher: V,
+    api: &'a W,
+    memo_mapper: &'a X,
+    exposure_grouper: ExposureGrouper,
+    alert_dao: Arc<Y>,
+    update_run_dao: Arc<Z>,
+    store_raw_reports: bool,
+    record_tcn_prefixes: bool,
+    clock: fn() -> UnixTime,
+    client_version: ClientVersion,
+    risk_config: RiskConfig,
+    update_report_symptoms: bool,
+    alerts_update_listener: Option<Arc<dyn AlertsUpdateListener>>,
 }
 
-#[derive(Debug, Clone)]
-struct SignedReportsChunk {
-    reports: Vec<SignedReport>,
-    interval: ReportsInterval,
+impl<'a, T, U, W, X, Y, Z> ReportsUpdaterBuilder<'a, T, U, DefaultTcnMatcher, W, X, Y, Z>
+where
+    T: Preferences,
+    U: TcnDao,
+    W: TcnApi,
+    X: MemoMapper,
+    Y: AlertDao,
+    Z: UpdateRunDao,
+{
+    pub fn new(
+        preferences: Arc<T>,
+        tcn_dao: Arc<U>,
+        api: &'a W,
+        memo_mapper: &'a X,
+        alert_dao: Arc<Y>,
+        update_run_dao: Arc<Z>,
+    ) -> ReportsUpdaterBuilder<'a, T, U, DefaultTcnMatcher, W, X, Y, Z> {
+        ReportsUpdaterBuilder {
+            preferences,
+            tcn_dao,
+            tcn_matcher: DefaultTcnMatcher::new(),
+            api,
+            memo_mapper,
+            exposure_grouper: ExposureGrouper::default(),
+            alert_dao,
+            update_run_dao,
+            store_raw_reports: false,
+            record_tcn_prefixes: false,
+            clock: UnixTime::now,
+            client_version: ClientVersion::default(),
+            risk_config: RiskConfig::default(),
+            update_report_symptoms: true,
+            alerts_update_listener: None,
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    // Utility to see quickly all TCNs (hex) for a report
-    #[test]
-    #[ignore]
-    fn print_tcns_for_report() {
-        let report_str = "rOFMgzy3y36MJns34Xj7EZu5Dti9XMhYGRpa/DVznep6q4hMtMYm9sYMg9+sRSHAj0Ff2rHTPXskuzJH0+pZMQEAAgAAFAEAnazaXgAAAAD//////////wMAMFLrKLNOvwUJQSNta9rlzTyjFdpfq25Kv34c6y+ZOoSzRewzNAWsd56Yzm8LUw9cpHB8yyzDUMJ9YTKhD8dADA==";
-        let report = SignedReport::with_str(report_str).unwrap();
-        info!("{:?}", report);
-        for tcn in report.verify().unwrap().temporary_contact_numbers() {
-            info!("{}", hex::encode(tcn.0));
+impl<'a, T, U, V, W, X, Y, Z> ReportsUpdaterBuilder<'a, T, U, V, W, X, Y, Z>
+where
+    T: Preferences,
+    U: TcnDao,
+    V: TcnMatcher,
+    W: TcnApi,
+    X: MemoMapper,
+    Y: AlertDao,
+    Z: UpdateRunDao,
+{
+    pub fn tcn_matcher<M: TcnMatcher>(
+        self,
+        tcn_matcher: M,
+    ) -> ReportsUpdaterBuilder<'a, T, U, M, W, X, Y, Z> {
+        ReportsUpdaterBuilder {
+            preferences: self.preferences,
+            tcn_dao: self.tcn_dao,
+            tcn_matcher,
+            api: self.api,
+            memo_mapper: self.memo_mapper,
+            exposure_grouper: self.exposure_grouper,
+            alert_dao: self.alert_dao,
+            update_run_dao: self.update_run_dao,
+            store_raw_reports: self.store_raw_reports,
+            record_tcn_prefixes: self.record_tcn_prefixes,
+            clock: self.clock,
+            client_version: self.client_version,
+            risk_config: self.risk_config,
+            update_report_symptoms: self.update_report_symptoms,
+            alerts_update_listener: self.alerts_update_listener,
         }
     }
 
-    #[test]
-    fn test_report_empty_is_none() {
-        assert!(SignedReport::with_str("").is_none())
+    pub fn exposure_grouper(mut self, exposure_grouper: ExposureGrouper) -> Self {
+        self.exposure_grouper = exposure_grouper;
+        self
     }
 
-    #[test]
-    fn test_report_base64_invalid_is_none() {
-        assert!(SignedReport::with_str("%~=-🥳").is_none())
+    pub fn store_raw_reports(mut self, store_raw_reports: bool) -> Self {
+        self.store_raw_reports = store_raw_reports;
+        self
     }
 
-    #[test]
-    fn test_report_base64_valid_report_invalid_is_none() {
-        assert!(SignedReport::with_str("slkdjfslfd").is_none())
+    pub fn record_tcn_prefixes(mut self, record_tcn_prefixes: bool) -> Self {
+        self.record_tcn_prefixes = record_tcn_prefixes;
+        self
+    }
+
+    pub fn clock(mut self, clock: fn() -> UnixTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn client_version(mut self, client_version: ClientVersion) -> Self {
+        self.client_version = client_version;
+        self
+    }
+
+    pub fn risk_config(mut self, risk_config: RiskConfig) -> Self {
+        self.risk_config = risk_config;
+        self
+    }
+
+    pub fn update_report_symptoms(mut self, update_report_symptoms: bool) -> Self {
+        self.update_report_symptoms = update_report_symptoms;
+        self
+    }
+
+    pub fn alerts_update_listener(mut self, listener: Arc<dyn AlertsUpdateListener>) -> Self {
+        self.alerts_update_listener = Some(listener);
+        self
+    }
+
+    pub fn build(self) -> ReportsUpdater<'a, T, U, V, W, X, Y, Z> {
+        ReportsUpdater {
+            preferences: self.preferences,
+            tcn_dao: self.tcn_dao,
+            tcn_matcher: self.tcn_matcher,
+            api: self.api,
+            memo_mapper: self.memo_mapper,
+            exposure_grouper: self.exposure_grouper,
+            alert_dao: self.alert_dao,
+            update_run_dao: self.update_run_dao,
+            store_raw_reports: self.store_raw_reports,
+            record_tcn_prefixes: self.record_tcn_prefixes,
+            clock: self.clock,
+            client_version: self.client_version,
+            risk_config: self.risk_config,
+            update_report_symptoms: self.update_report_symptoms,
+            alerts_update_listener: self.alerts_update_listener,
+            fetch_tracker: FetchTracker::default(),
+        }
+    }
+}
+
+// Converts the matched reports to alerts in parallel. Alerts are sorted by id, so the result is deterministic.
+// Doesn't use persisted exposures: every exposure creates a new alert.
+pub fn to_alerts<T: MemoMapper + Sync>(
+    matched_reports: Vec<MatchedReport>,
+    exposure_grouper: &ExposureGrouper,
+    memo_mapper: &T,
+    client_version: &ClientVersion,
+) -> Vec<Alert> {
+    let stored_exposures = matched_reports.iter().map(|_| vec![]).collect();
+    to_alerts_update(
+        matched_reports,
+        stored_exposures,
+        exposure_grouper,
+        memo_mapper,
+        client_version,
+        false,
+        0,
+    )
+    .new_alerts
+}
+
+// Result of merging matched reports into their persisted exposures
+#[derive(Debug, Default)]
+struct AlertsUpdate {
+    // New and extended exposures, to be persisted
+    exposures: Vec<ReportExposure>,
+    // Sorted by id
+    new_alerts: Vec<Alert>,
+    extended_alerts: Vec<Alert>,
+    // Not turned into alerts: ended before the oldest actionable contact end
+    expired_exposures: u32,
+    // Decoded symptoms of the matched reports, by report id
+    report_symptoms: Vec<(String, PublicSymptoms)>,
+}
+
+// stored_exposures: persisted exposures of each matched report, in the same order.
+// record_tcn_prefixes: see ReportsUpdater::record_tcn_prefixes.
+// oldest_contact_end: exposures that ended before it don't create or extend alerts.
+fn to_alerts_update<T: MemoMapper + Sync>(
+    matched_reports: Vec<MatchedReport>,
+    stored_exposures: Vec<Vec<ReportExposure>>,
+    exposure_grouper: &ExposureGrouper,
+    memo_mapper: &T,
+    client_version: &ClientVersion,
+    record_tcn_prefixes: bool,
+    oldest_contact_end: u64,
+) -> AlertsUpdate {
+    #[cfg(feature = "parallel-matching")]
+    let matched_reports = matched_reports.into_par_iter();
+    #[cfg(not(feature = "parallel-matching"))]
+    let matched_reports = matched_reports.into_iter();
+
+    let updates: Vec<AlertsUpdate> = matched_reports
+        .zip(stored_exposures)
+        .map(|(matched_report, stored)| {
+            to_ffi_alerts(
+                matched_report,
+                stored,
+                exposure_grouper,
+                memo_mapper,
+                client_version,
+                record_tcn_prefixes,
+                oldest_contact_end,
+            )
+        })
+        .collect();
+
+    let mut update = AlertsUpdate::default();
+    for report_update in updates {
+        update.exposures.extend(report_update.exposures);
+        update.new_alerts.extend(report_update.new_alerts);
+        update.extended_alerts.extend(report_update.extended_alerts);
+        update.expired_exposures += report_update.expired_exposures;
+        update.report_symptoms.extend(report_update.report_symptoms);
+    }
+    update.new_alerts.sort_by(|a1, a2| a1.id.cmp(&a2.id));
+    update
+}
+
+// Note: For now we will not create an FFI layer to handle JSON conversions, since it may be possible
+// to use directly the data structures.
+// Extends the report's persisted exposures with its matched TCNs (see ExposureGrouper::extend).
+// Alerts of unchanged exposures aren't returned: they're already stored.
+fn to_ffi_alerts<T: MemoMapper>(
+    matched_report: MatchedReport,
+    stored_exposures: Vec<ReportExposure>,
+    exposure_grouper: &ExposureGrouper,
+    memo_mapper: &T,
+    client_version: &ClientVersion,
+    record_tcn_prefixes: bool,
+    oldest_contact_end: u64,
+) -> AlertsUpdate {
+    let MatchedReport {
+        report,
+        tcns,
+        memo,
+        tcn_count,
+    } = matched_report;
+
+    // The report was verified during matching, so the memo can be decoded directly
+    let public_symptoms = memo_mapper.to_report(Memo { bytes: memo });
+    let coverage = report_coverage(&public_symptoms.report_time, tcn_count);
+
+    let grouped = exposure_grouper
+        .group(tcns)
+        .into_iter()
+        .map(|exposure| {
+            let measurements = exposure.measurements();
+            ReportExposure {
+                alert_id: alert_id(&report, measurements.contact_start.value),
+                report_id: report_id(&report),
+                contact_start: measurements.contact_start.value,
+                contact_end: measurements.contact_end.value,
+                min_distance: measurements.min_distance,
+                avg_distance: measurements.avg_distance,
+                p10_distance: measurements.p10_distance,
+                median_distance: measurements.median_distance,
+                total_count: measurements.total_count,
+                tcn_prefixes: if record_tcn_prefixes {
+                    exposure.tcn_prefixes()
+                } else {
+                    vec![]
+                },
+            }
+        })
+        .collect();
+
+    let mut update = AlertsUpdate {
+        report_symptoms: vec![(report_id(&report), public_symptoms.clone())],
+        ..AlertsUpdate::default()
+    };
+    for (exposure, change) in exposure_grouper.extend(stored_exposures, grouped) {
+        if change != ExposureChange::Unchanged && exposure.contact_end < oldest_contact_end {
+            update.expired_exposures += 1;
+            continue;
+        }
+        let alerts = match change {
+            ExposureChange::New => &mut update.new_alerts,
+            ExposureChange::Extended => &mut update.extended_alerts,
+            ExposureChange::Unchanged => continue,
+        };
+        alerts.push(to_alert(
+            public_symptoms.clone(),
+            &exposure,
+            coverage,
+            client_version,
+        ));
+        update.exposures.push(exposure);
+    }
+    update
+}
+
+// Time range (start, end) in which the reporter broadcasted the report's TCNs.
+// The tcn crate doesn't expose the report's key indices, so it's derived from the number of TCNs:
+// reports cover the TCNs up to the report time, one per rotation period.
+fn report_coverage(report_time: &UnixTime, tcn_count: usize) -> (u64, u64) {
+    let duration = (tcn_count as u64).saturating_mul(TCN_ROTATION_PERIOD_SECS);
+    let start = report_time.value.saturating_sub(duration);
+    (start, report_time.value)
+}
+
+// Creates an alert, corresponding to an exposure
+fn to_alert(
+    symptoms: PublicSymptoms,
+    exposure: &ReportExposure,
+    report_coverage: (u64, u64),
+    client_version: &ClientVersion,
+) -> Alert {
+    let (report_coverage_start, report_coverage_end) = report_coverage;
+
+    Alert {
+        id: exposure.alert_id.clone(),
+        report_id: exposure.report_id.clone(),
+        symptoms,
+        contact_start: exposure.contact_start,
+        contact_end: exposure.contact_end,
+        min_distance: exposure.min_distance,
+        avg_distance: exposure.avg_distance,
+        p10_distance: exposure.p10_distance,
+        median_distance: exposure.median_distance,
+        is_read: false,
+        has_raw_report: false,
+        report_coverage_start: Some(report_coverage_start),
+        report_coverage_end: Some(report_coverage_end),
+        debug_info: AlertDebugInfo {
+            core_version: Some(client_version.core.clone()),
+            app_version: Some(client_version.app.clone()),
+            tcn_prefixes: exposure.tcn_prefixes.clone(),
+        },
+        snoozed_until: None,
+        retracted: false,
+    }
+}
+
+// Set when the exposure is created, so it doesn't change when the exposure is extended.
+fn alert_id(signed_report: &SignedReport, contact_start: u64) -> String {
+    format!("{}-{}", report_id(signed_report), contact_start)
+}
+
+// Hex of the signature bytes. Unlike the Debug format of the signature (the ids stored before migration 23),
+// it doesn't depend on the tcn version.
+pub fn report_id(signed_report: &SignedReport) -> String {
+    let report_sig_bytes: [u8; 64] = signed_report.sig.into();
+    hex::encode(report_sig_bytes.to_vec())
+}
+
+// Identifies the reports of an interval (regardless of their order) and the TCNs they're matched with
+fn content_hash(reports: &[SignedReport], tcns: &[ObservedTcn]) -> String {
+    let mut signatures: Vec<[u8; 64]> = reports.iter().map(|report| report.sig.into()).collect();
+    signatures.sort();
+    let mut hasher = Sha256::new();
+    for signature in signatures {
+        hasher.update(&signature[..]);
+    }
+    hash_tcns(&mut hasher, tcns);
+    hex::encode(hasher.finalize())
+}
+
+// Identifies a batch as served, before decoding. Length prefixed, so the strings can't run into each other.
+fn payload_hash(report_strings: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for report_string in report_strings {
+        hasher.update(&(report_string.len() as u64).to_le_bytes());
+        hasher.update(report_string.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+// Identifies what the reports of an interval are matched with: the TCNs and the host app's filters
+fn inputs_hash(tcns: &[ObservedTcn], filter: &ReportFilter) -> String {
+    let mut hasher = Sha256::new();
+    hash_tcns(&mut hasher, tcns);
+    for (tag, prefixes) in &[(b'd', &filter.denylist), (b'a', &filter.allowlist)] {
+        for prefix in prefixes.iter() {
+            hasher.update(&[*tag]);
+            hasher.update(&(prefix.len() as u64).to_le_bytes());
+            hasher.update(prefix.as_bytes());
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn hash_tcns(hasher: &mut Sha256, tcns: &[ObservedTcn]) {
+    for tcn in tcns {
+        hasher.update(&tcn.tcn.0);
+        hasher.update(&tcn.contact_start.value.to_le_bytes());
+        hasher.update(&tcn.contact_end.value.to_le_bytes());
+        hasher.update(&tcn.min_distance.to_bits().to_le_bytes());
+        hasher.update(&tcn.avg_distance.to_bits().to_le_bytes());
+        hasher.update(&(tcn.total_count as u64).to_le_bytes());
+    }
+}
+
+// The reports of the fetched intervals (failed intervals have none)
+fn to_processed_reports(chunks: &[MatchedReportsChunk]) -> Vec<ProcessedReport> {
+    chunks
+        .iter()
+        .flat_map(|chunk| {
+            chunk.reports.iter().map(move |report| ProcessedReport {
+                report_id: report_id(report),
+                first_seen_interval: chunk.interval.number,
+                last_seen_interval: chunk.interval.number,
+                interval_length: chunk.interval.length,
+                content_hash: chunk.content_hash.clone(),
+            })
+        })
+        .collect()
+}
+
+fn signature_prefixes(sigs_json: &str) -> Result<Vec<String>, ServicesError> {
+    let prefixes: Vec<String> = serde_json::from_str(sigs_json).map_err(|error| {
+        ServicesError::FFIParameters(format!(
+            "Invalid signature prefixes: {}, error: {}",
+            sigs_json, error
+        ))
+    })?;
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=';
+    match prefixes
+        .iter()
+        .find(|prefix| prefix.is_empty() || !prefix.chars().all(is_base64))
+    {
+        Some(invalid) => Err(ServicesError::FFIParameters(format!(
+            "Invalid signature prefix: {:?}",
+            invalid
+        ))),
+        None => Ok(prefixes),
+    }
+}
+
+// Signature prefixes supplied by the host app. The denylist wins, an empty allowlist allows all reports.
+struct ReportFilter {
+    denylist: Vec<String>,
+    allowlist: Vec<String>,
+}
+
+impl ReportFilter {
+    fn allows(&self, report: &SignedReport) -> bool {
+        if self.denylist.is_empty() && self.allowlist.is_empty() {
+            return true;
+        }
+        let signature: [u8; 64] = report.sig.into();
+        let signature = base64::encode(&signature[..]);
+        let has_prefix = |prefixes: &[String]| {
+            prefixes
+                .iter()
+                .any(|prefix| signature.starts_with(prefix.as_str()))
+        };
+        !has_prefix(&self.denylist) && (self.allowlist.is_empty() || has_prefix(&self.allowlist))
+    }
+}
+
+// Result of fetching an interval's reports
+#[derive(Debug, Clone)]
+enum FetchOutcome {
+    Fetched,
+    Empty,
+    Failed(NetworkingError),
+}
+
+impl FetchOutcome {
+    fn is_failed(&self) -> bool {
+        matches!(self, FetchOutcome::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MatchedReportsChunk {
+    reports: Vec<SignedReport>,
+    matched: Vec<MatchedReport>,
+    interval: ReportsInterval,
+    fetch_outcome: FetchOutcome,
+    normalized_reports: u32,
+    // Not in reports (see ReportFilter)
+    filtered_reports: u32,
+    content_hash: String, // See content_hash
+    // None if the fetch failed or was empty, or the interval is unchanged
+    interval_state: Option<ReportIntervalState>,
+    // The interval's reports were already processed: reports and matched are empty
+    is_unchanged: bool,
+}
+
+#[derive(Debug, Clone)]
+struct SignedReportsChunk {
+    reports: Vec<SignedReport>,
+    interval: ReportsInterval,
+    fetch_outcome: FetchOutcome,
+    normalized_reports: u32,
+    payload_hash: Option<String>, // See payload_hash. None if the fetch failed or was empty.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            alert_dao::AlertDaoImpl,
+            database::Database,
+            preferences::{PreferencesDao, PreferencesImpl},
+            tcn_dao::{TcnDaoImpl, TcnStats},
+            update_run_dao::UpdateRunDaoImpl,
+        },
+        networking::{PagingLimits, ReportsPage},
+        reporting::{
+            memo::MemoMapperImpl,
+            public_symptoms::{CoughSeverity, FeverSeverity, ReportKind},
+            symptom_inputs::UserInput,
+        },
+        reports_update::alert_reference::{AlertReferences, AlertReferencesImpl},
+        tcn_ext::tcn_keys::ReportAuthorizationKeyExt,
+        tcn_recording::{
+            distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn,
+        },
+        test_support::{
+            fixtures::{
+                observed_tcn, seeded_test_reports, test_public_symptoms, verification_observed_tcn,
+                TEST_RAK_BYTES, VERIFICATION_CONTACT_END, VERIFICATION_CONTACT_START,
+                VERIFICATION_REPORT, VERIFICATION_REPORT_SIG, VERIFICATION_REPORT_TCN,
+            },
+            migrated_database, ApiCallArgs, ApiScriptStep, ScriptedTcnApi,
+        },
+    };
+    use rusqlite::NO_PARAMS;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+    use tcn::{MemoType, ReportAuthorizationKey, TemporaryContactNumber};
+
+    #[test]
+    fn test_built_updater_fetches_intervals_until_clock_time() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let api = ScriptedTcnApi::default();
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences.clone(),
+            Arc::new(TcnDaoImpl::new(database.clone())),
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        assert!(alerts_res.unwrap().is_empty());
+
+        assert_eq!(api.requested_intervals(), vec![73688, 73689, 73690]);
+        // The interval containing the clock's time isn't completed yet
+        assert_eq!(
+            preferences.last_completed_reports_interval(),
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            })
+        );
+    }
+
+    #[test]
+    fn test_matching_report_payload_creates_alert() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let overwrite_res = tcn_dao.overwrite(vec![verification_observed_tcn()]);
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![]));
+        api.script_get_reports(ApiScriptStep::ok(vec![VERIFICATION_REPORT.to_owned()]));
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences,
+            tcn_dao,
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        let alerts = alerts_res.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].contact_start, 1590528300);
+        assert_eq!(alerts[0].min_distance, 2.3);
+
+        let history_res = reports_updater.update_history();
+        assert!(history_res.is_ok());
+        let history = history_res.unwrap();
+        assert_eq!(history[0].reports, 1);
+        assert_eq!(history[0].matches, 1);
+    }
+
+    #[test]
+    fn test_matched_tcns_are_flagged() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let matching_tcn = TemporaryContactNumber(VERIFICATION_REPORT_TCN);
+        let other_tcn = TemporaryContactNumber([1; 16]);
+        let overwrite_res = tcn_dao.overwrite(
+            vec![matching_tcn.clone(), other_tcn]
+                .into_iter()
+                .map(|tcn| ObservedTcn {
+                    tcn,
+                    contact_start: UnixTime { value: 1590528300 },
+                    contact_end: UnixTime { value: 1590528301 },
+                    min_distance: 2.3,
+                    avg_distance: 3.0,
+                    total_count: 1,
+                    distance_histogram: DistanceHistogram::default(),
+                })
+                .collect(),
+        );
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![]));
+        api.script_get_reports(ApiScriptStep::ok(vec![VERIFICATION_REPORT.to_owned()]));
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences,
+            tcn_dao.clone(),
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database.clone())),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        let alerts = alerts_res.unwrap();
+        assert_eq!(alerts.len(), 1);
+
+        let flags_res = database.query(
+            "select tcn, matched, matched_report_id from tcn where matched = 1",
+            NO_PARAMS,
+            |row| -> Result<(Vec<u8>, bool, Option<String>), ServicesError> {
+                Ok((row.get_unwrap(0), row.get_unwrap(1), row.get_unwrap(2)))
+            },
+        );
+        assert!(flags_res.is_ok());
+        assert_eq!(
+            vec![(
+                matching_tcn.0.to_vec(),
+                true,
+                Some(alerts[0].report_id.clone())
+            )],
+            flags_res.unwrap().rows
+        );
+
+        let stats_res = tcn_dao.stats();
+        assert!(stats_res.is_ok());
+        assert_eq!(
+            TcnStats {
+                total: 2,
+                matched: 1,
+                unmatched: 1
+            },
+            stats_res.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_observations_below_risk_thresholds_dont_create_alerts() {
+        let risk_config = RiskConfig {
+            min_samples_per_tcn: 3,
+            min_window_seconds: 60,
+            ..RiskConfig::default()
+        };
+
+        // Single observation, under a second
+        let noisy_alerts = alerts_for_stored_tcn(1590528300, 1590528301, 1, risk_config.clone());
+        assert!(noisy_alerts.is_empty());
+
+        let sustained_alerts = alerts_for_stored_tcn(1590528300, 1590528600, 5, risk_config);
+        assert_eq!(1, sustained_alerts.len());
+
+        // Defaults keep every observation
+        let default_alerts =
+            alerts_for_stored_tcn(1590528300, 1590528301, 1, RiskConfig::default());
+        assert_eq!(1, default_alerts.len());
+    }
+
+    // Runs an update with a report matching the stored TCN, returning the alerts
+    fn alerts_for_stored_tcn(
+        contact_start: u64,
+        contact_end: u64,
+        total_count: usize,
+        risk_config: RiskConfig,
+    ) -> Vec<Alert> {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let overwrite_res = tcn_dao.overwrite(vec![ObservedTcn {
+            tcn: TemporaryContactNumber(VERIFICATION_REPORT_TCN),
+            contact_start: UnixTime {
+                value: contact_start,
+            },
+            contact_end: UnixTime { value: contact_end },
+            min_distance: 2.3,
+            avg_distance: 3.0,
+            total_count,
+            distance_histogram: DistanceHistogram::default(),
+        }]);
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![]));
+        api.script_get_reports(ApiScriptStep::ok(vec![VERIFICATION_REPORT.to_owned()]));
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences,
+            tcn_dao,
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .risk_config(risk_config)
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        alerts_res.unwrap()
+    }
+
+    #[test]
+    fn test_rerun_with_same_tcns_is_idempotent() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        let exposures_res = alert_dao.exposures(&alerts[0].report_id);
+        assert!(exposures_res.is_ok());
+        let exposures = exposures_res.unwrap();
+        assert_eq!(1, exposures.len());
+        assert_eq!(alerts[0].id, exposures[0].alert_id);
+        assert_eq!(3, exposures[0].total_count);
+
+        // E.g. the report is downloaded again
+        let rerun_alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(alerts, rerun_alerts);
+        assert_eq!(
+            exposures,
+            alert_dao.exposures(&alerts[0].report_id).unwrap()
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingAlertsUpdateListener {
+        updates: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl AlertsUpdateListener for RecordingAlertsUpdateListener {
+        fn alerts_updated(&self, report_ids: &[String]) {
+            self.updates.lock().unwrap().push(report_ids.to_vec());
+        }
+    }
+
+    // Stores symptoms that differ from the fixture report's, which is downloaded again next update
+    fn store_stale_report_symptoms(database: &Database, report_id: &str) {
+        let update_res = database.execute_sql(
+            "update report set diarrhea = 1 - diarrhea where report_id = ?1;",
+            &[report_id],
+        );
+        assert!(update_res.is_ok());
+        forget_processed_reports(database);
+    }
+
+    // The next update downloads and matches the reports again
+    fn forget_processed_reports(database: &Database) {
+        let delete_res = database
+            .execute_batch("delete from report_interval_state; delete from processed_report;");
+        assert!(delete_res.is_ok());
+    }
+
+    fn symptoms_updated_at(database: &Database, report_id: &str) -> Option<i64> {
+        database
+            .query_row(
+                "select symptoms_updated_at from report where report_id = ?1",
+                &[report_id],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_changed_symptoms_of_stored_report_are_updated() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        let report_id = alerts[0].report_id.clone();
+        let stored_alerts = alert_dao.all().unwrap();
+
+        store_stale_report_symptoms(&database, &report_id);
+        let stale_alerts = alert_dao.all().unwrap();
+        assert_ne!(stored_alerts[0].symptoms, stale_alerts[0].symptoms);
+
+        let listener = Arc::new(RecordingAlertsUpdateListener::default());
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .alerts_update_listener(listener.clone())
+            .build();
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+
+        assert_eq!(stored_alerts, alert_dao.all().unwrap());
+        assert_eq!(Some(1591706000), symptoms_updated_at(&database, &report_id));
+        assert_eq!(vec![vec![report_id]], *listener.updates.lock().unwrap());
+    }
+
+    #[test]
+    fn test_unchanged_symptoms_of_stored_report_are_not_updated() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        let report_id = alerts[0].report_id.clone();
+        let stored_alerts = alert_dao.all().unwrap();
+
+        // Downloaded again with the same symptoms
+        forget_processed_reports(&database);
+
+        let listener = Arc::new(RecordingAlertsUpdateListener::default());
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .alerts_update_listener(listener.clone())
+            .build();
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+
+        assert_eq!(stored_alerts, alert_dao.all().unwrap());
+        assert_eq!(None, symptoms_updated_at(&database, &report_id));
+        assert!(listener.updates.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_symptoms_of_stored_report_are_kept_if_updates_disabled() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        let report_id = alerts[0].report_id.clone();
+
+        store_stale_report_symptoms(&database, &report_id);
+        let stale_alerts = alert_dao.all().unwrap();
+
+        let listener = Arc::new(RecordingAlertsUpdateListener::default());
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .update_report_symptoms(false)
+            .alerts_update_listener(listener.clone())
+            .build();
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+
+        assert_eq!(stale_alerts, alert_dao.all().unwrap());
+        assert_eq!(None, symptoms_updated_at(&database, &report_id));
+        assert!(listener.updates.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fetch_tracker_tracks_first_running_fetch() {
+        let tracker = FetchTracker::default();
+        let now = UnixTime { value: 1591706000 };
+        assert_eq!(None, tracker.in_progress(&now));
+
+        let fetch = tracker.start(&now);
+        // A concurrent fetch doesn't replace the tracked one
+        drop(tracker.start(&UnixTime {
+            value: now.value + 5,
+        }));
+        assert_eq!(
+            Some(FetchInProgress {
+                started_at: now.value,
+                running_secs: 10,
+            }),
+            tracker.in_progress(&UnixTime {
+                value: now.value + 10,
+            })
+        );
+
+        drop(fetch);
+        assert_eq!(None, tracker.in_progress(&now));
+    }
+
+    #[test]
+    fn test_contiguous_observation_extends_exposure() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        assert!(alert_dao.update_is_read(alerts[0].id.clone(), true).is_ok());
+
+        // Observed again shortly after: merged with the stored window
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590529200, 5)]);
+        let extended_alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+
+        assert_eq!(1, extended_alerts.len());
+        assert_eq!(alerts[0].id, extended_alerts[0].id);
+        assert_eq!(1590528300, extended_alerts[0].contact_start);
+        assert_eq!(1590529200, extended_alerts[0].contact_end);
+        assert!(extended_alerts[0].is_read);
+
+        let exposures_res = alert_dao.exposures(&alerts[0].report_id);
+        assert!(exposures_res.is_ok());
+        let exposures = exposures_res.unwrap();
+        assert_eq!(1, exposures.len());
+        assert_eq!(5, exposures[0].total_count);
+    }
+
+    // Extending the exposure widens the alert's window: the reference tokens given out before must still verify
+    #[test]
+    fn test_extended_exposure_keeps_alert_reference_token() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let alert_references = AlertReferencesImpl::new(preferences, alert_dao.clone());
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+        let token = alert_references
+            .alert_reference_token(&alerts[0].id)
+            .unwrap();
+
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590529200, 5)]);
+        let extended_alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, extended_alerts.len());
+        assert_eq!(1590529200, extended_alerts[0].contact_end);
+
+        assert!(alert_references
+            .verify_alert_reference_token(&token, &alerts[0].id)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_disjoint_observation_creates_new_exposure() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(1, alerts.len());
+
+        // Observed again hours later
+        store_report_tcn(
+            &tcn_dao,
+            vec![(1590528300, 1590528600, 3), (1590540000, 1590540300, 1)],
+        );
+        let new_alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+
+        assert_eq!(2, new_alerts.len());
+        assert!(new_alerts.contains(&alerts[0]));
+        assert!(new_alerts
+            .iter()
+            .any(|alert| alert.contact_start == 1590540000 && alert.contact_end == 1590540300));
+
+        let exposures_res = alert_dao.exposures(&alerts[0].report_id);
+        assert!(exposures_res.is_ok());
+        assert_eq!(2, exposures_res.unwrap().len());
+    }
+
+    #[test]
+    fn test_tcn_prefixes_are_recorded_only_if_enabled() {
+        let full_tcn = hex::encode(VERIFICATION_REPORT_TCN);
+        let prefix = full_tcn[..8].to_owned();
+
+        for &record in &[false, true] {
+            let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+            store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+            let api = ScriptedTcnApi::default();
+            let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+                .record_tcn_prefixes(record)
+                .build();
+
+            let alerts_res = reports_updater.update_and_fetch_alerts();
+            assert!(alerts_res.is_ok());
+            let alerts = alerts_res.unwrap();
+            assert_eq!(1, alerts.len());
+            let expected_prefixes = if record { vec![prefix.clone()] } else { vec![] };
+            assert_eq!(expected_prefixes, alerts[0].debug_info.tcn_prefixes);
+            let exposures_res = alert_dao.exposures(&alerts[0].report_id);
+            assert_eq!(expected_prefixes, exposures_res.unwrap()[0].tcn_prefixes);
+
+            let stored_res =
+                database.query_row("select tcn_prefixes from exposure", NO_PARAMS, |row| {
+                    row.get::<_, Option<String>>(0)
+                });
+            assert!(stored_res.is_ok());
+            let stored = stored_res.unwrap();
+            assert_eq!(record, stored.is_some());
+            assert!(!stored.unwrap_or_default().contains(&full_tcn));
+
+            // Only in the debug info, and never the full TCN
+            let json = serde_json::to_string(&alerts[0]).unwrap();
+            assert_eq!(record, json.contains(r#""tcn_prefixes""#));
+            assert!(!json.contains(&full_tcn));
+        }
+    }
+
+    #[test]
+    fn test_raw_reports_are_stored_only_if_enabled() {
+        for &store in &[false, true] {
+            let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+            store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+            let api = ScriptedTcnApi::default();
+            let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+                .store_raw_reports(store)
+                .build();
+
+            let alerts_res = reports_updater.update_and_fetch_alerts();
+            assert!(alerts_res.is_ok());
+            let alerts = alerts_res.unwrap();
+            assert_eq!(1, alerts.len());
+            assert_eq!(store, alerts[0].has_raw_report);
+
+            let blob_count_res =
+                database.query_row("select count(*) from report_blob", NO_PARAMS, |row| {
+                    row.get::<_, i64>(0)
+                });
+            assert!(blob_count_res.is_ok());
+            assert_eq!(if store { 1 } else { 0 }, blob_count_res.unwrap());
+
+            let raw_report_res = reports_updater.raw_report(alerts[0].report_id.clone());
+            if store {
+                assert!(raw_report_res.is_ok());
+            } else {
+                assert!(matches!(raw_report_res, Err(ServicesError::General(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dismissed_report_creates_no_new_alerts() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(
+            &tcn_dao,
+            vec![(1590528300, 1590528600, 3), (1590540000, 1590540300, 1)],
+        );
+        let alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(2, alerts.len());
+        let other_report_alert = Alert {
+            id: "other".to_owned(),
+            report_id: "other".to_owned(),
+            ..alerts[0].clone()
+        };
+        assert!(alert_dao.save(vec![other_report_alert.clone()]).is_ok());
+
+        assert_eq!(
+            2,
+            alert_dao
+                .delete_by_report(alerts[0].report_id.clone())
+                .unwrap()
+        );
+        assert_eq!(vec![other_report_alert.clone()], alert_dao.all().unwrap());
+
+        // Observed again, disjoint from the stored exposures
+        store_report_tcn(
+            &tcn_dao,
+            vec![
+                (1590528300, 1590528600, 3),
+                (1590540000, 1590540300, 1),
+                (1590560000, 1590560300, 2),
+            ],
+        );
+        let rerun_alerts = update_with_report(&database, &tcn_dao, &alert_dao);
+        assert_eq!(vec![other_report_alert], rerun_alerts);
+    }
+
+    #[test]
+    fn test_denylisted_report_creates_no_alerts() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        assert!(reports_updater
+            .set_report_denylist(&format!(
+                "[\"AAAA\", \"{}\"]",
+                &VERIFICATION_REPORT_SIG[..8]
+            ))
+            .is_ok());
+
+        assert!(reports_updater
+            .update_and_fetch_alerts()
+            .unwrap()
+            .is_empty());
+        let metrics = &reports_updater.update_history().unwrap()[0];
+        assert_eq!(1, metrics.reports);
+        assert_eq!(1, metrics.filtered_reports);
+        assert_eq!(0, metrics.matches);
+    }
+
+    #[test]
+    fn test_report_whose_verification_panics_is_skipped() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let mut tcn_matcher = DefaultTcnMatcher::new();
+        tcn_matcher.verify = |_| panic!("Malformed key material");
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .tcn_matcher(tcn_matcher)
+            .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        assert!(alerts_res.unwrap().is_empty());
+        let metrics = &reports_updater.update_history().unwrap()[0];
+        assert_eq!(1, metrics.reports);
+        assert_eq!(1, metrics.invalid_reports);
+        assert_eq!(0, metrics.matches);
+        assert_eq!(None, metrics.error_code);
+    }
+
+    #[test]
+    fn test_no_symptoms_report_retracts_earlier_report_of_same_reporter() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        let rak = ReportAuthorizationKey::with_bytes(TEST_RAK_BYTES);
+        let memo_mapper = MemoMapperImpl {};
+        let create_report = |symptoms: PublicSymptoms| {
+            let memo = memo_mapper.to_memo(symptoms);
+            let report = rak
+                .create_report(MemoType::CoEpiV1, memo.bytes, 1, 10)
+                .unwrap();
+            base64::encode(signed_report_to_bytes(report))
+        };
+        let symptoms = test_public_symptoms();
+        let report = create_report(symptoms.clone());
+        let retraction = create_report(PublicSymptoms {
+            report_time: UnixTime {
+                value: symptoms.report_time.value + 1000,
+            },
+            earliest_symptom_time: UserInput::None,
+            fever_severity: FeverSeverity::None,
+            cough_severity: CoughSeverity::None,
+            breathlessness: false,
+            muscle_aches: false,
+            loss_smell_or_taste: false,
+            diarrhea: false,
+            runny_nose: false,
+            other: false,
+            no_symptoms: true,
+            memo_version: 1,
+            report_kind: ReportKind::Symptoms,
+        });
+        // Both reports cover the TCNs of the same key
+        let tcn = SignedReport::with_str(&report)
+            .unwrap()
+            .verify()
+            .unwrap()
+            .temporary_contact_numbers()
+            .next()
+            .unwrap();
+        let overwrite_res = tcn_dao.overwrite(vec![observed_tcn(
+            tcn.0,
+            VERIFICATION_CONTACT_START,
+            VERIFICATION_CONTACT_END,
+        )]);
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_reports(
+            &database,
+            &tcn_dao,
+            &alert_dao,
+            &api,
+            vec![report.clone(), retraction],
+        )
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        let alerts = alerts_res.unwrap();
+        // The retraction creates no alert
+        assert_eq!(1, alerts.len());
+        assert_eq!(
+            report_id(&SignedReport::with_str(&report).unwrap()),
+            alerts[0].report_id
+        );
+        assert!(alerts[0].retracted);
+    }
+
+    #[test]
+    fn test_only_allowlisted_reports_create_alerts() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        assert!(reports_updater.set_report_allowlist("[\"AAAA\"]").is_ok());
+        assert!(reports_updater
+            .update_and_fetch_alerts()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            1,
+            reports_updater.update_history().unwrap()[0].filtered_reports
+        );
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        assert!(reports_updater
+            .set_report_allowlist(&format!(
+                "[\"AAAA\", \"{}\"]",
+                &VERIFICATION_REPORT_SIG[..8]
+            ))
+            .is_ok());
+        assert_eq!(1, reports_updater.update_and_fetch_alerts().unwrap().len());
+        assert_eq!(
+            0,
+            reports_updater.update_history().unwrap()[0].filtered_reports
+        );
+    }
+
+    #[test]
+    fn test_denylist_takes_precedence_over_allowlist() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        let prefixes = format!("[\"{}\"]", &VERIFICATION_REPORT_SIG[..8]);
+        assert!(reports_updater.set_report_allowlist(&prefixes).is_ok());
+        assert!(reports_updater.set_report_denylist(&prefixes).is_ok());
+
+        assert!(reports_updater
+            .update_and_fetch_alerts()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            1,
+            reports_updater.update_history().unwrap()[0].filtered_reports
+        );
+    }
+
+    #[test]
+    fn test_invalid_signature_prefixes_are_rejected() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+
+        for sigs_json in &["[\"\"]", "[\"ab-c\"]", "\"abc\"", "[1]"] {
+            let res = reports_updater.set_report_denylist(sigs_json);
+            assert!(matches!(res, Err(ServicesError::FFIParameters(_))));
+        }
+        assert!(reports_updater.set_report_allowlist("[\" \"]").is_err());
+        assert!(reports_updater.preferences.report_denylist().is_empty());
+        assert!(reports_updater.preferences.report_allowlist().is_empty());
+
+        // Empty lists reset the filter
+        assert!(reports_updater.set_report_denylist("[]").is_ok());
+    }
+
+    #[test]
+    fn test_exposures_older_than_max_alert_age_dont_create_alerts() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        // The clock is at 1591706000: 14 days before is 1590496400
+        store_report_tcn(
+            &tcn_dao,
+            vec![(1590400000, 1590400300, 3), (1590528300, 1590528600, 3)],
+        );
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .risk_config(RiskConfig {
+                max_alert_age_days: 14,
+                ..RiskConfig::default()
+            })
+            .build();
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        let alerts = alerts_res.unwrap();
+        assert_eq!(1, alerts.len());
+        assert_eq!(1590528300, alerts[0].contact_start);
+        assert_eq!(
+            1,
+            reports_updater.update_history().unwrap()[0].expired_exposures
+        );
+
+        // Window tightened: 10 days before is 1590842000
+        let tightened_api = ScriptedTcnApi::default();
+        let tightened_updater =
+            updater_with_report(&database, &tcn_dao, &alert_dao, &tightened_api)
+                .risk_config(RiskConfig {
+                    max_alert_age_days: 10,
+                    ..RiskConfig::default()
+                })
+                .build();
+        let alerts_res = tightened_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        assert!(alerts_res.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_alerts_notification_is_deferred_during_quiet_hours() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+
+        let api = ScriptedTcnApi::default();
+        // The clock is at 12:33 UTC
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        let set_res = reports_updater.set_quiet_hours(QuietHours {
+            enabled: true,
+            start_minute: 12 * 60,
+            end_minute: 13 * 60,
+            utc_offset_minutes: 0,
+        });
+        assert!(set_res.is_ok());
+        assert!(!reports_updater.flush_pending_notifications());
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        // Stored immediately, notified later
+        assert_eq!(1, alerts_res.unwrap().len());
+        assert!(!reports_updater.flush_pending_notifications());
+
+        let later_api = ScriptedTcnApi::default();
+        let later_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &later_api)
+            .clock(|| UnixTime { value: 1591711200 }) // 14:00 UTC
+            .build();
+        assert!(later_updater.flush_pending_notifications());
+        // Flushed
+        assert!(!later_updater.flush_pending_notifications());
+    }
+
+    #[test]
+    fn test_alerts_notification_is_pending_outside_of_quiet_hours() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        let set_res = reports_updater.set_quiet_hours(QuietHours {
+            enabled: true,
+            start_minute: 22 * 60,
+            end_minute: 7 * 60,
+            utc_offset_minutes: 0,
+        });
+        assert!(set_res.is_ok());
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        assert!(reports_updater.flush_pending_notifications());
+        assert!(!reports_updater.flush_pending_notifications());
+    }
+
+    #[test]
+    fn test_reports_are_processed_once_per_interval_content_and_expire_when_unseen() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        // Alerts aren't deleted by age during the test
+        let risk_config = RiskConfig {
+            max_alert_age_days: 60,
+            report_expiry_days: 21,
+            expired_report_relevance_days: 7,
+            ..RiskConfig::default()
+        };
+        let processed_reports = Arc::new(AtomicUsize::new(0));
+
+        // The interval with the report is fetched again in the next runs, until it's completed
+        for _ in 0..3 {
+            let api = ScriptedTcnApi::default();
+            let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+                .tcn_matcher(CountingTcnMatcher {
+                    processed_reports: processed_reports.clone(),
+                })
+                .risk_config(risk_config.clone())
+                .build();
+            let alerts_res = reports_updater.update_and_fetch_alerts();
+            assert!(alerts_res.is_ok());
+            assert_eq!(1, alerts_res.unwrap().len());
+        }
+        assert_eq!(1, processed_reports.load(Ordering::SeqCst));
+
+        let report_id = alert_dao.all().unwrap()[0].report_id.clone();
+        let processed_res = alert_dao.processed_report(&report_id);
+        assert!(processed_res.is_ok());
+        let processed = processed_res.unwrap().unwrap();
+        assert_eq!(73689, processed.first_seen_interval);
+        assert_eq!(73689, processed.last_seen_interval);
+
+        // The report stops being served. Not expired yet 10 days later.
+        let ten_days_later: fn() -> UnixTime = || UnixTime { value: 1592570000 };
+        let alerts = update_without_reports(
+            &database,
+            &tcn_dao,
+            &alert_dao,
+            &risk_config,
+            ten_days_later,
+        );
+        assert_eq!(1, alerts.len());
+
+        // Not seen for more than 21 days, and the contact ended more than 7 days ago
+        let twenty_two_days_later: fn() -> UnixTime = || UnixTime { value: 1593606800 };
+        let alerts = update_without_reports(
+            &database,
+            &tcn_dao,
+            &alert_dao,
+            &risk_config,
+            twenty_two_days_later,
+        );
+        assert!(alerts.is_empty());
+        assert_eq!(1, processed_reports.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_processed_report_is_matched_again_when_tcns_change() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let processed_reports = Arc::new(AtomicUsize::new(0));
+
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+            .tcn_matcher(CountingTcnMatcher {
+                processed_reports: processed_reports.clone(),
+            })
+            .build();
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590529200, 5)]);
+        let rerun_api = ScriptedTcnApi::default();
+        let rerun_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &rerun_api)
+            .tcn_matcher(CountingTcnMatcher {
+                processed_reports: processed_reports.clone(),
+            })
+            .build();
+        let alerts_res = rerun_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+
+        assert_eq!(2, processed_reports.load(Ordering::SeqCst));
+        let alerts = alerts_res.unwrap();
+        assert_eq!(1, alerts.len());
+        assert_eq!(1590529200, alerts[0].contact_end);
+    }
+
+    #[test]
+    fn test_unchanged_interval_is_skipped() {
+        let (database, tcn_dao, alert_dao) = create_exposure_test_daos();
+        store_report_tcn(&tcn_dao, vec![(1590528300, 1590528600, 3)]);
+        let processed_reports = Arc::new(AtomicUsize::new(0));
+        let run_update = || {
+            let api = ScriptedTcnApi::default();
+            let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api)
+                .tcn_matcher(CountingTcnMatcher {
+                    processed_reports: processed_reports.clone(),
+                })
+                .build();
+            let alerts_res = reports_updater.update_and_fetch_alerts();
+            assert!(alerts_res.is_ok());
+            assert_eq!(1, alerts_res.unwrap().len());
+            reports_updater.update_history().unwrap()[0].clone()
+        };
+
+        let first_run = run_update();
+        assert_eq!(0, first_run.unchanged_intervals);
+        assert_eq!(1, first_run.reports);
+
+        // The backend serves the same batch again
+        let second_run = run_update();
+        assert_eq!(1, processed_reports.load(Ordering::SeqCst));
+        assert_eq!(1, second_run.unchanged_intervals);
+        assert_eq!(0, second_run.reports);
+        assert_eq!(0, second_run.matches);
+
+        // A changed filter is a different input: the batch isn't skipped
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(&database, &tcn_dao, &alert_dao, &api).build();
+        assert!(reports_updater.set_report_denylist("[\"AAAA\"]").is_ok());
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+        let filtered_run = &reports_updater.update_history().unwrap()[0];
+        assert_eq!(0, filtered_run.unchanged_intervals);
+        assert_eq!(1, filtered_run.reports);
+    }
+
+    // The ids are persisted and used by the apps: they mustn't change, e.g. with the tcn version
+    #[test]
+    fn test_report_and_alert_ids_of_fixture_report() {
+        let report = SignedReport::with_str(VERIFICATION_REPORT).unwrap();
+        let expected_report_id = "a3ca7559d19e5dbe4ee7fde437ac7b192ca5822606206b00065dcdaf1849bbd5\
+            c7c2c3777facaf470531b367e13f8a14e44dfe25669b04feafd3da46575c510b";
+        assert_eq!(expected_report_id, report_id(&report));
+        assert_eq!(
+            format!("{}-{}", expected_report_id, VERIFICATION_CONTACT_START),
+            alert_id(&report, VERIFICATION_CONTACT_START)
+        );
+    }
+
+    #[test]
+    fn test_payload_hash_depends_on_report_boundaries() {
+        let hash = payload_hash(&["ab".to_owned(), "c".to_owned()]);
+        assert_eq!(hash, payload_hash(&["ab".to_owned(), "c".to_owned()]));
+        assert_ne!(hash, payload_hash(&["a".to_owned(), "bc".to_owned()]));
+        assert_ne!(hash, payload_hash(&["c".to_owned(), "ab".to_owned()]));
+    }
+
+    // Counts the reports it's asked to match
+    struct CountingTcnMatcher {
+        processed_reports: Arc<AtomicUsize>,
+    }
+
+    impl TcnMatcher for CountingTcnMatcher {
+        fn match_reports(
+            &self,
+            tcns: Vec<ObservedTcn>,
+            reports: Vec<SignedReport>,
+        ) -> Result<Vec<MatchedReport>, ServicesError> {
+            self.processed_reports
+                .fetch_add(reports.len(), Ordering::SeqCst);
+            DefaultTcnMatcher::new().match_reports(tcns, reports)
+        }
+    }
+
+    // Runs an update at the clock time. The fetched intervals have no reports.
+    fn update_without_reports(
+        database: &Arc<Database>,
+        tcn_dao: &Arc<TcnDaoImpl>,
+        alert_dao: &Arc<AlertDaoImpl>,
+        risk_config: &RiskConfig,
+        clock: fn() -> UnixTime,
+    ) -> Vec<Alert> {
+        let api = ScriptedTcnApi::default();
+        let reports_updater = ReportsUpdaterBuilder::new(
+            Arc::new(PreferencesImpl {
+                dao: PreferencesDao::new(database.clone()),
+            }),
+            tcn_dao.clone(),
+            &api,
+            &MemoMapperImpl {},
+            alert_dao.clone(),
+            Arc::new(UpdateRunDaoImpl::new(database.clone())),
+        )
+        .clock(clock)
+        .risk_config(risk_config.clone())
+        .build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        alerts_res.unwrap()
+    }
+
+    fn create_exposure_test_daos() -> (Arc<Database>, Arc<TcnDaoImpl>, Arc<AlertDaoImpl>) {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        (database, tcn_dao, alert_dao)
+    }
+
+    // Stores the observation windows (start, end, count) of a TCN belonging to the fixture report
+    fn store_report_tcn(tcn_dao: &TcnDaoImpl, windows: Vec<(u64, u64, usize)>) {
+        let overwrite_res = tcn_dao.overwrite(
+            windows
+                .into_iter()
+                .map(|(contact_start, contact_end, total_count)| ObservedTcn {
+                    tcn: TemporaryContactNumber(VERIFICATION_REPORT_TCN),
+                    contact_start: UnixTime {
+                        value: contact_start,
+                    },
+                    contact_end: UnixTime { value: contact_end },
+                    min_distance: 2.3,
+                    avg_distance: 3.0,
+                    total_count,
+                    distance_histogram: DistanceHistogram::default(),
+                })
+                .collect(),
+        );
+        assert!(overwrite_res.is_ok());
+    }
+
+    // Runs an update downloading the fixture report, from the same interval each time
+    fn update_with_report(
+        database: &Arc<Database>,
+        tcn_dao: &Arc<TcnDaoImpl>,
+        alert_dao: &Arc<AlertDaoImpl>,
+    ) -> Vec<Alert> {
+        let api = ScriptedTcnApi::default();
+        let reports_updater = updater_with_report(database, tcn_dao, alert_dao, &api).build();
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        alerts_res.unwrap()
+    }
+
+    // Updater whose next run downloads the fixture report
+    fn updater_with_report<'a>(
+        database: &Arc<Database>,
+        tcn_dao: &Arc<TcnDaoImpl>,
+        alert_dao: &Arc<AlertDaoImpl>,
+        api: &'a ScriptedTcnApi,
+    ) -> ReportsUpdaterBuilder<
+        'a,
+        PreferencesImpl,
+        TcnDaoImpl,
+        DefaultTcnMatcher,
+        ScriptedTcnApi,
+        MemoMapperImpl,
+        AlertDaoImpl,
+        UpdateRunDaoImpl,
+    > {
+        updater_with_reports(
+            database,
+            tcn_dao,
+            alert_dao,
+            api,
+            vec![VERIFICATION_REPORT.to_owned()],
+        )
+    }
+
+    // Updater whose next run downloads the reports (base64), in the same interval
+    fn updater_with_reports<'a>(
+        database: &Arc<Database>,
+        tcn_dao: &Arc<TcnDaoImpl>,
+        alert_dao: &Arc<AlertDaoImpl>,
+        api: &'a ScriptedTcnApi,
+        reports: Vec<String>,
+    ) -> ReportsUpdaterBuilder<
+        'a,
+        PreferencesImpl,
+        TcnDaoImpl,
+        DefaultTcnMatcher,
+        ScriptedTcnApi,
+        MemoMapperImpl,
+        AlertDaoImpl,
+        UpdateRunDaoImpl,
+    > {
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+
+        api.script_get_reports(ApiScriptStep::ok(vec![]));
+        api.script_get_reports(ApiScriptStep::ok(reports));
+
+        ReportsUpdaterBuilder::new(
+            preferences,
+            tcn_dao.clone(),
+            api,
+            &MemoMapperImpl {},
+            alert_dao.clone(),
+            Arc::new(UpdateRunDaoImpl::new(database.clone())),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+    }
+
+    // Utility to see quickly all TCNs (hex) for a report
+    #[test]
+    #[ignore]
+    fn print_tcns_for_report() {
+        let report_str = "rOFMgzy3y36MJns34Xj7EZu5Dti9XMhYGRpa/DVznep6q4hMtMYm9sYMg9+sRSHAj0Ff2rHTPXskuzJH0+pZMQEAAgAAFAEAnazaXgAAAAD//////////wMAMFLrKLNOvwUJQSNta9rlzTyjFdpfq25Kv34c6y+ZOoSzRewzNAWsd56Yzm8LUw9cpHB8yyzDUMJ9YTKhD8dADA==";
+        let report = SignedReport::with_str(report_str).unwrap();
+        info!("{:?}", report);
+        for tcn in report.verify().unwrap().temporary_contact_numbers() {
+            info!("{}", hex::encode(tcn.0));
+        }
+    }
+
+    #[test]
+    fn test_update_runs_are_stored() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let api = ScriptedTcnApi::default();
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences,
+            Arc::new(TcnDaoImpl::new(database.clone())),
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+        api.script_get_reports(ApiScriptStep::failure(500));
+        assert!(reports_updater.update_and_fetch_alerts().is_err());
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+
+        let history_res = reports_updater.update_history();
+        assert!(history_res.is_ok());
+        let history = history_res.unwrap();
+        assert_eq!(history.len(), 3);
+
+        // Most recent first. After the first run only the current interval is pending.
+        assert_eq!(history[0].intervals, 1);
+        assert_eq!(history[0].error_code, None);
+        assert_eq!(history[1].intervals, 0);
+        assert_eq!(history[1].error_code, Some("networking".to_owned()));
+        assert_eq!(history[2].intervals, 3);
+        assert_eq!(history[2].reports, 0);
+        assert_eq!(history[2].matches, 0);
+        assert_eq!(history[2].error_code, None);
+        assert!(history.iter().all(|run| run.time == 1591706000));
+    }
+
+    #[test]
+    fn test_failed_interval_is_retried_and_others_processed() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let overwrite_res = tcn_dao.overwrite(vec![verification_observed_tcn()]);
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::failure(503)); // 73688
+        api.script_get_reports(ApiScriptStep::ok(vec![VERIFICATION_REPORT.to_owned()])); // 73689
+
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences.clone(),
+            tcn_dao,
+            &api,
+            &MemoMapperImpl {},
+            alert_dao.clone(),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        match reports_updater.update_and_fetch_alerts() {
+            Err(ServicesError::Networking(error)) => assert_eq!(503, error.http_status),
+            other => panic!("Expected networking error, got: {:?}", other),
+        }
+        // The report of the fetched interval was processed
+        assert_eq!(1, alert_dao.all().unwrap().len());
+        assert_eq!(
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            }),
+            preferences.last_completed_reports_interval()
+        );
+        assert_eq!(
+            vec![ReportsInterval {
+                number: 73688,
+                length: 21600
+            }],
+            preferences.reports_intervals_to_retry()
+        );
+        let history = reports_updater.update_history().unwrap();
+        assert_eq!(2, history[0].intervals);
+        assert_eq!(Some("networking".to_owned()), history[0].error_code);
+
+        // Fails again: stays queued
+        api.script_get_reports(ApiScriptStep::failure(500));
+        assert!(reports_updater.update_and_fetch_alerts().is_err());
+        assert_eq!(1, preferences.reports_intervals_to_retry().len());
+
+        // Succeeds (empty): removed from the queue
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+        assert!(preferences.reports_intervals_to_retry().is_empty());
+        assert_eq!(
+            vec![73688, 73689, 73690, 73688, 73690, 73688, 73690],
+            api.requested_intervals()
+        );
+        assert_eq!(
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            }),
+            preferences.last_completed_reports_interval()
+        );
+    }
+
+    #[test]
+    fn test_oversized_interval_is_rejected_and_others_processed() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let overwrite_res = tcn_dao.overwrite(vec![verification_observed_tcn()]);
+        assert!(overwrite_res.is_ok());
+
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::error(NetworkingError::payload_too_large(
+            50 * 1024 * 1024,
+            5 * 1024 * 1024,
+        ))); // 73688
+        api.script_get_reports(ApiScriptStep::ok(vec![VERIFICATION_REPORT.to_owned()])); // 73689
+
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences.clone(),
+            tcn_dao,
+            &api,
+            &MemoMapperImpl {},
+            alert_dao.clone(),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        match reports_updater.update_and_fetch_alerts() {
+            Err(ServicesError::Networking(error)) => assert!(error.is_payload_too_large()),
+            other => panic!("Expected networking error, got: {:?}", other),
+        }
+        // The other intervals were processed
+        assert_eq!(1, alert_dao.all().unwrap().len());
+        assert_eq!(
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            }),
+            preferences.last_completed_reports_interval()
+        );
+
+        let history = reports_updater.update_history().unwrap();
+        assert_eq!(1, history[0].oversized_intervals);
+        assert_eq!(2, history[0].intervals);
+    }
+
+    // 3 reports, and TCNs of them observed, for a paged interval
+    fn paged_interval_updater<'a>(
+        database: Arc<Database>,
+        api: &'a ScriptedTcnApi,
+        alert_dao: Arc<AlertDaoImpl>,
+    ) -> ReportsUpdater<
+        'a,
+        PreferencesImpl,
+        TcnDaoImpl,
+        DefaultTcnMatcher,
+        ScriptedTcnApi,
+        MemoMapperImpl,
+        AlertDaoImpl,
+        UpdateRunDaoImpl,
+    > {
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73688,
+            length: 21600,
+        });
+
+        let reports = seeded_test_reports(3, 1);
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let overwrite_res = tcn_dao.overwrite(
+            reports
+                .iter()
+                .map(|report| {
+                    let tcn = report
+                        .clone()
+                        .verify()
+                        .unwrap()
+                        .temporary_contact_numbers()
+                        .next()
+                        .unwrap();
+                    observed_tcn(tcn.0, VERIFICATION_CONTACT_START, VERIFICATION_CONTACT_END)
+                })
+                .collect(),
+        );
+        assert!(overwrite_res.is_ok());
+
+        // 73689
+        let pages_count = reports.len();
+        for (index, report) in reports.into_iter().enumerate() {
+            api.script_get_reports_page(ApiScriptStep::ok(ReportsPage {
+                reports: vec![base64::encode(signed_report_to_bytes(report))],
+                has_more: index < pages_count - 1,
+            }));
+        }
+
+        ReportsUpdaterBuilder::new(
+            preferences,
+            tcn_dao,
+            api,
+            &MemoMapperImpl {},
+            alert_dao,
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build()
+    }
+
+    #[test]
+    fn test_reports_of_all_pages_of_interval_are_matched() {
+        let database = migrated_database();
+        let api = ScriptedTcnApi::default();
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        let reports_updater = paged_interval_updater(database, &api, alert_dao);
+
+        let alerts_res = reports_updater.update_and_fetch_alerts();
+        assert!(alerts_res.is_ok());
+        assert_eq!(3, alerts_res.unwrap().len());
+
+        assert_eq!(vec![73689, 73690], api.requested_intervals());
+        let requested_pages: Vec<(u64, u32)> = api
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call.args {
+                ApiCallArgs::GetReports {
+                    interval_number,
+                    page,
+                    ..
+                } => Some((interval_number, page)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            vec![(73689, 0), (73689, 1), (73689, 2), (73690, 0)],
+            requested_pages
+        );
+
+        let history = reports_updater.update_history().unwrap();
+        assert_eq!(3, history[0].reports);
+        assert_eq!(3, history[0].matches);
+    }
+
+    #[test]
+    fn test_interval_above_page_cap_is_rejected_as_oversized() {
+        let database = migrated_database();
+        let api = ScriptedTcnApi::default().paging_limits(PagingLimits {
+            max_pages: 2,
+            ..PagingLimits::default()
+        });
+        let alert_dao = Arc::new(AlertDaoImpl::new(database.clone()));
+        let reports_updater = paged_interval_updater(database, &api, alert_dao.clone());
+
+        match reports_updater.update_and_fetch_alerts() {
+            Err(ServicesError::Networking(error)) => assert!(error.is_payload_too_large()),
+            other => panic!("Expected networking error, got: {:?}", other),
+        }
+        // Not partially processed
+        assert!(alert_dao.all().unwrap().is_empty());
+
+        let history = reports_updater.update_history().unwrap();
+        assert_eq!(1, history[0].oversized_intervals);
+    }
+
+    #[test]
+    fn test_failed_interval_after_completed_ones_isnt_queued() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73687,
+            length: 21600,
+        });
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![])); // 73688
+        api.script_get_reports(ApiScriptStep::failure(500)); // 73689
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences.clone(),
+            Arc::new(TcnDaoImpl::new(database.clone())),
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        assert!(reports_updater.update_and_fetch_alerts().is_err());
+        // Only the empty interval completed: the failed one is fetched again with the sequence
+        assert_eq!(
+            Some(ReportsInterval {
+                number: 73688,
+                length: 21600
+            }),
+            preferences.last_completed_reports_interval()
+        );
+        assert!(preferences.reports_intervals_to_retry().is_empty());
+
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+        assert_eq!(
+            vec![73688, 73689, 73690, 73689, 73690],
+            api.requested_intervals()
+        );
+        assert_eq!(
+            Some(ReportsInterval {
+                number: 73689,
+                length: 21600
+            }),
+            preferences.last_completed_reports_interval()
+        );
+    }
+
+    #[test]
+    fn test_alert_json() {
+        let alert = Alert {
+            id: "1".to_owned(),
+            report_id: "2".to_owned(),
+            symptoms: PublicSymptoms {
+                report_time: UnixTime { value: 1590356601 },
+                earliest_symptom_time: UserInput::None,
+                fever_severity: FeverSeverity::Mild,
+                cough_severity: CoughSeverity::Dry,
+                breathlessness: true,
+                muscle_aches: false,
+                loss_smell_or_taste: false,
+                diarrhea: false,
+                runny_nose: true,
+                other: false,
+                no_symptoms: false,
+                memo_version: 1,
+                report_kind: ReportKind::Symptoms,
+            },
+            contact_start: 1000,
+            contact_end: 2000,
+            min_distance: 2.5,
+            avg_distance: 4.25,
+            p10_distance: 2.75,
+            median_distance: 4.0,
+            is_read: false,
+            has_raw_report: false,
+            report_coverage_start: Some(500),
+            report_coverage_end: None,
+            debug_info: AlertDebugInfo {
+                core_version: Some("0.1.0".to_owned()),
+                app_version: None,
+                tcn_prefixes: vec![],
+            },
+            snoozed_until: None,
+            retracted: false,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&alert).unwrap(),
+            r#"{"id":"1","report_id":"2","symptoms":{"report_time":{"value":1590356601},"earliest_symptom_time":null,"fever_severity":"Mild","cough_severity":"Dry","breathlessness":true,"muscle_aches":false,"loss_smell_or_taste":false,"diarrhea":false,"runny_nose":true,"other":false,"no_symptoms":false,"memo_version":1,"report_kind":"Symptoms"},"contact_start":1000,"contact_end":2000,"min_distance":2.5,"avg_distance":4.25,"p10_distance":2.75,"median_distance":4.0,"is_read":false,"has_raw_report":false,"report_coverage_start":500,"report_coverage_end":null,"debug_info":{"core_version":"0.1.0","app_version":null},"snoozed_until":null,"retracted":false}"#
+        );
+    }
+
+    #[test]
+    fn test_report_empty_is_none() {
+        assert!(SignedReport::with_str("").is_none())
+    }
+
+    #[test]
+    fn test_report_base64_invalid_is_none() {
+        assert!(SignedReport::with_str("%~=-🥳").is_none())
+    }
+
+    #[test]
+    fn test_report_base64_valid_report_invalid_is_none() {
+        assert!(SignedReport::with_str("slkdjfslfd").is_none())
+    }
+
+    #[test]
+    fn test_report_with_whitespace_or_url_safe_alphabet_decodes() {
+        let report_str = VERIFICATION_REPORT;
+        let report = SignedReport::with_str(report_str).unwrap();
+        assert!(matches!(normalize_report_str(report_str), Cow::Borrowed(_)));
+
+        let url_safe = report_str.replace('+', "-").replace('/', "_");
+        let url_safe_unpadded = url_safe.trim_end_matches('=').to_owned();
+        let variants = vec![
+            format!("  {}  ", report_str),
+            format!("{}\n", report_str),
+            format!("\t{}\r\n", report_str),
+            url_safe,
+            url_safe_unpadded,
+        ];
+        for variant in variants {
+            assert!(matches!(normalize_report_str(&variant), Cow::Owned(_)));
+            let decoded = SignedReport::with_str(&variant);
+            assert!(decoded.is_some(), "Couldn't decode: {:?}", variant);
+            assert_eq!(
+                signed_report_to_bytes(report.clone()),
+                signed_report_to_bytes(decoded.unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalized_reports_are_counted_in_metrics() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        preferences.set_last_completed_reports_interval(ReportsInterval {
+            number: 73688,
+            length: 21600,
+        });
+
+        let report_str = VERIFICATION_REPORT;
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![
+            report_str.to_owned(),
+            format!("{}\n", report_str),
+        ])); // 73689
+
+        let reports_updater = ReportsUpdaterBuilder::new(
+            preferences,
+            Arc::new(TcnDaoImpl::new(database.clone())),
+            &api,
+            &MemoMapperImpl {},
+            Arc::new(AlertDaoImpl::new(database.clone())),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+        )
+        .clock(|| UnixTime { value: 1591706000 }) // In interval 73690
+        .build();
+
+        assert!(reports_updater.update_and_fetch_alerts().is_ok());
+        let history = reports_updater.update_history().unwrap();
+        assert_eq!(2, history[0].reports);
+        assert_eq!(1, history[0].normalized_reports);
     }
 }
@@ -1,9 +1,11 @@
 use crate::{errors::ServicesError, tcn_recording::observed_tcn_processor::ObservedTcn};
 use log::*;
+#[cfg(feature = "parallel-matching")]
 use rayon::prelude::*;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tcn::SignedReport;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tcn::{Error as TcnError, Report, SignedReport};
 
 pub trait TcnMatcher {
     fn match_reports(
@@ -11,199 +13,360 @@ pub trait TcnMatcher {
         tcns: Vec<ObservedTcn>,
         reports: Vec<SignedReport>,
     ) -> Result<Vec<MatchedReport>, ServicesError>;
+
+    // Reports skipped since the matcher was created, because they couldn't be verified
+    fn invalid_reports(&self) -> usize {
+        0
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct MatchedReport {
     pub report: SignedReport,
     pub tcns: Vec<ObservedTcn>,
+    pub memo: Vec<u8>,    // Memo data of the verified report
+    pub tcn_count: usize, // TCNs covered by the verified report
 }
 
-pub struct TcnMatcherRayon {}
+// The matcher of the core: in parallel unless the parallel-matching feature (default) is off, e.g.
+// for single-core devices where a thread pool isn't wanted.
+#[cfg(feature = "parallel-matching")]
+pub type DefaultTcnMatcher = TcnMatcherRayon;
+#[cfg(not(feature = "parallel-matching"))]
+pub type DefaultTcnMatcher = TcnMatcherSequential;
+
+#[cfg(feature = "parallel-matching")]
+pub struct TcnMatcherRayon {
+    // Replaced in tests, to simulate verification failures
+    pub verify: fn(SignedReport) -> Result<Report, TcnError>,
+    invalid_reports: AtomicUsize,
+}
 
+#[cfg(feature = "parallel-matching")]
+impl TcnMatcherRayon {
+    pub fn new() -> TcnMatcherRayon {
+        TcnMatcherRayon {
+            verify: SignedReport::verify,
+            invalid_reports: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[cfg(feature = "parallel-matching")]
+impl Default for TcnMatcherRayon {
+    fn default() -> TcnMatcherRayon {
+        TcnMatcherRayon::new()
+    }
+}
+
+#[cfg(feature = "parallel-matching")]
 impl TcnMatcher for TcnMatcherRayon {
     fn match_reports(
         &self,
         tcns: Vec<ObservedTcn>,
         reports: Vec<SignedReport>,
     ) -> Result<Vec<MatchedReport>, ServicesError> {
-        Self::match_reports_with(tcns, reports)
+        let observed_tcns_map = observed_tcns_map(tcns);
+
+        let res: Vec<Result<Option<MatchedReport>, ServicesError>> = reports
+            .par_iter()
+            .map(|report| match_report(&observed_tcns_map, report, self.verify))
+            .collect();
+
+        Ok(collect_matches(res, &self.invalid_reports))
+    }
+
+    fn invalid_reports(&self) -> usize {
+        self.invalid_reports.load(Ordering::Relaxed)
     }
 }
 
-impl TcnMatcherRayon {
-    pub fn match_reports_with(
+// Matches the reports one after the other, on the calling thread
+#[cfg_attr(feature = "parallel-matching", allow(dead_code))] // Default without parallel-matching
+pub struct TcnMatcherSequential {
+    // Replaced in tests, to simulate verification failures
+    pub verify: fn(SignedReport) -> Result<Report, TcnError>,
+    invalid_reports: AtomicUsize,
+}
+
+#[cfg_attr(feature = "parallel-matching", allow(dead_code))]
+impl TcnMatcherSequential {
+    pub fn new() -> TcnMatcherSequential {
+        TcnMatcherSequential {
+            verify: SignedReport::verify,
+            invalid_reports: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Default for TcnMatcherSequential {
+    fn default() -> TcnMatcherSequential {
+        TcnMatcherSequential::new()
+    }
+}
+
+impl TcnMatcher for TcnMatcherSequential {
+    fn match_reports(
+        &self,
         tcns: Vec<ObservedTcn>,
         reports: Vec<SignedReport>,
     ) -> Result<Vec<MatchedReport>, ServicesError> {
-        let observed_tcns_map: HashMap<[u8; 16], ObservedTcn> =
-            tcns.into_iter().map(|e| (e.tcn.0, e)).collect();
-
-        let observed_tcns_map = Arc::new(observed_tcns_map);
+        let observed_tcns_map = observed_tcns_map(tcns);
 
-        let res: Vec<Option<MatchedReport>> = reports
-            .par_iter()
-            .map(|report| Self::match_report_with(&observed_tcns_map, report))
+        let res: Vec<Result<Option<MatchedReport>, ServicesError>> = reports
+            .iter()
+            .map(|report| match_report(&observed_tcns_map, report, self.verify))
             .collect();
 
-        let res: Vec<MatchedReport> = res
-            .into_iter()
-            .filter_map(|option| option) // drop None (reports that didn't match)
-            .collect();
+        Ok(collect_matches(res, &self.invalid_reports))
+    }
 
-        Ok(res)
-    }
-
-    pub fn match_report_with(
-        observed_tcns_map: &HashMap<[u8; 16], ObservedTcn>,
-        report: &SignedReport,
-    ) -> Option<MatchedReport> {
-        let rep = report.clone().verify();
-        match rep {
-            Ok(rep) => {
-                let mut tcns: Vec<ObservedTcn> = vec![];
-                for tcn in rep.temporary_contact_numbers() {
-                    if let Some(observed_tcn) = observed_tcns_map.get(&tcn.0) {
-                        tcns.push(observed_tcn.to_owned());
-                    }
-                }
-                if tcns.is_empty() {
-                    None
-                } else {
-                    Some(MatchedReport {
-                        report: report.clone(),
-                        tcns,
-                    })
-                }
-            }
+    fn invalid_reports(&self) -> usize {
+        self.invalid_reports.load(Ordering::Relaxed)
+    }
+}
+
+fn observed_tcns_map(tcns: Vec<ObservedTcn>) -> HashMap<[u8; 16], ObservedTcn> {
+    tcns.into_iter().map(|e| (e.tcn.0, e)).collect()
+}
+
+// Matches of the report results, in report order. Counts and logs the reports that couldn't be
+// verified.
+fn collect_matches(
+    res: Vec<Result<Option<MatchedReport>, ServicesError>>,
+    invalid_reports: &AtomicUsize,
+) -> Vec<MatchedReport> {
+    let mut matched_reports = vec![];
+    for report_res in res {
+        match report_res {
+            Ok(Some(matched_report)) => matched_reports.push(matched_report),
+            Ok(None) => {} // Didn't match
             Err(error) => {
-                error!("Report can't be matched. Verification failed: {:?}", error);
-                None
+                error!("Report can't be matched: {:?}", error);
+                invalid_reports.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
+    matched_reports
+}
+
+// Err if the report couldn't be verified. This includes verification panicking: malformed (but
+// length-valid) key material can make the tcn crate panic instead of returning an error, which would
+// abort the whole update.
+pub fn match_report(
+    observed_tcns_map: &HashMap<[u8; 16], ObservedTcn>,
+    report: &SignedReport,
+    verify: fn(SignedReport) -> Result<Report, TcnError>,
+) -> Result<Option<MatchedReport>, ServicesError> {
+    // The report is plain data: there's no state that could be left inconsistent by the panic
+    let verify_res = panic::catch_unwind(AssertUnwindSafe(|| verify(report.clone())));
+    let rep = match verify_res {
+        Ok(Ok(rep)) => rep,
+        Ok(Err(error)) => {
+            debug!("Verification failed, report sig: {}", sig_prefix(report));
+            return Err(ServicesError::from(error));
+        }
+        Err(_) => {
+            debug!("Verification panicked, report sig: {}", sig_prefix(report));
+            return Err(ServicesError::General(
+                "Report verification panicked".to_owned(),
+            ));
+        }
+    };
+
+    let mut tcns: Vec<ObservedTcn> = vec![];
+    let mut tcn_count = 0;
+    for tcn in rep.temporary_contact_numbers() {
+        tcn_count += 1;
+        if let Some(observed_tcn) = observed_tcns_map.get(&tcn.0) {
+            tcns.push(observed_tcn.to_owned());
+        }
+    }
+    if tcns.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(MatchedReport {
+            report: report.clone(),
+            tcns,
+            memo: rep.memo_data().to_vec(),
+            tcn_count,
+        }))
+    }
+}
+
+// Enough to find the report in the server's data, without logging it whole
+fn sig_prefix(report: &SignedReport) -> String {
+    let signature: [u8; 64] = report.sig.into();
+    base64::encode(&signature[..]).chars().take(12).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        reporting::{
-            memo::{MemoMapper, MemoMapperImpl},
-            public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms},
-            symptom_inputs::UserInput,
-        },
-        reports_interval::UnixTime,
-        reports_update::reports_updater::SignedReportExt,
+        config::ClientVersion,
+        reporting::memo::MemoMapperImpl,
+        reports_update::{exposure::ExposureGrouper, reports_updater::to_alerts},
         signed_report_to_bytes,
+        tcn_ext::tcn_keys::TCN_ROTATION_PERIOD_SECS,
+        test_support::fixtures::{
+            observed_tcn, seeded_test_reports, verification_observed_tcn, verification_report,
+            VERIFICATION_CONTACT_END, VERIFICATION_CONTACT_START, VERIFICATION_REPORT,
+            VERIFICATION_REPORT_SIG,
+        },
     };
     use std::time::Instant;
-    use tcn::{MemoType, ReportAuthorizationKey, TemporaryContactNumber};
 
-    #[test]
-    fn one_report_matches() {
-        let verification_report_str = "D7Z8XrufMgfsFH3K5COnv17IFG2ahDb4VM/UMK/5y0+/OtUVVTh7sN0DQ5+R+ocecTilR+SIIpPHzujeJdJzugEAECcAFAEAmmq5XgAAAACaarleAAAAACEBo8p1WdGeXb5O5/3kN6x7GSylgiYGIGsABl3NrxhJu9XHwsN3f6yvRwUxs2fhP4oU5E3+JWabBP6v09pGV1xRCw==";
-        let verification_report_tcn: [u8; 16] = [
-            24, 229, 125, 245, 98, 86, 219, 221, 172, 25, 232, 150, 206, 66, 164, 173,
-        ]; // belongs to report
-        let verification_contact_start = UnixTime { value: 1590528300 };
-        let verification_contact_end = UnixTime { value: 1590528301 };
-        let verification_min_distance = 2.3;
-        let verification_avg_distance = 3.0;
-        let verification_total_count = 3;
-        let verification_report = SignedReport::with_str(verification_report_str).unwrap();
-
-        let mut reports: Vec<SignedReport> = vec![0; 20]
-            .into_iter()
-            .map(|_| create_test_report())
-            .collect();
-        reports.push(verification_report);
+    // The matcher tests, for each implementation
+    macro_rules! matcher_tests {
+        ($module:ident, $matcher:ident) => {
+            mod $module {
+                use super::*;
 
-        // let matcher = TcnMatcherStdThreadSpawn {}; // 20 -> 1s, 200 -> 16s, 1000 -> 84s, 10000 ->
-        let matcher = TcnMatcherRayon {}; // 20 -> 1s, 200 -> 7s, 1000 -> 87s, 10000 -> 927s
+                #[test]
+                fn one_report_matches() {
+                    let verification_tcn = ObservedTcn {
+                        total_count: 3,
+                        ..verification_observed_tcn()
+                    };
 
-        let tcns = vec![
-            ObservedTcn {
-                tcn: TemporaryContactNumber([0; 16]),
-                contact_start: UnixTime { value: 1590528300 },
-                contact_end: UnixTime { value: 1590528301 },
-                min_distance: 0.0,
-                avg_distance: 0.0,
-                total_count: 1,
-            },
-            ObservedTcn {
-                tcn: TemporaryContactNumber(verification_report_tcn),
-                contact_start: verification_contact_start.clone(),
-                contact_end: verification_contact_end.clone(),
-                min_distance: verification_min_distance,
-                avg_distance: verification_avg_distance,
-                total_count: verification_total_count,
-            },
-            ObservedTcn {
-                tcn: TemporaryContactNumber([1; 16]),
-                contact_start: UnixTime { value: 1590528300 },
-                contact_end: UnixTime { value: 1590528301 },
-                min_distance: 0.0,
-                avg_distance: 0.0,
-                total_count: 1,
-            },
-        ];
+                    let mut reports = seeded_test_reports(20, 1);
+                    reports.push(verification_report());
 
-        let res = matcher.match_reports(tcns, reports);
-        let matches = res.unwrap();
-        assert_eq!(matches.len(), 1);
+                    let matcher = $matcher::new();
 
-        let matched_report_str = base64::encode(signed_report_to_bytes(matches[0].report.clone()));
-        assert_eq!(matched_report_str, verification_report_str);
-        assert_eq!(matches[0].tcns[0].contact_start, verification_contact_start);
-        assert_eq!(matches[0].tcns[0].contact_end, verification_contact_end);
-        assert_eq!(matches[0].tcns[0].min_distance, verification_min_distance);
+                    let tcns = vec![
+                        unmatched_tcn([0; 16]),
+                        verification_tcn.clone(),
+                        unmatched_tcn([1; 16]),
+                    ];
+
+                    let res = matcher.match_reports(tcns, reports);
+                    let matches = res.unwrap();
+                    assert_eq!(matches.len(), 1);
+
+                    let matched_report_str =
+                        base64::encode(signed_report_to_bytes(matches[0].report.clone()));
+                    assert_eq!(matched_report_str, VERIFICATION_REPORT);
+                    assert_eq!(
+                        matches[0].tcns[0].contact_start,
+                        verification_tcn.contact_start
+                    );
+                    assert_eq!(matches[0].tcns[0].contact_end, verification_tcn.contact_end);
+                    assert_eq!(
+                        matches[0].tcns[0].min_distance,
+                        verification_tcn.min_distance
+                    );
+                }
+
+                #[test]
+                fn reports_whose_verification_fails_or_panics_are_skipped() {
+                    let mut matcher = $matcher::new();
+                    // Only the verification report can be verified
+                    matcher.verify = |report| {
+                        let signature: [u8; 64] = report.sig.into();
+                        if base64::encode(&signature[..]) != VERIFICATION_REPORT_SIG {
+                            panic!("Malformed key material");
+                        }
+                        report.verify()
+                    };
+
+                    let mut reports = seeded_test_reports(3, 1);
+                    reports.push(verification_report());
+                    let matches = matcher
+                        .match_reports(vec![verification_observed_tcn()], reports)
+                        .unwrap();
+                    assert_eq!(1, matches.len());
+                    assert_eq!(
+                        VERIFICATION_REPORT,
+                        base64::encode(signed_report_to_bytes(matches[0].report.clone()))
+                    );
+                    assert_eq!(3, matcher.invalid_reports());
+
+                    // Counted across runs
+                    assert!(matcher
+                        .match_reports(vec![], seeded_test_reports(2, 2))
+                        .unwrap()
+                        .is_empty());
+                    assert_eq!(5, matcher.invalid_reports());
+                }
+
+                #[test]
+                fn matched_report_alert_has_report_coverage() {
+                    let matches = $matcher::new()
+                        .match_reports(
+                            vec![verification_observed_tcn()],
+                            vec![verification_report()],
+                        )
+                        .unwrap();
+                    assert_eq!(matches.len(), 1);
+                    let tcn_count = matches[0].tcn_count;
+                    assert!(tcn_count > 0);
+
+                    let alerts = to_alerts(
+                        matches,
+                        &ExposureGrouper { threshold: 1000 },
+                        &MemoMapperImpl {},
+                        &ClientVersion::default(),
+                    );
+                    assert_eq!(alerts.len(), 1);
+
+                    let coverage_start = alerts[0].report_coverage_start.unwrap();
+                    let coverage_end = alerts[0].report_coverage_end.unwrap();
+                    assert!(coverage_start < coverage_end);
+                    assert_eq!(coverage_end, alerts[0].symptoms.report_time.value);
+                    assert_eq!(
+                        coverage_end - coverage_start,
+                        tcn_count as u64 * TCN_ROTATION_PERIOD_SECS
+                    );
+                }
+
+                #[test]
+                fn matched_report_alert_has_client_version() {
+                    let matches = $matcher::new()
+                        .match_reports(
+                            vec![verification_observed_tcn()],
+                            vec![verification_report()],
+                        )
+                        .unwrap();
+
+                    let alerts = to_alerts(
+                        matches,
+                        &ExposureGrouper { threshold: 1000 },
+                        &MemoMapperImpl {},
+                        &ClientVersion {
+                            core: "0.1.0".to_owned(),
+                            app: "1.2.0 (45)".to_owned(),
+                        },
+                    );
+                    assert_eq!(alerts.len(), 1);
+                    assert_eq!(alerts[0].debug_info.core_version, Some("0.1.0".to_owned()));
+                    assert_eq!(
+                        alerts[0].debug_info.app_version,
+                        Some("1.2.0 (45)".to_owned())
+                    );
+                }
+            }
+        };
     }
 
+    #[cfg(feature = "parallel-matching")]
+    matcher_tests!(rayon_matcher, TcnMatcherRayon);
+    matcher_tests!(sequential_matcher, TcnMatcherSequential);
+
     #[test]
     #[ignore]
     fn matching_benchmark() {
-        let verification_report_str = "D7Z8XrufMgfsFH3K5COnv17IFG2ahDb4VM/UMK/5y0+/OtUVVTh7sN0DQ5+R+ocecTilR+SIIpPHzujeJdJzugEAECcAFAEAmmq5XgAAAACaarleAAAAACEBo8p1WdGeXb5O5/3kN6x7GSylgiYGIGsABl3NrxhJu9XHwsN3f6yvRwUxs2fhP4oU5E3+JWabBP6v09pGV1xRCw==";
-        let verification_report_tcn: [u8; 16] = [
-            24, 229, 125, 245, 98, 86, 219, 221, 172, 25, 232, 150, 206, 66, 164, 173,
-        ]; // belongs to report
-        let verification_contact_time = UnixTime { value: 1590528300 };
-        let verification_report = SignedReport::with_str(verification_report_str).unwrap();
-
-        let mut reports: Vec<SignedReport> = vec![0; 20]
-            .into_iter()
-            .map(|_| create_test_report())
-            .collect();
-        reports.push(verification_report);
+        let mut reports = seeded_test_reports(20, 1);
+        reports.push(verification_report());
 
         // let matcher = TcnMatcherStdThreadSpawn {}; // 20 -> 1s, 200 -> 16s, 1000 -> 84s, 10000 ->
-        let matcher = TcnMatcherRayon {}; // 20 -> 1s, 200 -> 7s, 1000 -> 87s, 10000 -> 927s
+        let matcher = DefaultTcnMatcher::new(); // Rayon: 20 -> 1s, 200 -> 7s, 1000 -> 87s, 10000 -> 927s
 
         let tcns = vec![
-            ObservedTcn {
-                tcn: TemporaryContactNumber([0; 16]),
-                contact_start: UnixTime { value: 1590528300 },
-                contact_end: UnixTime { value: 1590528301 },
-                min_distance: 0.0,
-                avg_distance: 0.0,
-                total_count: 1,
-            },
-            ObservedTcn {
-                tcn: TemporaryContactNumber(verification_report_tcn),
-                contact_start: verification_contact_time.clone(),
-                contact_end: verification_contact_time.clone(),
-                min_distance: 0.0,
-                avg_distance: 0.0,
-                total_count: 1,
-            },
-            ObservedTcn {
-                tcn: TemporaryContactNumber([1; 16]),
-                contact_start: UnixTime { value: 1590528300 },
-                contact_end: UnixTime { value: 1590528301 },
-                min_distance: 0.0,
-                avg_distance: 0.0,
-                total_count: 1,
-            },
+            unmatched_tcn([0; 16]),
+            verification_observed_tcn(),
+            unmatched_tcn([1; 16]),
         ];
 
         let matching_start_time = Instant::now();
@@ -218,27 +381,59 @@ mod tests {
 
         // Short verification that matching is working
         let matched_report_str = base64::encode(signed_report_to_bytes(matches[0].report.clone()));
-        assert_eq!(matched_report_str, verification_report_str);
-    }
-
-    fn create_test_report() -> SignedReport {
-        let memo_mapper = MemoMapperImpl {};
-        let public_symptoms = PublicSymptoms {
-            report_time: UnixTime { value: 1589209754 },
-            earliest_symptom_time: UserInput::Some(UnixTime { value: 1589209754 }),
-            fever_severity: FeverSeverity::Serious,
-            cough_severity: CoughSeverity::Existing,
-            breathlessness: true,
-            muscle_aches: true,
-            loss_smell_or_taste: false,
-            diarrhea: false,
-            runny_nose: true,
-            other: false,
-            no_symptoms: true,
-        };
-        let rak = ReportAuthorizationKey::new(rand::thread_rng());
-        let memo_data = memo_mapper.to_memo(public_symptoms);
-        rak.create_report(MemoType::CoEpiV1, memo_data.bytes, 1, 10000)
-            .unwrap()
+        assert_eq!(matched_report_str, VERIFICATION_REPORT);
+    }
+
+    #[test]
+    #[ignore]
+    fn matching_and_alert_conversion_benchmark() {
+        let reports = seeded_test_reports(1000, 1);
+
+        // Observe a TCN of each report, so all the reports match
+        let tcns: Vec<ObservedTcn> = reports
+            .iter()
+            .map(|report| {
+                let tcn = report
+                    .clone()
+                    .verify()
+                    .unwrap()
+                    .temporary_contact_numbers()
+                    .next()
+                    .unwrap();
+                unmatched_tcn(tcn.0)
+            })
+            .collect();
+
+        let matcher = DefaultTcnMatcher::new();
+
+        let matching_start_time = Instant::now();
+        let matches = matcher.match_reports(tcns, reports).unwrap();
+        let matching_time = matching_start_time.elapsed();
+
+        let conversion_start_time = Instant::now();
+        let alerts = to_alerts(
+            matches,
+            &ExposureGrouper { threshold: 1000 },
+            &MemoMapperImpl {},
+            &ClientVersion::default(),
+        );
+        let conversion_time = conversion_start_time.elapsed();
+
+        info!(
+            "Took {:?} to match reports, {:?} to convert them to alerts",
+            matching_time, conversion_time
+        );
+
+        assert_eq!(alerts.len(), 1000);
+        assert!(alerts.windows(2).all(|w| w[0].id <= w[1].id));
+    }
+
+    // Observation at the verification contact time, that doesn't belong to the verification report
+    fn unmatched_tcn(tcn: [u8; 16]) -> ObservedTcn {
+        ObservedTcn {
+            min_distance: 0.0,
+            avg_distance: 0.0,
+            ..observed_tcn(tcn, VERIFICATION_CONTACT_START, VERIFICATION_CONTACT_END)
+        }
     }
 }
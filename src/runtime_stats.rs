@@ -0,0 +1,192 @@
+// How busy the core is, for battery drain reports. Cheap enough to be polled every few seconds: counters
+// and in-memory state, and the last housekeeping runs (preferences).
+use crate::{
+    database::{preferences::Preferences, update_run_dao::UpdateRunDao},
+    housekeeping::housekeeper::{Housekeeper, LastRun},
+    reports_interval::UnixTime,
+    reports_update::reports_updater::{FetchInProgress, FetchTracker},
+    simple_logger::LogQueueStats,
+    tcn_recording::observed_tcn_processor::ObservedTcnProcessor,
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuntimeStats {
+    pub tcn_batch_size: usize, // Observed TCNs not flushed yet
+    // Log messages waiting for the app's callback
+    pub log_queue: LogQueueStats,
+    pub fetch: Option<FetchInProgress>, // None if no reports update is running
+    // Threads of the matching pool. None if the reports are matched on the calling thread.
+    pub matching_threads: Option<usize>,
+    pub housekeeping: Vec<LastRun>,
+}
+
+// Called by get_runtime_stats only, which the host build doesn't have
+#[allow(dead_code)]
+pub fn runtime_stats<O, P, Z>(
+    observed_tcn_processor: &O,
+    fetch_tracker: &FetchTracker,
+    housekeeper: &Housekeeper<P, Z>,
+    log_queue: LogQueueStats,
+    now: &UnixTime,
+) -> RuntimeStats
+where
+    O: ObservedTcnProcessor,
+    P: Preferences,
+    Z: UpdateRunDao,
+{
+    RuntimeStats {
+        tcn_batch_size: observed_tcn_processor.tcn_batch_size(),
+        log_queue,
+        fetch: fetch_tracker.in_progress(now),
+        matching_threads: matching_threads(),
+        housekeeping: housekeeper.last_runs(),
+    }
+}
+
+#[cfg(feature = "parallel-matching")]
+fn matching_threads() -> Option<usize> {
+    Some(rayon::current_num_threads())
+}
+
+#[cfg(not(feature = "parallel-matching"))]
+fn matching_threads() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RiskConfig,
+        database::{
+            preferences::{PreferencesDao, PreferencesImpl},
+            tcn_dao::TcnDaoImpl,
+            update_run_dao::UpdateRunDaoImpl,
+        },
+        housekeeping::{housekeeper::HousekeepingTask, tasks::PruneTcnsTask},
+        reports_update::exposure::ExposureGrouper,
+        tcn_recording::{
+            flush_scheduler::ManualFlushScheduler,
+            observed_tcn_processor::{DistanceBounds, ObservedTcnProcessorImpl},
+            tcn_batches_manager::TcnBatchesManager,
+        },
+        test_support::migrated_database,
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const NOW: u64 = 1591706000;
+
+    #[test]
+    fn test_runtime_stats() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+        assert!(processor
+            .save("2485a64b57addcaea3ed1b538d07dbce", 1.2)
+            .is_ok());
+        assert!(processor
+            .save("18e57df56256dbddac19e896ce42a4ad", 2.0)
+            .is_ok());
+
+        let fetch_tracker = FetchTracker::default();
+        let _fetch = fetch_tracker.start(&UnixTime { value: NOW - 12 });
+
+        let tasks: Vec<Box<dyn HousekeepingTask>> = vec![Box::new(PruneTcnsTask {
+            tcn_dao,
+            risk_config: RiskConfig::default(),
+        })];
+        let housekeeper = Housekeeper::new(
+            preferences.clone(),
+            Arc::new(UpdateRunDaoImpl::new(database)),
+            tasks,
+        );
+        preferences.set_last_housekeeping_run("prune_tcns", NOW - 3600);
+
+        let stats = runtime_stats(
+            &processor,
+            &fetch_tracker,
+            &housekeeper,
+            LogQueueStats {
+                depth: 3,
+                dropped: 1,
+            },
+            &UnixTime { value: NOW },
+        );
+
+        assert_eq!(
+            json!({
+                "tcn_batch_size": 2,
+                "log_queue": {
+                    "depth": 3,
+                    "dropped": 1
+                },
+                "fetch": {
+                    "started_at": NOW - 12,
+                    "running_secs": 12
+                },
+                "matching_threads": matching_threads(),
+                "housekeeping": [{
+                    "task": "prune_tcns",
+                    "time": NOW - 3600
+                }]
+            }),
+            serde_json::to_value(&stats).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_runtime_stats_when_idle() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+        let tasks: Vec<Box<dyn HousekeepingTask>> = vec![Box::new(PruneTcnsTask {
+            tcn_dao,
+            risk_config: RiskConfig::default(),
+        })];
+        let housekeeper = Housekeeper::new(
+            preferences,
+            Arc::new(UpdateRunDaoImpl::new(database)),
+            tasks,
+        );
+
+        let stats = runtime_stats(
+            &processor,
+            &FetchTracker::default(),
+            &housekeeper,
+            LogQueueStats {
+                depth: 0,
+                dropped: 0,
+            },
+            &UnixTime { value: NOW },
+        );
+
+        assert_eq!(0, stats.tcn_batch_size);
+        assert_eq!(None, stats.fetch);
+        assert_eq!(
+            vec![LastRun {
+                task: "prune_tcns".to_owned(),
+                time: None,
+            }],
+            stats.housekeeping
+        );
+    }
+}
@@ -3,17 +3,85 @@ use chrono::Local;
 #[cfg(not(test))]
 use chrono::Utc;
 use log::*;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
 use std::fmt;
-use std::sync::{mpsc::Sender, Once};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{self, Receiver, Sender},
+    Arc, Once,
+};
+use std::thread;
+use std::time::{Duration, Instant};
 
 static INIT: Once = Once::new();
 
 pub static mut SENDER: Option<Sender<CoreLogMessageThreadSafe>> = None;
 
+static FORWARDER_INIT: Once = Once::new();
+
+// Target of the forwarding thread. Replaced when the app registers a callback again.
+static LOG_CALLBACK: Lazy<RwLock<Option<Arc<dyn LogCallback>>>> = Lazy::new(|| RwLock::new(None));
+
+// Each callback call crosses JNI: the messages are forwarded in batches, at most every LOG_BATCH_INTERVAL
+// (see forward_messages). Errors are forwarded immediately.
+const LOG_BATCH_INTERVAL: Duration = Duration::from_millis(50);
+// Bounds the joined text of a batch
+const LOG_BATCH_MAX_MESSAGES: usize = 200;
+
+// Messages sent to SENDER and not forwarded yet, since the process started (see runtime_stats)
+pub static LOG_QUEUE: LogQueueCounters = LogQueueCounters::new();
+
+pub struct LogQueueCounters {
+    sent: AtomicU64,
+    forwarded: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogQueueStats {
+    pub depth: u64, // Sent and not forwarded yet
+    // Couldn't be sent: the forwarding thread stopped
+    pub dropped: u64,
+}
+
+impl LogQueueCounters {
+    const fn new() -> LogQueueCounters {
+        LogQueueCounters {
+            sent: AtomicU64::new(0),
+            forwarded: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn send(&self, sender: &Sender<CoreLogMessageThreadSafe>, message: CoreLogMessageThreadSafe) {
+        match sender.send(message) {
+            Ok(_) => self.sent.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    // Called by the forwarding thread, for each received message
+    pub fn forwarded(&self) {
+        self.forwarded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[allow(dead_code)] // Used by the iOS and Android interfaces
+    pub fn stats(&self) -> LogQueueStats {
+        // Forwarded first: a message sent in between isn't counted as forwarded before being sent
+        let forwarded = self.forwarded.load(Ordering::Relaxed);
+        LogQueueStats {
+            depth: self.sent.load(Ordering::Relaxed).saturating_sub(forwarded),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
 //Supress warnings when compiling in test configuration (CoreLogLevel is not used in tests)
 #[allow(dead_code)]
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CoreLogLevel {
     Trace = 0,
     Debug = 1,
@@ -35,6 +103,78 @@ pub struct CoreLogMessageThreadSafe {
     pub time: i64,
 }
 
+// Receives the log messages sent to the app. A text can be several messages of the level, newline separated.
+pub trait LogCallback: Send + Sync {
+    fn call(&self, level: CoreLogLevel, text: String);
+}
+
+// Idempotent: the app can register again (e.g. Android bootstraps on every service restart).
+// Only the callback is replaced: the messages are forwarded by a single thread, to the last registered callback.
+#[allow(dead_code)] // Used by the Android interface
+pub fn register_log_callback(callback: Arc<dyn LogCallback>) {
+    *LOG_CALLBACK.write() = Some(callback);
+
+    FORWARDER_INIT.call_once(|| {
+        let (tx, rx) = mpsc::channel::<CoreLogMessageThreadSafe>();
+
+        // Save the sender in a static variable, which will be used to push elements to the callback
+        unsafe {
+            SENDER = Some(tx);
+        }
+
+        // Thread waits for elements pushed to SENDER and calls the current callback
+        thread::spawn(move || {
+            forward_messages(rx, LOG_BATCH_INTERVAL, |level, text| {
+                // Not holding the lock while calling, so the callback can be replaced meanwhile
+                let callback = LOG_CALLBACK.read().clone();
+                if let Some(callback) = callback {
+                    callback.call(level, text);
+                }
+            })
+        });
+    });
+}
+
+// Collects the messages received within batch_interval of the first one (or until an error or
+// LOG_BATCH_MAX_MESSAGES), and delivers each run of consecutive messages of the same level as one call,
+// joined with newlines. The order of the messages is kept. Returns when the channel is closed.
+fn forward_messages<F>(rx: Receiver<CoreLogMessageThreadSafe>, batch_interval: Duration, deliver: F)
+where
+    F: Fn(CoreLogLevel, String),
+{
+    while let Ok(first) = rx.recv() {
+        LOG_QUEUE.forwarded();
+        let deadline = Instant::now() + batch_interval;
+        let mut batch = vec![first];
+        while batch.len() < LOG_BATCH_MAX_MESSAGES
+            && batch.last().map(|message| message.level) != Some(CoreLogLevel::Error)
+        {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(message) => {
+                    LOG_QUEUE.forwarded();
+                    batch.push(message)
+                }
+                // Timeout or closed channel: the received messages are delivered first
+                Err(_) => break,
+            }
+        }
+
+        let mut messages = batch.into_iter().peekable();
+        while let Some(message) = messages.next() {
+            let mut text = message.text;
+            while let Some(next) = messages.peek() {
+                if next.level != message.level {
+                    break;
+                }
+                text.push('\n');
+                text.push_str(&next.text);
+                messages.next();
+            }
+            deliver(message.level, text);
+        }
+    }
+}
+
 //Boxed logger setup
 pub fn setup_logger(level: LevelFilter, coepi_only: bool) {
     INIT.call_once(|| {
@@ -70,24 +210,26 @@ pub struct CoEpiLogger {}
 #[cfg(not(test))]
 macro_rules! log_prod {
     ($sel: ident, $record: ident) => {{
-        if $sel.enabled($record.metadata()) {
-            let arg_string = format!("{}", $record.args());
-            let lvl = match $record.level() {
-                Level::Debug => CoreLogLevel::Debug,
-                Level::Error => CoreLogLevel::Error,
-                Level::Info => CoreLogLevel::Info,
-                Level::Warn => CoreLogLevel::Warn,
-                Level::Trace => CoreLogLevel::Trace,
-            };
-
-            let lmts = CoreLogMessageThreadSafe {
-                level: lvl,
-                text: arg_string,
-                time: Utc::now().timestamp(),
-            };
-
-            SimpleLogger::log_message_to_app(lmts);
+        // Before formatting: messages of disabled levels, or without an app callback, are dropped
+        if !$sel.enabled($record.metadata()) || unsafe { SENDER.is_none() } {
+            return;
         }
+        let arg_string = format!("{}", $record.args());
+        let lvl = match $record.level() {
+            Level::Debug => CoreLogLevel::Debug,
+            Level::Error => CoreLogLevel::Error,
+            Level::Info => CoreLogLevel::Info,
+            Level::Warn => CoreLogLevel::Warn,
+            Level::Trace => CoreLogLevel::Trace,
+        };
+
+        let lmts = CoreLogMessageThreadSafe {
+            level: lvl,
+            text: arg_string,
+            time: Utc::now().timestamp(),
+        };
+
+        SimpleLogger::log_message_to_app(lmts);
     }};
 }
 
@@ -123,12 +265,11 @@ impl log::Log for CoEpiLogger {
     fn flush(&self) {}
 }
 
-#[cfg(not(test))]
 impl SimpleLogger {
     fn log_message_to_app(log_message: CoreLogMessageThreadSafe) {
         unsafe {
             if let Some(s) = &SENDER {
-                s.send(log_message).expect("Couldn't send");
+                LOG_QUEUE.send(s, log_message);
             } else {
                 println!("No SENDER!");
             }
@@ -164,3 +305,139 @@ fn verify_test_macros() {
     warn!("warn");
     error!("error");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    };
+
+    #[derive(Default)]
+    struct RecordingLogCallback {
+        texts: Mutex<Vec<String>>,
+    }
+
+    impl LogCallback for RecordingLogCallback {
+        fn call(&self, _level: CoreLogLevel, text: String) {
+            self.texts.lock().unwrap().push(text);
+        }
+    }
+
+    #[test]
+    fn test_messages_are_delivered_once_to_last_registered_callback() {
+        let first = Arc::new(RecordingLogCallback::default());
+        let second = Arc::new(RecordingLogCallback::default());
+        register_log_callback(first.clone());
+        register_log_callback(second.clone());
+
+        SimpleLogger::log_message_to_app(CoreLogMessageThreadSafe {
+            level: CoreLogLevel::Info,
+            text: "message".to_owned(),
+            time: 0,
+        });
+
+        let start = Instant::now();
+        while second.texts.lock().unwrap().is_empty() && start.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Give a duplicate delivery the chance to arrive
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(*second.texts.lock().unwrap(), vec!["message".to_owned()]);
+        assert!(first.texts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_messages_under_load_are_batched_in_order() {
+        let (tx, rx) = mpsc::channel();
+        let texts: Vec<String> = (0..1000).map(|i| format!("message {}", i)).collect();
+        for text in &texts {
+            tx.send(message(CoreLogLevel::Debug, text)).unwrap();
+        }
+        // The forwarding returns when the channel is closed
+        drop(tx);
+
+        let calls = Mutex::new(vec![]);
+        forward_messages(rx, Duration::from_secs(10), |level, text| {
+            calls.lock().unwrap().push((level, text))
+        });
+        let calls = calls.into_inner().unwrap();
+
+        // Batches of the max size, instead of a call per message
+        assert_eq!(1000 / LOG_BATCH_MAX_MESSAGES, calls.len());
+        assert!(calls.iter().all(|(level, _)| *level == CoreLogLevel::Debug));
+        let forwarded: Vec<String> = calls
+            .iter()
+            .flat_map(|(_, text)| text.split('\n').map(|text| text.to_owned()))
+            .collect();
+        assert_eq!(texts, forwarded);
+    }
+
+    #[test]
+    fn test_errors_are_forwarded_immediately() {
+        let (tx, rx) = mpsc::channel();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = calls.clone();
+        thread::spawn(move || {
+            // Long interval: only the error ends the batch
+            forward_messages(rx, Duration::from_secs(60), |level, text| {
+                recorded.lock().unwrap().push((level, text))
+            })
+        });
+
+        tx.send(message(CoreLogLevel::Debug, "debug 1")).unwrap();
+        tx.send(message(CoreLogLevel::Info, "info")).unwrap();
+        tx.send(message(CoreLogLevel::Info, "info 2")).unwrap();
+        tx.send(message(CoreLogLevel::Error, "error")).unwrap();
+
+        let start = Instant::now();
+        while calls.lock().unwrap().len() < 3 && start.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                (CoreLogLevel::Debug, "debug 1".to_owned()),
+                (CoreLogLevel::Info, "info\ninfo 2".to_owned()),
+                (CoreLogLevel::Error, "error".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_queue_counts_pending_and_dropped_messages() {
+        let counters = LogQueueCounters::new();
+        let (tx, rx) = mpsc::channel();
+        counters.send(&tx, message(CoreLogLevel::Info, "1"));
+        counters.send(&tx, message(CoreLogLevel::Info, "2"));
+        counters.forwarded();
+        assert_eq!(
+            LogQueueStats {
+                depth: 1,
+                dropped: 0
+            },
+            counters.stats()
+        );
+
+        // The forwarding thread stopped
+        drop(rx);
+        counters.send(&tx, message(CoreLogLevel::Info, "3"));
+        assert_eq!(
+            LogQueueStats {
+                depth: 1,
+                dropped: 1
+            },
+            counters.stats()
+        );
+    }
+
+    fn message(level: CoreLogLevel, text: &str) -> CoreLogMessageThreadSafe {
+        CoreLogMessageThreadSafe {
+            level,
+            text: text.to_owned(),
+            time: 0,
+        }
+    }
+}
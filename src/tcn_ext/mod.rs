@@ -1 +1,2 @@
+pub mod tcn_hex;
 pub mod tcn_keys;
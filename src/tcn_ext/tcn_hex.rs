@@ -0,0 +1,216 @@
+use crate::{byte_vec_to_16_byte_array, errors::ServicesError, expect_log};
+#[cfg(target_os = "android")]
+use log::error;
+use std::fmt;
+use tcn::TemporaryContactNumber;
+
+const TCN_HEX_LENGTH: usize = 32; // 16 bytes
+const TCN_BASE64_LENGTH: usize = 24; // 16 bytes, padded
+
+// Hex encoded TCN, as exchanged with the apps and stored in the database.
+// Always valid (32 lowercase hex characters).
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct TcnHex(String);
+
+impl TcnHex {
+    // The error message classifies the failure (invalid char, odd length, byte count),
+    // to tell scanner / payload parsing bugs apart in field logs.
+    pub fn new(str: &str) -> Result<TcnHex, ServicesError> {
+        if let Some((index, c)) = str
+            .chars()
+            .enumerate()
+            .find(|(_, c)| !c.is_ascii_hexdigit())
+        {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid TCN (hex): invalid char {:?} at index {}",
+                c, index
+            )));
+        }
+        // Only ASCII from here: length in bytes is length in chars
+        if str.len() % 2 != 0 {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid TCN (hex): odd length: {}",
+                str.len()
+            )));
+        }
+        if str.len() != TCN_HEX_LENGTH {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid TCN (hex): {} bytes, expected: {}",
+                str.len() / 2,
+                TCN_HEX_LENGTH / 2
+            )));
+        }
+        // Lowercase, to match hex::encode (stored TCNs are compared as strings)
+        Ok(TcnHex(str.to_ascii_lowercase()))
+    }
+
+    // TCNs from the apps: hex, or base64 (the iOS scanner library's encoding).
+    // Only hex digits or 32 chars is hex, 24 chars is base64: the encodings of 16 bytes can't be confused,
+    // since base64 ends with padding. Errors name the detected encoding.
+    pub fn parse(str: &str) -> Result<TcnHex, ServicesError> {
+        let chars_count = str.chars().count();
+        let is_hex = chars_count == TCN_HEX_LENGTH || str.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hex || chars_count != TCN_BASE64_LENGTH {
+            return TcnHex::new(str);
+        }
+
+        let bytes = base64::decode(str).map_err(|error| {
+            ServicesError::FFIParameters(format!("Invalid TCN (base64): {}", error))
+        })?;
+        if bytes.len() != TCN_HEX_LENGTH / 2 {
+            return Err(ServicesError::FFIParameters(format!(
+                "Invalid TCN (base64): {} bytes, expected: {}",
+                bytes.len(),
+                TCN_HEX_LENGTH / 2
+            )));
+        }
+        Ok(TcnHex(hex::encode(bytes)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    pub fn as_bytes16(&self) -> [u8; 16] {
+        let bytes_res = hex::decode(&self.0);
+        let bytes = expect_log!(bytes_res, "TcnHex should be valid hex");
+        let array_res = byte_vec_to_16_byte_array(bytes);
+        expect_log!(array_res, "TcnHex should be 16 bytes")
+    }
+
+    pub fn to_tcn(&self) -> TemporaryContactNumber {
+        TemporaryContactNumber(self.as_bytes16())
+    }
+}
+
+impl From<&TemporaryContactNumber> for TcnHex {
+    fn from(tcn: &TemporaryContactNumber) -> Self {
+        TcnHex(hex::encode(tcn.0))
+    }
+}
+
+// TCNs are personal data: only shown in debug builds.
+// Use as_str() / into_string() where the value itself is needed.
+impl fmt::Display for TcnHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(debug_assertions) {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "<TCN>")
+        }
+    }
+}
+
+impl fmt::Debug for TcnHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TcnHex({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_tcn_hex() {
+        let tcn_hex = TcnHex::new("2485a64b57addcaea3ed1b538d07dbce").unwrap();
+        assert_eq!(
+            [36, 133, 166, 75, 87, 173, 220, 174, 163, 237, 27, 83, 141, 7, 219, 206],
+            tcn_hex.as_bytes16()
+        );
+    }
+
+    #[test]
+    fn test_uppercase_tcn_hex_is_lowercased() {
+        let tcn_hex = TcnHex::new("2485A64B57ADDCAEA3ED1B538D07DBCE").unwrap();
+        assert_eq!("2485a64b57addcaea3ed1b538d07dbce", tcn_hex.as_str());
+    }
+
+    #[test]
+    fn test_empty_tcn_hex_is_error() {
+        assert!(TcnHex::new("").is_err());
+    }
+
+    #[test]
+    fn test_too_short_tcn_hex_is_error() {
+        assert!(TcnHex::new("2485a64b57addcaea3ed1b538d07dbc").is_err());
+    }
+
+    #[test]
+    fn test_too_long_tcn_hex_is_error() {
+        assert!(TcnHex::new("2485a64b57addcaea3ed1b538d07dbce00").is_err());
+    }
+
+    #[test]
+    fn test_non_hex_tcn_hex_is_error() {
+        assert!(TcnHex::new("2485a64b57addcaea3ed1b538d07dbcg").is_err());
+    }
+
+    #[test]
+    fn test_multibyte_tcn_hex_is_error() {
+        // 32 bytes, but not 32 hex characters
+        assert!(TcnHex::new("2485a64b57addcaea3ed1b538d07dbé").is_err());
+    }
+
+    #[test]
+    fn test_parses_hex_and_base64_tcns() {
+        let expected = "2485a64b57addcaea3ed1b538d07dbce";
+        assert_eq!(expected, TcnHex::parse(expected).unwrap().as_str());
+        assert_eq!(
+            expected,
+            TcnHex::parse("JIWmS1et3K6j7RtTjQfbzg==").unwrap().as_str()
+        );
+    }
+
+    #[test]
+    fn test_parses_ambiguous_looking_tcns_as_hex() {
+        // Also valid base64 (of 24 bytes)
+        let tcn = "2485a64b57addcaea3ed1b538d07dbce";
+        assert_eq!(tcn, TcnHex::parse(tcn).unwrap().as_str());
+
+        // Base64 length, but only hex digits
+        assert_invalid(
+            "2485a64b57addcaea3ed1b53",
+            "Invalid TCN (hex): 12 bytes, expected: 16",
+        );
+    }
+
+    #[test]
+    fn test_invalid_tcns_name_the_detected_encoding() {
+        assert_invalid(
+            "2485a64b57addcaea3ed1b538d07dbcx",
+            "Invalid TCN (hex): invalid char 'x' at index 31",
+        );
+        assert_invalid("JIWmS1et3K6j7RtTjQfbzg", "Invalid TCN (hex): invalid char");
+        assert_invalid(
+            "JIWmS1et3K6j7RtTjQfb*g==",
+            "Invalid TCN (base64): Invalid byte",
+        );
+        assert_invalid(
+            "JIWmS1et3K6j7RtTjQfbzgAA",
+            "Invalid TCN (base64): 18 bytes, expected: 16",
+        );
+    }
+
+    fn assert_invalid(tcn: &str, expected_message: &str) {
+        match TcnHex::parse(tcn) {
+            Err(ServicesError::FFIParameters(message)) => assert!(
+                message.starts_with(expected_message),
+                "Message: {:?}, expected: {:?}",
+                message,
+                expected_message
+            ),
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tcn_hex_roundtrip() {
+        let tcn = TemporaryContactNumber([7; 16]);
+        assert_eq!(tcn, TcnHex::from(&tcn).to_tcn());
+    }
+}
@@ -1,17 +1,59 @@
 use crate::{
+    byte_vec_to_32_byte_array,
     database::preferences::{Preferences, TckBytesWrapper, TCK_SIZE_IN_BYTES},
+    errors::ServicesError,
     expect_log,
+    reports_interval::UnixTime,
+    tcn_ext::tcn_hex::TcnHex,
 };
 use log::*;
-use std::{io::Cursor, sync::Arc};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
 use tcn::{
-    Error, MemoType, ReportAuthorizationKey, SignedReport, TemporaryContactKey,
-    TemporaryContactNumber,
+    MemoType, ReportAuthorizationKey, SignedReport, TemporaryContactKey, TemporaryContactNumber,
 };
 
+// Expected time between TCN rotations (generate_tcn calls)
+pub const TCN_ROTATION_PERIOD_SECS: u64 = 15 * 60;
+
+// Stored TCK bytes: index (2), report verification key (32), TCK (32)
+const TCK_RVK_RANGE: std::ops::Range<usize> = 2..34;
+
+// Why the stored keys were replaced (see TcnKeysImpl::check_integrity)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyIntegrityIssue {
+    UnreadableAuthorizationKey,
+    UnreadableTck,
+    // The TCK wasn't derived from the stored authorization key, or there's no authorization key
+    TckKeyMismatch,
+}
+
 pub trait TcnKeys {
-    fn create_report(&self, report: Vec<u8>) -> Result<SignedReport, Error>;
-    fn generate_tcn(&self) -> TemporaryContactNumber;
+    fn create_report(&self, report: Vec<u8>) -> Result<SignedReport, ServicesError>;
+    fn generate_tcn(&self) -> Result<TemporaryContactNumber, ServicesError>;
+    // TCN to broadcast now and the time at which the next TCK index begins (expiry).
+    // Indices are aligned to rotation periods, so calls before the expiry return the same TCN.
+    fn current_tcn_with_expiry(&self) -> Result<(TemporaryContactNumber, UnixTime), ServicesError>;
+}
+
+// FFI representation of current_tcn_with_expiry
+#[derive(Debug, Serialize)]
+pub struct TcnWithExpiry {
+    pub tcn: String, // Hex
+    pub expiry: u64,
+}
+
+impl TcnWithExpiry {
+    pub fn new(tcn: &TemporaryContactNumber, expiry: UnixTime) -> TcnWithExpiry {
+        TcnWithExpiry {
+            tcn: TcnHex::from(tcn).into_string(),
+            expiry: expiry.value,
+        }
+    }
 }
 
 pub trait ReportAuthorizationKeyExt {
@@ -39,37 +81,96 @@ where
     T: Preferences,
 {
     pub preferences: Arc<T>,
+    // Single writer for the keys stored in preferences. generate_tcn (BLE advertiser thread)
+    // reads, ratchets and writes back the TCK: without it concurrent calls can lose a ratchet
+    // and broadcast the same TCN twice. create_report holds it too, to read a consistent RAK / TCK.
+    keys_lock: Mutex<()>,
+    pub clock: fn() -> UnixTime,
+}
+
+impl<T> TcnKeysImpl<T>
+where
+    T: Preferences,
+{
+    pub fn new(preferences: Arc<T>) -> TcnKeysImpl<T> {
+        TcnKeysImpl {
+            preferences,
+            keys_lock: Mutex::new(()),
+            clock: UnixTime::now,
+        }
+    }
+
+    pub fn clock(mut self, clock: fn() -> UnixTime) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 impl<T> TcnKeys for TcnKeysImpl<T>
 where
     T: Preferences,
 {
-    fn create_report(&self, report: Vec<u8>) -> Result<SignedReport, Error> {
-        let end_index = self.tck().index();
-        let periods = 14 * 24 * (60 / 15);
+    fn create_report(&self, report: Vec<u8>) -> Result<SignedReport, ServicesError> {
+        let keys_lock_res = self.keys_lock.lock();
+        let _keys_lock = expect_log!(keys_lock_res, "Couldn't lock keys");
+
+        let end_index = self.tck()?.index();
+        let periods = (14 * 24 * 60 * 60 / TCN_ROTATION_PERIOD_SECS) as u16;
         let mut start_index = 1;
         if end_index > periods {
             start_index = (end_index - periods) as u16
         }
         debug!("start_index={}, end_index={}", start_index, end_index);
 
-        self.rak()
-            .create_report(MemoType::CoEpiV1, report, start_index, end_index)
+        Ok(self
+            .rak()?
+            .create_report(MemoType::CoEpiV1, report, start_index, end_index)?)
     }
 
-    fn generate_tcn(&self) -> TemporaryContactNumber {
-        let tck = self.tck();
+    fn generate_tcn(&self) -> Result<TemporaryContactNumber, ServicesError> {
+        let keys_lock_res = self.keys_lock.lock();
+        let _keys_lock = expect_log!(keys_lock_res, "Couldn't lock keys");
+
+        let tck = self.tck()?;
         let tcn = tck.temporary_contact_number();
         let new_tck = tck.ratchet();
 
         if let Some(new_tck) = new_tck {
-            self.set_tck(new_tck);
+            self.set_tck(new_tck)?;
         }
 
         debug!("Generated tcn: {:?}", tcn);
         // TODO: if None, rotate RAK
-        tcn
+        Ok(tcn)
+    }
+
+    fn current_tcn_with_expiry(&self) -> Result<(TemporaryContactNumber, UnixTime), ServicesError> {
+        let keys_lock_res = self.keys_lock.lock();
+        let _keys_lock = expect_log!(keys_lock_res, "Couldn't lock keys");
+
+        let now = (self.clock)().value;
+        let mut tck = self.tck()?;
+        let epoch = self.tcn_epoch(now, tck.index());
+
+        let current_index = 1 + now.saturating_sub(epoch) / TCN_ROTATION_PERIOD_SECS;
+        // The stored TCK can be ahead (generate_tcn): it's kept, to not broadcast a TCN twice
+        let ratchet = (tck.index() as u64) < current_index;
+        while (tck.index() as u64) < current_index {
+            tck = tck.ratchet().ok_or_else(|| {
+                // TODO: rotate RAK
+                ServicesError::General("TCK indices exhausted".to_owned())
+            })?;
+        }
+
+        let tcn = tck.temporary_contact_number();
+        let expiry = UnixTime {
+            value: epoch + tck.index() as u64 * TCN_ROTATION_PERIOD_SECS,
+        };
+        if ratchet {
+            self.set_tck(tck)?;
+        }
+        debug!("Current tcn: {:?}, expiry: {:?}", tcn, expiry);
+        Ok((tcn, expiry))
     }
 }
 
@@ -77,41 +178,119 @@ impl<T> TcnKeysImpl<T>
 where
     T: Preferences,
 {
-    fn rak(&self) -> ReportAuthorizationKey {
+    // Called on bootstrap. Stored keys that can't be read or don't belong together would make
+    // generate_tcn / create_report fail (or panic) from then on: they're archived (see
+    // Preferences::archive_keys) and replaced with new ones. Returns the issue, if there was one: the
+    // TCNs broadcasted before can't be reported anymore, which the app should tell the user.
+    // Err only if the keys couldn't be loaded or saved.
+    pub fn check_integrity(&self) -> Result<Option<KeyIntegrityIssue>, ServicesError> {
+        let keys_lock_res = self.keys_lock.lock();
+        let _keys_lock = expect_log!(keys_lock_res, "Couldn't lock keys");
+
+        let issue = match self.integrity_issue()? {
+            Some(issue) => issue,
+            None => return Ok(None),
+        };
+
+        error!(
+            "Stored keys failed the integrity check: {:?}. Archiving them and creating new keys. \
+            Reports can't cover the TCNs broadcasted until now.",
+            issue
+        );
+        self.preferences.archive_keys((self.clock)().value, issue)?;
+
+        let new_key = ReportAuthorizationKey::new(rand::thread_rng());
         self.preferences
-            .authorization_key()
-            .map(|rak_bytes| ReportAuthorizationKey::with_bytes(rak_bytes)) //Self::bytes_to_rak(rak_bytes))
-            .unwrap_or_else(|| {
+            .set_autorization_key(Self::rak_to_bytes(new_key))?;
+        self.set_tck(new_key.initial_temporary_contact_key())?;
+
+        Ok(Some(issue))
+    }
+
+    fn integrity_issue(&self) -> Result<Option<KeyIntegrityIssue>, ServicesError> {
+        let rak_bytes = match self.preferences.authorization_key() {
+            Ok(rak_bytes) => rak_bytes,
+            Err(ServicesError::Database(message)) => return Err(ServicesError::Database(message)),
+            Err(error) => {
+                error!("Stored authorization key can't be read: {:?}", error);
+                return Ok(Some(KeyIntegrityIssue::UnreadableAuthorizationKey));
+            }
+        };
+
+        let tck_bytes = match self.preferences.tck() {
+            // Derived from the authorization key when needed
+            Ok(None) => return Ok(None),
+            Ok(Some(tck_bytes)) => tck_bytes,
+            Err(ServicesError::Database(message)) => return Err(ServicesError::Database(message)),
+            Err(error) => {
+                error!("Stored TCK can't be read: {:?}", error);
+                return Ok(Some(KeyIntegrityIssue::UnreadableTck));
+            }
+        };
+        if let Err(error) = Self::bytes_to_tck(tck_bytes) {
+            error!("Stored TCK can't be read: {:?}", error);
+            return Ok(Some(KeyIntegrityIssue::UnreadableTck));
+        }
+
+        // The TCKs of an authorization key contain its verification key
+        let rak_tck_bytes = rak_bytes.map(|rak_bytes| {
+            Self::tck_to_bytes(
+                ReportAuthorizationKey::with_bytes(rak_bytes).initial_temporary_contact_key(),
+            )
+        });
+        match rak_tck_bytes {
+            Some(rak_tck_bytes)
+                if rak_tck_bytes.tck_bytes[TCK_RVK_RANGE] == tck_bytes.tck_bytes[TCK_RVK_RANGE] =>
+            {
+                Ok(None)
+            }
+            _ => Ok(Some(KeyIntegrityIssue::TckKeyMismatch)),
+        }
+    }
+
+    // Created if there's none yet. If it can't be read, it's an error: a new key would
+    // make the previously broadcasted TCNs unreportable.
+    fn rak(&self) -> Result<ReportAuthorizationKey, ServicesError> {
+        match self.preferences.authorization_key()? {
+            Some(rak_bytes) => Ok(ReportAuthorizationKey::with_bytes(rak_bytes)),
+            None => {
                 let new_key = ReportAuthorizationKey::new(rand::thread_rng());
                 self.preferences
-                    .set_autorization_key(Self::rak_to_bytes(new_key));
-                new_key
-            })
+                    .set_autorization_key(Self::rak_to_bytes(new_key))?;
+                Ok(new_key)
+            }
+        }
     }
 
-    fn tck(&self) -> TemporaryContactKey {
-        self.preferences
-            .tck()
-            .map(|tck_bytes| Self::bytes_to_tck(tck_bytes))
-            .unwrap_or_else(|| self.rak().initial_temporary_contact_key())
+    fn tck(&self) -> Result<TemporaryContactKey, ServicesError> {
+        match self.preferences.tck()? {
+            Some(tck_bytes) => Self::bytes_to_tck(tck_bytes),
+            None => Ok(self.rak()?.initial_temporary_contact_key()),
+        }
+    }
+
+    fn set_tck(&self, tck: TemporaryContactKey) -> Result<(), ServicesError> {
+        self.preferences.set_tck(Self::tck_to_bytes(tck))
     }
 
-    fn set_tck(&self, tck: TemporaryContactKey) {
-        self.preferences.set_tck(Self::tck_to_bytes(tck));
+    // If not stored yet (first call, or keys created before the epoch existed), anchored such that
+    // the TCK at tck_index is the current one. Aligned to the clock's rotation periods.
+    fn tcn_epoch(&self, now: u64, tck_index: u16) -> u64 {
+        self.preferences.tcn_epoch().unwrap_or_else(|| {
+            let period_start = now - now % TCN_ROTATION_PERIOD_SECS;
+            let epoch =
+                period_start.saturating_sub((tck_index as u64 - 1) * TCN_ROTATION_PERIOD_SECS);
+            self.preferences.set_tcn_epoch(epoch);
+            epoch
+        })
     }
 
     fn rak_to_bytes(rak: ReportAuthorizationKey) -> [u8; 32] {
         let mut buf = Vec::new();
         let res = rak.write(Cursor::new(&mut buf));
         expect_log!(res, "Couldn't write RAK bytes");
-        Self::byte_vec_to_32_byte_array(buf)
-    }
-
-    fn byte_vec_to_32_byte_array(bytes: Vec<u8>) -> [u8; 32] {
-        let mut array = [0; 32];
-        let bytes = &bytes[..array.len()]; // panics if not enough data
-        array.copy_from_slice(bytes);
-        array
+        let bytes_res = byte_vec_to_32_byte_array(buf);
+        expect_log!(bytes_res, "RAK should be 32 bytes")
     }
 
     pub fn tck_to_bytes(tck: TemporaryContactKey) -> TckBytesWrapper {
@@ -122,16 +301,20 @@ where
         TckBytesWrapper::with_bytes(buf)
     }
 
-    fn bytes_to_tck(tck: TckBytesWrapper) -> TemporaryContactKey {
-        let res = TemporaryContactKey::read(Cursor::new(&tck));
-        expect_log!(res, "Couldn't read TCK bytes")
+    fn bytes_to_tck(tck: TckBytesWrapper) -> Result<TemporaryContactKey, ServicesError> {
+        TemporaryContactKey::read(Cursor::new(&tck))
+            .map_err(|e| ServicesError::General(format!("Couldn't read TCK bytes: {}", e)))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::preferences::PreferencesTckMock;
+    use crate::{
+        database::preferences::{PreferencesDao, PreferencesImpl, PreferencesTckMock},
+        test_support::{fixtures::TEST_RAK_BYTES, migrated_database},
+    };
+    use std::{collections::HashSet, thread};
 
     #[test]
     fn test_rak() {
@@ -142,10 +325,7 @@ mod tests {
 
     #[test]
     fn test_load_rak() {
-        let bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let bytes = TEST_RAK_BYTES;
         let key = ReportAuthorizationKey::with_bytes(bytes);
         let tck = key.initial_temporary_contact_key();
         TcnKeysImpl::<PreferencesTckMock>::tck_to_bytes(tck);
@@ -153,10 +333,7 @@ mod tests {
 
     #[test]
     fn test_load_tck() {
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
         let rak = ReportAuthorizationKey::with_bytes(rak_bytes);
         let _tck_1 = rak.initial_temporary_contact_key();
 
@@ -175,17 +352,14 @@ mod tests {
         let complete_tck_vec = [&version_vec[..], &rak_vec[..], &tck_inner_vec[..]].concat();
 
         let tck_bytes_wrapped = TckBytesWrapper::with_bytes(complete_tck_vec);
-        let tck = TcnKeysImpl::<PreferencesTckMock>::bytes_to_tck(tck_bytes_wrapped);
+        let tck = TcnKeysImpl::<PreferencesTckMock>::bytes_to_tck(tck_bytes_wrapped).unwrap();
 
         debug!("{:?}", tck);
     }
 
     #[test]
     fn test_generate_tcns() {
-        let rak_bytes = [
-            42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195,
-            126, 139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
-        ];
+        let rak_bytes = TEST_RAK_BYTES;
 
         let rak = ReportAuthorizationKey::with_bytes(rak_bytes);
         let mut tck = rak.initial_temporary_contact_key(); // tck <- tck_1
@@ -199,4 +373,268 @@ mod tests {
         info!("Number of generated TCNS: {}", tcns.len());
         assert_eq!(100, tcns.len(), "Expected 100 TCNs");
     }
+
+    #[test]
+    fn test_concurrent_generate_tcn_ratchets_once_per_call() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        });
+        let tcn_keys = Arc::new(TcnKeysImpl::new(preferences.clone()));
+
+        let threads = 8;
+        let tcns_per_thread = 50;
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let tcn_keys = tcn_keys.clone();
+                thread::spawn(move || {
+                    (0..tcns_per_thread)
+                        .map(|_| tcn_keys.generate_tcn().unwrap().0)
+                        .collect::<Vec<[u8; 16]>>()
+                })
+            })
+            .collect();
+
+        let tcns: Vec<[u8; 16]> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        let count = threads * tcns_per_thread;
+        assert_eq!(count, tcns.len());
+
+        let distinct_tcns: HashSet<[u8; 16]> = tcns.into_iter().collect();
+        assert_eq!(count, distinct_tcns.len());
+
+        // Index of the initial TCK is 1, each call ratchets it once
+        let stored_tck =
+            TcnKeysImpl::<PreferencesImpl>::bytes_to_tck(preferences.tck().unwrap().unwrap())
+                .unwrap();
+        assert_eq!(count as u16 + 1, stored_tck.index());
+    }
+
+    #[test]
+    fn test_current_tcn_expiry_is_aligned_to_index_boundaries() {
+        let preferences = create_test_preferences();
+        // Period 1768562 starts at 1591705800
+        let tcn_keys =
+            TcnKeysImpl::new(preferences.clone()).clock(|| UnixTime { value: 1591706000 });
+
+        let (tcn, expiry) = tcn_keys.current_tcn_with_expiry().unwrap();
+        assert_eq!(1591705800 + TCN_ROTATION_PERIOD_SECS, expiry.value);
+        assert_eq!(0, expiry.value % TCN_ROTATION_PERIOD_SECS);
+        assert_eq!(Some(1591705800), preferences.tcn_epoch());
+        // The initial TCK
+        assert_eq!(
+            tcn_keys
+                .rak()
+                .unwrap()
+                .initial_temporary_contact_key()
+                .temporary_contact_number()
+                .0,
+            tcn.0
+        );
+    }
+
+    #[test]
+    fn test_current_tcn_is_same_until_expiry() {
+        let preferences = create_test_preferences();
+        let tcn_keys =
+            TcnKeysImpl::new(preferences.clone()).clock(|| UnixTime { value: 1591706000 });
+        let (tcn, expiry) = tcn_keys.current_tcn_with_expiry().unwrap();
+        assert_eq!(
+            (tcn.0, expiry.clone()),
+            tcn_keys
+                .current_tcn_with_expiry()
+                .map(|(tcn, expiry)| (tcn.0, expiry))
+                .unwrap()
+        );
+
+        // Last second before the expiry
+        let tcn_keys_before_expiry =
+            TcnKeysImpl::new(preferences.clone()).clock(|| UnixTime { value: 1591706699 });
+        let (tcn_before_expiry, expiry_before_expiry) =
+            tcn_keys_before_expiry.current_tcn_with_expiry().unwrap();
+        assert_eq!(tcn.0, tcn_before_expiry.0);
+        assert_eq!(expiry, expiry_before_expiry);
+
+        // Two periods later
+        let tcn_keys_later =
+            TcnKeysImpl::new(preferences.clone()).clock(|| UnixTime { value: 1591707700 });
+        let (later_tcn, later_expiry) = tcn_keys_later.current_tcn_with_expiry().unwrap();
+        assert_ne!(tcn.0, later_tcn.0);
+        assert_eq!(
+            expiry.value + 2 * TCN_ROTATION_PERIOD_SECS,
+            later_expiry.value
+        );
+        let stored_tck =
+            TcnKeysImpl::<PreferencesImpl>::bytes_to_tck(preferences.tck().unwrap().unwrap())
+                .unwrap();
+        assert_eq!(3, stored_tck.index());
+    }
+
+    #[test]
+    fn test_unreadable_keys_are_errors() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let tcn_keys = TcnKeysImpl::new(preferences).clock(|| UnixTime { value: 1591706000 });
+        // The RAK can't be read nor created
+        database
+            .execute_sql("drop table preferences", rusqlite::params![])
+            .unwrap();
+
+        assert!(tcn_keys.generate_tcn().is_err());
+        assert!(tcn_keys.current_tcn_with_expiry().is_err());
+        assert!(tcn_keys.create_report(vec![1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_existing_rak_is_not_replaced_after_read_error() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let rak_bytes = TEST_RAK_BYTES;
+        preferences.set_autorization_key(rak_bytes).unwrap();
+        // Not hex: the stored key can't be read
+        preferences.dao.save("authorization_key", "invalid");
+
+        let tcn_keys = TcnKeysImpl::new(preferences.clone());
+        assert!(tcn_keys.generate_tcn().is_err());
+        assert_eq!(
+            Some("invalid".to_owned()),
+            preferences.dao.load("authorization_key")
+        );
+    }
+
+    #[test]
+    fn test_check_integrity_keeps_valid_keys() {
+        let preferences = create_test_preferences();
+        let tcn_keys = TcnKeysImpl::new(preferences.clone());
+        assert_eq!(Ok(None), tcn_keys.check_integrity().map_err(|_| ()));
+
+        // Created by the first TCN
+        assert!(tcn_keys.generate_tcn().is_ok());
+        let rak_bytes = preferences.authorization_key().unwrap();
+        let tck_bytes = preferences.tck().unwrap();
+
+        assert_eq!(Ok(None), tcn_keys.check_integrity().map_err(|_| ()));
+        assert_eq!(rak_bytes, preferences.authorization_key().unwrap());
+        assert_eq!(tck_bytes, preferences.tck().unwrap());
+        assert_eq!(None, preferences.archived_keys());
+    }
+
+    #[test]
+    fn test_check_integrity_replaces_truncated_tck() {
+        let preferences = create_test_preferences();
+        let tcn_keys =
+            TcnKeysImpl::new(preferences.clone()).clock(|| UnixTime { value: 1591706000 });
+        assert!(tcn_keys.generate_tcn().is_ok());
+        let rak_bytes = preferences.authorization_key().unwrap();
+        let tck_str = preferences.dao.load("tck").unwrap();
+        let truncated_tck_str = tck_str[..tck_str.len() / 2].to_owned();
+        preferences.dao.save("tck", &truncated_tck_str);
+        assert!(tcn_keys.generate_tcn().is_err());
+
+        assert_eq!(
+            Ok(Some(KeyIntegrityIssue::UnreadableTck)),
+            tcn_keys.check_integrity().map_err(|_| ())
+        );
+
+        let archived_keys = preferences.archived_keys().unwrap();
+        assert_eq!(1591706000, archived_keys.time);
+        assert_eq!(KeyIntegrityIssue::UnreadableTck, archived_keys.issue);
+        assert_eq!(rak_bytes.map(hex::encode), archived_keys.authorization_key);
+        assert_eq!(Some(truncated_tck_str), archived_keys.tck);
+
+        // New keys
+        assert_ne!(rak_bytes, preferences.authorization_key().unwrap());
+        assert!(tcn_keys.generate_tcn().is_ok());
+        assert!(tcn_keys.create_report(vec![1, 2, 3]).is_ok());
+        assert_eq!(Ok(None), tcn_keys.check_integrity().map_err(|_| ()));
+    }
+
+    #[test]
+    fn test_check_integrity_replaces_truncated_authorization_key() {
+        let preferences = create_test_preferences();
+        let tcn_keys = TcnKeysImpl::new(preferences.clone());
+        assert!(tcn_keys.generate_tcn().is_ok());
+        let rak_str = preferences.dao.load("authorization_key").unwrap();
+        preferences.dao.save("authorization_key", &rak_str[..20]);
+        assert!(tcn_keys.create_report(vec![1, 2, 3]).is_err());
+
+        assert_eq!(
+            Ok(Some(KeyIntegrityIssue::UnreadableAuthorizationKey)),
+            tcn_keys.check_integrity().map_err(|_| ())
+        );
+        assert_eq!(
+            Some(rak_str[..20].to_owned()),
+            preferences.archived_keys().unwrap().authorization_key
+        );
+        assert!(tcn_keys.generate_tcn().is_ok());
+        assert!(tcn_keys.create_report(vec![1, 2, 3]).is_ok());
+    }
+
+    #[test]
+    fn test_check_integrity_replaces_tck_of_other_key() {
+        let preferences = create_test_preferences();
+        let tcn_keys = TcnKeysImpl::new(preferences.clone());
+        assert!(tcn_keys.generate_tcn().is_ok());
+        let other_key = ReportAuthorizationKey::with_bytes(TEST_RAK_BYTES);
+        preferences
+            .set_tck(TcnKeysImpl::<PreferencesImpl>::tck_to_bytes(
+                other_key.initial_temporary_contact_key(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            Ok(Some(KeyIntegrityIssue::TckKeyMismatch)),
+            tcn_keys.check_integrity().map_err(|_| ())
+        );
+        assert_eq!(Ok(None), tcn_keys.check_integrity().map_err(|_| ()));
+    }
+
+    #[test]
+    fn test_check_integrity_replaces_tck_without_authorization_key() {
+        let preferences = create_test_preferences();
+        let other_key = ReportAuthorizationKey::with_bytes(TEST_RAK_BYTES);
+        preferences
+            .set_tck(TcnKeysImpl::<PreferencesImpl>::tck_to_bytes(
+                other_key.initial_temporary_contact_key(),
+            ))
+            .unwrap();
+        let tcn_keys = TcnKeysImpl::new(preferences.clone());
+
+        assert_eq!(
+            Ok(Some(KeyIntegrityIssue::TckKeyMismatch)),
+            tcn_keys.check_integrity().map_err(|_| ())
+        );
+        assert!(preferences.authorization_key().unwrap().is_some());
+        assert_eq!(Ok(None), tcn_keys.check_integrity().map_err(|_| ()));
+    }
+
+    #[test]
+    fn test_check_integrity_fails_if_keys_cant_be_loaded() {
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let tcn_keys = TcnKeysImpl::new(preferences);
+        database
+            .execute_sql("drop table preferences", rusqlite::params![])
+            .unwrap();
+
+        assert!(matches!(
+            tcn_keys.check_integrity(),
+            Err(ServicesError::Database(_))
+        ));
+    }
+
+    fn create_test_preferences() -> Arc<PreferencesImpl> {
+        let database = migrated_database();
+        Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database),
+        })
+    }
 }
@@ -0,0 +1,215 @@
+use crate::errors::ServicesError;
+
+const BUCKETS_COUNT: usize = 12;
+
+// Lower bounds of the distance buckets, in meters. The last bucket has no upper bound.
+// Includes the exposure minutes thresholds (see AlertDao::exposure_minutes), so they're classified exactly.
+pub const DISTANCE_BUCKETS: [f32; BUCKETS_COUNT] = [
+    0.0, 0.5, 1.0, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 20.0, 50.0,
+];
+
+// Samples of a TCN window by distance bucket, to approximate distance percentiles: the min distance
+// is dominated by a single noisy sample. Windows stored before it was recorded have an empty histogram.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DistanceHistogram {
+    counts: [u32; BUCKETS_COUNT],
+}
+
+impl DistanceHistogram {
+    pub fn with_sample(distance: f32) -> DistanceHistogram {
+        let mut histogram = DistanceHistogram::default();
+        histogram.counts[Self::bucket(distance)] = 1;
+        histogram
+    }
+
+    pub fn merge(&mut self, other: &DistanceHistogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count = count.saturating_add(*other_count);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|count| *count == 0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| *count as u64).sum()
+    }
+
+    // Approximate distance below which the fraction (0 to 1) of the samples are. Interpolated linearly in
+    // the bucket of the sample, so it's exact up to the bucket's width. The lower bound in the last bucket.
+    // None if there are no samples.
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let rank = fraction.max(0.0).min(1.0) as f64 * total as f64;
+
+        let mut below: u64 = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            let count = *count as u64;
+            if count == 0 || ((below + count) as f64) < rank {
+                below += count;
+                continue;
+            }
+            let lower = DISTANCE_BUCKETS[index];
+            return Some(match DISTANCE_BUCKETS.get(index + 1) {
+                Some(upper) => {
+                    let in_bucket = ((rank - below as f64) / count as f64).max(0.0) as f32;
+                    lower + (upper - lower) * in_bucket
+                }
+                None => lower,
+            });
+        }
+        // Not reached: rank <= total
+        None
+    }
+
+    // Comma separated counts, None if empty
+    pub fn to_db_str(&self) -> Option<String> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(
+                self.counts
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect::<Vec<String>>()
+                    .join(","),
+            )
+        }
+    }
+
+    pub fn from_db_str(str: Option<&str>) -> Result<DistanceHistogram, ServicesError> {
+        let mut histogram = DistanceHistogram::default();
+        let str = match str {
+            Some(str) => str,
+            None => return Ok(histogram),
+        };
+        let counts: Vec<&str> = str.split(',').collect();
+        if counts.len() != BUCKETS_COUNT {
+            return Err(ServicesError::Database(format!(
+                "Invalid stored distance histogram: {}",
+                str
+            )));
+        }
+        for (count, count_str) in histogram.counts.iter_mut().zip(counts) {
+            *count = count_str.parse().map_err(|_| {
+                ServicesError::Database(format!("Invalid stored distance histogram: {}", str))
+            })?;
+        }
+        Ok(histogram)
+    }
+
+    // Note: NaN is sorted into the first bucket. Distances are bounded before recording (DistanceBounds).
+    fn bucket(distance: f32) -> usize {
+        DISTANCE_BUCKETS
+            .iter()
+            .rposition(|lower| distance >= *lower)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(distances: &[f32]) -> DistanceHistogram {
+        let mut histogram = DistanceHistogram::default();
+        for distance in distances {
+            histogram.merge(&DistanceHistogram::with_sample(*distance));
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_empty_histogram_has_no_percentiles() {
+        let histogram = DistanceHistogram::default();
+        assert!(histogram.is_empty());
+        assert_eq!(None, histogram.percentile(0.1));
+        assert_eq!(None, histogram.percentile(0.5));
+    }
+
+    #[test]
+    fn test_samples_are_bucketed_by_lower_bound() {
+        let histogram = histogram(&[0.0, 0.49, 0.5, 2.0, 3.99, 4.0, 60.0]);
+        assert_eq!(
+            Some("2,1,0,0,1,1,1,0,0,0,0,1".to_owned()),
+            histogram.to_db_str()
+        );
+        assert_eq!(7, histogram.total());
+    }
+
+    #[test]
+    fn test_percentiles_are_within_bucket_resolution() {
+        // 1 noisy close sample, the rest around 3 meters
+        let mut distances = vec![0.2];
+        distances.extend(vec![2.8; 9]);
+        distances.extend(vec![3.4; 10]);
+        let histogram = histogram(&distances);
+
+        // 10th percentile: 2nd sample, in the 2 - 3 m bucket
+        let p10 = histogram.percentile(0.1).unwrap();
+        assert!(p10 >= 2.0 && p10 <= 3.0, "p10: {}", p10);
+        let median = histogram.percentile(0.5).unwrap();
+        assert!(median >= 2.0 && median <= 3.0, "median: {}", median);
+        // The min distance would be 0.2
+        assert_eq!(0.0, histogram.percentile(0.0).unwrap());
+    }
+
+    #[test]
+    fn test_percentiles_interpolate_in_bucket() {
+        // 10 samples in the 4 - 6 m bucket
+        let histogram = histogram(&[5.0; 10]);
+        assert_eq!(Some(5.0), histogram.percentile(0.5));
+        assert_eq!(Some(4.2), histogram.percentile(0.1));
+        assert_eq!(Some(6.0), histogram.percentile(1.0));
+    }
+
+    #[test]
+    fn test_percentile_in_last_bucket_is_its_lower_bound() {
+        let histogram = histogram(&[1.0, 70.0, 80.0]);
+        assert_eq!(Some(50.0), histogram.percentile(0.5));
+    }
+
+    #[test]
+    fn test_merge_saturates() {
+        let mut histogram = DistanceHistogram::default();
+        histogram.counts[2] = u32::MAX - 1;
+        histogram.merge(&DistanceHistogram::with_sample(1.2));
+        histogram.merge(&DistanceHistogram::with_sample(1.2));
+        assert_eq!(u32::MAX, histogram.counts[2]);
+    }
+
+    #[test]
+    fn test_db_str_round_trip() {
+        let histogram = histogram(&[0.3, 1.7, 1.8, 25.0]);
+        let db_str = histogram.to_db_str();
+        assert_eq!(
+            histogram,
+            DistanceHistogram::from_db_str(db_str.as_deref()).unwrap()
+        );
+
+        assert_eq!(None, DistanceHistogram::default().to_db_str());
+        assert_eq!(
+            DistanceHistogram::default(),
+            DistanceHistogram::from_db_str(None).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_db_str_is_error() {
+        for invalid in &[
+            "",
+            "1,2,3",
+            "a,0,0,0,0,0,0,0,0,0,0,0",
+            "-1,0,0,0,0,0,0,0,0,0,0,0",
+        ] {
+            assert!(matches!(
+                DistanceHistogram::from_db_str(Some(*invalid)),
+                Err(ServicesError::Database(_))
+            ));
+        }
+    }
+}
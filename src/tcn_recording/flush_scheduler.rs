@@ -0,0 +1,133 @@
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+};
+use timer::{Guard, Timer};
+
+// Keeps the scheduled task alive. Dropping it cancels the task.
+pub type ScheduleHandle = Box<dyn Any + Send>;
+
+pub trait FlushScheduler {
+    // Calls `f` every `interval`, until the returned handle is dropped
+    fn schedule<F>(&self, interval: chrono::Duration, f: F) -> ScheduleHandle
+    where
+        F: 'static + Fn() + Send;
+}
+
+pub struct TimerFlushScheduler {}
+
+struct TimerData {
+    _timer: Arc<Mutex<Timer>>,
+    _guard: Guard,
+}
+
+impl FlushScheduler for TimerFlushScheduler {
+    fn schedule<F>(&self, interval: chrono::Duration, f: F) -> ScheduleHandle
+    where
+        F: 'static + Fn() + Send,
+    {
+        let timer = Arc::new(Mutex::new(Timer::new()));
+        let guard = timer.lock().unwrap().schedule_repeating(interval, f);
+        // Dropping the guard cancels the task, dropping the timer ends its thread.
+        Box::new(TimerData {
+            _timer: timer,
+            _guard: guard,
+        })
+    }
+}
+
+#[cfg(test)]
+type ManualTask = Box<dyn Fn() + Send>;
+
+// Runs the scheduled task only when fire() is called, so tests don't depend on time.
+#[cfg(test)]
+#[derive(Default)]
+pub struct ManualFlushScheduler {
+    task: Arc<Mutex<Option<ManualTask>>>,
+}
+
+#[cfg(test)]
+impl ManualFlushScheduler {
+    // Runs the task, if it's scheduled (i.e. the handle is alive). Returns whether it ran.
+    pub fn fire(&self) -> bool {
+        match self.task.lock().unwrap().as_ref() {
+            Some(task) => {
+                task();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl FlushScheduler for ManualFlushScheduler {
+    fn schedule<F>(&self, _interval: chrono::Duration, f: F) -> ScheduleHandle
+    where
+        F: 'static + Fn() + Send,
+    {
+        *self.task.lock().unwrap() = Some(Box::new(f));
+        Box::new(ManualScheduleHandle {
+            task: Arc::downgrade(&self.task),
+        })
+    }
+}
+
+#[cfg(test)]
+struct ManualScheduleHandle {
+    task: std::sync::Weak<Mutex<Option<ManualTask>>>,
+}
+
+#[cfg(test)]
+impl Drop for ManualScheduleHandle {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.upgrade() {
+            *task.lock().unwrap() = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        time::Duration,
+    };
+
+    #[test]
+    #[ignore]
+    fn test_timer_scheduler_fires() {
+        let (sender, receiver) = mpsc::channel();
+
+        let _handle =
+            TimerFlushScheduler {}.schedule(chrono::Duration::milliseconds(50), move || {
+                let _ = sender.send(());
+            });
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_manual_scheduler_fires_until_handle_dropped() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let scheduler = ManualFlushScheduler::default();
+
+        let counter_clone = counter.clone();
+        let handle = scheduler.schedule(chrono::Duration::seconds(10), move || {
+            counter_clone.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert!(scheduler.fire());
+        assert!(scheduler.fire());
+        assert_eq!(2, counter.load(Ordering::SeqCst));
+
+        drop(handle);
+
+        assert!(!scheduler.fire());
+        assert_eq!(2, counter.load(Ordering::SeqCst));
+    }
+}
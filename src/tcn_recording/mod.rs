@@ -1,2 +1,4 @@
+pub mod distance_histogram;
+pub mod flush_scheduler;
 pub mod observed_tcn_processor;
 pub mod tcn_batches_manager;
@@ -1,13 +1,77 @@
-use super::tcn_batches_manager::TcnBatchesManager;
+use super::{
+    distance_histogram::DistanceHistogram,
+    flush_scheduler::{FlushScheduler, ScheduleHandle},
+    tcn_batches_manager::TcnBatchesManager,
+};
 use crate::{
-    byte_vec_to_16_byte_array, database::tcn_dao::TcnDao, errors::ServicesError, expect_log,
-    reports_interval,
+    database::tcn_dao::{TcnDao, TcnStats},
+    errors::ServicesError,
+    expect_log, reports_interval,
+    tcn_ext::tcn_hex::TcnHex,
 };
 use log::*;
 use reports_interval::UnixTime;
-use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
 use tcn::TemporaryContactNumber;
-use timer::{Guard, Timer};
+
+const FLUSH_INTERVAL_SECS: i64 = 10;
+
+// Ceiling for the samples count accumulated when merging TCN windows.
+// ~4 billion samples: unreachable in practice, fits in the db (i64) and in usize on 32 bit targets.
+pub const MAX_TOTAL_COUNT: usize = u32::MAX as usize;
+
+// Observation times accepted ahead of the core's clock (see ObservedTcnProcessor::save_with_time), for
+// clock differences between the scanner and the core
+pub const MAX_OBSERVATION_TIME_AHEAD_SECS: u64 = 60;
+
+// Raw observations kept for debugging, when enabled (see ObservedTcnProcessor::recent_observations)
+pub const RECENT_OBSERVATIONS_SIZE: usize = 100;
+// Bytes of the TCN kept in a recent observation
+const RECENT_OBSERVATION_TCN_PREFIX_BYTES: usize = 4;
+
+// What to do with distances above the max (e.g. caused by RSSI glitches)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutOfRangeDistancePolicy {
+    Clamp, // Store the max distance
+    Drop,  // Discard the sample
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DistanceBounds {
+    pub max_distance: f32, // Meters
+    pub policy: OutOfRangeDistancePolicy,
+}
+
+impl Default for DistanceBounds {
+    fn default() -> Self {
+        DistanceBounds {
+            max_distance: 50.0,
+            policy: OutOfRangeDistancePolicy::Clamp,
+        }
+    }
+}
+
+impl DistanceBounds {
+    // None if the sample has to be discarded
+    fn apply(&self, distance: f32) -> Option<f32> {
+        // Note: NaN is out of range too
+        if distance <= self.max_distance {
+            Some(distance)
+        } else {
+            match self.policy {
+                OutOfRangeDistancePolicy::Clamp => Some(self.max_distance),
+                OutOfRangeDistancePolicy::Drop => None,
+            }
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ObservedTcn {
@@ -15,12 +79,63 @@ pub struct ObservedTcn {
     pub contact_start: UnixTime,
     pub contact_end: UnixTime,
     pub min_distance: f32,
+    // Time-weighted if samples are bucketed (see TcnBatchesManager::sample_bucket_secs)
     pub avg_distance: f32,
     pub total_count: usize, // Needed to calculate correctly average of averages (= average of single values)
+    pub distance_histogram: DistanceHistogram,
+}
+
+// For field debugging: malformed observations point to scanner / payload parsing bugs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TcnDiagnostics {
+    #[serde(flatten)]
+    pub stored: TcnStats,
+    pub malformed_tcn_observations: usize, // Since bootstrap
+}
+
+// An observation as passed by the scanner, before bounding the distance and merging it into the batch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecentObservation {
+    pub tcn_prefix: String, // Hex, RECENT_OBSERVATION_TCN_PREFIX_BYTES: enough to tell observations apart
+    pub distance: f32,
+    pub time: u64,
+}
+
+// Result of ObservedTcnProcessor::save_batch
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct SaveBatchOutcome {
+    pub accepted: usize,
+    pub invalid: usize, // Malformed TCNs
 }
 
 pub trait ObservedTcnProcessor {
-    fn save(&self, tcn_str: &str, distance: f32) -> Result<(), ServicesError>;
+    // Observed now. See save_with_time.
+    fn save(&self, tcn: &str, distance: f32) -> Result<(), ServicesError>;
+    // tcn: hex or base64, as received from the BLE scanner. Err(FFIParameters) if malformed.
+    // time: when the TCN was observed. The apps buffer scan results and can deliver them later, also out of
+    // order. Err(FFIParameters) if more than MAX_OBSERVATION_TIME_AHEAD_SECS in the future.
+    // Err(General) after stop: the TCN wouldn't be flushed anymore.
+    fn save_with_time(&self, tcn: &str, distance: f32, time: UnixTime)
+        -> Result<(), ServicesError>;
+    // Observed now, e.g. a burst of scan results: takes the batch lock once. The malformed observations
+    // (see save_with_time) are counted, and don't prevent the others from being saved.
+    fn save_batch(
+        &self,
+        observations: Vec<(String, f32)>,
+    ) -> Result<SaveBatchOutcome, ServicesError>;
+    // TCNs rejected by save because they were malformed, since the processor was created
+    fn malformed_tcn_observations(&self) -> usize;
+    // Distinct TCNs not flushed yet
+    fn tcn_batch_size(&self) -> usize;
+    // The last RECENT_OBSERVATIONS_SIZE observations passed to save, oldest first.
+    // Err(General) if recording them isn't enabled.
+    fn recent_observations(&self) -> Result<Vec<RecentObservation>, ServicesError>;
+    // Writes the pending TCNs now, instead of at the next periodic flush. E.g. when the app goes to
+    // background. Err(StorageFull) if the storage is full: the TCNs are kept.
+    fn flush(&self) -> Result<(), ServicesError>;
+    // Flushes pending TCNs and stops the periodic flushing. Clears the recent observations. Idempotent.
+    // The TCNs saved after it are rejected.
+    fn stop(&self);
 }
 
 pub struct ObservedTcnProcessorImpl<T>
@@ -28,39 +143,159 @@ where
     T: 'static + TcnDao,
 {
     tcn_batches_manager: Arc<TcnBatchesManager<T>>,
-    _timer_data: TimerData,
-}
-
-struct TimerData {
-    _timer: Arc<Mutex<Timer>>,
-    _guard: Guard,
+    // The scheduled flush lives until stop() is called. None after that.
+    flush_schedule: Mutex<Option<ScheduleHandle>>,
+    // Set by stop() before the last flush. Held while pushing TCNs, so none is pushed after it (see running).
+    // Lock order: stopped, then the batch lock. Never held during database work.
+    stopped: Mutex<bool>,
+    // Applied to all recorded distances: save() is the only entry point for TCNs.
+    distance_bounds: DistanceBounds,
+    discarded_samples: AtomicUsize,
+    malformed_tcn_observations: AtomicUsize,
+    // Debugging only, off by default: the scanner's raw input is otherwise lost when merging
+    records_recent_observations: bool,
+    recent_observations: Mutex<VecDeque<RecentObservation>>,
+    // Time of the observations passed to save, and reference for the times passed to save_with_time
+    clock: fn() -> UnixTime,
 }
 
 impl<T> ObservedTcnProcessorImpl<T>
 where
     T: 'static + TcnDao,
 {
-    pub fn new(tcn_batches_manager: TcnBatchesManager<T>) -> ObservedTcnProcessorImpl<T> {
+    pub fn new<S: FlushScheduler>(
+        tcn_batches_manager: TcnBatchesManager<T>,
+        scheduler: &S,
+        distance_bounds: DistanceBounds,
+    ) -> ObservedTcnProcessorImpl<T> {
         let tcn_batches_manager = Arc::new(tcn_batches_manager);
-        let instance = ObservedTcnProcessorImpl {
-            tcn_batches_manager: tcn_batches_manager.clone(),
-            _timer_data: Self::schedule_process_batches(tcn_batches_manager),
+        let flush_schedule = Self::schedule_process_batches(&tcn_batches_manager, scheduler);
+        ObservedTcnProcessorImpl {
+            tcn_batches_manager,
+            flush_schedule: Mutex::new(Some(flush_schedule)),
+            stopped: Mutex::new(false),
+            distance_bounds,
+            discarded_samples: AtomicUsize::new(0),
+            malformed_tcn_observations: AtomicUsize::new(0),
+            records_recent_observations: false,
+            recent_observations: Mutex::new(VecDeque::new()),
+            clock: UnixTime::now,
+        }
+    }
+
+    pub fn record_recent_observations(mut self, enabled: bool) -> Self {
+        self.records_recent_observations = enabled;
+        self
+    }
+
+    pub fn clock(mut self, clock: fn() -> UnixTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Samples discarded because of out of range distance, since the processor was created
+    pub fn discarded_samples(&self) -> usize {
+        self.discarded_samples.load(Ordering::Relaxed)
+    }
+
+    fn push_recent_observation(&self, tcn: &TcnHex, distance: f32, time: &UnixTime) {
+        let recent_observations_res = self.recent_observations.lock();
+        let mut recent_observations =
+            expect_log!(recent_observations_res, "Couldn't lock recent observations");
+        if recent_observations.len() >= RECENT_OBSERVATIONS_SIZE {
+            recent_observations.pop_front();
+        }
+        recent_observations.push_back(RecentObservation {
+            tcn_prefix: tcn.as_str()[..RECENT_OBSERVATION_TCN_PREFIX_BYTES * 2].to_owned(),
+            distance,
+            time: time.value,
+        });
+    }
+
+    // The observation to push into the batch. None if the sample is discarded (see DistanceBounds).
+    fn observation(
+        &self,
+        tcn: &str,
+        distance: f32,
+        time: UnixTime,
+    ) -> Result<Option<ObservedTcn>, ServicesError> {
+        let tcn = match TcnHex::parse(tcn) {
+            Ok(tcn) => tcn,
+            Err(error) => {
+                warn!("Malformed TCN observation: {:?}", error);
+                self.malformed_tcn_observations
+                    .fetch_add(1, Ordering::Relaxed);
+                return Err(error);
+            }
         };
-        instance
-    }
-
-    fn schedule_process_batches(tcn_batches_manager: Arc<TcnBatchesManager<T>>) -> TimerData {
-        let timer = Arc::new(Mutex::new(Timer::new()));
-        TimerData {
-            _timer: timer.clone(),
-            _guard: timer.clone().lock().unwrap().schedule_repeating(
-                chrono::Duration::seconds(10),
-                move || {
-                    let flush_res = tcn_batches_manager.flush();
-                    expect_log!(flush_res, "Couldn't flush TCNs");
-                },
-            ),
+        let now = (self.clock)();
+        if time.value > now.value + MAX_OBSERVATION_TIME_AHEAD_SECS {
+            return Err(ServicesError::FFIParameters(format!(
+                "Observation time in the future: {}, now: {}",
+                time.value, now.value
+            )));
+        }
+
+        debug!(
+            "Recording a TCN {}, distance: {}, time: {}",
+            tcn, distance, time.value
+        );
+        if self.records_recent_observations {
+            self.push_recent_observation(&tcn, distance, &time);
+        }
+
+        let distance = match self.distance_bounds.apply(distance) {
+            Some(distance) => distance,
+            None => {
+                debug!("Discarding TCN sample, distance out of range: {}", distance);
+                self.discarded_samples.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        };
+
+        let observed_tcn = ObservedTcn {
+            tcn: tcn.to_tcn(),
+            contact_start: time.clone(),
+            contact_end: time,
+            min_distance: distance,
+            avg_distance: distance,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::with_sample(distance),
+        };
+
+        Ok(Some(observed_tcn))
+    }
+
+    // Held while pushing TCNs into the batch: stop() waits for the pushes in progress before the last
+    // flush. Err(General) if stopped.
+    fn running(&self) -> Result<MutexGuard<bool>, ServicesError> {
+        let stopped_res = self.stopped.lock();
+        let stopped = expect_log!(stopped_res, "Couldn't lock stopped flag");
+        if *stopped {
+            return Err(ServicesError::General(
+                "TCN processor is stopped".to_owned(),
+            ));
         }
+        Ok(stopped)
+    }
+
+    fn schedule_process_batches<S: FlushScheduler>(
+        tcn_batches_manager: &Arc<TcnBatchesManager<T>>,
+        scheduler: &S,
+    ) -> ScheduleHandle {
+        // The scheduled task doesn't keep the manager (and with it the database) alive.
+        let tcn_batches_manager = Arc::downgrade(tcn_batches_manager);
+        scheduler.schedule(chrono::Duration::seconds(FLUSH_INTERVAL_SECS), move || {
+            if let Some(tcn_batches_manager) = tcn_batches_manager.upgrade() {
+                let flush_res = tcn_batches_manager.scheduled_flush();
+                // Expected to happen: the batch is kept and the flush retried after a cooldown
+                if let Err(ServicesError::StorageFull) = flush_res {
+                    warn!("Couldn't flush TCNs: storage is full");
+                    return;
+                }
+                expect_log!(flush_res, "Couldn't flush TCNs");
+            }
+        })
     }
 }
 
@@ -68,21 +303,629 @@ impl<T> ObservedTcnProcessor for ObservedTcnProcessorImpl<T>
 where
     T: TcnDao + Sync + Send,
 {
-    fn save(&self, tcn_str: &str, distance: f32) -> Result<(), ServicesError> {
-        debug!("Recording a TCN {:?}, distance: {}", tcn_str, distance);
+    fn save(&self, tcn: &str, distance: f32) -> Result<(), ServicesError> {
+        self.save_with_time(tcn, distance, (self.clock)())
+    }
 
-        let bytes_vec: Vec<u8> = hex::decode(tcn_str)?;
-        let observed_tcn = ObservedTcn {
-            tcn: TemporaryContactNumber(byte_vec_to_16_byte_array(bytes_vec)),
-            contact_start: UnixTime::now(),
-            contact_end: UnixTime::now(),
-            min_distance: distance,
-            avg_distance: distance,
-            total_count: 1,
+    fn save_with_time(
+        &self,
+        tcn: &str,
+        distance: f32,
+        time: UnixTime,
+    ) -> Result<(), ServicesError> {
+        let _running = self.running()?;
+        if let Some(observed_tcn) = self.observation(tcn, distance, time)? {
+            self.tcn_batches_manager.push(observed_tcn);
+        }
+        Ok(())
+    }
+
+    fn save_batch(
+        &self,
+        observations: Vec<(String, f32)>,
+    ) -> Result<SaveBatchOutcome, ServicesError> {
+        let _running = self.running()?;
+        let now = (self.clock)();
+        let mut outcome = SaveBatchOutcome::default();
+        let mut observed_tcns = Vec::with_capacity(observations.len());
+        for (tcn, distance) in observations {
+            match self.observation(&tcn, distance, now.clone()) {
+                Ok(observed_tcn) => {
+                    outcome.accepted += 1;
+                    observed_tcns.extend(observed_tcn);
+                }
+                Err(_) => outcome.invalid += 1,
+            }
+        }
+        self.tcn_batches_manager.push_all(observed_tcns);
+        Ok(outcome)
+    }
+
+    fn malformed_tcn_observations(&self) -> usize {
+        self.malformed_tcn_observations.load(Ordering::Relaxed)
+    }
+
+    fn tcn_batch_size(&self) -> usize {
+        self.tcn_batches_manager.pending_tcns()
+    }
+
+    fn recent_observations(&self) -> Result<Vec<RecentObservation>, ServicesError> {
+        if !self.records_recent_observations {
+            return Err(ServicesError::General(
+                "Recent observations aren't recorded".to_owned(),
+            ));
+        }
+        let recent_observations_res = self.recent_observations.lock();
+        let recent_observations =
+            expect_log!(recent_observations_res, "Couldn't lock recent observations");
+        Ok(recent_observations.iter().cloned().collect())
+    }
+
+    fn flush(&self) -> Result<(), ServicesError> {
+        self.tcn_batches_manager.flush()
+    }
+
+    fn stop(&self) {
+        let recent_observations_res = self.recent_observations.lock();
+        expect_log!(recent_observations_res, "Couldn't lock recent observations").clear();
+
+        let stopped_res = self.stopped.lock();
+        *expect_log!(stopped_res, "Couldn't lock stopped flag") = true;
+
+        let flush_schedule = {
+            let flush_schedule_res = self.flush_schedule.lock();
+            expect_log!(flush_schedule_res, "Couldn't lock flush schedule").take()
         };
 
-        self.tcn_batches_manager.push(observed_tcn);
+        // Dropping the handle cancels the scheduled flush
+        if let Some(flush_schedule) = flush_schedule {
+            drop(flush_schedule);
+            info!("Stopped TCN flush schedule");
 
-        Ok(())
+            // No TCNs are pushed anymore: this flush writes the last ones
+            if let Err(error) = self.tcn_batches_manager.flush() {
+                error!("Couldn't flush TCNs on stop: {:?}", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::tcn_dao::TcnDaoImpl,
+        reports_update::exposure::ExposureGrouper,
+        tcn_recording::flush_scheduler::ManualFlushScheduler,
+        test_support::{migrated_database, set_storage_full},
+    };
+
+    #[test]
+    fn test_scheduled_flush_stores_tcns() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 1.2);
+        assert!(save_res.is_ok());
+
+        // Not flushed yet
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        assert_eq!(0, tcns_res.unwrap().len());
+
+        assert!(scheduler.fire());
+
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        assert_eq!(1, tcns_res.unwrap().len());
+    }
+
+    #[test]
+    fn test_flush_stores_tcns_without_timer() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 1.2);
+        assert!(save_res.is_ok());
+
+        assert!(processor.flush().is_ok());
+        assert_eq!(0, processor.tcn_batch_size());
+
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        let tcns = tcns_res.unwrap();
+        assert_eq!(1, tcns.len());
+        assert_eq!(
+            "2485a64b57addcaea3ed1b538d07dbce",
+            hex::encode(tcns[0].tcn.0)
+        );
+
+        // Flushing again without pending TCNs does nothing
+        assert!(processor.flush().is_ok());
+        assert_eq!(1, tcn_dao.all().unwrap().len());
+    }
+
+    #[test]
+    fn test_stop_flushes_and_stops_timer() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 1.2);
+        assert!(save_res.is_ok());
+
+        processor.stop();
+
+        // The pending TCN was flushed on stop
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        assert_eq!(1, tcns_res.unwrap().len());
+
+        // Rejected: it wouldn't be flushed anymore
+        let save_res = processor.save("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3", 2.1);
+        assert!(matches!(save_res, Err(ServicesError::General(_))));
+        let save_batch_res =
+            processor.save_batch(vec![("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3".to_owned(), 2.1)]);
+        assert!(matches!(save_batch_res, Err(ServicesError::General(_))));
+        assert_eq!(0, processor.tcn_batch_size());
+
+        // The flush isn't scheduled anymore: nothing is written
+        assert!(!scheduler.fire());
+
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        assert_eq!(1, tcns_res.unwrap().len());
+
+        // Stopping again does nothing
+        processor.stop();
+    }
+
+    #[test]
+    fn test_scheduled_flush_with_storage_full_keeps_tcns() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        for index in 0..1000 {
+            let save_res = processor.save(&format!("{:032x}", index), 1.2);
+            assert!(save_res.is_ok());
+        }
+
+        // Doesn't panic
+        set_storage_full(&database, true);
+        assert!(scheduler.fire());
+        assert_eq!(0, tcn_dao.all().unwrap().len());
+
+        // Stopping flushes regardless of the cooldown
+        set_storage_full(&database, false);
+        processor.stop();
+        assert_eq!(1000, tcn_dao.all().unwrap().len());
+    }
+
+    #[test]
+    fn test_out_of_range_distance_is_clamped() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds {
+                max_distance: 50.0,
+                policy: OutOfRangeDistancePolicy::Clamp,
+            },
+        );
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 800.0);
+        assert!(save_res.is_ok());
+        let save_res = processor.save("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3", 12.5);
+        assert!(save_res.is_ok());
+        assert!(scheduler.fire());
+
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        let mut tcns = tcns_res.unwrap();
+        tcns.sort_by(|t1, t2| t1.min_distance.partial_cmp(&t2.min_distance).unwrap());
+
+        assert_eq!(2, tcns.len());
+        assert_eq!(12.5, tcns[0].min_distance);
+        assert_eq!(50.0, tcns[1].min_distance);
+        assert_eq!(50.0, tcns[1].avg_distance);
+        assert_eq!(0, processor.discarded_samples());
+    }
+
+    #[test]
+    fn test_out_of_range_distance_is_dropped() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds {
+                max_distance: 50.0,
+                policy: OutOfRangeDistancePolicy::Drop,
+            },
+        );
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 800.0);
+        assert!(save_res.is_ok());
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", f32::NAN);
+        assert!(save_res.is_ok());
+        let save_res = processor.save("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3", 50.0);
+        assert!(save_res.is_ok());
+        assert!(scheduler.fire());
+
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        let tcns = tcns_res.unwrap();
+
+        assert_eq!(1, tcns.len());
+        assert_eq!(
+            tcn_hex("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3").to_tcn(),
+            tcns[0].tcn
+        );
+        assert_eq!(50.0, tcns[0].min_distance);
+        assert_eq!(2, processor.discarded_samples());
+    }
+
+    #[test]
+    fn test_malformed_tcns_are_classified_and_counted() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let malformed = vec![
+            // Replacement character from a mangled payload
+            (
+                "2485a64b57add\u{fffd}aea3ed1b538d07dbce",
+                "invalid char '\u{fffd}' at index 13",
+            ),
+            (
+                "2485a64b57addcaea3ed1b538d07dbcx",
+                "invalid char 'x' at index 31",
+            ),
+            ("2485a64b57addcaea3ed1b538d07dbc", "odd length: 31"),
+            ("2485a64b57addcaea3ed1b538d07db", "15 bytes, expected: 16"),
+            (
+                "2485a64b57addcaea3ed1b538d07dbce00",
+                "17 bytes, expected: 16",
+            ),
+        ];
+        for (tcn, expected_message) in &malformed {
+            match processor.save(tcn, 1.2) {
+                Err(ServicesError::FFIParameters(message)) => assert!(
+                    message.contains(expected_message),
+                    "Message: {:?}, expected: {:?}",
+                    message,
+                    expected_message
+                ),
+                other => panic!("Expected FFIParameters error, got: {:?}", other),
+            }
+        }
+        assert_eq!(malformed.len(), processor.malformed_tcn_observations());
+
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 1.2);
+        assert!(save_res.is_ok());
+        assert_eq!(malformed.len(), processor.malformed_tcn_observations());
+
+        // Only the valid TCN is stored
+        assert!(scheduler.fire());
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        assert_eq!(1, tcns_res.unwrap().len());
+    }
+
+    #[test]
+    fn test_base64_tcns_are_saved_like_hex() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let save_res = processor.save("JIWmS1et3K6j7RtTjQfbzg==", 1.2);
+        assert!(save_res.is_ok());
+        let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", 2.3);
+        assert!(save_res.is_ok());
+
+        match processor.save("JIWmS1et3K6j7RtTjQfb*g==", 1.2) {
+            Err(ServicesError::FFIParameters(message)) => {
+                assert!(message.contains("(base64)"), "Message: {:?}", message)
+            }
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+        assert_eq!(1, processor.malformed_tcn_observations());
+
+        // Same TCN: stored once
+        assert!(scheduler.fire());
+        let tcns_res = tcn_dao.all();
+        assert!(tcns_res.is_ok());
+        let tcns = tcns_res.unwrap();
+        assert_eq!(1, tcns.len());
+        assert_eq!(
+            tcn_hex("2485a64b57addcaea3ed1b538d07dbce").to_tcn(),
+            tcns[0].tcn
+        );
+    }
+
+    #[test]
+    fn test_recent_observations_are_raw_and_truncated() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao, ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds {
+                max_distance: 50.0,
+                policy: OutOfRangeDistancePolicy::Drop,
+            },
+        )
+        .record_recent_observations(true);
+
+        assert!(processor
+            .save("2485a64b57addcaea3ed1b538d07dbce", 1.2)
+            .is_ok());
+        // Base64 of the same TCN
+        assert!(processor.save("JIWmS1et3K6j7RtTjQfbzg==", 2.3).is_ok());
+        // Recorded as passed, though the sample is dropped
+        assert!(processor
+            .save("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3", 800.0)
+            .is_ok());
+        // Malformed: not an observation
+        assert!(processor.save("c5f2dbb8", 1.0).is_err());
+
+        let recent_res = processor.recent_observations();
+        assert!(recent_res.is_ok());
+        let recent = recent_res.unwrap();
+        let prefixes: Vec<&str> = recent.iter().map(|o| o.tcn_prefix.as_str()).collect();
+        assert_eq!(vec!["2485a64b", "2485a64b", "c5f2dbb8"], prefixes);
+        let distances: Vec<f32> = recent.iter().map(|o| o.distance).collect();
+        assert_eq!(vec![1.2, 2.3, 800.0], distances);
+        assert!(recent.iter().all(|o| o.time > 0));
+
+        // Merging doesn't affect them
+        assert!(scheduler.fire());
+        assert_eq!(recent, processor.recent_observations().unwrap());
+
+        processor.stop();
+        assert!(processor.recent_observations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recent_observations_keep_only_the_latest() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao, ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        )
+        .record_recent_observations(true);
+
+        for i in 0..RECENT_OBSERVATIONS_SIZE + 5 {
+            let save_res = processor.save("2485a64b57addcaea3ed1b538d07dbce", i as f32 / 10.0);
+            assert!(save_res.is_ok());
+        }
+
+        let recent = processor.recent_observations().unwrap();
+        assert_eq!(RECENT_OBSERVATIONS_SIZE, recent.len());
+        assert_eq!(0.5, recent[0].distance);
+        assert_eq!(
+            (RECENT_OBSERVATIONS_SIZE + 4) as f32 / 10.0,
+            recent[RECENT_OBSERVATIONS_SIZE - 1].distance
+        );
+    }
+
+    #[test]
+    fn test_recent_observations_are_off_by_default() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao, ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        assert!(processor
+            .save("2485a64b57addcaea3ed1b538d07dbce", 1.2)
+            .is_ok());
+        assert!(matches!(
+            processor.recent_observations(),
+            Err(ServicesError::General(_))
+        ));
+        assert!(processor.recent_observations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_out_of_order_observations_with_explicit_times_are_merged() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        )
+        .clock(|| UnixTime { value: 10000 });
+
+        // Delivered late and out of order. 1900 bridges 1000 and 2800.
+        for time in &[5000, 1000, 2800, 1900] {
+            let save_res = processor.save_with_time(
+                "2485a64b57addcaea3ed1b538d07dbce",
+                1.2,
+                UnixTime { value: *time },
+            );
+            assert!(save_res.is_ok());
+        }
+        assert!(scheduler.fire());
+        assert_eq!(
+            vec![(1000, 2800, 3), (5000, 5000, 1)],
+            stored_windows(&*tcn_dao)
+        );
+
+        // Arrives after the flush: merged with the stored window it's contiguous to
+        let save_res = processor.save_with_time(
+            "2485a64b57addcaea3ed1b538d07dbce",
+            1.2,
+            UnixTime { value: 4100 },
+        );
+        assert!(save_res.is_ok());
+        assert!(scheduler.fire());
+        assert_eq!(
+            vec![(1000, 2800, 3), (4100, 5000, 2)],
+            stored_windows(&*tcn_dao)
+        );
+    }
+
+    #[test]
+    fn test_observation_time_in_the_future_is_rejected() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao, ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        )
+        .clock(|| UnixTime { value: 10000 })
+        .record_recent_observations(true);
+
+        let save_res = processor.save_with_time(
+            "2485a64b57addcaea3ed1b538d07dbce",
+            1.2,
+            UnixTime {
+                value: 10000 + MAX_OBSERVATION_TIME_AHEAD_SECS,
+            },
+        );
+        assert!(save_res.is_ok());
+
+        let save_res = processor.save_with_time(
+            "c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3",
+            1.2,
+            UnixTime {
+                value: 10000 + MAX_OBSERVATION_TIME_AHEAD_SECS + 1,
+            },
+        );
+        assert!(matches!(save_res, Err(ServicesError::FFIParameters(_))));
+
+        // Without explicit time: the clock's
+        assert!(processor
+            .save("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3", 2.0)
+            .is_ok());
+
+        assert_eq!(2, processor.tcn_batch_size());
+        let times: Vec<u64> = processor
+            .recent_observations()
+            .unwrap()
+            .iter()
+            .map(|observation| observation.time)
+            .collect();
+        assert_eq!(vec![10000 + MAX_OBSERVATION_TIME_AHEAD_SECS, 10000], times);
+    }
+
+    #[test]
+    fn test_save_batch_counts_invalid_observations() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        )
+        .clock(|| UnixTime { value: 10000 });
+
+        let save_res = processor.save_batch(vec![
+            ("2485a64b57addcaea3ed1b538d07dbce".to_owned(), 1.2),
+            ("2485a64b57addcaea3ed1b538d07dbcz".to_owned(), 1.0),
+            // Same TCN as the first, base64
+            ("JIWmS1et3K6j7RtTjQfbzg==".to_owned(), 0.8),
+            ("c5f2dbb8".to_owned(), 1.0),
+            ("c5f2dbb8b3e3a4b1a4e2c0f1b7e5d9a3".to_owned(), 2.0),
+        ]);
+        assert_eq!(
+            SaveBatchOutcome {
+                accepted: 3,
+                invalid: 2
+            },
+            save_res.unwrap()
+        );
+        assert_eq!(2, processor.malformed_tcn_observations());
+
+        // Merged inside the batch
+        assert_eq!(2, processor.tcn_batch_size());
+        assert!(scheduler.fire());
+        let stored_tcns = tcn_dao.all().unwrap();
+        let merged = stored_tcns
+            .iter()
+            .find(|tcn| tcn.tcn == tcn_hex("2485a64b57addcaea3ed1b538d07dbce").to_tcn())
+            .unwrap();
+        assert_eq!(2, merged.total_count);
+        assert_eq!(0.8, merged.min_distance);
+        assert_eq!(10000, merged.contact_start.value);
+        assert_eq!(2, stored_tcns.len());
+    }
+
+    #[test]
+    fn test_save_empty_batch() {
+        let tcn_dao = create_tcn_dao();
+        let scheduler = ManualFlushScheduler::default();
+        let processor = ObservedTcnProcessorImpl::new(
+            TcnBatchesManager::new(tcn_dao, ExposureGrouper { threshold: 1000 }),
+            &scheduler,
+            DistanceBounds::default(),
+        );
+
+        let save_res = processor.save_batch(vec![]);
+        assert_eq!(SaveBatchOutcome::default(), save_res.unwrap());
+        assert_eq!(0, processor.tcn_batch_size());
+    }
+
+    // Start, end and count of the stored windows, sorted by start
+    fn stored_windows(tcn_dao: &TcnDaoImpl) -> Vec<(u64, u64, usize)> {
+        let mut windows: Vec<(u64, u64, usize)> = tcn_dao
+            .all()
+            .unwrap()
+            .iter()
+            .map(|tcn| {
+                (
+                    tcn.contact_start.value,
+                    tcn.contact_end.value,
+                    tcn.total_count,
+                )
+            })
+            .collect();
+        windows.sort();
+        windows
+    }
+
+    fn create_tcn_dao() -> Arc<TcnDaoImpl> {
+        let database = migrated_database();
+        Arc::new(TcnDaoImpl::new(database))
+    }
+
+    fn tcn_hex(str: &str) -> TcnHex {
+        TcnHex::new(str).unwrap()
     }
 }
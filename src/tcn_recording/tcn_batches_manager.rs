@@ -1,21 +1,101 @@
-use super::observed_tcn_processor::ObservedTcn;
+use super::{distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn};
 use crate::{
     database::tcn_dao::TcnDao,
     errors::ServicesError,
     expect_log,
+    reports_interval::UnixTime,
     reports_update::exposure::{Exposure, ExposureGrouper},
 };
 use log::*;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use tcn::TemporaryContactNumber;
+
+// Scheduled flushes are skipped for this long after a flush failed because the storage is full
+pub const STORAGE_FULL_FLUSH_COOLDOWN_SECS: u64 = 5 * 60;
 
+// Lock order: flushing, tcns_batch, open_buckets, then the database connection (taken by the DAO).
+// Never inverted: the DAO doesn't call back into the manager, so no DAO operation waits for the batch.
+// The database work of a flush (merge and overwrite) runs after releasing the batch lock, so pushes
+// (i.e. recording TCNs) don't wait for it. Only the journal reads / appends run under the batch lock.
 pub struct TcnBatchesManager<T>
 where
     T: TcnDao,
 {
     tcn_dao: Arc<T>,
-    tcns_batch: Mutex<HashMap<[u8; 16], ObservedTcn>>,
+    // Held for the whole flush. Concurrent flushes (e.g. the scheduled one and on stop) would merge
+    // with the same stored TCNs, and the last overwrite would drop the observations of the other.
+    flushing: Mutex<()>,
+    // The observation windows of each TCN, sorted by contact_start. Observations can arrive out of order
+    // (see ObservedTcnProcessor::save_with_time): a TCN can have windows that aren't contiguous.
+    tcns_batch: Mutex<HashMap<[u8; 16], Vec<ObservedTcn>>>,
+    // Locked only while holding the tcns_batch lock
+    open_buckets: Mutex<HashMap<[u8; 16], SampleBucket>>,
     exposure_grouper: ExposureGrouper,
+    // Pushed observations are also appended to the tcn_scratch table, so they survive a process death.
+    // Doubles the write volume.
+    journal: bool,
+    // Raw samples of a TCN within the same bucket of this length count as one sample, at their average
+    // distance. Otherwise total_count (and the weight in the average distance) follows the scanning
+    // frequency, so bursts of fast scanning dominate. 0: every sample counts.
+    sample_bucket_secs: u64,
+    // Unix time until which scheduled flushes are skipped (see scheduled_flush). 0: no cooldown.
+    flush_cooldown_until: AtomicU64,
+}
+
+// Raw samples of a TCN in the current bucket (see sample_bucket_secs), not yet in the batch
+#[derive(Debug, Clone)]
+struct SampleBucket {
+    number: u64,
+    tcn: TemporaryContactNumber,
+    contact_start: UnixTime,
+    contact_end: UnixTime,
+    min_distance: f32,
+    distance_sum: f32,
+    count: usize,
+}
+
+impl SampleBucket {
+    fn new(number: u64, sample: ObservedTcn) -> SampleBucket {
+        SampleBucket {
+            number,
+            tcn: sample.tcn,
+            contact_start: sample.contact_start,
+            contact_end: sample.contact_end,
+            min_distance: sample.min_distance,
+            distance_sum: sample.avg_distance,
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, sample: &ObservedTcn) {
+        if sample.contact_start.value < self.contact_start.value {
+            self.contact_start = sample.contact_start.clone();
+        }
+        if sample.contact_end.value > self.contact_end.value {
+            self.contact_end = sample.contact_end.clone();
+        }
+        self.min_distance = self.min_distance.min(sample.min_distance);
+        self.distance_sum += sample.avg_distance;
+        self.count += 1;
+    }
+
+    // The bucket as one sample, at the average distance
+    fn into_sample(self) -> ObservedTcn {
+        let avg_distance = self.distance_sum / self.count as f32;
+        ObservedTcn {
+            tcn: self.tcn,
+            contact_start: self.contact_start,
+            contact_end: self.contact_end,
+            min_distance: self.min_distance,
+            avg_distance,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::with_sample(avg_distance),
+        }
+    }
 }
 
 impl<T> TcnBatchesManager<T>
@@ -25,18 +105,98 @@ where
     pub fn new(tcn_dao: Arc<T>, exposure_grouper: ExposureGrouper) -> TcnBatchesManager<T> {
         TcnBatchesManager {
             tcn_dao,
+            flushing: Mutex::new(()),
             tcns_batch: Mutex::new(HashMap::new()),
+            open_buckets: Mutex::new(HashMap::new()),
             exposure_grouper,
+            journal: false,
+            sample_bucket_secs: 0,
+            flush_cooldown_until: AtomicU64::new(0),
         }
     }
 
+    pub fn journal(mut self, journal: bool) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    pub fn sample_bucket_secs(mut self, secs: u64) -> Self {
+        self.sample_bucket_secs = secs;
+        self
+    }
+
+    // Err(StorageFull) if the storage is full: the batch is kept, and written by a later flush
     pub fn flush(&self) -> Result<(), ServicesError> {
-        let tcns = {
+        self.flush_batch(self.journal)
+    }
+
+    // Like flush, for the periodic flushing: backs off for STORAGE_FULL_FLUSH_COOLDOWN_SECS when the
+    // storage is full, instead of retrying on every tick.
+    pub fn scheduled_flush(&self) -> Result<(), ServicesError> {
+        self.scheduled_flush_at(&UnixTime::now())
+    }
+
+    // Pushes the observations journaled before a process death and flushes them.
+    // Done also with the journal disabled, for rows left from a previous configuration.
+    // Returns the number of replayed observations.
+    pub fn replay_journal(&self) -> Result<usize, ServicesError> {
+        let journaled = self.tcn_dao.scratch()?;
+        if journaled.is_empty() {
+            return Ok(0);
+        }
+        info!("Replaying {} journaled TCN observations", journaled.len());
+
+        let count = journaled.len();
+        {
             let res = self.tcns_batch.lock();
             let mut tcns = expect_log!(res, "Couldn't lock tcns batch");
-            let clone = tcns.clone();
-            tcns.clear();
-            clone
+            for tcn in journaled {
+                self.push_sample(&mut tcns, tcn);
+            }
+        }
+        self.flush_batch(true)?;
+        Ok(count)
+    }
+
+    pub fn push(&self, tcn: ObservedTcn) {
+        self.push_all(vec![tcn])
+    }
+
+    // Takes the batch lock once for all the observations (e.g. a burst of scan results)
+    pub fn push_all(&self, observed_tcns: Vec<ObservedTcn>) {
+        let res = self.tcns_batch.lock();
+        let mut tcns = expect_log!(res, "Couldn't lock tcns batch");
+
+        for tcn in observed_tcns {
+            // Appended while holding the batch lock, so the journal order matches the batches
+            if self.journal {
+                if let Err(e) = self.tcn_dao.append_scratch(&tcn) {
+                    // Still recorded in memory: only lost if the process dies before the flush
+                    error!("Couldn't journal TCN: {:?}", e);
+                }
+            }
+
+            self.push_sample(&mut tcns, tcn);
+        }
+    }
+
+    // clear_journal: remove the journaled observations covered by the flushed batch
+    fn flush_batch(&self, clear_journal: bool) -> Result<(), ServicesError> {
+        let res = self.flushing.lock();
+        let _flushing = expect_log!(res, "Couldn't lock flushing");
+
+        let (tcns, journal_end) = {
+            let res = self.tcns_batch.lock();
+            let mut tcns = expect_log!(res, "Couldn't lock tcns batch");
+            // A bucket open across a flush counts as two samples: bounded by the flush frequency
+            self.close_buckets(&mut tcns);
+            // Read while holding the lock: covers exactly the observations pushed to this batch
+            let journal_end = match clear_journal {
+                true => self.tcn_dao.last_scratch_id()?,
+                false => None,
+            };
+            // Move the batch out, leaving an empty map, to not duplicate it in memory.
+            (std::mem::take(&mut *tcns), journal_end)
         };
 
         if tcns.is_empty() {
@@ -46,28 +206,91 @@ where
         debug!("Flushing TCN batch into database: {:?}", tcns);
 
         // Do an in-memory merge with the DB TCNs and overwrite stored exposures with result.
-        let merged = self.merge_with_db(tcns)?;
-        self.tcn_dao.overwrite(merged)?;
+        // The batch is only read, so it can be retained if the write fails.
+        let write_res = self
+            .merge_with_db(&tcns)
+            .and_then(|merged| self.tcn_dao.overwrite(merged));
+        if let Err(error) = write_res {
+            // Otherwise the observations would be lost (without journal): kept until there's space
+            if let ServicesError::StorageFull = error {
+                warn!("Storage full: keeping {} TCNs in the batch", tcns.len());
+                self.retain_batch(tcns);
+            }
+            return Err(error);
+        }
+
+        // Not reached if the flush failed: the journal is kept, and replayed on the next bootstrap
+        if let Some(journal_end) = journal_end {
+            self.tcn_dao.delete_scratch_up_to(journal_end)?;
+        }
 
         Ok(())
     }
 
-    pub fn push(&self, tcn: ObservedTcn) {
+    fn scheduled_flush_at(&self, now: &UnixTime) -> Result<(), ServicesError> {
+        let cooldown_until = self.flush_cooldown_until.load(Ordering::Relaxed);
+        if now.value < cooldown_until {
+            debug!("Storage full: skipping TCN flush until: {}", cooldown_until);
+            return Ok(());
+        }
+        let flush_res = self.flush();
+        if let Err(ServicesError::StorageFull) = flush_res {
+            self.flush_cooldown_until.store(
+                now.value + STORAGE_FULL_FLUSH_COOLDOWN_SECS,
+                Ordering::Relaxed,
+            );
+        }
+        flush_res
+    }
+
+    // Puts back a batch that couldn't be written, merged with the observations pushed meanwhile
+    fn retain_batch(&self, retained: HashMap<[u8; 16], Vec<ObservedTcn>>) {
         let res = self.tcns_batch.lock();
         let mut tcns = expect_log!(res, "Couldn't lock tcns batch");
+        for (_, windows) in retained {
+            for window in windows {
+                self.push_to_batch(&mut tcns, window);
+            }
+        }
+    }
 
-        // TCNs in batch are merged to save memory and simplify processing / reduce logs.
-        let merged_tcn = match tcns.get(&tcn.tcn.0) {
-            Some(existing_tcn) => {
-                match Self::merge_tcns(&self.exposure_grouper, existing_tcn.to_owned(), tcn.clone())
-                {
-                    Some(merged) => merged,
-                    None => tcn,
+    // Raw samples (total count 1) are accumulated in the bucket of their TCN (see sample_bucket_secs).
+    // The bucket enters the batch when a sample of another bucket arrives, or on flush.
+    // Already merged observations enter the batch directly.
+    fn push_sample(&self, tcns: &mut HashMap<[u8; 16], Vec<ObservedTcn>>, tcn: ObservedTcn) {
+        if self.sample_bucket_secs == 0 || tcn.total_count != 1 {
+            self.push_to_batch(tcns, tcn);
+            return;
+        }
+        let number = tcn.contact_start.value / self.sample_bucket_secs;
+
+        let res = self.open_buckets.lock();
+        let mut buckets = expect_log!(res, "Couldn't lock sample buckets");
+        match buckets.get_mut(&tcn.tcn.0) {
+            Some(bucket) if bucket.number == number => bucket.add(&tcn),
+            _ => {
+                if let Some(closed) = buckets.insert(tcn.tcn.0, SampleBucket::new(number, tcn)) {
+                    self.push_to_batch(tcns, closed.into_sample());
                 }
             }
-            None => tcn,
-        };
-        tcns.insert(merged_tcn.tcn.0, merged_tcn);
+        }
+    }
+
+    fn close_buckets(&self, tcns: &mut HashMap<[u8; 16], Vec<ObservedTcn>>) {
+        let res = self.open_buckets.lock();
+        let mut buckets = expect_log!(res, "Couldn't lock sample buckets");
+        for (_, bucket) in buckets.drain() {
+            self.push_to_batch(tcns, bucket.into_sample());
+        }
+    }
+
+    fn push_to_batch(&self, tcns: &mut HashMap<[u8; 16], Vec<ObservedTcn>>, tcn: ObservedTcn) {
+        // TCNs in batch are merged to save memory and simplify processing / reduce logs.
+        let windows = tcns.remove(&tcn.tcn.0).unwrap_or_default();
+        tcns.insert(
+            tcn.tcn.0,
+            Self::insert_merging(&self.exposure_grouper, windows, tcn),
+        );
 
         // debug!("Updated TCNs batch: {:?}", tcns);
     }
@@ -83,28 +306,38 @@ where
         }
     }
 
+    // Distinct TCNs not flushed yet: in the batch or in an open sample bucket
+    pub fn pending_tcns(&self) -> usize {
+        let res = self.tcns_batch.lock();
+        let tcns = expect_log!(res, "Couldn't lock tcns batch");
+        let res = self.open_buckets.lock();
+        let buckets = expect_log!(res, "Couldn't lock sample buckets");
+        tcns.len()
+            + buckets
+                .keys()
+                .filter(|tcn| !tcns.contains_key(*tcn))
+                .count()
+    }
+
     // Retrieves possible existing exposures from DB with same TCNs and does an in-memory merge.
     fn merge_with_db(
         &self,
-        tcns: HashMap<[u8; 16], ObservedTcn>,
+        tcns: &HashMap<[u8; 16], Vec<ObservedTcn>>,
     ) -> Result<Vec<ObservedTcn>, ServicesError> {
-        let tcns_vec: Vec<ObservedTcn> = tcns
-            .iter()
-            .map(|(_, observed_tcn)| observed_tcn.clone())
-            .collect();
-
-        let mut db_tcns = self
-            .tcn_dao
-            .find_tcns(tcns_vec.clone().into_iter().map(|tcn| tcn.tcn).collect())?;
+        let mut db_tcns = self.tcn_dao.find_tcns(
+            tcns.keys()
+                .map(|tcn| TemporaryContactNumber(*tcn))
+                .collect(),
+        )?;
         db_tcns.sort_by_key(|tcn| tcn.contact_start.value);
 
         let db_tcns_map: HashMap<[u8; 16], Vec<ObservedTcn>> = Self::to_hash_map(db_tcns);
 
-        Ok(tcns_vec
-            .into_iter()
-            .map(|tcn|
+        Ok(tcns
+            .values()
+            .map(|windows|
             // Values in db_tcns_map can't be empty: we built the map based on existing TCNs
-            Self::determine_tcns_to_write(self.exposure_grouper.clone(), db_tcns_map.clone(), tcn))
+            Self::determine_tcns_to_write(&self.exposure_grouper, &db_tcns_map, windows))
             .flatten()
             .collect())
     }
@@ -112,40 +345,71 @@ where
     // Expects:
     // - Values in db_tcns_map not empty
     // - db_tcns_map sorted by contact_start (ascending)
+    // - windows: of the same TCN
     fn determine_tcns_to_write(
-        exposure_grouper: ExposureGrouper,
-        db_tcns_map: HashMap<[u8; 16], Vec<ObservedTcn>>,
-        tcn: ObservedTcn,
+        exposure_grouper: &ExposureGrouper,
+        db_tcns_map: &HashMap<[u8; 16], Vec<ObservedTcn>>,
+        windows: &[ObservedTcn],
     ) -> Vec<ObservedTcn> {
-        let db_tcns = db_tcns_map.get(&tcn.tcn.0);
+        let db_tcns = windows.first().and_then(|tcn| db_tcns_map.get(&tcn.tcn.0));
 
         match db_tcns {
             // Matching exposures in DB
             Some(db_tcns) => {
-                if let Some(last) = db_tcns.last() {
-                    // If contiguous to last DB exposure, merge with it, otherwise append.
-                    let tail =
-                        match Self::merge_tcns(&exposure_grouper, last.to_owned(), tcn.clone()) {
-                            Some(merged) => vec![merged],
-                            None => vec![last.to_owned(), tcn],
-                        };
-                    let mut head: Vec<ObservedTcn> = db_tcns
-                        .to_owned()
-                        .into_iter()
-                        .take(db_tcns.len() - 1)
-                        .collect();
-                    head.extend(tail);
-                    head
-                } else {
+                if db_tcns.is_empty() {
                     error!("Illegal state: value in db_tcns_map is empty");
                     panic!();
                 }
+                windows.iter().fold(db_tcns.to_owned(), |merged, tcn| {
+                    Self::insert_merging(exposure_grouper, merged, tcn.clone())
+                })
             }
             // No matching exposures in DB: insert new TCN
-            None => vec![tcn],
+            None => windows.to_vec(),
         }
     }
 
+    // Inserts tcn in windows (sorted by contact_start, ascending), merging it with the windows
+    // it overlaps or is contiguous to. Windows bridged by tcn are collapsed into one.
+    // Returns the windows sorted by contact_start.
+    fn insert_merging(
+        exposure_grouper: &ExposureGrouper,
+        mut windows: Vec<ObservedTcn>,
+        tcn: ObservedTcn,
+    ) -> Vec<ObservedTcn> {
+        let index = match windows.binary_search_by_key(&tcn.contact_start.value, |window| {
+            window.contact_start.value
+        }) {
+            Ok(index) | Err(index) => index,
+        };
+
+        // Merge with the preceding window, if contiguous.
+        let preceding_merge = index.checked_sub(1).and_then(|preceding| {
+            Self::merge_tcns(exposure_grouper, windows[preceding].clone(), tcn.clone())
+                .map(|merged| (preceding, merged))
+        });
+        let (start, mut merged) = preceding_merge.unwrap_or((index, tcn));
+
+        // Merge with the following windows, while contiguous.
+        let mut end = index;
+        while end < windows.len() {
+            match Self::merge_tcns(exposure_grouper, merged.clone(), windows[end].clone()) {
+                Some(new_merged) => {
+                    merged = new_merged;
+                    end += 1;
+                }
+                None => break,
+            }
+        }
+
+        // Replace windows[start..end] with the merged window.
+        let tail = windows.split_off(end);
+        windows.truncate(start);
+        windows.push(merged);
+        windows.extend(tail);
+        windows
+    }
+
     fn to_hash_map(tcns: Vec<ObservedTcn>) -> HashMap<[u8; 16], Vec<ObservedTcn>> {
         let mut map: HashMap<[u8; 16], Vec<ObservedTcn>> = HashMap::new();
         for tcn in tcns {
@@ -178,6 +442,7 @@ where
                 min_distance: measurements.min_distance,
                 avg_distance: measurements.avg_distance,
                 total_count: measurements.total_count,
+                distance_histogram: measurements.distance_histogram,
             })
         } else {
             None
@@ -189,17 +454,19 @@ where
 mod tests {
     use super::*;
     use crate::{
-        database::{database::Database, tcn_dao::TcnDaoImpl},
+        database::tcn_dao::TcnDaoImpl,
         reports_interval::UnixTime,
+        test_support::{migrated_database, set_storage_full},
+    };
+    use std::{
+        sync::{mpsc, Barrier},
+        thread,
+        time::{Duration, Instant},
     };
-    use rusqlite::Connection;
-    use tcn::TemporaryContactNumber;
 
     #[test]
     fn test_push_merges_existing_tcn_in_batch_manager() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = TcnDaoImpl::new(database);
 
         let batches_manager =
@@ -212,6 +479,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 0.506, // (0.1 + 0.62 + 0.8 + 0.21 + 0.8) / 5
             total_count: 5,
+            distance_histogram: DistanceHistogram::default(),
         });
 
         batches_manager.push(ObservedTcn {
@@ -221,6 +489,7 @@ mod tests {
             min_distance: 2.0,
             avg_distance: 0.7, // (1.2 + 0.5 + 0.4) / 3
             total_count: 3,
+            distance_histogram: DistanceHistogram::default(),
         });
 
         let len_res = batches_manager.len();
@@ -230,22 +499,147 @@ mod tests {
         let tcns = batches_manager.tcns_batch.lock().unwrap();
         assert_eq!(
             tcns[&[0; 16]],
-            ObservedTcn {
+            vec![ObservedTcn {
                 tcn: TemporaryContactNumber([0; 16]),
                 contact_start: UnixTime { value: 1600 },
                 contact_end: UnixTime { value: 5000 },
                 min_distance: 2.0,
                 avg_distance: 0.57875, // (0.1 + 0.62 + 0.8 + 0.21 + 0.8 + 1.2 + 0.5 + 0.4) / (5 + 3)
-                total_count: 8         // 5 + 3
-            }
+                total_count: 8,        // 5 + 3
+                distance_histogram: DistanceHistogram::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_push_merges_distance_histograms() {
+        let database = migrated_database();
+        let batches_manager = TcnBatchesManager::new(
+            Arc::new(TcnDaoImpl::new(database)),
+            ExposureGrouper { threshold: 1000 },
         );
+
+        let tcn = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1600 },
+            contact_end: UnixTime { value: 1600 },
+            min_distance: 0.3,
+            avg_distance: 0.3,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::with_sample(0.3),
+        };
+        batches_manager.push(tcn.clone());
+        batches_manager.push(ObservedTcn {
+            contact_start: UnixTime { value: 1700 },
+            contact_end: UnixTime { value: 1700 },
+            min_distance: 3.2,
+            avg_distance: 3.2,
+            distance_histogram: DistanceHistogram::with_sample(3.2),
+            ..tcn
+        });
+
+        let tcns = batches_manager.tcns_batch.lock().unwrap();
+        let mut expected_histogram = DistanceHistogram::with_sample(0.3);
+        expected_histogram.merge(&DistanceHistogram::with_sample(3.2));
+        assert_eq!(expected_histogram, tcns[&[0; 16]][0].distance_histogram);
+        assert_eq!(2, tcns[&[0; 16]][0].total_count);
+    }
+
+    #[test]
+    fn test_journaled_tcns_are_replayed_after_process_death() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let observed_tcn = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1600 },
+            contact_end: UnixTime { value: 2600 },
+            min_distance: 2.3,
+            avg_distance: 2.3,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 })
+                .journal(true);
+        batches_manager.push(observed_tcn.clone());
+        // Process death: the batch isn't flushed
+        drop(batches_manager);
+        assert!(tcn_dao.all().unwrap().is_empty());
+
+        let restarted_tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let restarted_manager = TcnBatchesManager::new(
+            restarted_tcn_dao.clone(),
+            ExposureGrouper { threshold: 1000 },
+        )
+        .journal(true);
+        let replay_res = restarted_manager.replay_journal();
+        assert!(replay_res.is_ok());
+        assert_eq!(1, replay_res.unwrap());
+
+        assert_eq!(vec![observed_tcn], restarted_tcn_dao.all().unwrap());
+        assert!(restarted_tcn_dao.scratch().unwrap().is_empty());
+        // Replayed only once
+        assert_eq!(0, restarted_manager.replay_journal().unwrap());
+    }
+
+    #[test]
+    fn test_flush_clears_journal() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 })
+                .journal(true);
+
+        batches_manager.push(ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1600 },
+            contact_end: UnixTime { value: 2600 },
+            min_distance: 2.3,
+            avg_distance: 2.3,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        });
+        batches_manager.push(ObservedTcn {
+            tcn: TemporaryContactNumber([1; 16]),
+            contact_start: UnixTime { value: 1600 },
+            contact_end: UnixTime { value: 2600 },
+            min_distance: 2.3,
+            avg_distance: 2.3,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        });
+        assert_eq!(2, tcn_dao.scratch().unwrap().len());
+
+        assert!(batches_manager.flush().is_ok());
+
+        assert!(tcn_dao.scratch().unwrap().is_empty());
+        assert_eq!(2, tcn_dao.all().unwrap().len());
+    }
+
+    #[test]
+    fn test_push_without_journal_doesnt_write() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        batches_manager.push(ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1600 },
+            contact_end: UnixTime { value: 2600 },
+            min_distance: 2.3,
+            avg_distance: 2.3,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        });
+
+        assert!(tcn_dao.scratch().unwrap().is_empty());
     }
 
     #[test]
     fn test_flush_clears_tcns() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = TcnDaoImpl::new(database);
 
         let batches_manager =
@@ -258,6 +652,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 2.3,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         });
         let flush_res = batches_manager.flush();
         assert!(flush_res.is_ok());
@@ -269,9 +664,7 @@ mod tests {
 
     #[test]
     fn test_flush_adds_entries_to_db() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let batches_manager =
@@ -284,6 +677,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 2.3,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         batches_manager.push(tcn.clone());
 
@@ -300,9 +694,7 @@ mod tests {
 
     #[test]
     fn test_flush_updates_correctly_existing_entry() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let batches_manager =
@@ -315,6 +707,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 1.25, // (2.3 + 0.7 + 1 + 1) / 4
             total_count: 4,
+            distance_histogram: DistanceHistogram::default(),
         };
         let save_res = tcn_dao.overwrite(vec![stored_tcn]);
         assert!(save_res.is_ok());
@@ -326,6 +719,7 @@ mod tests {
             min_distance: 1.12,
             avg_distance: 1.0, // (1.12 + 0.88 + 1) / 3
             total_count: 3,
+            distance_histogram: DistanceHistogram::default(),
         };
         batches_manager.push(tcn.clone());
 
@@ -347,15 +741,14 @@ mod tests {
                 min_distance: 1.12,
                 avg_distance: 1.14285714, // (2.3 + 0.7 + 1 + 1 + 1.12 + 0.88 + 1) / (4 + 3)
                 total_count: 7,
+                distance_histogram: DistanceHistogram::default(),
             }
         );
     }
 
     #[test]
     fn test_flush_does_not_affect_different_stored_tcn() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let batches_manager =
@@ -368,6 +761,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 2.3,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         let save_res = tcn_dao.overwrite(vec![stored_tcn]);
         assert!(save_res.is_ok());
@@ -379,6 +773,7 @@ mod tests {
             min_distance: 1.12,
             avg_distance: 1.12,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         batches_manager.push(tcn.clone());
 
@@ -403,6 +798,7 @@ mod tests {
                 min_distance: 2.3,
                 avg_distance: 2.3,
                 total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
             }
         );
         assert_eq!(
@@ -413,16 +809,15 @@ mod tests {
                 contact_end: UnixTime { value: 5000 },
                 min_distance: 1.12,
                 avg_distance: 1.12,
-                total_count: 1
+                total_count: 1,
+                distance_histogram: DistanceHistogram::default()
             }
         );
     }
 
     #[test]
     fn test_flush_updates_correctly_2_stored_1_updated() {
-        let database = Arc::new(Database::new(
-            Connection::open_in_memory().expect("Couldn't create database!"),
-        ));
+        let database = migrated_database();
         let tcn_dao = Arc::new(TcnDaoImpl::new(database));
 
         let batches_manager =
@@ -435,6 +830,7 @@ mod tests {
             min_distance: 0.4,
             avg_distance: 0.4,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
 
         let stored_tcn2 = ObservedTcn {
@@ -444,6 +840,7 @@ mod tests {
             min_distance: 2.3,
             avg_distance: 2.3,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         let save_res = tcn_dao.overwrite(vec![stored_tcn1.clone(), stored_tcn2.clone()]);
         assert!(save_res.is_ok());
@@ -455,6 +852,7 @@ mod tests {
             min_distance: 1.12,
             avg_distance: 1.12,
             total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
         };
         batches_manager.push(tcn.clone());
 
@@ -479,9 +877,493 @@ mod tests {
                 contact_end: UnixTime { value: 7000 },
                 min_distance: 0.4,
                 avg_distance: 0.76, // (0.4, + 1.12) / (1 + 1)
-                total_count: 2      // 1 + 1
+                total_count: 2,     // 1 + 1
+                distance_histogram: DistanceHistogram::default()
             }
         );
         assert_eq!(loaded_tcns[1], stored_tcn2);
     }
+
+    #[test]
+    fn test_flush_bridging_tcn_merges_stored_windows() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        let stored_tcn1 = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1000 },
+            contact_end: UnixTime { value: 2000 },
+            min_distance: 0.4,
+            avg_distance: 0.4,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let stored_tcn2 = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 4000 },
+            contact_end: UnixTime { value: 5000 },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let save_res = tcn_dao.overwrite(vec![stored_tcn1, stored_tcn2]);
+        assert!(save_res.is_ok());
+
+        // Contiguous to both stored windows
+        batches_manager.push(ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 2500 },
+            contact_end: UnixTime { value: 3500 },
+            min_distance: 1.6,
+            avg_distance: 1.6,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        });
+
+        let flush_res = batches_manager.flush();
+        assert!(flush_res.is_ok());
+
+        let loaded_tcns_res = tcn_dao.all();
+        assert!(loaded_tcns_res.is_ok());
+
+        let loaded_tcns = loaded_tcns_res.unwrap();
+        assert_eq!(1, loaded_tcns.len());
+        assert_eq!(
+            loaded_tcns[0],
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 1000 },
+                contact_end: UnixTime { value: 5000 },
+                min_distance: 0.4,
+                avg_distance: 1.0, // (0.4 + 1.6 + 1.0) / 3
+                total_count: 3,
+                distance_histogram: DistanceHistogram::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_flush_tcn_overlapping_earlier_window_merges_with_it() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        let stored_tcn1 = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1000 },
+            contact_end: UnixTime { value: 2000 },
+            min_distance: 0.5,
+            avg_distance: 0.5,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let stored_tcn2 = ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 10000 },
+            contact_end: UnixTime { value: 11000 },
+            min_distance: 1.0,
+            avg_distance: 1.0,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        };
+        let save_res = tcn_dao.overwrite(vec![stored_tcn1, stored_tcn2.clone()]);
+        assert!(save_res.is_ok());
+
+        // Overlaps the first (not the last) stored window
+        batches_manager.push(ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: 1500 },
+            contact_end: UnixTime { value: 1800 },
+            min_distance: 1.5,
+            avg_distance: 1.5,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::default(),
+        });
+
+        let flush_res = batches_manager.flush();
+        assert!(flush_res.is_ok());
+
+        let loaded_tcns_res = tcn_dao.all();
+        assert!(loaded_tcns_res.is_ok());
+
+        let mut loaded_tcns = loaded_tcns_res.unwrap();
+        assert_eq!(2, loaded_tcns.len());
+
+        // Sqlite doesn't guarantee insertion order, so sort
+        loaded_tcns.sort_by_key(|tcn| tcn.contact_start.value);
+
+        assert_eq!(
+            loaded_tcns[0],
+            ObservedTcn {
+                tcn: TemporaryContactNumber([0; 16]),
+                contact_start: UnixTime { value: 1000 },
+                contact_end: UnixTime { value: 2000 },
+                min_distance: 0.5,
+                avg_distance: 1.0, // (0.5 + 1.5) / 2
+                total_count: 2,
+                distance_histogram: DistanceHistogram::default()
+            }
+        );
+        assert_eq!(loaded_tcns[1], stored_tcn2);
+    }
+
+    #[test]
+    fn test_flush_large_batch() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        let count: usize = 10_000;
+        for i in 0..count {
+            let mut tcn_bytes = [0; 16];
+            tcn_bytes[0] = (i & 0xFF) as u8;
+            tcn_bytes[1] = ((i >> 8) & 0xFF) as u8;
+            batches_manager.push(ObservedTcn {
+                tcn: TemporaryContactNumber(tcn_bytes),
+                contact_start: UnixTime { value: 1600 },
+                contact_end: UnixTime { value: 2600 },
+                min_distance: 2.3,
+                avg_distance: 2.3,
+                total_count: 1,
+                distance_histogram: DistanceHistogram::default(),
+            });
+        }
+        assert_eq!(count, batches_manager.len().unwrap());
+
+        let flush_start_time = Instant::now();
+        let flush_res = batches_manager.flush();
+        let flush_time = flush_start_time.elapsed();
+        assert!(flush_res.is_ok());
+
+        // Generous bound: before, each TCN copied the complete DB TCNs map (quadratic).
+        assert!(
+            flush_time.as_secs() < 10,
+            "Flushing took too long: {:?}",
+            flush_time
+        );
+
+        assert_eq!(0, batches_manager.len().unwrap());
+
+        let stored_tcns_res = tcn_dao.all();
+        assert!(stored_tcns_res.is_ok());
+        assert_eq!(count, stored_tcns_res.unwrap().len());
+    }
+
+    #[test]
+    fn test_bursty_and_steady_samples_have_near_equal_averages() {
+        // True distance in each 10 seconds bucket, starting at 1000
+        let distances = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let steady: Vec<(u64, f32)> = distances
+            .iter()
+            .enumerate()
+            .map(|(i, distance)| (1005 + i as u64 * 10, *distance))
+            .collect();
+        // Scanning every 200ms during the first bucket, then every 10 seconds
+        let bursty: Vec<(u64, f32)> = (0..50)
+            .map(|i| (1000 + i / 5, distances[0]))
+            .chain(steady.iter().skip(1).cloned())
+            .collect();
+
+        let steady_tcn = flushed_samples(10, &steady);
+        let bursty_tcn = flushed_samples(10, &bursty);
+
+        assert_eq!(6, steady_tcn.total_count);
+        assert_eq!(6, bursty_tcn.total_count);
+        assert!((steady_tcn.avg_distance - 3.5).abs() < 0.001);
+        assert!((bursty_tcn.avg_distance - steady_tcn.avg_distance).abs() < 0.001);
+        assert_eq!(1000, bursty_tcn.contact_start.value);
+        assert_eq!(1055, bursty_tcn.contact_end.value);
+        assert_eq!(1.0, bursty_tcn.min_distance);
+
+        // Without buckets, the burst dominates
+        let unbucketed_tcn = flushed_samples(0, &bursty);
+        assert_eq!(55, unbucketed_tcn.total_count);
+        assert!(unbucketed_tcn.avg_distance < 1.5);
+    }
+
+    #[test]
+    fn test_open_sample_buckets_are_flushed() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 })
+                .sample_bucket_secs(10);
+
+        batches_manager.push(sample(1000, 1.0));
+        batches_manager.push(sample(1004, 2.0));
+        // Not in the batch until the bucket is closed
+        assert_eq!(0, batches_manager.len().unwrap());
+        assert_eq!(1, batches_manager.pending_tcns());
+
+        assert!(batches_manager.flush().is_ok());
+        assert_eq!(0, batches_manager.pending_tcns());
+        let stored_tcns = tcn_dao.all().unwrap();
+        assert_eq!(1, stored_tcns.len());
+        assert_eq!(1, stored_tcns[0].total_count);
+        assert_eq!(1.5, stored_tcns[0].avg_distance);
+        assert_eq!(1.0, stored_tcns[0].min_distance);
+        assert_eq!(1000, stored_tcns[0].contact_start.value);
+        assert_eq!(1004, stored_tcns[0].contact_end.value);
+
+        // Already merged observations aren't bucketed
+        batches_manager.push(ObservedTcn {
+            total_count: 5,
+            ..sample(1010, 3.0)
+        });
+        assert_eq!(1, batches_manager.len().unwrap());
+        // In the batch and in a bucket: counted once
+        batches_manager.push(sample(1012, 1.0));
+        assert_eq!(1, batches_manager.pending_tcns());
+    }
+
+    // The TCN observed with the samples (time, distance), after flushing them
+    #[test]
+    fn test_flush_with_storage_full_keeps_batch_and_backs_off() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 });
+
+        // More than fits in the free space of the allocated pages
+        for index in 0..1000u16 {
+            batches_manager.push(ObservedTcn {
+                tcn: numbered_tcn(index),
+                ..sample(1000, 1.0)
+            });
+        }
+
+        set_storage_full(&database, true);
+        match batches_manager.scheduled_flush_at(&UnixTime { value: 10000 }) {
+            Err(ServicesError::StorageFull) => {}
+            other => panic!("Expected StorageFull, got: {:?}", other),
+        }
+        assert_eq!(1000, batches_manager.len().unwrap());
+        assert!(tcn_dao.all().unwrap().is_empty());
+
+        // Merged with the kept observation
+        batches_manager.push(ObservedTcn {
+            tcn: numbered_tcn(0),
+            ..sample(1010, 0.5)
+        });
+        set_storage_full(&database, false);
+
+        // Skipped during the cooldown
+        let cooldown_end = 10000 + STORAGE_FULL_FLUSH_COOLDOWN_SECS;
+        assert!(batches_manager
+            .scheduled_flush_at(&UnixTime {
+                value: cooldown_end - 1
+            })
+            .is_ok());
+        assert_eq!(1000, batches_manager.len().unwrap());
+        assert!(tcn_dao.all().unwrap().is_empty());
+
+        assert!(batches_manager
+            .scheduled_flush_at(&UnixTime {
+                value: cooldown_end
+            })
+            .is_ok());
+        assert_eq!(0, batches_manager.len().unwrap());
+        let stored_tcns = tcn_dao.all().unwrap();
+        assert_eq!(1000, stored_tcns.len());
+        let merged = stored_tcns
+            .iter()
+            .find(|tcn| tcn.tcn == numbered_tcn(0))
+            .unwrap();
+        assert_eq!(2, merged.total_count);
+        assert_eq!(0.5, merged.min_distance);
+        assert_eq!(1010, merged.contact_end.value);
+    }
+
+    #[test]
+    fn test_concurrent_push_flush_and_find_complete() {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let batches_manager = Arc::new(
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 })
+                .journal(true),
+        );
+        let tcns: Vec<TemporaryContactNumber> = (0..20).map(numbered_tcn).collect();
+        let duration = Duration::from_secs(3);
+        let start = Instant::now();
+
+        let (done_sender, done_receiver) = mpsc::channel();
+        for thread_index in 0..4 {
+            let batches_manager = batches_manager.clone();
+            let tcns = tcns.clone();
+            let done_sender = done_sender.clone();
+            thread::spawn(move || {
+                let mut pushed = 0;
+                while start.elapsed() < duration {
+                    batches_manager.push(ObservedTcn {
+                        tcn: tcns[(thread_index + pushed) % tcns.len()].clone(),
+                        ..sample(1000, 1.0)
+                    });
+                    pushed += 1;
+                }
+                done_sender.send(pushed).unwrap();
+            });
+        }
+        for _ in 0..2 {
+            let batches_manager = batches_manager.clone();
+            let done_sender = done_sender.clone();
+            thread::spawn(move || {
+                while start.elapsed() < duration {
+                    assert!(batches_manager.flush().is_ok());
+                }
+                done_sender.send(0).unwrap();
+            });
+        }
+        {
+            let tcn_dao = tcn_dao.clone();
+            let tcns = tcns.clone();
+            thread::spawn(move || {
+                while start.elapsed() < duration {
+                    assert!(tcn_dao.find_tcns(tcns.clone()).is_ok());
+                }
+                done_sender.send(0).unwrap();
+            });
+        }
+
+        // A deadlock (or a panic) shows as a thread that doesn't finish
+        let mut pushed = 0;
+        for _ in 0..7 {
+            let done_res = done_receiver.recv_timeout(duration + Duration::from_secs(30));
+            pushed += expect_log!(done_res, "A thread didn't finish");
+        }
+
+        assert!(batches_manager.flush().is_ok());
+        assert_eq!(0, batches_manager.pending_tcns());
+        assert!(tcn_dao.scratch().unwrap().is_empty());
+
+        // No observation lost by concurrent flushes
+        let stored_tcns = tcn_dao.all().unwrap();
+        assert_eq!(tcns.len(), stored_tcns.len());
+        assert_eq!(
+            pushed,
+            stored_tcns.iter().map(|tcn| tcn.total_count).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_concurrent_flushes_of_overlapping_batches_store_sequential_rows() {
+        // Both batches have observations of the same TCNs in the same windows
+        let batches: Vec<Vec<ObservedTcn>> = [(1000, 5000), (1500, 5500)]
+            .iter()
+            .map(|&(first_time, second_time)| {
+                (0..10)
+                    .flat_map(|number| {
+                        vec![
+                            ObservedTcn {
+                                tcn: numbered_tcn(number),
+                                ..sample(first_time, 1.0)
+                            },
+                            ObservedTcn {
+                                tcn: numbered_tcn(number),
+                                ..sample(second_time, 1.0)
+                            },
+                        ]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let sequential_dao = Arc::new(TcnDaoImpl::new(migrated_database()));
+        let sequential_manager =
+            TcnBatchesManager::new(sequential_dao.clone(), ExposureGrouper { threshold: 1000 });
+        for batch in &batches {
+            sequential_manager.push_all(batch.clone());
+            assert!(sequential_manager.flush().is_ok());
+        }
+
+        let tcn_dao = Arc::new(TcnDaoImpl::new(migrated_database()));
+        let batches_manager = Arc::new(TcnBatchesManager::new(
+            tcn_dao.clone(),
+            ExposureGrouper { threshold: 1000 },
+        ));
+        let barrier = Arc::new(Barrier::new(batches.len()));
+        let threads: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                let batches_manager = batches_manager.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    batches_manager.push_all(batch);
+                    barrier.wait();
+                    batches_manager.flush()
+                })
+            })
+            .collect();
+        for thread in threads {
+            let join_res = thread.join();
+            let flush_res = expect_log!(join_res, "A flush thread panicked");
+            assert!(flush_res.is_ok());
+        }
+
+        let expected = stored_rows(&sequential_dao);
+        assert_eq!(20, expected.len());
+        assert_eq!(expected, stored_rows(&tcn_dao));
+    }
+
+    fn stored_rows(tcn_dao: &TcnDaoImpl) -> Vec<([u8; 16], u64, u64, usize, f32)> {
+        let mut rows: Vec<_> = tcn_dao
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|tcn| {
+                (
+                    tcn.tcn.0,
+                    tcn.contact_start.value,
+                    tcn.contact_end.value,
+                    tcn.total_count,
+                    tcn.min_distance,
+                )
+            })
+            .collect();
+        rows.sort_by_key(|&(tcn, start, ..)| (tcn, start));
+        rows
+    }
+
+    fn numbered_tcn(number: u16) -> TemporaryContactNumber {
+        let mut bytes = [0; 16];
+        bytes[..2].copy_from_slice(&number.to_be_bytes());
+        TemporaryContactNumber(bytes)
+    }
+
+    fn flushed_samples(sample_bucket_secs: u64, samples: &[(u64, f32)]) -> ObservedTcn {
+        let database = migrated_database();
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database));
+        let batches_manager =
+            TcnBatchesManager::new(tcn_dao.clone(), ExposureGrouper { threshold: 1000 })
+                .sample_bucket_secs(sample_bucket_secs);
+
+        for (time, distance) in samples {
+            batches_manager.push(sample(*time, *distance));
+        }
+        assert!(batches_manager.flush().is_ok());
+
+        let mut stored_tcns = tcn_dao.all().unwrap();
+        assert_eq!(1, stored_tcns.len());
+        stored_tcns.remove(0)
+    }
+
+    // Raw sample, as pushed by the ObservedTcnProcessor
+    fn sample(time: u64, distance: f32) -> ObservedTcn {
+        ObservedTcn {
+            tcn: TemporaryContactNumber([0; 16]),
+            contact_start: UnixTime { value: time },
+            contact_end: UnixTime { value: time },
+            min_distance: distance,
+            avg_distance: distance,
+            total_count: 1,
+            distance_histogram: DistanceHistogram::with_sample(distance),
+        }
+    }
 }
@@ -0,0 +1,157 @@
+// Simulated device for end-to-end tests: its own in-memory database and dependency graph, wired like
+// bootstrap (see create_dependencies) with the default config. The devices share a ScriptedTcnApi as
+// backend, and each has a clock driven by the test.
+
+use super::{migrated_database, ScriptedTcnApi};
+use crate::{
+    config::CoreConfig,
+    database::{
+        alert_dao::AlertDaoImpl,
+        preferences::{PreferencesDao, PreferencesImpl},
+        tcn_dao::TcnDaoImpl,
+        update_run_dao::UpdateRunDaoImpl,
+    },
+    errors::ServicesError,
+    reporting::{
+        memo::MemoMapperImpl,
+        symptom_inputs::{
+            SubmitOutcome, SymptomId, SymptomInputs, SymptomInputsSubmitter,
+            SymptomInputsSubmitterImpl,
+        },
+    },
+    reports_update::{
+        exposure::ExposureGrouper,
+        reports_updater::{ReportsUpdater, ReportsUpdaterBuilder},
+        tcn_matcher::DefaultTcnMatcher,
+    },
+    tcn_ext::{
+        tcn_hex::TcnHex,
+        tcn_keys::{TcnKeys, TcnKeysImpl},
+    },
+    tcn_recording::{
+        flush_scheduler::TimerFlushScheduler,
+        observed_tcn_processor::{DistanceBounds, ObservedTcnProcessor, ObservedTcnProcessorImpl},
+        tcn_batches_manager::TcnBatchesManager,
+    },
+};
+use std::{collections::HashSet, sync::Arc, thread};
+
+// The tests' crate can't name the core's types: the device clocks and its alerts
+pub use crate::{reports_interval::UnixTime, reports_update::reports_updater::Alert};
+
+pub struct TestDevice<'a> {
+    tcn_keys: Arc<TcnKeysImpl<PreferencesImpl>>,
+    observed_tcn_processor: ObservedTcnProcessorImpl<TcnDaoImpl>,
+    symptom_inputs_submitter: SymptomInputsSubmitterImpl<
+        'a,
+        MemoMapperImpl,
+        TcnKeysImpl<PreferencesImpl>,
+        ScriptedTcnApi,
+        PreferencesImpl,
+    >,
+    reports_updater: ReportsUpdater<
+        'a,
+        PreferencesImpl,
+        TcnDaoImpl,
+        DefaultTcnMatcher,
+        ScriptedTcnApi,
+        MemoMapperImpl,
+        AlertDaoImpl,
+        UpdateRunDaoImpl,
+    >,
+}
+
+impl<'a> TestDevice<'a> {
+    pub fn new(api: &'a ScriptedTcnApi, clock: fn() -> UnixTime) -> TestDevice<'a> {
+        let config = CoreConfig::default();
+        let database = migrated_database();
+        let preferences = Arc::new(PreferencesImpl {
+            dao: PreferencesDao::new(database.clone()),
+        });
+        let memo_mapper = &MemoMapperImpl {};
+        let tcn_keys = Arc::new(TcnKeysImpl::new(preferences.clone()).clock(clock));
+        let tcn_dao = Arc::new(TcnDaoImpl::new(database.clone()));
+        let alert_dao = Arc::new(
+            AlertDaoImpl::new(database.clone()).use_p10_distance(config.risk.use_p10_distance),
+        );
+        let exposure_grouper = ExposureGrouper::default();
+
+        let tcn_batches_manager = TcnBatchesManager::new(tcn_dao.clone(), exposure_grouper.clone())
+            .sample_bucket_secs(config.recording.sample_bucket_secs);
+
+        TestDevice {
+            tcn_keys: tcn_keys.clone(),
+            observed_tcn_processor: ObservedTcnProcessorImpl::new(
+                tcn_batches_manager,
+                &TimerFlushScheduler {},
+                DistanceBounds::default(),
+            )
+            .clock(clock),
+            symptom_inputs_submitter: SymptomInputsSubmitterImpl {
+                memo_mapper,
+                tcn_keys,
+                api,
+                client_version: config.client_version(),
+                preferences: preferences.clone(),
+                clock,
+                sleep: thread::sleep,
+            },
+            reports_updater: ReportsUpdaterBuilder::new(
+                preferences,
+                tcn_dao,
+                api,
+                memo_mapper,
+                alert_dao,
+                Arc::new(UpdateRunDaoImpl::new(database)),
+            )
+            .exposure_grouper(exposure_grouper)
+            .client_version(config.client_version())
+            .risk_config(config.risk)
+            .update_report_symptoms(config.reports.update_symptoms)
+            .clock(clock)
+            .build(),
+        }
+    }
+
+    // The TCN to broadcast now (hex), like the apps' BLE advertiser
+    pub fn broadcast_tcn(&self) -> String {
+        let (tcn, _) = self
+            .tcn_keys
+            .current_tcn_with_expiry()
+            .expect("Couldn't generate TCN");
+        TcnHex::from(&tcn).into_string()
+    }
+
+    // A TCN (hex) received from a nearby device, like the apps' BLE scanner
+    pub fn record_tcn(&self, tcn: &str, distance: f32) {
+        let save_res = self.observed_tcn_processor.save(tcn, distance);
+        assert!(save_res.is_ok(), "Couldn't record TCN: {:?}", save_res);
+    }
+
+    // Writes the recorded TCNs to the database, like when the app terminates. No TCNs can be recorded
+    // after it.
+    pub fn stop_recording(&self) {
+        self.observed_tcn_processor.stop();
+    }
+
+    // symptom_ids_json: JSON array of symptom ids, like the apps' inputs (e.g. ["Cough"])
+    pub fn submit_symptoms(&self, symptom_ids_json: &str) -> Result<(), ServicesError> {
+        let ids: HashSet<SymptomId> = serde_json::from_str(symptom_ids_json)?;
+        let outcome = self.symptom_inputs_submitter.submit_inputs(SymptomInputs {
+            ids,
+            ..SymptomInputs::default()
+        })?;
+        match outcome {
+            SubmitOutcome::Sent { .. } => Ok(()),
+            other => Err(ServicesError::General(format!(
+                "Report not sent: {:?}",
+                other
+            ))),
+        }
+    }
+
+    // Runs a reports update, like the apps' periodic fetch. Returns all the stored alerts.
+    pub fn fetch_alerts(&self) -> Result<Vec<Alert>, ServicesError> {
+        self.reports_updater.update_and_fetch_alerts()
+    }
+}
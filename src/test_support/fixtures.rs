@@ -0,0 +1,139 @@
+// Deterministic data shared by the tests. The values belong together (e.g. the TCN is one of the
+// verification report's): don't edit them, add new fixtures instead.
+
+use crate::{
+    reporting::{
+        memo::{MemoMapper, MemoMapperImpl},
+        public_symptoms::{CoughSeverity, FeverSeverity, PublicSymptoms, ReportKind},
+        symptom_inputs::UserInput,
+    },
+    reports_interval::UnixTime,
+    reports_update::reports_updater::SignedReportExt,
+    tcn_recording::{distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn},
+};
+use rand::{rngs::StdRng, CryptoRng, RngCore, SeedableRng};
+use tcn::{MemoType, ReportAuthorizationKey, SignedReport, TemporaryContactNumber};
+
+// Base64 signed report with a CoEpi v1 memo
+pub const VERIFICATION_REPORT: &str = "D7Z8XrufMgfsFH3K5COnv17IFG2ahDb4VM/UMK/5y0+/OtUVVTh7sN0DQ5+R+ocecTilR+SIIpPHzujeJdJzugEAECcAFAEAmmq5XgAAAACaarleAAAAACEBo8p1WdGeXb5O5/3kN6x7GSylgiYGIGsABl3NrxhJu9XHwsN3f6yvRwUxs2fhP4oU5E3+JWabBP6v09pGV1xRCw==";
+
+// Base64 signature of VERIFICATION_REPORT
+pub const VERIFICATION_REPORT_SIG: &str =
+    "o8p1WdGeXb5O5/3kN6x7GSylgiYGIGsABl3NrxhJu9XHwsN3f6yvRwUxs2fhP4oU5E3+JWabBP6v09pGV1xRCw==";
+
+// Memo data of VERIFICATION_REPORT (hex)
+pub const VERIFICATION_REPORT_MEMO: &str = "01009a6ab95e000000009a6ab95e000000002101";
+
+// A TCN of VERIFICATION_REPORT (hex: 18e57df56256dbddac19e896ce42a4ad)
+pub const VERIFICATION_REPORT_TCN: [u8; 16] = [
+    24, 229, 125, 245, 98, 86, 219, 221, 172, 25, 232, 150, 206, 66, 164, 173,
+];
+
+// When the tests observed VERIFICATION_REPORT_TCN
+pub const VERIFICATION_CONTACT_START: u64 = 1590528300;
+pub const VERIFICATION_CONTACT_END: u64 = 1590528301;
+
+// Report authorization key of the device in the reporting tests
+pub const TEST_RAK_BYTES: [u8; 32] = [
+    42, 118, 64, 131, 236, 36, 122, 23, 13, 108, 73, 171, 102, 145, 66, 91, 157, 105, 195, 126,
+    139, 162, 15, 31, 0, 22, 31, 230, 242, 241, 225, 85,
+];
+
+pub fn verification_report() -> SignedReport {
+    SignedReport::with_str(VERIFICATION_REPORT).expect("Invalid verification report")
+}
+
+// Observation of VERIFICATION_REPORT_TCN, see observed_tcn
+pub fn verification_observed_tcn() -> ObservedTcn {
+    observed_tcn(
+        VERIFICATION_REPORT_TCN,
+        VERIFICATION_CONTACT_START,
+        VERIFICATION_CONTACT_END,
+    )
+}
+
+// One sample, at 2.3 meters (3.0 average). Other values with struct update syntax.
+pub fn observed_tcn(tcn: [u8; 16], contact_start: u64, contact_end: u64) -> ObservedTcn {
+    ObservedTcn {
+        tcn: TemporaryContactNumber(tcn),
+        contact_start: UnixTime {
+            value: contact_start,
+        },
+        contact_end: UnixTime { value: contact_end },
+        min_distance: 2.3,
+        avg_distance: 3.0,
+        total_count: 1,
+        distance_histogram: DistanceHistogram::default(),
+    }
+}
+
+// Memo of the generated reports
+pub fn test_public_symptoms() -> PublicSymptoms {
+    PublicSymptoms {
+        report_time: UnixTime { value: 1589209754 },
+        earliest_symptom_time: UserInput::Some(UnixTime { value: 1589209754 }),
+        fever_severity: FeverSeverity::Serious,
+        cough_severity: CoughSeverity::Existing,
+        breathlessness: true,
+        muscle_aches: true,
+        loss_smell_or_taste: false,
+        diarrhea: false,
+        runny_nose: true,
+        other: false,
+        no_symptoms: true,
+        memo_version: 1,
+        report_kind: ReportKind::Symptoms,
+    }
+}
+
+// Report of a new key from rng, with TCNs 1 to 10000
+pub fn create_test_report_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> SignedReport {
+    let rak = ReportAuthorizationKey::new(rng);
+    let memo_data = MemoMapperImpl {}.to_memo(test_public_symptoms());
+    rak.create_report(MemoType::CoEpiV1, memo_data.bytes, 1, 10000)
+        .expect("Couldn't create test report")
+}
+
+// The same seed generates the same reports, so benchmarks are reproducible
+pub fn seeded_test_reports(count: usize, seed: u64) -> Vec<SignedReport> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| create_test_report_with_rng(&mut rng))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signed_report_to_bytes;
+
+    #[test]
+    fn test_verification_fixtures_belong_together() {
+        let report = verification_report();
+        let signature: [u8; 64] = report.sig.into();
+        assert_eq!(VERIFICATION_REPORT_SIG, base64::encode(&signature[..]));
+
+        let verified_report = report.verify().unwrap();
+        assert_eq!(
+            VERIFICATION_REPORT_MEMO,
+            hex::encode(verified_report.memo_data())
+        );
+
+        let tcns: Vec<TemporaryContactNumber> =
+            verified_report.temporary_contact_numbers().collect();
+        assert!(tcns.contains(&TemporaryContactNumber(VERIFICATION_REPORT_TCN)));
+    }
+
+    #[test]
+    fn test_seeded_reports_are_reproducible() {
+        let to_bytes = |reports: Vec<SignedReport>| -> Vec<Vec<u8>> {
+            reports.into_iter().map(signed_report_to_bytes).collect()
+        };
+        let reports = to_bytes(seeded_test_reports(3, 1));
+
+        assert_eq!(reports, to_bytes(seeded_test_reports(3, 1)));
+        assert_ne!(reports, to_bytes(seeded_test_reports(3, 2)));
+        // Different keys
+        assert_ne!(reports[0], reports[1]);
+    }
+}
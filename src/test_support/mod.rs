@@ -0,0 +1,406 @@
+// Test doubles, used by the core's tests and (with the "test-support" feature) by the apps' integration tests.
+
+pub mod device;
+pub mod fixtures;
+
+use crate::{
+    config::ClientVersion,
+    database::{
+        database::Database,
+        migration::{Migration, REQUIRED_DB_VERSION},
+    },
+    errors::ServicesError,
+    networking::{
+        get_reports_pages, NetworkingError, PagingLimits, PostReportRequest, ReportsPage, TcnApi,
+    },
+    reporting::memo::MemoMapperImpl,
+    reports_interval::UnixTime,
+    reports_update::{
+        exposure::ExposureGrouper,
+        reports_updater::{to_alerts, SignedReportExt},
+        tcn_matcher::{DefaultTcnMatcher, TcnMatcher},
+    },
+    tcn_ext::tcn_hex::TcnHex,
+    tcn_recording::{distance_histogram::DistanceHistogram, observed_tcn_processor::ObservedTcn},
+};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+use tcn::SignedReport;
+
+// Behavior of one api call
+#[derive(Debug, Clone)]
+pub struct ApiScriptStep<T> {
+    pub delay: Duration, // Before returning the result
+    pub result: Result<T, NetworkingError>,
+}
+
+impl<T> ApiScriptStep<T> {
+    pub fn ok(value: T) -> ApiScriptStep<T> {
+        ApiScriptStep {
+            delay: Duration::from_millis(0),
+            result: Ok(value),
+        }
+    }
+
+    pub fn failure(http_status: u16) -> ApiScriptStep<T> {
+        ApiScriptStep {
+            delay: Duration::from_millis(0),
+            result: Err(NetworkingError {
+                http_status,
+                message: "Scripted failure".to_owned(),
+            }),
+        }
+    }
+
+    pub fn error(error: NetworkingError) -> ApiScriptStep<T> {
+        ApiScriptStep {
+            delay: Duration::from_millis(0),
+            result: Err(error),
+        }
+    }
+
+    pub fn delayed(mut self, delay: Duration) -> ApiScriptStep<T> {
+        self.delay = delay;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiCallArgs {
+    GetReports {
+        interval_number: u64,
+        interval_length: u64,
+        page: u32,
+    },
+    PostReport(PostReportRequest),
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiCall {
+    pub args: ApiCallArgs,
+    pub time: Instant, // When the call started
+    pub thread: ThreadId,
+}
+
+// TcnApi whose calls consume the scripted steps in order, per endpoint.
+// When an endpoint's script is exhausted, calls succeed immediately (no reports / report posted).
+// get_reports requests the pages like TcnApiImpl: each page consumes a step.
+#[derive(Default)]
+pub struct ScriptedTcnApi {
+    get_reports_script: Mutex<VecDeque<ApiScriptStep<ReportsPage>>>,
+    post_report_script: Mutex<VecDeque<ApiScriptStep<()>>>,
+    calls: Mutex<Vec<ApiCall>>,
+    paging_limits: PagingLimits,
+}
+
+impl ScriptedTcnApi {
+    pub fn paging_limits(mut self, paging_limits: PagingLimits) -> Self {
+        self.paging_limits = paging_limits;
+        self
+    }
+
+    // The whole interval, from a backend that doesn't page
+    pub fn script_get_reports(&self, step: ApiScriptStep<Vec<String>>) {
+        self.script_get_reports_page(ApiScriptStep {
+            delay: step.delay,
+            result: step.result.map(|reports| ReportsPage {
+                reports,
+                has_more: false,
+            }),
+        });
+    }
+
+    pub fn script_get_reports_page(&self, step: ApiScriptStep<ReportsPage>) {
+        self.get_reports_script.lock().unwrap().push_back(step);
+    }
+
+    pub fn script_post_report(&self, step: ApiScriptStep<()>) {
+        self.post_report_script.lock().unwrap().push_back(step);
+    }
+
+    // The next get_reports call returns the reports posted until now, like the backend serving them
+    // in their interval. Shared by simulated devices (see TestDevice).
+    pub fn serve_posted_reports(&self) {
+        let reports = self
+            .posted_reports()
+            .into_iter()
+            .map(|request| request.report)
+            .collect();
+        self.script_get_reports(ApiScriptStep::ok(reports));
+    }
+
+    // In the order they started
+    pub fn calls(&self) -> Vec<ApiCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    // Once per interval request, not per page
+    pub fn requested_intervals(&self) -> Vec<u64> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call.args {
+                ApiCallArgs::GetReports {
+                    interval_number,
+                    page: 0,
+                    ..
+                } => Some(interval_number),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn posted_reports(&self) -> Vec<PostReportRequest> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call.args {
+                ApiCallArgs::PostReport(request) => Some(request),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, args: ApiCallArgs) {
+        self.calls.lock().unwrap().push(ApiCall {
+            args,
+            time: Instant::now(),
+            thread: thread::current().id(),
+        });
+    }
+
+    // The script lock is released before sleeping, so concurrent calls aren't serialized.
+    fn run_step<T>(
+        script: &Mutex<VecDeque<ApiScriptStep<T>>>,
+        default: T,
+    ) -> Result<T, NetworkingError> {
+        let step = script.lock().unwrap().pop_front();
+        match step {
+            Some(step) => {
+                thread::sleep(step.delay);
+                step.result
+            }
+            None => Ok(default),
+        }
+    }
+}
+
+impl TcnApi for ScriptedTcnApi {
+    fn get_reports(
+        &self,
+        interval_number: u64,
+        interval_length: u64,
+    ) -> Result<Vec<String>, NetworkingError> {
+        get_reports_pages(&self.paging_limits, |page, _| {
+            self.record(ApiCallArgs::GetReports {
+                interval_number,
+                interval_length,
+                page,
+            });
+            Self::run_step(&self.get_reports_script, ReportsPage::default())
+        })
+    }
+
+    fn post_report(&self, request: PostReportRequest) -> Result<(), NetworkingError> {
+        self.record(ApiCallArgs::PostReport(request));
+        Self::run_step(&self.post_report_script, ())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ObservedTcnJson {
+    tcn: String, // Hex or base64
+    contact_start: u64,
+    contact_end: u64,
+    min_distance: f32,
+    avg_distance: f32,
+    total_count: usize,
+}
+
+// In-memory database with the current schema, as after bootstrap
+pub fn migrated_database() -> Arc<Database> {
+    let database = Arc::new(Database::new(
+        Connection::open_in_memory().expect("Couldn't create database!"),
+    ));
+    let migration_res = Migration::new(database.clone()).run_db_migrations(REQUIRED_DB_VERSION);
+    assert!(migration_res.is_ok(), "Couldn't migrate database!");
+    database
+}
+
+// Simulates a full device storage: writes that need more pages than the database has fail with
+// SQLITE_FULL (ServicesError::StorageFull). full: false lifts the limit.
+pub fn set_storage_full(database: &Database, full: bool) {
+    // Can't be set below the current page count: 1 is the current size
+    let max_page_count = if full { 1 } else { 1_073_741_823 };
+    let pragma_res =
+        database.execute_batch(&format!("pragma max_page_count = {};", max_page_count));
+    assert!(pragma_res.is_ok(), "Couldn't set max page count!");
+}
+
+// Runs matching, exposure grouping and memo decoding like a reports update, without db or network,
+// so the apps can test with realistic alerts.
+// reports_json: array of base64 signed reports.
+// observed_tcns_json: array of {tcn (hex or base64), contact_start, contact_end, min_distance, avg_distance, total_count}.
+// Returns the alerts as JSON.
+//
+// E.g. with the verification fixture, returns one alert:
+// match_reports_json(
+//     r#"["D7Z8XrufMgfsFH3K5COnv17IFG2ahDb4VM/UMK/5y0+/OtUVVTh7sN0DQ5+R+ocecTilR+SIIpPHzujeJdJzugEAECcAFAEAmmq5XgAAAACaarleAAAAACEBo8p1WdGeXb5O5/3kN6x7GSylgiYGIGsABl3NrxhJu9XHwsN3f6yvRwUxs2fhP4oU5E3+JWabBP6v09pGV1xRCw=="]"#,
+//     r#"[{"tcn":"18e57df56256dbddac19e896ce42a4ad","contact_start":1590528300,"contact_end":1590528301,
+//          "min_distance":2.3,"avg_distance":3.0,"total_count":1}]"#,
+// )
+pub fn match_reports_json(
+    reports_json: &str,
+    observed_tcns_json: &str,
+) -> Result<String, ServicesError> {
+    let reports_base64: Vec<String> = serde_json::from_str(reports_json)
+        .map_err(|e| ServicesError::FFIParameters(format!("Invalid reports JSON: {}", e)))?;
+    let reports = reports_base64
+        .iter()
+        .map(|report| {
+            SignedReport::with_str(report)
+                .ok_or_else(|| ServicesError::FFIParameters(format!("Invalid report: {}", report)))
+        })
+        .collect::<Result<Vec<SignedReport>, ServicesError>>()?;
+
+    let observed_tcns_json: Vec<ObservedTcnJson> = serde_json::from_str(observed_tcns_json)
+        .map_err(|e| ServicesError::FFIParameters(format!("Invalid observed TCNs JSON: {}", e)))?;
+    let observed_tcns = observed_tcns_json
+        .into_iter()
+        .map(|json| {
+            TcnHex::parse(&json.tcn).map(|tcn_hex| ObservedTcn {
+                tcn: tcn_hex.to_tcn(),
+                contact_start: UnixTime {
+                    value: json.contact_start,
+                },
+                contact_end: UnixTime {
+                    value: json.contact_end,
+                },
+                min_distance: json.min_distance,
+                avg_distance: json.avg_distance,
+                total_count: json.total_count,
+                distance_histogram: DistanceHistogram::default(),
+            })
+        })
+        .collect::<Result<Vec<ObservedTcn>, ServicesError>>()?;
+
+    let matched_reports = DefaultTcnMatcher::new().match_reports(observed_tcns, reports)?;
+    let alerts = to_alerts(
+        matched_reports,
+        &ExposureGrouper::default(),
+        &MemoMapperImpl {},
+        &ClientVersion::default(),
+    );
+    serde_json::to_string(&alerts).map_err(ServicesError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientVersion;
+    use fixtures::{VERIFICATION_CONTACT_END, VERIFICATION_CONTACT_START, VERIFICATION_REPORT};
+
+    #[test]
+    fn test_steps_are_consumed_in_order_per_endpoint() {
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec!["report".to_owned()]));
+        api.script_get_reports(ApiScriptStep::failure(503));
+        api.script_post_report(ApiScriptStep::failure(400));
+
+        let post_res = api.post_report(PostReportRequest::new(
+            "report".to_owned(),
+            ClientVersion::default(),
+        ));
+        assert_eq!(400, post_res.unwrap_err().http_status);
+
+        assert_eq!(
+            vec!["report".to_owned()],
+            api.get_reports(1, 21600).unwrap()
+        );
+        assert_eq!(503, api.get_reports(2, 21600).unwrap_err().http_status);
+        // Script exhausted
+        assert!(api.get_reports(3, 21600).unwrap().is_empty());
+        assert!(api
+            .post_report(PostReportRequest::new(
+                "report".to_owned(),
+                ClientVersion::default()
+            ))
+            .is_ok());
+
+        assert_eq!(5, api.calls().len());
+        assert_eq!(vec![1, 2, 3], api.requested_intervals());
+        assert_eq!(2, api.posted_reports().len());
+    }
+
+    #[test]
+    fn test_serves_posted_reports() {
+        let api = ScriptedTcnApi::default();
+        for report in &["report1", "report2"] {
+            let post_res = api.post_report(PostReportRequest::new(
+                report.to_string(),
+                ClientVersion::default(),
+            ));
+            assert!(post_res.is_ok());
+        }
+
+        api.serve_posted_reports();
+        assert_eq!(
+            vec!["report1".to_owned(), "report2".to_owned()],
+            api.get_reports(1, 21600).unwrap()
+        );
+        assert!(api.get_reports(2, 21600).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delayed_step_records_call_start() {
+        let api = ScriptedTcnApi::default();
+        api.script_get_reports(ApiScriptStep::ok(vec![]).delayed(Duration::from_millis(50)));
+
+        let start = Instant::now();
+        assert!(api.get_reports(1, 21600).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let calls = api.calls();
+        assert_eq!(1, calls.len());
+        assert!(calls[0].time - start < Duration::from_millis(50));
+        assert_eq!(thread::current().id(), calls[0].thread);
+    }
+
+    #[test]
+    fn test_match_reports_json_with_fixture() {
+        let alerts_json_res = match_reports_json(
+            &format!("[\"{}\"]", VERIFICATION_REPORT),
+            r#"[
+                {"tcn":"18e57df56256dbddac19e896ce42a4ad","contact_start":1590528300,"contact_end":1590528301,
+                 "min_distance":2.3,"avg_distance":3.0,"total_count":1},
+                {"tcn":"01010101010101010101010101010101","contact_start":1590528300,"contact_end":1590528301,
+                 "min_distance":1.0,"avg_distance":1.0,"total_count":1}
+            ]"#,
+        );
+        assert!(alerts_json_res.is_ok());
+
+        let alerts: serde_json::Value = serde_json::from_str(&alerts_json_res.unwrap()).unwrap();
+        let alerts = alerts.as_array().unwrap();
+        assert_eq!(1, alerts.len());
+        assert_eq!(VERIFICATION_CONTACT_START, alerts[0]["contact_start"]);
+        assert_eq!(VERIFICATION_CONTACT_END, alerts[0]["contact_end"]);
+        assert_eq!(2.3, alerts[0]["min_distance"].as_f64().unwrap() as f32);
+        assert_eq!(3.0, alerts[0]["avg_distance"].as_f64().unwrap() as f32);
+    }
+
+    #[test]
+    fn test_match_reports_json_invalid_input_is_error() {
+        match match_reports_json(r#"["not a report"]"#, "[]") {
+            Err(ServicesError::FFIParameters(_)) => {}
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+        match match_reports_json("[]", r#"[{"tcn":"zz"}]"#) {
+            Err(ServicesError::FFIParameters(_)) => {}
+            other => panic!("Expected FFIParameters error, got: {:?}", other),
+        }
+    }
+}
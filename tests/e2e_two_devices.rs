@@ -0,0 +1,132 @@
+// Two simulated devices exchanging TCNs through a shared backend, driven by a fake clock:
+// A broadcasts its TCNs, B records them, A reports symptoms and B gets the alerts.
+use coepi_core::test_support::{
+    device::{Alert, TestDevice, UnixTime},
+    ScriptedTcnApi,
+};
+use std::cell::Cell;
+
+// Time of device B (Unix time)
+const START: u64 = 1600000000;
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+thread_local! {
+    static NOW: Cell<u64> = Cell::new(START);
+    // Offset of device A's clock, in seconds
+    static SKEW: Cell<i64> = Cell::new(0);
+}
+
+fn clock_a() -> UnixTime {
+    let now = NOW.with(|now| now.get()) as i64;
+    let skew = SKEW.with(|skew| skew.get());
+    UnixTime {
+        value: (now + skew) as u64,
+    }
+}
+
+fn clock_b() -> UnixTime {
+    UnixTime {
+        value: NOW.with(|now| now.get()),
+    }
+}
+
+fn set_time(time: u64) {
+    NOW.with(|now| now.set(time));
+}
+
+struct ScenarioResult {
+    alerts: Vec<Alert>,
+    report_time: u64,
+}
+
+// A broadcasts every minute for 4 hours. B is near A in two encounters:
+// START..START+29min at 1m/2m/3m and START+3h..START+3h+9min at 4m.
+// B also records a TCN of a device that doesn't report.
+fn run_scenario(skew: i64) -> ScenarioResult {
+    SKEW.with(|s| s.set(skew));
+    set_time(START);
+
+    let api = ScriptedTcnApi::default();
+    let device_a = TestDevice::new(&api, clock_a);
+    let device_b = TestDevice::new(&api, clock_b);
+
+    let distances = [1.0, 2.0, 3.0];
+    for minute in 0..(4 * HOUR / MINUTE) {
+        let time = START + minute * MINUTE;
+        set_time(time);
+        let tcn = device_a.broadcast_tcn();
+
+        if minute < 30 {
+            device_b.record_tcn(&tcn, distances[minute as usize % distances.len()]);
+        } else if time >= START + 3 * HOUR && time < START + 3 * HOUR + 10 * MINUTE {
+            device_b.record_tcn(&tcn, 4.0);
+        }
+        if minute < 5 {
+            device_b.record_tcn(&"ab".repeat(16), 1.0);
+        }
+    }
+    device_b.stop_recording();
+
+    set_time(START + 4 * HOUR + 30 * MINUTE);
+    let report_time = clock_a().value;
+    let submit_res = device_a.submit_symptoms("[\"Breathlessness\", \"MuscleAches\"]");
+    assert!(submit_res.is_ok(), "Couldn't submit: {:?}", submit_res);
+    assert_eq!(1, api.posted_reports().len());
+
+    api.serve_posted_reports();
+    set_time(START + 5 * HOUR);
+    let alerts_res = device_b.fetch_alerts();
+    assert!(alerts_res.is_ok(), "Couldn't fetch: {:?}", alerts_res);
+
+    let mut alerts = alerts_res.unwrap();
+    alerts.sort_by_key(|alert| alert.contact_start);
+    ScenarioResult {
+        alerts,
+        report_time,
+    }
+}
+
+fn assert_expected_alerts(result: &ScenarioResult) {
+    let alerts = &result.alerts;
+    assert_eq!(2, alerts.len(), "Alerts: {:?}", alerts);
+
+    assert_eq!(START, alerts[0].contact_start);
+    assert_eq!(START + 29 * MINUTE, alerts[0].contact_end);
+    assert_eq!(1.0, alerts[0].min_distance);
+    assert_eq!(2.0, alerts[0].avg_distance);
+
+    assert_eq!(START + 3 * HOUR, alerts[1].contact_start);
+    assert_eq!(START + 3 * HOUR + 9 * MINUTE, alerts[1].contact_end);
+    assert_eq!(4.0, alerts[1].min_distance);
+    assert_eq!(4.0, alerts[1].avg_distance);
+
+    for alert in alerts {
+        assert_eq!(alerts[0].report_id, alert.report_id);
+        assert!(alert.symptoms.breathlessness);
+        assert!(alert.symptoms.muscle_aches);
+        assert!(!alert.symptoms.diarrhea);
+        assert!(!alert.symptoms.no_symptoms);
+        assert_eq!(result.report_time, alert.symptoms.report_time.value);
+        assert!(!alert.is_read);
+    }
+}
+
+#[test]
+fn test_recorded_contacts_become_alerts() {
+    let result = run_scenario(0);
+    assert_expected_alerts(&result);
+}
+
+// The devices' clocks aren't synchronized. The contact times are B's, the report time A's.
+#[test]
+fn test_recorded_contacts_become_alerts_with_clock_skew() {
+    for skew in &[7 * MINUTE as i64, -7 * MINUTE as i64] {
+        let result = run_scenario(*skew);
+        assert_eq!(
+            (START + 4 * HOUR + 30 * MINUTE) as i64 + skew,
+            result.report_time as i64
+        );
+        assert_expected_alerts(&result);
+    }
+}